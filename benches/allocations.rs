@@ -0,0 +1,93 @@
+//! Allocation pressure of short-lived facts: each reading is asserted, fires
+//! a rule and is retracted
+//!
+//! Counts heap allocations per reading with a counting global allocator.
+//! Compare `cargo bench --bench allocations` with
+//! `cargo bench --bench allocations --features pool` to see the allocations
+//! of fact handles and activations the `pool` feature reuses.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nools::pattern::ObjectPattern;
+use nools::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// System allocator counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Debug, Clone)]
+struct Reading {
+    value: f64,
+}
+
+fn create_reading_flow() -> Flow {
+    let mut flow = Flow::new("Allocations Benchmark");
+
+    for (name, threshold) in [("Warning", 50.0), ("Alarm", 90.0)] {
+        let rule = Rule::new(name)
+            .when(Box::new(
+                ObjectPattern::<Reading>::new("r").with_filter(move |r| r.value > threshold, name),
+            ) as Box<dyn Pattern>)
+            .then(|_session, _match_data| Ok(()))
+            .build()
+            .unwrap();
+        flow.add_rule(rule).unwrap();
+    }
+    flow
+}
+
+/// Assert, fire and retract readings one at a time
+async fn churn(session: &mut Session, readings: &[Reading]) {
+    for reading in readings {
+        let handle = session.assert(reading.clone()).unwrap();
+        session.match_rules().await.unwrap();
+        session.retract(handle).unwrap();
+    }
+}
+
+fn benchmark_allocations(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let flow = create_reading_flow();
+    let readings: Vec<_> = (0..10_000)
+        .map(|i| Reading {
+            value: f64::from(i % 100),
+        })
+        .collect();
+
+    let mut session = flow.session();
+    rt.block_on(churn(&mut session, &readings));
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    rt.block_on(churn(&mut session, &readings));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "allocations per short-lived reading (pool {}): {:.2}",
+        if cfg!(feature = "pool") { "on" } else { "off" },
+        allocations as f64 / readings.len() as f64
+    );
+
+    let mut group = c.benchmark_group("allocations");
+    group.sample_size(10);
+    group.bench_function("assert_fire_retract_10k_readings", |b| {
+        b.iter(|| rt.block_on(churn(&mut session, black_box(&readings))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_allocations);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nools::pattern::ObjectPattern;
+use nools::prelude::*;
+
+#[derive(Debug, Clone)]
+struct Reading {
+    sensor: u32,
+    value: f64,
+}
+
+/// A flow of `rules` rules, each matching the readings of its own sensors
+/// above a threshold
+fn create_bulk_flow(rules: u32) -> Flow {
+    let mut flow = Flow::new("Bulk Assert Benchmark");
+
+    for index in 0..rules {
+        let rule = Rule::new(format!("Alarm{}", index))
+            .when(Box::new(ObjectPattern::<Reading>::new("r").with_filter(
+                move |r| r.sensor % rules == index && r.value.sin().abs() > 0.99,
+                "alarm",
+            )) as Box<dyn Pattern>)
+            .then(|_session, _match_data| Ok(()))
+            .build()
+            .unwrap();
+        flow.add_rule(rule).unwrap();
+    }
+    flow
+}
+
+fn benchmark_bulk_assert(c: &mut Criterion) {
+    let flow = create_bulk_flow(50);
+    let readings: Vec<_> = (0..100_000)
+        .map(|i| Reading {
+            sensor: i,
+            value: f64::from(i) * 0.37,
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("bulk_assert");
+    group.sample_size(10);
+    group.bench_function("assert_all_100k_readings_x_50_rules", |b| {
+        b.iter(|| {
+            let mut session = flow.session();
+            session.assert_all(black_box(readings.clone())).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_bulk_assert);
+criterion_main!(benches);
@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nools::pattern::ObjectPattern;
+use nools::prelude::*;
+
+#[derive(Debug, Clone)]
+struct Customer {
+    id: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Order {
+    customer_id: u32,
+}
+
+fn create_join_flow() -> Flow {
+    let mut flow = Flow::new("Join Benchmark");
+
+    let rule = Rule::new("CustomerOrder")
+        .when(Box::new(ObjectPattern::<Customer>::new("c")) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Order>::new("o").join_on(
+            "c",
+            |c: &Customer| c.id,
+            |o: &Order| o.customer_id,
+        )) as Box<dyn Pattern>)
+        .then(|_session, _match_data| Ok(()))
+        .build()
+        .unwrap();
+
+    flow.add_rule(rule).unwrap();
+    flow
+}
+
+fn benchmark_join(c: &mut Criterion) {
+    c.bench_function("join_10k_customers_x_10k_orders", |b| {
+        b.iter(|| {
+            let flow = create_join_flow();
+            let mut session = flow.session();
+
+            for id in 0..black_box(10_000) {
+                session.assert(Customer { id }).unwrap();
+            }
+            for id in 0..black_box(10_000) {
+                session.assert(Order { customer_id: id }).unwrap();
+            }
+        })
+    });
+}
+
+criterion_group!(benches, benchmark_join);
+criterion_main!(benches);
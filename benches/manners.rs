@@ -0,0 +1,361 @@
+//! Miss Manners: seat guests alternating sex, each sharing a hobby with a
+//! neighbour, by depth-first search over the seatings (after the OPS5
+//! benchmark by Brant and Miranker)
+//!
+//! The engine has no negation node, so the `not` conditions of the original
+//! rules are checked by their actions, and the original's preference for the
+//! most recent seating is a salience on its seat.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use nools::pattern::{ObjectPattern, TestPattern};
+use nools::prelude::*;
+use nools::rule::Match;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sex {
+    Male,
+    Female,
+}
+
+#[derive(Debug, Clone)]
+struct Guest {
+    name: u32,
+    sex: Sex,
+    hobby: u32,
+}
+
+#[derive(Debug, Clone)]
+struct LastSeat {
+    seat: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Count {
+    value: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    AssignSeats,
+    MakePath,
+    CheckDone,
+    PrintResults,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+struct Context {
+    state: State,
+}
+
+#[derive(Debug, Clone)]
+struct Seating {
+    seat2: u32,
+    name2: u32,
+    id: u32,
+    pid: u32,
+    path_done: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Path {
+    id: u32,
+    name: u32,
+    seat: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Chosen {
+    id: u32,
+    name: u32,
+    hobby: u32,
+}
+
+/// Generate `count` guests, half of each sex, with two or three of three
+/// hobbies each, one `Guest` fact per hobby
+fn generate_guests(count: u32) -> Vec<Guest> {
+    let mut seed = 0x2545_f491_u32;
+    let mut random = move |bound: u32| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed % bound
+    };
+
+    let mut guests = Vec::new();
+    for name in 1..=count {
+        let sex = if name % 2 == 0 {
+            Sex::Female
+        } else {
+            Sex::Male
+        };
+        let skipped = match random(2) {
+            0 => None,
+            _ => Some(1 + random(3)),
+        };
+        for hobby in (1..=3).filter(|hobby| Some(*hobby) != skipped) {
+            guests.push(Guest { name, sex, hobby });
+        }
+    }
+    guests
+}
+
+fn context(state: State) -> Box<dyn Pattern> {
+    Box::new(ObjectPattern::<Context>::new("ctx").with_filter(move |c| c.state == state, "state"))
+}
+
+fn set_state(session: &mut Session, m: &Match, state: State) -> Result<()> {
+    let id = m.get("ctx").expect("context is bound").id;
+    session.modify_with::<Context, _>(id, |c| c.state = state)
+}
+
+fn create_manners_flow() -> Flow {
+    let mut flow = Flow::new("Manners Benchmark");
+
+    let assign_first_seat = Rule::new("assign_first_seat")
+        .when(context(State::Start))
+        .when(Box::new(ObjectPattern::<Guest>::new("g")) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Count>::new("count")) as Box<dyn Pattern>)
+        .then(|session, m| {
+            let guest = m.get_as::<Guest>("g")?.name;
+            let count = m.get_as::<Count>("count")?.value;
+            session.assert(Seating {
+                seat2: 1,
+                name2: guest,
+                id: count,
+                pid: 0,
+                path_done: true,
+            })?;
+            session.assert(Path {
+                id: count,
+                name: guest,
+                seat: 1,
+            })?;
+            let id = m.get("count").expect("count is bound").id;
+            session.modify_with::<Count, _>(id, |c| c.value += 1)?;
+            set_state(session, m, State::AssignSeats)
+        })
+        .build()
+        .unwrap();
+
+    let find_seating = Rule::new("find_seating")
+        .when(context(State::AssignSeats))
+        .when(
+            Box::new(ObjectPattern::<Seating>::new("s").with_filter(|s| s.path_done, "path_done"))
+                as Box<dyn Pattern>,
+        )
+        .when(Box::new(ObjectPattern::<Guest>::new("g1").join_on(
+            "s",
+            |s: &Seating| s.name2,
+            |g: &Guest| g.name,
+        )) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Guest>::new("g2").join_on(
+            "g1",
+            |g: &Guest| g.hobby,
+            |g: &Guest| g.hobby,
+        )) as Box<dyn Pattern>)
+        .when(Box::new(TestPattern::new("g1.sex != g2.sex", |m| {
+            match (m.get_as::<Guest>("g1"), m.get_as::<Guest>("g2")) {
+                (Ok(g1), Ok(g2)) => g1.sex != g2.sex,
+                _ => false,
+            }
+        })) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Count>::new("count")) as Box<dyn Pattern>)
+        .priority_fn(|m| m.get_as::<Seating>("s").map_or(0, |s| s.seat2 as i32))
+        .live_reads(true)
+        .then(|session, m| {
+            let seating = m.get_as::<Seating>("s")?.clone();
+            let hobby = m.get_as::<Guest>("g1")?.hobby;
+            let guest = m.get_as::<Guest>("g2")?.name;
+            let count = m.get_as::<Count>("count")?.value;
+
+            // not Path(id == s.id, name == g2.name)
+            let seated = session
+                .get_facts::<Path>()
+                .iter()
+                .filter_map(|handle| handle.downcast_ref::<Path>())
+                .any(|p| p.id == seating.id && p.name == guest);
+            // not Chosen(id == s.id, name == g2.name, hobby == g1.hobby)
+            let chosen = session
+                .get_facts::<Chosen>()
+                .iter()
+                .filter_map(|handle| handle.downcast_ref::<Chosen>())
+                .any(|c| c.id == seating.id && c.name == guest && c.hobby == hobby);
+            if seated || chosen {
+                return Ok(());
+            }
+
+            session.assert(Seating {
+                seat2: seating.seat2 + 1,
+                name2: guest,
+                id: count,
+                pid: seating.id,
+                path_done: false,
+            })?;
+            session.assert(Path {
+                id: count,
+                name: guest,
+                seat: seating.seat2 + 1,
+            })?;
+            session.assert(Chosen {
+                id: seating.id,
+                name: guest,
+                hobby,
+            })?;
+            let id = m.get("count").expect("count is bound").id;
+            session.modify_with::<Count, _>(id, |c| c.value += 1)?;
+            set_state(session, m, State::MakePath)
+        })
+        .build()
+        .unwrap();
+
+    let make_path = Rule::new("make_path")
+        .when(context(State::MakePath))
+        .when(Box::new(
+            ObjectPattern::<Seating>::new("s").with_filter(|s| !s.path_done, "!path_done"),
+        ) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Path>::new("p").join_on(
+            "s",
+            |s: &Seating| s.pid,
+            |p: &Path| p.id,
+        )) as Box<dyn Pattern>)
+        .priority(10)
+        .live_reads(true)
+        .then(|session, m| {
+            let id = m.get_as::<Seating>("s")?.id;
+            let path = m.get_as::<Path>("p")?.clone();
+
+            // not Path(id == s.id, name == p.name)
+            let copied = session
+                .get_facts::<Path>()
+                .iter()
+                .filter_map(|handle| handle.downcast_ref::<Path>())
+                .any(|p| p.id == id && p.name == path.name);
+            if !copied {
+                session.assert(Path { id, ..path })?;
+            }
+            Ok(())
+        })
+        .build()
+        .unwrap();
+
+    let path_done = Rule::new("path_done")
+        .when(context(State::MakePath))
+        .when(Box::new(
+            ObjectPattern::<Seating>::new("s").with_filter(|s| !s.path_done, "!path_done"),
+        ) as Box<dyn Pattern>)
+        .then(|session, m| {
+            let id = m.get("s").expect("seating is bound").id;
+            session.modify_with::<Seating, _>(id, |s| s.path_done = true)?;
+            set_state(session, m, State::CheckDone)
+        })
+        .build()
+        .unwrap();
+
+    let are_we_done = Rule::new("are_we_done")
+        .when(context(State::CheckDone))
+        .when(Box::new(ObjectPattern::<LastSeat>::new("l")) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Seating>::new("s").join_on(
+            "l",
+            |l: &LastSeat| l.seat,
+            |s: &Seating| s.seat2,
+        )) as Box<dyn Pattern>)
+        .priority(10)
+        .then(|session, m| set_state(session, m, State::PrintResults))
+        .build()
+        .unwrap();
+
+    let continue_seating = Rule::new("continue")
+        .when(context(State::CheckDone))
+        .then(|session, m| set_state(session, m, State::AssignSeats))
+        .build()
+        .unwrap();
+
+    let all_done = Rule::new("all_done")
+        .when(context(State::PrintResults))
+        .then(|session, m| {
+            set_state(session, m, State::Done)?;
+            session.halt();
+            Ok(())
+        })
+        .build()
+        .unwrap();
+
+    for rule in [
+        assign_first_seat,
+        find_seating,
+        make_path,
+        path_done,
+        are_we_done,
+        continue_seating,
+        all_done,
+    ] {
+        flow.add_rule(rule).unwrap();
+    }
+    flow
+}
+
+/// Seat the guests, returning the names in seat order
+async fn run_manners(flow: &Flow, guests: &[Guest]) -> Vec<u32> {
+    let mut session = flow.session();
+    for guest in guests {
+        session.assert(guest.clone()).unwrap();
+    }
+    let seats = guests.iter().map(|g| g.name).max().unwrap_or(0);
+    session.assert(LastSeat { seat: seats }).unwrap();
+    session.assert(Count { value: 1 }).unwrap();
+    session
+        .assert(Context {
+            state: State::Start,
+        })
+        .unwrap();
+    session.match_rules().await.unwrap();
+
+    let seatings: Vec<Arc<_>> = session.get_facts::<Seating>();
+    let last = seatings
+        .iter()
+        .filter_map(|handle| handle.downcast_ref::<Seating>())
+        .find(|s| s.seat2 == seats)
+        .map_or(0, |s| s.id);
+    let mut path: Vec<_> = session
+        .get_facts::<Path>()
+        .iter()
+        .filter_map(|handle| handle.downcast_ref::<Path>())
+        .filter(|p| p.id == last)
+        .map(|p| (p.seat, p.name))
+        .collect();
+    path.sort_unstable();
+    path.into_iter().map(|(_, name)| name).collect()
+}
+
+fn benchmark_manners(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let flow = create_manners_flow();
+
+    let mut group = c.benchmark_group("manners");
+    group.sample_size(10);
+    for count in [64, 128] {
+        let guests = generate_guests(count);
+
+        let seating = rt.block_on(run_manners(&flow, &guests));
+        assert_eq!(seating.len(), count as usize, "every guest is seated");
+        let sexes: Vec<_> = seating
+            .iter()
+            .map(|name| guests.iter().find(|g| g.name == *name).unwrap().sex)
+            .collect();
+        assert!(
+            sexes.windows(2).all(|pair| pair[0] != pair[1]),
+            "sexes alternate"
+        );
+
+        group.bench_function(format!("manners_{}", count), |b| {
+            b.iter(|| rt.block_on(run_manners(&flow, &guests)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_manners);
+criterion_main!(benches);
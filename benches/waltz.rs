@@ -0,0 +1,668 @@
+//! Waltz: label the edges of a line drawing by constraint propagation over
+//! its junctions (after the OPS5 benchmark by Brant and Miranker)
+//!
+//! The drawing is a row of cubes, each joined to the next by a bar, so it
+//! has L, arrow and fork junctions. The engine has no negation node, so the
+//! original's `not` conditions are checked by `make_L`'s action and, for the
+//! boundary rules, by a salience on the junction's base point.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use nools::pattern::{ObjectPattern, TestPattern};
+use nools::prelude::*;
+use nools::rule::Match;
+use std::collections::BTreeSet;
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Label {
+    Nil,
+    Plus,
+    Minus,
+    Boundary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    L,
+    Tee,
+    Fork,
+    Arrow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Duplicate,
+    DetectJunctions,
+    FindInitialBoundary,
+    FindSecondBoundary,
+    Labeling,
+    PlotRemainingEdges,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+struct Stage {
+    value: Step,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    p1: Point,
+    p2: Point,
+}
+
+#[derive(Debug, Clone)]
+struct Edge {
+    p1: Point,
+    p2: Point,
+    joined: bool,
+    label: Label,
+    plotted: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Junction {
+    base_point: Point,
+    kind: Kind,
+    p1: Point,
+    p2: Point,
+    p3: Point,
+}
+
+/// Generate the lines of `regions` cubes in a row, each joined to the next
+/// by a bar from its right corner to the next cube's left corner
+fn generate_lines(regions: i32) -> Vec<Line> {
+    // The centre of a cube and its outline clockwise from the top; the
+    // centre is joined to every other corner
+    const CENTRE: (i32, i32) = (0, 0);
+    const OUTLINE: [(i32, i32); 6] = [
+        (0, 20),
+        (17, 10),
+        (17, -10),
+        (0, -20),
+        (-17, -10),
+        (-17, 10),
+    ];
+
+    let mut lines = Vec::new();
+    for region in 0..regions {
+        let point = |(x, y): (i32, i32)| Point {
+            x: region * 60 + x,
+            y,
+        };
+        for (i, corner) in OUTLINE.iter().enumerate() {
+            lines.push(Line {
+                p1: point(*corner),
+                p2: point(OUTLINE[(i + 1) % OUTLINE.len()]),
+            });
+            if i % 2 == 0 {
+                lines.push(Line {
+                    p1: point(CENTRE),
+                    p2: point(*corner),
+                });
+            }
+        }
+        if region + 1 < regions {
+            lines.push(Line {
+                p1: point(OUTLINE[1]),
+                p2: Point {
+                    x: (region + 1) * 60 + OUTLINE[5].0,
+                    y: OUTLINE[5].1,
+                },
+            });
+        }
+    }
+    lines
+}
+
+/// Classify the junction of three edges meeting at `base` and order its
+/// points: an arrow's shaft and a tee's stem are `p2`
+fn make_3_junction(base: Point, points: [Point; 3]) -> Junction {
+    let angle = |p: &Point| f64::from(p.y - base.y).atan2(f64::from(p.x - base.x));
+    let mut points = points;
+    points.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+    let gap = |i: usize| {
+        let gap = angle(&points[(i + 1) % 3]) - angle(&points[i]);
+        if gap <= 0.0 {
+            gap + 2.0 * PI
+        } else {
+            gap
+        }
+    };
+    let widest = (0..3)
+        .max_by(|a, b| gap(*a).total_cmp(&gap(*b)))
+        .unwrap_or(0);
+    let kind = match gap(widest) {
+        gap if (gap - PI).abs() < 1e-9 => Kind::Tee,
+        gap if gap > PI => Kind::Arrow,
+        _ => Kind::Fork,
+    };
+    Junction {
+        base_point: base,
+        kind,
+        p1: points[(widest + 1) % 3],
+        p2: points[(widest + 2) % 3],
+        p3: points[widest],
+    }
+}
+
+fn boxed<T: Fact>(pattern: ObjectPattern<T>) -> Box<dyn Pattern> {
+    Box::new(pattern)
+}
+
+fn stage(step: Step) -> Box<dyn Pattern> {
+    boxed(ObjectPattern::<Stage>::new("stage").with_filter(move |s| s.value == step, "stage"))
+}
+
+fn set_stage(session: &mut Session, m: &Match, step: Step) -> Result<()> {
+    let id = m.get("stage").expect("stage is bound").id;
+    session.modify_with::<Stage, _>(id, |s| s.value = step)
+}
+
+fn junction(kind: Kind) -> Box<dyn Pattern> {
+    boxed(ObjectPattern::<Junction>::new("j").with_filter(move |j| j.kind == kind, "kind"))
+}
+
+/// An edge of the junction `j` with one of `labels`, any label if empty
+fn edge_from(alias: &str, labels: &'static [Label]) -> ObjectPattern<Edge> {
+    ObjectPattern::<Edge>::new(alias)
+        .join_on("j", |j: &Junction| j.base_point, |e: &Edge| e.p1)
+        .with_filter(
+            move |e| labels.is_empty() || labels.contains(&e.label),
+            "label",
+        )
+}
+
+/// One of the points of a junction
+type End = fn(&Junction) -> Point;
+
+/// The edge of the junction `j` to its point `end`
+fn edge_to(alias: &str, end: End, labels: &'static [Label]) -> Box<dyn Pattern> {
+    boxed(
+        ObjectPattern::<Edge>::new(alias)
+            .join_on(
+                "j",
+                move |j: &Junction| (j.base_point, end(j)),
+                |e: &Edge| (e.p1, e.p2),
+            )
+            .with_filter(
+                move |e| labels.is_empty() || labels.contains(&e.label),
+                "label",
+            ),
+    )
+}
+
+/// Test that the edges bound as `aliases` lead to different points
+fn distinct(aliases: &'static [&'static str]) -> Box<dyn Pattern> {
+    Box::new(TestPattern::new("edges differ", move |m| {
+        let ends: Option<BTreeSet<_>> = aliases
+            .iter()
+            .map(|alias| m.get_as::<Edge>(alias).ok().map(|e| e.p2))
+            .collect();
+        ends.is_some_and(|ends| ends.len() == aliases.len())
+    }))
+}
+
+/// A new label for an edge: fixed, or that of another edge of the match
+#[derive(Clone, Copy)]
+enum Set {
+    To(Label),
+    LabelOf(&'static str),
+}
+
+fn relabel(session: &mut Session, m: &Match, alias: &str, label: Label) -> Result<()> {
+    let id = m.get(alias).expect("edge is bound").id;
+    session.modify_with::<Edge, _>(id, |e| e.label = label)
+}
+
+/// A labeling rule setting the labels of edges of its match
+fn labeling(
+    name: &str,
+    patterns: Vec<Box<dyn Pattern>>,
+    labels: &'static [(&'static str, Set)],
+) -> Rule {
+    let mut rule = Rule::new(name).when(stage(Step::Labeling));
+    for pattern in patterns {
+        rule = rule.when(pattern);
+    }
+    rule.then(move |session, m| {
+        for (alias, set) in labels {
+            let label = match set {
+                Set::To(label) => *label,
+                Set::LabelOf(other) => m.get_as::<Edge>(other)?.label,
+            };
+            relabel(session, m, alias, label)?;
+        }
+        Ok(())
+    })
+    .build()
+    .unwrap()
+}
+
+const ANY: &[Label] = &[];
+const NIL: &[Label] = &[Label::Nil];
+const PLUS: &[Label] = &[Label::Plus];
+const MINUS: &[Label] = &[Label::Minus];
+const BOUNDARY: &[Label] = &[Label::Boundary];
+const OCCLUDING: &[Label] = &[Label::Boundary, Label::Minus];
+const CONVEXITY: &[Label] = &[Label::Plus, Label::Minus];
+const LABELED: &[Label] = &[Label::Plus, Label::Minus, Label::Boundary];
+
+/// Salience of a boundary junction, higher for greater base points
+fn rank(m: &Match) -> i32 {
+    m.get_as::<Junction>("j")
+        .map_or(0, |j| j.base_point.x * 1000 + j.base_point.y)
+}
+
+fn create_waltz_flow() -> Flow {
+    let mut flow = Flow::new("Waltz Benchmark");
+    let mut rules = Vec::new();
+
+    rules.push(
+        Rule::new("reverse_edges")
+            .when(stage(Step::Duplicate))
+            .when(boxed(ObjectPattern::<Line>::new("l")))
+            .then(|session, m| {
+                let line = m.get("l").expect("line is bound");
+                let Line { p1, p2 } = line.downcast_ref::<Line>().cloned().expect("a line");
+                for (p1, p2) in [(p1, p2), (p2, p1)] {
+                    session.assert(Edge {
+                        p1,
+                        p2,
+                        joined: false,
+                        label: Label::Nil,
+                        plotted: false,
+                    })?;
+                }
+                session.retract(line.id)
+            })
+            .build()
+            .unwrap(),
+    );
+    rules.push(
+        Rule::new("done_reversing")
+            .when(stage(Step::Duplicate))
+            .priority(-10)
+            .then(|session, m| set_stage(session, m, Step::DetectJunctions))
+            .build()
+            .unwrap(),
+    );
+
+    let unjoined = || boxed(ObjectPattern::<Edge>::new("e1").with_filter(|e| !e.joined, "!joined"));
+    let sibling = |alias: &str| {
+        boxed(ObjectPattern::<Edge>::new(alias).join_on("e1", |e: &Edge| e.p1, |e: &Edge| e.p1))
+    };
+    rules.push(
+        Rule::new("make_3_junction")
+            .when(stage(Step::DetectJunctions))
+            .when(unjoined())
+            .when(sibling("e2"))
+            .when(sibling("e3"))
+            .when(distinct(&["e1", "e2", "e3"]))
+            .priority(1)
+            .then(|session, m| {
+                let edges = ["e1", "e2", "e3"].map(|alias| m.get_as::<Edge>(alias).cloned());
+                let [e1, e2, e3] = edges;
+                let (e1, e2, e3) = (e1?, e2?, e3?);
+                session.assert(make_3_junction(e1.p1, [e1.p2, e2.p2, e3.p2]))?;
+                for alias in ["e1", "e2", "e3"] {
+                    let id = m.get(alias).expect("edge is bound").id;
+                    session.modify_with::<Edge, _>(id, |e| e.joined = true)?;
+                }
+                Ok(())
+            })
+            .build()
+            .unwrap(),
+    );
+    rules.push(
+        Rule::new("make_L")
+            .when(stage(Step::DetectJunctions))
+            .when(unjoined())
+            .when(sibling("e2"))
+            .when(distinct(&["e1", "e2"]))
+            .live_reads(true)
+            .then(|session, m| {
+                let e1 = m.get_as::<Edge>("e1")?.clone();
+                let e2 = m.get_as::<Edge>("e2")?.clone();
+                // not Edge(p1 == e1.p1, p2 != e1.p2, p2 != e2.p2)
+                let third = session
+                    .get_facts::<Edge>()
+                    .iter()
+                    .filter_map(|handle| handle.downcast_ref::<Edge>())
+                    .any(|e| e.p1 == e1.p1 && e.p2 != e1.p2 && e.p2 != e2.p2);
+                if third {
+                    return Ok(());
+                }
+                session.assert(Junction {
+                    base_point: e1.p1,
+                    kind: Kind::L,
+                    p1: e1.p2,
+                    p2: e2.p2,
+                    p3: e2.p2,
+                })?;
+                for alias in ["e1", "e2"] {
+                    let id = m.get(alias).expect("edge is bound").id;
+                    session.modify_with::<Edge, _>(id, |e| e.joined = true)?;
+                }
+                Ok(())
+            })
+            .build()
+            .unwrap(),
+    );
+    rules.push(
+        Rule::new("done_detecting")
+            .when(stage(Step::DetectJunctions))
+            .priority(-10)
+            .then(|session, m| set_stage(session, m, Step::FindInitialBoundary))
+            .build()
+            .unwrap(),
+    );
+
+    // The initial boundary is at the greatest junction, the second at the least
+    for (step, next, salience) in [
+        (
+            Step::FindInitialBoundary,
+            Step::FindSecondBoundary,
+            rank as fn(&Match) -> i32,
+        ),
+        (Step::FindSecondBoundary, Step::Labeling, |m: &Match| {
+            -rank(m)
+        }),
+    ] {
+        let name = match step {
+            Step::FindInitialBoundary => "initial",
+            _ => "second",
+        };
+        rules.push(
+            Rule::new(format!("{}_boundary_junction_L", name))
+                .when(stage(step))
+                .when(junction(Kind::L))
+                .when(edge_to("e1", |j| j.p1, ANY))
+                .when(edge_to("e2", |j| j.p2, ANY))
+                .priority_fn(salience)
+                .then(move |session, m| {
+                    relabel(session, m, "e1", Label::Boundary)?;
+                    relabel(session, m, "e2", Label::Boundary)?;
+                    set_stage(session, m, next)
+                })
+                .build()
+                .unwrap(),
+        );
+        rules.push(
+            Rule::new(format!("{}_boundary_junction_arrow", name))
+                .when(stage(step))
+                .when(junction(Kind::Arrow))
+                .when(edge_to("e1", |j| j.p1, ANY))
+                .when(edge_to("e2", |j| j.p2, ANY))
+                .when(edge_to("e3", |j| j.p3, ANY))
+                .priority_fn(salience)
+                .then(move |session, m| {
+                    relabel(session, m, "e1", Label::Boundary)?;
+                    relabel(session, m, "e2", Label::Plus)?;
+                    relabel(session, m, "e3", Label::Boundary)?;
+                    set_stage(session, m, next)
+                })
+                .build()
+                .unwrap(),
+        );
+    }
+
+    rules.push(
+        Rule::new("match_edge")
+            .when(stage(Step::Labeling))
+            .when(boxed(
+                ObjectPattern::<Edge>::new("e1")
+                    .with_filter(|e| LABELED.contains(&e.label), "labeled"),
+            ))
+            .when(boxed(
+                ObjectPattern::<Edge>::new("e2")
+                    .join_on("e1", |e: &Edge| (e.p2, e.p1), |e: &Edge| (e.p1, e.p2))
+                    .with_filter(|e| e.label == Label::Nil, "unlabeled"),
+            ))
+            .then(|session, m| {
+                let label = m.get_as::<Edge>("e1")?.label;
+                let e1 = m.get("e1").expect("edge is bound").id;
+                let e2 = m.get("e2").expect("edge is bound").id;
+                session.modify_with::<Edge, _>(e1, |e| e.plotted = true)?;
+                session.modify_with::<Edge, _>(e2, |e| {
+                    e.label = label;
+                    e.plotted = true;
+                })
+            })
+            .build()
+            .unwrap(),
+    );
+
+    rules.push(labeling(
+        "label_L",
+        vec![
+            junction(Kind::L),
+            boxed(edge_from("e1", CONVEXITY)),
+            boxed(edge_from("e2", NIL)),
+            distinct(&["e1", "e2"]),
+        ],
+        &[("e2", Set::To(Label::Boundary))],
+    ));
+    rules.push(labeling(
+        "label_tee_A",
+        vec![
+            junction(Kind::Tee),
+            edge_to("e1", |j| j.p1, NIL),
+            edge_to("e2", |j| j.p3, ANY),
+        ],
+        &[
+            ("e1", Set::To(Label::Boundary)),
+            ("e2", Set::To(Label::Boundary)),
+        ],
+    ));
+    rules.push(labeling(
+        "label_tee_B",
+        vec![
+            junction(Kind::Tee),
+            edge_to("e1", |j| j.p1, ANY),
+            edge_to("e2", |j| j.p3, NIL),
+        ],
+        &[
+            ("e1", Set::To(Label::Boundary)),
+            ("e2", Set::To(Label::Boundary)),
+        ],
+    ));
+
+    for (name, first, second, third, labels) in [
+        (
+            "label_fork_1",
+            PLUS,
+            NIL,
+            ANY,
+            &[("e2", Set::To(Label::Plus)), ("e3", Set::To(Label::Plus))][..],
+        ),
+        (
+            "label_fork_2",
+            BOUNDARY,
+            MINUS,
+            NIL,
+            &[("e3", Set::To(Label::Boundary))][..],
+        ),
+        (
+            "label_fork_3",
+            BOUNDARY,
+            BOUNDARY,
+            NIL,
+            &[("e3", Set::To(Label::Minus))][..],
+        ),
+        (
+            "label_fork_4",
+            MINUS,
+            MINUS,
+            NIL,
+            &[("e3", Set::To(Label::Minus))][..],
+        ),
+    ] {
+        rules.push(labeling(
+            name,
+            vec![
+                junction(Kind::Fork),
+                boxed(edge_from("e1", first)),
+                boxed(edge_from("e2", second)),
+                boxed(edge_from("e3", third)),
+                distinct(&["e1", "e2", "e3"]),
+            ],
+            labels,
+        ));
+    }
+
+    // Arrows: barbs `e1` and `e3` either side of the shaft `e2`, the B
+    // rules mirroring the A rules
+    let barbs: [(&str, End, End); 2] = [("A", |j| j.p1, |j| j.p3), ("B", |j| j.p3, |j| j.p1)];
+    for (side, barb, other) in barbs {
+        for (number, first, shaft, third, labels) in [
+            (
+                1,
+                OCCLUDING,
+                NIL,
+                ANY,
+                &[("e2", Set::To(Label::Plus)), ("e3", Set::LabelOf("e1"))][..],
+            ),
+            (
+                2,
+                PLUS,
+                NIL,
+                ANY,
+                &[("e2", Set::To(Label::Minus)), ("e3", Set::To(Label::Plus))][..],
+            ),
+            (3, OCCLUDING, PLUS, NIL, &[("e3", Set::LabelOf("e1"))][..]),
+            (4, PLUS, MINUS, NIL, &[("e3", Set::To(Label::Plus))][..]),
+        ] {
+            rules.push(labeling(
+                &format!("label_arrow_{}{}", number, side),
+                vec![
+                    junction(Kind::Arrow),
+                    edge_to("e1", barb, first),
+                    edge_to("e2", |j| j.p2, shaft),
+                    edge_to("e3", other, third),
+                ],
+                labels,
+            ));
+        }
+    }
+    rules.push(labeling(
+        "label_arrow_5",
+        vec![
+            junction(Kind::Arrow),
+            edge_to("e1", |j| j.p1, NIL),
+            edge_to("e2", |j| j.p2, MINUS),
+            edge_to("e3", |j| j.p3, NIL),
+        ],
+        &[("e1", Set::To(Label::Plus)), ("e3", Set::To(Label::Plus))],
+    ));
+
+    rules.push(
+        Rule::new("done_labeling")
+            .when(stage(Step::Labeling))
+            .priority(-10)
+            .then(|session, m| set_stage(session, m, Step::PlotRemainingEdges))
+            .build()
+            .unwrap(),
+    );
+    rules.push(
+        Rule::new("plot_remaining")
+            .when(stage(Step::PlotRemainingEdges))
+            .when(boxed(ObjectPattern::<Edge>::new("e").with_filter(
+                |e| !e.plotted && e.label != Label::Nil,
+                "unplotted",
+            )))
+            .then(|session, m| {
+                let id = m.get("e").expect("edge is bound").id;
+                session.modify_with::<Edge, _>(id, |e| e.plotted = true)
+            })
+            .build()
+            .unwrap(),
+    );
+    rules.push(
+        Rule::new("plot_boundaries")
+            .when(stage(Step::PlotRemainingEdges))
+            .when(boxed(ObjectPattern::<Edge>::new("e").with_filter(
+                |e| !e.plotted && e.label == Label::Nil,
+                "unplotted",
+            )))
+            .then(|session, m| {
+                let id = m.get("e").expect("edge is bound").id;
+                session.modify_with::<Edge, _>(id, |e| e.plotted = true)
+            })
+            .build()
+            .unwrap(),
+    );
+    rules.push(
+        Rule::new("done_plotting")
+            .when(stage(Step::PlotRemainingEdges))
+            .priority(-10)
+            .then(|session, m| {
+                set_stage(session, m, Step::Done)?;
+                session.halt();
+                Ok(())
+            })
+            .build()
+            .unwrap(),
+    );
+
+    for rule in rules {
+        flow.add_rule(rule).unwrap();
+    }
+    flow
+}
+
+/// Label a drawing, returning its edges
+async fn run_waltz(flow: &Flow, lines: &[Line]) -> Vec<Edge> {
+    let mut session = flow.session();
+    for line in lines {
+        session.assert(line.clone()).unwrap();
+    }
+    session
+        .assert(Stage {
+            value: Step::Duplicate,
+        })
+        .unwrap();
+    session.match_rules().await.unwrap();
+
+    session
+        .get_facts::<Edge>()
+        .iter()
+        .filter_map(|handle| handle.downcast_ref::<Edge>().cloned())
+        .collect()
+}
+
+fn benchmark_waltz(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let flow = create_waltz_flow();
+
+    let mut group = c.benchmark_group("waltz");
+    group.sample_size(10);
+    for regions in [12, 50] {
+        let lines = generate_lines(regions);
+
+        let edges = rt.block_on(run_waltz(&flow, &lines));
+        assert_eq!(edges.len(), lines.len() * 2, "every line has two edges");
+        assert!(
+            edges.iter().all(|e| e.joined && e.plotted),
+            "every edge is plotted"
+        );
+        assert!(
+            edges.iter().any(|e| e.label == Label::Boundary),
+            "the boundaries are found"
+        );
+
+        group.bench_function(format!("waltz_{}", regions), |b| {
+            b.iter(|| rt.block_on(run_waltz(&flow, &lines)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_waltz);
+criterion_main!(benches);
@@ -0,0 +1,435 @@
+//! Build-time code generation for nools rules
+//!
+//! Rule files are parsed while the crate builds and turned into Rust source
+//! that constructs the same rules with typed `ObjectPattern`s. The generated
+//! source is pulled in with `include!`, so text-authored rules cost nothing
+//! to parse at runtime and every fact field they reference is checked by the
+//! compiler.
+//!
+//! In `build.rs`:
+//!
+//! ```no_run
+//! nools_buildgen::compile("rules/orders.json", "orders_rules.rs").unwrap();
+//! ```
+//!
+//! And in the crate, with the fact types and actions in scope:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/orders_rules.rs"));
+//!
+//! let mut flow = nools::Flow::new("orders");
+//! add_rules(&mut flow)?;
+//! ```
+//!
+//! A rule file lists rules, each with its patterns and the path of the
+//! action function to run:
+//!
+//! ```json
+//! {
+//!   "rules": [{
+//!     "name": "vip_order",
+//!     "priority": 10,
+//!     "when": [
+//!       { "alias": "c", "type": "Customer", "where": [{ "field": "vip", "op": "==", "value": true }] },
+//!       { "alias": "o", "type": "Order", "where": [
+//!         { "field": "customer_id", "op": "==", "ref": "c.id" },
+//!         { "field": "total", "op": ">", "value": 100 }
+//!       ]}
+//!     ],
+//!     "then": "actions::flag_vip_order"
+//!   }]
+//! }
+//! ```
+//!
+//! A constraint compares a field with a literal `value`, or with a field of
+//! an earlier pattern through `ref`, which becomes an indexed join. Literals
+//! are emitted as written, so use `100.0` for floating point fields.
+
+use serde::Deserialize;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Result type alias for code generation
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors raised while generating rule source
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The rule file is not valid JSON or does not match the format
+    #[error("Failed to parse rule file: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    /// The rule file is well formed but describes invalid rules
+    #[error("Invalid rule '{rule}': {message}")]
+    InvalidRule {
+        /// Name of the offending rule
+        rule: String,
+        /// What is wrong with it
+        message: String,
+    },
+
+    /// Reading the rule file or writing the generated source failed
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// `compile` was called outside a build script
+    #[error("OUT_DIR is not set; call compile from a build script")]
+    OutDirNotSet,
+}
+
+/// A file of rules
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleFile {
+    /// The rules, in the order they are added to the flow
+    pub rules: Vec<RuleSpec>,
+}
+
+/// A rule as written in a rule file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSpec {
+    /// Rule name
+    pub name: String,
+    /// Rule priority
+    #[serde(default)]
+    pub priority: Option<i32>,
+    /// Agenda group of the rule
+    #[serde(default)]
+    pub agenda_group: Option<String>,
+    /// Activation group of the rule
+    #[serde(default)]
+    pub activation_group: Option<String>,
+    /// Rule tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Patterns that must all match
+    pub when: Vec<PatternSpec>,
+    /// Path of the action function, called as `fn(&mut Session, &Match) -> Result<()>`
+    pub then: String,
+}
+
+/// A pattern matching facts of one type
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternSpec {
+    /// Alias the matched fact is bound to
+    pub alias: String,
+    /// Path of the fact type, resolved where the source is included
+    #[serde(rename = "type")]
+    pub type_path: String,
+    /// Constraints on the fact's fields
+    #[serde(default, rename = "where")]
+    pub constraints: Vec<ConstraintSpec>,
+}
+
+/// A comparison of a fact field
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConstraintSpec {
+    /// Field of the pattern's fact
+    pub field: String,
+    /// Comparison operator
+    pub op: Operator,
+    /// Literal to compare with
+    #[serde(default)]
+    pub value: Option<serde_json::Value>,
+    /// `alias.field` of an earlier pattern to compare with
+    #[serde(default, rename = "ref")]
+    pub reference: Option<String>,
+}
+
+/// Comparison operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Operator {
+    /// `==`
+    #[serde(rename = "==")]
+    Eq,
+    /// `!=`
+    #[serde(rename = "!=")]
+    Ne,
+    /// `<`
+    #[serde(rename = "<")]
+    Lt,
+    /// `<=`
+    #[serde(rename = "<=")]
+    Le,
+    /// `>`
+    #[serde(rename = ">")]
+    Gt,
+    /// `>=`
+    #[serde(rename = ">=")]
+    Ge,
+}
+
+impl Operator {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+        }
+    }
+}
+
+impl RuleFile {
+    /// Parse a rule file from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Generate the source of an `add_rules(&mut Flow)` function adding these rules
+    pub fn generate(&self) -> Result<String> {
+        let mut out = String::new();
+        out.push_str("// Generated by nools-buildgen. Do not edit.\n\n");
+        out.push_str("/// Add the generated rules to a flow\n");
+        out.push_str("#[allow(clippy::all)]\n");
+        out.push_str("pub fn add_rules(flow: &mut ::nools::Flow) -> ::nools::Result<()> {\n");
+        for rule in &self.rules {
+            rule.generate(&mut out)?;
+        }
+        out.push_str("    Ok(())\n}\n");
+        Ok(out)
+    }
+}
+
+impl RuleSpec {
+    fn generate(&self, out: &mut String) -> Result<()> {
+        if self.when.is_empty() {
+            return Err(self.invalid("a rule needs at least one pattern"));
+        }
+        check_path(&self.then).map_err(|e| self.invalid(e))?;
+
+        out.push_str("    flow.add_rule(\n");
+        let _ = writeln!(out, "        ::nools::Rule::new({:?})", self.name);
+        for (i, pattern) in self.when.iter().enumerate() {
+            if self.when[..i].iter().any(|p| p.alias == pattern.alias) {
+                return Err(self.invalid(format!("alias '{}' is bound twice", pattern.alias)));
+            }
+            pattern
+                .generate(&self.when[..i], out)
+                .map_err(|e| self.invalid(e))?;
+        }
+        let _ = writeln!(out, "            .then({})", self.then);
+        if let Some(priority) = self.priority {
+            let _ = writeln!(out, "            .priority({})", priority);
+        }
+        if let Some(group) = &self.agenda_group {
+            let _ = writeln!(out, "            .agenda_group({:?})", group);
+        }
+        if let Some(group) = &self.activation_group {
+            let _ = writeln!(out, "            .activation_group({:?})", group);
+        }
+        for tag in &self.tags {
+            let _ = writeln!(out, "            .tag({:?})", tag);
+        }
+        out.push_str("            .build()?,\n");
+        out.push_str("    )?;\n");
+        Ok(())
+    }
+
+    fn invalid(&self, message: impl Into<String>) -> Error {
+        Error::InvalidRule {
+            rule: self.name.clone(),
+            message: message.into(),
+        }
+    }
+}
+
+impl PatternSpec {
+    fn generate(
+        &self,
+        earlier: &[PatternSpec],
+        out: &mut String,
+    ) -> std::result::Result<(), String> {
+        check_ident(&self.alias)?;
+        check_path(&self.type_path)?;
+
+        out.push_str("            .when(Box::new(\n");
+        let _ = writeln!(
+            out,
+            "                ::nools::pattern::ObjectPattern::<{}>::new({:?})",
+            self.type_path, self.alias
+        );
+        for constraint in &self.constraints {
+            check_ident(&constraint.field)?;
+            let line = match (&constraint.value, &constraint.reference) {
+                (Some(value), None) => self.filter(constraint, value)?,
+                (None, Some(reference)) => self.join(constraint, reference, earlier)?,
+                _ => {
+                    return Err(format!(
+                        "constraint on '{}.{}' needs exactly one of 'value' or 'ref'",
+                        self.alias, constraint.field
+                    ))
+                }
+            };
+            let _ = writeln!(out, "                    {}", line);
+        }
+        out.push_str("            ) as Box<dyn ::nools::Pattern>)\n");
+        Ok(())
+    }
+
+    fn filter(
+        &self,
+        constraint: &ConstraintSpec,
+        value: &serde_json::Value,
+    ) -> std::result::Result<String, String> {
+        let field = format!("{}.{}", self.alias, constraint.field);
+        let op = constraint.op;
+        let test = match value {
+            serde_json::Value::Bool(b) => match (op, *b) {
+                (Operator::Eq, true) | (Operator::Ne, false) => field.clone(),
+                (Operator::Eq, false) | (Operator::Ne, true) => format!("!{}", field),
+                _ => return Err(format!("'{}' cannot compare booleans", op.as_str())),
+            },
+            serde_json::Value::Number(n) => format!("{} {} {}", field, op.as_str(), n),
+            serde_json::Value::String(s) if matches!(op, Operator::Eq | Operator::Ne) => {
+                format!("{} {} {:?}", field, op.as_str(), s)
+            }
+            serde_json::Value::String(_) => {
+                return Err(format!("'{}' cannot compare strings", op.as_str()))
+            }
+            other => return Err(format!("unsupported literal {} for '{}'", other, field)),
+        };
+        let description = format!("{} {} {}", constraint.field, op.as_str(), value);
+        Ok(format!(
+            ".with_filter(|{}: &{}| {}, {:?})",
+            self.alias, self.type_path, test, description
+        ))
+    }
+
+    fn join(
+        &self,
+        constraint: &ConstraintSpec,
+        reference: &str,
+        earlier: &[PatternSpec],
+    ) -> std::result::Result<String, String> {
+        if constraint.op != Operator::Eq {
+            return Err(format!(
+                "only '==' can compare with a ref, found '{}'",
+                constraint.op.as_str()
+            ));
+        }
+        let (alias, field) = reference
+            .split_once('.')
+            .ok_or_else(|| format!("ref '{}' must be written as alias.field", reference))?;
+        check_ident(field)?;
+        let bound = earlier
+            .iter()
+            .find(|p| p.alias == alias)
+            .ok_or_else(|| format!("ref '{}' names no earlier pattern", reference))?;
+        Ok(format!(
+            ".join_on({:?}, |{}: &{}| {}.{}.clone(), |{}: &{}| {}.{}.clone())",
+            alias,
+            alias,
+            bound.type_path,
+            alias,
+            field,
+            self.alias,
+            self.type_path,
+            self.alias,
+            constraint.field
+        ))
+    }
+}
+
+fn check_ident(ident: &str) -> std::result::Result<(), String> {
+    let mut chars = ident.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid identifier", ident))
+    }
+}
+
+fn check_path(path: &str) -> std::result::Result<(), String> {
+    path.strip_prefix("::")
+        .unwrap_or(path)
+        .split("::")
+        .try_for_each(check_ident)
+        .map_err(|_| format!("'{}' is not a valid path", path))
+}
+
+/// Generate Rust source from a JSON rule file
+pub fn generate(json: &str) -> Result<String> {
+    RuleFile::from_json(json)?.generate()
+}
+
+/// Generate source for a rule file into `OUT_DIR`, from a build script
+///
+/// Cargo is told to rerun the build script when the rule file changes.
+/// Returns the path of the generated file.
+pub fn compile(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<PathBuf> {
+    let input = input.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR").ok_or(Error::OutDirNotSet)?;
+    println!("cargo:rerun-if-changed={}", input.display());
+
+    let source = generate(&std::fs::read_to_string(input)?)?;
+    let path = Path::new(&out_dir).join(output);
+    std::fs::write(&path, source)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(when: serde_json::Value) -> String {
+        serde_json::json!({ "rules": [{ "name": "r", "when": when, "then": "act" }] }).to_string()
+    }
+
+    #[test]
+    fn test_literals() {
+        let source = generate(&rule(serde_json::json!([{
+            "alias": "o",
+            "type": "shop::Order",
+            "where": [
+                { "field": "total", "op": ">=", "value": 2.5 },
+                { "field": "status", "op": "!=", "value": "closed \"x\"" },
+                { "field": "paid", "op": "==", "value": false }
+            ]
+        }])))
+        .unwrap();
+
+        assert!(source.contains("ObjectPattern::<shop::Order>::new(\"o\")"));
+        assert!(source.contains("|o: &shop::Order| o.total >= 2.5"));
+        assert!(source.contains("|o: &shop::Order| o.status != \"closed \\\"x\\\"\""));
+        assert!(source.contains("|o: &shop::Order| !o.paid"));
+        assert!(source.contains(".then(act)"));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let invalid = [
+            serde_json::json!([]),
+            serde_json::json!([{ "alias": "o", "type": "Order; panic!()" }]),
+            serde_json::json!([{ "alias": "o", "type": "Order", "where": [
+                { "field": "total", "op": "<", "value": "high" }
+            ]}]),
+            serde_json::json!([{ "alias": "o", "type": "Order", "where": [
+                { "field": "id", "op": "==", "ref": "c.id" }
+            ]}]),
+            serde_json::json!([
+                { "alias": "c", "type": "Customer" },
+                { "alias": "o", "type": "Order", "where": [
+                    { "field": "id", "op": ">", "ref": "c.id" }
+                ]}
+            ]),
+        ];
+        for when in invalid {
+            assert!(matches!(
+                generate(&rule(when)),
+                Err(Error::InvalidRule { .. })
+            ));
+        }
+        assert!(matches!(generate("{\"rule\": []}"), Err(Error::Parse(_))));
+    }
+}
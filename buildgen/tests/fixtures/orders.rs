@@ -0,0 +1,32 @@
+// Generated by nools-buildgen. Do not edit.
+
+/// Add the generated rules to a flow
+#[allow(clippy::all)]
+pub fn add_rules(flow: &mut ::nools::Flow) -> ::nools::Result<()> {
+    flow.add_rule(
+        ::nools::Rule::new("vip_order")
+            .when(Box::new(
+                ::nools::pattern::ObjectPattern::<Customer>::new("c")
+                    .with_filter(|c: &Customer| c.vip, "vip == true")
+            ) as Box<dyn ::nools::Pattern>)
+            .when(Box::new(
+                ::nools::pattern::ObjectPattern::<Order>::new("o")
+                    .join_on("c", |c: &Customer| c.id.clone(), |o: &Order| o.customer_id.clone())
+                    .with_filter(|o: &Order| o.total > 100.0, "total > 100.0")
+            ) as Box<dyn ::nools::Pattern>)
+            .then(flag_vip_order)
+            .priority(10)
+            .tag("vip")
+            .build()?,
+    )?;
+    flow.add_rule(
+        ::nools::Rule::new("open_order")
+            .when(Box::new(
+                ::nools::pattern::ObjectPattern::<Order>::new("o")
+                    .with_filter(|o: &Order| o.status == "open", "status == \"open\"")
+            ) as Box<dyn ::nools::Pattern>)
+            .then(count_open_order)
+            .build()?,
+    )?;
+    Ok(())
+}
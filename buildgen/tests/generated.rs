@@ -0,0 +1,5 @@
+#[test]
+fn test_generated_source_is_up_to_date() {
+    let source = nools_buildgen::generate(include_str!("fixtures/orders.json")).unwrap();
+    assert_eq!(source, include_str!("fixtures/orders.rs"));
+}
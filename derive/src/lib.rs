@@ -0,0 +1,117 @@
+//! Derive macros for nools fact types
+//!
+//! Enable the `derive` feature of `nools-rust` and use them through
+//! `nools::fact::Fact`:
+//!
+//! ```ignore
+//! use nools::fact::Fact;
+//!
+//! #[derive(Debug, Clone, Fact)]
+//! struct Message {
+//!     text: String,
+//!     count: usize,
+//!     #[fact(skip)]
+//!     raw: Vec<u8>,
+//! }
+//!
+//! let pattern = Message::pattern("m").with(Message::field_count().gt(5));
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Result};
+
+/// Derive `nools::value::Fields`, typed `field_<name>()` accessors and `pattern(alias)`
+///
+/// Fields read by name must convert into `nools::value::Value`; mark others
+/// with `#[fact(skip)]`. They keep their typed accessor.
+#[proc_macro_derive(Fact, attributes(fact))]
+pub fn derive_fact(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(unsupported()),
+        },
+        _ => return Err(unsupported()),
+    };
+
+    let ty = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut arms = Vec::new();
+    let mut accessors = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().ok_or_else(unsupported)?;
+        let field_ty = &field.ty;
+        let name = ident.to_string();
+        let name = name.strip_prefix("r#").unwrap_or(&name);
+        let accessor = format_ident!("field_{}", name);
+        let doc = format!("Typed accessor of the `{}` field", name);
+        accessors.push(quote! {
+            #[doc = #doc]
+            pub fn #accessor() -> ::nools::field::Field<Self, #field_ty> {
+                ::nools::field::Field::new(#name, |fact| &fact.#ident)
+            }
+        });
+        if !skipped(field)? {
+            arms.push(quote! {
+                #name => ::std::option::Option::Some(::nools::value::Value::from(
+                    ::std::clone::Clone::clone(&self.#ident),
+                )),
+            });
+        }
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::nools::value::Fields for #ty #ty_generics #where_clause {
+            fn field(&self, name: &str) -> ::std::option::Option<::nools::value::Value> {
+                match name {
+                    #(#arms)*
+                    _ => ::std::option::Option::None,
+                }
+            }
+        }
+
+        impl #impl_generics #ty #ty_generics #where_clause {
+            #(#accessors)*
+
+            /// A pattern on these facts, registering their fields for expressions
+            pub fn pattern(
+                alias: impl ::std::convert::Into<::std::string::String>,
+            ) -> ::nools::pattern::ObjectPattern<Self> {
+                ::nools::value::register_fields::<Self>();
+                ::nools::pattern::ObjectPattern::new(alias)
+            }
+        }
+    })
+}
+
+/// Check for `#[fact(skip)]`
+fn skipped(field: &syn::Field) -> Result<bool> {
+    let mut skip = false;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("fact")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip`"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
+
+fn unsupported() -> Error {
+    Error::new(
+        Span::call_site(),
+        "Fact can only be derived for structs with named fields",
+    )
+}
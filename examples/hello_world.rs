@@ -1,7 +1,6 @@
 //! Hello World example demonstrating basic rule matching
 
 use nools::prelude::*;
-use nools::pattern::ObjectPattern;
 
 #[derive(Debug, Clone)]
 struct Message {
@@ -13,48 +12,24 @@ async fn main() -> Result<()> {
     // Create a new flow
     let mut flow = Flow::new("Hello World");
 
-    // Rule 1: Find messages containing "hello" and append " world"
-    let rule1 = Rule::new("Hello")
-        .when(
-            Box::new(
-                ObjectPattern::<Message>::new("m")
-                    .with_filter(|m| m.text.contains("hello"), "text contains 'hello'"),
-            ) as Box<dyn Pattern>,
-        )
-        .then(|session, match_data| {
-            if let Some(handle) = match_data.get("m") {
-                if let Some(msg) = handle.downcast_ref::<Message>() {
-                    println!("Rule 'Hello' matched: {}", msg.text);
-                    // In a real implementation, we would modify the fact
-                    // For now, just print
-                }
-            }
+    // Rule 1: Find messages containing "hello"
+    flow.add_rule(rule!("Hello",
+        when m: Message (m.text.contains("hello")),
+        then |_session| {
+            println!("Rule 'Hello' matched: {}", m.text);
             Ok(())
-        })
-        .build()?;
-
-    flow.add_rule(rule1)?;
+        }
+    )?)?;
 
     // Rule 2: Find messages ending with "world"
-    let rule2 = Rule::new("Goodbye")
-        .when(
-            Box::new(
-                ObjectPattern::<Message>::new("m")
-                    .with_filter(|m| m.text.ends_with("world"), "text ends with 'world'"),
-            ) as Box<dyn Pattern>,
-        )
-        .then(|_session, match_data| {
-            if let Some(handle) = match_data.get("m") {
-                if let Some(msg) = handle.downcast_ref::<Message>() {
-                    println!("Rule 'Goodbye' matched: {}", msg.text);
-                }
-            }
+    flow.add_rule(rule!("Goodbye",
+        priority: 5,
+        when m: Message (m.text.ends_with("world")),
+        then |_session| {
+            println!("Rule 'Goodbye' matched: {}", m.text);
             Ok(())
-        })
-        .priority(5)
-        .build()?;
-
-    flow.add_rule(rule2)?;
+        }
+    )?)?;
 
     // Create a session and assert facts
     let mut session = flow.session();
@@ -23,22 +23,11 @@ const session = flow.session();
 console.log('Created session, initial fact count:', session.factCount);
 
 // Assert facts into the session
-// Facts are JSON objects stored as strings
-// Note: Fact IDs must be saved BEFORE asserting, as assert() consumes the fact
-const fact1 = new nools.Fact(JSON.stringify({ 
-  type: 'message', 
-  text: 'hello' 
-}));
-const fact1Id = fact1.id;
-session.assert(fact1);
+// Facts are plain objects; assert() returns the fact ID
+const fact1Id = session.assert({ text: 'hello' }, 'Message');
 console.log('Asserted fact1, ID:', fact1Id);
 
-const fact2 = new nools.Fact(JSON.stringify({ 
-  type: 'message', 
-  text: 'world' 
-}));
-const fact2Id = fact2.id;
-session.assert(fact2);
+const fact2Id = session.assert({ text: 'world' }, 'Message');
 console.log('Asserted fact2, ID:', fact2Id);
 
 console.log('Fact count after assertions:', session.factCount);
@@ -47,6 +36,7 @@ console.log('');
 // Get all facts
 const allFacts = session.getFacts();
 console.log('All facts in session:', allFacts);
+console.log('Messages:', session.getFactsOfType('Message'));
 console.log('');
 
 // Match and fire rules
@@ -13,21 +13,18 @@ console.log('Created flow:', flow.name);
 // Create a session
 const session = flow.session();
 
-// Create and assert facts
-const fact1 = new nools.Fact('{"message": "hello world"}');
-const fact2 = new nools.Fact('{"message": "goodbye"}');
-
+// Assert facts
 console.log('');
 console.log('Asserting facts...');
-session.assert(fact1);
-session.assert(fact2);
+session.assert({ message: 'hello world' });
+session.assert({ message: 'goodbye' });
 
 console.log('Facts in session:', session.factCount);
 
 // Match rules
 console.log('');
 console.log('Matching rules...');
-const fired = session.match_rules();
+const fired = session.matchRules();
 console.log('Rules fired:', fired);
 
 // Clean up
@@ -16,20 +16,18 @@ console.log(`Created flow with ${flow.ruleCount} rules\n`);
 // 3. Create a session
 const session = flow.session();
 
-// 4. Assert facts (as JSON strings)
-const cart1 = new nools.Fact(JSON.stringify({
+// 4. Assert facts (plain objects, typed as 'Cart')
+session.assert({
   cart: 'A123',
   total: 150,
   items: 5
-}));
-session.assert(cart1);
+}, 'Cart');
 
-const cart2 = new nools.Fact(JSON.stringify({
+session.assert({
   cart: 'B456',
   total: 45,
   items: 2
-}));
-session.assert(cart2);
+}, 'Cart');
 
 console.log(`Asserted ${session.factCount} facts\n`);
 
@@ -4,6 +4,7 @@ use nools::prelude::*;
 use nools::pattern::ObjectPattern;
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
 enum StateValue {
     NotRun,
     Running,
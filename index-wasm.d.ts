@@ -1,36 +1,75 @@
 // TypeScript definitions for nools-rust WASM
+//
+// Flows, rule builders and sessions take the fact types they work with as
+// a type parameter mapping type names to fact shapes, so asserts and fact
+// queries are checked:
+//
+//   type Facts = { Order: { total: number; status: string } };
+//   const flow = nools.flow<Facts>('orders');
+//   flow.session().assert({ total: 150, status: 'open' }, 'Order');
 
-export class Fact {
-  constructor(data: string);
-  readonly id: number;
-  data: string;
+/** Fact shapes by type name */
+export type FactTypes = Record<string, object>;
+
+/** Names of the fact types of `F` */
+export type FactType<F extends FactTypes> = Extract<keyof F, string>;
+
+/** A fact of any of the types of `F` */
+export type AnyFact<F extends FactTypes> = F[FactType<F>];
+
+/** A JSON schema `type` a fact property can have */
+export type SchemaType = 'boolean' | 'integer' | 'number' | 'string' | 'null';
+
+/** A JSON schema of an object fact type, as accepted by `Flow.defineType` */
+export interface FactSchema {
+  /** Name of the fact type */
+  title: string;
+  type?: 'object';
+  properties?: Record<string, {
+    type?: SchemaType | SchemaType[];
+    default?: boolean | number | string | null;
+  }>;
 }
 
-export class Flow {
+export class Flow<F extends FactTypes = FactTypes> {
   constructor(name: string);
   readonly name: string;
   readonly ruleCount: number;
-  
-  add_rule(name: string, priority: number): RuleBuilder;
-  session(): Session;
+
+  addRule(name: string, priority: number): RuleBuilder<F>;
+  /** Declare a fact type, checking the facts asserted under its `title` */
+  defineType(schema: FactSchema): void;
+  session(): Session<F>;
 }
 
-export class RuleBuilder {
-  when(condition: string): RuleBuilder;
+export class RuleBuilder<F extends FactTypes = FactTypes> {
+  /** Add a condition over a fact's fields, e.g. "m.count > 3 && m.text.includes('hello')" */
+  when(condition: string, type?: FactType<F>): RuleBuilder<F>;
+  agendaGroup(group: string): RuleBuilder<F>;
+  autoFocus(autoFocus: boolean): RuleBuilder<F>;
 }
 
-export class Session {
+export class Session<F extends FactTypes = FactTypes> {
   readonly factCount: number;
   readonly halted: boolean;
-  
-  assert(fact: Fact): void;
-  retract(factId: number): boolean;
-  get_facts(): any;
-  match_rules(): number;
+
+  /** Assert a fact of a type; returns the fact ID */
+  assert<K extends FactType<F>>(fact: F[K], type: K): bigint;
+  /** Assert an object typed by its constructor name; returns the fact ID */
+  assert(fact: AnyFact<F>): bigint;
+  retract(factId: bigint): boolean;
+  getFacts(): AnyFact<F>[];
+  getFactsOfType<K extends FactType<F>>(type: K): F[K][];
+  matchRules(): number;
+  focus(group: string): void;
+  on(event: 'assert' | 'retract', callback: (fact: AnyFact<F>) => void): void;
+  on(event: 'fire', callback: (ruleName: string, facts: AnyFact<F>[]) => void): void;
   halt(): void;
   dispose(): void;
 }
 
-export function flow(name: string): Flow;
+export function flow<F extends FactTypes = FactTypes>(name: string): Flow<F>;
+/** Compile rules written in the nools DSL */
+export function compile<F extends FactTypes = FactTypes>(source: string, name?: string): Flow<F>;
 export function version(): string;
 export function init(): void;
@@ -1,13 +1,20 @@
 //! Agenda for managing rule activations and conflict resolution
 
+use crate::checkpoint::{self, Checkpoint, FiredActivation, PendingActivation};
+use crate::clock::{Instant, RealTimeClock, SessionClock};
 use crate::error::{Error, Result};
-use crate::rule::Activation;
+use crate::event::{EventListener, EventListeners};
+use crate::fact::{FactHandle, FactId};
+use crate::rule::{Activation, Priority, Rule};
 use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Conflict resolution strategy
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Clone)]
 pub enum ConflictResolution {
     /// Order by salience (priority)
     Salience,
@@ -15,8 +22,119 @@ pub enum ConflictResolution {
     ActivationRecency,
     /// Order by fact recency
     FactRecency,
+    /// Prefer rules testing more patterns and constraints
+    Specificity,
+    /// Prefer rules testing fewer patterns and constraints
+    Simplicity,
+    /// Prefer rules declared earlier in the flow
+    LoadOrder,
+    /// Order pseudo-randomly, reproducibly for a seed
+    ///
+    /// Meant as the last strategy, to spread load among activations that tie
+    /// on the others. A session asserting the same facts in the same order
+    /// fires them in the same order for the same seed.
+    Random {
+        /// Seed of the ordering
+        seed: u64,
+    },
+    /// Order by a user-defined strategy
+    Custom(Arc<dyn ConflictResolutionStrategy>),
 }
 
+impl ConflictResolution {
+    /// Wrap a user-defined strategy
+    pub fn custom(strategy: impl ConflictResolutionStrategy + 'static) -> Self {
+        ConflictResolution::Custom(Arc::new(strategy))
+    }
+}
+
+impl std::fmt::Debug for ConflictResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictResolution::Salience => f.write_str("Salience"),
+            ConflictResolution::ActivationRecency => f.write_str("ActivationRecency"),
+            ConflictResolution::FactRecency => f.write_str("FactRecency"),
+            ConflictResolution::Specificity => f.write_str("Specificity"),
+            ConflictResolution::Simplicity => f.write_str("Simplicity"),
+            ConflictResolution::LoadOrder => f.write_str("LoadOrder"),
+            ConflictResolution::Random { seed } => {
+                f.debug_struct("Random").field("seed", seed).finish()
+            }
+            ConflictResolution::Custom(strategy) => {
+                f.debug_tuple("Custom").field(&strategy.name()).finish()
+            }
+        }
+    }
+}
+
+impl PartialEq for ConflictResolution {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ConflictResolution::Custom(a), ConflictResolution::Custom(b)) => Arc::ptr_eq(a, b),
+            (ConflictResolution::Random { seed: a }, ConflictResolution::Random { seed: b }) => {
+                a == b
+            }
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for ConflictResolution {}
+
+/// A domain-specific ordering of activations
+///
+/// Strategies are applied in order; a strategy returning `Equal` defers to
+/// the next one.
+pub trait ConflictResolutionStrategy: Send + Sync {
+    /// Compare two activations; the greater one fires first
+    fn compare(&self, a: &Activation, b: &Activation) -> Ordering;
+
+    /// Name shown in debug output
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+impl<F> ConflictResolutionStrategy for F
+where
+    F: Fn(&Activation, &Activation) -> Ordering + Send + Sync,
+{
+    fn compare(&self, a: &Activation, b: &Activation) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// How activations created while a rule fires are prioritized
+///
+/// With inheritance, facts asserted or modified by a rule's action produce
+/// activations that are at least as urgent as the firing rule, so follow-up
+/// work of a high-salience rule is not starved by unrelated activations.
+/// Inherited salience carries down the whole cascade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PriorityInheritance {
+    /// Activations keep their own salience
+    #[default]
+    None,
+    /// Activations take the firing rule's salience if it is higher
+    Inherit,
+    /// The firing rule's salience is added to the activations' own
+    Boost,
+}
+
+impl PriorityInheritance {
+    /// Salience of an activation created while a rule with `parent` salience fires
+    fn apply(self, own: Priority, parent: Priority) -> Priority {
+        match self {
+            PriorityInheritance::None => own,
+            PriorityInheritance::Inherit => own.max(parent),
+            PriorityInheritance::Boost => own.saturating_add(parent),
+        }
+    }
+}
+
+/// Identifies a rule firing: the rule name and the matched fact ids
+type MatchKey = (String, Vec<FactId>);
+
 /// Wrapper for activations in the priority queue
 #[derive(Debug, Clone)]
 struct ActivationWrapper {
@@ -60,6 +178,27 @@ impl ActivationWrapper {
                         .unwrap_or(0);
                     self_max.cmp(&other_max)
                 }
+                ConflictResolution::Specificity => self
+                    .activation
+                    .rule
+                    .specificity()
+                    .cmp(&other.activation.rule.specificity()),
+                ConflictResolution::Simplicity => other
+                    .activation
+                    .rule
+                    .specificity()
+                    .cmp(&self.activation.rule.specificity()),
+                ConflictResolution::LoadOrder => other
+                    .activation
+                    .rule
+                    .load_order
+                    .cmp(&self.activation.rule.load_order),
+                ConflictResolution::Random { seed } => {
+                    random_key(*seed, &self.activation).cmp(&random_key(*seed, &other.activation))
+                }
+                ConflictResolution::Custom(strategy) => {
+                    strategy.compare(&self.activation, &other.activation)
+                }
             };
 
             if ord != Ordering::Equal {
@@ -70,6 +209,15 @@ impl ActivationWrapper {
     }
 }
 
+/// Pseudo-random rank of an activation, stable within a session for a seed
+fn random_key(seed: u64, activation: &Activation) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    activation.rule.name.hash(&mut hasher);
+    activation.recency.hash(&mut hasher);
+    hasher.finish()
+}
+
 impl PartialEq for ActivationWrapper {
     fn eq(&self, other: &Self) -> bool {
         self.compare(other) == Ordering::Equal
@@ -80,7 +228,7 @@ impl Eq for ActivationWrapper {}
 
 impl PartialOrd for ActivationWrapper {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.compare(other))
+        Some(self.cmp(other))
     }
 }
 
@@ -97,6 +245,10 @@ struct AgendaGroup {
     name: String,
     activations: BinaryHeap<ActivationWrapper>,
     strategies: Vec<ConflictResolution>,
+    /// When the group last had pending activations without being served
+    waiting_since: Option<Instant>,
+    /// Whether the group leaves the focus stack as soon as it runs empty
+    auto_deactivate: bool,
 }
 
 impl AgendaGroup {
@@ -105,16 +257,78 @@ impl AgendaGroup {
             name,
             activations: BinaryHeap::new(),
             strategies,
+            waiting_since: None,
+            auto_deactivate: false,
         }
     }
 
     fn insert(&mut self, activation: Arc<Activation>) {
+        self.waiting_since.get_or_insert_with(Instant::now);
         self.activations
             .push(ActivationWrapper::new(activation, self.strategies.clone()));
     }
 
+    fn extend(&mut self, activations: Vec<Arc<Activation>>) {
+        self.waiting_since.get_or_insert_with(Instant::now);
+        let strategies = &self.strategies;
+        self.activations.extend(
+            activations
+                .into_iter()
+                .map(|activation| ActivationWrapper::new(activation, strategies.clone())),
+        );
+    }
+
     fn pop(&mut self) -> Option<Arc<Activation>> {
-        self.activations.pop().map(|w| w.activation)
+        let activation = self.activations.pop().map(|w| w.activation);
+        self.served();
+        activation
+    }
+
+    /// Restart the waiting time after the group was served
+    fn served(&mut self) {
+        self.waiting_since = if self.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+    }
+
+    /// Remove and return the highest ranked activation matching the predicate
+    fn pop_where(&mut self, mut f: impl FnMut(&Activation) -> bool) -> Option<Arc<Activation>> {
+        let next = self
+            .activations
+            .iter()
+            .filter(|w| f(&w.activation))
+            .max()
+            .map(|w| Arc::clone(&w.activation))?;
+        self.activations
+            .retain(|w| !Arc::ptr_eq(&w.activation, &next));
+        self.served();
+        Some(next)
+    }
+
+    /// Remove and return all activations matching the predicate
+    fn remove_where(&mut self, mut f: impl FnMut(&Activation) -> bool) -> Vec<Arc<Activation>> {
+        let mut removed = Vec::new();
+        self.activations.retain(|w| {
+            if f(&w.activation) {
+                removed.push(Arc::clone(&w.activation));
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Arc<Activation>> {
+        self.activations.iter().map(|w| &w.activation)
+    }
+
+    /// Iterate over the activations in firing order, ranking them lazily
+    fn ranked(&self) -> impl Iterator<Item = &Arc<Activation>> {
+        let mut heap: BinaryHeap<&ActivationWrapper> = self.activations.iter().collect();
+        std::iter::from_fn(move || heap.pop().map(|w| &w.activation))
     }
 
     fn is_empty(&self) -> bool {
@@ -126,6 +340,59 @@ impl AgendaGroup {
     }
 }
 
+/// An agenda group with pending activations that has not been focused for a while
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StarvedGroup {
+    /// Name of the agenda group
+    pub name: String,
+    /// Number of pending activations
+    pub pending: usize,
+    /// When the group started waiting for focus
+    pub waiting_since: Instant,
+}
+
+/// Reports agenda groups whose activations wait for focus longer than a threshold
+#[derive(Clone)]
+pub struct StarvationMonitor {
+    threshold: Duration,
+    listener: Arc<dyn Fn(&StarvedGroup) + Send + Sync>,
+    reported: HashMap<String, Instant>,
+}
+
+impl StarvationMonitor {
+    /// Create a monitor calling `listener` once per starved waiting period
+    pub fn new<F>(threshold: Duration, listener: F) -> Self
+    where
+        F: Fn(&StarvedGroup) + Send + Sync + 'static,
+    {
+        Self {
+            threshold,
+            listener: Arc::new(listener),
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Report the agenda's newly starved groups
+    pub fn check(&mut self, agenda: &Agenda) {
+        for group in agenda.starved_groups(self.threshold) {
+            if self.reported.get(&group.name) != Some(&group.waiting_since) {
+                (self.listener)(&group);
+                self.reported
+                    .insert(group.name.clone(), group.waiting_since);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for StarvationMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StarvationMonitor")
+            .field("threshold", &self.threshold)
+            .field("reported", &self.reported)
+            .finish()
+    }
+}
+
 /// The agenda manages rule activations and determines execution order
 #[derive(Debug)]
 pub struct Agenda {
@@ -133,10 +400,39 @@ pub struct Agenda {
     groups: HashMap<String, AgendaGroup>,
     /// Stack of focused agenda groups
     focus_stack: Vec<String>,
+    /// Agenda group names in creation order
+    group_order: Vec<String>,
     /// Conflict resolution strategies
     strategies: Vec<ConflictResolution>,
     /// Set of rule names that have been registered
     registered_rules: HashSet<String>,
+    /// Number of pending activations referencing each fact, per agenda group
+    fact_links: HashMap<FactId, HashMap<String, usize>>,
+    /// Rule/fact combinations that have already fired (refraction)
+    fired: HashSet<MatchKey>,
+    /// Fired combinations referencing each fact
+    fired_links: HashMap<FactId, Vec<MatchKey>>,
+    /// Seed for sampled rules
+    sample_seed: u64,
+    /// Listeners notified of activations entering and leaving the agenda
+    listeners: EventListeners,
+    /// How activations created during a firing are prioritized
+    inheritance: PriorityInheritance,
+    /// Salience of the activation currently firing
+    firing_salience: Option<Priority>,
+    /// Working memory generation stamped on new activations
+    generation: u64,
+    /// Matches of timer rules waiting for their next firing
+    timers: Vec<ScheduledActivation>,
+    /// Time source of timers
+    clock: Arc<dyn SessionClock>,
+}
+
+/// A match of a timer rule and when it fires next
+#[derive(Debug)]
+struct ScheduledActivation {
+    activation: Arc<Activation>,
+    due: Instant,
 }
 
 impl Agenda {
@@ -153,8 +449,19 @@ impl Agenda {
         let mut agenda = Self {
             groups: HashMap::new(),
             focus_stack: Vec::new(),
+            group_order: Vec::new(),
             strategies: strategies.clone(),
             registered_rules: HashSet::new(),
+            fact_links: HashMap::new(),
+            fired: HashSet::new(),
+            fired_links: HashMap::new(),
+            sample_seed: 0,
+            listeners: EventListeners::default(),
+            inheritance: PriorityInheritance::None,
+            firing_salience: None,
+            generation: 0,
+            timers: Vec::new(),
+            clock: Arc::new(RealTimeClock),
         };
 
         // Create default "main" group
@@ -165,8 +472,16 @@ impl Agenda {
     }
 
     /// Add a new agenda group
+    ///
+    /// Groups nest by name: `billing/refunds` is a child of `billing`, which
+    /// is created if needed. A focused group fires its own activations first,
+    /// then those of its children in the order the children were created.
     pub fn add_agenda_group(&mut self, name: String) {
         if !self.groups.contains_key(&name) {
+            if let Some((parent, _)) = name.rsplit_once('/') {
+                self.add_agenda_group(parent.to_string());
+            }
+            self.group_order.push(name.clone());
             self.groups.insert(
                 name.clone(),
                 AgendaGroup::new(name, self.strategies.clone()),
@@ -174,6 +489,20 @@ impl Agenda {
         }
     }
 
+    /// Get a group and its descendants, in the order they fire
+    pub fn group_hierarchy(&self, name: &str) -> Vec<String> {
+        let mut hierarchy = vec![name.to_string()];
+        for child in &self.group_order {
+            if child
+                .rsplit_once('/')
+                .is_some_and(|(parent, _)| parent == name)
+            {
+                hierarchy.extend(self.group_hierarchy(child));
+            }
+        }
+        hierarchy
+    }
+
     /// Get the currently focused agenda group
     pub fn get_focused(&self) -> Option<&str> {
         self.focus_stack.last().map(|s| s.as_str())
@@ -186,12 +515,83 @@ impl Agenda {
         }
 
         if self.get_focused() != Some(&name) {
+            for member in self.group_hierarchy(&name) {
+                if let Some(group) = self.groups.get_mut(&member) {
+                    group.served();
+                }
+            }
             self.focus_stack.push(name);
         }
 
         Ok(())
     }
 
+    /// Set whether a group leaves the focus stack as soon as it and its
+    /// children have no pending activations, creating the group if needed
+    pub fn set_auto_deactivate(&mut self, name: &str, auto_deactivate: bool) {
+        self.add_agenda_group(name.to_string());
+        if let Some(group) = self.groups.get_mut(name) {
+            group.auto_deactivate = auto_deactivate;
+        }
+        self.deactivate_empty();
+    }
+
+    /// Remove an agenda group from the focus stack, keeping its activations
+    ///
+    /// The `main` group stays at the bottom of the stack.
+    pub fn deactivate_group(&mut self, name: &str) -> Result<()> {
+        if !self.groups.contains_key(name) {
+            return Err(Error::AgendaGroupNotFound(name.to_string()));
+        }
+        self.focus_stack
+            .retain(|focused| focused != name || focused == "main");
+        Ok(())
+    }
+
+    /// Remove all pending activations of an agenda group and its children
+    ///
+    /// Returns the cancelled activations.
+    pub fn clear_group(&mut self, name: &str) -> Result<Vec<Arc<Activation>>> {
+        if !self.groups.contains_key(name) {
+            return Err(Error::AgendaGroupNotFound(name.to_string()));
+        }
+
+        let mut cancelled = Vec::new();
+        for member in self.group_hierarchy(name) {
+            if let Some(group) = self.groups.get_mut(&member) {
+                cancelled.extend(group.remove_where(|_| true));
+                group.served();
+            }
+        }
+
+        for activation in &cancelled {
+            self.unlink(activation);
+            self.listeners
+                .notify(|l| l.on_activation_cancelled(activation));
+        }
+        self.deactivate_empty();
+        Ok(cancelled)
+    }
+
+    /// Pop empty auto-deactivating groups off the top of the focus stack
+    fn deactivate_empty(&mut self) {
+        while let Some(focused) = self.focus_stack.last() {
+            let deactivate = focused != "main"
+                && self
+                    .groups
+                    .get(focused)
+                    .is_some_and(|group| group.auto_deactivate)
+                && self
+                    .group_hierarchy(focused)
+                    .iter()
+                    .all(|member| self.groups.get(member).is_none_or(|group| group.is_empty()));
+            if !deactivate {
+                break;
+            }
+            self.focus_stack.pop();
+        }
+    }
+
     /// Register a rule name
     pub fn register_rule(&mut self, rule_name: String, agenda_group: Option<String>) {
         self.registered_rules.insert(rule_name);
@@ -201,7 +601,27 @@ impl Agenda {
     }
 
     /// Insert an activation into the appropriate agenda group
+    ///
+    /// Activations for a rule and fact combination that already fired are
+    /// ignored until one of the facts is released with `forget_fired`.
     pub fn insert(&mut self, activation: Arc<Activation>) -> Result<()> {
+        let activation = self.inherit(activation);
+        if !self.admits(&activation) {
+            return Ok(());
+        }
+        if let Some(timer) = activation.rule.timer {
+            self.schedule(activation, timer.delay);
+            return Ok(());
+        }
+
+        self.enqueue(Arc::clone(&activation))?;
+        self.listeners
+            .notify(|l| l.on_activation_created(&activation));
+        Ok(())
+    }
+
+    /// Add an admitted activation to its agenda group
+    fn enqueue(&mut self, activation: Arc<Activation>) -> Result<()> {
         let group_name = &activation.rule.agenda_group;
 
         if !self.groups.contains_key(group_name) {
@@ -213,7 +633,9 @@ impl Agenda {
             .get_mut(group_name)
             .ok_or_else(|| Error::AgendaGroupNotFound(group_name.clone()))?;
 
+        activation.stamp_generation(self.generation);
         group.insert(activation.clone());
+        self.link(&activation);
 
         // Auto-focus if needed
         if activation.rule.auto_focus {
@@ -223,13 +645,203 @@ impl Agenda {
         Ok(())
     }
 
+    /// Insert a batch of activations, ordering each agenda group once
+    pub fn insert_all(
+        &mut self,
+        activations: impl IntoIterator<Item = Arc<Activation>>,
+    ) -> Result<()> {
+        let mut batches: HashMap<String, Vec<Arc<Activation>>> = HashMap::new();
+        let mut auto_focus = Vec::new();
+        for activation in activations {
+            let activation = self.inherit(activation);
+            if !self.admits(&activation) {
+                continue;
+            }
+            if let Some(timer) = activation.rule.timer {
+                self.schedule(activation, timer.delay);
+                continue;
+            }
+            activation.stamp_generation(self.generation);
+            self.link(&activation);
+            self.listeners
+                .notify(|l| l.on_activation_created(&activation));
+            let group_name = activation.rule.agenda_group.clone();
+            if activation.rule.auto_focus {
+                auto_focus.push(group_name.clone());
+            }
+            batches.entry(group_name).or_default().push(activation);
+        }
+
+        for (group_name, batch) in batches {
+            self.add_agenda_group(group_name.clone());
+            let group = self
+                .groups
+                .get_mut(&group_name)
+                .ok_or_else(|| Error::AgendaGroupNotFound(group_name.clone()))?;
+            group.extend(batch);
+        }
+
+        for group_name in auto_focus {
+            self.set_focus(group_name)?;
+        }
+        Ok(())
+    }
+
+    /// Set the clock timers read
+    pub fn set_clock(&mut self, clock: Arc<dyn SessionClock>) {
+        self.clock = clock;
+    }
+
+    /// Hold a timer rule's match until `delay` from now
+    fn schedule(&mut self, activation: Arc<Activation>, delay: Duration) {
+        let due = self.clock.now() + delay;
+        self.timers.push(ScheduledActivation { activation, due });
+    }
+
+    /// Put the timer matches that are due on the agenda, returning how many
+    ///
+    /// Repeating timers are scheduled for their next period; a match still
+    /// pending from its last period is not added twice.
+    pub fn release_due(&mut self) -> Result<usize> {
+        let now = self.clock.now();
+        let mut due = Vec::new();
+        self.timers.retain_mut(|scheduled| {
+            if scheduled.due > now {
+                return true;
+            }
+            due.push(Arc::clone(&scheduled.activation));
+            match scheduled
+                .activation
+                .rule
+                .timer
+                .and_then(|timer| timer.period)
+            {
+                Some(period) if !period.is_zero() => {
+                    while scheduled.due <= now {
+                        scheduled.due += period;
+                    }
+                    true
+                }
+                Some(_) => {
+                    scheduled.due = now;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        let mut released = 0;
+        for activation in due {
+            let pending = self
+                .groups
+                .get(&activation.rule.agenda_group)
+                .is_some_and(|group| group.iter().any(|a| Arc::ptr_eq(a, &activation)));
+            if pending {
+                continue;
+            }
+            self.enqueue(Arc::clone(&activation))?;
+            self.listeners
+                .notify(|l| l.on_activation_created(&activation));
+            released += 1;
+        }
+        Ok(released)
+    }
+
+    /// Get when the next timer match is due
+    pub fn next_timer(&self) -> Option<Instant> {
+        self.timers.iter().map(|scheduled| scheduled.due).min()
+    }
+
+    /// Drop the timer matches selected by `f`
+    fn cancel_timers(&mut self, f: impl Fn(&Activation) -> bool) {
+        self.timers.retain(|scheduled| !f(&scheduled.activation));
+    }
+
+    /// Check if an activation may enter the agenda (refraction and sampling)
+    fn admits(&self, activation: &Activation) -> bool {
+        if let Some(key) = Self::match_key(activation) {
+            if self.fired.contains(&key) {
+                return false;
+            }
+        }
+
+        match activation.rule.sample_rate {
+            Some(rate) => self.sampled(activation, rate),
+            None => true,
+        }
+    }
+
+    /// Set how activations created while a rule fires are prioritized
+    pub fn set_priority_inheritance(&mut self, inheritance: PriorityInheritance) {
+        self.inheritance = inheritance;
+    }
+
+    /// Mark the start of a rule firing with the given salience
+    ///
+    /// Returns the salience of the enclosing firing, to pass to
+    /// [`Agenda::end_firing`].
+    pub fn begin_firing(&mut self, salience: Priority) -> Option<Priority> {
+        self.firing_salience.replace(salience)
+    }
+
+    /// Mark the end of a rule firing
+    pub fn end_firing(&mut self, previous: Option<Priority>) {
+        self.firing_salience = previous;
+    }
+
+    /// Apply priority inheritance to an activation created during a firing
+    fn inherit(&self, activation: Arc<Activation>) -> Arc<Activation> {
+        let Some(parent) = self.firing_salience else {
+            return activation;
+        };
+        let own = activation.salience();
+        let salience = self.inheritance.apply(own, parent);
+        if salience == own {
+            return activation;
+        }
+        Arc::new(Activation::with_salience(
+            Arc::clone(&activation.rule),
+            activation.match_data.clone(),
+            activation.recency,
+            salience,
+        ))
+    }
+
+    /// Set the working memory generation stamped on activations added from now on
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// Register a listener for activation events
+    pub fn add_listener(&mut self, listener: Arc<dyn EventListener>) {
+        self.listeners.add(listener);
+    }
+
+    /// Set the seed deciding which matches of sampled rules activate
+    pub fn set_sample_seed(&mut self, seed: u64) {
+        self.sample_seed = seed;
+    }
+
+    /// Check if an activation of a sampled rule falls within its sample
+    fn sampled(&self, activation: &Activation, rate: f64) -> bool {
+        let mut hasher = DefaultHasher::new();
+        self.sample_seed.hash(&mut hasher);
+        activation.rule.name.hash(&mut hasher);
+        activation.match_data.fact_ids().hash(&mut hasher);
+        (hasher.finish() as f64 / u64::MAX as f64) < rate
+    }
+
     /// Pop the next activation from the focused agenda group
     pub fn pop(&mut self) -> Option<Arc<Activation>> {
         // Try focused groups from top of stack
         while let Some(focused) = self.focus_stack.last().cloned() {
-            if let Some(group) = self.groups.get_mut(&focused) {
-                if let Some(activation) = group.pop() {
-                    return Some(activation);
+            for member in self.group_hierarchy(&focused) {
+                if let Some(group) = self.groups.get_mut(&member) {
+                    if let Some(activation) = group.pop() {
+                        self.on_fire(&activation);
+                        self.deactivate_empty();
+                        return Some(activation);
+                    }
                 }
             }
 
@@ -244,17 +856,581 @@ impl Agenda {
         None
     }
 
-    /// Check if the agenda is empty
-    pub fn is_empty(&self) -> bool {
-        // Check if focused groups have any activations
-        for focused in self.focus_stack.iter().rev() {
-            if let Some(group) = self.groups.get(focused) {
-                if !group.is_empty() {
-                    return false;
+    /// Pop the next activation whose rule matches the predicate
+    ///
+    /// Searches the focus stack from the top; activations of other rules stay
+    /// on the agenda and the focus stack is left unchanged.
+    pub fn pop_where(&mut self, mut f: impl FnMut(&Rule) -> bool) -> Option<Arc<Activation>> {
+        self.pop_filtered(|activation| f(&activation.rule))
+    }
+
+    /// Pop the next activation matching the predicate
+    ///
+    /// Searches the focus stack from the top; other activations stay on the
+    /// agenda and the focus stack is left unchanged unless an auto-deactivating
+    /// group runs empty.
+    pub fn pop_filtered(
+        &mut self,
+        mut f: impl FnMut(&Activation) -> bool,
+    ) -> Option<Arc<Activation>> {
+        let members: Vec<_> = self
+            .focus_stack
+            .iter()
+            .rev()
+            .flat_map(|name| self.group_hierarchy(name))
+            .collect();
+        let activation = members
+            .iter()
+            .find_map(|name| self.groups.get_mut(name)?.pop_where(&mut f))?;
+        self.on_fire(&activation);
+        self.deactivate_empty();
+        Some(activation)
+    }
+
+    /// Pop up to `max` activations that may fire simultaneously
+    ///
+    /// The batch starts with the next activation in firing order. An
+    /// exclusive activation fires alone; otherwise the batch takes the
+    /// following activations of the focused group in firing order, skipping
+    /// those whose concurrency key or activation group is already in the batch,
+    /// and stops at the first exclusive one. Independent activations never
+    /// conflict.
+    pub fn pop_batch(&mut self, max: usize) -> Vec<Arc<Activation>> {
+        if max == 0 {
+            return Vec::new();
+        }
+        let Some(first) = self.pop() else {
+            return Vec::new();
+        };
+        let Some(key) = first.concurrency_key() else {
+            return vec![first];
+        };
+
+        let mut keys = HashSet::from([key]);
+        let mut activation_groups: HashSet<_> =
+            first.rule.activation_group.iter().cloned().collect();
+        let members = self
+            .focus_stack
+            .last()
+            .map(|focused| self.group_hierarchy(focused))
+            .unwrap_or_default();
+        let mut picked = Vec::new();
+        'members: for member in &members {
+            let Some(group) = self.groups.get(member) else {
+                continue;
+            };
+            for activation in group.ranked() {
+                if picked.len() + 1 >= max {
+                    break 'members;
+                }
+                let Some(key) = activation.concurrency_key() else {
+                    break 'members;
+                };
+                let activation_group = activation.rule.activation_group.as_ref();
+                if activation_group.is_some_and(|name| activation_groups.contains(name))
+                    || (!key.is_empty() && !keys.insert(key))
+                {
+                    continue;
+                }
+                activation_groups.extend(activation_group.cloned());
+                picked.push(Arc::clone(activation));
+            }
+        }
+
+        for member in &members {
+            if let Some(group) = self.groups.get_mut(member) {
+                let removed = group.remove_where(|activation| {
+                    picked
+                        .iter()
+                        .any(|picked| std::ptr::eq(Arc::as_ptr(picked), activation))
+                });
+                if !removed.is_empty() {
+                    group.served();
+                }
+            }
+        }
+        let mut batch = vec![first];
+        for activation in picked {
+            self.on_fire(&activation);
+            batch.push(activation);
+        }
+        self.deactivate_empty();
+        batch
+    }
+
+    /// Put popped activations that did not fire back on the agenda
+    ///
+    /// Their firing is forgotten, so refraction no longer holds them back.
+    pub fn requeue(&mut self, activations: Vec<Arc<Activation>>) -> Result<()> {
+        for activation in activations {
+            if let Some(key) = Self::match_key(&activation) {
+                self.forget_key(&key);
+            }
+            self.enqueue(activation)?;
+        }
+        Ok(())
+    }
+
+    /// Bookkeeping for an activation leaving the agenda to fire
+    fn on_fire(&mut self, activation: &Activation) {
+        self.unlink(activation);
+        self.record_fired(activation);
+        if let Some(activation_group) = &activation.rule.activation_group {
+            self.cancel_activation_group(activation_group);
+        }
+    }
+
+    /// Remove all pending activations whose match references the given fact
+    ///
+    /// Returns the cancelled activations.
+    pub fn cancel_for_fact(&mut self, fact_id: FactId) -> Vec<Arc<Activation>> {
+        self.cancel_timers(|activation| {
+            activation
+                .match_data
+                .facts
+                .values()
+                .any(|f| f.id == fact_id)
+        });
+        let groups = match self.fact_links.remove(&fact_id) {
+            Some(groups) => groups,
+            None => return Vec::new(),
+        };
+
+        let mut cancelled = Vec::new();
+        for group_name in groups.keys() {
+            if let Some(group) = self.groups.get_mut(group_name) {
+                cancelled.extend(group.remove_where(|activation| {
+                    activation
+                        .match_data
+                        .facts
+                        .values()
+                        .any(|f| f.id == fact_id)
+                }));
+            }
+        }
+
+        for activation in &cancelled {
+            self.unlink(activation);
+            self.listeners
+                .notify(|l| l.on_activation_cancelled(activation));
+        }
+        self.deactivate_empty();
+        cancelled
+    }
+
+    /// Remove the pending activations of a rule binding a fact to an alias
+    ///
+    /// Returns the cancelled activations.
+    pub fn cancel_binding(
+        &mut self,
+        rule: &str,
+        alias: &str,
+        fact_id: FactId,
+    ) -> Vec<Arc<Activation>> {
+        let binds = |activation: &Activation| {
+            activation.rule.name == rule
+                && activation
+                    .match_data
+                    .get(alias)
+                    .is_some_and(|f| f.id == fact_id)
+        };
+        self.cancel_timers(binds);
+        let groups: Vec<_> = match self.fact_links.get(&fact_id) {
+            Some(groups) => groups.keys().cloned().collect(),
+            None => return Vec::new(),
+        };
+
+        let mut cancelled = Vec::new();
+        for group_name in groups {
+            if let Some(group) = self.groups.get_mut(&group_name) {
+                cancelled.extend(group.remove_where(|activation| binds(activation)));
+            }
+        }
+
+        for activation in &cancelled {
+            self.unlink(activation);
+            self.listeners
+                .notify(|l| l.on_activation_cancelled(activation));
+        }
+        self.deactivate_empty();
+        cancelled
+    }
+
+    /// Remove all pending activations of rules in an activation group
+    ///
+    /// Returns the cancelled activations.
+    pub fn cancel_activation_group(&mut self, activation_group: &str) -> Vec<Arc<Activation>> {
+        let mut cancelled = Vec::new();
+        for group in self.groups.values_mut() {
+            cancelled.extend(group.remove_where(|activation| {
+                activation.rule.activation_group.as_deref() == Some(activation_group)
+            }));
+        }
+
+        for activation in &cancelled {
+            self.unlink(activation);
+            self.listeners
+                .notify(|l| l.on_activation_cancelled(activation));
+        }
+        self.deactivate_empty();
+        cancelled
+    }
+
+    /// Remove all pending activations of the named rules
+    ///
+    /// Returns the cancelled activations.
+    pub fn cancel_rules(&mut self, rule_names: &HashSet<String>) -> Vec<Arc<Activation>> {
+        self.cancel_timers(|activation| rule_names.contains(&activation.rule.name));
+        let mut cancelled = Vec::new();
+        for group in self.groups.values_mut() {
+            cancelled.extend(
+                group.remove_where(|activation| rule_names.contains(&activation.rule.name)),
+            );
+        }
+
+        for activation in &cancelled {
+            self.unlink(activation);
+            self.listeners
+                .notify(|l| l.on_activation_cancelled(activation));
+        }
+        self.deactivate_empty();
+        cancelled
+    }
+
+    /// Bring pending activations up to date with a modified fact
+    ///
+    /// Activations of `rematched` rules are cancelled and those rules may fire
+    /// again for the fact, since the network re-matched them. Activations of
+    /// other rules stay pending with the new version of the fact.
+    pub fn refresh_fact(
+        &mut self,
+        fact: &Arc<FactHandle>,
+        rematched: &HashSet<String>,
+    ) -> Result<()> {
+        let forgotten: Vec<_> = self
+            .fired_links
+            .get(&fact.id)
+            .into_iter()
+            .flatten()
+            .filter(|key| rematched.contains(&key.0))
+            .cloned()
+            .collect();
+        for key in forgotten {
+            self.forget_key(&key);
+        }
+
+        let mut removed = Vec::new();
+        for group in self.groups.values_mut() {
+            removed.extend(group.remove_where(|activation| {
+                activation
+                    .match_data
+                    .facts
+                    .values()
+                    .any(|f| f.id == fact.id)
+            }));
+        }
+
+        for activation in removed {
+            self.unlink(&activation);
+            if rematched.contains(&activation.rule.name) {
+                self.listeners
+                    .notify(|l| l.on_activation_cancelled(&activation));
+            } else {
+                // Keep inherited salience, the read generation and the explanation
+                let mut match_data = activation.match_data.clone();
+                match_data.replace_fact(fact);
+                self.enqueue(Arc::new(activation.rebuild(
+                    match_data,
+                    activation.recency,
+                    activation.salience(),
+                )))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Allow rule combinations involving a fact to fire again
+    ///
+    /// Called when the fact is modified or retracted.
+    pub fn forget_fired(&mut self, fact_id: FactId) {
+        for key in self.fired_links.remove(&fact_id).unwrap_or_default() {
+            self.forget_key(&key);
+        }
+    }
+
+    /// Forget a fired combination, unlinking it from each of its facts
+    fn forget_key(&mut self, key: &MatchKey) {
+        if !self.fired.remove(key) {
+            return;
+        }
+        for fact_id in &key.1 {
+            if let Some(keys) = self.fired_links.get_mut(fact_id) {
+                keys.retain(|linked| linked != key);
+                if keys.is_empty() {
+                    self.fired_links.remove(fact_id);
                 }
             }
         }
-        true
+    }
+
+    /// Remember a fired combination, linking it to each of its facts
+    fn remember_fired(&mut self, key: MatchKey) {
+        if self.fired.contains(&key) {
+            return;
+        }
+        for fact_id in &key.1 {
+            self.fired_links
+                .entry(*fact_id)
+                .or_default()
+                .push(key.clone());
+        }
+        self.fired.insert(key);
+    }
+
+    /// Check if a rule already fired for the given facts
+    pub fn has_fired(&self, rule_name: &str, fact_ids: &[FactId]) -> bool {
+        self.fired
+            .contains(&(rule_name.to_string(), fact_ids.to_vec()))
+    }
+
+    /// The refraction key of an activation, or `None` if it matched no facts
+    fn match_key(activation: &Activation) -> Option<MatchKey> {
+        let fact_ids = activation.match_data.fact_ids();
+        if fact_ids.is_empty() {
+            None
+        } else {
+            Some((activation.rule.name.clone(), fact_ids))
+        }
+    }
+
+    /// Remember that an activation fired
+    fn record_fired(&mut self, activation: &Activation) {
+        if let Some(key) = Self::match_key(activation) {
+            self.remember_fired(key);
+        }
+    }
+
+    /// Record which facts a pending activation references
+    fn link(&mut self, activation: &Activation) {
+        for fact in activation.match_data.facts.values() {
+            *self
+                .fact_links
+                .entry(fact.id)
+                .or_default()
+                .entry(activation.rule.agenda_group.clone())
+                .or_default() += 1;
+        }
+    }
+
+    /// Forget the fact references of an activation that left the agenda
+    fn unlink(&mut self, activation: &Activation) {
+        for fact in activation.match_data.facts.values() {
+            if let Some(groups) = self.fact_links.get_mut(&fact.id) {
+                if let Some(count) = groups.get_mut(&activation.rule.agenda_group) {
+                    *count -= 1;
+                    if *count == 0 {
+                        groups.remove(&activation.rule.agenda_group);
+                    }
+                }
+                if groups.is_empty() {
+                    self.fact_links.remove(&fact.id);
+                }
+            }
+        }
+    }
+
+    /// Capture pending activations, fired combinations and the focus stack
+    pub fn checkpoint(&self, flow: &str) -> Checkpoint {
+        let mut pending: Vec<_> = self
+            .groups
+            .values()
+            .flat_map(AgendaGroup::iter)
+            .map(|activation| PendingActivation {
+                rule: activation.rule.name.clone(),
+                agenda_group: activation.rule.agenda_group.clone(),
+                facts: activation
+                    .match_data
+                    .facts
+                    .iter()
+                    .map(|(alias, fact)| (alias.clone(), fact.id))
+                    .collect(),
+                recency: activation.recency,
+                salience: Some(activation.salience()),
+            })
+            .collect();
+        pending.sort_by_key(|activation| activation.recency);
+
+        let mut fired: Vec<_> = self
+            .fired
+            .iter()
+            .map(|(rule, facts)| FiredActivation {
+                rule: rule.clone(),
+                facts: facts.clone(),
+            })
+            .collect();
+        fired.sort_by(|a, b| (&a.rule, &a.facts).cmp(&(&b.rule, &b.facts)));
+
+        let now = self.clock.now();
+        let mut scheduled: Vec<_> = self
+            .timers
+            .iter()
+            .map(|scheduled| {
+                let activation = &scheduled.activation;
+                checkpoint::ScheduledActivation {
+                    rule: activation.rule.name.clone(),
+                    facts: activation
+                        .match_data
+                        .facts
+                        .iter()
+                        .map(|(alias, fact)| (alias.clone(), fact.id))
+                        .collect(),
+                    due_in: scheduled.due.saturating_duration_since(now),
+                    period: activation.rule.timer.and_then(|timer| timer.period),
+                }
+            })
+            .collect();
+        scheduled.sort_by(|a, b| (&a.rule, &a.facts).cmp(&(&b.rule, &b.facts)));
+
+        Checkpoint {
+            flow: flow.to_string(),
+            pending,
+            fired,
+            focus_stack: self.focus_stack.clone(),
+            scheduled,
+            ..Checkpoint::default()
+        }
+    }
+
+    /// Reconcile the agenda with a checkpoint
+    ///
+    /// `fact_ids` maps checkpointed fact ids to the ids of the re-asserted
+    /// facts. Fired combinations are restored and their re-derived activations
+    /// dropped, and re-derived activations that were pending get back their
+    /// checkpointed recency. Re-derived timer matches are due when their
+    /// checkpointed time left runs out, and those that fired for good are
+    /// dropped. Entries referencing a missing fact are discarded.
+    pub fn restore(
+        &mut self,
+        checkpoint: &Checkpoint,
+        fact_ids: &HashMap<FactId, FactId>,
+    ) -> Result<()> {
+        for fired in &checkpoint.fired {
+            if let Some(facts) = checkpoint::remap(&fired.facts, fact_ids) {
+                self.remember_fired((fired.rule.clone(), facts));
+            }
+        }
+
+        let recencies: HashMap<MatchKey, (u64, Option<Priority>)> = checkpoint
+            .pending
+            .iter()
+            .filter_map(|pending| {
+                let facts = checkpoint::remap(&pending.fact_ids(), fact_ids)?;
+                Some((
+                    (pending.rule.clone(), facts),
+                    (pending.recency, pending.salience),
+                ))
+            })
+            .collect();
+
+        let mut restored = Vec::new();
+        for group in self.groups.values_mut() {
+            restored.extend(group.remove_where(|activation| {
+                Self::match_key(activation)
+                    .is_some_and(|key| self.fired.contains(&key) || recencies.contains_key(&key))
+            }));
+        }
+        for activation in restored {
+            self.unlink(&activation);
+            let key = Self::match_key(&activation);
+            if let Some((recency, salience)) = key.and_then(|key| recencies.get(&key).copied()) {
+                let salience = salience.unwrap_or_else(|| activation.salience());
+                self.enqueue(Arc::new(activation.rebuild(
+                    activation.match_data.clone(),
+                    recency,
+                    salience,
+                )))?;
+            } else {
+                self.listeners
+                    .notify(|l| l.on_activation_cancelled(&activation));
+            }
+        }
+
+        let due_in: HashMap<MatchKey, Duration> = checkpoint
+            .scheduled
+            .iter()
+            .filter_map(|scheduled| {
+                let facts = checkpoint::remap(&scheduled.fact_ids(), fact_ids)?;
+                Some(((scheduled.rule.clone(), facts), scheduled.due_in))
+            })
+            .collect();
+        let now = self.clock.now();
+        self.timers.retain_mut(|timer| {
+            let Some(key) = Self::match_key(&timer.activation) else {
+                return true;
+            };
+            match due_in.get(&key) {
+                Some(due_in) => {
+                    timer.due = now + *due_in;
+                    true
+                }
+                None => !self.fired.contains(&key),
+            }
+        });
+
+        for group in &checkpoint.focus_stack {
+            self.add_agenda_group(group.clone());
+        }
+        if !checkpoint.focus_stack.is_empty() {
+            self.focus_stack = checkpoint.focus_stack.clone();
+        }
+        Ok(())
+    }
+
+    /// Get the unfocused groups whose activations have waited longer than `threshold`
+    pub fn starved_groups(&self, threshold: Duration) -> Vec<StarvedGroup> {
+        let focused = self
+            .get_focused()
+            .map(|name| self.group_hierarchy(name))
+            .unwrap_or_default();
+        let mut starved: Vec<_> = self
+            .groups
+            .iter()
+            .filter(|(name, group)| !group.is_empty() && !focused.contains(name))
+            .filter_map(|(name, group)| {
+                let waiting_since = group.waiting_since?;
+                (waiting_since.elapsed() >= threshold).then(|| StarvedGroup {
+                    name: name.clone(),
+                    pending: group.activations.len(),
+                    waiting_since,
+                })
+            })
+            .collect();
+        starved.sort_by(|a, b| a.name.cmp(&b.name));
+        starved
+    }
+
+    /// Check if the agenda is empty
+    pub fn is_empty(&self) -> bool {
+        // Check if focused groups or their children have any activations
+        !self
+            .focus_stack
+            .iter()
+            .flat_map(|focused| self.group_hierarchy(focused))
+            .any(|name| {
+                self.groups
+                    .get(&name)
+                    .is_some_and(|group| !group.is_empty())
+            })
+    }
+
+    /// Iterate over the pending activations of every agenda group
+    pub fn activations(&self) -> impl Iterator<Item = &Arc<Activation>> {
+        self.groups.values().flat_map(AgendaGroup::iter)
+    }
+
+    /// Check if any agenda group, focused or not, has pending activations
+    pub fn has_pending(&self) -> bool {
+        self.groups.values().any(|group| !group.is_empty())
     }
 
     /// Clear all activations from all groups
@@ -262,14 +1438,19 @@ impl Agenda {
         for group in self.groups.values_mut() {
             group.clear();
         }
+        self.fact_links.clear();
+        self.timers.clear();
     }
 
     /// Dispose of the agenda
     pub fn dispose(&mut self) {
         self.clear();
         self.groups.clear();
+        self.group_order.clear();
         self.focus_stack.clear();
         self.registered_rules.clear();
+        self.fired.clear();
+        self.fired_links.clear();
     }
 }
 
@@ -282,7 +1463,26 @@ impl Default for Agenda {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rule::{Match, Rule};
+    use crate::fact::FactHandle;
+    use crate::rule::{Match, Priority, Rule};
+
+    fn create_activation_for_fact(
+        name: &str,
+        group: &str,
+        fact: Arc<FactHandle>,
+    ) -> Arc<Activation> {
+        let rule = Arc::new(
+            Rule::new(name)
+                .then(|_, _| Ok(()))
+                .agenda_group(group)
+                .build()
+                .unwrap(),
+        );
+
+        let mut match_data = Match::new();
+        match_data.insert("f".to_string(), fact);
+        Arc::new(Activation::new(rule, match_data, 0))
+    }
 
     fn create_test_activation(name: &str, priority: Priority, recency: u64) -> Arc<Activation> {
         let rule = Arc::new(
@@ -337,6 +1537,144 @@ mod tests {
         assert_eq!(first.rule.name, "high");
     }
 
+    #[test]
+    fn test_custom_conflict_resolution() {
+        // Rules named "urgent*" fire before everything else, then by salience
+        let urgent_first = |a: &Activation, b: &Activation| {
+            a.rule
+                .name
+                .starts_with("urgent")
+                .cmp(&b.rule.name.starts_with("urgent"))
+        };
+        let mut agenda = Agenda::with_strategies(vec![
+            ConflictResolution::custom(urgent_first),
+            ConflictResolution::Salience,
+        ]);
+
+        agenda
+            .insert(create_test_activation("high", 10, 1))
+            .unwrap();
+        agenda
+            .insert(create_test_activation("urgent_low", 1, 2))
+            .unwrap();
+        agenda.insert(create_test_activation("low", 1, 3)).unwrap();
+
+        let order: Vec<_> = std::iter::from_fn(|| agenda.pop())
+            .map(|activation| activation.rule.name.clone())
+            .collect();
+        assert_eq!(order, ["urgent_low", "high", "low"]);
+    }
+
+    #[test]
+    fn test_random_conflict_resolution() {
+        let order = |seed| {
+            let mut agenda = Agenda::with_strategies(vec![
+                ConflictResolution::Salience,
+                ConflictResolution::Random { seed },
+            ]);
+            agenda
+                .insert(create_test_activation("urgent", 10, 0))
+                .unwrap();
+            for recency in 1..=8 {
+                agenda
+                    .insert(create_test_activation(&format!("r{}", recency), 1, recency))
+                    .unwrap();
+            }
+            std::iter::from_fn(|| agenda.pop())
+                .map(|activation| activation.rule.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let first = order(7);
+        assert_eq!(first[0], "urgent");
+        assert_eq!(first, order(7));
+        assert!((0..8).any(|seed| order(seed) != first));
+        assert_ne!(
+            ConflictResolution::Random { seed: 1 },
+            ConflictResolution::Random { seed: 2 }
+        );
+    }
+
+    fn batch_activation(
+        name: &str,
+        priority: Priority,
+        customer: &str,
+        concurrency: &str,
+    ) -> Arc<Activation> {
+        let mut rule = Rule::new(name).then(|_, _| Ok(())).priority(priority);
+        rule = match concurrency {
+            "keyed" => rule.concurrency_key(|m| m.get_as::<String>("c").unwrap().clone()),
+            "independent" => rule.independent(),
+            _ => rule,
+        };
+        let mut match_data = Match::new();
+        match_data.insert(
+            "c".to_string(),
+            Arc::new(FactHandle::new(customer.to_string(), 0)),
+        );
+        Arc::new(Activation::new(
+            Arc::new(rule.build().unwrap()),
+            match_data,
+            0,
+        ))
+    }
+
+    fn pop_batches(agenda: &mut Agenda) -> Vec<Vec<String>> {
+        let mut batches = Vec::new();
+        loop {
+            let batch: Vec<_> = agenda
+                .pop_batch(10)
+                .iter()
+                .map(|activation| activation.rule.name.clone())
+                .collect();
+            if batch.is_empty() {
+                return batches;
+            }
+            batches.push(batch);
+        }
+    }
+
+    #[test]
+    fn test_pop_batch_by_concurrency() {
+        let mut agenda = Agenda::new();
+        for activation in [
+            batch_activation("alice_1", 5, "alice", "keyed"),
+            batch_activation("alice_2", 4, "alice", "keyed"),
+            batch_activation("bob", 3, "bob", "keyed"),
+            batch_activation("audit", 2, "bob", "independent"),
+            batch_activation("close", 1, "bob", "exclusive"),
+        ] {
+            agenda.insert(activation).unwrap();
+        }
+
+        assert_eq!(
+            pop_batches(&mut agenda),
+            [
+                vec!["alice_1", "bob", "audit"],
+                vec!["alice_2"],
+                vec!["close"]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pop_batch_stops_at_exclusive_activation() {
+        let mut agenda = Agenda::new();
+        for activation in [
+            batch_activation("alice", 5, "alice", "keyed"),
+            batch_activation("close", 4, "alice", "exclusive"),
+            batch_activation("bob", 3, "bob", "keyed"),
+            batch_activation("audit", 2, "bob", "independent"),
+        ] {
+            agenda.insert(activation).unwrap();
+        }
+
+        assert_eq!(
+            pop_batches(&mut agenda),
+            [vec!["alice"], vec!["close"], vec!["bob", "audit"]]
+        );
+    }
+
     #[test]
     fn test_focus_management() {
         let mut agenda = Agenda::new();
@@ -345,4 +1683,349 @@ mod tests {
 
         assert_eq!(agenda.get_focused(), Some("group1"));
     }
+
+    #[test]
+    fn test_nested_agenda_groups() {
+        let mut agenda = Agenda::new();
+        agenda.add_agenda_group("billing/refunds/partial".to_string());
+        agenda.add_agenda_group("billing/invoices".to_string());
+        assert_eq!(
+            agenda.group_hierarchy("billing"),
+            [
+                "billing",
+                "billing/refunds",
+                "billing/refunds/partial",
+                "billing/invoices"
+            ]
+        );
+
+        for (name, group) in [
+            ("invoice", "billing/invoices"),
+            ("partial", "billing/refunds/partial"),
+            ("billing", "billing"),
+            ("shipping", "shipping"),
+        ] {
+            let fact = Arc::new(FactHandle::new(name.to_string(), 0));
+            agenda
+                .insert(create_activation_for_fact(name, group, fact))
+                .unwrap();
+        }
+
+        agenda.set_focus("billing".to_string()).unwrap();
+        let order: Vec<_> = std::iter::from_fn(|| agenda.pop())
+            .map(|activation| activation.rule.name.clone())
+            .collect();
+        assert_eq!(order, ["billing", "partial", "invoice"]);
+        assert_eq!(agenda.get_focused(), Some("main"));
+    }
+
+    #[test]
+    fn test_clear_and_deactivate_groups() {
+        let mut agenda = Agenda::new();
+        agenda.set_auto_deactivate("validate", true);
+        for (name, group) in [
+            ("check", "validate"),
+            ("price", "pricing"),
+            ("default", "main"),
+        ] {
+            let fact = Arc::new(FactHandle::new(name.to_string(), 0));
+            agenda
+                .insert(create_activation_for_fact(name, group, fact))
+                .unwrap();
+        }
+
+        agenda.set_focus("pricing".to_string()).unwrap();
+        agenda.set_focus("validate".to_string()).unwrap();
+        assert_eq!(agenda.pop().unwrap().rule.name, "check");
+        assert_eq!(agenda.get_focused(), Some("pricing"));
+
+        assert_eq!(agenda.clear_group("pricing").unwrap().len(), 1);
+        assert_eq!(agenda.get_focused(), Some("pricing"));
+        agenda.deactivate_group("pricing").unwrap();
+        assert_eq!(agenda.get_focused(), Some("main"));
+        agenda.deactivate_group("main").unwrap();
+        assert_eq!(agenda.get_focused(), Some("main"));
+
+        assert_eq!(agenda.pop().unwrap().rule.name, "default");
+        assert!(agenda.clear_group("missing").is_err());
+    }
+
+    #[test]
+    fn test_cancel_for_fact() {
+        let mut agenda = Agenda::new();
+        let kept = Arc::new(FactHandle::new(1u32, 0));
+        let retracted = Arc::new(FactHandle::new(2u32, 1));
+
+        agenda
+            .insert(create_activation_for_fact(
+                "keep",
+                "main",
+                Arc::clone(&kept),
+            ))
+            .unwrap();
+        agenda
+            .insert(create_activation_for_fact(
+                "drop",
+                "main",
+                Arc::clone(&retracted),
+            ))
+            .unwrap();
+        agenda
+            .insert(create_activation_for_fact(
+                "drop_other",
+                "other",
+                Arc::clone(&retracted),
+            ))
+            .unwrap();
+
+        let cancelled = agenda.cancel_for_fact(retracted.id);
+        assert_eq!(cancelled.len(), 2);
+
+        assert_eq!(agenda.pop().unwrap().rule.name, "keep");
+        assert!(agenda.pop().is_none());
+        assert!(agenda.cancel_for_fact(retracted.id).is_empty());
+    }
+
+    #[test]
+    fn test_fact_links_released_on_pop() {
+        let mut agenda = Agenda::new();
+        let fact = Arc::new(FactHandle::new(1u32, 0));
+
+        agenda
+            .insert(create_activation_for_fact(
+                "rule",
+                "main",
+                Arc::clone(&fact),
+            ))
+            .unwrap();
+        agenda.pop().unwrap();
+
+        assert!(agenda.fact_links.is_empty());
+    }
+
+    #[test]
+    fn test_refraction() {
+        let mut agenda = Agenda::new();
+        let fact = Arc::new(FactHandle::new(1u32, 0));
+        let activation = || create_activation_for_fact("rule", "main", Arc::clone(&fact));
+
+        agenda.insert(activation()).unwrap();
+        agenda.pop().unwrap();
+        assert!(agenda.has_fired("rule", &[fact.id]));
+
+        // The same rule and facts are not activated again
+        agenda.insert(activation()).unwrap();
+        assert!(agenda.is_empty());
+
+        // Until the fact changes
+        agenda.forget_fired(fact.id);
+        agenda.insert(activation()).unwrap();
+        assert!(agenda.pop().is_some());
+    }
+
+    #[test]
+    fn test_fired_links_released_by_every_fact() {
+        let mut agenda = Agenda::new();
+        let rule = Arc::new(
+            Rule::new("customer_order")
+                .then(|_, _| Ok(()))
+                .build()
+                .unwrap(),
+        );
+        let customer = Arc::new(FactHandle::new("alice".to_string(), 0));
+        let fire = |agenda: &mut Agenda, order: &Arc<FactHandle>| {
+            let mut match_data = Match::new();
+            match_data.insert("c".to_string(), Arc::clone(&customer));
+            match_data.insert("o".to_string(), Arc::clone(order));
+            agenda
+                .insert(Arc::new(Activation::new(Arc::clone(&rule), match_data, 0)))
+                .unwrap();
+            agenda.pop().unwrap();
+        };
+
+        // A modified order fires again with the long-lived customer
+        let order = Arc::new(FactHandle::new(1u32, 1));
+        for _ in 0..10 {
+            fire(&mut agenda, &order);
+            agenda.forget_fired(order.id);
+        }
+        // Retracted orders are replaced by new ones
+        for n in 0..10u32 {
+            let order = Arc::new(FactHandle::new(n, 2));
+            fire(&mut agenda, &order);
+            agenda.forget_fired(order.id);
+        }
+        assert!(agenda.fired.is_empty());
+        assert!(agenda.fired_links.is_empty());
+
+        fire(&mut agenda, &order);
+        assert_eq!(agenda.fired_links[&customer.id].len(), 1);
+        agenda
+            .refresh_fact(&order, &HashSet::from(["customer_order".to_string()]))
+            .unwrap();
+        assert!(agenda.fired.is_empty());
+        assert!(agenda.fired_links.is_empty());
+    }
+
+    #[test]
+    fn test_refresh_fact_keeps_salience_generation_and_explanation() {
+        let mut agenda = Agenda::new();
+        let fact = Arc::new(FactHandle::new(1u32, 0));
+        let rule = Arc::new(Rule::new("kept").then(|_, _| Ok(())).build().unwrap());
+        let mut match_data = Match::new();
+        match_data.insert("n".to_string(), Arc::clone(&fact));
+        let activation = Activation::with_salience(rule, match_data, 3, 42);
+        activation.record_explanation();
+        agenda.set_generation(7);
+        agenda.insert(Arc::new(activation)).unwrap();
+
+        agenda.set_generation(8);
+        let modified = Arc::new(FactHandle {
+            recency: 4,
+            ..FactHandle::clone(&fact)
+        });
+        agenda.refresh_fact(&modified, &HashSet::new()).unwrap();
+
+        let refreshed = agenda.pop().unwrap();
+        assert_eq!(refreshed.salience(), 42);
+        assert_eq!(refreshed.recency, 3);
+        assert_eq!(refreshed.generation(), Some(7));
+        assert!(refreshed.explain().recorded);
+        assert_eq!(refreshed.match_data.get("n").unwrap().recency, 4);
+    }
+
+    #[test]
+    fn test_restore_keeps_checkpointed_salience() {
+        let boosted = Arc::new(Rule::new("boosted").then(|_, _| Ok(())).build().unwrap());
+        let plain = Arc::new(
+            Rule::new("plain")
+                .then(|_, _| Ok(()))
+                .priority(5)
+                .build()
+                .unwrap(),
+        );
+        let facts = [
+            Arc::new(FactHandle::new(1u32, 0)),
+            Arc::new(FactHandle::new(2u32, 1)),
+        ];
+        let match_of = |fact: &Arc<FactHandle>| {
+            let mut match_data = Match::new();
+            match_data.insert("n".to_string(), Arc::clone(fact));
+            match_data
+        };
+
+        // "boosted" inherited a salience above "plain" before the checkpoint
+        let mut agenda = Agenda::new();
+        agenda
+            .insert(Arc::new(Activation::with_salience(
+                Arc::clone(&boosted),
+                match_of(&facts[0]),
+                0,
+                10,
+            )))
+            .unwrap();
+        agenda
+            .insert(Arc::new(Activation::new(
+                Arc::clone(&plain),
+                match_of(&facts[1]),
+                1,
+            )))
+            .unwrap();
+        let checkpoint = agenda.checkpoint("test");
+
+        // After a restart the network re-derives both at their rule priority
+        let mut restored = Agenda::new();
+        for (rule, fact) in [(&boosted, &facts[0]), (&plain, &facts[1])] {
+            restored
+                .insert(Arc::new(Activation::new(
+                    Arc::clone(rule),
+                    match_of(fact),
+                    0,
+                )))
+                .unwrap();
+        }
+        let fact_ids = facts.iter().map(|fact| (fact.id, fact.id)).collect();
+        restored.restore(&checkpoint, &fact_ids).unwrap();
+
+        let first = restored.pop().unwrap();
+        assert_eq!(first.rule.name, "boosted");
+        assert_eq!(first.salience(), 10);
+        assert_eq!(restored.pop().unwrap().rule.name, "plain");
+    }
+
+    #[test]
+    fn test_activation_group_cancels_siblings() {
+        let mut agenda = Agenda::new();
+        let activation = |name: &str, priority: Priority| {
+            let rule = Rule::new(name)
+                .then(|_, _| Ok(()))
+                .priority(priority)
+                .activation_group("discount")
+                .build()
+                .unwrap();
+            Arc::new(Activation::new(Arc::new(rule), Match::new(), 0))
+        };
+
+        agenda.insert(activation("ten_percent", 1)).unwrap();
+        agenda.insert(activation("twenty_percent", 2)).unwrap();
+        agenda
+            .insert(create_test_activation("unrelated", 0, 0))
+            .unwrap();
+
+        assert_eq!(agenda.pop().unwrap().rule.name, "twenty_percent");
+        assert_eq!(agenda.pop().unwrap().rule.name, "unrelated");
+        assert!(agenda.pop().is_none());
+    }
+
+    #[test]
+    fn test_sample_rate_is_deterministic() {
+        let rule = Arc::new(
+            Rule::new("audit")
+                .then(|_, _| Ok(()))
+                .sample_rate(0.25)
+                .build()
+                .unwrap(),
+        );
+        let facts: Vec<_> = (0..400).map(|i| Arc::new(FactHandle::new(i, 0))).collect();
+
+        let sampled = |seed: u64| {
+            let mut agenda = Agenda::new();
+            agenda.set_sample_seed(seed);
+            let mut sampled = Vec::new();
+            for fact in &facts {
+                let mut match_data = Match::new();
+                match_data.insert("f".to_string(), Arc::clone(fact));
+                agenda
+                    .insert(Arc::new(Activation::new(Arc::clone(&rule), match_data, 0)))
+                    .unwrap();
+            }
+            while let Some(activation) = agenda.pop() {
+                sampled.push(activation.match_data.fact_ids()[0]);
+            }
+            sampled.sort();
+            sampled
+        };
+
+        let first = sampled(7);
+        assert!((60..140).contains(&first.len()));
+        assert_eq!(first, sampled(7));
+        assert_ne!(first, sampled(8));
+    }
+
+    #[test]
+    fn test_insert_all_orders_batch() {
+        let mut agenda = Agenda::new();
+        agenda
+            .insert_all(vec![
+                create_test_activation("low", 1, 0),
+                create_test_activation("high", 10, 1),
+                create_test_activation("mid", 5, 2),
+            ])
+            .unwrap();
+
+        let order: Vec<_> = std::iter::from_fn(|| agenda.pop())
+            .map(|a| a.rule.name.clone())
+            .collect();
+        assert_eq!(order, vec!["high", "mid", "low"]);
+    }
 }
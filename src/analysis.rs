@@ -0,0 +1,441 @@
+//! Static checks of a flow's rules
+//!
+//! [`Flow::analyze`] reports rules that can never fire, or never fire as
+//! intended, before a rulebase is deployed. A condition is only understood
+//! when its meaning is visible: [`Expression`]s, typed
+//! [`Field`](crate::field::Field) constraints and the conditions of rule
+//! files. Closures passed to `with_filter` are opaque, so rules built from
+//! them are only compared by their descriptions.
+//!
+//! [`Flow::analyze`]: crate::flow::Flow::analyze
+//! [`Expression`]: crate::expr::Expression
+
+use crate::rule::Rule;
+use crate::value::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// How a field compares with a constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl CompareOp {
+    /// The operator with its operands swapped, such as `>` for `<`
+    pub fn flip(self) -> Self {
+        match self {
+            CompareOp::Lt => CompareOp::Gt,
+            CompareOp::Le => CompareOp::Ge,
+            CompareOp::Gt => CompareOp::Lt,
+            CompareOp::Ge => CompareOp::Le,
+            op => op,
+        }
+    }
+
+    /// Check if a field ordered this way against the constant satisfies the operator
+    pub fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+}
+
+/// A comparison of a fact's field with a constant, such as `total > 100`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Name of the field
+    pub field: String,
+    /// How the field compares with the constant
+    pub op: CompareOp,
+    /// The constant
+    pub value: Value,
+}
+
+impl Comparison {
+    /// Create a comparison
+    pub fn new(field: impl Into<String>, op: CompareOp, value: Value) -> Self {
+        Self {
+            field: field.into(),
+            op,
+            value,
+        }
+    }
+
+    /// Check if a value of the field satisfies the comparison, if they are comparable
+    pub fn accepts(&self, value: &Value) -> Option<bool> {
+        value
+            .compare(&self.value)
+            .map(|ordering| self.op.holds(ordering))
+    }
+
+    /// Check if some value of the field satisfies both comparisons
+    ///
+    /// Comparisons whose constants cannot be ordered are assumed compatible.
+    pub fn compatible(&self, other: &Comparison) -> bool {
+        if self.field != other.field {
+            return true;
+        }
+        match (self.op, other.op) {
+            (CompareOp::Eq, _) => other.accepts(&self.value) != Some(false),
+            (_, CompareOp::Eq) => self.accepts(&other.value) != Some(false),
+            (CompareOp::Ne, _) | (_, CompareOp::Ne) => true,
+            _ => {
+                let (Some(a), Some(b)) = (self.bound(), other.bound()) else {
+                    return true;
+                };
+                let (lower, upper) = match (a, b) {
+                    (Bound::Lower(lower), Bound::Upper(upper))
+                    | (Bound::Upper(upper), Bound::Lower(lower)) => (lower, upper),
+                    _ => return true,
+                };
+                match lower.0.compare(&upper.0) {
+                    Some(Ordering::Greater) => false,
+                    Some(Ordering::Equal) => lower.1 && upper.1,
+                    _ => true,
+                }
+            }
+        }
+    }
+
+    /// The range limit set by an ordering comparison, with whether it is
+    /// inclusive; integer limits are made inclusive
+    fn bound(&self) -> Option<Bound> {
+        let inclusive = |value: &Value, step: i64| match value {
+            Value::Int(i) => i.checked_add(step).map(|i| (Value::Int(i), true)),
+            value => Some((value.clone(), false)),
+        };
+        Some(match self.op {
+            CompareOp::Gt => Bound::Lower(inclusive(&self.value, 1)?),
+            CompareOp::Ge => Bound::Lower((self.value.clone(), true)),
+            CompareOp::Lt => Bound::Upper(inclusive(&self.value, -1)?),
+            CompareOp::Le => Bound::Upper((self.value.clone(), true)),
+            CompareOp::Eq | CompareOp::Ne => return None,
+        })
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Value::String(s) => write!(f, "{} {} {:?}", self.field, self.op.symbol(), s),
+            value => write!(f, "{} {} {}", self.field, self.op.symbol(), value),
+        }
+    }
+}
+
+enum Bound {
+    Lower((Value, bool)),
+    Upper((Value, bool)),
+}
+
+/// What a [`Warning`] is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    /// A pattern's conditions contradict each other, so the rule never fires
+    Unreachable,
+    /// Rules have identical conditions
+    DuplicateConditions,
+    /// A join key reads another type than the facts it joins, so the rule never fires
+    NeverJoins,
+    /// A rule of higher priority in the same activation group matches
+    /// whenever the rule does, so the rule never fires
+    ShadowedSalience,
+}
+
+/// A problem found by [`Flow::analyze`](crate::flow::Flow::analyze)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// What the warning is about
+    pub kind: WarningKind,
+    /// The rules involved, the one the warning is about first
+    pub rules: Vec<String>,
+    /// A description of the problem
+    pub message: String,
+}
+
+impl Warning {
+    fn new(kind: WarningKind, rules: Vec<String>, message: String) -> Self {
+        Self {
+            kind,
+            rules,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Check rules, given in load order
+pub(crate) fn analyze(rules: &[Arc<Rule>]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for rule in rules {
+        unreachable(rule, &mut warnings);
+        never_joins(rule, &mut warnings);
+    }
+    duplicates(rules, &mut warnings);
+    shadowed(rules, &mut warnings);
+    warnings
+}
+
+fn unreachable(rule: &Rule, warnings: &mut Vec<Warning>) {
+    for pattern in &rule.patterns {
+        let comparisons = pattern.comparisons();
+        let conflict = comparisons.iter().enumerate().find_map(|(i, a)| {
+            comparisons[i + 1..]
+                .iter()
+                .find(|b| !a.compatible(b))
+                .map(|b| (a, b))
+        });
+        if let Some((a, b)) = conflict {
+            warnings.push(Warning::new(
+                WarningKind::Unreachable,
+                vec![rule.name.clone()],
+                format!(
+                    "Rule '{}': pattern '{}' requires both `{}` and `{}`, so it never matches",
+                    rule.name,
+                    pattern.alias(),
+                    a,
+                    b
+                ),
+            ));
+        }
+    }
+}
+
+fn never_joins(rule: &Rule, warnings: &mut Vec<Warning>) {
+    for (index, pattern) in rule.patterns.iter().enumerate() {
+        for key in pattern.join_keys() {
+            let bound = rule.patterns[..index]
+                .iter()
+                .find(|earlier| earlier.alias() == key.alias);
+            let mismatched = bound.is_some_and(|bound| bound.type_id() != key.left_type())
+                || pattern.type_id() != key.right_type();
+            if mismatched {
+                warnings.push(Warning::new(
+                    WarningKind::NeverJoins,
+                    vec![rule.name.clone()],
+                    format!(
+                        "Rule '{}': pattern '{}' joins on '{}' with a key reading another fact type, so it never matches",
+                        rule.name,
+                        pattern.alias(),
+                        key.alias
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn duplicates(rules: &[Arc<Rule>], warnings: &mut Vec<Warning>) {
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for rule in rules.iter().filter(|rule| !rule.patterns.is_empty()) {
+        let conditions = format!("{:?}", rule.patterns);
+        match index.get(&conditions) {
+            Some(&group) => groups[group].1.push(rule.name.clone()),
+            None => {
+                index.insert(conditions.clone(), groups.len());
+                groups.push((conditions, vec![rule.name.clone()]));
+            }
+        }
+    }
+    for (_, names) in groups.into_iter().filter(|(_, names)| names.len() > 1) {
+        let quoted: Vec<_> = names.iter().map(|name| format!("'{}'", name)).collect();
+        warnings.push(Warning::new(
+            WarningKind::DuplicateConditions,
+            names,
+            format!("Rules {} have identical conditions", quoted.join(", ")),
+        ));
+    }
+}
+
+fn shadowed(rules: &[Arc<Rule>], warnings: &mut Vec<Warning>) {
+    for rule in rules {
+        let Some(group) = &rule.activation_group else {
+            continue;
+        };
+        let conditions: Vec<_> = rule.patterns.iter().map(|p| format!("{:?}", p)).collect();
+        let shadowing = rules.iter().find(|other| {
+            other.activation_group.as_ref() == Some(group)
+                && other.agenda_group == rule.agenda_group
+                && other.salience.is_none()
+                && rule.salience.is_none()
+                && other.sample_rate.is_none()
+                && other.priority > rule.priority
+                && !other.patterns.is_empty()
+                && other
+                    .patterns
+                    .iter()
+                    .all(|p| conditions.contains(&format!("{:?}", p)))
+        });
+        if let Some(other) = shadowing {
+            warnings.push(Warning::new(
+                WarningKind::ShadowedSalience,
+                vec![rule.name.clone(), other.name.clone()],
+                format!(
+                    "Rule '{}' never fires: '{}' has a higher priority in activation group '{}' and matches whenever it does",
+                    rule.name, other.name, group
+                ),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint::Constraint;
+    use crate::expr::Expression;
+    use crate::flow::Flow;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use crate::value::{register_fields, Fields};
+
+    #[derive(Debug, Clone)]
+    struct Order {
+        total: i64,
+        status: String,
+    }
+
+    impl Fields for Order {
+        fn field(&self, name: &str) -> Option<Value> {
+            match name {
+                "total" => Some(self.total.into()),
+                "status" => Some(self.status.clone().into()),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Customer {
+        id: i64,
+    }
+
+    fn order(alias: &str, conditions: &[&str]) -> Box<dyn Pattern> {
+        let mut pattern = ObjectPattern::<Order>::new(alias);
+        for condition in conditions {
+            pattern = pattern.with_expression(condition).unwrap();
+        }
+        Box::new(pattern)
+    }
+
+    fn add(flow: &mut Flow, name: &str, priority: i32, patterns: Vec<Box<dyn Pattern>>) {
+        let mut rule = Rule::new(name)
+            .priority(priority)
+            .activation_group("discounts")
+            .then(|_, _| Ok(()));
+        for pattern in patterns {
+            rule = rule.when(pattern);
+        }
+        flow.add_rule(rule.build().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_compatible_comparisons() {
+        let parse = |source: &str| Expression::parse(source).unwrap().comparisons();
+        let conflict = |source: &str| {
+            let comparisons = parse(source);
+            !comparisons[0].compatible(&comparisons[1])
+        };
+        assert!(conflict("total > 100 && total < 50"));
+        assert!(conflict("total > 1 && total < 2"));
+        assert!(conflict("total == 3 && 5 <= total"));
+        assert!(conflict("status == 'open' && status != 'open'"));
+        assert!(!conflict("total >= 2 && total <= 2"));
+        assert!(!conflict("total > 1 && total < 3"));
+        assert!(!conflict("total > 1.5 && total < 1.6"));
+        assert!(!conflict("total != 1 && total != 2"));
+        assert!(!conflict("status == 'open' && status > 5"));
+        assert_eq!(parse("total > -5 || total < 0"), []);
+        assert_eq!(parse("total > -5")[0].to_string(), "total > -5");
+    }
+
+    #[test]
+    fn test_analyze() {
+        register_fields::<Order>();
+        let mut flow = Flow::new("analysis");
+        add(
+            &mut flow,
+            "impossible",
+            0,
+            vec![order("o", &["total > 100", "total < 50"])],
+        );
+        add(&mut flow, "large", 10, vec![order("o", &["total > 100"])]);
+        let customer = Box::new(ObjectPattern::<Customer>::new("c"));
+        add(
+            &mut flow,
+            "large_customer",
+            5,
+            vec![order("o", &["total > 100"]), customer],
+        );
+        add(
+            &mut flow,
+            "also_large",
+            1,
+            vec![order("o", &["total > 100"])],
+        );
+        let join =
+            ObjectPattern::<Order>::new("o").join_on("c", |c: &Customer| c.id, |o: &Order| o.total);
+        add(
+            &mut flow,
+            "mismatched",
+            0,
+            vec![order("c", &[]), Box::new(join)],
+        );
+
+        let warnings = flow.analyze();
+        let found: Vec<_> = warnings
+            .iter()
+            .map(|w| (w.kind, w.rules[0].as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (WarningKind::Unreachable, "impossible"),
+                (WarningKind::NeverJoins, "mismatched"),
+                (WarningKind::DuplicateConditions, "large"),
+                (WarningKind::ShadowedSalience, "large_customer"),
+                (WarningKind::ShadowedSalience, "also_large"),
+            ]
+        );
+        assert_eq!(warnings[2].rules, ["large", "also_large"]);
+        assert_eq!(warnings[3].rules, ["large_customer", "large"]);
+        assert!(warnings[0]
+            .to_string()
+            .contains("`total > 100` and `total < 50`"));
+    }
+}
@@ -0,0 +1,235 @@
+//! Audit trails of session activity
+//!
+//! After [`Session::record_audit_log`], a session logs every assert, retract,
+//! modify and rule firing with a wall clock timestamp. Fact changes carry a
+//! snapshot of the fact: its JSON form for types registered in the given
+//! [`FixtureTypes`], its `Debug` output otherwise. [`Session::audit_log`]
+//! returns the log, which exports to JSON for decision auditing.
+//!
+//! [`Session::record_audit_log`]: crate::session::Session::record_audit_log
+//! [`Session::audit_log`]: crate::session::Session::audit_log
+
+use crate::clock::{SystemTime, UNIX_EPOCH};
+use crate::error::{Error, Result};
+use crate::event::EventListener;
+use crate::fact::{FactHandle, FactId};
+use crate::fixture::FixtureTypes;
+use crate::rule::Activation;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+/// A fact as it was when it changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FactSnapshot {
+    /// Id of the fact
+    pub id: FactId,
+    /// Registered name of the fact's type, or its Rust type name
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// The serialized fact, or its `Debug` output as a string
+    pub fact: serde_json::Value,
+}
+
+/// What happened in an audited session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A fact was asserted
+    Asserted(FactSnapshot),
+    /// A fact was retracted
+    Retracted(FactSnapshot),
+    /// A fact was modified; the snapshot is the new version
+    Modified(FactSnapshot),
+    /// A rule's action ran successfully
+    Fired {
+        /// Name of the rule
+        rule: String,
+        /// Matched fact ids by alias
+        facts: BTreeMap<String, FactId>,
+    },
+}
+
+/// An event of an audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch when the event happened
+    pub timestamp: u64,
+    /// The event
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// The events of a session, in order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditLog {
+    /// Name of the session's flow
+    pub flow: String,
+    /// The recorded events
+    pub entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    /// Serialize this log to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Execution(format!("Failed to serialize audit log: {}", e)))
+    }
+
+    /// Deserialize a log from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| Error::Execution(format!("Failed to deserialize audit log: {}", e)))
+    }
+}
+
+/// Records a session's events into an audit log
+#[derive(Debug)]
+pub(crate) struct AuditRecorder {
+    types: FixtureTypes,
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditRecorder {
+    pub(crate) fn new(types: FixtureTypes) -> Self {
+        Self {
+            types,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Vec<AuditEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Get the log recorded so far
+    pub(crate) fn log(&self, flow: &str) -> AuditLog {
+        AuditLog {
+            flow: flow.to_string(),
+            entries: self.entries().clone(),
+        }
+    }
+
+    fn record(&self, event: AuditEvent) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.entries().push(AuditEntry { timestamp, event });
+    }
+
+    fn snapshot(&self, fact: &FactHandle) -> FactSnapshot {
+        let (type_name, fact_value) = match self.types.serialize(fact) {
+            Ok(serialized) => (serialized.type_name, serialized.fact),
+            Err(_) => (
+                fact.type_name().to_string(),
+                serde_json::Value::String(format!("{:?}", fact.fact)),
+            ),
+        };
+        FactSnapshot {
+            id: fact.id,
+            type_name,
+            fact: fact_value,
+        }
+    }
+}
+
+impl EventListener for AuditRecorder {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        self.record(AuditEvent::Asserted(self.snapshot(fact)));
+    }
+
+    fn on_fact_retracted(&self, fact: &FactHandle) {
+        self.record(AuditEvent::Retracted(self.snapshot(fact)));
+    }
+
+    fn on_fact_modified(&self, fact: &FactHandle) {
+        self.record(AuditEvent::Modified(self.snapshot(fact)));
+    }
+
+    fn on_rule_fired(&self, activation: &Activation) {
+        let facts = activation
+            .match_data
+            .facts
+            .iter()
+            .map(|(alias, fact)| (alias.clone(), fact.id))
+            .collect();
+        self.record(AuditEvent::Fired {
+            rule: activation.rule.name.clone(),
+            facts,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use serde_json::json;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Application {
+        score: u32,
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let mut flow = Flow::new("underwriting");
+        flow.rule("approve")
+            .when(Box::new(
+                ObjectPattern::<Application>::new("a")
+                    .with_filter(|a| a.score > 700, "score > 700"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert("before".to_string()).unwrap();
+        assert!(session.audit_log().entries.is_empty());
+
+        session.record_audit_log(FixtureTypes::new().register::<Application>("Application"));
+        let id = session.assert(Application { score: 650 }).unwrap().id();
+        session
+            .modify_with(id, |a: &mut Application| a.score = 720)
+            .unwrap();
+        session.match_rules().await.unwrap();
+        let note = session.assert("note".to_string()).unwrap();
+        session.retract(note).unwrap();
+
+        let log = session.audit_log();
+        assert_eq!(log.flow, "underwriting");
+        let events: Vec<_> = log.entries.iter().map(|entry| &entry.event).collect();
+        assert_eq!(
+            events[..3],
+            [
+                &AuditEvent::Asserted(FactSnapshot {
+                    id,
+                    type_name: "Application".to_string(),
+                    fact: json!({"score": 650}),
+                }),
+                &AuditEvent::Modified(FactSnapshot {
+                    id,
+                    type_name: "Application".to_string(),
+                    fact: json!({"score": 720}),
+                }),
+                &AuditEvent::Fired {
+                    rule: "approve".to_string(),
+                    facts: BTreeMap::from([("a".to_string(), id)]),
+                },
+            ]
+        );
+        let AuditEvent::Retracted(snapshot) = &events[4] else {
+            panic!("expected a retraction, got {:?}", events[4]);
+        };
+        assert_eq!(snapshot.type_name, "alloc::string::String");
+        assert_eq!(snapshot.fact, json!("\"note\""));
+        assert!(log
+            .entries
+            .windows(2)
+            .all(|w| w[0].timestamp <= w[1].timestamp));
+
+        let json = log.to_json().unwrap();
+        assert!(json.contains("\"event\": \"fired\""));
+        assert_eq!(AuditLog::from_json(&json).unwrap(), log);
+    }
+}
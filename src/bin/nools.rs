@@ -0,0 +1,108 @@
+//! Command line tools for rule files in the nools DSL
+//!
+//! ```text
+//! nools watch <rules.nools> <facts.jsonl>
+//! nools test <rules.nools>
+//! ```
+//!
+//! `watch` replays the facts against the rules whenever either file changes
+//! and prints the fired rules and decisions that changed, see
+//! [`nools::watch`]. `test` runs the `test` blocks of a rule file, see
+//! [`nools::dsl::run_tests`].
+
+use nools::dsl::{run_tests, CompileOptions};
+use nools::fixture::FixtureTypes;
+use nools::watch::watch_rule_file;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+const USAGE: &str = "usage: nools watch <rules.nools> <facts.jsonl>
+       nools test <rules.nools>";
+
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["watch", rules, facts] => watch(rules, facts),
+        ["test", rules] => test(rules),
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn watch(rules: &str, facts: &str) -> ExitCode {
+    let mut watch = watch_rule_file(rules, facts, FixtureTypes::new(), CompileOptions::new())
+        .color(std::io::stdout().is_terminal());
+    println!("watching {} and {}", rules, facts);
+    loop {
+        if let Some(report) = block_on(watch.poll()) {
+            print!("{}", report);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn test(rules: &str) -> ExitCode {
+    let outcomes = std::fs::read_to_string(rules)
+        .map_err(|e| format!("Failed to read {}: {}", rules, e))
+        .and_then(|source| {
+            block_on(run_tests(&source, &CompileOptions::new())).map_err(|e| e.to_string())
+        });
+    let outcomes = match outcomes {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut failed = 0;
+    for outcome in &outcomes {
+        if outcome.passed() {
+            println!("ok {}", outcome.name);
+        } else {
+            failed += 1;
+            println!("FAILED {}", outcome.name);
+            for failure in &outcome.failures {
+                println!("  {}", failure);
+            }
+        }
+    }
+    println!("{} tests, {} failed", outcomes.len(), failed);
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Wakes the thread blocked on a future
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        thread::park();
+    }
+}
@@ -0,0 +1,108 @@
+//! Serializable checkpoints of a session's agenda and scratchpad
+//!
+//! Facts are not part of a checkpoint: after a restart the application
+//! re-asserts them and passes [`Session::restore`](crate::session::Session::restore)
+//! a map from the checkpointed fact ids to the new ones. Pending activations
+//! are re-derived by the network from the re-asserted facts, so an activation
+//! whose supporting facts are gone is never rehydrated. Matches of timer
+//! rules keep the time left until their next firing.
+
+use crate::error::{Error, Result};
+use crate::fact::FactId;
+use crate::rule::Priority;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// A pending activation in a checkpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingActivation {
+    /// Name of the rule
+    pub rule: String,
+    /// Agenda group of the rule
+    pub agenda_group: String,
+    /// Matched fact ids by alias
+    pub facts: BTreeMap<String, FactId>,
+    /// Recency of the activation
+    pub recency: u64,
+    /// Salience of the activation, including any inherited priority
+    ///
+    /// Absent from checkpoints taken before it was recorded; the salience of
+    /// the re-derived activation is kept then.
+    #[serde(default)]
+    pub salience: Option<Priority>,
+}
+
+impl PendingActivation {
+    /// The matched fact ids, ordered by alias
+    pub fn fact_ids(&self) -> Vec<FactId> {
+        self.facts.values().copied().collect()
+    }
+}
+
+/// A match of a timer rule waiting for its next firing in a checkpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledActivation {
+    /// Name of the rule
+    pub rule: String,
+    /// Matched fact ids by alias
+    pub facts: BTreeMap<String, FactId>,
+    /// Time left until the next firing when the checkpoint was taken
+    pub due_in: Duration,
+    /// Time between firings, or `None` to fire once
+    pub period: Option<Duration>,
+}
+
+impl ScheduledActivation {
+    /// The matched fact ids, ordered by alias
+    pub fn fact_ids(&self) -> Vec<FactId> {
+        self.facts.values().copied().collect()
+    }
+}
+
+/// A rule and fact combination that already fired
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FiredActivation {
+    /// Name of the rule
+    pub rule: String,
+    /// Matched fact ids, ordered by alias
+    pub facts: Vec<FactId>,
+}
+
+/// A checkpoint of a session's agenda
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Name of the flow the session belongs to
+    pub flow: String,
+    /// Activations waiting to fire
+    pub pending: Vec<PendingActivation>,
+    /// Combinations that already fired
+    pub fired: Vec<FiredActivation>,
+    /// Focused agenda groups, bottom first
+    pub focus_stack: Vec<String>,
+    /// Matches of timer rules waiting for their next firing
+    #[serde(default)]
+    pub scheduled: Vec<ScheduledActivation>,
+    /// Serialized scratchpad values by key
+    #[serde(default)]
+    pub scratchpad: BTreeMap<String, serde_json::Value>,
+}
+
+impl Checkpoint {
+    /// Serialize this checkpoint to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| Error::Execution(format!("Failed to serialize checkpoint: {}", e)))
+    }
+
+    /// Deserialize a checkpoint from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| Error::Execution(format!("Failed to deserialize checkpoint: {}", e)))
+    }
+}
+
+/// Map checkpointed fact ids to current ones, or `None` if any fact is gone
+pub(crate) fn remap(facts: &[FactId], fact_ids: &HashMap<FactId, FactId>) -> Option<Vec<FactId>> {
+    facts.iter().map(|id| fact_ids.get(id).copied()).collect()
+}
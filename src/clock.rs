@@ -0,0 +1,233 @@
+//! Clocks telling a session the time, and rule timers
+//!
+//! Time windows and [`Timer`]s read the session's clock. Sessions start with a
+//! [`RealTimeClock`]; tests install a [`PseudoClock`] with
+//! [`Session::set_clock`] and move it with [`Session::advance_time`], so
+//! temporal rules run the same way every time.
+//!
+//! [`Session::set_clock`]: crate::session::Session::set_clock
+//! [`Session::advance_time`]: crate::session::Session::advance_time
+
+use crate::error::{Error, Result};
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// std::time panics on wasm32, where web-time reads the JS clock instead
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub use web_time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The time source of a session
+pub trait SessionClock: Debug + Send + Sync {
+    /// Get the current time
+    fn now(&self) -> Instant;
+
+    /// Move the clock forward; clocks following real time ignore this
+    fn advance(&self, _by: Duration) {}
+
+    /// Wait until `deadline`
+    fn wait_until(&self, deadline: Instant);
+}
+
+/// A clock following real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealTimeClock;
+
+impl SessionClock for RealTimeClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Blocks the current thread; spins on wasm32, which cannot sleep
+    fn wait_until(&self, deadline: Instant) {
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        #[cfg(target_arch = "wasm32")]
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// A clock that only moves when advanced
+#[derive(Debug)]
+pub struct PseudoClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl PseudoClock {
+    /// Create a clock stopped at the current time
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Get the time advanced since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for PseudoClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionClock for PseudoClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+
+    /// Jumps to `deadline` without waiting
+    fn wait_until(&self, deadline: Instant) {
+        self.advance(deadline.saturating_duration_since(self.now()));
+    }
+}
+
+/// When a timer rule fires while its conditions hold
+///
+/// A match of a timer rule first fires `delay` after it was made, then
+/// every `period`, until the match is lost. Parses from an interval such as
+/// `"30s"` or `"1m30s"` (fire every interval) or from `"int: <delay>
+/// [<period>]"`; units are `ms`, `s`, `m`, `h` and `d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timer {
+    /// Time from the match to the first firing
+    pub delay: Duration,
+    /// Time between firings, or `None` to fire once
+    pub period: Option<Duration>,
+}
+
+impl Timer {
+    /// Fire every `period`, starting one period after the match
+    pub fn every(period: Duration) -> Self {
+        Self {
+            delay: period,
+            period: Some(period),
+        }
+    }
+
+    /// Fire once, `delay` after the match
+    pub fn after(delay: Duration) -> Self {
+        Self {
+            delay,
+            period: None,
+        }
+    }
+
+    /// Repeat every `period` after the first firing
+    pub fn repeating(mut self, period: Duration) -> Self {
+        self.period = Some(period);
+        self
+    }
+}
+
+impl FromStr for Timer {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if let Some(interval) = spec.strip_prefix("int:") {
+            let mut parts = interval.split_whitespace();
+            let delay = parts
+                .next()
+                .ok_or_else(|| timer_error(spec, "missing delay"))?;
+            let timer =
+                Timer::after(parse_duration(delay).ok_or_else(|| timer_error(spec, delay))?);
+            return match (parts.next(), parts.next()) {
+                (None, _) => Ok(timer),
+                (Some(period), None) => match parse_duration(period) {
+                    Some(Duration::ZERO) => Err(timer_error(spec, "the period must not be zero")),
+                    Some(period) => Ok(timer.repeating(period)),
+                    None => Err(timer_error(spec, period)),
+                },
+                (Some(_), Some(extra)) => Err(timer_error(spec, extra)),
+            };
+        }
+        if spec.starts_with("cron:") {
+            return Err(Error::Compilation(format!(
+                "timer '{}': cron timers are not supported, use an interval",
+                spec
+            )));
+        }
+        match parse_duration(spec) {
+            Some(Duration::ZERO) => Err(timer_error(spec, "the period must not be zero")),
+            Some(period) => Ok(Timer::every(period)),
+            None => Err(timer_error(spec, spec)),
+        }
+    }
+}
+
+fn timer_error(spec: &str, problem: &str) -> Error {
+    Error::Compilation(format!("invalid timer '{}': {}", spec, problem))
+}
+
+/// Parse a duration such as `500ms`, `30s` or `1h30m`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        total += match &rest[..unit] {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value.checked_mul(60)?),
+            "h" => Duration::from_secs(value.checked_mul(3600)?),
+            "d" => Duration::from_secs(value.checked_mul(86400)?),
+            _ => return None,
+        };
+        rest = &rest[unit..];
+    }
+    (!text.is_empty()).then_some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudo_clock_only_moves_when_advanced() {
+        let clock = PseudoClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+        assert_eq!(clock.elapsed(), Duration::from_secs(5));
+
+        clock.wait_until(start + Duration::from_secs(8));
+        assert_eq!(clock.elapsed(), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_parse_timer() {
+        let every = "1m30s".parse::<Timer>().unwrap();
+        assert_eq!(every, Timer::every(Duration::from_secs(90)));
+
+        let timer = "int: 500ms 2h".parse::<Timer>().unwrap();
+        assert_eq!(timer.delay, Duration::from_millis(500));
+        assert_eq!(timer.period, Some(Duration::from_secs(7200)));
+        assert_eq!("int: 10s".parse::<Timer>().unwrap().period, None);
+
+        assert!("30x".parse::<Timer>().is_err());
+        assert!("s".parse::<Timer>().is_err());
+        assert!("0s".parse::<Timer>().is_err());
+        assert!("cron: */5 * * * *".parse::<Timer>().is_err());
+    }
+}
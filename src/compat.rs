@@ -0,0 +1,308 @@
+//! nools.js-style names for code ported from the JavaScript library
+//!
+//! The traits here add the camelCase methods nools.js users know to
+//! [`Flow`] and [`Session`]; `assert`, `modify`, `retract` and `focus`
+//! already share their JavaScript names. Sessions also get the
+//! `EventEmitter` side of nools.js: `on("assert" | "retract" | "modify" |
+//! "fire", ...)` for engine events and `emit`/`on` for custom events raised
+//! from rule actions.
+//!
+//! `nools.compile` has no counterpart: rules are defined in Rust with
+//! [`flow`], the equivalent of `nools.flow(name, function (flow) {...})`.
+//! [`getFlow`], [`hasFlow`], [`deleteFlow`] and [`deleteFlows`] act on
+//! [`FlowRegistry::global`], where [`FlowRegistry::flow`] registers flows.
+//!
+//! ```ignore
+//! use nools::compat::{self, FlowExt, SessionExt};
+//!
+//! let flow = compat::flow("hello", |flow| {
+//!     flow.rule("greet")
+//!         .when(Box::new(ObjectPattern::<Message>::new("m")))
+//!         .then(|session, _| {
+//!             session.emit("greeted", ());
+//!             Ok(())
+//!         })
+//! })?;
+//! let mut session = flow.getSession();
+//! session.on("greeted", |_| println!("hello"));
+//! session.assert(Message::new("hello"))?;
+//! session.r#match().await?;
+//! ```
+
+#![allow(non_snake_case)]
+
+use crate::error::Result;
+use crate::event::EventListener;
+use crate::fact::{Fact, FactHandle};
+use crate::flow::Flow;
+use crate::registry::FlowRegistry;
+use crate::rule::{Activation, Rule};
+use crate::session::Session;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+/// Global under which a session keeps its emitter
+const EMITTER: &str = "nools.compat.emitter";
+
+/// Define a flow, like `nools.flow(name, definer)`
+pub fn flow<F>(name: impl Into<String>, define: F) -> Result<Flow>
+where
+    F: FnOnce(&mut Flow) -> Result<()>,
+{
+    let mut flow = Flow::new(name);
+    define(&mut flow)?;
+    Ok(flow)
+}
+
+/// A registered flow, like `nools.getFlow(name)`
+pub fn getFlow(name: &str) -> Option<Arc<Flow>> {
+    FlowRegistry::global().get_flow(name)
+}
+
+/// Like `nools.hasFlow(name)`
+pub fn hasFlow(name: &str) -> bool {
+    FlowRegistry::global().has_flow(name)
+}
+
+/// Unregister a flow, like `nools.deleteFlow(name)`
+pub fn deleteFlow(name: &str) -> Option<Arc<Flow>> {
+    FlowRegistry::global().delete_flow(name)
+}
+
+/// Unregister every flow, like `nools.deleteFlows()`
+pub fn deleteFlows() {
+    FlowRegistry::global().delete_flows()
+}
+
+/// What an event handler receives
+#[derive(Clone, Copy)]
+pub enum Payload<'a> {
+    /// The fact of an `assert`, `retract` or `modify` event
+    Fact(&'a FactHandle),
+    /// The activation of a `fire` event
+    Fire(&'a Activation),
+    /// The data passed to [`SessionExt::emit`]
+    Data(&'a (dyn Any + Send + Sync)),
+}
+
+impl<'a> Payload<'a> {
+    /// The fact, for fact events
+    pub fn fact(&self) -> Option<&'a FactHandle> {
+        match self {
+            Payload::Fact(fact) => Some(fact),
+            _ => None,
+        }
+    }
+
+    /// The data of a custom event, if it is a `T`
+    pub fn data<T: Any>(&self) -> Option<&'a T> {
+        match self {
+            Payload::Data(data) => data.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// An event handler
+pub type Handler = Arc<dyn Fn(&Payload<'_>) + Send + Sync>;
+
+/// Handlers by event name, shared by a session and its listener
+#[derive(Default)]
+struct Emitter {
+    handlers: RwLock<HashMap<String, Vec<Handler>>>,
+}
+
+impl Emitter {
+    fn emit(&self, event: &str, payload: Payload<'_>) {
+        // Clone the handlers so one may register others while running
+        let handlers = match self.handlers.read() {
+            Ok(handlers) => handlers.get(event).cloned().unwrap_or_default(),
+            Err(_) => return,
+        };
+        for handler in handlers {
+            handler(&payload);
+        }
+    }
+}
+
+impl EventListener for Emitter {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        self.emit("assert", Payload::Fact(fact));
+    }
+
+    fn on_fact_retracted(&self, fact: &FactHandle) {
+        self.emit("retract", Payload::Fact(fact));
+    }
+
+    fn on_fact_modified(&self, fact: &FactHandle) {
+        self.emit("modify", Payload::Fact(fact));
+    }
+
+    fn on_rule_fired(&self, activation: &Activation) {
+        self.emit("fire", Payload::Fire(activation));
+    }
+}
+
+/// nools.js names for [`Flow`]
+pub trait FlowExt {
+    /// Rules in the order they were added, like `flow.getRules()`
+    fn getRules(&self) -> Vec<Arc<Rule>>;
+
+    /// Like `flow.containsRule(name)`
+    fn containsRule(&self, name: &str) -> bool;
+
+    /// A new session, like `flow.getSession()`
+    fn getSession(&self) -> Session;
+}
+
+impl FlowExt for Flow {
+    fn getRules(&self) -> Vec<Arc<Rule>> {
+        let mut rules: Vec<_> = self
+            .rule_names()
+            .iter()
+            .filter_map(|name| self.get_rule(name))
+            .collect();
+        rules.sort_by_key(|rule| rule.load_order);
+        rules
+    }
+
+    fn containsRule(&self, name: &str) -> bool {
+        self.has_rule(name)
+    }
+
+    fn getSession(&self) -> Session {
+        self.session()
+    }
+}
+
+/// nools.js names and events for [`Session`]
+pub trait SessionExt {
+    /// Call `handler` on every `event`, like `session.on(event, handler)`
+    fn on<F>(&mut self, event: impl Into<String>, handler: F) -> &mut Self
+    where
+        F: Fn(&Payload<'_>) + Send + Sync + 'static;
+
+    /// Raise a custom event, like `session.emit(event, data)`
+    ///
+    /// Does nothing if no handler was ever registered on the session.
+    fn emit<T: Any + Send + Sync>(&self, event: &str, data: T);
+
+    /// Facts of type `T`, like `session.getFacts(Type)`
+    fn getFacts<T: Fact>(&self) -> Vec<Arc<FactHandle>>;
+
+    /// Fire until the agenda is empty, like `session.match()`
+    fn r#match(&mut self) -> impl Future<Output = Result<usize>>;
+
+    /// Fire until halted or out of activations, like `session.matchUntilHalt()`
+    fn matchUntilHalt(&mut self) -> impl Future<Output = Result<usize>>;
+}
+
+impl SessionExt for Session {
+    fn on<F>(&mut self, event: impl Into<String>, handler: F) -> &mut Self
+    where
+        F: Fn(&Payload<'_>) + Send + Sync + 'static,
+    {
+        let emitter = match self.global::<Emitter>(EMITTER) {
+            Some(emitter) => emitter,
+            None => {
+                let emitter = Arc::new(Emitter::default());
+                self.set_global_arc(EMITTER, emitter.clone());
+                self.add_event_listener(emitter.clone());
+                emitter
+            }
+        };
+        if let Ok(mut handlers) = emitter.handlers.write() {
+            handlers
+                .entry(event.into())
+                .or_default()
+                .push(Arc::new(handler));
+        }
+        self
+    }
+
+    fn emit<T: Any + Send + Sync>(&self, event: &str, data: T) {
+        if let Some(emitter) = self.global::<Emitter>(EMITTER) {
+            emitter.emit(event, Payload::Data(&data));
+        }
+    }
+
+    fn getFacts<T: Fact>(&self) -> Vec<Arc<FactHandle>> {
+        self.get_facts::<T>()
+    }
+
+    fn r#match(&mut self) -> impl Future<Output = Result<usize>> {
+        self.match_rules()
+    }
+
+    fn matchUntilHalt(&mut self) -> impl Future<Output = Result<usize>> {
+        self.match_until_halt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use std::sync::Mutex;
+
+    #[derive(Debug, Clone)]
+    struct TestFact {
+        value: i32,
+    }
+
+    #[tokio::test]
+    async fn test_nools_style_session() {
+        let flow = flow("compat", |flow| {
+            flow.rule("first")
+                .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+                .then(|session, m| {
+                    let value = m.get_as::<TestFact>("t")?.value;
+                    session.emit("seen", value);
+                    Ok(())
+                })?;
+            flow.rule("second")
+                .when(Box::new(ObjectPattern::<()>::new("u")) as Box<dyn Pattern>)
+                .then(|_, _| Ok(()))
+        })
+        .unwrap();
+        let names: Vec<_> = flow.getRules().iter().map(|r| r.name.clone()).collect();
+        assert_eq!(names, ["first", "second"]);
+        assert!(flow.containsRule("second"));
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut session = flow.getSession();
+        session.emit("seen", 0);
+        let seen = log.clone();
+        session.on("seen", move |payload| {
+            let value = payload.data::<i32>().unwrap();
+            seen.lock().unwrap().push(format!("seen {}", value));
+        });
+        let asserted = log.clone();
+        session.on("assert", move |payload| {
+            let fact = payload.fact().unwrap();
+            asserted
+                .lock()
+                .unwrap()
+                .push(format!("assert {}", fact.type_name()));
+        });
+        let fired = log.clone();
+        session.on("fire", move |payload| {
+            if let Payload::Fire(activation) = payload {
+                fired
+                    .lock()
+                    .unwrap()
+                    .push(format!("fire {}", activation.rule.name));
+            }
+        });
+
+        session.assert(TestFact { value: 7 }).unwrap();
+        assert_eq!(session.getFacts::<TestFact>().len(), 1);
+        assert_eq!(session.r#match().await.unwrap(), 1);
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 3);
+        assert!(log[0].starts_with("assert "));
+        assert_eq!(log[1..], ["seen 7", "fire first"]);
+    }
+}
@@ -1,8 +1,14 @@
 //! Constraint evaluation for pattern matching
 
+use crate::analysis::Comparison;
 use crate::error::Result;
 use crate::fact::FactHandle;
+use crate::value::Value;
 use std::fmt::Debug;
+#[cfg(feature = "async-constraints")]
+use std::future::Future;
+#[cfg(feature = "async-constraints")]
+use std::pin::Pin;
 use std::sync::Arc;
 
 /// A constraint that can be evaluated against facts
@@ -12,6 +18,17 @@ pub trait Constraint: Debug + Send + Sync {
 
     /// Clone this constraint into a box
     fn clone_box(&self) -> Box<dyn Constraint>;
+
+    /// Comparisons of the fact's fields with constants that hold whenever
+    /// this constraint does, for [`crate::analysis`]
+    fn comparisons(&self) -> Vec<Comparison> {
+        Vec::new()
+    }
+
+    /// Describe this constraint for explanations, such as `value > 40`
+    fn description(&self) -> String {
+        format!("{:?}", self)
+    }
 }
 
 /// Context for constraint evaluation
@@ -19,6 +36,8 @@ pub trait Constraint: Debug + Send + Sync {
 pub struct ConstraintContext {
     /// Variables bound during pattern matching
     pub bindings: std::collections::HashMap<String, Arc<FactHandle>>,
+    /// Field values bound during pattern matching
+    pub values: std::collections::HashMap<String, Value>,
 }
 
 impl ConstraintContext {
@@ -37,10 +56,21 @@ impl ConstraintContext {
         self.bindings.insert(name, fact);
     }
 
+    /// Get a bound value by name
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// Bind a value
+    pub fn set_value(&mut self, name: String, value: Value) {
+        self.values.insert(name, value);
+    }
+
     /// Clone the context
     pub fn clone_bindings(&self) -> Self {
         Self {
             bindings: self.bindings.clone(),
+            values: self.values.clone(),
         }
     }
 }
@@ -87,6 +117,10 @@ where
             description: self.description.clone(),
         })
     }
+
+    fn description(&self) -> String {
+        self.description.clone()
+    }
 }
 
 /// Combines multiple constraints with AND logic
@@ -117,6 +151,17 @@ impl Constraint for AndConstraint {
             constraints: self.constraints.iter().map(|c| c.clone_box()).collect(),
         })
     }
+
+    fn comparisons(&self) -> Vec<Comparison> {
+        self.constraints
+            .iter()
+            .flat_map(|constraint| constraint.comparisons())
+            .collect()
+    }
+
+    fn description(&self) -> String {
+        join_descriptions(&self.constraints, " && ")
+    }
 }
 
 /// Combines multiple constraints with OR logic
@@ -147,6 +192,19 @@ impl Constraint for OrConstraint {
             constraints: self.constraints.iter().map(|c| c.clone_box()).collect(),
         })
     }
+
+    fn description(&self) -> String {
+        join_descriptions(&self.constraints, " || ")
+    }
+}
+
+/// Parenthesize and join the descriptions of constraints
+fn join_descriptions(constraints: &[Box<dyn Constraint>], separator: &str) -> String {
+    constraints
+        .iter()
+        .map(|constraint| format!("({})", constraint.description()))
+        .collect::<Vec<_>>()
+        .join(separator)
 }
 
 /// Negates a constraint
@@ -172,6 +230,10 @@ impl Constraint for NotConstraint {
             constraint: self.constraint.clone_box(),
         })
     }
+
+    fn description(&self) -> String {
+        format!("!({})", self.constraint.description())
+    }
 }
 
 // Implement Clone for Box<dyn Constraint>
@@ -181,10 +243,85 @@ impl Clone for Box<dyn Constraint> {
     }
 }
 
+/// Future returned by asynchronous constraint evaluation
+#[cfg(feature = "async-constraints")]
+pub type ConstraintFuture = Pin<Box<dyn Future<Output = Result<bool>> + Send>>;
+
+/// A constraint whose evaluation needs asynchronous work (e.g. a feature store lookup)
+///
+/// Async constraints are only evaluated on the async assert path
+/// (`Session::assert_async`); the synchronous path reports an error instead
+/// of silently skipping them.
+#[cfg(feature = "async-constraints")]
+pub trait AsyncConstraint: Debug + Send + Sync {
+    /// Evaluate this constraint against a fact
+    fn evaluate(&self, fact: Arc<FactHandle>, context: ConstraintContext) -> ConstraintFuture;
+
+    /// Clone this constraint into a box
+    fn clone_box(&self) -> Box<dyn AsyncConstraint>;
+}
+
+/// An asynchronous constraint defined by a closure returning a future
+#[cfg(feature = "async-constraints")]
+pub struct AsyncFunctionConstraint<F> {
+    func: Arc<F>,
+    description: String,
+}
+
+#[cfg(feature = "async-constraints")]
+impl<F, Fut> AsyncFunctionConstraint<F>
+where
+    F: Fn(Arc<FactHandle>, ConstraintContext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<bool>> + Send + 'static,
+{
+    /// Create a new async function constraint
+    pub fn new(func: F, description: impl Into<String>) -> Self {
+        Self {
+            func: Arc::new(func),
+            description: description.into(),
+        }
+    }
+}
+
+#[cfg(feature = "async-constraints")]
+impl<F> Debug for AsyncFunctionConstraint<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncFunctionConstraint")
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+#[cfg(feature = "async-constraints")]
+impl<F, Fut> AsyncConstraint for AsyncFunctionConstraint<F>
+where
+    F: Fn(Arc<FactHandle>, ConstraintContext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<bool>> + Send + 'static,
+{
+    fn evaluate(&self, fact: Arc<FactHandle>, context: ConstraintContext) -> ConstraintFuture {
+        Box::pin((self.func)(fact, context))
+    }
+
+    fn clone_box(&self) -> Box<dyn AsyncConstraint> {
+        Box::new(AsyncFunctionConstraint {
+            func: self.func.clone(),
+            description: self.description.clone(),
+        })
+    }
+}
+
+// Implement Clone for Box<dyn AsyncConstraint>
+#[cfg(feature = "async-constraints")]
+impl Clone for Box<dyn AsyncConstraint> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::fact::{Fact, FactHandle};
+    use crate::fact::FactHandle;
 
     #[derive(Debug, Clone)]
     struct TestFact {
@@ -0,0 +1,110 @@
+//! Working memory changes since a marker
+//!
+//! [`Session::mark`] returns a [`Marker`]; [`Session::diff_since`] then lists
+//! the facts asserted, modified and retracted after it, netting out facts
+//! that were asserted and retracted in between.
+//!
+//! [`Session::mark`]: crate::session::Session::mark
+//! [`Session::diff_since`]: crate::session::Session::diff_since
+
+use crate::event::EventListener;
+use crate::fact::{FactHandle, FactId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A position in a session's change history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Marker(pub(crate) usize);
+
+/// Facts changed since a marker, each listed once in order of first change
+#[derive(Debug, Clone, Default)]
+pub struct FactDiff {
+    /// Facts asserted since the marker and still in working memory
+    pub asserted: Vec<Arc<FactHandle>>,
+    /// Facts present at the marker that were modified, in their latest version
+    pub modified: Vec<Arc<FactHandle>>,
+    /// Facts present at the marker that were retracted, as last seen
+    pub retracted: Vec<Arc<FactHandle>>,
+}
+
+impl FactDiff {
+    /// Check if nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.asserted.is_empty() && self.modified.is_empty() && self.retracted.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Asserted,
+    Modified,
+    Retracted,
+}
+
+/// Records fact changes once a session has been marked
+#[derive(Debug, Default)]
+pub(crate) struct ChangeJournal(Mutex<Vec<(Change, Arc<FactHandle>)>>);
+
+impl ChangeJournal {
+    fn entries(&self) -> MutexGuard<'_, Vec<(Change, Arc<FactHandle>)>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, change: Change, fact: &FactHandle) {
+        self.entries().push((change, Arc::new(fact.clone())));
+    }
+
+    pub(crate) fn mark(&self) -> Marker {
+        Marker(self.entries().len())
+    }
+
+    /// Net changes recorded after a marker
+    pub(crate) fn diff_since(&self, marker: Marker) -> FactDiff {
+        let entries = self.entries();
+        let mut order: Vec<FactId> = Vec::new();
+        let mut net: HashMap<FactId, (Change, Arc<FactHandle>)> = HashMap::new();
+
+        for (change, fact) in entries.iter().skip(marker.0) {
+            let id = fact.id;
+            let state = match (net.get(&id).map(|(state, _)| *state), change) {
+                (None, change) => *change,
+                (Some(Change::Asserted), Change::Retracted) => {
+                    net.remove(&id);
+                    continue;
+                }
+                (Some(Change::Asserted), _) => Change::Asserted,
+                (Some(_), change) => *change,
+            };
+            if !net.contains_key(&id) {
+                order.push(id);
+            }
+            net.insert(id, (state, Arc::clone(fact)));
+        }
+
+        let mut diff = FactDiff::default();
+        for id in order {
+            if let Some((state, fact)) = net.remove(&id) {
+                match state {
+                    Change::Asserted => diff.asserted.push(fact),
+                    Change::Modified => diff.modified.push(fact),
+                    Change::Retracted => diff.retracted.push(fact),
+                }
+            }
+        }
+        diff
+    }
+}
+
+impl EventListener for ChangeJournal {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        self.record(Change::Asserted, fact);
+    }
+
+    fn on_fact_retracted(&self, fact: &FactHandle) {
+        self.record(Change::Retracted, fact);
+    }
+
+    fn on_fact_modified(&self, fact: &FactHandle) {
+        self.record(Change::Modified, fact);
+    }
+}
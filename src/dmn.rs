@@ -0,0 +1,479 @@
+//! DMN-style decision tables
+//!
+//! Input entries are FEEL unary tests (`-`, literals, `< 10`, `[1..5)`,
+//! `"a", "b"`, `not(...)`); output entries are FEEL literals. Tables can be
+//! evaluated directly from rule actions, or turned into a rule that asserts a
+//! [`Decision`] fact for every hit.
+
+use crate::error::{Error, Result};
+use crate::fact::{Fact, FactId};
+use crate::pattern::{ObjectPattern, Pattern};
+use crate::rule::Rule;
+use crate::value::{Fields, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Alias under which decision rules bind the input fact
+pub const INPUT_ALIAS: &str = "input";
+
+/// How a decision table combines the rules that match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitPolicy {
+    /// At most one rule may match; several matches are an error
+    Unique,
+    /// The first matching rule in table order wins
+    First,
+    /// Every matching rule contributes, in table order
+    Collect,
+}
+
+/// Comparison operator of a FEEL unary test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A FEEL unary test applied to one input value
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryTest {
+    /// `-`: matches anything
+    Any,
+    /// A literal the input must equal
+    Equal(Value),
+    /// `< x`, `<= x`, `> x`, `>= x`
+    Compare(Comparison, Value),
+    /// `[a..b]`, `(a..b)`, `]a..b[` and mixes
+    Range {
+        low: Value,
+        low_inclusive: bool,
+        high: Value,
+        high_inclusive: bool,
+    },
+    /// Comma separated tests, any of which may match
+    AnyOf(Vec<UnaryTest>),
+    /// `not(...)`
+    Not(Box<UnaryTest>),
+}
+
+impl UnaryTest {
+    /// Parse a FEEL unary test
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if text.is_empty() || text == "-" {
+            return Ok(UnaryTest::Any);
+        }
+
+        let parts = split_top_level(text);
+        if parts.len() > 1 {
+            return parts
+                .into_iter()
+                .map(UnaryTest::parse)
+                .collect::<Result<_>>()
+                .map(UnaryTest::AnyOf);
+        }
+
+        if let Some(inner) = text
+            .strip_prefix("not(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            return Ok(UnaryTest::Not(Box::new(UnaryTest::parse(inner)?)));
+        }
+
+        for (prefix, comparison) in [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ] {
+            if let Some(rest) = text.strip_prefix(prefix) {
+                return Ok(UnaryTest::Compare(comparison, parse_literal(rest)?));
+            }
+        }
+
+        if let Some((low, high)) = text.split_once("..") {
+            let low_inclusive = match low.chars().next() {
+                Some('[') => true,
+                Some('(') | Some(']') => false,
+                _ => return Err(invalid(text)),
+            };
+            let high_inclusive = match high.chars().last() {
+                Some(']') => true,
+                Some(')') | Some('[') => false,
+                _ => return Err(invalid(text)),
+            };
+            return Ok(UnaryTest::Range {
+                low: parse_literal(&low[1..])?,
+                low_inclusive,
+                high: parse_literal(&high[..high.len() - 1])?,
+                high_inclusive,
+            });
+        }
+
+        parse_literal(text).map(UnaryTest::Equal)
+    }
+
+    /// Check if an input value satisfies this test; missing inputs only match `-` and `null`
+    pub fn matches(&self, input: &Value) -> bool {
+        match self {
+            UnaryTest::Any => true,
+            UnaryTest::Equal(expected) => input.compare(expected) == Some(Ordering::Equal),
+            UnaryTest::Compare(comparison, bound) => match input.compare(bound) {
+                Some(ordering) => match comparison {
+                    Comparison::Less => ordering == Ordering::Less,
+                    Comparison::LessOrEqual => ordering != Ordering::Greater,
+                    Comparison::Greater => ordering == Ordering::Greater,
+                    Comparison::GreaterOrEqual => ordering != Ordering::Less,
+                },
+                None => false,
+            },
+            UnaryTest::Range {
+                low,
+                low_inclusive,
+                high,
+                high_inclusive,
+            } => {
+                let above = match input.compare(low) {
+                    Some(Ordering::Greater) => true,
+                    Some(Ordering::Equal) => *low_inclusive,
+                    _ => false,
+                };
+                let below = match input.compare(high) {
+                    Some(Ordering::Less) => true,
+                    Some(Ordering::Equal) => *high_inclusive,
+                    _ => false,
+                };
+                above && below
+            }
+            UnaryTest::AnyOf(tests) => tests.iter().any(|test| test.matches(input)),
+            UnaryTest::Not(test) => !test.matches(input),
+        }
+    }
+}
+
+/// Parse a FEEL literal: a quoted string, number, boolean or `null`
+pub fn parse_literal(text: &str) -> Result<Value> {
+    let text = text.trim();
+    if let Some(inner) = text
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        return Ok(Value::String(inner.replace("\\\"", "\"")));
+    }
+    if text == "null" {
+        return Ok(Value::Null);
+    }
+    match Value::parse(text) {
+        Value::String(_) => Err(invalid(text)),
+        value => Ok(value),
+    }
+}
+
+/// Split on commas that are not inside quotes or range brackets
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_string = false;
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' | '[' if !in_string => depth += 1,
+            ')' | ']' if !in_string => depth -= 1,
+            ',' if !in_string && depth <= 0 => {
+                parts.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn invalid(text: &str) -> Error {
+    Error::Compilation(format!("Invalid FEEL expression: {}", text))
+}
+
+/// One row of a decision table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionRule {
+    /// One test per input column
+    pub input_entries: Vec<UnaryTest>,
+    /// One value per output column
+    pub output_entries: Vec<Value>,
+}
+
+/// The outputs of one matching decision rule, keyed by output name
+pub type DecisionOutput = HashMap<String, Value>;
+
+/// A DMN decision table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionTable {
+    name: String,
+    hit_policy: HitPolicy,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    rules: Vec<DecisionRule>,
+}
+
+impl DecisionTable {
+    /// Create an empty decision table
+    pub fn new(name: impl Into<String>, hit_policy: HitPolicy) -> Self {
+        Self {
+            name: name.into(),
+            hit_policy,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Add an input column reading the named field
+    pub fn input(mut self, field: impl Into<String>) -> Self {
+        self.inputs.push(field.into());
+        self
+    }
+
+    /// Add an output column
+    pub fn output(mut self, name: impl Into<String>) -> Self {
+        self.outputs.push(name.into());
+        self
+    }
+
+    /// Add a rule from FEEL input tests and output literals
+    pub fn rule(mut self, input_entries: &[&str], output_entries: &[&str]) -> Result<Self> {
+        if input_entries.len() != self.inputs.len() || output_entries.len() != self.outputs.len() {
+            return Err(Error::Compilation(format!(
+                "Decision table '{}': rule {} has {} inputs and {} outputs, expected {} and {}",
+                self.name,
+                self.rules.len() + 1,
+                input_entries.len(),
+                output_entries.len(),
+                self.inputs.len(),
+                self.outputs.len()
+            )));
+        }
+        self.rules.push(DecisionRule {
+            input_entries: input_entries
+                .iter()
+                .map(|entry| UnaryTest::parse(entry))
+                .collect::<Result<_>>()?,
+            output_entries: output_entries
+                .iter()
+                .map(|entry| parse_literal(entry))
+                .collect::<Result<_>>()?,
+        });
+        Ok(self)
+    }
+
+    /// Get the table name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the hit policy
+    pub fn hit_policy(&self) -> HitPolicy {
+        self.hit_policy
+    }
+
+    /// Get the rules in table order
+    pub fn rules(&self) -> &[DecisionRule] {
+        &self.rules
+    }
+
+    /// Evaluate the table against a fact's fields
+    ///
+    /// `Unique` and `First` produce at most one output; `Collect` produces
+    /// one per matching rule.
+    pub fn evaluate(&self, fields: &dyn Fields) -> Result<Vec<DecisionOutput>> {
+        let values: Vec<Value> = self
+            .inputs
+            .iter()
+            .map(|field| fields.field(field).unwrap_or(Value::Null))
+            .collect();
+
+        let mut hits = self.rules.iter().enumerate().filter(|(_, rule)| {
+            rule.input_entries
+                .iter()
+                .zip(&values)
+                .all(|(test, value)| test.matches(value))
+        });
+
+        let selected: Vec<(usize, &DecisionRule)> = match self.hit_policy {
+            HitPolicy::First => hits.next().into_iter().collect(),
+            HitPolicy::Collect => hits.collect(),
+            HitPolicy::Unique => {
+                let selected: Vec<_> = hits.collect();
+                if selected.len() > 1 {
+                    let rows: Vec<String> =
+                        selected.iter().map(|(i, _)| (i + 1).to_string()).collect();
+                    return Err(Error::Execution(format!(
+                        "Decision table '{}' has unique hit policy but rules {} match",
+                        self.name,
+                        rows.join(", ")
+                    )));
+                }
+                selected
+            }
+        };
+
+        Ok(selected
+            .into_iter()
+            .map(|(_, rule)| {
+                self.outputs
+                    .iter()
+                    .cloned()
+                    .zip(rule.output_entries.iter().cloned())
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Build a rule that asserts a [`Decision`] for every hit on facts of type `T`
+    pub fn into_rule<T: Fact + Fields>(self) -> Result<Rule> {
+        let name = self.name.clone();
+        Rule::new(format!("decision::{}", name))
+            .when(Box::new(ObjectPattern::<T>::new(INPUT_ALIAS)) as Box<dyn Pattern>)
+            .then(move |session, match_data| {
+                let handle = match_data.get(INPUT_ALIAS).ok_or_else(|| {
+                    Error::Execution(format!("Match has no '{}' fact", INPUT_ALIAS))
+                })?;
+                let fact = handle.downcast_ref::<T>().ok_or_else(|| {
+                    Error::Execution(format!(
+                        "Decision table '{}' expected a {}",
+                        self.name,
+                        std::any::type_name::<T>()
+                    ))
+                })?;
+                for outputs in self.evaluate(fact)? {
+                    session.assert(Decision {
+                        table: self.name.clone(),
+                        outputs,
+                        source: handle.id,
+                    })?;
+                }
+                Ok(())
+            })
+            .build()
+    }
+}
+
+/// A decision derived from a table for an input fact
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// Name of the decision table
+    pub table: String,
+    /// Output values of the matching rule
+    pub outputs: DecisionOutput,
+    /// The input fact
+    pub source: FactId,
+}
+
+impl Fields for Decision {
+    fn field(&self, name: &str) -> Option<Value> {
+        self.outputs.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+
+    fn applicant(age: i64, status: &str) -> HashMap<String, Value> {
+        let mut fields = HashMap::new();
+        fields.insert("age".to_string(), Value::from(age));
+        fields.insert("status".to_string(), Value::from(status));
+        fields
+    }
+
+    fn risk_table(hit_policy: HitPolicy) -> DecisionTable {
+        DecisionTable::new("risk", hit_policy)
+            .input("age")
+            .input("status")
+            .output("risk")
+            .rule(&["< 25", "-"], &["\"high\""])
+            .unwrap()
+            .rule(&["[25..60]", "\"employed\", \"retired\""], &["\"low\""])
+            .unwrap()
+            .rule(&["-", "not(\"employed\")"], &["\"medium\""])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_unary_tests() {
+        assert_eq!(UnaryTest::parse("-").unwrap(), UnaryTest::Any);
+        assert_eq!(
+            UnaryTest::parse(">= 10").unwrap(),
+            UnaryTest::Compare(Comparison::GreaterOrEqual, Value::Int(10))
+        );
+        assert_eq!(
+            UnaryTest::parse("]1..5)").unwrap(),
+            UnaryTest::Range {
+                low: Value::Int(1),
+                low_inclusive: false,
+                high: Value::Int(5),
+                high_inclusive: false,
+            }
+        );
+        assert_eq!(
+            UnaryTest::parse("not(1), 2").unwrap(),
+            UnaryTest::AnyOf(vec![
+                UnaryTest::Not(Box::new(UnaryTest::Equal(Value::Int(1)))),
+                UnaryTest::Equal(Value::Int(2)),
+            ])
+        );
+        assert!(UnaryTest::parse("bare_name").is_err());
+    }
+
+    #[test]
+    fn test_first_and_collect_hit_policies() {
+        let young = applicant(20, "student");
+        let first = risk_table(HitPolicy::First).evaluate(&young).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0]["risk"], Value::from("high"));
+
+        let all = risk_table(HitPolicy::Collect).evaluate(&young).unwrap();
+        let risks: Vec<_> = all.iter().map(|o| o["risk"].clone()).collect();
+        assert_eq!(risks, vec![Value::from("high"), Value::from("medium")]);
+    }
+
+    #[test]
+    fn test_unique_hit_policy_violation() {
+        let table = risk_table(HitPolicy::Unique);
+        assert!(table.evaluate(&applicant(40, "employed")).is_ok());
+        assert!(table.evaluate(&applicant(20, "student")).is_err());
+    }
+
+    #[test]
+    fn test_rule_arity_mismatch() {
+        let table = DecisionTable::new("t", HitPolicy::First)
+            .input("a")
+            .output("b");
+        assert!(table.rule(&["1", "2"], &["3"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decisions_as_derived_facts() {
+        let mut flow = Flow::new("dmn");
+        flow.add_rule(
+            risk_table(HitPolicy::First)
+                .into_rule::<HashMap<String, Value>>()
+                .unwrap(),
+        )
+        .unwrap();
+
+        let mut session = flow.session();
+        let id = session.assert(applicant(40, "retired")).unwrap();
+        session.match_rules().await.unwrap();
+
+        let decisions = session.get_facts::<Decision>();
+        assert_eq!(decisions.len(), 1);
+        let decision = decisions[0].downcast_ref::<Decision>().unwrap();
+        assert_eq!(decision.source, id);
+        assert_eq!(decision.field("risk"), Some(Value::from("low")));
+    }
+}
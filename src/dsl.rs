@@ -0,0 +1,1779 @@
+//! Text DSL for rule files
+//!
+//! [`compile`] turns rules written in the classic nools DSL into a [`Flow`],
+//! so rule files from nools.js can be reused:
+//!
+//! ```text
+//! define Message {
+//!     text: '',
+//!     status: 'new'
+//! }
+//!
+//! rule Hello {
+//!     salience: 10,
+//!     agenda-group: "greetings",
+//!     when {
+//!         m : Message m.text == 'hello' && m.status != 'done' {text: t};
+//!         r : Reply r.to == t;
+//!     }
+//!     then {
+//!         modify(m, function () { this.status = 'done'; });
+//!         retract(r);
+//!     }
+//! }
+//! ```
+//!
+//! `define` blocks declare [`DefinedFact`] types, which [`parse_templates`]
+//! reads as [`FactTemplate`]s; Rust types implementing [`Fields`] and
+//! templates built at runtime are made available with
+//! [`CompileOptions::define`] and [`CompileOptions::template`].
+//! Conditions are [`crate::expr`] expressions reading fields through
+//! aliases (`m.text`) or variables bound with `{field: variable}`, and may
+//! compare with the fields of earlier patterns. `not`, `or`, `exists` and
+//! `from` conditions are not supported.
+//!
+//! Actions are JavaScript in nools.js. `then` blocks made of `assert(new
+//! Type({...}))`, `modify(m, function () { this.field = ...; })`,
+//! `retract(m)`, `focus("group")`, `halt()` and `console.log(...)`
+//! statements run as written; other actions are supplied in Rust with
+//! [`CompileOptions::action`].
+//!
+//! Rule files can also carry `test` blocks so authors who never touch Rust
+//! can still ship tests with their rules:
+//!
+//! ```text
+//! test "large orders are flagged" {
+//!     given {
+//!         Order { total: 150, status: "open" }
+//!     }
+//!     expect {
+//!         fired big_order
+//!         fired audit 1 times
+//!         not fired small_order
+//!     }
+//! }
+//! ```
+//!
+//! [`run_tests`] compiles the rules and runs the tests of the same source,
+//! building the facts in `given` from its `define` blocks. Tests run against
+//! another flow with [`run_tests_on`] build them from the type registered
+//! under their name in [`FixtureTypes`]. Anything outside test blocks is
+//! skipped by [`parse_tests`].
+
+use crate::analysis::Comparison;
+use crate::constraint::ConstraintContext;
+use crate::error::{Error, Result};
+use crate::explain::ConstraintResult;
+use crate::expr::{truthy, BinaryOp, Expr, Scope};
+use crate::extension::{BetaExtension, ExtensionPattern};
+use crate::fact::{Fact, FactHandle};
+use crate::fixture::{FixtureFact, FixtureTypes};
+use crate::flow::Flow;
+use crate::node::NodeFactory;
+use crate::pattern::Pattern;
+use crate::rule::{Match, Rule, RuleAction};
+use crate::session::Session;
+use crate::template::FactTemplate;
+use crate::testing::RuleTest;
+use crate::value::{register_fields, Fields, Value};
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A token of DSL source
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    Str(String),
+    Number(String),
+    /// A `/pattern/flags` literal, with the flags inlined into the pattern
+    Regex(String),
+    Punct(char),
+}
+
+/// Check if a `/` starts a regular expression literal rather than a division
+fn regex_allowed(tokens: &[(Token, usize)]) -> bool {
+    match tokens.last() {
+        Some((Token::Punct('~'), _)) => true,
+        Some((Token::Ident(ident), _)) => matches!(ident.as_str(), "like" | "notLike" | "matches"),
+        _ => false,
+    }
+}
+
+/// Split source into tokens, each with its line number
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if regex_allowed(&tokens) => {
+                let mut pattern = String::new();
+                loop {
+                    match chars.next() {
+                        Some('/') => break,
+                        Some('\\') => match chars.next() {
+                            Some('/') => pattern.push('/'),
+                            Some(escaped) => {
+                                pattern.push('\\');
+                                pattern.push(escaped);
+                            }
+                            None => {}
+                        },
+                        Some(c) if c != '\n' => pattern.push(c),
+                        _ => {
+                            return Err(Error::Compilation(format!(
+                                "Unterminated regular expression on line {}",
+                                line
+                            )))
+                        }
+                    }
+                }
+                let mut flags = String::new();
+                while let Some(&flag) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    // `g`, `u` and `y` change nothing for a single match
+                    if matches!(flag, 'i' | 'm' | 's') {
+                        flags.push(flag);
+                    }
+                    chars.next();
+                }
+                if !flags.is_empty() {
+                    pattern = format!("(?{}){}", flags, pattern);
+                }
+                tokens.push((Token::Regex(pattern), line));
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' | '\'' => {
+                let start = line;
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(other) => text.push(other),
+                            None => break,
+                        },
+                        Some(other) => {
+                            if other == '\n' {
+                                line += 1;
+                            }
+                            text.push(other);
+                        }
+                        None => {
+                            return Err(Error::Compilation(format!(
+                                "Unterminated string starting on line {}",
+                                start
+                            )))
+                        }
+                    }
+                }
+                tokens.push((Token::Str(text), start));
+            }
+            c if c.is_ascii_digit()
+                || (c == '-' && chars.peek().is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let mut number = c.to_string();
+                while let Some(&n) = chars.peek() {
+                    let exponent_sign = matches!(n, '+' | '-') && number.ends_with(['e', 'E']);
+                    if n.is_ascii_digit() || matches!(n, '.' | 'e' | 'E') || exponent_sign {
+                        number.push(n);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Number(number), line));
+            }
+            c if c.is_alphabetic() || c == '_' || c == '$' => {
+                let mut ident = c.to_string();
+                while let Some(&n) = chars.peek() {
+                    if n.is_alphanumeric() || n == '_' || n == '$' {
+                        ident.push(n);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c => tokens.push((Token::Punct(c), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Cursor over DSL tokens
+pub(crate) struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pub(crate) pos: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(source: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: tokenize(source)?,
+            pos: 0,
+        })
+    }
+
+    pub(crate) fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    pub(crate) fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(token, _)| token)
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    pub(crate) fn error(&self, message: impl std::fmt::Display) -> Error {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line);
+        Error::Compilation(format!("line {}: {}", line, message))
+    }
+
+    pub(crate) fn at_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    pub(crate) fn at_ident(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident == word)
+    }
+
+    pub(crate) fn expect_punct(&mut self, c: char) -> Result<()> {
+        if self.at_punct(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", c)))
+        }
+    }
+
+    fn expect_ident(&mut self, word: &str) -> Result<()> {
+        if self.at_ident(word) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", word)))
+        }
+    }
+
+    /// An identifier or quoted string
+    fn name(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Ident(name)) | Some(Token::Str(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    /// Skip optional `;` and `,` separators
+    pub(crate) fn skip_separators(&mut self) {
+        while self.at_punct(';') || self.at_punct(',') {
+            self.pos += 1;
+        }
+    }
+
+    /// A literal, array or object value
+    fn value(&mut self) -> Result<serde_json::Value> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(serde_json::Value::String(s)),
+            Some(Token::Number(n)) => {
+                serde_json::from_str(&n).map_err(|_| self.error(format!("invalid number '{}'", n)))
+            }
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Ok(serde_json::Value::Bool(true)),
+                "false" => Ok(serde_json::Value::Bool(false)),
+                "null" => Ok(serde_json::Value::Null),
+                _ => Err(self.error(format!("unexpected '{}'", ident))),
+            },
+            Some(Token::Punct('[')) => {
+                let mut items = Vec::new();
+                while !self.at_punct(']') {
+                    items.push(self.value()?);
+                    self.skip_separators();
+                }
+                self.expect_punct(']')?;
+                Ok(serde_json::Value::Array(items))
+            }
+            Some(Token::Punct('{')) => {
+                self.pos -= 1;
+                self.object()
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    /// `{ field: value, ... }`
+    fn object(&mut self) -> Result<serde_json::Value> {
+        self.expect_punct('{')?;
+        let mut fields = serde_json::Map::new();
+        while !self.at_punct('}') {
+            let field = self.name()?;
+            self.expect_punct(':')?;
+            fields.insert(field, self.value()?);
+            self.skip_separators();
+        }
+        self.expect_punct('}')?;
+        Ok(serde_json::Value::Object(fields))
+    }
+
+    /// `test "name" { given { ... } expect { ... } }`
+    fn test(&mut self) -> Result<DslTest> {
+        self.expect_ident("test")?;
+        let name = self.name()?;
+        let mut test = DslTest {
+            name,
+            given: Vec::new(),
+            expect: Vec::new(),
+        };
+
+        self.expect_punct('{')?;
+        while !self.at_punct('}') {
+            if self.at_ident("given") {
+                self.pos += 1;
+                self.expect_punct('{')?;
+                while !self.at_punct('}') {
+                    let type_name = self.name()?;
+                    let fact = self.object()?;
+                    test.given.push(FixtureFact { type_name, fact });
+                    self.skip_separators();
+                }
+                self.expect_punct('}')?;
+            } else if self.at_ident("expect") {
+                self.pos += 1;
+                self.expect_punct('{')?;
+                while !self.at_punct('}') {
+                    test.expect.push(self.expectation()?);
+                    self.skip_separators();
+                }
+                self.expect_punct('}')?;
+            } else {
+                return Err(self.error("expected 'given' or 'expect'"));
+            }
+        }
+        self.expect_punct('}')?;
+        Ok(test)
+    }
+
+    /// `fired rule [n times]` or `not fired rule`
+    fn expectation(&mut self) -> Result<Expectation> {
+        if self.at_ident("not") {
+            self.pos += 1;
+            self.expect_ident("fired")?;
+            return Ok(Expectation::NotFired(self.name()?));
+        }
+
+        self.expect_ident("fired")?;
+        let rule = self.name()?;
+        let times = match self.peek() {
+            Some(Token::Number(n)) => {
+                let times = n
+                    .parse()
+                    .map_err(|_| self.error(format!("invalid count '{}'", n)))?;
+                self.pos += 1;
+                self.expect_ident("times")?;
+                Some(times)
+            }
+            _ => None,
+        };
+        Ok(Expectation::Fired { rule, times })
+    }
+}
+
+/// What a test expects of the rules fired for its facts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// The rule fired, optionally an exact number of times
+    Fired {
+        /// Rule name
+        rule: String,
+        /// Exact number of firings, if given
+        times: Option<usize>,
+    },
+    /// The rule did not fire
+    NotFired(String),
+}
+
+/// A test case declared in a `test` block
+#[derive(Debug, Clone, PartialEq)]
+pub struct DslTest {
+    /// Test name
+    pub name: String,
+    /// Facts asserted before firing, in order
+    pub given: Vec<FixtureFact>,
+    /// Expectations checked after firing
+    pub expect: Vec<Expectation>,
+}
+
+/// The result of running a [`DslTest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestOutcome {
+    /// Test name
+    pub name: String,
+    /// Rules fired, in firing order
+    pub fired: Vec<String>,
+    /// Unmet expectations and errors
+    pub failures: Vec<String>,
+}
+
+impl TestOutcome {
+    /// Check if every expectation was met
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl DslTest {
+    /// Run this test in a fresh session of a flow, see [`RuleTest`]
+    pub async fn run(&self, flow: &Flow, types: &FixtureTypes) -> TestOutcome {
+        let run = async {
+            let mut test = RuleTest::new(flow);
+            for fact in &self.given {
+                test = test.given_boxed(types.build(&fact.type_name, fact.fact.clone())?);
+            }
+            test.when_matched().await
+        }
+        .await;
+
+        let (fired, mut failures) = match run {
+            Ok(run) => (run.fired_rules().to_vec(), Vec::new()),
+            Err(e) => (Vec::new(), vec![e.to_string()]),
+        };
+        for expectation in &self.expect {
+            match expectation {
+                Expectation::Fired { rule, times } => {
+                    let count = fired.iter().filter(|name| *name == rule).count();
+                    match times {
+                        Some(times) if count != *times => failures.push(format!(
+                            "expected '{}' to fire {} times, fired {}",
+                            rule, times, count
+                        )),
+                        None if count == 0 => failures.push(format!("expected '{}' to fire", rule)),
+                        _ => {}
+                    }
+                }
+                Expectation::NotFired(rule) => {
+                    if fired.contains(rule) {
+                        failures.push(format!("expected '{}' not to fire", rule));
+                    }
+                }
+            }
+        }
+
+        TestOutcome {
+            name: self.name.clone(),
+            fired,
+            failures,
+        }
+    }
+}
+
+/// Read the `define` blocks of DSL source as templates, skipping everything else
+///
+/// Slot types are inferred from the defaults; methods are ignored.
+pub fn parse_templates(source: &str) -> Result<Vec<FactTemplate>> {
+    let mut parser = Parser::new(source)?;
+    let mut templates = Vec::new();
+    let mut depth = 0usize;
+
+    while let Some(token) = parser.peek() {
+        match token {
+            Token::Ident(ident) if depth == 0 && ident == "define" => {
+                parser.pos += 1;
+                let name = parser.name()?;
+                templates.push(parser.define_body(name)?.0);
+            }
+            Token::Punct('{') => {
+                depth += 1;
+                parser.pos += 1;
+            }
+            Token::Punct('}') => {
+                depth = depth.saturating_sub(1);
+                parser.pos += 1;
+            }
+            _ => parser.pos += 1,
+        }
+    }
+    Ok(templates)
+}
+
+/// Parse the `test` blocks of DSL source, skipping everything else
+pub fn parse_tests(source: &str) -> Result<Vec<DslTest>> {
+    let mut parser = Parser::new(source)?;
+    let mut tests = Vec::new();
+    let mut depth = 0usize;
+
+    while let Some(token) = parser.peek() {
+        match token {
+            Token::Ident(ident)
+                if depth == 0
+                    && ident == "test"
+                    && matches!(parser.peek_at(1), Some(Token::Str(_))) =>
+            {
+                tests.push(parser.test()?);
+            }
+            Token::Punct('{') => {
+                depth += 1;
+                parser.pos += 1;
+            }
+            Token::Punct('}') => {
+                depth = depth.saturating_sub(1);
+                parser.pos += 1;
+            }
+            _ => parser.pos += 1,
+        }
+    }
+    Ok(tests)
+}
+
+/// Compile DSL source and run its `test` blocks against the compiled rules
+///
+/// Facts in `given` blocks are built from the source's `define` blocks and
+/// the templates of `options`. Compilation and parse errors are returned as
+/// errors; failing tests are reported in their [`TestOutcome`].
+pub async fn run_tests(source: &str, options: &CompileOptions) -> Result<Vec<TestOutcome>> {
+    let flow = compile("tests", source, options)?;
+    let types = parse_templates(source)?
+        .iter()
+        .chain(&options.templates)
+        .fold(FixtureTypes::new(), |types, template| {
+            if types.contains(template.name()) {
+                types
+            } else {
+                types.register_template(template)
+            }
+        });
+    run_tests_on(&flow, &types, source).await
+}
+
+/// Run the `test` blocks of DSL source against a flow
+///
+/// Each test gets a fresh session. Parse errors are returned as errors;
+/// failing tests are reported in their [`TestOutcome`].
+pub async fn run_tests_on(
+    flow: &Flow,
+    types: &FixtureTypes,
+    source: &str,
+) -> Result<Vec<TestOutcome>> {
+    let mut outcomes = Vec::new();
+    for test in parse_tests(source)? {
+        outcomes.push(test.run(flow, types).await);
+    }
+    Ok(outcomes)
+}
+
+/// A fact of a type declared by a `define` block
+///
+/// Facts created by rule actions start from the defaults of their `define`
+/// block; fields left unset on facts built in Rust read as null.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefinedFact {
+    type_name: String,
+    fields: BTreeMap<String, Value>,
+}
+
+impl DefinedFact {
+    /// Create a fact of the named type with no fields set
+    pub fn new(type_name: impl Into<String>) -> Self {
+        Self {
+            type_name: type_name.into(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Set a field, builder style
+    pub fn with(mut self, field: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.set(field, value);
+        self
+    }
+
+    /// Set a field
+    pub fn set(&mut self, field: impl Into<String>, value: impl Into<Value>) {
+        self.fields.insert(field.into(), value.into());
+    }
+
+    /// Get a field, or `None` if it is not set
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.fields.get(field)
+    }
+
+    /// Get the name of the defined type
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// Iterate over the set fields, by name
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields
+            .iter()
+            .map(|(field, value)| (field.as_str(), value))
+    }
+}
+
+impl Fields for DefinedFact {
+    fn field(&self, name: &str) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+}
+
+/// A type that patterns can name
+#[derive(Debug)]
+struct FactType {
+    name: String,
+    type_id: TypeId,
+    /// Field defaults of a type from a `define` block, `None` for Rust types
+    defaults: Option<BTreeMap<String, Value>>,
+    /// Whether the `define` block has a JavaScript constructor
+    constructor: bool,
+    /// Build the node of a pattern joined to earlier patterns by its condition
+    join: fn(String, DslJoin) -> Option<NodeFactory>,
+}
+
+impl FactType {
+    /// The type of a template's facts
+    fn template(template: &FactTemplate, constructor: bool) -> Self {
+        Self {
+            name: template.name().to_string(),
+            type_id: TypeId::of::<DefinedFact>(),
+            defaults: Some(template.defaults()),
+            constructor,
+            join: join_node::<DefinedFact>,
+        }
+    }
+
+    fn accepts(&self, fact: &FactHandle) -> bool {
+        fact.type_id == self.type_id
+            && (self.defaults.is_none()
+                || fact
+                    .downcast_ref::<DefinedFact>()
+                    .is_some_and(|fact| fact.type_name == self.name))
+    }
+}
+
+fn join_node<T: Fact>(alias: String, join: DslJoin) -> Option<NodeFactory> {
+    ExtensionPattern::<T>::beta(alias, join).node_factory()
+}
+
+/// A pattern of a compiled rule
+#[derive(Debug, Clone)]
+struct DslPattern {
+    alias: String,
+    fact_type: Arc<FactType>,
+    condition: Option<Arc<Expr>>,
+    /// Whether the condition reads facts of earlier patterns
+    joined: bool,
+}
+
+impl Pattern for DslPattern {
+    fn type_id(&self) -> TypeId {
+        self.fact_type.type_id
+    }
+
+    fn matches(&self, fact: &FactHandle, _context: &ConstraintContext) -> Result<bool> {
+        if !self.fact_type.accepts(fact) {
+            return Ok(false);
+        }
+        Ok(match &self.condition {
+            Some(condition) if !self.joined => {
+                truthy(&condition.eval(&|_, field| fact.field(field)))
+            }
+            _ => true,
+        })
+    }
+
+    fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    fn constraint_count(&self) -> usize {
+        usize::from(self.condition.is_some())
+    }
+
+    fn comparisons(&self) -> Vec<Comparison> {
+        let mut comparisons = Vec::new();
+        if let Some(condition) = &self.condition {
+            condition.comparisons(&self.alias, &mut comparisons);
+        }
+        comparisons
+    }
+
+    fn explain(&self, fact: &FactHandle, token: &Match) -> Vec<ConstraintResult> {
+        let lookup = |alias: &str, field: &str| {
+            if alias == self.alias {
+                fact.field(field)
+            } else {
+                token.get(alias)?.field(field)
+            }
+        };
+        self.condition
+            .iter()
+            .map(|condition| ConstraintResult {
+                description: condition.to_string(),
+                passed: truthy(&condition.eval(&lookup)),
+            })
+            .collect()
+    }
+
+    fn node_factory(&self) -> Option<NodeFactory> {
+        let condition = self.condition.as_ref().filter(|_| self.joined)?;
+        let join = DslJoin {
+            alias: self.alias.clone(),
+            fact_type: Arc::clone(&self.fact_type),
+            condition: Arc::clone(condition),
+        };
+        (self.fact_type.join)(self.alias.clone(), join)
+    }
+
+    fn clone_box(&self) -> Box<dyn Pattern> {
+        Box::new(self.clone())
+    }
+}
+
+/// Joins a pattern to earlier patterns through its condition
+#[derive(Debug)]
+struct DslJoin {
+    alias: String,
+    fact_type: Arc<FactType>,
+    condition: Arc<Expr>,
+}
+
+impl BetaExtension for DslJoin {
+    type State = Vec<Arc<FactHandle>>;
+
+    fn insert(&self, fact: &Arc<FactHandle>, state: &mut Self::State) -> Result<bool> {
+        let accepted = self.fact_type.accepts(fact);
+        if accepted {
+            state.push(Arc::clone(fact));
+        }
+        Ok(accepted)
+    }
+
+    fn remove(&self, fact: &FactHandle, state: &mut Self::State) {
+        state.retain(|stored| stored.id != fact.id);
+    }
+
+    fn candidates(&self, token: &Match, state: &Self::State) -> Result<Vec<Arc<FactHandle>>> {
+        let mut candidates = Vec::new();
+        for fact in state {
+            if self.joins(token, fact, state)? {
+                candidates.push(Arc::clone(fact));
+            }
+        }
+        Ok(candidates)
+    }
+
+    fn joins(&self, token: &Match, fact: &FactHandle, _state: &Self::State) -> Result<bool> {
+        let lookup = |alias: &str, field: &str| {
+            if alias == self.alias {
+                fact.field(field)
+            } else {
+                token.get(alias)?.field(field)
+            }
+        };
+        Ok(truthy(&self.condition.eval(&lookup)))
+    }
+}
+
+/// Fields with the expressions assigned to them, in order
+type Assignments = Vec<(String, Expr)>;
+
+/// A statement of a `then` block
+#[derive(Debug)]
+enum Statement {
+    Retract(String),
+    Modify(String, Assignments),
+    Assert(Arc<FactType>, Assignments),
+    Focus(String),
+    Halt,
+    Log(Vec<Expr>),
+}
+
+fn bound_fact<'m>(token: &'m Match, alias: &str) -> Result<&'m Arc<FactHandle>> {
+    token
+        .get(alias)
+        .ok_or_else(|| Error::Execution(format!("No fact bound to '{}'", alias)))
+}
+
+/// Run the statements of a `then` block
+fn run(statements: &[Statement], session: &mut Session, token: &Match) -> Result<()> {
+    let lookup = |alias: &str, field: &str| token.get(alias)?.field(field);
+    for statement in statements {
+        match statement {
+            Statement::Retract(alias) => session.retract(bound_fact(token, alias)?.id)?,
+            Statement::Modify(alias, assignments) if assignments.is_empty() => {
+                session.modify(bound_fact(token, alias)?.id)?
+            }
+            Statement::Modify(alias, assignments) => {
+                let id = bound_fact(token, alias)?.id;
+                session.modify_with(id, |fact: &mut DefinedFact| {
+                    for (field, expr) in assignments {
+                        let value = expr.eval(&|from, name| {
+                            if from == alias {
+                                fact.field(name)
+                            } else {
+                                lookup(from, name)
+                            }
+                        });
+                        fact.set(field.clone(), value);
+                    }
+                })?
+            }
+            Statement::Assert(fact_type, fields) => {
+                let mut fact = DefinedFact {
+                    type_name: fact_type.name.clone(),
+                    fields: fact_type.defaults.clone().unwrap_or_default(),
+                };
+                for (field, expr) in fields {
+                    fact.set(field.clone(), expr.eval(&lookup));
+                }
+                session.assert(fact)?;
+            }
+            Statement::Focus(group) => {
+                session.focus(group.clone())?;
+            }
+            Statement::Halt => session.halt(),
+            Statement::Log(exprs) => {
+                let values: Vec<_> = exprs.iter().map(|e| e.eval(&lookup).to_string()).collect();
+                #[cfg(not(target_arch = "wasm32"))]
+                println!("{}", values.join(" "));
+                #[cfg(target_arch = "wasm32")]
+                web_sys::console::log_1(&values.join(" ").into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rust types and actions available to rule files compiled with [`compile`]
+#[derive(Default)]
+pub struct CompileOptions {
+    types: HashMap<String, Arc<FactType>>,
+    /// Templates made available with [`CompileOptions::template`]
+    templates: Vec<FactTemplate>,
+    actions: HashMap<String, RuleAction>,
+}
+
+impl CompileOptions {
+    /// Create options with no types or actions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let patterns match facts of a Rust type under `name`, like nools' `define` option
+    pub fn define<T: Fact + Fields>(mut self, name: impl Into<String>) -> Self {
+        register_fields::<T>();
+        let name = name.into();
+        let fact_type = FactType {
+            name: name.clone(),
+            type_id: TypeId::of::<T>(),
+            defaults: None,
+            constructor: false,
+            join: join_node::<T>,
+        };
+        self.types.insert(name, Arc::new(fact_type));
+        self
+    }
+
+    /// Let patterns match the facts of a template, as if it had a `define` block
+    pub fn template(mut self, template: &FactTemplate) -> Self {
+        let fact_type = FactType::template(template, false);
+        self.types
+            .insert(template.name().to_string(), Arc::new(fact_type));
+        self.templates.push(template.clone());
+        self
+    }
+
+    /// Run `action` instead of the `then` block of the named rule
+    pub fn action<F>(mut self, rule: impl Into<String>, action: F) -> Self
+    where
+        F: Fn(&mut Session, &Match) -> Result<()> + Send + Sync + 'static,
+    {
+        self.actions.insert(rule.into(), Arc::new(action));
+        self
+    }
+}
+
+impl std::fmt::Debug for CompileOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompileOptions")
+            .field("types", &self.types.keys())
+            .field("actions", &self.actions.keys())
+            .finish()
+    }
+}
+
+impl Parser {
+    /// The `{ field: default, ... }` body of a `define` block, and whether
+    /// it has a JavaScript constructor
+    fn define_body(&mut self, name: String) -> Result<(FactTemplate, bool)> {
+        let mut defaults = Vec::new();
+        let mut constructor = false;
+        self.expect_punct('{')?;
+        while !self.at_punct('}') {
+            let field = self.name()?;
+            self.expect_punct(':')?;
+            if self.at_ident("function") {
+                self.pos += 1;
+                self.skip_group('(', ')')?;
+                self.skip_group('{', '}')?;
+                constructor |= field == "constructor";
+            } else {
+                let value = match self.value()? {
+                    serde_json::Value::Null => Value::Null,
+                    serde_json::Value::Bool(b) => Value::Bool(b),
+                    serde_json::Value::Number(n) => n
+                        .as_i64()
+                        .map_or_else(|| Value::Float(n.as_f64().unwrap_or_default()), Value::Int),
+                    serde_json::Value::String(s) => Value::String(s),
+                    _ => {
+                        let message = format!("the default of '{}' must be a literal", field);
+                        return Err(self.error(message));
+                    }
+                };
+                defaults.push((field, value));
+            }
+            self.skip_separators();
+        }
+        self.expect_punct('}')?;
+        Ok((FactTemplate::from_defaults(name, defaults), constructor))
+    }
+
+    /// Skip a bracketed group such as a JavaScript function body
+    fn skip_group(&mut self, open: char, close: char) -> Result<()> {
+        self.expect_punct(open)?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Punct(c)) if c == open => depth += 1,
+                Some(Token::Punct(c)) if c == close => depth -= 1,
+                Some(_) => {}
+                None => return Err(self.error(format!("expected '{}'", close))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles `define` and `rule` blocks into a flow
+struct Compiler<'a> {
+    parser: Parser,
+    options: &'a CompileOptions,
+    types: HashMap<String, Arc<FactType>>,
+    /// Types of the aliases bound by the rule being compiled
+    bound: HashMap<String, Arc<FactType>>,
+}
+
+impl Compiler<'_> {
+    fn compile(&mut self, flow: &mut Flow) -> Result<()> {
+        while let Some(token) = self.parser.peek() {
+            match token {
+                Token::Ident(ident) if ident == "define" => self.define()?,
+                Token::Ident(ident) if ident == "rule" => {
+                    let rule = self.rule()?;
+                    flow.add_rule(rule)?;
+                }
+                Token::Ident(ident)
+                    if ident == "test" && matches!(self.parser.peek_at(1), Some(Token::Str(_))) =>
+                {
+                    self.parser.test()?;
+                }
+                Token::Punct(';') => self.parser.pos += 1,
+                Token::Ident(ident) if ident == "global" || ident == "import" => {
+                    let message = format!("'{}' is not supported", ident);
+                    return Err(self.parser.error(message));
+                }
+                _ => return Err(self.parser.error("expected 'define' or 'rule'")),
+            }
+        }
+        Ok(())
+    }
+
+    /// `define Name { field: default, method: function (...) { ... } }`
+    fn define(&mut self) -> Result<()> {
+        self.parser.expect_ident("define")?;
+        let name = self.parser.name()?;
+        if self.types.contains_key(&name) {
+            return Err(self
+                .parser
+                .error(format!("type '{}' is already defined", name)));
+        }
+        let (template, constructor) = self.parser.define_body(name)?;
+        self.types.insert(
+            template.name().to_string(),
+            Arc::new(FactType::template(&template, constructor)),
+        );
+        Ok(())
+    }
+
+    /// `rule Name { options when { ... } then { ... } }`
+    fn rule(&mut self) -> Result<Rule> {
+        self.parser.expect_ident("rule")?;
+        let name = self.parser.name()?;
+        let mut builder = Rule::new(name.clone());
+        self.parser.expect_punct('{')?;
+
+        while !self.parser.at_ident("when") {
+            let option = self.option_name()?;
+            self.parser.expect_punct(':')?;
+            builder = match option.as_str() {
+                "salience" | "priority" => builder.priority(self.integer()?),
+                "agendaGroup" | "agenda-group" => builder.agenda_group(self.parser.name()?),
+                "autoFocus" | "auto-focus" => builder.auto_focus(self.boolean()?),
+                _ => {
+                    return Err(self
+                        .parser
+                        .error(format!("unknown rule option '{}'", option)))
+                }
+            };
+            self.parser.skip_separators();
+        }
+
+        self.parser.expect_ident("when")?;
+        let mut scope = Scope::default();
+        self.bound.clear();
+        self.parser.expect_punct('{')?;
+        while !self.parser.at_punct('}') {
+            builder = builder.when(Box::new(self.pattern(&mut scope)?));
+            self.parser.skip_separators();
+        }
+        self.parser.expect_punct('}')?;
+
+        self.parser.expect_ident("then")?;
+        let action = match self.options.actions.get(&name) {
+            Some(action) => {
+                self.parser.skip_group('{', '}')?;
+                Arc::clone(action)
+            }
+            None => {
+                let statements = self.statements(&mut scope)?;
+                let action: RuleAction =
+                    Arc::new(move |session, token| run(&statements, session, token));
+                action
+            }
+        };
+        self.parser.expect_punct('}')?;
+        builder
+            .then(move |session, token| action(session, token))
+            .build()
+    }
+
+    /// A rule option name; dashed names such as `agenda-group` are joined
+    fn option_name(&mut self) -> Result<String> {
+        if self.parser.at_punct('}') || self.parser.peek().is_none() {
+            return Err(self.parser.error("expected 'when'"));
+        }
+        let mut name = self.parser.name()?;
+        while self.parser.at_punct('-') {
+            self.parser.pos += 1;
+            name.push('-');
+            name.push_str(&self.parser.name()?);
+        }
+        Ok(name)
+    }
+
+    fn integer(&mut self) -> Result<i32> {
+        match self.parser.next() {
+            Some(Token::Number(n)) => n
+                .parse()
+                .map_err(|_| self.parser.error(format!("invalid integer '{}'", n))),
+            _ => Err(self.parser.error("expected an integer")),
+        }
+    }
+
+    fn boolean(&mut self) -> Result<bool> {
+        if self.parser.eat_word("true") {
+            Ok(true)
+        } else if self.parser.eat_word("false") {
+            Ok(false)
+        } else {
+            Err(self.parser.error("expected 'true' or 'false'"))
+        }
+    }
+
+    /// `alias : Type [condition] [{field: variable, ...}]`
+    fn pattern(&mut self, scope: &mut Scope) -> Result<DslPattern> {
+        if let Some(Token::Ident(ident)) = self.parser.peek() {
+            if matches!(ident.as_str(), "not" | "or" | "exists")
+                && self.parser.peek_at(1) == Some(&Token::Punct('('))
+            {
+                let message = format!("'{}' conditions are not supported", ident);
+                return Err(self.parser.error(message));
+            }
+        }
+
+        let alias = self.parser.name()?;
+        if scope.aliases.contains(&alias) || scope.bindings.contains_key(&alias) {
+            return Err(self.parser.error(format!("'{}' is already bound", alias)));
+        }
+        self.parser.expect_punct(':')?;
+        let type_name = self.parser.name()?;
+        let fact_type = match self.types.get(&type_name) {
+            Some(fact_type) => Arc::clone(fact_type),
+            None => return Err(self.parser.error(format!("unknown type '{}'", type_name))),
+        };
+        scope.aliases.insert(alias.clone());
+        self.bound.insert(alias.clone(), Arc::clone(&fact_type));
+
+        let ends_pattern = match self.parser.peek() {
+            None | Some(Token::Punct(';' | '{' | '}')) => true,
+            Some(Token::Ident(_)) => self.parser.peek_at(1) == Some(&Token::Punct(':')),
+            _ => false,
+        };
+        let condition = if ends_pattern {
+            None
+        } else {
+            Some(self.parser.expr(scope)?)
+        };
+        let joined = condition.as_ref().is_some_and(|condition| {
+            let mut aliases = HashSet::new();
+            condition.aliases(&mut aliases);
+            aliases.iter().any(|read| *read != alias)
+        });
+
+        if self.parser.at_punct('{') {
+            self.parser.pos += 1;
+            while !self.parser.at_punct('}') {
+                let field = self.parser.name()?;
+                self.parser.expect_punct(':')?;
+                let variable = self.parser.name()?;
+                if scope.aliases.contains(&variable) || scope.bindings.contains_key(&variable) {
+                    return Err(self
+                        .parser
+                        .error(format!("'{}' is already bound", variable)));
+                }
+                scope.bindings.insert(variable, (alias.clone(), field));
+                self.parser.skip_separators();
+            }
+            self.parser.expect_punct('}')?;
+        }
+        if self.parser.at_ident("from") {
+            return Err(self.parser.error("'from' is not supported"));
+        }
+
+        Ok(DslPattern {
+            alias,
+            fact_type,
+            condition: condition.map(Arc::new),
+            joined,
+        })
+    }
+
+    /// The statements of a `then` block
+    fn statements(&mut self, scope: &mut Scope) -> Result<Vec<Statement>> {
+        let mut statements = Vec::new();
+        self.parser.expect_punct('{')?;
+        while !self.parser.at_punct('}') {
+            statements.push(self.statement(scope)?);
+            self.parser.skip_separators();
+        }
+        self.parser.expect_punct('}')?;
+        Ok(statements)
+    }
+
+    fn statement(&mut self, scope: &mut Scope) -> Result<Statement> {
+        let start = self.parser.pos;
+        let call = match self.parser.next() {
+            Some(Token::Ident(call)) => call,
+            _ => String::new(),
+        };
+        let statement = match call.as_str() {
+            "retract" => {
+                self.parser.expect_punct('(')?;
+                let alias = self.bound_alias(scope)?;
+                Statement::Retract(alias)
+            }
+            "modify" => {
+                self.parser.expect_punct('(')?;
+                let alias = self.bound_alias(scope)?;
+                let assignments = if self.parser.at_punct(',') {
+                    self.parser.pos += 1;
+                    self.modify_function(&alias, scope)?
+                } else {
+                    Vec::new()
+                };
+                Statement::Modify(alias, assignments)
+            }
+            "assert" => {
+                self.parser.expect_punct('(')?;
+                self.parser.expect_ident("new")?;
+                let (fact_type, fields) = self.construction(scope)?;
+                Statement::Assert(fact_type, fields)
+            }
+            "focus" => {
+                self.parser.expect_punct('(')?;
+                match self.parser.next() {
+                    Some(Token::Str(group)) => Statement::Focus(group),
+                    _ => return Err(self.parser.error("expected an agenda group name")),
+                }
+            }
+            "halt" => {
+                self.parser.expect_punct('(')?;
+                Statement::Halt
+            }
+            "console" if self.parser.eat_op(".") && self.parser.eat_word("log") => {
+                self.parser.expect_punct('(')?;
+                let mut exprs = Vec::new();
+                while !self.parser.at_punct(')') {
+                    exprs.push(self.parser.expr(scope)?);
+                    if !self.parser.eat_op(",") {
+                        break;
+                    }
+                }
+                Statement::Log(exprs)
+            }
+            _ => {
+                self.parser.pos = start;
+                return Err(self.parser.error(
+                    "unsupported statement; supply this action in Rust with CompileOptions::action",
+                ));
+            }
+        };
+        self.parser.expect_punct(')')?;
+        Ok(statement)
+    }
+
+    /// An alias bound by the rule's patterns
+    fn bound_alias(&mut self, scope: &Scope) -> Result<String> {
+        let alias = self.parser.name()?;
+        if scope.aliases.contains(&alias) {
+            Ok(alias)
+        } else {
+            Err(self.parser.error(format!("unknown alias '{}'", alias)))
+        }
+    }
+
+    /// `function () { this.field = value; this.field += value; ... }`
+    fn modify_function(&mut self, alias: &str, scope: &mut Scope) -> Result<Assignments> {
+        if self.bound.get(alias).is_some_and(|t| t.defaults.is_none()) {
+            let message = format!(
+                "'{}' is a Rust type; modify it in an action supplied with CompileOptions::action",
+                alias
+            );
+            return Err(self.parser.error(message));
+        }
+        self.parser.expect_ident("function")?;
+        self.parser.expect_punct('(')?;
+        self.parser.expect_punct(')')?;
+        self.parser.expect_punct('{')?;
+
+        scope.this = Some(alias.to_string());
+        let mut assignments = Vec::new();
+        while !self.parser.at_punct('}') {
+            self.parser.expect_ident("this")?;
+            self.parser.expect_punct('.')?;
+            let field = self.parser.name()?;
+            let current = Expr::Field(alias.to_string(), field.clone());
+            let op = if self.parser.eat_op("+=") {
+                Some(BinaryOp::Add)
+            } else if self.parser.eat_op("-=") {
+                Some(BinaryOp::Sub)
+            } else {
+                self.parser.expect_punct('=')?;
+                None
+            };
+            let value = self.parser.expr(scope)?;
+            let value = match op {
+                Some(op) => Expr::Binary(op, Box::new(current), Box::new(value)),
+                None => value,
+            };
+            assignments.push((field, value));
+            self.parser.skip_separators();
+        }
+        scope.this = None;
+        self.parser.expect_punct('}')?;
+        Ok(assignments)
+    }
+
+    /// `Type()` or `Type({field: value, ...})` after `new`
+    fn construction(&mut self, scope: &Scope) -> Result<(Arc<FactType>, Assignments)> {
+        let type_name = self.parser.name()?;
+        let fact_type = match self.types.get(&type_name) {
+            Some(fact_type) if fact_type.defaults.is_none() => {
+                let message = format!(
+                    "'{}' is a Rust type; assert it in an action supplied with CompileOptions::action",
+                    type_name
+                );
+                return Err(self.parser.error(message));
+            }
+            Some(fact_type) if fact_type.constructor => {
+                let message = format!(
+                    "'{}' has a JavaScript constructor; supply this action with CompileOptions::action",
+                    type_name
+                );
+                return Err(self.parser.error(message));
+            }
+            Some(fact_type) => Arc::clone(fact_type),
+            None => return Err(self.parser.error(format!("unknown type '{}'", type_name))),
+        };
+
+        let mut fields = Vec::new();
+        self.parser.expect_punct('(')?;
+        if self.parser.at_punct('{') {
+            self.parser.pos += 1;
+            while !self.parser.at_punct('}') {
+                let field = self.parser.name()?;
+                self.parser.expect_punct(':')?;
+                fields.push((field, self.parser.expr(scope)?));
+                self.parser.skip_separators();
+            }
+            self.parser.expect_punct('}')?;
+        }
+        self.parser.expect_punct(')')?;
+        Ok((fact_type, fields))
+    }
+}
+
+/// Compile rules written in the nools DSL into a flow
+///
+/// `test` blocks are skipped; run them with [`run_tests`].
+pub fn compile(name: impl Into<String>, source: &str, options: &CompileOptions) -> Result<Flow> {
+    register_fields::<DefinedFact>();
+    let mut flow = Flow::new(name);
+    let mut compiler = Compiler {
+        parser: Parser::new(source)?,
+        options,
+        types: options.types.clone(),
+        bound: HashMap::new(),
+    };
+    compiler.compile(&mut flow)?;
+
+    if let Some(rule) = options.actions.keys().find(|rule| !flow.has_rule(rule)) {
+        return Err(Error::Compilation(format!(
+            "An action was supplied for unknown rule '{}'",
+            rule
+        )));
+    }
+    Ok(flow)
+}
+
+/// Compile a rule file, naming the flow after the file like `nools.compile(path)`
+pub fn compile_file(path: impl AsRef<Path>, options: &CompileOptions) -> Result<Flow> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| Error::Compilation(format!("Failed to read {}: {}", path.display(), e)))?;
+    compile(flow_name(path), &source, options)
+}
+
+/// Name of the flow compiled from a rule file: its file name without extension
+pub(crate) fn flow_name(path: &Path) -> String {
+    path.file_stem().map_or_else(
+        || "flow".to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Order {
+        total: f64,
+        status: String,
+    }
+
+    const SOURCE: &str = r#"
+        // rules are skipped when parsing tests
+        rule ignored { when { o: Order } then { test "not a test" {} } }
+
+        test "large orders are flagged" {
+            given {
+                Order { total: 150, status: "open" }
+                Order { total: 10.5, status: 'open' }
+            }
+            expect {
+                fired big_order
+                fired "open_order" 2 times;
+                not fired small_order
+            }
+        }
+
+        /* a failing test */
+        test "small orders" {
+            given { Order { total: 5, status: "closed" } }
+            expect { fired big_order }
+        }
+    "#;
+
+    fn flow() -> Flow {
+        let mut flow = Flow::new("orders");
+        flow.rule("big_order")
+            .when(Box::new(
+                ObjectPattern::<Order>::new("o").with_filter(|o| o.total > 100.0, "total > 100"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("open_order")
+            .when(Box::new(
+                ObjectPattern::<Order>::new("o").with_filter(|o| o.status == "open", "open"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow
+    }
+
+    #[test]
+    fn test_parse_tests() {
+        let tests = parse_tests(SOURCE).unwrap();
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].name, "large orders are flagged");
+        assert_eq!(tests[0].given[1].fact["total"], 10.5);
+        assert_eq!(
+            tests[0].expect[1],
+            Expectation::Fired {
+                rule: "open_order".to_string(),
+                times: Some(2)
+            }
+        );
+
+        assert!(parse_tests(r#"test "x" { given { Order { total } } }"#).is_err());
+        assert!(parse_tests(r#"test "x" { expect { fired } }"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_tests_on_flow() {
+        let types = FixtureTypes::new().register::<Order>("Order");
+        let outcomes = run_tests_on(&flow(), &types, SOURCE).await.unwrap();
+
+        assert!(outcomes[0].passed(), "{:?}", outcomes[0].failures);
+        assert_eq!(outcomes[0].fired.len(), 3);
+        assert_eq!(outcomes[1].failures, ["expected 'big_order' to fire"]);
+
+        let outcomes = run_tests_on(&flow(), &FixtureTypes::new(), SOURCE)
+            .await
+            .unwrap();
+        assert!(!outcomes[0].passed());
+    }
+
+    #[tokio::test]
+    async fn test_run_tests_compiles_the_same_source() {
+        let source = r#"
+            define Order { total: 0, status: 'open' }
+
+            rule big_order {
+                when { o: Order o.total > 100 && o.status == 'open' }
+                then { modify(o, function () { this.status = 'flagged'; }); }
+            }
+
+            test "large orders are flagged" {
+                given { Order { total: 150 } }
+                expect { fired big_order 1 times }
+            }
+
+            test "small orders" {
+                given { Order { total: 5 } }
+                expect { fired big_order }
+            }
+        "#;
+        let outcomes = run_tests(source, &CompileOptions::new()).await.unwrap();
+        assert!(outcomes[0].passed(), "{:?}", outcomes[0].failures);
+        assert_eq!(outcomes[1].failures, ["expected 'big_order' to fire"]);
+
+        assert!(run_tests("rule {", &CompileOptions::new()).await.is_err());
+    }
+
+    const RULES: &str = r#"
+        define Message {
+            text: '',
+            status: "new",
+            describe: function () { return this.text + "!"; }
+        }
+
+        define Reply {
+            to: null,
+            length: 0
+        }
+
+        rule Hello {
+            salience: 10,
+            when {
+                m : Message m.text == 'hello' && !(m.status in ['done', 'failed']);
+            }
+            then {
+                modify(m, function () {
+                    this.text += " world";
+                    this.status = 'done';
+                });
+            }
+        }
+
+        rule "Long messages" {
+            priority: -1
+            when {
+                m : Message m.text.length > 5 {text: t}
+                r : Reply r.to == t && r.length == 0;
+            }
+            then {
+                modify(r, function () { this.length = t.length * 2 - 1; });
+            }
+        }
+
+        rule Answer {
+            when { m : Message isTrue(m.status == 'new') and m.text neq 'hello' }
+            then {
+                assert(new Reply({to: m.text}));
+                retract(m);
+            }
+        }
+    "#;
+
+    #[tokio::test]
+    async fn test_compile_rules() {
+        let flow = compile("messages", RULES, &CompileOptions::new()).unwrap();
+        assert_eq!(flow.rule_names().len(), 3);
+        assert_eq!(flow.get_rule("Hello").unwrap().priority, 10);
+        assert_eq!(flow.get_rule("Long messages").unwrap().priority, -1);
+
+        let mut session = flow.session();
+        session
+            .assert(
+                DefinedFact::new("Message")
+                    .with("text", "hello")
+                    .with("status", "new"),
+            )
+            .unwrap();
+        session
+            .assert(
+                DefinedFact::new("Message")
+                    .with("text", "hey")
+                    .with("status", "new"),
+            )
+            .unwrap();
+        session
+            .assert(
+                DefinedFact::new("Reply")
+                    .with("to", "hello world")
+                    .with("length", 0),
+            )
+            .unwrap();
+        session.match_rules().await.unwrap();
+
+        let facts = session.iter_facts::<DefinedFact>();
+        let mut facts: Vec<_> = facts.iter().map(|(_, fact)| fact.clone()).collect();
+        facts.sort_by_key(|fact| format!("{:?}", fact));
+        assert_eq!(
+            facts,
+            [
+                DefinedFact::new("Message")
+                    .with("text", "hello world")
+                    .with("status", "done"),
+                DefinedFact::new("Reply")
+                    .with("to", "hey")
+                    .with("length", 0),
+                DefinedFact::new("Reply")
+                    .with("to", "hello world")
+                    .with("length", 21),
+            ]
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    struct Account {
+        owner: String,
+        balance: f64,
+    }
+
+    impl Fields for Account {
+        fn field(&self, name: &str) -> Option<Value> {
+            match name {
+                "owner" => Some(self.owner.clone().into()),
+                "balance" => Some(self.balance.into()),
+                _ => None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_templates() {
+        use crate::template::{SlotType, TemplatePattern};
+
+        let source = r#"
+            define Order { total: 0, status: 'open', note: null }
+            rule Ignored { when { o : Order } then { halt(); } }
+        "#;
+        let templates = parse_templates(source).unwrap();
+        let [order] = templates.as_slice() else {
+            panic!("expected one template, got {:?}", templates);
+        };
+        let slots: Vec<_> = order
+            .slots()
+            .iter()
+            .map(|slot| (slot.name.as_str(), slot.slot_type))
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                ("total", SlotType::Number),
+                ("status", SlotType::String),
+                ("note", SlotType::Any)
+            ]
+        );
+
+        // Templates built at runtime are available to rule files
+        let invoice = FactTemplate::new("Invoice").slot("amount", SlotType::Number, 0);
+        let source = r#"
+            rule Big { when { i : Invoice i.amount > 100; } then { halt(); } }
+        "#;
+        let options = CompileOptions::new().template(&invoice);
+        let mut flow = compile("billing", source, &options).unwrap();
+        flow.rule("Typed")
+            .when(Box::new(TemplatePattern::new("i", &invoice)) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        let mut session = flow.session();
+        session
+            .assert(invoice.create([("amount", 500)]).unwrap())
+            .unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_compile_with_rust_types_and_actions() {
+        let source = r#"
+            define Withdrawal { owner: '', amount: 0 }
+
+            rule Overdraft {
+                agenda-group: "checks",
+                autoFocus: true,
+                when {
+                    $a : Account $a.balance >= 0;
+                    w : Withdrawal w.owner == $a.owner && w.amount > $a.balance;
+                }
+                then {
+                    emit('overdraft', w);
+                }
+            }
+        "#;
+        let options = CompileOptions::new().define::<Account>("Account").action(
+            "Overdraft",
+            |session, token| {
+                let owner = token.get("$a").and_then(|a| a.field("owner"));
+                session
+                    .kv()
+                    .insert("overdrawn", owner.unwrap_or(Value::Null))
+            },
+        );
+        let flow = compile("bank", source, &options).unwrap();
+        let rule = flow.get_rule("Overdraft").unwrap();
+        assert_eq!(rule.agenda_group, "checks");
+        assert!(rule.auto_focus);
+
+        let mut session = flow.session();
+        for (owner, balance) in [("ann", 50.0), ("bob", 10.0)] {
+            let owner = owner.to_string();
+            session.assert(Account { owner, balance }).unwrap();
+        }
+        let withdrawal = DefinedFact::new("Withdrawal").with("owner", "bob");
+        session
+            .assert(withdrawal.clone().with("amount", 5))
+            .unwrap();
+        session.assert(withdrawal.with("amount", 20)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(
+            session.kv().get::<Value>("overdrawn"),
+            Some(Value::from("bob"))
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let options = CompileOptions::new().define::<Account>("Account");
+        let error = |source: &str| match compile("errors", source, &options) {
+            Ok(_) => panic!("compiled: {}", source),
+            Err(e) => e.to_string(),
+        };
+
+        let e = error("rule r {\n when { o : Order }\n then { halt(); } }");
+        assert!(e.contains("line 2: unknown type 'Order'"), "{}", e);
+        let e = error("rule r { when { a : Account a.balance > b.balance } then {} }");
+        assert!(e.contains("unknown name 'b'"), "{}", e);
+        let e = error("rule r { when { a : Account } then { emit('x'); } }");
+        assert!(e.contains("CompileOptions::action"), "{}", e);
+        let e = error("rule r { when { a : Account } then { modify(a, function () { this.balance = 0; }); } }");
+        assert!(e.contains("Rust type"), "{}", e);
+        let e = error("rule r { when { not(a : Account) } then {} }");
+        assert!(e.contains("'not' conditions are not supported"), "{}", e);
+        let e = error("rule r { when { a : Account; a : Account } then {} }");
+        assert!(e.contains("'a' is already bound"), "{}", e);
+        let e = error("define T { items: [] }");
+        assert!(e.contains("must be a literal"), "{}", e);
+        let e = error("define T { constructor: function (x) { this.x = x; } }\nrule r { when { a : Account } then { assert(new T(1)); } }");
+        assert!(e.contains("JavaScript constructor"), "{}", e);
+
+        let options = CompileOptions::new().action("missing", |_, _| Ok(()));
+        assert!(compile("errors", "", &options).is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[tokio::test]
+    async fn test_compile_regex_conditions() {
+        let source = r#"
+            define Message { text: '' }
+            rule Greeting {
+                when { m : Message m.text =~ /^hello(\s*world)?$/i && m.text notLike 'x' }
+                then { retract(m); }
+            }
+        "#;
+        let flow = compile("regex", source, &CompileOptions::new()).unwrap();
+        let mut session = flow.session();
+        for text in ["Hello World", "hello", "hello there"] {
+            session
+                .assert(DefinedFact::new("Message").with("text", text))
+                .unwrap();
+        }
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+        assert_eq!(session.fact_count(), 1);
+    }
+
+    #[test]
+    fn test_compile_file() {
+        let path = std::env::temp_dir().join(format!("nools-dsl-{}.nools", std::process::id()));
+        std::fs::write(&path, RULES).unwrap();
+        let flow = compile_file(&path, &CompileOptions::new());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            flow.unwrap().name(),
+            path.file_stem().unwrap().to_string_lossy()
+        );
+        assert!(compile_file(&path, &CompileOptions::new()).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 //! Error types for the nools rules engine
 
+use crate::fact::FactId;
 use thiserror::Error;
 
 /// Result type alias for nools operations
@@ -28,6 +29,10 @@ pub enum Error {
     #[error("Rule not found: {0}")]
     RuleNotFound(String),
 
+    /// Query not found
+    #[error("Query not found: {0}")]
+    QueryNotFound(String),
+
     /// Invalid constraint
     #[error("Invalid constraint: {0}")]
     InvalidConstraint(String),
@@ -36,6 +41,74 @@ pub enum Error {
     #[error("Agenda group not found: {0}")]
     AgendaGroupNotFound(String),
 
+    /// Flow not found in a registry
+    #[error("Flow not found: {0}")]
+    FlowNotFound(String),
+
+    /// Tenant not found
+    #[error("Tenant not found: {0}")]
+    TenantNotFound(String),
+
+    /// A fact does not fit its template
+    #[error("Invalid fact: {0}")]
+    InvalidFact(String),
+
+    /// A fact handle refers to a version of a fact that was since modified or retracted
+    #[error("Stale handle of fact {fact:?}: generation {generation} is no longer current")]
+    StaleFact {
+        /// The fact
+        fact: FactId,
+        /// Generation of the handle's version of the fact
+        generation: u64,
+    },
+
+    /// A constraint of a pattern failed to evaluate against a fact
+    #[error(
+        "Constraint '{constraint}' of pattern '{alias}'{} failed on {fact:?}: {source}",
+        in_rule(.rule)
+    )]
+    ConstraintFailed {
+        /// Rule whose network evaluated the pattern, if known
+        rule: Option<String>,
+        /// Alias of the pattern
+        alias: String,
+        /// Description of the constraint
+        constraint: String,
+        /// The fact under test
+        fact: FactId,
+        /// The constraint's error
+        source: Box<Error>,
+    },
+
+    /// A rule's action returned an error
+    #[error("Action of rule '{rule}' failed on {facts:?}: {source}")]
+    ActionFailed {
+        /// Name of the rule
+        rule: String,
+        /// Matched fact ids, ordered by alias
+        facts: Vec<FactId>,
+        /// The action's error
+        source: Box<Error>,
+    },
+
+    /// A rule's action panicked; the panic was caught
+    #[error("Action of rule '{rule}' panicked: {message}")]
+    ActionPanicked {
+        /// Name of the rule
+        rule: String,
+        /// The panic message
+        message: String,
+    },
+
+    /// A firing run was aborted by its fire guard
+    #[error("Fire limit exceeded after {fired} firings: {reason}")]
+    FireLimitExceeded {
+        /// Activations fired by the run before it was aborted
+        fired: usize,
+        /// Which limit was exceeded
+        reason: String,
+    },
+
     /// Generic error with custom message
     #[error("{0}")]
     Custom(String),
@@ -46,4 +119,30 @@ impl Error {
     pub fn custom(msg: impl Into<String>) -> Self {
         Error::Custom(msg.into())
     }
+
+    /// Name the rule of a constraint failure that does not name one yet
+    pub(crate) fn in_rule(self, name: &str) -> Self {
+        match self {
+            Error::ConstraintFailed {
+                rule: None,
+                alias,
+                constraint,
+                fact,
+                source,
+            } => Error::ConstraintFailed {
+                rule: Some(name.to_string()),
+                alias,
+                constraint,
+                fact,
+                source,
+            },
+            error => error,
+        }
+    }
+}
+
+fn in_rule(rule: &Option<String>) -> String {
+    rule.as_ref()
+        .map(|rule| format!(" of rule '{}'", rule))
+        .unwrap_or_default()
 }
@@ -0,0 +1,167 @@
+//! Listeners notified of working memory and agenda events
+//!
+//! Register an [`EventListener`] with [`Session::add_event_listener`] to
+//! drive UIs, logging or metrics without touching rule actions. Listeners
+//! are called synchronously, after the event took effect.
+//!
+//! With the `metrics` feature, every session publishes the facts it asserts
+//! and the rules it fires as `metrics` counters. With the `tracing` feature, every session also reports these events as
+//! `tracing` events at debug level, inside spans for fact changes and rule
+//! firings.
+//!
+//! [`Session::add_event_listener`]: crate::session::Session::add_event_listener
+
+use crate::error::Error;
+use crate::fact::FactHandle;
+use crate::rule::Activation;
+use std::sync::{Arc, Mutex};
+
+/// Receives session events; every method defaults to doing nothing
+pub trait EventListener: Send + Sync {
+    /// A fact was asserted
+    fn on_fact_asserted(&self, _fact: &FactHandle) {}
+
+    /// A fact was retracted
+    fn on_fact_retracted(&self, _fact: &FactHandle) {}
+
+    /// A fact was modified; `fact` is the new version
+    fn on_fact_modified(&self, _fact: &FactHandle) {}
+
+    /// An activation was added to the agenda
+    fn on_activation_created(&self, _activation: &Activation) {}
+
+    /// A pending activation was removed from the agenda without firing
+    fn on_activation_cancelled(&self, _activation: &Activation) {}
+
+    /// A rule's action ran successfully
+    fn on_rule_fired(&self, _activation: &Activation) {}
+
+    /// A rule's action returned an error or panicked, before the session's
+    /// error policy applies
+    fn on_action_failed(&self, _activation: &Activation, _error: &Error) {}
+}
+
+/// The listeners registered on a session
+#[derive(Clone, Default)]
+pub(crate) struct EventListeners(Vec<Arc<dyn EventListener>>);
+
+impl EventListeners {
+    pub(crate) fn add(&mut self, listener: Arc<dyn EventListener>) {
+        self.0.push(listener);
+    }
+
+    /// Call `f` with every listener
+    pub(crate) fn notify(&self, f: impl Fn(&dyn EventListener)) {
+        for listener in &self.0 {
+            f(listener.as_ref());
+        }
+    }
+}
+
+/// Records the names of fired rules, in firing order
+#[derive(Debug, Default)]
+pub(crate) struct FiredRules(Mutex<Vec<String>>);
+
+impl FiredRules {
+    /// Take the names recorded so far
+    pub(crate) fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl EventListener for FiredRules {
+    fn on_rule_fired(&self, activation: &Activation) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(activation.rule.name.clone());
+    }
+}
+
+/// Counts a session's facts and firings as `metrics` counters
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub(crate) struct MetricsListener {
+    /// Name of the session's flow, labelling every metric
+    pub(crate) flow: String,
+}
+
+#[cfg(feature = "metrics")]
+impl EventListener for MetricsListener {
+    fn on_fact_asserted(&self, _fact: &FactHandle) {
+        metrics::counter!("nools_facts_asserted_total", "flow" => self.flow.clone()).increment(1);
+    }
+
+    fn on_rule_fired(&self, activation: &Activation) {
+        metrics::counter!(
+            "nools_rules_fired_total",
+            "flow" => self.flow.clone(),
+            "rule" => activation.rule.name.clone()
+        )
+        .increment(1);
+    }
+}
+
+/// Reports session events as `tracing` events
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TracingListener;
+
+#[cfg(feature = "tracing")]
+impl EventListener for TracingListener {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        tracing::debug!(
+            fact = fact.id.as_u64(),
+            r#type = fact.type_name(),
+            "fact asserted"
+        );
+    }
+
+    fn on_fact_retracted(&self, fact: &FactHandle) {
+        tracing::debug!(
+            fact = fact.id.as_u64(),
+            r#type = fact.type_name(),
+            "fact retracted"
+        );
+    }
+
+    fn on_fact_modified(&self, fact: &FactHandle) {
+        tracing::debug!(
+            fact = fact.id.as_u64(),
+            r#type = fact.type_name(),
+            "fact modified"
+        );
+    }
+
+    fn on_activation_created(&self, activation: &Activation) {
+        tracing::debug!(
+            rule = %activation.rule.name,
+            facts = ?activation.match_data.fact_ids(),
+            "activation created"
+        );
+    }
+
+    fn on_activation_cancelled(&self, activation: &Activation) {
+        tracing::debug!(
+            rule = %activation.rule.name,
+            facts = ?activation.match_data.fact_ids(),
+            "activation cancelled"
+        );
+    }
+
+    fn on_rule_fired(&self, activation: &Activation) {
+        tracing::debug!(
+            rule = %activation.rule.name,
+            facts = ?activation.match_data.fact_ids(),
+            "rule fired"
+        );
+    }
+}
+
+impl std::fmt::Debug for EventListeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventListeners")
+            .field("count", &self.0.len())
+            .finish()
+    }
+}
@@ -0,0 +1,94 @@
+//! Why an activation was created
+//!
+//! [`Activation::explain`] tells which fact each pattern of a rule matched.
+//! After [`Session::record_explanations`], it also tells which constraints
+//! of each pattern held, evaluated when the activation entered the agenda,
+//! so decisions can be audited after the facts changed:
+//!
+//! ```ignore
+//! struct Auditor;
+//!
+//! impl EventListener for Auditor {
+//!     fn on_rule_fired(&self, activation: &Activation) {
+//!         println!("{}", activation.explain());
+//!     }
+//! }
+//!
+//! session.record_explanations();
+//! session.add_event_listener(Arc::new(Auditor));
+//! ```
+//!
+//! [`Activation::explain`]: crate::rule::Activation::explain
+//! [`Session::record_explanations`]: crate::session::Session::record_explanations
+
+use crate::event::EventListener;
+use crate::fact::FactId;
+use crate::rule::Activation;
+use std::fmt;
+
+/// The outcome of one constraint of a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintResult {
+    /// Description of the constraint, such as `amount > 1000`
+    pub description: String,
+    /// Whether the constraint held for the matched fact
+    pub passed: bool,
+}
+
+/// The fact a pattern matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternExplanation {
+    /// Alias of the pattern
+    pub alias: String,
+    /// The matched fact
+    pub fact: FactId,
+    /// Type name of the matched fact
+    pub type_name: &'static str,
+    /// The pattern's constraints, if they were recorded
+    pub constraints: Vec<ConstraintResult>,
+}
+
+/// Which facts an activation matched and which constraints held
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// Name of the activated rule
+    pub rule: String,
+    /// The rule's patterns that bound a fact, in rule order
+    pub patterns: Vec<PatternExplanation>,
+    /// Whether constraint results were recorded when the activation was created
+    pub recorded: bool,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule '{}'", self.rule)?;
+        for pattern in &self.patterns {
+            write!(
+                f,
+                "\n  {} matched fact {} ({})",
+                pattern.alias,
+                pattern.fact.as_u64(),
+                pattern.type_name
+            )?;
+            for constraint in &pattern.constraints {
+                let outcome = if constraint.passed {
+                    "passed"
+                } else {
+                    "failed"
+                };
+                write!(f, "\n    {}: {}", constraint.description, outcome)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records the explanation of every activation as it enters the agenda
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ExplanationRecorder;
+
+impl EventListener for ExplanationRecorder {
+    fn on_activation_created(&self, activation: &Activation) {
+        activation.record_explanation();
+    }
+}
@@ -0,0 +1,967 @@
+//! String expressions over fact fields
+//!
+//! An [`Expression`] is a condition written as text, such as
+//! `"value > 40 && name matches '^A.*'"`, for rules that come from data
+//! rather than Rust closures. Names in it are fields of the fact under test,
+//! read with [`fact_field`]: register a type's fields with
+//! [`register_fields`] if it implements [`Fields`], or with
+//! [`register_serde_fields`] to read them through its `Serialize`
+//! implementation. Dotted names such as `address.city` read nested fields.
+//!
+//! Expressions support literals, `[...]` lists, arithmetic, comparisons
+//! (`==`, `!=`, `<`, `<=`, `>`, `>=` or `eq`, `neq`, `lt`, `lte`, `gt`,
+//! `gte`), `in`/`notIn`, `.length`, `&&`/`||`/`!` (or `and`/`or`/`not`),
+//! the string tests `contains`, `startsWith`, `endsWith` and `ilike` (a
+//! case-insensitive SQL `LIKE`, with `%` and `_` wildcards), also callable
+//! as the JavaScript methods `includes`, `startsWith` and `endsWith`,
+//! the `isNull`-style checks and, with the `regex` feature, regular
+//! expressions (`matches`, `=~`, `like`). The rule files of [`crate::dsl`]
+//! use the same language.
+//!
+//! [`Fields`]: crate::value::Fields
+//! [`register_fields`]: crate::value::register_fields
+//! [`register_serde_fields`]: crate::value::register_serde_fields
+
+use crate::analysis::{CompareOp, Comparison};
+use crate::constraint::{Constraint, ConstraintContext};
+use crate::dsl::{Parser, Token};
+use crate::error::Result;
+use crate::fact::FactHandle;
+use crate::value::{fact_field, Value};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// A condition over the fields of a fact, parsed from text
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    alias: String,
+    expr: Expr,
+}
+
+impl Expression {
+    /// Parse an expression
+    pub fn parse(source: &str) -> Result<Self> {
+        Self::parse_with_alias(source, "")
+    }
+
+    /// Parse an expression that may also name the fact under test by an
+    /// alias, as in `"m.count > 3"`
+    pub fn parse_with_alias(source: &str, alias: &str) -> Result<Self> {
+        let mut parser = Parser::new(source)?;
+        let mut scope = Scope {
+            bare_fields: true,
+            ..Scope::default()
+        };
+        if !alias.is_empty() {
+            scope.aliases.insert(alias.to_string());
+        }
+        let expr = parser.expr(&scope)?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected input after the expression"));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            alias: alias.to_string(),
+            expr,
+        })
+    }
+
+    /// Get the text the expression was parsed from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the expression against a fact
+    ///
+    /// Fields the fact does not have read as null.
+    pub fn eval(&self, fact: &FactHandle) -> Value {
+        self.expr.eval(&|_, field| fact_field(fact, field))
+    }
+
+    /// Check if a fact satisfies the expression
+    pub fn test(&self, fact: &FactHandle) -> bool {
+        truthy(&self.eval(fact))
+    }
+
+    /// Get the names of the fields the expression reads, sorted
+    pub fn fields(&self) -> Vec<&str> {
+        let mut fields = Vec::new();
+        self.expr.each_field(&mut |_, field| fields.push(field));
+        fields.sort_unstable();
+        fields.dedup();
+        fields
+    }
+}
+
+impl FromStr for Expression {
+    type Err = crate::error::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        Self::parse(source)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Constraint for Expression {
+    fn evaluate(&self, fact: &FactHandle, _context: &ConstraintContext) -> Result<bool> {
+        Ok(self.test(fact))
+    }
+
+    fn clone_box(&self) -> Box<dyn Constraint> {
+        Box::new(self.clone())
+    }
+
+    fn comparisons(&self) -> Vec<Comparison> {
+        let mut comparisons = Vec::new();
+        self.expr.comparisons("", &mut comparisons);
+        if !self.alias.is_empty() {
+            self.expr.comparisons(&self.alias, &mut comparisons);
+        }
+        comparisons
+    }
+
+    fn description(&self) -> String {
+        self.source.clone()
+    }
+}
+
+/// Names an expression can refer to
+#[derive(Debug, Default)]
+pub(crate) struct Scope {
+    /// Aliases of facts
+    pub(crate) aliases: HashSet<String>,
+    /// Variables bound by `{field: variable}`, with the alias and field they stand for
+    pub(crate) bindings: HashMap<String, (String, String)>,
+    /// The alias `this` stands for, inside `modify` functions
+    pub(crate) this: Option<String>,
+    /// Whether other names are fields of the one fact under test, bound to the alias `""`
+    pub(crate) bare_fields: bool,
+}
+
+/// A condition or action expression
+#[derive(Debug, Clone)]
+pub(crate) enum Expr {
+    Literal(Value),
+    /// A field of the fact bound to an alias
+    Field(String, String),
+    Length(Box<Expr>),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Check(Check, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    #[cfg(feature = "regex")]
+    Matches(Box<Expr>, regex::Regex),
+}
+
+/// The `isTrue`-style checks of nools conditions
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Check {
+    True,
+    False,
+    Null,
+    NotNull,
+    String,
+    Number,
+    Boolean,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Like,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Reads a field of the fact bound to an alias
+pub(crate) type Lookup<'a> = &'a dyn Fn(&str, &str) -> Option<Value>;
+
+impl Expr {
+    pub(crate) fn eval(&self, lookup: Lookup<'_>) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(alias, field) => lookup(alias, field).unwrap_or(Value::Null),
+            Expr::Length(expr) => match expr.eval(lookup) {
+                Value::String(s) => Value::Int(s.chars().count() as i64),
+                _ => Value::Null,
+            },
+            // Lists only appear on the right of `in`
+            Expr::List(_) => Value::Null,
+            Expr::Not(expr) => Value::Bool(!truthy(&expr.eval(lookup))),
+            Expr::Negate(expr) => match expr.eval(lookup) {
+                Value::Int(i) => i.checked_neg().map_or(Value::Null, Value::Int),
+                Value::Float(f) => Value::Float(-f),
+                _ => Value::Null,
+            },
+            Expr::Check(check, expr) => {
+                let value = expr.eval(lookup);
+                Value::Bool(match check {
+                    Check::True => value == Value::Bool(true),
+                    Check::False => value == Value::Bool(false),
+                    Check::Null => value.is_null(),
+                    Check::NotNull => !value.is_null(),
+                    Check::String => value.as_str().is_some(),
+                    Check::Number => value.as_f64().is_some(),
+                    Check::Boolean => value.as_bool().is_some(),
+                })
+            }
+            Expr::Binary(BinaryOp::And, left, right) => {
+                Value::Bool(truthy(&left.eval(lookup)) && truthy(&right.eval(lookup)))
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                Value::Bool(truthy(&left.eval(lookup)) || truthy(&right.eval(lookup)))
+            }
+            Expr::Binary(BinaryOp::In, left, right) => {
+                let value = left.eval(lookup);
+                Value::Bool(match right.as_ref() {
+                    Expr::List(items) => items.iter().any(|item| equal(&value, &item.eval(lookup))),
+                    _ => false,
+                })
+            }
+            Expr::Binary(op, left, right) => binary(*op, left.eval(lookup), right.eval(lookup)),
+            #[cfg(feature = "regex")]
+            Expr::Matches(expr, regex) => {
+                Value::Bool(matches!(expr.eval(lookup), Value::String(s) if regex.is_match(&s)))
+            }
+        }
+    }
+
+    /// Add the comparisons of fields of `alias` with constants that hold
+    /// whenever this expression is true to `comparisons`
+    pub(crate) fn comparisons(&self, alias: &str, comparisons: &mut Vec<Comparison>) {
+        let Expr::Binary(op, left, right) = self else {
+            return;
+        };
+        let op = match op {
+            BinaryOp::And => {
+                left.comparisons(alias, comparisons);
+                right.comparisons(alias, comparisons);
+                return;
+            }
+            BinaryOp::Eq => CompareOp::Eq,
+            BinaryOp::Ne => CompareOp::Ne,
+            BinaryOp::Lt => CompareOp::Lt,
+            BinaryOp::Le => CompareOp::Le,
+            BinaryOp::Gt => CompareOp::Gt,
+            BinaryOp::Ge => CompareOp::Ge,
+            _ => return,
+        };
+        let comparison = match (left.as_ref(), right.as_ref()) {
+            (Expr::Field(bound, field), other) if bound == alias => other
+                .constant()
+                .map(|value| Comparison::new(field, op, value)),
+            (other, Expr::Field(bound, field)) if bound == alias => other
+                .constant()
+                .map(|value| Comparison::new(field, op.flip(), value)),
+            _ => None,
+        };
+        comparisons.extend(comparison);
+    }
+
+    /// The value of a literal, possibly negated
+    fn constant(&self) -> Option<Value> {
+        match self {
+            Expr::Literal(value) => Some(value.clone()),
+            Expr::Negate(expr) if matches!(expr.as_ref(), Expr::Literal(_)) => {
+                Some(self.eval(&|_, _| None))
+            }
+            _ => None,
+        }
+    }
+
+    /// Add the aliases this expression reads to `aliases`
+    pub(crate) fn aliases<'a>(&'a self, aliases: &mut HashSet<&'a str>) {
+        self.each_field(&mut |alias, _| {
+            aliases.insert(alias);
+        });
+    }
+
+    /// Call `f` with the alias and name of every field this expression reads
+    fn each_field<'a>(&'a self, f: &mut dyn FnMut(&'a str, &'a str)) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Field(alias, field) => f(alias, field),
+            Expr::List(items) => items.iter().for_each(|item| item.each_field(f)),
+            Expr::Length(expr) | Expr::Not(expr) | Expr::Negate(expr) | Expr::Check(_, expr) => {
+                expr.each_field(f)
+            }
+            Expr::Binary(_, left, right) => {
+                left.each_field(f);
+                right.each_field(f);
+            }
+            #[cfg(feature = "regex")]
+            Expr::Matches(expr, _) => expr.each_field(f),
+        }
+    }
+}
+
+/// Writes the expression back as text, parenthesizing nested operators
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nested = |expr: &Expr, f: &mut fmt::Formatter<'_>| match expr {
+            Expr::Binary(..) => write!(f, "({})", expr),
+            _ => write!(f, "{}", expr),
+        };
+        match self {
+            Expr::Literal(Value::String(s)) => write!(f, "{:?}", s),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Field(alias, field) if alias.is_empty() => f.write_str(field),
+            Expr::Field(alias, field) => write!(f, "{}.{}", alias, field),
+            Expr::Length(expr) => {
+                nested(expr, f)?;
+                f.write_str(".length")
+            }
+            Expr::List(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Expr::Not(expr) => {
+                f.write_str("!")?;
+                nested(expr, f)
+            }
+            Expr::Negate(expr) => {
+                f.write_str("-")?;
+                nested(expr, f)
+            }
+            Expr::Check(check, expr) => write!(f, "{}({})", check.name(), expr),
+            Expr::Binary(op, left, right) => {
+                nested(left, f)?;
+                write!(f, " {} ", op.symbol())?;
+                nested(right, f)
+            }
+            #[cfg(feature = "regex")]
+            Expr::Matches(expr, regex) => {
+                nested(expr, f)?;
+                write!(f, " =~ /{}/", regex.as_str())
+            }
+        }
+    }
+}
+
+impl Check {
+    fn name(self) -> &'static str {
+        match self {
+            Check::True => "isTrue",
+            Check::False => "isFalse",
+            Check::Null => "isNull",
+            Check::NotNull => "isNotNull",
+            Check::String => "isString",
+            Check::Number => "isNumber",
+            Check::Boolean => "isBoolean",
+        }
+    }
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::In => "in",
+            BinaryOp::Contains => "contains",
+            BinaryOp::StartsWith => "startsWith",
+            BinaryOp::EndsWith => "endsWith",
+            BinaryOp::Like => "ilike",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+        }
+    }
+}
+
+/// JavaScript truthiness
+pub(crate) fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Int(i) => *i != 0,
+        Value::Float(f) => *f != 0.0 && !f.is_nan(),
+        Value::String(s) => !s.is_empty(),
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    a.compare(b) == Some(Ordering::Equal)
+}
+
+fn binary(op: BinaryOp, a: Value, b: Value) -> Value {
+    let ordering = || a.compare(&b);
+    match op {
+        BinaryOp::Eq => Value::Bool(equal(&a, &b)),
+        BinaryOp::Ne => Value::Bool(!equal(&a, &b)),
+        BinaryOp::Lt => Value::Bool(ordering() == Some(Ordering::Less)),
+        BinaryOp::Le => Value::Bool(matches!(ordering(), Some(Ordering::Less | Ordering::Equal))),
+        BinaryOp::Gt => Value::Bool(ordering() == Some(Ordering::Greater)),
+        BinaryOp::Ge => Value::Bool(matches!(
+            ordering(),
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        BinaryOp::Contains | BinaryOp::StartsWith | BinaryOp::EndsWith | BinaryOp::Like => {
+            Value::Bool(match (a.as_str(), b.as_str()) {
+                (Some(text), Some(pattern)) => match op {
+                    BinaryOp::Contains => text.contains(pattern),
+                    BinaryOp::StartsWith => text.starts_with(pattern),
+                    BinaryOp::EndsWith => text.ends_with(pattern),
+                    _ => like(text, pattern),
+                },
+                _ => false,
+            })
+        }
+        BinaryOp::Add if a.as_str().is_some() || b.as_str().is_some() => {
+            Value::String(format!("{}{}", a, b))
+        }
+        _ => arithmetic(op, &a, &b).unwrap_or(Value::Null),
+    }
+}
+
+/// Match text against a case-insensitive SQL `LIKE` pattern
+///
+/// `%` stands for any run of characters and `_` for exactly one.
+pub(crate) fn like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let (mut t, mut p) = (0, 0);
+    // Position after the last `%` and where the text resumed from it
+    let mut wildcard = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                wildcard = Some((p, t));
+            }
+            Some(&c) if c == '_' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match wildcard {
+                Some((after, resumed)) => {
+                    p = after;
+                    t = resumed + 1;
+                    wildcard = Some((after, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+/// Integer arithmetic while it stays exact, floating point otherwise
+fn arithmetic(op: BinaryOp, a: &Value, b: &Value) -> Option<Value> {
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        let exact = match op {
+            BinaryOp::Add => x.checked_add(*y),
+            BinaryOp::Sub => x.checked_sub(*y),
+            BinaryOp::Mul => x.checked_mul(*y),
+            BinaryOp::Rem => x.checked_rem(*y),
+            _ => x.checked_rem(*y).filter(|r| *r == 0).and(x.checked_div(*y)),
+        };
+        if let Some(exact) = exact {
+            return Some(Value::Int(exact));
+        }
+    }
+    let (x, y) = (a.as_f64()?, b.as_f64()?);
+    Some(Value::Float(match op {
+        BinaryOp::Add => x + y,
+        BinaryOp::Sub => x - y,
+        BinaryOp::Mul => x * y,
+        BinaryOp::Div => x / y,
+        BinaryOp::Rem => x % y,
+        _ => return None,
+    }))
+}
+
+/// Comparison operators, longest spelling first
+const COMPARISONS: &[(&str, BinaryOp)] = &[
+    ("===", BinaryOp::Eq),
+    ("!==", BinaryOp::Ne),
+    ("==", BinaryOp::Eq),
+    ("!=", BinaryOp::Ne),
+    ("<=", BinaryOp::Le),
+    (">=", BinaryOp::Ge),
+    ("<", BinaryOp::Lt),
+    (">", BinaryOp::Gt),
+    ("eq", BinaryOp::Eq),
+    ("seq", BinaryOp::Eq),
+    ("neq", BinaryOp::Ne),
+    ("sneq", BinaryOp::Ne),
+    ("lt", BinaryOp::Lt),
+    ("lte", BinaryOp::Le),
+    ("gt", BinaryOp::Gt),
+    ("gte", BinaryOp::Ge),
+    ("in", BinaryOp::In),
+    ("contains", BinaryOp::Contains),
+    ("startsWith", BinaryOp::StartsWith),
+    ("endsWith", BinaryOp::EndsWith),
+    ("ilike", BinaryOp::Like),
+];
+
+impl Parser {
+    /// Consume an operator spelled with punctuation, such as `&&` or `!==`
+    pub(crate) fn eat_op(&mut self, op: &str) -> bool {
+        let spelled = op
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(&Token::Punct(c)));
+        if spelled {
+            self.pos += op.len();
+        }
+        spelled
+    }
+
+    /// Consume an operator spelled as a word, such as `and`
+    pub(crate) fn eat_word(&mut self, word: &str) -> bool {
+        let found = self.at_ident(word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub(crate) fn expr(&mut self, scope: &Scope) -> Result<Expr> {
+        let mut left = self.and_expr(scope)?;
+        while self.eat_op("||") || self.eat_word("or") {
+            let right = self.and_expr(scope)?;
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and_expr(&mut self, scope: &Scope) -> Result<Expr> {
+        let mut left = self.comparison(scope)?;
+        while self.eat_op("&&") || self.eat_word("and") {
+            let right = self.comparison(scope)?;
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self, scope: &Scope) -> Result<Expr> {
+        let left = self.additive(scope)?;
+        if self.eat_op("!=~") || self.eat_word("notLike") {
+            return Ok(Expr::Not(Box::new(self.regex_match(left)?)));
+        }
+        if self.eat_op("=~") || self.eat_word("like") || self.eat_word("matches") {
+            return self.regex_match(left);
+        }
+        if self.eat_word("notIn") {
+            let right = self.additive(scope)?;
+            let contains = Expr::Binary(BinaryOp::In, Box::new(left), Box::new(right));
+            return Ok(Expr::Not(Box::new(contains)));
+        }
+        for (spelling, op) in COMPARISONS {
+            let found = if spelling.starts_with(char::is_alphabetic) {
+                self.eat_word(spelling)
+            } else {
+                self.eat_op(spelling)
+            };
+            if found {
+                let right = self.additive(scope)?;
+                return Ok(Expr::Binary(*op, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    #[cfg(feature = "regex")]
+    fn regex_match(&mut self, left: Expr) -> Result<Expr> {
+        let pattern = match self.next() {
+            Some(Token::Regex(pattern)) | Some(Token::Str(pattern)) => pattern,
+            _ => return Err(self.error("expected a regular expression")),
+        };
+        let regex = regex::Regex::new(&pattern)
+            .map_err(|e| self.error(format!("invalid regular expression: {}", e)))?;
+        Ok(Expr::Matches(Box::new(left), regex))
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn regex_match(&mut self, _left: Expr) -> Result<Expr> {
+        Err(self.error("regular expression operators need the `regex` feature"))
+    }
+
+    fn additive(&mut self, scope: &Scope) -> Result<Expr> {
+        let mut left = self.multiplicative(scope)?;
+        loop {
+            let op = if self.eat_op("+") {
+                BinaryOp::Add
+            } else if self.eat_op("-") {
+                BinaryOp::Sub
+            } else if let Some(Token::Number(n)) = self.peek() {
+                // `a -1` is tokenized as `a` followed by the number `-1`
+                match n.strip_prefix('-') {
+                    Some(digits) => {
+                        let right = Expr::Literal(self.number(digits)?);
+                        self.pos += 1;
+                        left = Expr::Binary(BinaryOp::Sub, Box::new(left), Box::new(right));
+                        continue;
+                    }
+                    None => break,
+                }
+            } else {
+                break;
+            };
+            let right = self.multiplicative(scope)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self, scope: &Scope) -> Result<Expr> {
+        let mut left = self.unary(scope)?;
+        loop {
+            let op = if self.eat_op("*") {
+                BinaryOp::Mul
+            } else if self.eat_op("/") {
+                BinaryOp::Div
+            } else if self.eat_op("%") {
+                BinaryOp::Rem
+            } else {
+                break;
+            };
+            let right = self.unary(scope)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self, scope: &Scope) -> Result<Expr> {
+        if self.at_punct('!') && self.peek_at(1) != Some(&Token::Punct('=')) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary(scope)?)));
+        }
+        if self.eat_word("not") {
+            return Ok(Expr::Not(Box::new(self.unary(scope)?)));
+        }
+        if self.eat_op("-") {
+            return Ok(Expr::Negate(Box::new(self.unary(scope)?)));
+        }
+        self.primary(scope)
+    }
+
+    fn primary(&mut self, scope: &Scope) -> Result<Expr> {
+        let token = self.next();
+        let expr = match token {
+            Some(Token::Str(s)) => Expr::Literal(Value::String(s)),
+            Some(Token::Number(n)) => Expr::Literal(self.number(&n)?),
+            Some(Token::Punct('(')) => {
+                let expr = self.expr(scope)?;
+                self.expect_punct(')')?;
+                expr
+            }
+            Some(Token::Punct('[')) => {
+                let mut items = Vec::new();
+                while !self.at_punct(']') {
+                    items.push(self.expr(scope)?);
+                    self.skip_separators();
+                }
+                self.expect_punct(']')?;
+                Expr::List(items)
+            }
+            Some(Token::Ident(ident)) => self.name_expr(ident, scope)?,
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("expected an expression"));
+            }
+        };
+        if self.at_length() {
+            self.pos += 2;
+            return Ok(Expr::Length(Box::new(expr)));
+        }
+        if let Some(op) = self.at_method() {
+            self.pos += 3;
+            let argument = self.expr(scope)?;
+            self.expect_punct(')')?;
+            return Ok(Expr::Binary(op, Box::new(expr), Box::new(argument)));
+        }
+        Ok(expr)
+    }
+
+    /// An expression starting with an identifier
+    fn name_expr(&mut self, ident: String, scope: &Scope) -> Result<Expr> {
+        let check = match ident.as_str() {
+            "true" => return Ok(Expr::Literal(Value::Bool(true))),
+            "false" => return Ok(Expr::Literal(Value::Bool(false))),
+            "null" | "undefined" => return Ok(Expr::Literal(Value::Null)),
+            "isTrue" => Some(Check::True),
+            "isFalse" => Some(Check::False),
+            "isNull" | "isUndefined" => Some(Check::Null),
+            "isNotNull" | "isDefined" => Some(Check::NotNull),
+            "isString" => Some(Check::String),
+            "isNumber" => Some(Check::Number),
+            "isBoolean" => Some(Check::Boolean),
+            _ => None,
+        };
+        if let Some(check) = check {
+            self.expect_punct('(')?;
+            let expr = self.expr(scope)?;
+            self.expect_punct(')')?;
+            return Ok(Expr::Check(check, Box::new(expr)));
+        }
+
+        if let Some((alias, field)) = scope.bindings.get(&ident) {
+            return Ok(Expr::Field(alias.clone(), field.clone()));
+        }
+        let (alias, mut path) = match (ident.as_str(), &scope.this) {
+            ("this", Some(this)) => (this.clone(), None),
+            _ if scope.aliases.contains(&ident) => (ident, None),
+            _ if scope.bare_fields => (String::new(), Some(ident)),
+            _ => {
+                self.pos -= 1;
+                return Err(self.error(format!("unknown name '{}'", ident)));
+            }
+        };
+        if path.is_none() && !self.at_punct('.') {
+            return Err(self.error(format!("expected a field of '{}'", alias)));
+        }
+        // Nested fields are read by dotted name; `.length` and method calls
+        // after a field are left to `primary`
+        while self.at_punct('.')
+            && (path.is_none() || !(self.at_length() || self.at_method().is_some()))
+        {
+            self.pos += 1;
+            match self.next() {
+                Some(Token::Ident(field)) => match &mut path {
+                    Some(path) => {
+                        path.push('.');
+                        path.push_str(&field);
+                    }
+                    None => path = Some(field),
+                },
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected a field name"));
+                }
+            }
+        }
+        Ok(Expr::Field(alias, path.unwrap_or_default()))
+    }
+
+    /// Check if the next tokens are `.length`
+    fn at_length(&self) -> bool {
+        self.at_punct('.')
+            && matches!(self.peek_at(1), Some(Token::Ident(ident)) if ident == "length")
+    }
+
+    /// Check if the next tokens call a string method, such as `.includes(`
+    fn at_method(&self) -> Option<BinaryOp> {
+        if !self.at_punct('.') || self.peek_at(2) != Some(&Token::Punct('(')) {
+            return None;
+        }
+        match self.peek_at(1) {
+            Some(Token::Ident(method)) => match method.as_str() {
+                "includes" => Some(BinaryOp::Contains),
+                "startsWith" => Some(BinaryOp::StartsWith),
+                "endsWith" => Some(BinaryOp::EndsWith),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn number(&self, text: &str) -> Result<Value> {
+        if let Ok(i) = text.parse() {
+            return Ok(Value::Int(i));
+        }
+        text.parse()
+            .map(Value::Float)
+            .map_err(|_| self.error(format!("invalid number '{}'", text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use crate::value::register_serde_fields;
+    use serde::Serialize;
+
+    fn record() -> FactHandle {
+        let mut record = HashMap::new();
+        record.insert("value".to_string(), Value::from(42));
+        record.insert("name".to_string(), Value::from("Alice"));
+        record.insert("ratio".to_string(), Value::from(0.25));
+        FactHandle::new(record, 0)
+    }
+
+    #[test]
+    fn test_evaluate_expressions() {
+        let fact = record();
+        let holds = |source: &str| Expression::parse(source).unwrap().test(&fact);
+
+        assert!(holds("value > 40 && name.length == 5"));
+        assert!(holds("value * 2 - 4 == 80 and ratio lt 0.5"));
+        assert!(holds("value / 4 == 10.5 && value % 5 == 2"));
+        assert!(holds("name in ['Alice', 'Bob'] && value notIn [1, 2]"));
+        assert!(holds("!(value < 10) || false"));
+        assert!(holds(
+            "isNull(missing) && isString(name) && name + '!' == 'Alice!'"
+        ));
+        assert!(!holds("missing > 0 || name != 'Alice'"));
+        assert_eq!(
+            Expression::parse("name == 'x' || value > ratio * value")
+                .unwrap()
+                .fields(),
+            ["name", "ratio", "value"]
+        );
+        assert!(holds(
+            "name contains 'lic' && name startsWith 'Al' && name endsWith 'ce'"
+        ));
+        assert!(holds("name ilike 'a%E' && name ilike '_LIC_'"));
+        assert!(!holds("name ilike 'a_e' || value contains '4'"));
+        assert_eq!(
+            Expression::parse("-value + 2").unwrap().eval(&fact),
+            Value::Int(-40)
+        );
+    }
+
+    #[test]
+    fn test_aliased_expressions() {
+        let fact = record();
+        let expression = Expression::parse_with_alias(
+            "r.value > 40 && r.name.includes('lic') && !name.endsWith('b') && ratio < 1",
+            "r",
+        )
+        .unwrap();
+        assert!(expression.test(&fact));
+        assert_eq!(expression.fields(), ["name", "ratio", "value"]);
+        assert_eq!(
+            expression.comparisons(),
+            [
+                Comparison::new("ratio", CompareOp::Lt, Value::Int(1)),
+                Comparison::new("value", CompareOp::Gt, Value::Int(40)),
+            ]
+        );
+        let holds = |source: &str| Expression::parse(source).unwrap().test(&fact);
+        assert!(holds("name.startsWith('Al') && name.includes('i' + 'c')"));
+        assert!(!holds("r.value > 40"));
+        assert!(Expression::parse("name.includes('A'").is_err());
+    }
+
+    #[test]
+    fn test_print_expressions() {
+        let print = |source: &str| Expression::parse(source).unwrap().expr.to_string();
+        assert_eq!(
+            print("value > 40 && name.length == 5"),
+            "(value > 40) && (name.length == 5)"
+        );
+        assert_eq!(
+            print("not (a or b) and c notIn [1, 'x']"),
+            "!(a || b) && !(c in [1, \"x\"])"
+        );
+        assert_eq!(
+            print("isNull(address.city) || -value < 2"),
+            "isNull(address.city) || (-value < 2)"
+        );
+
+        let printed = print("a + b * 2 >= 10 || !c");
+        assert_eq!(print(&printed), printed);
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like("Hello World", "hello%"));
+        assert!(like("Hello World", "%WORLD"));
+        assert!(like("Hello World", "%o w%"));
+        assert!(like("abcabd", "%abd"));
+        assert!(like("", "%"));
+        assert!(like("a", "_"));
+        assert!(!like("", "_"));
+        assert!(!like("Hello", "hell"));
+        assert!(!like("Hello", "%x%"));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(Expression::parse("value >").is_err());
+        assert!(Expression::parse("value > 1 value").is_err());
+        assert!(Expression::parse("address.").is_err());
+        assert!("(value".parse::<Expression>().is_err());
+        #[cfg(not(feature = "regex"))]
+        assert!(Expression::parse("name matches '^A'").is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_expressions() {
+        let fact = record();
+        let holds = |source: &str| Expression::parse(source).unwrap().test(&fact);
+        assert!(holds("value > 40 && name matches '^A.*'"));
+        assert!(holds("name =~ /^alice$/i && name !=~ /b/"));
+        assert!(Expression::parse("name matches '('").is_err());
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    struct Order {
+        total: f64,
+        customer: Customer,
+    }
+
+    #[derive(Debug, Clone, Serialize)]
+    struct Customer {
+        country: String,
+    }
+
+    #[tokio::test]
+    async fn test_expression_constraints() {
+        assert!(ObjectPattern::<Order>::new("o")
+            .with_expression("total > 100")
+            .is_err());
+
+        register_serde_fields::<Order>();
+        let pattern = ObjectPattern::<Order>::new("o")
+            .with_expression("total > 100 && customer.country == 'PT'")
+            .unwrap();
+        let mut flow = Flow::new("orders");
+        flow.rule("big_order")
+            .when(Box::new(pattern) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        for (total, country) in [(150.0, "PT"), (50.0, "PT"), (150.0, "ES")] {
+            let customer = Customer {
+                country: country.to_string(),
+            };
+            session.assert(Order { total, customer }).unwrap();
+        }
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,550 @@
+//! Custom matching nodes
+//!
+//! Patterns compile to built-in alpha and join nodes. An extension replaces
+//! the node of one pattern with matching logic of its own, such as a
+//! vector-similarity index, without changing how networks are built:
+//!
+//! - an [`AlphaExtension`] decides which single facts match, like an alpha
+//!   node's pattern, and may keep per-session state to do so;
+//! - a [`BetaExtension`] owns the right memory of a join: it stores facts
+//!   and finds those joining a partial match of the earlier patterns.
+//!
+//! Wrap either in an [`ExtensionPattern`] and use it like any other pattern.
+//!
+//! The engine keeps the rest of the contract: it routes only facts of the
+//! extension's type to it, calls `retract`/`remove` for every retracted fact
+//! of that type (whether or not it matched) and a retract followed by an
+//! assert for modified facts, stores partial matches, binds matched facts
+//! to the pattern's alias and creates activations. State lives in the
+//! session's [`NetworkMemory`], so each session has its own.
+
+use crate::constraint::ConstraintContext;
+use crate::error::Result;
+use crate::fact::{Fact, FactHandle};
+use crate::node::{NetworkMemory, Node, NodeFactory, NodeId, NodePosition};
+use crate::pattern::Pattern;
+use crate::rule::{Activation, Match, Rule};
+use std::any::TypeId;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Matching logic for single facts with per-session state
+pub trait AlphaExtension: Debug + Send + Sync + 'static {
+    /// Per-session state of the node
+    type State: Default + Send + Sync + 'static;
+
+    /// Check if an asserted fact matches, updating the state as needed
+    fn assert(&self, fact: &Arc<FactHandle>, state: &mut Self::State) -> Result<bool>;
+
+    /// Forget a retracted fact
+    fn retract(&self, fact: &FactHandle, state: &mut Self::State);
+}
+
+/// Matching logic for the right side of a join with per-session state
+pub trait BetaExtension: Debug + Send + Sync + 'static {
+    /// Per-session state of the node, holding the stored facts
+    type State: Default + Send + Sync + 'static;
+
+    /// Store an asserted fact if it can take part in joins, returning whether it was stored
+    fn insert(&self, fact: &Arc<FactHandle>, state: &mut Self::State) -> Result<bool>;
+
+    /// Remove a retracted fact
+    fn remove(&self, fact: &FactHandle, state: &mut Self::State);
+
+    /// Get the stored facts that join a partial match
+    fn candidates(&self, token: &Match, state: &Self::State) -> Result<Vec<Arc<FactHandle>>>;
+
+    /// Check if a newly stored fact joins a partial match
+    fn joins(&self, token: &Match, fact: &FactHandle, state: &Self::State) -> Result<bool>;
+}
+
+/// A pattern whose node is built from an extension
+pub struct ExtensionPattern<T> {
+    alias: String,
+    description: String,
+    factory: NodeFactory,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T: Fact> ExtensionPattern<T> {
+    /// Match facts of type `T` with an alpha extension
+    ///
+    /// In a rule with several patterns, the facts it matches join every
+    /// partial match of the patterns before it.
+    pub fn alpha<E: AlphaExtension>(alias: impl Into<String>, extension: E) -> Self {
+        let alias = alias.into();
+        let description = format!("{:?}", extension);
+        let extension = Arc::new(extension);
+        let node_alias = alias.clone();
+        let factory: NodeFactory = Arc::new(move |child, position| match position {
+            NodePosition::Alone => Box::new(AlphaExtensionNode {
+                id: NodeId::new(),
+                type_id: TypeId::of::<T>(),
+                extension: Arc::clone(&extension),
+                child,
+            }),
+            NodePosition::Join { head, last } => Box::new(BetaExtensionNode {
+                id: NodeId::new(),
+                type_id: TypeId::of::<T>(),
+                alias: node_alias.clone(),
+                extension: Arc::new(CrossJoin(Arc::clone(&extension))),
+                is_head: head,
+                child,
+                child_is_join: !last,
+            }),
+        });
+        Self::with_factory(alias, description, factory)
+    }
+
+    /// Join facts of type `T` to the partial matches of earlier patterns with a beta extension
+    ///
+    /// As a rule's only pattern, it joins facts to the empty match.
+    pub fn beta<E: BetaExtension>(alias: impl Into<String>, extension: E) -> Self {
+        let alias = alias.into();
+        let description = format!("{:?}", extension);
+        let extension = Arc::new(extension);
+        let node_alias = alias.clone();
+        let factory: NodeFactory = Arc::new(move |child, position| {
+            let (is_head, child_is_join) = match position {
+                NodePosition::Alone => (true, false),
+                NodePosition::Join { head, last } => (head, !last),
+            };
+            Box::new(BetaExtensionNode {
+                id: NodeId::new(),
+                type_id: TypeId::of::<T>(),
+                alias: node_alias.clone(),
+                extension: Arc::clone(&extension),
+                is_head,
+                child,
+                child_is_join,
+            })
+        });
+        Self::with_factory(alias, description, factory)
+    }
+
+    fn with_factory(alias: String, description: String, factory: NodeFactory) -> Self {
+        Self {
+            alias,
+            description,
+            factory,
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for ExtensionPattern<T> {
+    fn clone(&self) -> Self {
+        Self {
+            alias: self.alias.clone(),
+            description: self.description.clone(),
+            factory: Arc::clone(&self.factory),
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for ExtensionPattern<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtensionPattern")
+            .field("alias", &self.alias)
+            .field("type", &std::any::type_name::<T>())
+            .field("extension", &self.description)
+            .finish()
+    }
+}
+
+impl<T: Fact> Pattern for ExtensionPattern<T> {
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    /// Only checks the fact's type; the extension node decides the rest
+    fn matches(&self, fact: &FactHandle, _context: &ConstraintContext) -> Result<bool> {
+        Ok(fact.type_id == TypeId::of::<T>())
+    }
+
+    fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    fn node_factory(&self) -> Option<NodeFactory> {
+        Some(Arc::clone(&self.factory))
+    }
+
+    fn clone_box(&self) -> Box<dyn Pattern> {
+        Box::new(self.clone())
+    }
+}
+
+/// A rule's only node, matching facts with an alpha extension
+struct AlphaExtensionNode<E> {
+    id: NodeId,
+    type_id: TypeId,
+    extension: Arc<E>,
+    child: Box<dyn Node>,
+}
+
+impl<E: AlphaExtension> Node for AlphaExtensionNode<E> {
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if fact.type_id != self.type_id || !self.extension.assert(&fact, memory.state(self.id))? {
+            return Ok(Vec::new());
+        }
+        self.child.assert_fact(fact, memory)
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if fact.type_id == self.type_id {
+            self.extension.retract(&fact, memory.state(self.id));
+        }
+        self.child.retract_fact(fact, memory)
+    }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        self.child.retarget(rule);
+    }
+
+    fn forget(&self, memory: &mut NetworkMemory) {
+        memory.remove_state(self.id);
+        self.child.forget(memory);
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        self.child.rule_name()
+    }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        self.child.expire_windows(memory)
+    }
+}
+
+/// Joins every fact an alpha extension matches to every partial match
+#[derive(Debug)]
+struct CrossJoin<E>(Arc<E>);
+
+impl<E: AlphaExtension> BetaExtension for CrossJoin<E> {
+    type State = (E::State, Vec<Arc<FactHandle>>);
+
+    fn insert(&self, fact: &Arc<FactHandle>, state: &mut Self::State) -> Result<bool> {
+        let matched = self.0.assert(fact, &mut state.0)?;
+        if matched {
+            state.1.push(Arc::clone(fact));
+        }
+        Ok(matched)
+    }
+
+    fn remove(&self, fact: &FactHandle, state: &mut Self::State) {
+        self.0.retract(fact, &mut state.0);
+        state.1.retain(|stored| stored.id != fact.id);
+    }
+
+    fn candidates(&self, _token: &Match, state: &Self::State) -> Result<Vec<Arc<FactHandle>>> {
+        Ok(state.1.clone())
+    }
+
+    fn joins(&self, _token: &Match, _fact: &FactHandle, _state: &Self::State) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Partial matches of a beta extension node and the extension's own state
+struct BetaState<S> {
+    tokens: Vec<Match>,
+    seeded: bool,
+    extension: S,
+}
+
+impl<S: Default> Default for BetaState<S> {
+    fn default() -> Self {
+        Self {
+            tokens: Vec::new(),
+            seeded: false,
+            extension: S::default(),
+        }
+    }
+}
+
+/// A join node whose right memory is kept by a beta extension
+struct BetaExtensionNode<E> {
+    id: NodeId,
+    type_id: TypeId,
+    alias: String,
+    extension: Arc<E>,
+    is_head: bool,
+    child: Box<dyn Node>,
+    child_is_join: bool,
+}
+
+impl<E: BetaExtension> BetaExtensionNode<E> {
+    /// Get this node's state, seeding the head with the empty match
+    fn state<'m>(&self, memory: &'m mut NetworkMemory) -> &'m mut BetaState<E::State> {
+        let state: &mut BetaState<E::State> = memory.state(self.id);
+        if self.is_head && !state.seeded {
+            state.tokens.push(Match::new());
+            state.seeded = true;
+        }
+        state
+    }
+
+    fn extend(&self, token: &Match, fact: &Arc<FactHandle>) -> Match {
+        let mut extended = token.clone();
+        extended.insert(self.alias.clone(), Arc::clone(fact));
+        extended
+    }
+
+    fn propagate(
+        &self,
+        tokens: Vec<Match>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let mut activations = Vec::new();
+        for token in tokens {
+            activations.extend(self.child.left_activate(token, memory)?);
+        }
+        Ok(activations)
+    }
+}
+
+impl<E: BetaExtension> Node for BetaExtensionNode<E> {
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let mut activations = Vec::new();
+        if fact.type_id == self.type_id {
+            let state = self.state(memory);
+            let mut joined = Vec::new();
+            if self.extension.insert(&fact, &mut state.extension)? {
+                for token in &state.tokens {
+                    if self.extension.joins(token, &fact, &state.extension)? {
+                        joined.push(self.extend(token, &fact));
+                    }
+                }
+            }
+            activations.extend(self.propagate(joined, memory)?);
+        }
+
+        // Downstream joins may match the same fact on their own pattern
+        if self.child_is_join {
+            activations.extend(self.child.assert_fact(fact, memory)?);
+        }
+        Ok(activations)
+    }
+
+    fn left_activate(
+        &self,
+        token: Match,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let state = self.state(memory);
+        let joined = self
+            .extension
+            .candidates(&token, &state.extension)?
+            .iter()
+            .map(|fact| self.extend(&token, fact))
+            .collect();
+        state.tokens.push(token);
+        self.propagate(joined, memory)
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let state = self.state(memory);
+        if fact.type_id == self.type_id {
+            self.extension.remove(&fact, &mut state.extension);
+        }
+        state
+            .tokens
+            .retain(|token| token.facts.values().all(|f| f.id != fact.id));
+
+        if self.child_is_join {
+            return self.child.retract_fact(fact, memory);
+        }
+        Ok(Vec::new())
+    }
+
+    fn refresh_fact(&self, fact: &Arc<FactHandle>, memory: &mut NetworkMemory) {
+        for token in &mut self.state(memory).tokens {
+            token.replace_fact(fact);
+        }
+        if self.child_is_join {
+            self.child.refresh_fact(fact, memory);
+        }
+    }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        self.child.retarget(rule);
+    }
+
+    fn forget(&self, memory: &mut NetworkMemory) {
+        memory.remove_state(self.id);
+        self.child.forget(memory);
+    }
+
+    fn evict_fact(
+        &self,
+        fact: &Arc<FactHandle>,
+        alias: &str,
+        memory: &mut NetworkMemory,
+    ) -> Result<()> {
+        let state = self.state(memory);
+        if self.alias == alias {
+            self.extension.remove(fact, &mut state.extension);
+        }
+        state
+            .tokens
+            .retain(|token| token.get(alias).is_none_or(|f| f.id != fact.id));
+
+        if self.child_is_join {
+            return self.child.evict_fact(fact, alias, memory);
+        }
+        Ok(())
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        self.child.rule_name()
+    }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        self.child.expire_windows(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::pattern::ObjectPattern;
+
+    #[derive(Debug, Clone)]
+    struct Point {
+        x: f64,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Probe {
+        x: f64,
+    }
+
+    /// Matches the first `n` points asserted
+    #[derive(Debug)]
+    struct FirstN(usize);
+
+    impl AlphaExtension for FirstN {
+        type State = Vec<crate::fact::FactId>;
+
+        fn assert(&self, fact: &Arc<FactHandle>, state: &mut Self::State) -> Result<bool> {
+            if state.len() < self.0 {
+                state.push(fact.id);
+                return Ok(true);
+            }
+            Ok(false)
+        }
+
+        fn retract(&self, fact: &FactHandle, state: &mut Self::State) {
+            state.retain(|id| *id != fact.id);
+        }
+    }
+
+    /// Joins points within a distance of the probe, scanning points sorted by x
+    #[derive(Debug)]
+    struct Near(f64);
+
+    impl Near {
+        fn probe(token: &Match) -> f64 {
+            token
+                .get_as::<Probe>("probe")
+                .map_or(f64::NAN, |probe| probe.x)
+        }
+    }
+
+    impl BetaExtension for Near {
+        type State = Vec<Arc<FactHandle>>;
+
+        fn insert(&self, fact: &Arc<FactHandle>, state: &mut Self::State) -> Result<bool> {
+            let x = fact.downcast_ref::<Point>().map_or(f64::NAN, |p| p.x);
+            let index = state.partition_point(|p| p.downcast_ref::<Point>().unwrap().x < x);
+            state.insert(index, Arc::clone(fact));
+            Ok(true)
+        }
+
+        fn remove(&self, fact: &FactHandle, state: &mut Self::State) {
+            state.retain(|p| p.id != fact.id);
+        }
+
+        fn candidates(&self, token: &Match, state: &Self::State) -> Result<Vec<Arc<FactHandle>>> {
+            let x = Self::probe(token);
+            let x_of = |p: &Arc<FactHandle>| p.downcast_ref::<Point>().unwrap().x;
+            let start = state.partition_point(|p| x_of(p) < x - self.0);
+            Ok(state[start..]
+                .iter()
+                .take_while(|p| x_of(p) <= x + self.0)
+                .cloned()
+                .collect())
+        }
+
+        fn joins(&self, token: &Match, fact: &FactHandle, _state: &Self::State) -> Result<bool> {
+            let x = fact.downcast_ref::<Point>().map_or(f64::NAN, |p| p.x);
+            Ok((x - Self::probe(token)).abs() <= self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alpha_extension() {
+        let mut flow = Flow::new("test");
+        flow.rule("first_two")
+            .when(Box::new(ExtensionPattern::<Point>::alpha("p", FirstN(2))) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        let first = session.assert(Point { x: 0.0 }).unwrap();
+        session.assert(Point { x: 1.0 }).unwrap();
+        session.assert(Point { x: 2.0 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+
+        session.retract(first).unwrap();
+        session.assert(Point { x: 3.0 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_beta_extension() {
+        let mut flow = Flow::new("test");
+        flow.rule("near")
+            .when(Box::new(ObjectPattern::<Probe>::new("probe")) as Box<dyn Pattern>)
+            .when(Box::new(ExtensionPattern::<Point>::beta("p", Near(1.0))) as Box<dyn Pattern>)
+            .then(|_, m| {
+                m.get_as::<Point>("p")?;
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        for x in [0.0, 4.5, 5.0, 9.0] {
+            session.assert(Point { x }).unwrap();
+        }
+        session.assert(Probe { x: 5.2 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+
+        let near = session.assert(Point { x: 6.0 }).unwrap();
+        session.assert(Point { x: 7.0 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        session.retract(near).unwrap();
+        session.assert(Probe { x: 6.1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+    }
+}
@@ -1,12 +1,17 @@
 //! Fact representation and management
 
+use serde::{Deserialize, Serialize};
 use std::any::{Any, TypeId};
 use std::fmt::Debug;
+use std::marker::PhantomData;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+#[cfg(feature = "derive")]
+pub use nools_derive::Fact;
+
 /// Unique identifier for facts in working memory
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct FactId(u64);
 
 impl FactId {
@@ -77,6 +82,8 @@ pub struct FactHandle {
     pub type_id: TypeId,
     /// Recency counter for conflict resolution
     pub recency: u64,
+    /// Working-memory generation that produced this version of the fact
+    pub generation: u64,
 }
 
 impl FactHandle {
@@ -87,12 +94,25 @@ impl FactHandle {
             type_id: TypeId::of::<T>(),
             fact: Arc::new(fact),
             recency,
+            generation: 0,
+        }
+    }
+
+    /// Create a fact handle for a type-erased fact
+    pub fn from_boxed(fact: Box<dyn Fact>, recency: u64) -> Self {
+        let fact: Arc<dyn Fact> = Arc::from(fact);
+        Self {
+            id: FactId::new(),
+            type_id: fact.as_ref().as_any().type_id(),
+            fact,
+            recency,
+            generation: 0,
         }
     }
 
     /// Try to downcast the fact to a specific type
     pub fn downcast_ref<T: Fact>(&self) -> Option<&T> {
-        self.fact.as_any().downcast_ref::<T>()
+        self.fact.as_ref().as_any().downcast_ref::<T>()
     }
 
     /// Check if this fact is of a specific type
@@ -102,7 +122,19 @@ impl FactHandle {
 
     /// Get the type name of the contained fact
     pub fn type_name(&self) -> &'static str {
-        self.fact.type_name()
+        self.fact.as_ref().type_name()
+    }
+
+    /// Read a named field, if the fact type registered its fields
+    pub fn field(&self, name: &str) -> Option<crate::value::Value> {
+        crate::value::fact_field(self, name)
+    }
+
+    /// Check whether this is the current version of the fact in a session
+    ///
+    /// A handle goes stale once its fact is modified or retracted.
+    pub fn is_current(&self, session: &crate::session::Session) -> bool {
+        session.is_current(self)
     }
 }
 
@@ -120,6 +152,190 @@ impl std::hash::Hash for FactHandle {
     }
 }
 
+/// The id of an asserted fact together with its type
+///
+/// Returned by [`Session::assert`] so callers can read, update or retract a
+/// fact without downcasting. Changes propagate through the network as soon
+/// as they are made, like any other modification. Converts into the erased
+/// [`FactId`] wherever one is expected.
+///
+/// [`Session::assert`]: crate::session::Session::assert
+pub struct TypedFactHandle<T> {
+    id: FactId,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedFactHandle<T> {
+    pub(crate) fn new(id: FactId) -> Self {
+        Self {
+            id,
+            _type: PhantomData,
+        }
+    }
+
+    /// Get the fact id
+    pub fn id(&self) -> FactId {
+        self.id
+    }
+
+    /// Get the fact id as a raw u64
+    pub fn as_u64(&self) -> u64 {
+        self.id.as_u64()
+    }
+}
+
+impl<T: Fact> TypedFactHandle<T> {
+    /// Read the fact's current data, if it is still asserted
+    pub fn read<R>(&self, session: &crate::session::Session, f: impl FnOnce(&T) -> R) -> Option<R> {
+        session.get_fact(self.id)?.downcast_ref::<T>().map(f)
+    }
+}
+
+impl<T: Fact + Clone> TypedFactHandle<T> {
+    /// Get a copy of the fact's current data, if it is still asserted
+    pub fn get(&self, session: &crate::session::Session) -> Option<T> {
+        session.get_fact(self.id)?.downcast_ref::<T>().cloned()
+    }
+
+    /// Change the fact's data and re-run matching against the new version
+    pub fn update<F>(&self, session: &mut crate::session::Session, f: F) -> crate::error::Result<()>
+    where
+        F: FnOnce(&mut T),
+    {
+        session.modify_with(self.id, f)
+    }
+
+    /// Retract the fact
+    pub fn retract(self, session: &mut crate::session::Session) -> crate::error::Result<()> {
+        session.retract(self.id)
+    }
+}
+
+impl<T> Clone for TypedFactHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TypedFactHandle<T> {}
+
+impl<T> PartialEq for TypedFactHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for TypedFactHandle<T> {}
+
+impl<T> PartialEq<FactId> for TypedFactHandle<T> {
+    fn eq(&self, other: &FactId) -> bool {
+        self.id == *other
+    }
+}
+
+impl<T> PartialEq<TypedFactHandle<T>> for FactId {
+    fn eq(&self, other: &TypedFactHandle<T>) -> bool {
+        *self == other.id
+    }
+}
+
+impl<T> std::hash::Hash for TypedFactHandle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> Debug for TypedFactHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedFactHandle")
+            .field("id", &self.id)
+            .field("type", &std::any::type_name::<T>())
+            .finish()
+    }
+}
+
+impl<T> From<TypedFactHandle<T>> for FactId {
+    fn from(handle: TypedFactHandle<T>) -> Self {
+        handle.id
+    }
+}
+
+/// The facts of one type, read without downcasting
+///
+/// Returned by [`Session::iter_facts`]; holds the handles so the facts can
+/// be borrowed while the session changes.
+///
+/// [`Session::iter_facts`]: crate::session::Session::iter_facts
+pub struct FactsOf<T> {
+    handles: Vec<Arc<FactHandle>>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T: Fact> FactsOf<T> {
+    pub(crate) fn new(handles: Vec<Arc<FactHandle>>) -> Self {
+        Self {
+            handles,
+            _type: PhantomData,
+        }
+    }
+
+    /// Iterate over the facts with their ids
+    pub fn iter(&self) -> FactsOfIter<'_, T> {
+        FactsOfIter {
+            handles: self.handles.iter(),
+            _type: PhantomData,
+        }
+    }
+
+    /// Get the number of facts
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Check if there are no facts
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+impl<'a, T: Fact> IntoIterator for &'a FactsOf<T> {
+    type Item = (FactId, &'a T);
+    type IntoIter = FactsOfIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the facts of a [`FactsOf`] with their ids
+pub struct FactsOfIter<'a, T> {
+    handles: std::slice::Iter<'a, Arc<FactHandle>>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Fact> Iterator for FactsOfIter<'a, T> {
+    type Item = (FactId, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.handles
+            .by_ref()
+            .find_map(|handle| Some((handle.id, handle.downcast_ref::<T>()?)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.handles.size_hint().1)
+    }
+}
+
+impl<T> Debug for FactsOf<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FactsOf")
+            .field("type", &std::any::type_name::<T>())
+            .field("count", &self.handles.len())
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,6 +345,13 @@ mod tests {
         value: i32,
     }
 
+    #[test]
+    fn test_boxed_fact_handle() {
+        let handle = FactHandle::from_boxed(Box::new(TestFact { value: 3 }), 0);
+        assert!(handle.is_type::<TestFact>());
+        assert_eq!(handle.downcast_ref::<TestFact>().unwrap().value, 3);
+    }
+
     #[test]
     fn test_fact_id_uniqueness() {
         let id1 = FactId::new();
@@ -153,4 +376,10 @@ mod tests {
         assert!(handle.is_type::<TestFact>());
         assert!(!handle.is_type::<String>());
     }
+
+    #[test]
+    fn test_fact_handle_type_name() {
+        let handle = FactHandle::new(TestFact { value: 42 }, 0);
+        assert_eq!(handle.type_name(), std::any::type_name::<TestFact>());
+    }
 }
@@ -0,0 +1,667 @@
+//! Typed field accessors and the constraints built from them
+//!
+//! A [`Field`] names a field of a fact type and reads it without
+//! downcasting, so constraints can be written as `Message::field_count().gt(5)`
+//! instead of `with_filter` closures. `#[derive(Fact)]` (feature `derive`)
+//! generates a `field_<name>()` accessor for every field of a struct, a
+//! [`Fields`](crate::value::Fields) implementation for expressions and a
+//! `pattern(alias)` constructor that registers it:
+//!
+//! ```ignore
+//! #[derive(Debug, Clone, Fact)]
+//! struct Message {
+//!     text: String,
+//!     count: usize,
+//! }
+//!
+//! let pattern = Message::pattern("m")
+//!     .with(Message::field_count().gt(5))
+//!     .with_expression("text != ''")?;
+//! ```
+
+use crate::analysis::Comparison;
+use crate::constraint::{Constraint, ConstraintContext};
+use crate::error::Result;
+use crate::expr::{like, Expression};
+use crate::fact::{Fact, FactHandle};
+use crate::pattern::ObjectPattern;
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fmt::{self, Debug};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// A named field of fact type `T` holding a `V`
+pub struct Field<T, V> {
+    name: &'static str,
+    get: fn(&T) -> &V,
+}
+
+impl<T, V> Field<T, V> {
+    /// Create an accessor for the field called `name`
+    pub const fn new(name: &'static str, get: fn(&T) -> &V) -> Self {
+        Self { name, get }
+    }
+
+    /// Get the name of the field
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Read the field of a fact
+    pub fn get<'a>(&self, fact: &'a T) -> &'a V {
+        (self.get)(fact)
+    }
+}
+
+impl<T: Fact, V: Debug + Send + Sync + 'static> Field<T, V> {
+    fn compare(self, op: &str, value: V, test: fn(&V, &V) -> bool) -> FieldConstraint<T> {
+        let description = format!("{} {} {:?}", self.name, op, value);
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| test(get(fact), &value))
+    }
+
+    /// Match facts whose field equals `value`
+    pub fn eq(self, value: V) -> FieldConstraint<T>
+    where
+        V: PartialEq,
+    {
+        self.compare("==", value, V::eq)
+    }
+
+    /// Match facts whose field differs from `value`
+    pub fn ne(self, value: V) -> FieldConstraint<T>
+    where
+        V: PartialEq,
+    {
+        self.compare("!=", value, V::ne)
+    }
+
+    /// Match facts whose field is greater than `value`
+    pub fn gt(self, value: V) -> FieldConstraint<T>
+    where
+        V: PartialOrd,
+    {
+        self.compare(">", value, V::gt)
+    }
+
+    /// Match facts whose field is at least `value`
+    pub fn ge(self, value: V) -> FieldConstraint<T>
+    where
+        V: PartialOrd,
+    {
+        self.compare(">=", value, V::ge)
+    }
+
+    /// Match facts whose field is less than `value`
+    pub fn lt(self, value: V) -> FieldConstraint<T>
+    where
+        V: PartialOrd,
+    {
+        self.compare("<", value, V::lt)
+    }
+
+    /// Match facts whose field is at most `value`
+    pub fn le(self, value: V) -> FieldConstraint<T>
+    where
+        V: PartialOrd,
+    {
+        self.compare("<=", value, V::le)
+    }
+
+    /// Match facts whose field lies between `low` and `high`, inclusive
+    pub fn between(self, low: V, high: V) -> FieldConstraint<T>
+    where
+        V: PartialOrd,
+    {
+        let description = format!(
+            "{name} >= {:?} && {name} <= {:?}",
+            low,
+            high,
+            name = self.name
+        );
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| {
+            let value = get(fact);
+            *value >= low && *value <= high
+        })
+    }
+
+    /// Match facts whose field equals one of `values`
+    pub fn in_list(self, values: impl IntoIterator<Item = V>) -> FieldConstraint<T>
+    where
+        V: PartialEq,
+    {
+        let values: Vec<V> = values.into_iter().collect();
+        let description = format!("{} in {:?}", self.name, values);
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| values.contains(get(fact)))
+    }
+
+    fn text(self, op: &str, pattern: String, test: fn(&str, &str) -> bool) -> FieldConstraint<T>
+    where
+        V: AsRef<str>,
+    {
+        let description = format!("{} {} {:?}", self.name, op, pattern);
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| test(get(fact).as_ref(), &pattern))
+    }
+
+    /// Match facts whose field contains `value`, as a substring of text
+    /// or an item of a collection
+    pub fn contains<Q>(self, value: Q) -> FieldConstraint<T>
+    where
+        V: Contains<Q>,
+        Q: Debug + Send + Sync + 'static,
+    {
+        let description = format!("{} contains {:?}", self.name, value);
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| get(fact).contains_value(&value))
+    }
+
+    /// Match facts whose collection field has an item passing `predicate`
+    pub fn any<I, F>(self, predicate: F, description: impl Into<String>) -> FieldConstraint<T>
+    where
+        for<'a> &'a V: IntoIterator<Item = &'a I>,
+        F: Fn(&I) -> bool + Send + Sync + 'static,
+    {
+        let description = format!("any of {}: {}", self.name, description.into());
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| {
+            get(fact).into_iter().any(&predicate)
+        })
+    }
+
+    /// Match facts whose collection field has only items passing `predicate`
+    pub fn all<I, F>(self, predicate: F, description: impl Into<String>) -> FieldConstraint<T>
+    where
+        for<'a> &'a V: IntoIterator<Item = &'a I>,
+        F: Fn(&I) -> bool + Send + Sync + 'static,
+    {
+        let description = format!("all of {}: {}", self.name, description.into());
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| {
+            get(fact).into_iter().all(&predicate)
+        })
+    }
+
+    /// Match facts whose collection field holds between `min` and `max`
+    /// items, inclusive
+    pub fn len_between(self, min: usize, max: usize) -> FieldConstraint<T>
+    where
+        for<'a> &'a V: IntoIterator,
+        for<'a> <&'a V as IntoIterator>::IntoIter: ExactSizeIterator,
+    {
+        let description = format!(
+            "{name}.length >= {} && {name}.length <= {}",
+            min,
+            max,
+            name = self.name
+        );
+        let get = self.get;
+        FieldConstraint::new(description, move |fact| {
+            (min..=max).contains(&get(fact).into_iter().len())
+        })
+    }
+
+    /// Match facts whose field starts with `prefix`
+    pub fn starts_with(self, prefix: impl Into<String>) -> FieldConstraint<T>
+    where
+        V: AsRef<str>,
+    {
+        self.text("startsWith", prefix.into(), |field, prefix| {
+            field.starts_with(prefix)
+        })
+    }
+
+    /// Match facts whose field ends with `suffix`
+    pub fn ends_with(self, suffix: impl Into<String>) -> FieldConstraint<T>
+    where
+        V: AsRef<str>,
+    {
+        self.text("endsWith", suffix.into(), |field, suffix| {
+            field.ends_with(suffix)
+        })
+    }
+
+    /// Match facts whose field matches a case-insensitive SQL `LIKE`
+    /// pattern, where `%` stands for any run of characters and `_` for one
+    pub fn like(self, pattern: impl Into<String>) -> FieldConstraint<T>
+    where
+        V: AsRef<str>,
+    {
+        self.text("ilike", pattern.into(), like)
+    }
+}
+
+/// Field types [`Field::contains`] can look a `Q` up in
+pub trait Contains<Q> {
+    /// Check whether `value` is part of this field
+    fn contains_value(&self, value: &Q) -> bool;
+}
+
+impl<Q: AsRef<str>> Contains<Q> for String {
+    fn contains_value(&self, value: &Q) -> bool {
+        self.contains(value.as_ref())
+    }
+}
+
+impl<Q: AsRef<str>> Contains<Q> for &str {
+    fn contains_value(&self, value: &Q) -> bool {
+        self.contains(value.as_ref())
+    }
+}
+
+impl<I: PartialEq<Q>, Q> Contains<Q> for Vec<I> {
+    fn contains_value(&self, value: &Q) -> bool {
+        self.iter().any(|item| item == value)
+    }
+}
+
+impl<I: PartialEq<Q>, Q> Contains<Q> for VecDeque<I> {
+    fn contains_value(&self, value: &Q) -> bool {
+        self.iter().any(|item| item == value)
+    }
+}
+
+impl<I, Q, S> Contains<Q> for HashSet<I, S>
+where
+    I: Borrow<Q> + Eq + Hash,
+    Q: Eq + Hash,
+    S: BuildHasher,
+{
+    fn contains_value(&self, value: &Q) -> bool {
+        self.contains(value)
+    }
+}
+
+impl<I: Borrow<Q> + Ord, Q: Ord> Contains<Q> for BTreeSet<I> {
+    fn contains_value(&self, value: &Q) -> bool {
+        self.contains(value)
+    }
+}
+
+/// A field of the facts an [`ObjectPattern`] matches, awaiting its comparison
+///
+/// Created by [`ObjectPattern::where_field`]; every comparison adds a
+/// [`FieldConstraint`] to the pattern and returns it.
+pub struct WhereField<T: Fact, V> {
+    pattern: ObjectPattern<T>,
+    field: Field<T, V>,
+}
+
+impl<T: Fact, V: Debug + Send + Sync + 'static> WhereField<T, V> {
+    pub(crate) fn new(pattern: ObjectPattern<T>, field: Field<T, V>) -> Self {
+        Self { pattern, field }
+    }
+
+    /// Match facts whose field equals `value`
+    pub fn eq(self, value: V) -> ObjectPattern<T>
+    where
+        V: PartialEq,
+    {
+        self.pattern.with(self.field.eq(value))
+    }
+
+    /// Match facts whose field differs from `value`
+    pub fn ne(self, value: V) -> ObjectPattern<T>
+    where
+        V: PartialEq,
+    {
+        self.pattern.with(self.field.ne(value))
+    }
+
+    /// Match facts whose field is greater than `value`
+    pub fn gt(self, value: V) -> ObjectPattern<T>
+    where
+        V: PartialOrd,
+    {
+        self.pattern.with(self.field.gt(value))
+    }
+
+    /// Match facts whose field is at least `value`
+    pub fn ge(self, value: V) -> ObjectPattern<T>
+    where
+        V: PartialOrd,
+    {
+        self.pattern.with(self.field.ge(value))
+    }
+
+    /// Match facts whose field is less than `value`
+    pub fn lt(self, value: V) -> ObjectPattern<T>
+    where
+        V: PartialOrd,
+    {
+        self.pattern.with(self.field.lt(value))
+    }
+
+    /// Match facts whose field is at most `value`
+    pub fn le(self, value: V) -> ObjectPattern<T>
+    where
+        V: PartialOrd,
+    {
+        self.pattern.with(self.field.le(value))
+    }
+
+    /// Match facts whose field lies between `low` and `high`, inclusive
+    pub fn between(self, low: V, high: V) -> ObjectPattern<T>
+    where
+        V: PartialOrd,
+    {
+        self.pattern.with(self.field.between(low, high))
+    }
+
+    /// Match facts whose field equals one of `values`
+    pub fn in_list(self, values: impl IntoIterator<Item = V>) -> ObjectPattern<T>
+    where
+        V: PartialEq,
+    {
+        self.pattern.with(self.field.in_list(values))
+    }
+
+    /// Match facts whose field contains `value`, as a substring of text
+    /// or an item of a collection
+    pub fn contains<Q>(self, value: Q) -> ObjectPattern<T>
+    where
+        V: Contains<Q>,
+        Q: Debug + Send + Sync + 'static,
+    {
+        self.pattern.with(self.field.contains(value))
+    }
+
+    /// Match facts whose collection field has an item passing `predicate`
+    pub fn any<I, F>(self, predicate: F, description: impl Into<String>) -> ObjectPattern<T>
+    where
+        for<'a> &'a V: IntoIterator<Item = &'a I>,
+        F: Fn(&I) -> bool + Send + Sync + 'static,
+    {
+        self.pattern.with(self.field.any(predicate, description))
+    }
+
+    /// Match facts whose collection field has only items passing `predicate`
+    pub fn all<I, F>(self, predicate: F, description: impl Into<String>) -> ObjectPattern<T>
+    where
+        for<'a> &'a V: IntoIterator<Item = &'a I>,
+        F: Fn(&I) -> bool + Send + Sync + 'static,
+    {
+        self.pattern.with(self.field.all(predicate, description))
+    }
+
+    /// Match facts whose collection field holds between `min` and `max` items
+    pub fn len_between(self, min: usize, max: usize) -> ObjectPattern<T>
+    where
+        for<'a> &'a V: IntoIterator,
+        for<'a> <&'a V as IntoIterator>::IntoIter: ExactSizeIterator,
+    {
+        self.pattern.with(self.field.len_between(min, max))
+    }
+
+    /// Match facts whose field starts with `prefix`
+    pub fn starts_with(self, prefix: impl Into<String>) -> ObjectPattern<T>
+    where
+        V: AsRef<str>,
+    {
+        self.pattern.with(self.field.starts_with(prefix))
+    }
+
+    /// Match facts whose field ends with `suffix`
+    pub fn ends_with(self, suffix: impl Into<String>) -> ObjectPattern<T>
+    where
+        V: AsRef<str>,
+    {
+        self.pattern.with(self.field.ends_with(suffix))
+    }
+
+    /// Match facts whose field matches a case-insensitive SQL `LIKE` pattern
+    pub fn like(self, pattern: impl Into<String>) -> ObjectPattern<T>
+    where
+        V: AsRef<str>,
+    {
+        self.pattern.with(self.field.like(pattern))
+    }
+}
+
+impl<T: Fact, V> Debug for WhereField<T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WhereField")
+            .field("pattern", &self.pattern)
+            .field("field", &self.field)
+            .finish()
+    }
+}
+
+impl<T, V> Clone for Field<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Field<T, V> {}
+
+impl<T, V> Debug for Field<T, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Field").field(&self.name).finish()
+    }
+}
+
+/// A described constraint on facts of type `T`
+pub struct FieldConstraint<T> {
+    description: String,
+    test: Arc<dyn Fn(&T) -> bool + Send + Sync>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T: Fact> FieldConstraint<T> {
+    /// Create a constraint from a description and a test
+    pub fn new<F>(description: impl Into<String>, test: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            description: description.into(),
+            test: Arc::new(test),
+            _type: PhantomData,
+        }
+    }
+
+    /// Get the description, such as `count > 5`
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl<T> Clone for FieldConstraint<T> {
+    fn clone(&self) -> Self {
+        Self {
+            description: self.description.clone(),
+            test: Arc::clone(&self.test),
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for FieldConstraint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FieldConstraint")
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+impl<T: Fact> Constraint for FieldConstraint<T> {
+    fn evaluate(&self, fact: &FactHandle, _context: &ConstraintContext) -> Result<bool> {
+        Ok(fact
+            .downcast_ref::<T>()
+            .is_some_and(|fact| (self.test)(fact)))
+    }
+
+    fn clone_box(&self) -> Box<dyn Constraint> {
+        Box::new(self.clone())
+    }
+
+    /// Read from the description, which is an expression when the field's
+    /// value formats as a literal
+    fn comparisons(&self) -> Vec<Comparison> {
+        Expression::parse(&self.description)
+            .map(|expression| expression.comparisons())
+            .unwrap_or_default()
+    }
+
+    fn description(&self) -> String {
+        self.description.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::CompareOp;
+    use crate::pattern::Pattern;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone)]
+    struct Message {
+        text: String,
+        count: usize,
+    }
+
+    #[test]
+    fn test_field_constraints() {
+        let count = Field::new("count", |m: &Message| &m.count);
+        let text = Field::new("text", |m: &Message| &m.text);
+        let message = Message {
+            text: "hello".to_string(),
+            count: 7,
+        };
+        assert_eq!(*count.get(&message), 7);
+
+        let handle = FactHandle::new(message, 0);
+        let context = ConstraintContext::new();
+        let holds = |c: FieldConstraint<Message>| c.evaluate(&handle, &context).unwrap();
+        assert!(holds(count.gt(5)));
+        assert!(holds(count.le(7)));
+        assert!(!holds(count.lt(7)));
+        assert!(holds(text.eq("hello".to_string())));
+        assert!(!holds(text.ne("hello".to_string())));
+
+        assert_eq!(count.ge(3).description(), "count >= 3");
+        assert_eq!(text.eq("a".to_string()).description(), "text == \"a\"");
+        assert_eq!(
+            count.gt(5).comparisons(),
+            [Comparison::new("count", CompareOp::Gt, 5.into())]
+        );
+        let other = FactHandle::new(3u8, 0);
+        assert!(!count.gt(0).evaluate(&other, &context).unwrap());
+
+        assert!(holds(count.between(7, 9)));
+        assert!(!holds(count.between(1, 6)));
+        assert!(holds(count.in_list([1, 7])));
+        assert!(!holds(count.in_list(Vec::new())));
+        assert_eq!(
+            count.between(1, 6).description(),
+            "count >= 1 && count <= 6"
+        );
+        assert_eq!(count.in_list([1, 7]).description(), "count in [1, 7]");
+
+        assert!(holds(text.contains("ell")));
+        assert!(holds(text.starts_with("he")));
+        assert!(!holds(text.ends_with("x")));
+        assert!(holds(text.like("HE_L%")));
+        assert_eq!(text.like("he%").description(), "text ilike \"he%\"");
+        // Descriptions are expressions
+        let record = FactHandle::new(
+            HashMap::from([("text".to_string(), crate::value::Value::from("hello"))]),
+            0,
+        );
+        for constraint in [text.contains("ll"), text.ends_with("lo"), text.like("%L_")] {
+            assert!(Expression::parse(constraint.description())
+                .unwrap()
+                .test(&record));
+        }
+        assert_eq!(
+            count.between(1, 6).comparisons(),
+            [
+                Comparison::new("count", CompareOp::Ge, 1.into()),
+                Comparison::new("count", CompareOp::Le, 6.into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collection_constraints() {
+        #[derive(Debug, Clone)]
+        struct Order {
+            tags: Vec<String>,
+            items: HashSet<u32>,
+        }
+
+        let tags = Field::new("tags", |o: &Order| &o.tags);
+        let items = Field::new("items", |o: &Order| &o.items);
+        let handle = FactHandle::new(
+            Order {
+                tags: vec!["vip".to_string(), "rush".to_string()],
+                items: HashSet::from([3, 8]),
+            },
+            0,
+        );
+        let context = ConstraintContext::new();
+        let holds = |c: FieldConstraint<Order>| c.evaluate(&handle, &context).unwrap();
+        assert!(holds(tags.contains("vip")));
+        assert!(!holds(tags.contains("gift")));
+        assert!(holds(items.contains(8)));
+        assert!(holds(tags.any(|t| t.len() == 4, "4 letters")));
+        assert!(!holds(tags.all(|t| t.len() == 4, "4 letters")));
+        assert!(holds(items.all(|i| *i < 10, "item < 10")));
+        assert!(holds(items.len_between(1, 2)));
+        assert!(!holds(tags.len_between(3, 5)));
+
+        assert_eq!(tags.contains("vip").description(), "tags contains \"vip\"");
+        assert_eq!(
+            items.any(|i| *i > 5, "item > 5").description(),
+            "any of items: item > 5"
+        );
+        assert_eq!(
+            items.len_between(1, 3).description(),
+            "items.length >= 1 && items.length <= 3"
+        );
+
+        let pattern = ObjectPattern::<Order>::new("o")
+            .where_field("tags", |o| &o.tags)
+            .contains("rush")
+            .where_field("items", |o| &o.items)
+            .len_between(1, 5);
+        assert!(pattern.matches(&handle, &context).unwrap());
+    }
+
+    #[test]
+    fn test_where_field() {
+        let pattern = ObjectPattern::<Message>::new("m")
+            .where_field("count", |m| &m.count)
+            .between(5, 10)
+            .where_field("text", |m| &m.text)
+            .in_list(["hi".to_string(), "hello".to_string()]);
+        let descriptions: Vec<_> = pattern
+            .constraints
+            .iter()
+            .map(|c| c.description())
+            .collect();
+        assert_eq!(
+            descriptions,
+            ["count >= 5 && count <= 10", "text in [\"hi\", \"hello\"]"]
+        );
+
+        let context = ConstraintContext::new();
+        let message = FactHandle::new(
+            Message {
+                text: "hello".to_string(),
+                count: 7,
+            },
+            0,
+        );
+        assert!(pattern
+            .constraints
+            .iter()
+            .all(|c| c.evaluate(&message, &context).unwrap()));
+    }
+}
@@ -0,0 +1,633 @@
+//! Replayable fixtures captured from asserted facts
+//!
+//! A [`FixtureCapture`] attached to a session with [`Session::capture`]
+//! samples the facts asserted into it, redacts sensitive fields and collects
+//! them into a [`Fixture`]. Saved fixtures can later be replayed into a fresh
+//! session, so regression suites can be built from real inputs.
+//!
+//! Fixtures record a schema fingerprint of each fact type: the sorted field
+//! names of the struct. When a fact type changes, register a migration from
+//! the old shape with [`FixtureTypes::register_migration`] and older fixtures
+//! are upgraded while they are replayed.
+//!
+//! The same registry serializes facts for [`crate::snapshot`] and
+//! [`crate::store`].
+
+use crate::dsl::DefinedFact;
+use crate::error::{Error, Result};
+use crate::fact::{Fact, FactHandle, FactId};
+use crate::session::Session;
+use crate::snapshot::SerializedFact;
+use crate::template::FactTemplate;
+use crate::value::Value;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Placeholder for redacted string fields
+pub const REDACTED: &str = "[REDACTED]";
+
+type SerializeFn = fn(&dyn Any) -> serde_json::Result<serde_json::Value>;
+type DeserializeFn = fn(serde_json::Value) -> serde_json::Result<Box<dyn Fact>>;
+type AssertFn = fn(&mut Session, serde_json::Value) -> Result<FactId>;
+type MigrateFn = Arc<dyn Fn(serde_json::Value) -> Result<serde_json::Value> + Send + Sync>;
+
+/// Upgrades facts of one schema to the next
+#[derive(Clone)]
+struct Migration {
+    to: String,
+    migrate: MigrateFn,
+}
+
+/// Fact types that can be serialized, by stable name
+///
+/// Used to capture and replay fixtures, and to snapshot and store working memory.
+#[derive(Clone, Default)]
+pub struct FixtureTypes {
+    serializers: HashMap<TypeId, (String, SerializeFn)>,
+    deserializers: HashMap<String, DeserializeFn>,
+    asserters: HashMap<String, AssertFn>,
+    /// Templates whose [`DefinedFact`]s are serialized as their fields
+    templates: HashMap<String, FactTemplate>,
+    schemas: HashMap<String, String>,
+    migrations: HashMap<String, Migration>,
+}
+
+impl FixtureTypes {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a fact type under a stable name
+    pub fn register<T>(mut self, name: impl Into<String>) -> Self
+    where
+        T: Fact + Serialize + DeserializeOwned,
+    {
+        let name = name.into();
+        self.serializers
+            .insert(TypeId::of::<T>(), (name.clone(), serialize::<T>));
+        self.deserializers.insert(name.clone(), deserialize::<T>);
+        self.asserters.insert(name.clone(), assert::<T>);
+        self.schemas.insert(name, fingerprint::<T>());
+        self
+    }
+
+    /// Register the facts of a template, such as a DSL `define` block, under its name
+    ///
+    /// Facts are recorded as an object of their slots and created from the
+    /// template's defaults, so slots missing from a recorded fact keep them.
+    pub fn register_template(mut self, template: &FactTemplate) -> Self {
+        let mut slots: Vec<_> = template
+            .slots()
+            .iter()
+            .map(|slot| slot.name.as_str())
+            .collect();
+        slots.sort_unstable();
+        self.schemas
+            .insert(template.name().to_string(), slots.join(","));
+        self.templates
+            .insert(template.name().to_string(), template.clone());
+        self
+    }
+
+    /// Register how facts recorded with `Old`'s schema become `New` facts
+    ///
+    /// Migrations chain, so fixtures several versions behind are upgraded
+    /// step by step to the registered type's current schema.
+    pub fn register_migration<Old, New, F>(mut self, f: F) -> Self
+    where
+        Old: DeserializeOwned,
+        New: Serialize + DeserializeOwned,
+        F: Fn(Old) -> New + Send + Sync + 'static,
+    {
+        let migrate: MigrateFn = Arc::new(move |fact| {
+            let old: Old = serde_json::from_value(fact).map_err(|e| {
+                Error::Execution(format!(
+                    "Invalid {} fixture fact: {}",
+                    std::any::type_name::<Old>(),
+                    e
+                ))
+            })?;
+            serde_json::to_value(f(old))
+                .map_err(|e| Error::Execution(format!("Failed to serialize migrated fact: {}", e)))
+        });
+        self.migrations.insert(
+            fingerprint::<Old>(),
+            Migration {
+                to: fingerprint::<New>(),
+                migrate,
+            },
+        );
+        self
+    }
+
+    /// Deserialize a fact of a registered type and assert it into a session
+    pub(crate) fn assert(
+        &self,
+        session: &mut Session,
+        type_name: &str,
+        fact: serde_json::Value,
+    ) -> Result<FactId> {
+        if let Some(template) = self.templates.get(type_name) {
+            return session
+                .assert(template_fact(template, fact)?)
+                .map(FactId::from);
+        }
+        let assert = self.asserters.get(type_name).ok_or_else(|| {
+            Error::Execution(format!(
+                "Fixture fact type '{}' is not registered",
+                type_name
+            ))
+        })?;
+        assert(session, fact)
+    }
+
+    /// Check whether a type was registered under a name
+    pub fn contains(&self, name: &str) -> bool {
+        self.deserializers.contains_key(name) || self.templates.contains_key(name)
+    }
+
+    /// Fail unless a fact is of a registered type
+    pub(crate) fn check(&self, fact: &dyn Fact) -> Result<()> {
+        match fact.as_any().downcast_ref::<DefinedFact>() {
+            Some(defined) if self.templates.contains_key(defined.type_name()) => Ok(()),
+            _ if self.serializers.contains_key(&fact.as_any().type_id()) => Ok(()),
+            Some(defined) => Err(unregistered(defined.type_name())),
+            None => Err(unregistered(fact.type_name())),
+        }
+    }
+
+    /// Serialize a fact of a registered type
+    pub fn serialize(&self, fact: &FactHandle) -> Result<SerializedFact> {
+        if let Some(defined) = fact.downcast_ref::<DefinedFact>() {
+            if self.templates.contains_key(defined.type_name()) {
+                let fields: BTreeMap<_, _> = defined.fields().collect();
+                return Ok(SerializedFact {
+                    id: fact.id,
+                    type_name: defined.type_name().to_string(),
+                    fact: serde_json::to_value(fields).map_err(|e| {
+                        Error::Execution(format!("Failed to serialize fact {:?}: {}", fact.id, e))
+                    })?,
+                });
+            }
+        }
+        let (name, serialize) = self
+            .serializers
+            .get(&fact.type_id)
+            .ok_or_else(|| unregistered(fact.type_name()))?;
+        let value = serialize(fact.fact.as_ref().as_any()).map_err(|e| {
+            Error::Execution(format!("Failed to serialize fact {:?}: {}", fact.id, e))
+        })?;
+        Ok(SerializedFact {
+            id: fact.id,
+            type_name: name.clone(),
+            fact: value,
+        })
+    }
+
+    /// Deserialize a fact of a registered type
+    pub fn deserialize(&self, fact: &SerializedFact) -> Result<Box<dyn Fact>> {
+        self.build(&fact.type_name, fact.fact.clone())
+    }
+
+    /// Build a fact of the type registered under a name from its JSON form
+    pub(crate) fn build(&self, type_name: &str, fact: serde_json::Value) -> Result<Box<dyn Fact>> {
+        if let Some(template) = self.templates.get(type_name) {
+            return Ok(Box::new(template_fact(template, fact)?));
+        }
+        let deserialize = self
+            .deserializers
+            .get(type_name)
+            .ok_or_else(|| unregistered(type_name))?;
+        deserialize(fact)
+            .map_err(|e| Error::Execution(format!("Invalid {} fact: {}", type_name, e)))
+    }
+
+    /// Get the schema fingerprint of a registered type
+    pub fn schema(&self, name: &str) -> Option<&str> {
+        self.schemas.get(name).map(String::as_str)
+    }
+
+    /// Upgrade a fact recorded with schema `from` to the registered type's schema
+    fn migrate(
+        &self,
+        name: &str,
+        from: &str,
+        mut fact: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let Some(target) = self.schemas.get(name) else {
+            return Ok(fact);
+        };
+
+        let mut schema = from;
+        let mut visited = HashSet::new();
+        while schema != target {
+            let migration = self
+                .migrations
+                .get(schema)
+                .filter(|_| visited.insert(schema))
+                .ok_or_else(|| {
+                    Error::Execution(format!(
+                        "No migration path for fixture type '{}' from schema [{}] to [{}]",
+                        name, from, target
+                    ))
+                })?;
+            fact = (migration.migrate)(fact)?;
+            schema = &migration.to;
+        }
+        Ok(fact)
+    }
+}
+
+impl std::fmt::Debug for FixtureTypes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixtureTypes")
+            .field("schemas", &self.schemas)
+            .field("migrations", &self.migrations.len())
+            .finish()
+    }
+}
+
+/// Schema fingerprint of a type: the sorted field names of a struct, or the
+/// type name for anything else
+pub fn fingerprint<T: DeserializeOwned>() -> String {
+    let mut fields = None;
+    let _ = T::deserialize(SchemaProbe(&mut fields));
+    match fields {
+        Some(fields) => {
+            let mut fields = fields.to_vec();
+            fields.sort_unstable();
+            fields.join(",")
+        }
+        None => std::any::type_name::<T>().to_string(),
+    }
+}
+
+/// Deserializer that only records the fields a struct asks for
+struct SchemaProbe<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de> serde::Deserializer<'de> for SchemaProbe<'_> {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        Err(serde::de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: serde::de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(serde::de::Error::custom("schema probed"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Create a fact of a template from an object of slot values
+fn template_fact(template: &FactTemplate, fact: serde_json::Value) -> Result<DefinedFact> {
+    let slots: BTreeMap<String, Value> = serde_json::from_value(fact)
+        .map_err(|e| Error::Execution(format!("Invalid {} fact: {}", template.name(), e)))?;
+    template.create(slots)
+}
+
+fn unregistered(type_name: &str) -> Error {
+    Error::Execution(format!(
+        "Fact type '{}' is not registered for serialization",
+        type_name
+    ))
+}
+
+fn serialize<T: Fact + Serialize>(fact: &dyn Any) -> serde_json::Result<serde_json::Value> {
+    serde_json::to_value(fact.downcast_ref::<T>())
+}
+
+fn deserialize<T: Fact + DeserializeOwned>(
+    fact: serde_json::Value,
+) -> serde_json::Result<Box<dyn Fact>> {
+    serde_json::from_value::<T>(fact).map(|fact| Box::new(fact) as Box<dyn Fact>)
+}
+
+fn assert<T: Fact + DeserializeOwned>(
+    session: &mut Session,
+    fact: serde_json::Value,
+) -> Result<FactId> {
+    let fact: T = serde_json::from_value(fact)
+        .map_err(|e| Error::Execution(format!("Invalid fixture fact: {}", e)))?;
+    session.assert(fact).map(FactId::from)
+}
+
+/// A fact recorded in a fixture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixtureFact {
+    /// Registered name of the fact type
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// The serialized fact
+    pub fact: serde_json::Value,
+}
+
+/// Facts captured from a session, in assertion order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    /// Name of the flow the facts were asserted into
+    pub flow: String,
+    /// Schema fingerprint of each captured fact type when it was captured
+    #[serde(default)]
+    pub schemas: BTreeMap<String, String>,
+    /// The captured facts
+    pub facts: Vec<FixtureFact>,
+}
+
+impl Fixture {
+    /// Serialize this fixture to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Execution(format!("Failed to serialize fixture: {}", e)))
+    }
+
+    /// Deserialize a fixture from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| Error::Execution(format!("Failed to deserialize fixture: {}", e)))
+    }
+
+    /// Write this fixture to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?).map_err(|e| {
+            Error::Execution(format!("Failed to write fixture {}: {}", path.display(), e))
+        })
+    }
+
+    /// Read a fixture from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|e| {
+            Error::Execution(format!("Failed to read fixture {}: {}", path.display(), e))
+        })?;
+        Self::from_json(&json)
+    }
+
+    /// Assert the captured facts into a session, migrating outdated schemas
+    pub fn replay(&self, session: &mut Session, types: &FixtureTypes) -> Result<Vec<FactId>> {
+        self.facts
+            .iter()
+            .map(|fact| types.assert(session, &fact.type_name, self.migrated(fact, types)?))
+            .collect()
+    }
+
+    /// Upgrade every fact to the current schema of its registered type
+    pub fn migrate(&mut self, types: &FixtureTypes) -> Result<()> {
+        let facts = self
+            .facts
+            .iter()
+            .map(|fact| self.migrated(fact, types))
+            .collect::<Result<Vec<_>>>()?;
+        for (fact, migrated) in self.facts.iter_mut().zip(facts) {
+            fact.fact = migrated;
+        }
+        for (name, schema) in self.schemas.iter_mut() {
+            if let Some(current) = types.schema(name) {
+                *schema = current.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    fn migrated(&self, fact: &FixtureFact, types: &FixtureTypes) -> Result<serde_json::Value> {
+        match self.schemas.get(&fact.type_name) {
+            Some(schema) => types.migrate(&fact.type_name, schema, fact.fact.clone()),
+            None => Ok(fact.fact.clone()),
+        }
+    }
+}
+
+/// Samples and redacts asserted facts into a fixture
+#[derive(Debug, Clone)]
+pub struct FixtureCapture {
+    types: FixtureTypes,
+    sample_rate: f64,
+    redacted: HashSet<String>,
+    observed: u64,
+    fixture: Fixture,
+}
+
+impl FixtureCapture {
+    /// Capture every fact of the registered types
+    pub fn new(flow: impl Into<String>, types: FixtureTypes) -> Self {
+        Self {
+            types,
+            sample_rate: 1.0,
+            redacted: HashSet::new(),
+            observed: 0,
+            fixture: Fixture {
+                flow: flow.into(),
+                schemas: BTreeMap::new(),
+                facts: Vec::new(),
+            },
+        }
+    }
+
+    /// Capture this fraction of the observed facts, evenly spaced
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Redact a field wherever it appears in a captured fact
+    ///
+    /// Redacted values keep their JSON type so fixtures still deserialize.
+    pub fn redact(mut self, field: impl Into<String>) -> Self {
+        self.redacted.insert(field.into());
+        self
+    }
+
+    /// Record an asserted fact if it is sampled and of a registered type
+    pub fn observe(&mut self, fact: &FactHandle) {
+        if self.types.check(fact.fact.as_ref()).is_err() {
+            return;
+        }
+
+        self.observed += 1;
+        let due = (self.observed as f64 * self.sample_rate).floor()
+            > ((self.observed - 1) as f64 * self.sample_rate).floor();
+        if !due {
+            return;
+        }
+
+        if let Ok(SerializedFact {
+            type_name,
+            mut fact,
+            ..
+        }) = self.types.serialize(fact)
+        {
+            if let Some(schema) = self.types.schemas.get(&type_name) {
+                self.fixture
+                    .schemas
+                    .entry(type_name.clone())
+                    .or_insert_with(|| schema.clone());
+            }
+            redact_fields(&mut fact, &self.redacted);
+            self.fixture.facts.push(FixtureFact { type_name, fact });
+        }
+    }
+
+    /// Get the fixture captured so far
+    pub fn fixture(&self) -> &Fixture {
+        &self.fixture
+    }
+
+    /// Finish capturing and return the fixture
+    pub fn into_fixture(self) -> Fixture {
+        self.fixture
+    }
+}
+
+fn redact_fields(value: &mut serde_json::Value, fields: &HashSet<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if fields.contains(key) {
+                    redact_value(field);
+                } else {
+                    redact_fields(field, fields);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact_fields(item, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => *s = REDACTED.to_string(),
+        serde_json::Value::Number(n) => *n = 0.into(),
+        serde_json::Value::Bool(b) => *b = false,
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_value),
+        serde_json::Value::Object(map) => map.values_mut().for_each(redact_value),
+        serde_json::Value::Null => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Payment {
+        amount: u32,
+        card_number: String,
+    }
+
+    fn types() -> FixtureTypes {
+        FixtureTypes::new().register::<Payment>("Payment")
+    }
+
+    fn payment(amount: u32) -> Payment {
+        Payment {
+            amount,
+            card_number: "4111111111111111".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_capture_samples_and_redacts() {
+        let flow = Flow::new("payments");
+        let mut session = flow.session();
+        session.capture(
+            FixtureCapture::new("payments", types())
+                .sample_rate(0.5)
+                .redact("card_number"),
+        );
+
+        for amount in 1..=4 {
+            session.assert(payment(amount)).unwrap();
+        }
+        session.assert("ignored".to_string()).unwrap();
+
+        let fixture = session.take_capture().unwrap().into_fixture();
+        assert_eq!(fixture.facts.len(), 2);
+        assert_eq!(fixture.facts[0].fact["amount"], 2);
+        assert_eq!(fixture.facts[0].fact["card_number"], REDACTED);
+    }
+
+    #[test]
+    fn test_fixture_round_trip_and_replay() {
+        let mut capture = FixtureCapture::new("payments", types());
+        capture.observe(&FactHandle::new(payment(10), 0));
+
+        let path = std::env::temp_dir().join(format!("nools-fixture-{}.json", std::process::id()));
+        capture.fixture().save(&path).unwrap();
+        let fixture = Fixture::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut session = Flow::new("payments").session();
+        let ids = fixture.replay(&mut session, &types()).unwrap();
+        assert_eq!(ids.len(), 1);
+        let handle = session.get_fact(ids[0]).unwrap();
+        assert_eq!(handle.downcast_ref::<Payment>(), Some(&payment(10)));
+
+        assert!(fixture.replay(&mut session, &FixtureTypes::new()).is_err());
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct PaymentV1 {
+        cents: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct PaymentV2 {
+        amount: u32,
+    }
+
+    #[test]
+    fn test_replay_migrates_old_schemas() {
+        let mut capture = FixtureCapture::new(
+            "payments",
+            FixtureTypes::new().register::<PaymentV1>("Payment"),
+        );
+        capture.observe(&FactHandle::new(PaymentV1 { cents: 1250 }, 0));
+        let mut fixture = capture.into_fixture();
+        assert_eq!(fixture.schemas["Payment"], "cents");
+
+        let mut session = Flow::new("payments").session();
+        let err = fixture.replay(&mut session, &types()).unwrap_err();
+        assert!(err.to_string().contains("from schema [cents]"));
+
+        let types = types()
+            .register_migration(|old: PaymentV1| PaymentV2 {
+                amount: old.cents / 100,
+            })
+            .register_migration(|old: PaymentV2| Payment {
+                amount: old.amount,
+                card_number: String::new(),
+            });
+        let ids = fixture.replay(&mut session, &types).unwrap();
+        let handle = session.get_fact(ids[0]).unwrap();
+        assert_eq!(handle.downcast_ref::<Payment>().unwrap().amount, 12);
+
+        fixture.migrate(&types).unwrap();
+        assert_eq!(fixture.schemas["Payment"], "amount,card_number");
+        assert_eq!(fixture.facts[0].fact["amount"], 12);
+    }
+}
@@ -1,13 +1,95 @@
 //! Flow container for rules and their execution
 
 use crate::agenda::ConflictResolution;
+use crate::analysis::{self, Warning};
 use crate::error::{Error, Result};
-use crate::node::{AlphaNode, RootNode, TerminalNode};
+use crate::fixture::{Fixture, FixtureTypes};
+use crate::model::{ModelRegistry, Predictor};
+use crate::node::{AlphaNode, JoinNode, Node, NodePosition, RootNode, TerminalNode};
+use crate::pattern::TestPattern;
 use crate::rule::Rule;
-use crate::session::Session;
-use std::collections::HashMap;
+use crate::session::{Global, Session};
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 
+/// Bootstrapping applied to every new session of a flow
+pub type Seed = Arc<dyn Fn(&mut Session) -> Result<()> + Send + Sync>;
+
+/// Deployment configuration that rules can be enabled by
+///
+/// See [`RuleBuilder::enabled_if`](crate::rule::RuleBuilder::enabled_if).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowEnv {
+    flags: HashSet<String>,
+    vars: HashMap<String, String>,
+}
+
+impl FlowEnv {
+    /// Create an environment with no flags or variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn a flag on
+    pub fn with_flag(mut self, name: impl Into<String>) -> Self {
+        self.flags.insert(name.into());
+        self
+    }
+
+    /// Set a variable
+    pub fn with_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Check if a flag is on
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// Get a variable
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+}
+
+/// How [`Flow::reload`] changed a flow's network
+///
+/// Pass it to [`Session::reprime`] to bring the flow's existing sessions up
+/// to date.
+#[derive(Clone, Default)]
+pub struct NetworkChanges {
+    /// Rules whose network, and the node memories of their sessions, were kept
+    pub kept: Vec<String>,
+    /// Changed rules whose network was rebuilt
+    pub rebuilt: Vec<String>,
+    /// New rules
+    pub added: Vec<String>,
+    /// Rules no longer in the flow
+    pub removed: Vec<String>,
+    /// Networks taken out of the flow, whose memories sessions drop
+    pub(crate) retired: Vec<Arc<dyn Node>>,
+}
+
+impl NetworkChanges {
+    /// Check if every rule kept its network
+    pub fn is_empty(&self) -> bool {
+        self.rebuilt.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl std::fmt::Debug for NetworkChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NetworkChanges")
+            .field("kept", &self.kept)
+            .field("rebuilt", &self.rebuilt)
+            .field("added", &self.added)
+            .field("removed", &self.removed)
+            .finish()
+    }
+}
+
 /// Flow represents a container for rules
 pub struct Flow {
     /// Name of this flow
@@ -18,6 +100,16 @@ pub struct Flow {
     root: Arc<RwLock<RootNode>>,
     /// Conflict resolution strategies
     strategies: Vec<ConflictResolution>,
+    /// Predictors available to rule patterns
+    models: ModelRegistry,
+    /// Seeds applied to every new session
+    seeds: Vec<Seed>,
+    /// Load order given to the next added rule
+    next_load_order: u64,
+    /// Environment deciding which conditional rules are active
+    env: FlowEnv,
+    /// Globals shared by every session
+    globals: HashMap<String, Global>,
 }
 
 impl Flow {
@@ -31,6 +123,11 @@ impl Flow {
                 ConflictResolution::Salience,
                 ConflictResolution::ActivationRecency,
             ],
+            models: ModelRegistry::new(),
+            seeds: Vec::new(),
+            next_load_order: 0,
+            env: FlowEnv::default(),
+            globals: HashMap::new(),
         }
     }
 
@@ -39,14 +136,121 @@ impl Flow {
         &self.name
     }
 
+    /// Create a flow without rules but with this flow's queries, strategies,
+    /// models and seeds
+    pub(crate) fn empty_like(&self, name: impl Into<String>) -> Self {
+        let mut root = RootNode::new();
+        if let Ok(own) = self.read_root() {
+            for query in own.query_names() {
+                if let Some(network) = own.query_network(&query) {
+                    root.add_query_network(query, network);
+                }
+            }
+        }
+        Self {
+            name: name.into(),
+            rules: HashMap::new(),
+            root: Arc::new(RwLock::new(root)),
+            strategies: self.strategies.clone(),
+            models: self.models.clone(),
+            seeds: self.seeds.clone(),
+            next_load_order: 0,
+            env: self.env.clone(),
+            globals: self.globals.clone(),
+        }
+    }
+
     /// Set conflict resolution strategies
     pub fn with_strategies(mut self, strategies: Vec<ConflictResolution>) -> Self {
         self.strategies = strategies;
         self
     }
 
+    /// Get the conflict resolution strategies
+    pub fn strategies(&self) -> &[ConflictResolution] {
+        &self.strategies
+    }
+
+    /// Set the environment deciding which conditional rules are active
+    pub fn with_env(mut self, env: FlowEnv) -> Result<Self> {
+        self.set_env(env)?;
+        Ok(self)
+    }
+
+    /// Change the environment, adding and removing conditional rules' networks
+    ///
+    /// Sessions share their flow's network, so this is meant to be called
+    /// before any session is created.
+    pub fn set_env(&mut self, env: FlowEnv) -> Result<()> {
+        self.env = env;
+        let mut rules: Vec<_> = self.rules.values().cloned().collect();
+        rules.sort_by_key(|rule| rule.load_order);
+
+        let mut root = self.write_root()?;
+        for rule in rules {
+            let built = root.rule_network(&rule.name).is_some();
+            match (rule.is_enabled(&self.env), built) {
+                (true, false) => build_rule_network(&mut root, rule),
+                (false, true) => {
+                    root.remove_rule_network(&rule.name);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the environment
+    pub fn env(&self) -> &FlowEnv {
+        &self.env
+    }
+
+    /// Check if a rule exists and is active in this flow's environment
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.rules
+            .get(name)
+            .is_some_and(|rule| rule.is_enabled(&self.env))
+    }
+
+    /// Register a model predictor that rule patterns can invoke
+    pub fn register_model(&mut self, name: impl Into<String>, predictor: Arc<dyn Predictor>) {
+        self.models.register(name, predictor);
+    }
+
+    /// Get a registered model predictor by name
+    pub fn model(&self, name: &str) -> Result<Arc<dyn Predictor>> {
+        self.models.get(name)
+    }
+
+    /// Apply a seed, such as asserting reference facts, to every new session
+    pub fn seed<F>(&mut self, seed: F)
+    where
+        F: Fn(&mut Session) -> Result<()> + Send + Sync + 'static,
+    {
+        self.seeds.push(Arc::new(seed));
+    }
+
+    /// Share a service or value with the rule actions of every new session
+    ///
+    /// Like nools' compile-time `scope`; see [`Session::global`].
+    pub fn set_global<T: std::any::Any + Send + Sync>(
+        &mut self,
+        name: impl Into<String>,
+        value: T,
+    ) {
+        self.globals.insert(name.into(), Arc::new(value));
+    }
+
+    /// Assert the facts of a fixture into every new session
+    pub fn seed_fixture(&mut self, fixture: Fixture, types: FixtureTypes) {
+        self.seed(move |session| fixture.replay(session, &types).map(|_| ()));
+    }
+
     /// Add a rule to this flow
-    pub fn add_rule(&mut self, rule: Rule) -> Result<()> {
+    ///
+    /// Existing sessions match the new rule against the facts they already
+    /// hold at their next assert, retract, modify or match.
+    pub fn add_rule(&mut self, mut rule: Rule) -> Result<()> {
         let rule_name = rule.name.clone();
         if self.rules.contains_key(&rule_name) {
             return Err(Error::Compilation(format!(
@@ -55,6 +259,8 @@ impl Flow {
             )));
         }
 
+        rule.load_order = self.next_load_order;
+        self.next_load_order += 1;
         let rule_arc = Arc::new(rule);
 
         // Build Rete network for this rule
@@ -64,24 +270,206 @@ impl Flow {
         Ok(())
     }
 
-    /// Build Rete network nodes for a rule
+    /// Add copies of the rules of another flow accepted by `filter`
+    ///
+    /// Rules keep their relative load order and get their own network, so
+    /// later changes to `other` do not reach this flow. Nothing is imported
+    /// if a rule's name is already taken. Returns the imported rule names.
+    pub fn import_rules<F>(&mut self, other: &Flow, filter: F) -> Result<Vec<String>>
+    where
+        F: Fn(&Rule) -> bool,
+    {
+        let mut rules: Vec<_> = other.rules.values().filter(|rule| filter(rule)).collect();
+        rules.sort_by_key(|rule| rule.load_order);
+        if let Some(taken) = rules
+            .iter()
+            .find(|rule| self.rules.contains_key(&rule.name))
+        {
+            return Err(Error::Compilation(format!(
+                "Rule '{}' already exists",
+                taken.name
+            )));
+        }
+        let mut imported = Vec::with_capacity(rules.len());
+        for rule in rules {
+            self.add_rule(Rule::clone(rule))?;
+            imported.push(rule.name.clone());
+        }
+        Ok(imported)
+    }
+
+    /// Add copies of every rule of another flow, see [`Flow::import_rules`]
+    ///
+    /// Queries, seeds, globals and models are not merged.
+    pub fn merge(&mut self, other: &Flow) -> Result<Vec<String>> {
+        self.import_rules(other, |_| true)
+    }
+
+    /// Build Rete network nodes for a rule active in this flow's environment
     fn build_network_for_rule(&mut self, rule: Arc<Rule>) -> Result<()> {
-        let mut root = self.root.write().map_err(|e| {
+        check_join_keys(&rule)?;
+        if rule.is_enabled(&self.env) {
+            build_rule_network(&mut *self.write_root()?, rule);
+        }
+        Ok(())
+    }
+
+    /// Replace this flow's rules with those of a recompiled flow
+    ///
+    /// Rules whose patterns describe themselves the same (including
+    /// constraint descriptions) keep their network nodes and only switch to
+    /// the new rule's action and attributes, so sessions keep their node
+    /// memories for them. Other rules get a new network. Queries are replaced
+    /// by the recompiled flow's. The flow keeps its own strategies, models,
+    /// seeds and environment.
+    pub fn reload(&mut self, updated: Flow) -> Result<NetworkChanges> {
+        let mut changes = NetworkChanges::default();
+        let mut rules: Vec<_> = updated.rules.values().cloned().collect();
+        rules.sort_by_key(|rule| rule.load_order);
+        let names: HashSet<_> = rules.iter().map(|rule| rule.name.clone()).collect();
+
+        let shared = Arc::clone(&self.root);
+        let mut root = shared.write().map_err(|e| {
             Error::Compilation(format!("Failed to acquire lock on root node: {}", e))
         })?;
+        let mut removed: Vec<_> = self
+            .rules
+            .values()
+            .filter(|rule| !names.contains(&rule.name))
+            .map(|rule| (rule.load_order, rule.name.clone()))
+            .collect();
+        removed.sort();
+        for (_, name) in removed {
+            changes.retired.extend(root.remove_rule_network(&name));
+            self.rules.remove(&name);
+            changes.removed.push(name);
+        }
+
+        for rule in rules {
+            let name = rule.name.clone();
+            let enabled = rule.is_enabled(&self.env);
+            let network = root.rule_network(&name);
+            match self.rules.get(&name) {
+                Some(old) if same_network(old, &rule) && network.is_some() == enabled => {
+                    if let Some(node) = network {
+                        node.retarget(&rule);
+                    }
+                    changes.kept.push(name.clone());
+                }
+                Some(_) => {
+                    changes.retired.extend(root.remove_rule_network(&name));
+                    if enabled {
+                        build_rule_network(&mut root, Arc::clone(&rule));
+                    }
+                    changes.rebuilt.push(name.clone());
+                }
+                None => {
+                    if enabled {
+                        build_rule_network(&mut root, Arc::clone(&rule));
+                    }
+                    changes.added.push(name.clone());
+                }
+            }
+            self.rules.insert(name, rule);
+        }
+        let queries = updated.read_root()?;
+        for query in root.query_names() {
+            root.remove_query_network(&query);
+        }
+        for query in queries.query_names() {
+            if let Some(network) = queries.query_network(&query) {
+                root.add_query_network(query, network);
+            }
+        }
+        drop(root);
+
+        self.next_load_order = updated.next_load_order;
+        Ok(changes)
+    }
+
+    /// Remove a rule and take its network out of the flow
+    ///
+    /// Other flows sharing the rule's network keep it. Existing sessions
+    /// cancel the rule's activations and drop its node memories at their
+    /// next operation, or right away when given the changes with
+    /// [`Session::reprime`].
+    pub fn remove_rule(&mut self, name: &str) -> Result<NetworkChanges> {
+        if !self.rules.contains_key(name) {
+            return Err(Error::RuleNotFound(name.to_string()));
+        }
+        let mut changes = NetworkChanges::default();
+        changes
+            .retired
+            .extend(self.write_root()?.remove_rule_network(name));
+        self.rules.remove(name);
+        changes.removed.push(name.to_string());
+        Ok(changes)
+    }
+
+    /// Add a rule, replacing any existing rule of the same name
+    pub(crate) fn replace_rule(&mut self, rule: Rule) -> Result<()> {
+        self.remove_rule_network(&rule.name)?;
+        self.rules.remove(&rule.name);
+        self.add_rule(rule)
+    }
+
+    /// Add a rule of another flow, sharing its network nodes
+    pub(crate) fn share_rule(&mut self, other: &Flow, name: &str) -> Result<()> {
+        let rule = other
+            .get_rule(name)
+            .ok_or_else(|| Error::RuleNotFound(name.to_string()))?;
+        let node = other.read_root()?.rule_network(name);
 
-        // For now, create a simple alpha node -> terminal node chain
-        // In a full implementation, this would build a complete Rete network with joins
-        for pattern in &rule.patterns {
-            let mut alpha = AlphaNode::new(pattern.clone_box());
-            let terminal = TerminalNode::new(Arc::clone(&rule));
-            alpha.add_child(Box::new(terminal));
-            root.add_child(Box::new(alpha));
+        self.remove_rule_network(name)?;
+        if let Some(node) = node {
+            self.write_root()?.add_rule_network(name, node);
         }
+        self.rules.insert(name.to_string(), rule);
+        Ok(())
+    }
+
+    /// Check if this flow and another use the same network nodes for a rule
+    pub(crate) fn shares_rule_network(&self, other: &Flow, name: &str) -> bool {
+        let (Ok(mine), Ok(theirs)) = (self.read_root(), other.read_root()) else {
+            return false;
+        };
+        match (mine.rule_network(name), theirs.rule_network(name)) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a, &b),
+            _ => false,
+        }
+    }
 
+    fn remove_rule_network(&mut self, name: &str) -> Result<()> {
+        self.write_root()?.remove_rule_network(name);
         Ok(())
     }
 
+    fn read_root(&self) -> Result<std::sync::RwLockReadGuard<'_, RootNode>> {
+        self.root
+            .read()
+            .map_err(|e| Error::Compilation(format!("Failed to acquire lock on root node: {}", e)))
+    }
+
+    fn write_root(&self) -> Result<std::sync::RwLockWriteGuard<'_, RootNode>> {
+        self.root
+            .write()
+            .map_err(|e| Error::Compilation(format!("Failed to acquire lock on root node: {}", e)))
+    }
+
+    /// Check the rules for problems such as contradictory conditions, see [`crate::analysis`]
+    ///
+    /// Rules disabled in the flow's environment are left out.
+    pub fn analyze(&self) -> Vec<Warning> {
+        let mut rules: Vec<_> = self
+            .rules
+            .values()
+            .filter(|rule| rule.is_enabled(&self.env))
+            .cloned()
+            .collect();
+        rules.sort_by_key(|rule| rule.load_order);
+        analysis::analyze(&rules)
+    }
+
     /// Get a rule by name
     pub fn get_rule(&self, name: &str) -> Option<Arc<Rule>> {
         self.rules.get(name).map(Arc::clone)
@@ -98,12 +486,86 @@ impl Flow {
     }
 
     /// Create a new session from this flow
+    ///
+    /// # Panics
+    ///
+    /// Panics if a seed fails; use [`Flow::try_session`] to handle seed errors.
     pub fn session(&self) -> Session {
-        Session::new(
-            self.name.clone(),
-            Arc::clone(&self.root),
-            self.strategies.clone(),
-        )
+        self.try_session()
+            .unwrap_or_else(|e| panic!("Failed to seed session of flow '{}': {}", self.name, e))
+    }
+
+    /// Create a new session from this flow, returning any seed error
+    pub fn try_session(&self) -> Result<Session> {
+        self.try_session_with_strategies(self.strategies.clone())
+    }
+
+    /// Create a new session that orders its agenda with other strategies
+    pub fn try_session_with_strategies(
+        &self,
+        strategies: Vec<ConflictResolution>,
+    ) -> Result<Session> {
+        self.seeded_session(Arc::clone(&self.root), strategies)
+    }
+
+    /// Create a new session whose rules are chosen by another environment
+    ///
+    /// The session gets its own network, built from the rules active in
+    /// `env`, instead of sharing the flow's.
+    pub fn try_session_with_env(&self, env: &FlowEnv) -> Result<Session> {
+        let mut root = RootNode::new();
+        let mut rules: Vec<_> = self.rules.values().cloned().collect();
+        rules.sort_by_key(|rule| rule.load_order);
+        for rule in rules.into_iter().filter(|rule| rule.is_enabled(env)) {
+            build_rule_network(&mut root, rule);
+        }
+
+        self.seeded_session(Arc::new(RwLock::new(root)), self.strategies.clone())
+    }
+
+    /// Create a session that keeps its facts and agenda in a store, see [`crate::store`]
+    ///
+    /// If the store holds the state of an earlier session, the session
+    /// resumes from it: the stored facts are re-asserted without seeding the
+    /// session again, and combinations that already fired do not fire again.
+    /// Only a store holding neither facts nor a checkpoint is seeded.
+    pub fn restore_session(
+        &self,
+        store: Arc<dyn crate::store::SessionStore>,
+        types: crate::fixture::FixtureTypes,
+    ) -> Result<Session> {
+        let resumed = store.load_checkpoint()?.is_some() || !store.load_page(None, 1)?.is_empty();
+        let mut session = if resumed {
+            self.unseeded_session(Arc::clone(&self.root), self.strategies.clone())
+        } else {
+            self.try_session()?
+        };
+        session.attach_session_store(store, types)?;
+        Ok(session)
+    }
+
+    fn seeded_session(
+        &self,
+        root: Arc<RwLock<RootNode>>,
+        strategies: Vec<ConflictResolution>,
+    ) -> Result<Session> {
+        let mut session = self.unseeded_session(root, strategies);
+        for seed in &self.seeds {
+            seed(&mut session)?;
+        }
+        Ok(session)
+    }
+
+    fn unseeded_session(
+        &self,
+        root: Arc<RwLock<RootNode>>,
+        strategies: Vec<ConflictResolution>,
+    ) -> Session {
+        let mut session = Session::new(self.name.clone(), root, strategies);
+        for (name, value) in &self.globals {
+            session.set_global_arc(name.clone(), Arc::clone(value));
+        }
+        session
     }
 
     /// Create a fluent rule builder
@@ -113,6 +575,133 @@ impl Flow {
             builder: Rule::new(name),
         }
     }
+
+    /// Create a fluent query builder, see [`Session::query`]
+    pub fn query(&mut self, name: impl Into<String>) -> FlowQueryBuilder<'_> {
+        FlowQueryBuilder {
+            flow: self,
+            builder: Rule::new(name),
+        }
+    }
+
+    /// Add a query, whose patterns are matched only when the query is run
+    fn add_query(&mut self, query: Rule) -> Result<()> {
+        if self.has_query(&query.name) {
+            return Err(Error::Compilation(format!(
+                "Query '{}' already exists",
+                query.name
+            )));
+        }
+        check_join_keys(&query)?;
+        let name = query.name.clone();
+        let network = compile_network(&Arc::new(query))
+            .ok_or_else(|| Error::Compilation(format!("Query '{}' has no patterns", name)))?;
+        self.write_root()?.add_query_network(name, network);
+        Ok(())
+    }
+
+    /// Check if a query exists
+    pub fn has_query(&self, name: &str) -> bool {
+        self.read_root()
+            .is_ok_and(|root| root.query_network(name).is_some())
+    }
+
+    /// Get all query names
+    pub fn query_names(&self) -> Vec<String> {
+        self.read_root()
+            .map(|root| root.query_names())
+            .unwrap_or_default()
+    }
+}
+
+/// Builder for adding queries to a flow
+pub struct FlowQueryBuilder<'a> {
+    flow: &'a mut Flow,
+    builder: crate::rule::RuleBuilder,
+}
+
+impl<'a> FlowQueryBuilder<'a> {
+    /// Add a pattern
+    pub fn when(mut self, pattern: Box<dyn crate::pattern::Pattern>) -> Self {
+        self.builder = self.builder.when(pattern);
+        self
+    }
+
+    /// Add the query to the flow
+    pub fn build(self) -> Result<()> {
+        let query = self.builder.then(|_, _| Ok(())).build()?;
+        self.flow.add_query(query)
+    }
+}
+
+/// Check that every join key refers to an alias bound by an earlier pattern,
+/// and that tests follow a pattern binding something to test
+fn check_join_keys(rule: &Rule) -> Result<()> {
+    if let Some(first) = rule.patterns.first() {
+        if first.type_id() == TypeId::of::<TestPattern>() {
+            return Err(Error::Compilation(format!(
+                "Rule '{}': a test must follow another pattern",
+                rule.name
+            )));
+        }
+    }
+    for (index, pattern) in rule.patterns.iter().enumerate() {
+        for key in pattern.join_keys() {
+            let bound = rule.patterns[..index]
+                .iter()
+                .any(|earlier| earlier.alias() == key.alias);
+            if !bound {
+                return Err(Error::Compilation(format!(
+                    "Rule '{}': pattern '{}' joins on unbound alias '{}'",
+                    rule.name,
+                    pattern.alias(),
+                    key.alias
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check if two versions of a rule compile to the same network
+fn same_network(old: &Rule, new: &Rule) -> bool {
+    format!("{:?}", old.patterns) == format!("{:?}", new.patterns)
+}
+
+/// Build Rete network nodes for a rule under a root
+fn build_rule_network(root: &mut RootNode, rule: Arc<Rule>) {
+    if let Some(network) = compile_network(&rule) {
+        root.add_rule_network(rule.name.clone(), network);
+    }
+}
+
+/// Build the Rete network nodes of a rule, if it has patterns
+fn compile_network(rule: &Arc<Rule>) -> Option<Arc<dyn Node>> {
+    let terminal: Box<dyn Node> = Box::new(TerminalNode::new(Arc::clone(rule)));
+    if let [pattern] = rule.patterns.as_slice() {
+        // Single pattern: alpha node -> terminal node
+        if let Some(factory) = pattern.node_factory() {
+            return Some(Arc::from(factory(terminal, NodePosition::Alone)));
+        }
+        let mut alpha = AlphaNode::new(pattern.clone_box());
+        alpha.add_child(terminal);
+        return Some(Arc::new(alpha));
+    }
+
+    // Multiple patterns: a chain of join nodes ending in the terminal node
+    let count = rule.patterns.len();
+    let mut next = terminal;
+    for (index, pattern) in rule.patterns.iter().enumerate().rev() {
+        let position = NodePosition::Join {
+            head: index == 0,
+            last: index + 1 == count,
+        };
+        next = match pattern.node_factory() {
+            Some(factory) => factory(next, position),
+            None => Box::new(JoinNode::link(pattern.clone_box(), next, position)),
+        };
+    }
+    (count > 0).then(|| Arc::from(next))
 }
 
 /// Builder for adding rules to a flow
@@ -138,12 +727,37 @@ impl<'a> FlowRuleBuilder<'a> {
         self.flow.add_rule(rule)
     }
 
+    /// Set an action that only reads its match and records its changes
+    pub fn then_parallel<F>(mut self, action: F) -> Result<()>
+    where
+        F: Fn(&crate::rule::Match, &mut crate::rule::Effects) -> Result<()> + Send + Sync + 'static,
+    {
+        self.builder = self.builder.then_parallel(action);
+        let rule = self.builder.build()?;
+        self.flow.add_rule(rule)
+    }
+
     /// Set priority
     pub fn priority(mut self, priority: i32) -> Self {
         self.builder = self.builder.priority(priority);
         self
     }
 
+    /// Fire matches on a schedule for as long as they hold
+    pub fn timer(mut self, timer: crate::clock::Timer) -> Self {
+        self.builder = self.builder.timer(timer);
+        self
+    }
+
+    /// Set priority computed from the match
+    pub fn priority_fn<F>(mut self, salience: F) -> Self
+    where
+        F: Fn(&crate::rule::Match) -> i32 + Send + Sync + 'static,
+    {
+        self.builder = self.builder.priority_fn(salience);
+        self
+    }
+
     /// Set agenda group
     pub fn agenda_group(mut self, group: impl Into<String>) -> Self {
         self.builder = self.builder.agenda_group(group);
@@ -155,6 +769,66 @@ impl<'a> FlowRuleBuilder<'a> {
         self.builder = self.builder.auto_focus(auto_focus);
         self
     }
+
+    /// Set activation group
+    pub fn activation_group(mut self, group: impl Into<String>) -> Self {
+        self.builder = self.builder.activation_group(group);
+        self
+    }
+
+    /// Set whether the action reads working memory live
+    pub fn live_reads(mut self, live_reads: bool) -> Self {
+        self.builder = self.builder.live_reads(live_reads);
+        self
+    }
+
+    /// Set what happens when the action fails
+    pub fn on_error(mut self, policy: crate::rule::ErrorPolicy) -> Self {
+        self.builder = self.builder.on_error(policy);
+        self
+    }
+
+    /// Allow activations to fire alongside any non-exclusive activation
+    pub fn independent(mut self) -> Self {
+        self.builder = self.builder.independent();
+        self
+    }
+
+    /// Set the key deciding which activations may fire simultaneously
+    pub fn concurrency_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&crate::rule::Match) -> String + Send + Sync + 'static,
+    {
+        self.builder = self.builder.concurrency_key(key);
+        self
+    }
+
+    /// Only make the rule part of the flow if its environment passes a check
+    pub fn enabled_if<F>(mut self, enabled: F) -> Self
+    where
+        F: Fn(&FlowEnv) -> bool + Send + Sync + 'static,
+    {
+        self.builder = self.builder.enabled_if(enabled);
+        self
+    }
+
+    /// Set sample rate
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.builder = self.builder.sample_rate(rate);
+        self
+    }
+
+    /// Add a tag
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.builder = self.builder.tag(tag);
+        self
+    }
+
+    /// Add a metadata entry
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.builder = self.builder.metadata(key, value);
+        self
+    }
 }
 
 impl std::fmt::Debug for Flow {
@@ -162,6 +836,9 @@ impl std::fmt::Debug for Flow {
         f.debug_struct("Flow")
             .field("name", &self.name)
             .field("rules", &self.rules.keys())
+            .field("models", &self.models)
+            .field("seeds", &self.seeds.len())
+            .field("globals", &self.globals.keys())
             .finish()
     }
 }
@@ -169,7 +846,6 @@ impl std::fmt::Debug for Flow {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::fact::Fact;
     use crate::pattern::ObjectPattern;
 
     #[derive(Debug, Clone)]
@@ -177,6 +853,70 @@ mod tests {
         value: i32,
     }
 
+    #[tokio::test]
+    async fn test_restore_session() {
+        use crate::fixture::FixtureTypes;
+        use crate::store::MemoryStore;
+        use std::sync::Mutex;
+
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        struct Payment {
+            amount: u32,
+        }
+
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&applied);
+        let mut flow = Flow::new("payments");
+        flow.seed(|session| session.assert(Payment { amount: 1 }).map(|_| ()));
+        flow.rule("apply")
+            .when(Box::new(ObjectPattern::<Payment>::new("p")) as Box<dyn crate::pattern::Pattern>)
+            .then(move |_, m| {
+                log.lock().unwrap().push(m.get_as::<Payment>("p")?.amount);
+                Ok(())
+            })
+            .unwrap();
+
+        let types = FixtureTypes::new().register::<Payment>("Payment");
+        let store = Arc::new(MemoryStore::new());
+        let mut session = flow.restore_session(store.clone(), types.clone()).unwrap();
+        session.assert(Payment { amount: 10 }).unwrap();
+        session.match_rules().await.unwrap();
+        session.assert(Payment { amount: 20 }).unwrap();
+        drop(session);
+
+        // The restarted session neither re-seeds nor refires applied payments
+        let mut session = flow.restore_session(store, types).unwrap();
+        assert_eq!(session.fact_count(), 3);
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        let mut applied = applied.lock().unwrap().clone();
+        applied.sort();
+        assert_eq!(applied, [1, 10, 20]);
+    }
+
+    #[test]
+    fn test_restore_session_without_checkpoint_keeps_stored_facts() {
+        use crate::fixture::FixtureTypes;
+        use crate::store::MemoryStore;
+
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        struct Payment {
+            amount: u32,
+        }
+
+        let mut flow = Flow::new("payments");
+        flow.seed(|session| session.assert(Payment { amount: 1 }).map(|_| ()));
+        let types = FixtureTypes::new().register::<Payment>("Payment");
+        let store = Arc::new(MemoryStore::new());
+        let mut session = flow.session();
+        session.attach_store(store.clone(), types.clone()).unwrap();
+        drop(session);
+
+        // The store holds the seeded fact but no checkpoint
+        let session = flow.restore_session(store.clone(), types).unwrap();
+        assert_eq!(session.fact_count(), 1);
+        assert_eq!(store.len(), 1);
+    }
+
     #[test]
     fn test_flow_creation() {
         let flow = Flow::new("test_flow");
@@ -215,4 +955,358 @@ mod tests {
         flow.add_rule(rule1).unwrap();
         assert!(flow.add_rule(rule2).is_err());
     }
+
+    #[test]
+    fn test_join_on_unbound_alias_error() {
+        let mut flow = Flow::new("test");
+        let rule = Rule::new("join_rule")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t").join_on(
+                "missing",
+                |other: &TestFact| other.value,
+                |t: &TestFact| t.value,
+            )) as Box<dyn crate::pattern::Pattern>)
+            .then(|_, _| Ok(()))
+            .build()
+            .unwrap();
+
+        assert!(flow.add_rule(rule).is_err());
+        assert!(!flow.has_rule("join_rule"));
+    }
+
+    #[test]
+    fn test_seeds_apply_to_every_session() {
+        let mut flow = Flow::new("test");
+        flow.seed(|session| session.assert(TestFact { value: 1 }).map(|_| ()));
+
+        assert_eq!(flow.session().get_facts::<TestFact>().len(), 1);
+        assert_eq!(flow.session().get_facts::<TestFact>().len(), 1);
+
+        flow.seed(|_| Err(Error::custom("missing reference data")));
+        assert!(flow.try_session().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_globals_reach_actions() {
+        use std::sync::Mutex;
+
+        struct Log(Mutex<Vec<i32>>);
+
+        let mut flow = Flow::new("test");
+        flow.set_global("threshold", 5i32);
+        flow.rule("log")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn crate::pattern::Pattern>)
+            .then(|session, m| {
+                let threshold = session.global::<i32>("threshold").unwrap();
+                let value = m.get_as::<TestFact>("t")?.value;
+                if value > *threshold {
+                    let log = session.global::<Log>("log").unwrap();
+                    log.0.lock().unwrap().push(value);
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let log = Arc::new(Log(Mutex::new(Vec::new())));
+        let mut session = flow.session();
+        session.set_global_arc("log", log.clone());
+        assert!(session.global::<String>("threshold").is_none());
+        session.assert(TestFact { value: 3 }).unwrap();
+        session.assert(TestFact { value: 8 }).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(*log.0.lock().unwrap(), [8]);
+
+        session.set_global("threshold", 10i32);
+        session.assert(TestFact { value: 9 }).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(*log.0.lock().unwrap(), [8]);
+        assert!(session.remove_global("log"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_untouched_networks() {
+        use std::sync::Mutex;
+
+        type Log = Arc<Mutex<Vec<String>>>;
+        fn compile(threshold: i32, version: &'static str, log: &Log) -> Flow {
+            let mut flow = Flow::new("test");
+            let big = Arc::clone(log);
+            flow.rule("big")
+                .when(Box::new(ObjectPattern::<TestFact>::new("t").with_filter(
+                    move |t| t.value > threshold,
+                    format!("value > {}", threshold),
+                )) as Box<dyn crate::pattern::Pattern>)
+                .then(move |_, m| {
+                    let value = m.get_as::<TestFact>("t")?.value;
+                    big.lock().unwrap().push(format!("big {}", value));
+                    Ok(())
+                })
+                .unwrap();
+            let all = Arc::clone(log);
+            flow.rule("all")
+                .when(Box::new(ObjectPattern::<TestFact>::new("t"))
+                    as Box<dyn crate::pattern::Pattern>)
+                .then(move |_, m| {
+                    let value = m.get_as::<TestFact>("t")?.value;
+                    all.lock()
+                        .unwrap()
+                        .push(format!("all {} {}", version, value));
+                    Ok(())
+                })
+                .unwrap();
+            flow
+        }
+
+        let log: Log = Arc::default();
+        let mut flow = compile(5, "v1", &log);
+        let mut session = flow.session();
+        session.assert(TestFact { value: 3 }).unwrap();
+        session.assert(TestFact { value: 8 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 3);
+        log.lock().unwrap().clear();
+
+        let changes = flow.reload(compile(2, "v2", &log)).unwrap();
+        assert_eq!(changes.kept, ["all"]);
+        assert_eq!(changes.rebuilt, ["big"]);
+        assert!(changes.added.is_empty() && changes.removed.is_empty());
+
+        session.reprime(&changes).unwrap();
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.match_rules().await.unwrap();
+        let mut fired = log.lock().unwrap().clone();
+        fired.sort();
+        assert_eq!(fired, ["all v2 1", "big 3"]);
+
+        let mut smaller = Flow::new("test");
+        smaller
+            .rule("all")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn crate::pattern::Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        let changes = flow.reload(smaller).unwrap();
+        assert_eq!(changes.removed, ["big"]);
+        session.reprime(&changes).unwrap();
+        assert!(!flow.has_rule("big"));
+    }
+
+    #[tokio::test]
+    async fn test_remove_rule() {
+        let mut flow = Flow::new("test");
+        for name in ["first", "second"] {
+            flow.rule(name)
+                .when(Box::new(ObjectPattern::<TestFact>::new("t"))
+                    as Box<dyn crate::pattern::Pattern>)
+                .then(|_, _| Ok(()))
+                .unwrap();
+        }
+        let mut shared = Flow::new("shared");
+        shared.share_rule(&flow, "first").unwrap();
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+        let changes = flow.remove_rule("first").unwrap();
+        assert_eq!(changes.removed, ["first"]);
+        assert!(!flow.has_rule("first"));
+        assert!(matches!(
+            flow.remove_rule("first"),
+            Err(Error::RuleNotFound(_))
+        ));
+
+        session.reprime(&changes).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        session.assert(TestFact { value: 2 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        let mut other = shared.session();
+        other.assert(TestFact { value: 3 }).unwrap();
+        assert_eq!(other.match_rules().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rules_added_to_live_sessions() {
+        let mut flow = Flow::new("test");
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 20 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        flow.rule("late")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 10, "value > 10"),
+            ) as Box<dyn crate::pattern::Pattern>)
+            .then(move |_, m| {
+                log.lock().unwrap().push(m.get_as::<TestFact>("t")?.value);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        session.assert(TestFact { value: 30 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(*seen.lock().unwrap(), [20, 30]);
+
+        session.assert(TestFact { value: 40 }).unwrap();
+        flow.remove_rule("late").unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_import_rules() {
+        let mut library = Flow::new("validation");
+        for (name, tag) in [
+            ("positive", "checks"),
+            ("small", "checks"),
+            ("audit", "log"),
+        ] {
+            library
+                .add_rule(
+                    Rule::new(name)
+                        .when(Box::new(ObjectPattern::<TestFact>::new("t")))
+                        .then(|_, _| Ok(()))
+                        .tag(tag)
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let mut flow = Flow::new("orders");
+        let imported = flow
+            .import_rules(&library, |rule| rule.has_tag("checks"))
+            .unwrap();
+        assert_eq!(imported, ["positive", "small"]);
+        assert!(!flow.has_rule("audit"));
+        assert!(!flow.shares_rule_network(&library, "positive"));
+
+        assert!(flow.merge(&library).is_err());
+        assert!(!flow.has_rule("audit"));
+        let mut other = Flow::new("other");
+        assert_eq!(other.merge(&library).unwrap().len(), 3);
+
+        library.remove_rule("small").unwrap();
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_queries() {
+        #[derive(Debug, Clone)]
+        struct Wanted {
+            value: i32,
+        }
+
+        let mut flow = Flow::new("test");
+        flow.query("all")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn crate::pattern::Pattern>)
+            .build()
+            .unwrap();
+        flow.query("with_value")
+            .when(Box::new(ObjectPattern::<Wanted>::new("args")) as Box<dyn crate::pattern::Pattern>)
+            .when(Box::new(ObjectPattern::<TestFact>::new("t").join_on(
+                "args",
+                |args: &Wanted| args.value,
+                |t: &TestFact| t.value,
+            )) as Box<dyn crate::pattern::Pattern>)
+            .build()
+            .unwrap();
+        assert!(flow.query("empty").build().is_err());
+        assert!(flow
+            .query("all")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn crate::pattern::Pattern>)
+            .build()
+            .is_err());
+        assert_eq!(flow.query_names(), ["all", "with_value"]);
+
+        let mut session = flow.session();
+        for value in [1, 2, 2, 3] {
+            session.assert(TestFact { value }).unwrap();
+        }
+        assert_eq!(session.query("all", ()).unwrap().len(), 4);
+        let matches = session.query("with_value", Wanted { value: 2 }).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].get_as::<TestFact>("t").unwrap().value, 2);
+        assert!(session
+            .query("with_value", Wanted { value: 5 })
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            session.query("missing", ()),
+            Err(Error::QueryNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rules_enabled_by_environment() {
+        let beta = FlowEnv::new().with_flag("beta_pricing");
+        let mut flow = Flow::new("test");
+        flow.rule("beta_price")
+            .enabled_if(|env| env.flag("beta_pricing"))
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn crate::pattern::Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("price")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn crate::pattern::Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        assert!(flow.has_rule("beta_price"));
+        assert!(!flow.is_enabled("beta_price"));
+
+        let fired = |mut session: Session| async move {
+            session.assert(TestFact { value: 1 }).unwrap();
+            session.match_rules().await.unwrap()
+        };
+        assert_eq!(fired(flow.session()).await, 1);
+        assert_eq!(fired(flow.try_session_with_env(&beta).unwrap()).await, 2);
+
+        let flow = flow.with_env(beta).unwrap();
+        assert!(flow.is_enabled("beta_price"));
+        assert_eq!(fired(flow.session()).await, 2);
+        let flow = flow.with_env(FlowEnv::new()).unwrap();
+        assert_eq!(fired(flow.session()).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_specificity_simplicity_and_load_order() {
+        use crate::pattern::Pattern;
+        use std::sync::Mutex;
+
+        async fn order(strategy: ConflictResolution) -> Vec<&'static str> {
+            let fired = Arc::new(Mutex::new(Vec::new()));
+            let mut flow = Flow::new("test").with_strategies(vec![strategy]);
+            for (name, constraints) in [("one", 1), ("none", 0), ("two", 2)] {
+                let fired = Arc::clone(&fired);
+                let mut pattern = ObjectPattern::<TestFact>::new("t");
+                for _ in 0..constraints {
+                    pattern = pattern.with_filter(|t| t.value > 0, "positive");
+                }
+                flow.rule(name)
+                    .when(Box::new(pattern) as Box<dyn Pattern>)
+                    .then(move |_, _| {
+                        fired.lock().unwrap().push(name);
+                        Ok(())
+                    })
+                    .unwrap();
+            }
+
+            let mut session = flow.session();
+            session.assert(TestFact { value: 1 }).unwrap();
+            session.match_rules().await.unwrap();
+            let order = fired.lock().unwrap().clone();
+            order
+        }
+
+        assert_eq!(
+            order(ConflictResolution::Specificity).await,
+            ["two", "one", "none"]
+        );
+        assert_eq!(
+            order(ConflictResolution::Simplicity).await,
+            ["none", "one", "two"]
+        );
+        assert_eq!(
+            order(ConflictResolution::LoadOrder).await,
+            ["one", "none", "two"]
+        );
+    }
 }
@@ -0,0 +1,133 @@
+//! Location-based constraints backed by the `geo` crate
+//!
+//! Points are `(longitude, latitude)` in degrees; distances are in meters
+//! on a spherical Earth (haversine).
+
+use crate::fact::Fact;
+use crate::pattern::ObjectPattern;
+pub use geo::{coord, point, polygon, Point, Polygon, Rect};
+use geo::{Contains, Distance, Haversine, Intersects};
+
+/// Haversine distance between two points in meters
+pub fn distance_meters(a: Point<f64>, b: Point<f64>) -> f64 {
+    Haversine::distance(a, b)
+}
+
+/// Check if two points are at most `meters` apart
+pub fn within_distance(a: Point<f64>, b: Point<f64>, meters: f64) -> bool {
+    distance_meters(a, b) <= meters
+}
+
+impl<T: Fact> ObjectPattern<T> {
+    /// Match facts located inside a polygon
+    pub fn within_polygon<F>(self, locate: F, polygon: Polygon<f64>) -> Self
+    where
+        F: Fn(&T) -> Point<f64> + Send + Sync + 'static,
+    {
+        let description = format!(
+            "location within polygon of {} points",
+            polygon.exterior().0.len()
+        );
+        self.with_filter(move |fact| polygon.contains(&locate(fact)), description)
+    }
+
+    /// Match facts located at most `meters` from `center`
+    pub fn within_distance<F>(self, locate: F, center: Point<f64>, meters: f64) -> Self
+    where
+        F: Fn(&T) -> Point<f64> + Send + Sync + 'static,
+    {
+        let description = format!(
+            "location within {}m of ({}, {})",
+            meters,
+            center.x(),
+            center.y()
+        );
+        self.with_filter(
+            move |fact| within_distance(locate(fact), center, meters),
+            description,
+        )
+    }
+
+    /// Match facts located inside a bounding box
+    pub fn within_bounds<F>(self, locate: F, bounds: Rect<f64>) -> Self
+    where
+        F: Fn(&T) -> Point<f64> + Send + Sync + 'static,
+    {
+        let description = format!("location within bounds {:?}", bounds);
+        self.with_filter(move |fact| bounds.intersects(&locate(fact)), description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::pattern::Pattern;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone)]
+    struct Device {
+        lon: f64,
+        lat: f64,
+    }
+
+    fn location(device: &Device) -> Point<f64> {
+        point!(x: device.lon, y: device.lat)
+    }
+
+    #[test]
+    fn test_distance() {
+        let paris = point!(x: 2.3522, y: 48.8566);
+        let london = point!(x: -0.1276, y: 51.5072);
+        let km = distance_meters(paris, london) / 1000.0;
+        assert!((km - 343.5).abs() < 2.0);
+        assert!(within_distance(paris, london, 350_000.0));
+        assert!(!within_distance(paris, london, 5_000.0));
+    }
+
+    #[tokio::test]
+    async fn test_geo_patterns() {
+        let fired = Arc::new(AtomicUsize::new(0));
+        let mut flow = Flow::new("geo");
+        let zone = polygon![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 1.0, y: 1.0), (x: 0.0, y: 1.0)];
+        let bounds = Rect::new(coord! { x: -1.0, y: -1.0 }, coord! { x: 2.0, y: 2.0 });
+        let patterns: Vec<Box<dyn Pattern>> = vec![
+            Box::new(ObjectPattern::<Device>::new("d").within_polygon(location, zone)),
+            Box::new(ObjectPattern::<Device>::new("d").within_distance(
+                location,
+                point!(x: 0.5, y: 0.5),
+                5_000.0,
+            )),
+            Box::new(ObjectPattern::<Device>::new("d").within_bounds(location, bounds)),
+        ];
+        for (index, pattern) in patterns.into_iter().enumerate() {
+            let counter = Arc::clone(&fired);
+            flow.rule(format!("restricted_{}", index))
+                .when(pattern)
+                .then(move |_, _| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .unwrap();
+        }
+
+        let mut session = flow.session();
+        session
+            .assert(Device {
+                lon: 0.51,
+                lat: 0.52,
+            })
+            .unwrap();
+        session.assert(Device { lon: 1.5, lat: 1.5 }).unwrap();
+        session
+            .assert(Device {
+                lon: 10.0,
+                lat: 10.0,
+            })
+            .unwrap();
+        session.match_rules().await.unwrap();
+
+        assert_eq!(fired.load(Ordering::SeqCst), 4);
+    }
+}
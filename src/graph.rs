@@ -0,0 +1,220 @@
+//! Graph facts with an incrementally maintained transitive closure
+//!
+//! Assert [`Edge`] facts into a session of a flow prepared with [`install`]
+//! and rules can match [`Reachable`] facts instead of chaining edge patterns.
+//! Asserting an edge only derives the pairs that become reachable through
+//! it; retract edges with [`retract_edge`] so stale pairs are removed.
+
+use crate::error::Result;
+use crate::fact::FactId;
+use crate::flow::Flow;
+use crate::pattern::{ObjectPattern, Pattern};
+use crate::rule::Rule;
+use crate::session::Session;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Name of the rule maintaining the closure
+pub const REACHABILITY_RULE: &str = "graph::reachability";
+
+/// A directed edge between two nodes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Edge {
+    /// Source node
+    pub from: String,
+    /// Target node
+    pub to: String,
+}
+
+impl Edge {
+    /// Create an edge
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+}
+
+/// A node reachable from another through one or more edges
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reachable {
+    /// Source node
+    pub from: String,
+    /// Reachable node
+    pub to: String,
+}
+
+/// Add the rule maintaining [`Reachable`] facts to a flow
+///
+/// The rule runs at the highest priority so the closure is complete before
+/// other rules fire, and reads live so it builds on pairs derived by its
+/// earlier firings.
+pub fn install(flow: &mut Flow) -> Result<()> {
+    let rule = Rule::new(REACHABILITY_RULE)
+        .when(Box::new(ObjectPattern::<Edge>::new("edge")) as Box<dyn Pattern>)
+        .then(|session, match_data| extend_closure(session, match_data.get_as::<Edge>("edge")?))
+        .priority(i32::MAX)
+        .live_reads(true)
+        .build()?;
+    flow.add_rule(rule)
+}
+
+/// Derive the pairs that became reachable through a new edge
+fn extend_closure(session: &mut Session, edge: &Edge) -> Result<()> {
+    let mut known: HashSet<(String, String)> = reachable_pairs(session).into_keys().collect();
+
+    let mut sources = BTreeSet::from([edge.from.clone()]);
+    let mut targets = BTreeSet::from([edge.to.clone()]);
+    for (from, to) in &known {
+        if *to == edge.from {
+            sources.insert(from.clone());
+        }
+        if *from == edge.to {
+            targets.insert(to.clone());
+        }
+    }
+
+    for from in &sources {
+        for to in &targets {
+            if known.insert((from.clone(), to.clone())) {
+                session.assert(Reachable {
+                    from: from.clone(),
+                    to: to.clone(),
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Retract an edge and the pairs that are no longer reachable without it
+pub fn retract_edge(session: &mut Session, edge_id: FactId) -> Result<()> {
+    session.retract(edge_id)?;
+
+    let mut successors: HashMap<String, Vec<String>> = HashMap::new();
+    for handle in session.get_facts::<Edge>() {
+        if let Some(edge) = handle.downcast_ref::<Edge>() {
+            successors
+                .entry(edge.from.clone())
+                .or_default()
+                .push(edge.to.clone());
+        }
+    }
+
+    let mut closure = HashSet::new();
+    for start in successors.keys() {
+        let mut stack = successors[start].clone();
+        while let Some(node) = stack.pop() {
+            if closure.insert((start.clone(), node.clone())) {
+                stack.extend(successors.get(&node).into_iter().flatten().cloned());
+            }
+        }
+    }
+
+    for (pair, fact_id) in reachable_pairs(session) {
+        if !closure.contains(&pair) {
+            session.retract(fact_id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check if `to` is reachable from `from`
+pub fn reaches(session: &Session, from: &str, to: &str) -> bool {
+    session.get_facts::<Reachable>().iter().any(|handle| {
+        handle
+            .downcast_ref::<Reachable>()
+            .is_some_and(|r| r.from == from && r.to == to)
+    })
+}
+
+/// Pattern matching the [`Reachable`] fact for a pair of nodes
+pub fn reachable(
+    alias: impl Into<String>,
+    from: impl Into<String>,
+    to: impl Into<String>,
+) -> ObjectPattern<Reachable> {
+    let (from, to) = (from.into(), to.into());
+    let description = format!("{} reaches {}", from, to);
+    ObjectPattern::new(alias).with_filter(
+        move |r: &Reachable| r.from == from && r.to == to,
+        description,
+    )
+}
+
+/// Pattern matching the [`Reachable`] facts of every node reachable from `from`
+pub fn reachable_from(
+    alias: impl Into<String>,
+    from: impl Into<String>,
+) -> ObjectPattern<Reachable> {
+    let from = from.into();
+    let description = format!("reachable from {}", from);
+    ObjectPattern::new(alias).with_filter(move |r: &Reachable| r.from == from, description)
+}
+
+fn reachable_pairs(session: &Session) -> HashMap<(String, String), FactId> {
+    session
+        .get_facts::<Reachable>()
+        .iter()
+        .filter_map(|handle| {
+            let r = handle.downcast_ref::<Reachable>()?;
+            Some(((r.from.clone(), r.to.clone()), handle.id))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_closure_is_extended_incrementally() {
+        let alerts = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&alerts);
+
+        let mut flow = Flow::new("graph");
+        install(&mut flow).unwrap();
+        flow.rule("reaches_vault")
+            .when(Box::new(reachable("r", "guest", "vault")) as Box<dyn Pattern>)
+            .then(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(Edge::new("guest", "lobby")).unwrap();
+        session.assert(Edge::new("office", "vault")).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(session.get_facts::<Reachable>().len(), 2);
+        assert_eq!(alerts.load(Ordering::SeqCst), 0);
+
+        session.assert(Edge::new("lobby", "office")).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(session.get_facts::<Reachable>().len(), 6);
+        assert!(reaches(&session, "guest", "vault"));
+        assert_eq!(alerts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retract_edge_removes_stale_pairs() {
+        let mut flow = Flow::new("graph");
+        install(&mut flow).unwrap();
+
+        let mut session = flow.session();
+        session.assert(Edge::new("a", "b")).unwrap();
+        let bc = session.assert(Edge::new("b", "c")).unwrap();
+        session.assert(Edge::new("c", "a")).unwrap();
+        session.match_rules().await.unwrap();
+        assert!(reaches(&session, "a", "a"));
+        assert_eq!(session.get_facts::<Reachable>().len(), 9);
+
+        retract_edge(&mut session, bc.id()).unwrap();
+        assert!(reaches(&session, "c", "b"));
+        assert!(!reaches(&session, "a", "c"));
+        assert!(!reaches(&session, "a", "a"));
+        assert_eq!(session.get_facts::<Reachable>().len(), 3);
+    }
+}
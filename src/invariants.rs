@@ -0,0 +1,101 @@
+//! Engine invariant checks for debugging the Rete implementation
+//!
+//! With the `debug-invariants` feature, a session checks after every
+//! propagation that:
+//!
+//! - node memories only hold facts that are in working memory,
+//! - pending activations only reference facts that are in working memory,
+//! - the type index agrees with the main fact index.
+//!
+//! A violation panics with a report of everything found, so engine bugs
+//! surface at the change that caused them rather than as a wrong firing
+//! much later. The checks walk all memories and are meant for tests only.
+
+use crate::agenda::Agenda;
+use crate::node::NetworkMemory;
+use crate::working_memory::WorkingMemory;
+
+/// Describe every broken invariant
+pub fn violations(
+    working_memory: &WorkingMemory,
+    memory: &NetworkMemory,
+    agenda: &Agenda,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (node, fact) in memory.facts() {
+        if working_memory.get(fact.id).is_none() {
+            problems.push(format!(
+                "node {} holds retracted fact {:?} ({})",
+                node.as_u64(),
+                fact.id,
+                fact.type_name()
+            ));
+        }
+    }
+
+    for activation in agenda.activations() {
+        let mut aliases: Vec<_> = activation.match_data.facts.iter().collect();
+        aliases.sort_by(|a, b| a.0.cmp(b.0));
+        for (alias, fact) in aliases {
+            if working_memory.get(fact.id).is_none() {
+                problems.push(format!(
+                    "activation of '{}' binds {} to retracted fact {:?} ({})",
+                    activation.rule.name,
+                    alias,
+                    fact.id,
+                    fact.type_name()
+                ));
+            }
+        }
+    }
+
+    problems.extend(working_memory.index_problems());
+    problems
+}
+
+/// Panic with a report if any invariant is broken after `operation`
+pub fn check(
+    operation: &str,
+    working_memory: &WorkingMemory,
+    memory: &NetworkMemory,
+    agenda: &Agenda,
+) {
+    let problems = violations(working_memory, memory, agenda);
+    if !problems.is_empty() {
+        panic!(
+            "engine invariants broken after {}:\n  - {}",
+            operation,
+            problems.join("\n  - ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{AlphaNode, Node};
+    use crate::pattern::ObjectPattern;
+
+    #[derive(Debug, Clone)]
+    struct TestFact {
+        value: i32,
+    }
+
+    #[test]
+    fn test_reports_retracted_fact_in_node_memory() {
+        let working_memory = WorkingMemory::new();
+        let mut memory = NetworkMemory::new();
+        let alpha = AlphaNode::new(Box::new(ObjectPattern::<TestFact>::new("t")));
+        let handle = working_memory.assert(TestFact { value: 1 }).unwrap();
+        alpha.assert_fact(handle.clone(), &mut memory).unwrap();
+        assert!(violations(&working_memory, &memory, &Agenda::new()).is_empty());
+
+        // Retract from working memory only, as a buggy propagation would
+        working_memory.retract(handle.id).unwrap();
+        let problems = violations(&working_memory, &memory, &Agenda::new());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("holds retracted fact"));
+        assert_eq!(handle.downcast_ref::<TestFact>().unwrap().value, 1);
+    }
+}
@@ -9,37 +9,68 @@ mod wasm;
 #[cfg(target_arch = "wasm32")]
 pub use wasm::*;
 
-#[cfg(not(target_arch = "wasm32"))]
 pub mod agenda;
-#[cfg(not(target_arch = "wasm32"))]
+pub mod analysis;
+pub mod audit;
+pub mod checkpoint;
+pub mod clock;
+pub mod compat;
 pub mod constraint;
-#[cfg(not(target_arch = "wasm32"))]
+pub mod diff;
+pub mod dmn;
+pub mod dsl;
 pub mod error;
-#[cfg(not(target_arch = "wasm32"))]
+pub mod event;
+pub mod explain;
+pub mod expr;
+pub mod extension;
 pub mod fact;
-#[cfg(not(target_arch = "wasm32"))]
+pub mod field;
+pub mod fixture;
 pub mod flow;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "geo")]
+pub mod geospatial;
+pub mod graph;
+#[cfg(feature = "debug-invariants")]
+pub mod invariants;
+pub mod memory_report;
+pub mod model;
 pub mod node;
-#[cfg(not(target_arch = "wasm32"))]
 pub mod pattern;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "wasm-plugins", not(target_arch = "wasm32")))]
+pub mod plugin;
+#[cfg(feature = "pmml")]
+pub mod pmml;
+#[cfg(feature = "pool")]
+pub mod pool;
+pub mod projection;
+pub mod registry;
 pub mod rule;
-#[cfg(not(target_arch = "wasm32"))]
+pub mod scratchpad;
 pub mod session;
-#[cfg(not(target_arch = "wasm32"))]
+pub mod snapshot;
+pub mod stats;
+pub mod store;
+pub mod strategy_report;
+pub mod template;
+pub mod tenancy;
+pub mod testing;
+pub mod value;
+pub mod watch;
+pub mod window;
 pub mod working_memory;
 
 /// Commonly used types and traits
-#[cfg(not(target_arch = "wasm32"))]
 pub mod prelude {
     pub use crate::error::{Error, Result};
     pub use crate::fact::{Fact, FactId};
-    pub use crate::flow::Flow;
+    pub use crate::flow::{Flow, FlowEnv};
     pub use crate::pattern::Pattern;
+    pub use crate::rule;
     pub use crate::rule::{Rule, RuleBuilder};
     pub use crate::session::Session;
 }
 
+// On wasm32 the root exports the JavaScript classes of the same names instead
 #[cfg(not(target_arch = "wasm32"))]
 pub use prelude::*;
@@ -0,0 +1,307 @@
+//! Memory usage of a session
+//!
+//! [`Session::memory_report`] counts what a session holds: the facts, tokens
+//! and state kept by each node of the Rete network, the facts in working
+//! memory by type and the activations waiting on the agenda, with an
+//! approximate size in bytes for each, so large sessions can be sized.
+//!
+//! Sizes are estimates: they count the engine's own structures and the
+//! shallow size of each fact value, not heap data owned by the facts or by
+//! the state of custom nodes.
+//!
+//! [`Session::memory_report`]: crate::session::Session::memory_report
+
+use crate::agenda::Agenda;
+use crate::fact::FactHandle;
+use crate::node::{NetworkMemory, NodeId, RootNode};
+use crate::rule::{Activation, Match};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::{size_of, size_of_val};
+use std::sync::Arc;
+
+/// Reference counts stored ahead of the value of an `Arc`
+const ARC_COUNTS: usize = 2 * size_of::<usize>();
+
+/// Memory held by one node of the network for a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeMemory {
+    /// The node
+    pub node: NodeId,
+    /// Rules whose network contains the node, empty for query networks
+    pub rules: Vec<String>,
+    /// Facts that passed the node's pattern, for alpha nodes
+    pub alpha_facts: usize,
+    /// Partial matches waiting at the node, for join nodes
+    pub left_tokens: usize,
+    /// Facts waiting at the node, for join nodes
+    pub right_facts: usize,
+    /// Whether a custom node keeps state, whose size is not counted
+    pub has_state: bool,
+    /// Approximate bytes of the node's memories
+    pub bytes: usize,
+}
+
+/// Facts of one type in working memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeMemory {
+    /// Name of the fact type
+    pub type_name: &'static str,
+    /// Number of facts
+    pub facts: usize,
+    /// Approximate bytes of the facts and their handles
+    pub bytes: usize,
+}
+
+/// What a session holds in memory, see [`crate::memory_report`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Nodes holding memories, largest first
+    pub nodes: Vec<NodeMemory>,
+    /// Facts in working memory by type, most numerous first
+    pub types: Vec<TypeMemory>,
+    /// Activations waiting on the agenda
+    pub agenda_depth: usize,
+    /// Approximate bytes of the waiting activations
+    pub agenda_bytes: usize,
+}
+
+impl MemoryReport {
+    /// Build the report of a session's network, facts and agenda
+    pub(crate) fn new(
+        root: &RootNode,
+        memory: &NetworkMemory,
+        facts: &[Arc<FactHandle>],
+        agenda: &Agenda,
+    ) -> Self {
+        let mut rules_by_node: HashMap<NodeId, Vec<String>> = HashMap::new();
+        for (rule, network) in root.rule_networks() {
+            let mut patterns = Vec::new();
+            network.patterns(&mut patterns);
+            for (node, _) in patterns {
+                rules_by_node
+                    .entry(node)
+                    .or_default()
+                    .push(rule.to_string());
+            }
+        }
+
+        let mut nodes: Vec<_> = memory
+            .nodes()
+            .into_iter()
+            .map(|node| node_memory(node, memory, &mut rules_by_node))
+            .collect();
+        nodes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.node.cmp(&b.node)));
+
+        let mut by_type: HashMap<&'static str, TypeMemory> = HashMap::new();
+        for fact in facts {
+            let type_name = fact.type_name();
+            let types = by_type.entry(type_name).or_insert(TypeMemory {
+                type_name,
+                facts: 0,
+                bytes: 0,
+            });
+            types.facts += 1;
+            types.bytes += fact_bytes(fact);
+        }
+        let mut types: Vec<_> = by_type.into_values().collect();
+        types.sort_by(|a, b| b.facts.cmp(&a.facts).then(a.type_name.cmp(b.type_name)));
+
+        let mut agenda_depth = 0;
+        let mut agenda_bytes = 0;
+        for activation in agenda.activations() {
+            agenda_depth += 1;
+            agenda_bytes += activation_bytes(activation);
+        }
+
+        Self {
+            nodes,
+            types,
+            agenda_depth,
+            agenda_bytes,
+        }
+    }
+
+    /// Get the number of facts in working memory
+    pub fn facts(&self) -> usize {
+        self.types.iter().map(|types| types.facts).sum()
+    }
+
+    /// Get the approximate bytes of the facts in working memory
+    pub fn fact_bytes(&self) -> usize {
+        self.types.iter().map(|types| types.bytes).sum()
+    }
+
+    /// Get the approximate bytes of the network's memories
+    pub fn node_bytes(&self) -> usize {
+        self.nodes.iter().map(|node| node.bytes).sum()
+    }
+
+    /// Get the approximate bytes of the memories of a rule's network
+    pub fn rule_bytes(&self, rule: &str) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.rules.iter().any(|name| name == rule))
+            .map(|node| node.bytes)
+            .sum()
+    }
+
+    /// Get the approximate bytes of facts, node memories and the agenda
+    pub fn total_bytes(&self) -> usize {
+        self.fact_bytes() + self.node_bytes() + self.agenda_bytes
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} facts ({} bytes), {} node memories ({} bytes), {} activations ({} bytes)",
+            self.facts(),
+            self.fact_bytes(),
+            self.nodes.len(),
+            self.node_bytes(),
+            self.agenda_depth,
+            self.agenda_bytes
+        )?;
+        for types in &self.types {
+            writeln!(
+                f,
+                "  {}: {} facts, {} bytes",
+                types.type_name, types.facts, types.bytes
+            )?;
+        }
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "  node {} [{}]: {} alpha, {} left, {} right, {} bytes",
+                node.node.as_u64(),
+                node.rules.join(", "),
+                node.alpha_facts,
+                node.left_tokens,
+                node.right_facts,
+                node.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn node_memory(
+    node: NodeId,
+    memory: &NetworkMemory,
+    rules_by_node: &mut HashMap<NodeId, Vec<String>>,
+) -> NodeMemory {
+    let alpha_facts = memory.alpha_memory(node).len();
+    let (left_tokens, left_bytes) = memory.left_memory(node).map_or((0, 0), |left| {
+        left.iter().fold((0, 0), |(count, bytes), token| {
+            (count + 1, bytes + match_bytes(token))
+        })
+    });
+    let right_facts = memory
+        .right_memory(node)
+        .map_or(0, |right| right.iter().count());
+    NodeMemory {
+        node,
+        rules: rules_by_node.remove(&node).unwrap_or_default(),
+        alpha_facts,
+        left_tokens,
+        right_facts,
+        has_state: memory.has_state(node),
+        bytes: (alpha_facts + right_facts) * size_of::<Arc<FactHandle>>() + left_bytes,
+    }
+}
+
+/// Approximate bytes of a fact, its handle and their reference counts
+fn fact_bytes(fact: &FactHandle) -> usize {
+    ARC_COUNTS + size_of::<FactHandle>() + ARC_COUNTS + size_of_val(fact.fact.as_ref())
+}
+
+/// Approximate bytes of a match and the entries of its maps
+fn match_bytes(match_data: &Match) -> usize {
+    fn keys<'a, V: 'a>(keys: impl Iterator<Item = (&'a String, V)>) -> usize {
+        keys.map(|(key, _)| key.capacity()).sum()
+    }
+    size_of::<Match>()
+        + match_data.facts.capacity() * size_of::<(String, Arc<FactHandle>)>()
+        + keys(match_data.facts.iter())
+        + match_data.context.bindings.capacity() * size_of::<(String, Arc<FactHandle>)>()
+        + keys(match_data.context.bindings.iter())
+        + match_data.context.values.capacity() * size_of::<(String, Value)>()
+        + keys(match_data.context.values.iter())
+        + match_data.scores.capacity() * size_of::<(String, f64)>()
+        + keys(match_data.scores.iter())
+}
+
+/// Approximate bytes of an activation and its match
+fn activation_bytes(activation: &Activation) -> usize {
+    ARC_COUNTS + size_of::<Activation>() - size_of::<Match>() + match_bytes(&activation.match_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::Flow;
+    use crate::pattern::{ObjectPattern, Pattern};
+
+    #[derive(Debug, Clone)]
+    struct Customer {
+        id: u32,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Order {
+        customer_id: u32,
+    }
+
+    #[test]
+    fn test_memory_report_counts_nodes_facts_and_agenda() {
+        let mut flow = Flow::new("report");
+        flow.rule("customer_orders")
+            .when(Box::new(ObjectPattern::<Customer>::new("c")) as Box<dyn Pattern>)
+            .when(Box::new(ObjectPattern::<Order>::new("o").join_on(
+                "c",
+                |c: &Customer| c.id,
+                |o: &Order| o.customer_id,
+            )) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("orders")
+            .when(Box::new(ObjectPattern::<Order>::new("o")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session
+            .assert_all((0..3).map(|id| Customer { id }))
+            .unwrap();
+        session
+            .assert_all((0..5).map(|i| Order { customer_id: i % 2 }))
+            .unwrap();
+
+        let report = session.memory_report().unwrap();
+        assert_eq!(report.facts(), 8);
+        let types: Vec<_> = report
+            .types
+            .iter()
+            .map(|types| (types.type_name.rsplit("::").next().unwrap(), types.facts))
+            .collect();
+        assert_eq!(types, [("Order", 5), ("Customer", 3)]);
+        assert_eq!(report.agenda_depth, 5 + 5);
+        assert!(report.agenda_bytes > 0);
+
+        let join = report
+            .nodes
+            .iter()
+            .find(|node| node.right_facts > 0)
+            .unwrap();
+        assert_eq!(join.rules, ["customer_orders"]);
+        assert_eq!(join.right_facts, 5);
+        assert!(report.rule_bytes("customer_orders") >= join.bytes);
+        assert_eq!(
+            report.total_bytes(),
+            report.fact_bytes() + report.node_bytes() + report.agenda_bytes
+        );
+        assert!(report.to_string().starts_with("8 facts"));
+    }
+}
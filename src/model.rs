@@ -0,0 +1,192 @@
+//! Machine-learning model integration for patterns
+
+use crate::constraint::{Constraint, ConstraintContext};
+use crate::error::{Error, Result};
+use crate::fact::{Fact, FactHandle};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// A model that scores a feature vector
+///
+/// Implement this to plug a model runtime (ONNX, a remote scorer, a
+/// hand-written scorecard) into rule conditions.
+pub trait Predictor: Send + Sync {
+    /// Score a feature vector
+    fn predict(&self, features: &[f64]) -> Result<f64>;
+}
+
+impl<F> Predictor for F
+where
+    F: Fn(&[f64]) -> Result<f64> + Send + Sync,
+{
+    fn predict(&self, features: &[f64]) -> Result<f64> {
+        self(features)
+    }
+}
+
+/// Named predictors available to the rules of a flow
+#[derive(Clone, Default)]
+pub struct ModelRegistry {
+    models: HashMap<String, Arc<dyn Predictor>>,
+}
+
+impl ModelRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a predictor under a name, replacing any previous one
+    pub fn register(&mut self, name: impl Into<String>, predictor: Arc<dyn Predictor>) {
+        self.models.insert(name.into(), predictor);
+    }
+
+    /// Get a predictor by name
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Predictor>> {
+        self.models
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::Custom(format!("Model not registered: {}", name)))
+    }
+
+    /// Check if a predictor is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.models.contains_key(name)
+    }
+
+    /// Get all registered model names
+    pub fn names(&self) -> Vec<String> {
+        self.models.keys().cloned().collect()
+    }
+}
+
+impl Debug for ModelRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelRegistry")
+            .field("models", &self.models.keys())
+            .finish()
+    }
+}
+
+/// Extracts the feature vector of a fact for a model
+pub type FeatureFn = Arc<dyn Fn(&FactHandle) -> Option<Vec<f64>> + Send + Sync>;
+
+/// Invokes a predictor on a fact's features
+#[derive(Clone)]
+pub struct ModelScorer {
+    predictor: Arc<dyn Predictor>,
+    features: FeatureFn,
+}
+
+impl ModelScorer {
+    /// Create a scorer for facts of type `T`
+    pub fn new<T, F>(predictor: Arc<dyn Predictor>, features: F) -> Self
+    where
+        T: Fact,
+        F: Fn(&T) -> Vec<f64> + Send + Sync + 'static,
+    {
+        Self {
+            predictor,
+            features: Arc::new(move |fact: &FactHandle| fact.downcast_ref::<T>().map(&features)),
+        }
+    }
+
+    /// Score a fact, or `None` if it is not of the scorer's type
+    pub fn score(&self, fact: &FactHandle) -> Result<Option<f64>> {
+        match (self.features)(fact) {
+            Some(features) => self.predictor.predict(&features).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Debug for ModelScorer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelScorer").finish_non_exhaustive()
+    }
+}
+
+/// A constraint that passes when a model score satisfies a predicate
+#[derive(Clone)]
+pub struct ModelConstraint {
+    scorer: ModelScorer,
+    predicate: Arc<dyn Fn(f64) -> bool + Send + Sync>,
+    description: String,
+}
+
+impl ModelConstraint {
+    /// Create a new model constraint
+    pub fn new<P>(scorer: ModelScorer, predicate: P, description: impl Into<String>) -> Self
+    where
+        P: Fn(f64) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            scorer,
+            predicate: Arc::new(predicate),
+            description: description.into(),
+        }
+    }
+}
+
+impl Debug for ModelConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelConstraint")
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+impl Constraint for ModelConstraint {
+    fn evaluate(&self, fact: &FactHandle, _context: &ConstraintContext) -> Result<bool> {
+        Ok(self
+            .scorer
+            .score(fact)?
+            .map(|score| (self.predicate)(score))
+            .unwrap_or(false))
+    }
+
+    fn clone_box(&self) -> Box<dyn Constraint> {
+        Box::new(self.clone())
+    }
+
+    fn description(&self) -> String {
+        self.description.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct Transaction {
+        amount: f64,
+    }
+
+    fn amount_model() -> Arc<dyn Predictor> {
+        Arc::new(|features: &[f64]| Ok(features[0] / 1000.0))
+    }
+
+    #[test]
+    fn test_model_constraint() {
+        let scorer = ModelScorer::new(amount_model(), |t: &Transaction| vec![t.amount]);
+        let constraint = ModelConstraint::new(scorer, |score| score > 0.8, "score > 0.8");
+        let context = ConstraintContext::new();
+
+        let high = FactHandle::new(Transaction { amount: 900.0 }, 0);
+        let low = FactHandle::new(Transaction { amount: 100.0 }, 1);
+        assert!(constraint.evaluate(&high, &context).unwrap());
+        assert!(!constraint.evaluate(&low, &context).unwrap());
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        let mut registry = ModelRegistry::new();
+        registry.register("fraud", amount_model());
+
+        assert!(registry.contains("fraud"));
+        assert!(registry.get("fraud").is_ok());
+        assert!(registry.get("churn").is_err());
+    }
+}
@@ -1,33 +1,425 @@
 //! Rete network node implementations
+//!
+//! Nodes describe the compiled network and are shared by every session of a
+//! flow. Match state (alpha and beta memories) lives in a per-session
+//! [`NetworkMemory`] keyed by [`NodeId`], so sessions never see each other's
+//! partial matches.
 
+use crate::clock::{Instant, RealTimeClock, SessionClock};
 use crate::constraint::ConstraintContext;
 use crate::error::Result;
-use crate::fact::FactHandle;
-use crate::pattern::Pattern;
-use crate::rule::{Activation, Match};
-use std::sync::Arc;
+use crate::fact::{FactHandle, FactId};
+use crate::pattern::{JoinKey, Pattern};
+use crate::rule::{Activation, Match, Rule};
+use std::any::Any;
+#[cfg(feature = "parallel")]
+use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "async-constraints")]
+use std::future::Future;
+use std::hash::Hasher;
+#[cfg(feature = "async-constraints")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Future returned by asynchronous fact propagation
+#[cfg(feature = "async-constraints")]
+pub type NodeFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<Arc<Activation>>>> + Send + 'a>>;
+
+/// Unique identifier for nodes in the Rete network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u64);
+
+impl NodeId {
+    /// Create a new unique node ID
+    pub fn new() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(1);
+        NodeId(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Get the raw ID value
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for NodeId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-session match state for the nodes of a shared Rete network
+#[derive(Debug)]
+pub struct NetworkMemory {
+    /// Facts that passed each alpha node
+    alpha: HashMap<NodeId, Vec<Arc<FactHandle>>>,
+    /// Partial matches waiting at each join node
+    left: HashMap<NodeId, BetaMemory<Match>>,
+    /// Facts waiting at each join node
+    right: HashMap<NodeId, BetaMemory<Arc<FactHandle>>>,
+    /// State of extension nodes, see [`crate::extension`]
+    extensions: HashMap<NodeId, Box<dyn Any + Send + Sync>>,
+    /// Facts windows dropped from rule matches, not yet seen by the session
+    evictions: Vec<Eviction>,
+    /// Counter for activation recency
+    activation_recency: u64,
+    /// Time source of time windows
+    clock: Arc<dyn SessionClock>,
+    /// Time spent matching facts in each rule's network, when measured
+    match_times: Option<HashMap<String, Duration>>,
+    /// Results of pattern tests of the fact being asserted, evaluated ahead
+    /// of propagation by [`RootNode::assert_facts`]
+    #[cfg(feature = "parallel")]
+    tested: Vec<(NodeId, bool)>,
+    /// Allocations of fired activations, reused for new ones
+    #[cfg(feature = "pool")]
+    pool: crate::pool::ActivationPool,
+}
+
+impl Default for NetworkMemory {
+    fn default() -> Self {
+        Self {
+            alpha: HashMap::new(),
+            left: HashMap::new(),
+            right: HashMap::new(),
+            extensions: HashMap::new(),
+            evictions: Vec::new(),
+            activation_recency: 0,
+            clock: Arc::new(RealTimeClock),
+            match_times: None,
+            #[cfg(feature = "parallel")]
+            tested: Vec::new(),
+            #[cfg(feature = "pool")]
+            pool: crate::pool::ActivationPool::new(),
+        }
+    }
+}
+
+/// A fact a window dropped from a rule's matches while it stays in working memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    /// Rule whose pending activations must not bind the fact anymore
+    pub rule: String,
+    /// Alias of the windowed pattern
+    pub alias: String,
+    /// The evicted fact
+    pub fact: FactId,
+}
+
+impl NetworkMemory {
+    /// Create an empty network memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the facts stored by an alpha node
+    pub fn alpha_memory(&self, node: NodeId) -> &[Arc<FactHandle>] {
+        self.alpha.get(&node).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Get the partial matches stored by a join node
+    pub fn left_memory(&self, node: NodeId) -> Option<&BetaMemory<Match>> {
+        self.left.get(&node)
+    }
+
+    /// Get the facts stored by a join node
+    pub fn right_memory(&self, node: NodeId) -> Option<&BetaMemory<Arc<FactHandle>>> {
+        self.right.get(&node)
+    }
+
+    /// Get the nodes holding memories or state in this memory, in id order
+    pub fn nodes(&self) -> Vec<NodeId> {
+        let mut nodes: Vec<_> = self
+            .alpha
+            .keys()
+            .chain(self.left.keys())
+            .chain(self.right.keys())
+            .chain(self.extensions.keys())
+            .copied()
+            .collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes
+    }
+
+    /// Check whether a custom node keeps state in this memory
+    pub fn has_state(&self, node: NodeId) -> bool {
+        self.extensions.contains_key(&node)
+    }
+
+    /// Iterate over the facts held by every node memory, with their node
+    pub fn facts(&self) -> impl Iterator<Item = (NodeId, &Arc<FactHandle>)> {
+        let alpha = self
+            .alpha
+            .iter()
+            .flat_map(|(node, facts)| facts.iter().map(move |fact| (*node, fact)));
+        let right = self
+            .right
+            .iter()
+            .flat_map(|(node, memory)| memory.iter().map(move |fact| (*node, fact)));
+        let left = self.left.iter().flat_map(|(node, memory)| {
+            memory
+                .iter()
+                .flat_map(move |token| token.facts.values().map(move |fact| (*node, fact)))
+        });
+        alpha.chain(right).chain(left)
+    }
+
+    /// Get the state a custom node keeps in this memory, creating it if needed
+    pub fn state<S: Default + Send + Sync + 'static>(&mut self, node: NodeId) -> &mut S {
+        let state = self
+            .extensions
+            .entry(node)
+            .or_insert_with(|| Box::new(S::default()));
+        if !state.is::<S>() {
+            *state = Box::new(S::default());
+        }
+        state
+            .downcast_mut()
+            .expect("extension state was just set to the requested type")
+    }
+
+    /// Drop the state of a custom node
+    pub fn remove_state(&mut self, node: NodeId) {
+        self.extensions.remove(&node);
+    }
+
+    /// Record a fact a window dropped from a rule's matches
+    pub fn evict(&mut self, eviction: Eviction) {
+        self.evictions.push(eviction);
+    }
+
+    /// Take the evictions recorded since the last call
+    pub fn take_evictions(&mut self) -> Vec<Eviction> {
+        std::mem::take(&mut self.evictions)
+    }
+
+    /// Get the clock nodes read the time from
+    pub fn clock(&self) -> &Arc<dyn SessionClock> {
+        &self.clock
+    }
+
+    /// Set the clock nodes read the time from
+    pub fn set_clock(&mut self, clock: Arc<dyn SessionClock>) {
+        self.clock = clock;
+    }
+
+    /// Start measuring the time spent matching facts in each rule's network
+    pub fn measure_match_times(&mut self) {
+        self.match_times.get_or_insert_with(HashMap::new);
+    }
+
+    /// Get the time spent matching facts in each rule's network, if measured
+    pub fn match_times(&self) -> Option<&HashMap<String, Duration>> {
+        self.match_times.as_ref()
+    }
+
+    /// Add to the match time of a rule, if match times are measured
+    fn add_match_time(&mut self, rule: &str, elapsed: Duration) {
+        if let Some(times) = &mut self.match_times {
+            match times.get_mut(rule) {
+                Some(total) => *total += elapsed,
+                None => {
+                    times.insert(rule.to_string(), elapsed);
+                }
+            }
+        }
+    }
+
+    /// Run `f` on a rule's network, measuring it if match times are measured
+    /// and naming the rule in constraint failures
+    fn in_network<R>(&mut self, rule: &str, f: impl FnOnce(&mut Self) -> Result<R>) -> Result<R> {
+        if self.match_times.is_none() {
+            return f(self).map_err(|e| e.in_rule(rule));
+        }
+        let start = Instant::now();
+        let result = f(self);
+        self.add_match_time(rule, start.elapsed());
+        result.map_err(|e| e.in_rule(rule))
+    }
+
+    /// Test a fact against a node's pattern, unless it was tested ahead of propagation
+    fn test(&self, node: NodeId, pattern: &dyn Pattern, fact: &FactHandle) -> Result<bool> {
+        #[cfg(feature = "parallel")]
+        if let Ok(index) = self.tested.binary_search_by_key(&node, |(node, _)| *node) {
+            return Ok(self.tested[index].1);
+        }
+        #[cfg(not(feature = "parallel"))]
+        let _ = node;
+        pattern.matches(fact, &ConstraintContext::new())
+    }
+
+    /// Take an empty match to bind facts in
+    fn new_match(&mut self) -> Match {
+        #[cfg(feature = "pool")]
+        return self.pool.new_match();
+        #[cfg(not(feature = "pool"))]
+        Match::new()
+    }
+
+    /// Activate a rule for a match
+    fn new_activation(&mut self, rule: Arc<Rule>, match_data: Match) -> Arc<Activation> {
+        let recency = self.next_activation_recency();
+        #[cfg(feature = "pool")]
+        return self.pool.activation(rule, match_data, recency);
+        #[cfg(not(feature = "pool"))]
+        Arc::new(Activation::new(rule, match_data, recency))
+    }
+
+    /// Hand a fired activation back for its allocation to be reused
+    #[cfg(feature = "pool")]
+    pub fn recycle(&mut self, activation: Arc<Activation>) {
+        self.pool.recycle(activation);
+    }
+
+    /// Get the counts of activations built in reused allocations
+    #[cfg(feature = "pool")]
+    pub fn pool_stats(&self) -> crate::pool::PoolStats {
+        self.pool.stats()
+    }
+
+    fn next_activation_recency(&mut self) -> u64 {
+        let recency = self.activation_recency;
+        self.activation_recency += 1;
+        recency
+    }
+
+    /// Clear all node memories
+    pub fn clear(&mut self) {
+        self.alpha.clear();
+        self.left.clear();
+        self.right.clear();
+        self.extensions.clear();
+        self.evictions.clear();
+    }
+}
+
+/// Where a pattern's node sits in its rule's network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodePosition {
+    /// The rule's only pattern: facts come from the root and matches go to
+    /// the terminal node through `assert_fact`
+    Alone,
+    /// One of several patterns, each joining its facts to the partial
+    /// matches of the patterns before it
+    Join {
+        /// Whether this is the first pattern, whose only partial match is the
+        /// empty match
+        head: bool,
+        /// Whether the child is the terminal node rather than the next join,
+        /// which also needs every asserted and retracted fact passed on
+        last: bool,
+    },
+}
+
+/// Builds a pattern's own node around the node it feeds
+pub type NodeFactory = Arc<dyn Fn(Box<dyn Node>, NodePosition) -> Box<dyn Node> + Send + Sync>;
 
 /// Base trait for nodes in the Rete network
 pub trait Node: Send + Sync {
     /// Process a fact assertion
-    fn assert_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>>;
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>>;
+
+    /// Process a fact assertion, awaiting asynchronous constraints
+    #[cfg(feature = "async-constraints")]
+    fn assert_fact_async<'a>(
+        &'a self,
+        fact: Arc<FactHandle>,
+        memory: &'a mut NetworkMemory,
+    ) -> NodeFuture<'a> {
+        Box::pin(std::future::ready(self.assert_fact(fact, memory)))
+    }
 
     /// Process a fact retraction
-    fn retract_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>>;
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>>;
+
+    /// Process a partial match arriving from the left input of a beta node
+    fn left_activate(
+        &self,
+        _token: Match,
+        _memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        Ok(Vec::new())
+    }
+
+    /// Collect the patterns this node and the nodes below it test asserted
+    /// facts against, with the nodes testing them
+    fn patterns<'a>(&'a self, _patterns: &mut Vec<(NodeId, &'a dyn Pattern)>) {}
+
+    /// Check if modifying the given fields of a fact can change this node's matches
+    fn reacts_to(&self, _fact: &FactHandle, _changed: &[&str]) -> bool {
+        true
+    }
+
+    /// Swap a newer version of a fact into this node's memories without re-matching
+    fn refresh_fact(&self, _fact: &Arc<FactHandle>, _memory: &mut NetworkMemory) {}
+
+    /// Make terminal nodes below this one activate a new version of their rule
+    fn retarget(&self, _rule: &Arc<Rule>) {}
+
+    /// Drop the memories of this node and the nodes below it
+    fn forget(&self, _memory: &mut NetworkMemory) {}
+
+    /// Drop the matches binding `fact` to `alias` below this node, keeping
+    /// the fact in working memory, as when it leaves a window
+    fn evict_fact(
+        &self,
+        fact: &Arc<FactHandle>,
+        _alias: &str,
+        memory: &mut NetworkMemory,
+    ) -> Result<()> {
+        self.retract_fact(Arc::clone(fact), memory).map(drop)
+    }
+
+    /// Name of the rule the network below this node activates
+    fn rule_name(&self) -> Option<String> {
+        None
+    }
+
+    /// Evict facts whose time is up from the windows at and below this node
+    fn expire_windows(&self, _memory: &mut NetworkMemory) -> Result<()> {
+        Ok(())
+    }
 
     /// Process a fact modification
-    fn modify_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
+    fn modify_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
         // Default: retract then assert
-        let mut results = self.retract_fact(Arc::clone(&fact))?;
-        results.extend(self.assert_fact(fact)?);
+        let mut results = self.retract_fact(Arc::clone(&fact), memory)?;
+        results.extend(self.assert_fact(fact, memory)?);
         Ok(results)
     }
 }
 
 /// Root node of the Rete network
 pub struct RootNode {
-    /// Child nodes (type nodes)
-    children: Vec<Box<dyn Node>>,
+    /// Top node of each rule's network, by rule name
+    ///
+    /// Nodes are reference counted so that flows built from a common rule
+    /// library (see `tenancy`) can share them.
+    children: Vec<(String, Arc<dyn Node>)>,
+    /// Networks of queries, by query name
+    ///
+    /// Queries are evaluated on demand against a scratch memory, so facts are
+    /// never propagated to these networks.
+    queries: Vec<(String, Arc<dyn Node>)>,
+    /// Count of rule networks added and removed, so sessions notice changes
+    revision: u64,
 }
 
 impl RootNode {
@@ -35,12 +427,172 @@ impl RootNode {
     pub fn new() -> Self {
         Self {
             children: Vec::new(),
+            queries: Vec::new(),
+            revision: 0,
         }
     }
 
-    /// Add a child node
-    pub fn add_child(&mut self, child: Box<dyn Node>) {
-        self.children.push(child);
+    /// Get the number of times a rule network was added or removed
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Get the network of every rule, by rule name
+    pub fn rule_networks(&self) -> impl Iterator<Item = (&str, &Arc<dyn Node>)> {
+        self.children
+            .iter()
+            .map(|(name, node)| (name.as_str(), node))
+    }
+
+    /// Add or replace the network of a query
+    pub fn add_query_network(&mut self, query_name: impl Into<String>, node: Arc<dyn Node>) {
+        let query_name = query_name.into();
+        self.queries.retain(|(name, _)| *name != query_name);
+        self.queries.push((query_name, node));
+    }
+
+    /// Get the network of a query
+    pub fn query_network(&self, query_name: &str) -> Option<Arc<dyn Node>> {
+        self.queries
+            .iter()
+            .find(|(name, _)| name == query_name)
+            .map(|(_, node)| Arc::clone(node))
+    }
+
+    /// Remove the network of a query
+    pub fn remove_query_network(&mut self, query_name: &str) -> Option<Arc<dyn Node>> {
+        let index = self
+            .queries
+            .iter()
+            .position(|(name, _)| name == query_name)?;
+        Some(self.queries.remove(index).1)
+    }
+
+    /// Get the names of all queries
+    pub fn query_names(&self) -> Vec<String> {
+        self.queries.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Add the network of a rule
+    pub fn add_rule_network(&mut self, rule_name: impl Into<String>, node: Arc<dyn Node>) {
+        self.children.push((rule_name.into(), node));
+        self.revision += 1;
+    }
+
+    /// Get the network of a rule
+    pub fn rule_network(&self, rule_name: &str) -> Option<Arc<dyn Node>> {
+        self.children
+            .iter()
+            .find(|(name, _)| name == rule_name)
+            .map(|(_, node)| Arc::clone(node))
+    }
+
+    /// Propagate a modification of some of a fact's fields
+    ///
+    /// Only rule networks whose patterns read a changed field re-match the
+    /// fact; the others just see the new version. Returns the new activations
+    /// and the names of the re-matched rules.
+    pub fn modify_fact_fields(
+        &self,
+        fact: Arc<FactHandle>,
+        changed: &[&str],
+        memory: &mut NetworkMemory,
+    ) -> Result<(Vec<Arc<Activation>>, HashSet<String>)> {
+        let mut activations = Vec::new();
+        let mut rematched = HashSet::new();
+        for (name, child) in &self.children {
+            if child.reacts_to(&fact, changed) {
+                activations.extend(
+                    memory
+                        .in_network(name, |memory| child.modify_fact(Arc::clone(&fact), memory))?,
+                );
+                rematched.insert(name.clone());
+            } else {
+                child.refresh_fact(&fact, memory);
+            }
+        }
+        Ok((activations, rematched))
+    }
+
+    /// Remove the network of a rule
+    pub fn remove_rule_network(&mut self, rule_name: &str) -> Option<Arc<dyn Node>> {
+        let index = self
+            .children
+            .iter()
+            .position(|(name, _)| name == rule_name)?;
+        self.revision += 1;
+        Some(self.children.remove(index).1)
+    }
+
+    /// Assert a batch of facts, propagating them one by one
+    ///
+    /// With the `parallel` feature, the facts are first tested against the
+    /// patterns of every rule's network in parallel, so propagation only
+    /// updates memories and joins.
+    pub fn assert_facts(
+        &self,
+        facts: Vec<Arc<FactHandle>>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let mut activations = Vec::new();
+        #[cfg(feature = "parallel")]
+        if let Some(tested) = self.test_facts(&facts) {
+            for (fact, tested) in facts.into_iter().zip(tested) {
+                memory.tested = tested;
+                let matched = self.assert_fact(fact, memory);
+                memory.tested.clear();
+                activations.extend(matched?);
+            }
+            return Ok(activations);
+        }
+        for fact in facts {
+            activations.extend(self.assert_fact(fact, memory)?);
+        }
+        Ok(activations)
+    }
+
+    /// Test facts against the patterns of their type in parallel, giving
+    /// each fact's results sorted by node, or `None` if there is no second
+    /// thread to share the work
+    ///
+    /// Tests that fail are left out, to fail again with their rule named
+    /// during propagation.
+    #[cfg(feature = "parallel")]
+    fn test_facts(&self, facts: &[Arc<FactHandle>]) -> Option<Vec<Vec<(NodeId, bool)>>> {
+        use rayon::prelude::*;
+
+        if facts.len() < 2 || rayon::current_num_threads() < 2 {
+            return None;
+        }
+        let mut patterns = Vec::new();
+        self.patterns(&mut patterns);
+        patterns.sort_by_key(|(node, _)| *node);
+        let mut by_type: HashMap<TypeId, Vec<(NodeId, &dyn Pattern)>> = HashMap::new();
+        for (node, pattern) in patterns {
+            by_type
+                .entry(pattern.type_id())
+                .or_default()
+                .push((node, pattern));
+        }
+
+        let tested = facts
+            .par_iter()
+            .map(|fact| {
+                let context = ConstraintContext::new();
+                by_type
+                    .get(&fact.type_id)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(node, pattern)| {
+                        pattern
+                            .matches(fact, &context)
+                            .ok()
+                            .map(|matched| (*node, matched))
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(tested)
     }
 }
 
@@ -51,43 +603,106 @@ impl Default for RootNode {
 }
 
 impl Node for RootNode {
-    fn assert_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
         let mut activations = Vec::new();
-        for child in &mut self.children {
-            activations.extend(child.assert_fact(Arc::clone(&fact))?);
+        for (name, child) in &self.children {
+            activations.extend(
+                memory.in_network(name, |memory| child.assert_fact(Arc::clone(&fact), memory))?,
+            );
         }
         Ok(activations)
     }
 
-    fn retract_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
+    #[cfg(feature = "async-constraints")]
+    fn assert_fact_async<'a>(
+        &'a self,
+        fact: Arc<FactHandle>,
+        memory: &'a mut NetworkMemory,
+    ) -> NodeFuture<'a> {
+        Box::pin(async move {
+            let mut activations = Vec::new();
+            for (name, child) in &self.children {
+                let start = Instant::now();
+                let matched = child.assert_fact_async(Arc::clone(&fact), memory).await;
+                memory.add_match_time(name, start.elapsed());
+                activations.extend(matched.map_err(|e| e.in_rule(name))?);
+            }
+            Ok(activations)
+        })
+    }
+
+    fn patterns<'a>(&'a self, patterns: &mut Vec<(NodeId, &'a dyn Pattern)>) {
+        for (_, child) in &self.children {
+            child.patterns(patterns);
+        }
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
         let mut activations = Vec::new();
-        for child in &mut self.children {
-            activations.extend(child.retract_fact(Arc::clone(&fact))?);
+        for (name, child) in &self.children {
+            activations.extend(
+                memory.in_network(name, |memory| child.retract_fact(Arc::clone(&fact), memory))?,
+            );
         }
         Ok(activations)
     }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        for (_, child) in &self.children {
+            child.expire_windows(memory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if a pattern reads any changed field of a fact of its type
+fn pattern_reacts_to(pattern: &dyn Pattern, fact: &FactHandle, changed: &[&str]) -> bool {
+    pattern.type_id() == fact.type_id
+        && pattern
+            .reads()
+            .is_none_or(|reads| reads.iter().any(|f| changed.contains(&f.as_str())))
+}
+
+/// Swap a newer version of a fact into a list of handles
+fn refresh_handles(handles: &mut [Arc<FactHandle>], fact: &Arc<FactHandle>) {
+    for handle in handles.iter_mut().filter(|h| h.id == fact.id) {
+        *handle = Arc::clone(fact);
+    }
 }
 
 /// Alpha node for pattern matching
 pub struct AlphaNode {
+    /// Identifier of this node's memory
+    id: NodeId,
     /// Pattern to match
     pattern: Box<dyn Pattern>,
     /// Child nodes
     children: Vec<Box<dyn Node>>,
-    /// Matched facts
-    memory: Vec<Arc<FactHandle>>,
 }
 
 impl AlphaNode {
     /// Create a new alpha node
     pub fn new(pattern: Box<dyn Pattern>) -> Self {
         Self {
+            id: NodeId::new(),
             pattern,
             children: Vec::new(),
-            memory: Vec::new(),
         }
     }
 
+    /// Get the ID of this node
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
     /// Add a child node
     pub fn add_child(&mut self, child: Box<dyn Node>) {
         self.children.push(child);
@@ -97,22 +712,28 @@ impl AlphaNode {
 impl std::fmt::Debug for AlphaNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AlphaNode")
+            .field("id", &self.id)
             .field("pattern", &self.pattern)
-            .field("memory_size", &self.memory.len())
             .finish()
     }
 }
 
 impl Node for AlphaNode {
-    fn assert_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
-        let context = ConstraintContext::new();
-
-        if self.pattern.matches(&fact, &context)? {
-            self.memory.push(Arc::clone(&fact));
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if memory.test(self.id, self.pattern.as_ref(), &fact)? {
+            memory
+                .alpha
+                .entry(self.id)
+                .or_default()
+                .push(Arc::clone(&fact));
 
             let mut activations = Vec::new();
-            for child in &mut self.children {
-                activations.extend(child.assert_fact(Arc::clone(&fact))?);
+            for child in &self.children {
+                activations.extend(child.assert_fact(Arc::clone(&fact), memory)?);
             }
             return Ok(activations);
         }
@@ -120,74 +741,531 @@ impl Node for AlphaNode {
         Ok(Vec::new())
     }
 
-    fn retract_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
-        self.memory.retain(|f| f.id != fact.id);
+    #[cfg(feature = "async-constraints")]
+    fn assert_fact_async<'a>(
+        &'a self,
+        fact: Arc<FactHandle>,
+        memory: &'a mut NetworkMemory,
+    ) -> NodeFuture<'a> {
+        Box::pin(async move {
+            let context = ConstraintContext::new();
+
+            if self.pattern.matches_async(&fact, &context).await? {
+                memory
+                    .alpha
+                    .entry(self.id)
+                    .or_default()
+                    .push(Arc::clone(&fact));
+
+                let mut activations = Vec::new();
+                for child in &self.children {
+                    activations.extend(child.assert_fact_async(Arc::clone(&fact), memory).await?);
+                }
+                return Ok(activations);
+            }
+
+            Ok(Vec::new())
+        })
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if let Some(facts) = memory.alpha.get_mut(&self.id) {
+            facts.retain(|f| f.id != fact.id);
+        }
 
         let mut activations = Vec::new();
-        for child in &mut self.children {
-            activations.extend(child.retract_fact(Arc::clone(&fact))?);
+        for child in &self.children {
+            activations.extend(child.retract_fact(Arc::clone(&fact), memory)?);
         }
         Ok(activations)
     }
+
+    fn patterns<'a>(&'a self, patterns: &mut Vec<(NodeId, &'a dyn Pattern)>) {
+        patterns.push((self.id, self.pattern.as_ref()));
+        for child in &self.children {
+            child.patterns(patterns);
+        }
+    }
+
+    fn reacts_to(&self, fact: &FactHandle, changed: &[&str]) -> bool {
+        pattern_reacts_to(self.pattern.as_ref(), fact, changed)
+    }
+
+    fn refresh_fact(&self, fact: &Arc<FactHandle>, memory: &mut NetworkMemory) {
+        if let Some(facts) = memory.alpha.get_mut(&self.id) {
+            refresh_handles(facts, fact);
+        }
+        for child in &self.children {
+            child.refresh_fact(fact, memory);
+        }
+    }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        self.children.iter().for_each(|child| child.retarget(rule));
+    }
+
+    fn forget(&self, memory: &mut NetworkMemory) {
+        memory.alpha.remove(&self.id);
+        self.children.iter().for_each(|child| child.forget(memory));
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        self.children.iter().find_map(|child| child.rule_name())
+    }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        for child in &self.children {
+            child.expire_windows(memory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hash-indexed memory used by join nodes
+///
+/// Entries are bucketed by the hash of their join key values so a join only
+/// visits the bucket matching the incoming key instead of the whole memory.
+#[derive(Debug)]
+pub struct BetaMemory<T> {
+    buckets: HashMap<u64, Vec<T>>,
+}
+
+impl<T> BetaMemory<T> {
+    /// Create an empty beta memory
+    pub fn new() -> Self {
+        Self {
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Insert an entry under the given key hash
+    pub fn insert(&mut self, key: u64, item: T) {
+        self.buckets.entry(key).or_default().push(item);
+    }
+
+    /// Get all entries stored under the given key hash
+    pub fn bucket(&self, key: u64) -> &[T] {
+        self.buckets.get(&key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Keep only entries for which the predicate holds
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.buckets.retain(|_, items| {
+            items.retain(&mut f);
+            !items.is_empty()
+        });
+    }
+
+    /// Update every entry in place
+    ///
+    /// The update must not change the entry's key hash.
+    pub fn update(&mut self, mut f: impl FnMut(&mut T)) {
+        self.buckets.values_mut().flatten().for_each(&mut f);
+    }
+
+    /// Iterate over all entries
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.buckets.values().flatten()
+    }
+
+    /// Get the total number of entries
+    pub fn len(&self) -> usize {
+        self.buckets.values().map(Vec::len).sum()
+    }
+
+    /// Check if the memory is empty
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Get the number of distinct key buckets
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+impl<T> Default for BetaMemory<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Join node combining partial matches (left) with facts matching a pattern (right)
+pub struct JoinNode {
+    /// Identifier of this node's memories
+    id: NodeId,
+    /// Pattern facts must match to enter the right memory
+    pattern: Box<dyn Pattern>,
+    /// Whether this is the first join of a rule, fed by the empty match
+    is_head: bool,
+    /// Next join node or the terminal node
+    child: Box<dyn Node>,
+    /// Whether the child is another join node that also receives facts
+    child_is_join: bool,
+}
+
+impl JoinNode {
+    /// Create the last join node of a rule, feeding a terminal node
+    pub fn new(pattern: Box<dyn Pattern>, terminal: TerminalNode) -> Self {
+        Self {
+            id: NodeId::new(),
+            pattern,
+            is_head: false,
+            child: Box::new(terminal),
+            child_is_join: false,
+        }
+    }
+
+    /// Create a join node feeding another join node
+    pub fn chain(pattern: Box<dyn Pattern>, next: JoinNode) -> Self {
+        Self {
+            id: NodeId::new(),
+            pattern,
+            is_head: false,
+            child: Box::new(next),
+            child_is_join: true,
+        }
+    }
+
+    /// Create a join node at a position in a rule's network, feeding `child`
+    pub fn link(pattern: Box<dyn Pattern>, child: Box<dyn Node>, position: NodePosition) -> Self {
+        let (is_head, child_is_join) = match position {
+            NodePosition::Alone => (true, false),
+            NodePosition::Join { head, last } => (head, !last),
+        };
+        Self {
+            id: NodeId::new(),
+            pattern,
+            is_head,
+            child,
+            child_is_join,
+        }
+    }
+
+    /// Make this the first join node of a rule, whose left input is the empty match
+    pub fn into_head(mut self) -> Self {
+        self.is_head = true;
+        self
+    }
+
+    /// Get the ID of this node
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Get this node's partial-match memory, seeding the head with the empty match
+    fn left_memory<'m>(&self, memory: &'m mut NetworkMemory) -> &'m mut BetaMemory<Match> {
+        let is_head = self.is_head;
+        memory.left.entry(self.id).or_insert_with(|| {
+            let mut left = BetaMemory::new();
+            if is_head {
+                left.insert(combine_hashes(std::iter::empty()), Match::new());
+            }
+            left
+        })
+    }
+
+    fn keys(&self) -> &[JoinKey] {
+        self.pattern.join_keys()
+    }
+
+    fn left_key(&self, token: &Match) -> Option<u64> {
+        let hashes: Option<Vec<u64>> = self.keys().iter().map(|k| k.left_hash(token)).collect();
+        hashes.map(|h| combine_hashes(h.into_iter()))
+    }
+
+    fn right_key(&self, fact: &FactHandle) -> Option<u64> {
+        let hashes: Option<Vec<u64>> = self.keys().iter().map(|k| k.right_hash(fact)).collect();
+        hashes.map(|h| combine_hashes(h.into_iter()))
+    }
+
+    /// Extend a partial match with a fact, if the fact joins it
+    fn join(&self, token: &Match, fact: &Arc<FactHandle>) -> Result<Option<Match>> {
+        if !self.keys().iter().all(|k| k.matches(token, fact))
+            || !self.pattern.matches_bound(fact, token)?
+        {
+            return Ok(None);
+        }
+        let mut extended = token.clone();
+        extended.insert(self.pattern.alias().to_string(), Arc::clone(fact));
+        self.pattern.bind(fact, &mut extended)?;
+        Ok(Some(extended))
+    }
+
+    /// Store a fact that passed the pattern and join it against the left memory
+    fn right_activate(
+        &self,
+        fact: &Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let key = match self.right_key(fact) {
+            Some(key) => key,
+            None => return Ok(Vec::new()),
+        };
+        memory
+            .right
+            .entry(self.id)
+            .or_default()
+            .insert(key, Arc::clone(fact));
+
+        let tokens = self
+            .left_memory(memory)
+            .bucket(key)
+            .iter()
+            .filter_map(|token| self.join(token, fact).transpose())
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut activations = Vec::new();
+        for token in tokens {
+            activations.extend(self.child.left_activate(token, memory)?);
+        }
+        Ok(activations)
+    }
+}
+
+/// Combine per-key hashes into a single bucket key
+fn combine_hashes(hashes: impl Iterator<Item = u64>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for hash in hashes {
+        hasher.write_u64(hash);
+    }
+    hasher.finish()
+}
+
+impl std::fmt::Debug for JoinNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinNode")
+            .field("id", &self.id)
+            .field("pattern", &self.pattern)
+            .field("is_head", &self.is_head)
+            .finish()
+    }
+}
+
+impl Node for JoinNode {
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let mut activations = Vec::new();
+        if memory.test(self.id, self.pattern.as_ref(), &fact)? {
+            activations.extend(self.right_activate(&fact, memory)?);
+        }
+
+        // Downstream joins may match the same fact on their own pattern
+        if self.child_is_join {
+            activations.extend(self.child.assert_fact(fact, memory)?);
+        }
+        Ok(activations)
+    }
+
+    #[cfg(feature = "async-constraints")]
+    fn assert_fact_async<'a>(
+        &'a self,
+        fact: Arc<FactHandle>,
+        memory: &'a mut NetworkMemory,
+    ) -> NodeFuture<'a> {
+        Box::pin(async move {
+            let context = ConstraintContext::new();
+
+            let mut activations = Vec::new();
+            if self.pattern.matches_async(&fact, &context).await? {
+                activations.extend(self.right_activate(&fact, memory)?);
+            }
+
+            if self.child_is_join {
+                activations.extend(self.child.assert_fact_async(fact, memory).await?);
+            }
+            Ok(activations)
+        })
+    }
+
+    fn left_activate(
+        &self,
+        token: Match,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let key = match self.left_key(&token) {
+            Some(key) => key,
+            None => return Ok(Vec::new()),
+        };
+
+        let tokens = match memory.right.get(&self.id) {
+            Some(right) => right
+                .bucket(key)
+                .iter()
+                .filter_map(|fact| self.join(&token, fact).transpose())
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        self.left_memory(memory).insert(key, token);
+
+        let mut activations = Vec::new();
+        for token in tokens {
+            activations.extend(self.child.left_activate(token, memory)?);
+        }
+        Ok(activations)
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if let Some(right) = memory.right.get_mut(&self.id) {
+            right.retain(|f| f.id != fact.id);
+        }
+        self.left_memory(memory)
+            .retain(|token| token.facts.values().all(|f| f.id != fact.id));
+
+        if self.child_is_join {
+            return self.child.retract_fact(fact, memory);
+        }
+        Ok(Vec::new())
+    }
+
+    fn patterns<'a>(&'a self, patterns: &mut Vec<(NodeId, &'a dyn Pattern)>) {
+        patterns.push((self.id, self.pattern.as_ref()));
+        if self.child_is_join {
+            self.child.patterns(patterns);
+        }
+    }
+
+    fn reacts_to(&self, fact: &FactHandle, changed: &[&str]) -> bool {
+        pattern_reacts_to(self.pattern.as_ref(), fact, changed)
+            || (self.child_is_join && self.child.reacts_to(fact, changed))
+    }
+
+    fn refresh_fact(&self, fact: &Arc<FactHandle>, memory: &mut NetworkMemory) {
+        if let Some(right) = memory.right.get_mut(&self.id) {
+            right.update(|handle| refresh_handles(std::slice::from_mut(handle), fact));
+        }
+        self.left_memory(memory)
+            .update(|token| token.replace_fact(fact));
+
+        if self.child_is_join {
+            self.child.refresh_fact(fact, memory);
+        }
+    }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        self.child.retarget(rule);
+    }
+
+    fn forget(&self, memory: &mut NetworkMemory) {
+        memory.left.remove(&self.id);
+        memory.right.remove(&self.id);
+        self.child.forget(memory);
+    }
+
+    fn evict_fact(
+        &self,
+        fact: &Arc<FactHandle>,
+        alias: &str,
+        memory: &mut NetworkMemory,
+    ) -> Result<()> {
+        if self.pattern.alias() == alias {
+            if let Some(right) = memory.right.get_mut(&self.id) {
+                right.retain(|f| f.id != fact.id);
+            }
+        }
+        self.left_memory(memory)
+            .retain(|token| token.get(alias).is_none_or(|f| f.id != fact.id));
+
+        if self.child_is_join {
+            return self.child.evict_fact(fact, alias, memory);
+        }
+        Ok(())
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        self.child.rule_name()
+    }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        self.child.expire_windows(memory)
+    }
 }
 
 /// Terminal node that creates activations
 pub struct TerminalNode {
-    /// The rule this terminal represents
-    rule: Arc<crate::rule::Rule>,
-    /// Counter for activation recency
-    recency: std::sync::atomic::AtomicU64,
+    /// The rule this terminal represents, replaced when a reload keeps the network
+    rule: RwLock<Arc<Rule>>,
 }
 
 impl TerminalNode {
     /// Create a new terminal node
-    pub fn new(rule: Arc<crate::rule::Rule>) -> Self {
+    pub fn new(rule: Arc<Rule>) -> Self {
         Self {
-            rule,
-            recency: std::sync::atomic::AtomicU64::new(0),
+            rule: RwLock::new(rule),
         }
     }
+
+    /// Get the rule this terminal currently activates
+    fn rule(&self) -> Arc<Rule> {
+        Arc::clone(&self.rule.read().unwrap_or_else(|e| e.into_inner()))
+    }
 }
 
 impl std::fmt::Debug for TerminalNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TerminalNode")
-            .field("rule", &self.rule.name)
+            .field("rule", &self.rule().name)
             .finish()
     }
 }
 
 impl Node for TerminalNode {
-    fn assert_fact(&mut self, fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
-        let recency = self
-            .recency
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-
-        let mut match_data = Match::new();
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let rule = self.rule();
+        let mut match_data = memory.new_match();
         // For simple rules with one pattern, use the first pattern's alias
-        if let Some(pattern) = self.rule.patterns.first() {
+        if let Some(pattern) = rule.patterns.first() {
+            pattern.bind(&fact, &mut match_data)?;
             match_data.insert(pattern.alias().to_string(), fact);
         }
 
-        let activation = Arc::new(Activation::new(
-            Arc::clone(&self.rule),
-            match_data,
-            recency,
-        ));
+        Ok(vec![memory.new_activation(rule, match_data)])
+    }
 
-        Ok(vec![activation])
+    fn left_activate(
+        &self,
+        token: Match,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        Ok(vec![memory.new_activation(self.rule(), token)])
     }
 
-    fn retract_fact(&mut self, _fact: Arc<FactHandle>) -> Result<Vec<Arc<Activation>>> {
+    fn retract_fact(
+        &self,
+        _fact: Arc<FactHandle>,
+        _memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
         // Retractions don't create activations in terminal nodes
         Ok(Vec::new())
     }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        *self.rule.write().unwrap_or_else(|e| e.into_inner()) = Arc::clone(rule);
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        Some(self.rule().name.clone())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::fact::Fact;
     use crate::pattern::ObjectPattern;
     use crate::rule::Rule;
 
@@ -196,22 +1274,49 @@ mod tests {
         value: i32,
     }
 
+    #[derive(Debug, Clone)]
+    struct Customer {
+        id: u32,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Order {
+        customer_id: u32,
+    }
+
+    fn customer_order_join() -> JoinNode {
+        let rule = Arc::new(
+            Rule::new("customer_orders")
+                .then(|_, _| Ok(()))
+                .build()
+                .unwrap(),
+        );
+        let orders = ObjectPattern::<Order>::new("o").join_on(
+            "c",
+            |c: &Customer| c.id,
+            |o: &Order| o.customer_id,
+        );
+        let last = JoinNode::new(Box::new(orders), TerminalNode::new(rule));
+        JoinNode::chain(Box::new(ObjectPattern::<Customer>::new("c")), last).into_head()
+    }
+
     #[test]
     fn test_alpha_node_matching() {
         let pattern = Box::new(
             ObjectPattern::<TestFact>::new("test").with_filter(|f| f.value > 40, "value > 40"),
         ) as Box<dyn Pattern>;
 
-        let mut node = AlphaNode::new(pattern);
+        let node = AlphaNode::new(pattern);
+        let mut memory = NetworkMemory::new();
 
         let fact1 = FactHandle::new(TestFact { value: 42 }, 0);
         let fact2 = FactHandle::new(TestFact { value: 30 }, 1);
 
-        node.assert_fact(Arc::new(fact1)).unwrap();
-        assert_eq!(node.memory.len(), 1);
+        node.assert_fact(Arc::new(fact1), &mut memory).unwrap();
+        assert_eq!(memory.alpha_memory(node.id()).len(), 1);
 
-        node.assert_fact(Arc::new(fact2)).unwrap();
-        assert_eq!(node.memory.len(), 1); // Should still be 1
+        node.assert_fact(Arc::new(fact2), &mut memory).unwrap();
+        assert_eq!(memory.alpha_memory(node.id()).len(), 1); // Should still be 1
     }
 
     #[test]
@@ -224,12 +1329,136 @@ mod tests {
                 .unwrap(),
         );
 
-        let mut node = TerminalNode::new(rule);
+        let node = TerminalNode::new(rule);
+        let mut memory = NetworkMemory::new();
 
         let fact = FactHandle::new(TestFact { value: 42 }, 0);
-        let activations = node.assert_fact(Arc::new(fact)).unwrap();
+        let activations = node.assert_fact(Arc::new(fact), &mut memory).unwrap();
 
         assert_eq!(activations.len(), 1);
         assert_eq!(activations[0].rule.name, "test_rule");
     }
+
+    #[test]
+    fn test_join_node_matches_on_key() {
+        let node = customer_order_join();
+        let mut memory = NetworkMemory::new();
+
+        for (recency, id) in [(0, 1), (1, 2)] {
+            let customer = Arc::new(FactHandle::new(Customer { id }, recency));
+            node.assert_fact(customer, &mut memory).unwrap();
+        }
+
+        let order = Arc::new(FactHandle::new(Order { customer_id: 2 }, 2));
+        let activations = node.assert_fact(order, &mut memory).unwrap();
+        assert_eq!(activations.len(), 1);
+        let customer = activations[0].match_data.get("c").unwrap();
+        assert_eq!(customer.downcast_ref::<Customer>().unwrap().id, 2);
+
+        let order = Arc::new(FactHandle::new(Order { customer_id: 3 }, 3));
+        let activations = node.assert_fact(order, &mut memory).unwrap();
+        assert!(activations.is_empty());
+    }
+
+    #[test]
+    fn test_beta_memory_is_indexed() {
+        let mut memory = BetaMemory::new();
+        memory.insert(1, "a");
+        memory.insert(1, "b");
+        memory.insert(2, "c");
+
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.bucket_count(), 2);
+        assert_eq!(memory.bucket(1), &["a", "b"]);
+        assert!(memory.bucket(3).is_empty());
+
+        memory.retain(|item| *item != "c");
+        assert_eq!(memory.bucket_count(), 1);
+    }
+
+    #[test]
+    fn test_join_node_retraction() {
+        let node = customer_order_join();
+        let mut memory = NetworkMemory::new();
+        let customer = Arc::new(FactHandle::new(Customer { id: 1 }, 0));
+
+        node.assert_fact(Arc::clone(&customer), &mut memory)
+            .unwrap();
+        node.retract_fact(customer, &mut memory).unwrap();
+
+        let order = Arc::new(FactHandle::new(Order { customer_id: 1 }, 1));
+        let activations = node.assert_fact(order, &mut memory).unwrap();
+        assert!(activations.is_empty());
+    }
+
+    #[test]
+    fn test_memories_are_isolated() {
+        let node = customer_order_join();
+        let mut first = NetworkMemory::new();
+        let mut second = NetworkMemory::new();
+
+        let customer = Arc::new(FactHandle::new(Customer { id: 1 }, 0));
+        node.assert_fact(customer, &mut first).unwrap();
+
+        let order = Arc::new(FactHandle::new(Order { customer_id: 1 }, 1));
+        let activations = node.assert_fact(Arc::clone(&order), &mut second).unwrap();
+        assert!(activations.is_empty());
+
+        let activations = node.assert_fact(order, &mut first).unwrap();
+        assert_eq!(activations.len(), 1);
+    }
+
+    #[test]
+    fn test_assert_facts_matches_like_assert_fact() {
+        let mut root = RootNode::new();
+        root.add_rule_network("customer_orders", Arc::new(customer_order_join()));
+        let mut alpha = AlphaNode::new(Box::new(
+            ObjectPattern::<TestFact>::new("t").with_filter(|f| f.value > 40, "value > 40"),
+        ));
+        let rule = Rule::new("big").then(|_, _| Ok(())).build().unwrap();
+        alpha.add_child(Box::new(TerminalNode::new(Arc::new(rule))));
+        root.add_rule_network("big", Arc::new(alpha));
+
+        let mut facts: Vec<Arc<FactHandle>> = Vec::new();
+        for id in 0..100u32 {
+            let recency = u64::from(id) * 3;
+            facts.push(Arc::new(FactHandle::new(Customer { id }, recency)));
+            facts.push(Arc::new(FactHandle::new(
+                Order {
+                    customer_id: id % 50,
+                },
+                recency + 1,
+            )));
+            facts.push(Arc::new(FactHandle::new(
+                TestFact { value: id as i32 },
+                recency + 2,
+            )));
+        }
+
+        let mut one_by_one = NetworkMemory::new();
+        let mut expected = Vec::new();
+        for fact in &facts {
+            expected.extend(root.assert_fact(Arc::clone(fact), &mut one_by_one).unwrap());
+        }
+        let mut batched = NetworkMemory::new();
+        let assert_facts = || root.assert_facts(facts, &mut batched).unwrap();
+        // Test facts ahead of propagation even on a single CPU
+        #[cfg(feature = "parallel")]
+        let activations = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap()
+            .install(assert_facts);
+        #[cfg(not(feature = "parallel"))]
+        let activations = assert_facts();
+
+        let names = |activations: &[Arc<Activation>]| {
+            activations
+                .iter()
+                .map(|a| (a.rule.name.clone(), a.match_data.fact_ids()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(activations.len(), 100 + 59);
+        assert_eq!(names(&activations), names(&expected));
+    }
 }
@@ -1,11 +1,34 @@
 //! Pattern definitions for fact matching
 
+use crate::analysis::Comparison;
+#[cfg(feature = "async-constraints")]
+use crate::constraint::AsyncConstraint;
 use crate::constraint::{Constraint, ConstraintContext};
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::explain::ConstraintResult;
+use crate::expr::Expression;
 use crate::fact::{Fact, FactHandle};
+use crate::field::{Field, WhereField};
+use crate::model::{ModelConstraint, ModelScorer, Predictor};
+use crate::node::{NetworkMemory, Node, NodeFactory, NodeId, NodePosition};
+use crate::rule::{Activation, Match, Rule};
+use crate::value::{has_fields, Value};
+use crate::window::{Window, WindowPattern};
 use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
+#[cfg(feature = "async-constraints")]
+use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+#[cfg(feature = "async-constraints")]
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Future returned by asynchronous pattern matching
+#[cfg(feature = "async-constraints")]
+pub type PatternFuture<'a> = Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>>;
 
 /// A pattern that matches facts in working memory
 pub trait Pattern: Debug + Send + Sync {
@@ -15,13 +38,185 @@ pub trait Pattern: Debug + Send + Sync {
     /// Check if a fact matches this pattern
     fn matches(&self, fact: &FactHandle, context: &ConstraintContext) -> Result<bool>;
 
+    /// Check if a fact matches this pattern, awaiting any asynchronous constraints
+    #[cfg(feature = "async-constraints")]
+    fn matches_async<'a>(
+        &'a self,
+        fact: &'a Arc<FactHandle>,
+        context: &'a ConstraintContext,
+    ) -> PatternFuture<'a> {
+        Box::pin(std::future::ready(self.matches(fact, context)))
+    }
+
     /// Get the alias for this pattern
     fn alias(&self) -> &str;
 
+    /// Equality joins against facts bound by earlier patterns
+    fn join_keys(&self) -> &[JoinKey] {
+        &[]
+    }
+
+    /// Fields of the fact this pattern reads, or `None` if it may read any field
+    ///
+    /// Modifications that only change other fields do not re-evaluate the pattern.
+    fn reads(&self) -> Option<&[String]> {
+        None
+    }
+
+    /// Number of constraints and joins this pattern tests
+    fn constraint_count(&self) -> usize {
+        0
+    }
+
+    /// Comparisons of the matched fact's fields with constants that hold for
+    /// every fact this pattern matches, for [`crate::analysis`]
+    fn comparisons(&self) -> Vec<Comparison> {
+        Vec::new()
+    }
+
+    /// Evaluate this pattern's constraints against a fact it matched in
+    /// `token`, for [`Activation::explain`](crate::rule::Activation::explain)
+    fn explain(&self, _fact: &FactHandle, _token: &Match) -> Vec<ConstraintResult> {
+        Vec::new()
+    }
+
+    /// Test the constraints reading values bound by earlier patterns, once
+    /// the fact joins the partial match `token`
+    fn matches_bound(&self, _fact: &FactHandle, _token: &Match) -> Result<bool> {
+        Ok(true)
+    }
+
+    /// Add values derived from a matched fact (e.g. model scores) to a match
+    fn bind(&self, _fact: &FactHandle, _token: &mut Match) -> Result<()> {
+        Ok(())
+    }
+
+    /// Build this pattern's own node instead of the built-in alpha or join node
+    ///
+    /// Patterns backed by node extensions return one, see [`crate::extension`].
+    fn node_factory(&self) -> Option<NodeFactory> {
+        None
+    }
+
     /// Clone this pattern into a box
     fn clone_box(&self) -> Box<dyn Pattern>;
+
+    /// Only match the facts that arrived within `duration`, see [`crate::window`]
+    fn over_window(self, duration: Duration) -> WindowPattern
+    where
+        Self: Sized + 'static,
+    {
+        WindowPattern::new(Box::new(self), Window::Time(duration))
+    }
+
+    /// Only match the last `n` facts, see [`crate::window`]
+    fn over_length(self, n: usize) -> WindowPattern
+    where
+        Self: Sized + 'static,
+    {
+        WindowPattern::new(Box::new(self), Window::Length(n))
+    }
+}
+
+type KeyHashFn = Arc<dyn Fn(&FactHandle) -> Option<u64> + Send + Sync>;
+type KeyEqFn = Arc<dyn Fn(&FactHandle, &FactHandle) -> bool + Send + Sync>;
+
+/// An equality join between a pattern and a fact bound by an earlier pattern
+///
+/// Join nodes hash both sides on these keys, so joining large populations
+/// only compares facts that land in the same bucket.
+#[derive(Clone)]
+pub struct JoinKey {
+    /// Alias of the earlier pattern to join against
+    pub alias: String,
+    left_type: TypeId,
+    right_type: TypeId,
+    left_hash: KeyHashFn,
+    right_hash: KeyHashFn,
+    equals: KeyEqFn,
+}
+
+impl JoinKey {
+    /// Create a join key comparing `left(bound fact) == right(candidate fact)`
+    pub fn new<L, R, K, FL, FR>(alias: impl Into<String>, left: FL, right: FR) -> Self
+    where
+        L: Fact,
+        R: Fact,
+        K: Hash + Eq + 'static,
+        FL: Fn(&L) -> K + Send + Sync + 'static,
+        FR: Fn(&R) -> K + Send + Sync + 'static,
+    {
+        let left = Arc::new(left);
+        let right = Arc::new(right);
+        let (left_eq, right_eq) = (Arc::clone(&left), Arc::clone(&right));
+
+        Self {
+            alias: alias.into(),
+            left_type: TypeId::of::<L>(),
+            right_type: TypeId::of::<R>(),
+            left_hash: Arc::new(move |fact: &FactHandle| {
+                fact.downcast_ref::<L>().map(|f| hash_key(&left(f)))
+            }),
+            right_hash: Arc::new(move |fact: &FactHandle| {
+                fact.downcast_ref::<R>().map(|f| hash_key(&right(f)))
+            }),
+            equals: Arc::new(move |bound: &FactHandle, candidate: &FactHandle| {
+                match (bound.downcast_ref::<L>(), candidate.downcast_ref::<R>()) {
+                    (Some(l), Some(r)) => left_eq(l) == right_eq(r),
+                    _ => false,
+                }
+            }),
+        }
+    }
+
+    /// Get the type of the bound fact the key reads
+    pub fn left_type(&self) -> TypeId {
+        self.left_type
+    }
+
+    /// Get the type of the candidate fact the key reads
+    pub fn right_type(&self) -> TypeId {
+        self.right_type
+    }
+
+    /// Hash the key value of a partial match (the left side of a join)
+    pub fn left_hash(&self, token: &Match) -> Option<u64> {
+        token
+            .get(&self.alias)
+            .and_then(|fact| (self.left_hash)(fact))
+    }
+
+    /// Hash the key value of a candidate fact (the right side of a join)
+    pub fn right_hash(&self, fact: &FactHandle) -> Option<u64> {
+        (self.right_hash)(fact)
+    }
+
+    /// Check that the key values of a partial match and a fact are equal
+    pub fn matches(&self, token: &Match, fact: &FactHandle) -> bool {
+        token
+            .get(&self.alias)
+            .map(|bound| (self.equals)(bound, fact))
+            .unwrap_or(false)
+    }
+}
+
+impl Debug for JoinKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinKey")
+            .field("alias", &self.alias)
+            .finish()
+    }
+}
+
+fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
 }
 
+/// Computes a value bound into matches from a fact of type `T`
+pub type ValueBinding<T> = Arc<dyn Fn(&T) -> Value + Send + Sync>;
+
 /// An object pattern that matches facts of a specific type with constraints
 #[derive(Clone)]
 pub struct ObjectPattern<T: Fact> {
@@ -29,6 +224,19 @@ pub struct ObjectPattern<T: Fact> {
     pub alias: String,
     /// Constraints to apply
     pub constraints: Vec<Box<dyn Constraint>>,
+    /// Asynchronous constraints, evaluated after the synchronous ones
+    #[cfg(feature = "async-constraints")]
+    pub async_constraints: Vec<Box<dyn AsyncConstraint>>,
+    /// Equality joins against earlier patterns
+    pub join_keys: Vec<JoinKey>,
+    /// Model scores bound into the match, by binding name
+    pub score_bindings: Vec<(String, ModelScorer)>,
+    /// Field values bound into the match, by binding name
+    pub value_bindings: Vec<(String, ValueBinding<T>)>,
+    /// Constraints reading values bound by earlier patterns, tested on joins
+    pub bound_constraints: Vec<Box<dyn Constraint>>,
+    /// Fields read by the constraints and join keys, if declared
+    pub reads: Option<Vec<String>>,
     /// Type marker
     _phantom: PhantomData<T>,
 }
@@ -39,6 +247,13 @@ impl<T: Fact> ObjectPattern<T> {
         Self {
             alias: alias.into(),
             constraints: Vec::new(),
+            #[cfg(feature = "async-constraints")]
+            async_constraints: Vec::new(),
+            join_keys: Vec::new(),
+            score_bindings: Vec::new(),
+            value_bindings: Vec::new(),
+            bound_constraints: Vec::new(),
+            reads: None,
             _phantom: PhantomData,
         }
     }
@@ -49,6 +264,33 @@ impl<T: Fact> ObjectPattern<T> {
         self
     }
 
+    /// Add a constraint, such as a [`FieldConstraint`](crate::field::FieldConstraint)
+    pub fn with(self, constraint: impl Constraint + 'static) -> Self {
+        self.with_constraint(Box::new(constraint))
+    }
+
+    /// Compare a field read by `get`, adding a described constraint
+    ///
+    /// Unlike [`with_filter`](Self::with_filter) closures, the constraint's
+    /// comparisons are visible to analysis and explanations.
+    pub fn where_field<V>(self, name: &'static str, get: fn(&T) -> &V) -> WhereField<T, V>
+    where
+        V: Debug + Send + Sync + 'static,
+    {
+        WhereField::new(self, Field::new(name, get))
+    }
+
+    /// Declare the fields this pattern's constraints and join keys read
+    ///
+    /// Modifications made with `Session::modify_fields` that change none of
+    /// these fields skip re-evaluating the pattern.
+    pub fn reads(mut self, fields: &[&str]) -> Self {
+        self.reads
+            .get_or_insert_with(Vec::new)
+            .extend(fields.iter().map(|f| f.to_string()));
+        self
+    }
+
     /// Add a function constraint
     pub fn with_filter<F>(self, f: F, description: impl Into<String>) -> Self
     where
@@ -63,15 +305,208 @@ impl<T: Fact> ObjectPattern<T> {
         );
         self.with_constraint(Box::new(constraint))
     }
+
+    /// Add a constraint written as an [`Expression`], such as `"total > 100"`
+    ///
+    /// Fails if the expression does not parse or the fields of `T` were not
+    /// registered, see [`crate::expr`].
+    pub fn with_expression(self, source: &str) -> Result<Self> {
+        if !has_fields(TypeId::of::<T>()) {
+            return Err(Error::InvalidConstraint(format!(
+                "'{}' reads fields of {}, which are not registered",
+                source,
+                std::any::type_name::<T>()
+            )));
+        }
+        let expression = Expression::parse(source)?;
+        Ok(self.with_constraint(Box::new(expression)))
+    }
+
+    /// Match facts whose text read by `accessor` matches a regular expression
+    ///
+    /// The expression is compiled once, here, rather than on every match.
+    #[cfg(feature = "regex")]
+    pub fn with_regex<F>(self, accessor: F, pattern: &str) -> Result<Self>
+    where
+        F: Fn(&T) -> &str + Send + Sync + 'static,
+    {
+        let regex = regex::Regex::new(pattern).map_err(|e| {
+            Error::InvalidConstraint(format!("invalid regular expression '{}': {}", pattern, e))
+        })?;
+        let description = format!("matches /{}/", pattern);
+        Ok(self.with(crate::field::FieldConstraint::new(
+            description,
+            move |fact: &T| regex.is_match(accessor(fact)),
+        )))
+    }
+
+    /// Join this pattern to the fact bound as `alias` where `left(bound) == right(fact)`
+    pub fn join_on<L, K, FL, FR>(mut self, alias: impl Into<String>, left: FL, right: FR) -> Self
+    where
+        L: Fact,
+        K: Hash + Eq + 'static,
+        FL: Fn(&L) -> K + Send + Sync + 'static,
+        FR: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        self.join_keys.push(JoinKey::new(alias, left, right));
+        self
+    }
+
+    /// Only match facts whose model score satisfies `predicate`
+    pub fn with_model_filter<F, P>(
+        self,
+        predictor: Arc<dyn Predictor>,
+        features: F,
+        predicate: P,
+        description: impl Into<String>,
+    ) -> Self
+    where
+        F: Fn(&T) -> Vec<f64> + Send + Sync + 'static,
+        P: Fn(f64) -> bool + Send + Sync + 'static,
+    {
+        let scorer = ModelScorer::new(predictor, features);
+        self.with_constraint(Box::new(ModelConstraint::new(
+            scorer,
+            predicate,
+            description,
+        )))
+    }
+
+    /// Bind the model score of the matched fact into the match as `binding`
+    pub fn bind_model_score<F>(
+        mut self,
+        binding: impl Into<String>,
+        predictor: Arc<dyn Predictor>,
+        features: F,
+    ) -> Self
+    where
+        F: Fn(&T) -> Vec<f64> + Send + Sync + 'static,
+    {
+        self.score_bindings
+            .push((binding.into(), ModelScorer::new(predictor, features)));
+        self
+    }
+
+    /// Bind a value computed from the matched fact into the match as `binding`
+    ///
+    /// Actions read it with [`Match::value`] and later patterns with
+    /// [`with_bound_filter`](Self::with_bound_filter). The value is computed
+    /// when the fact matches; if the pattern declares its
+    /// [`reads`](Self::reads), they must include the fields `value` reads.
+    pub fn bind<F, V>(mut self, binding: impl Into<String>, value: F) -> Self
+    where
+        F: Fn(&T) -> V + Send + Sync + 'static,
+        V: Into<Value>,
+    {
+        self.value_bindings
+            .push((binding.into(), Arc::new(move |fact: &T| value(fact).into())));
+        self
+    }
+
+    /// Only match facts passing `f`, which also reads the values bound by
+    /// earlier patterns of the rule
+    ///
+    /// The filter runs when the fact joins a partial match, against the
+    /// match's [`ConstraintContext`], so it cannot prune facts ahead of joins.
+    pub fn with_bound_filter<F>(mut self, f: F, description: impl Into<String>) -> Self
+    where
+        F: Fn(&T, &ConstraintContext) -> bool + Send + Sync + 'static,
+    {
+        use crate::constraint::FunctionConstraint;
+        let constraint = FunctionConstraint::new(
+            move |fact: &FactHandle, context: &ConstraintContext| {
+                fact.downcast_ref::<T>()
+                    .is_some_and(|fact| f(fact, context))
+            },
+            description,
+        );
+        self.bound_constraints.push(Box::new(constraint));
+        self
+    }
+
+    /// Add an asynchronous constraint to this pattern
+    #[cfg(feature = "async-constraints")]
+    pub fn with_async_constraint(mut self, constraint: Box<dyn AsyncConstraint>) -> Self {
+        self.async_constraints.push(constraint);
+        self
+    }
+
+    /// Add an asynchronous function constraint
+    #[cfg(feature = "async-constraints")]
+    pub fn with_async_filter<F, Fut>(self, f: F, description: impl Into<String>) -> Self
+    where
+        F: Fn(&T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        use crate::constraint::AsyncFunctionConstraint;
+        let constraint = AsyncFunctionConstraint::new(
+            move |fact: Arc<FactHandle>, _ctx| {
+                let pending = fact.downcast_ref::<T>().map(&f);
+                async move {
+                    match pending {
+                        Some(pending) => Ok(pending.await),
+                        None => Ok(false),
+                    }
+                }
+            },
+            description,
+        );
+        self.with_async_constraint(Box::new(constraint))
+    }
+
+    /// Check the type and synchronous constraints
+    fn matches_sync(&self, fact: &FactHandle, context: &ConstraintContext) -> Result<bool> {
+        // Check type
+        if fact.type_id != TypeId::of::<T>() {
+            return Ok(false);
+        }
+
+        // Check all constraints
+        for constraint in &self.constraints {
+            let holds = constraint
+                .evaluate(fact, context)
+                .map_err(|e| self.constraint_failed(constraint.description(), fact, e))?;
+            if !holds {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Add the pattern, constraint and fact to a constraint's error
+    fn constraint_failed(&self, constraint: String, fact: &FactHandle, source: Error) -> Error {
+        Error::ConstraintFailed {
+            rule: None,
+            alias: self.alias.clone(),
+            constraint,
+            fact: fact.id,
+            source: Box::new(source),
+        }
+    }
 }
 
 impl<T: Fact> Debug for ObjectPattern<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ObjectPattern")
+        let mut debug = f.debug_struct("ObjectPattern");
+        debug
             .field("alias", &self.alias)
             .field("type", &std::any::type_name::<T>())
             .field("constraints", &self.constraints)
-            .finish()
+            .field("join_keys", &self.join_keys)
+            .field("score_bindings", &self.score_bindings)
+            .field(
+                "value_bindings",
+                &self
+                    .value_bindings
+                    .iter()
+                    .map(|(binding, _)| binding)
+                    .collect::<Vec<_>>(),
+            )
+            .field("reads", &self.reads);
+        #[cfg(feature = "async-constraints")]
+        debug.field("async_constraints", &self.async_constraints);
+        debug.finish()
     }
 }
 
@@ -81,29 +516,125 @@ impl<T: Fact> Pattern for ObjectPattern<T> {
     }
 
     fn matches(&self, fact: &FactHandle, context: &ConstraintContext) -> Result<bool> {
-        // Check type
-        if fact.type_id != self.type_id() {
+        if !self.matches_sync(fact, context)? {
             return Ok(false);
         }
 
-        // Check all constraints
-        for constraint in &self.constraints {
-            if !constraint.evaluate(fact, context)? {
-                return Ok(false);
-            }
+        #[cfg(feature = "async-constraints")]
+        if !self.async_constraints.is_empty() {
+            return Err(Error::PatternMatch(format!(
+                "pattern '{}' has asynchronous constraints; use Session::assert_async",
+                self.alias
+            )));
         }
 
         Ok(true)
     }
 
+    #[cfg(feature = "async-constraints")]
+    fn matches_async<'a>(
+        &'a self,
+        fact: &'a Arc<FactHandle>,
+        context: &'a ConstraintContext,
+    ) -> PatternFuture<'a> {
+        Box::pin(async move {
+            if !self.matches_sync(fact, context)? {
+                return Ok(false);
+            }
+
+            for constraint in &self.async_constraints {
+                let holds = constraint
+                    .evaluate(Arc::clone(fact), context.clone())
+                    .await
+                    .map_err(|e| self.constraint_failed(format!("{:?}", constraint), fact, e))?;
+                if !holds {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        })
+    }
+
     fn alias(&self) -> &str {
         &self.alias
     }
 
+    fn join_keys(&self) -> &[JoinKey] {
+        &self.join_keys
+    }
+
+    fn reads(&self) -> Option<&[String]> {
+        self.reads.as_deref()
+    }
+
+    fn constraint_count(&self) -> usize {
+        #[cfg(feature = "async-constraints")]
+        let async_constraints = self.async_constraints.len();
+        #[cfg(not(feature = "async-constraints"))]
+        let async_constraints = 0;
+        self.constraints.len() + async_constraints + self.join_keys.len()
+    }
+
+    fn comparisons(&self) -> Vec<Comparison> {
+        self.constraints
+            .iter()
+            .flat_map(|constraint| constraint.comparisons())
+            .collect()
+    }
+
+    /// Constraints that fail to evaluate are reported as failed
+    fn explain(&self, fact: &FactHandle, token: &Match) -> Vec<ConstraintResult> {
+        self.constraints
+            .iter()
+            .map(|constraint| ConstraintResult {
+                description: constraint.description(),
+                passed: constraint.evaluate(fact, &token.context).unwrap_or(false),
+            })
+            .collect()
+    }
+
+    fn matches_bound(&self, fact: &FactHandle, token: &Match) -> Result<bool> {
+        for constraint in &self.bound_constraints {
+            let holds = constraint
+                .evaluate(fact, &token.context)
+                .map_err(|e| self.constraint_failed(constraint.description(), fact, e))?;
+            if !holds {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn bind(&self, fact: &FactHandle, token: &mut Match) -> Result<()> {
+        for (binding, scorer) in &self.score_bindings {
+            if let Some(score) = scorer.score(fact)? {
+                token.set_score(binding.clone(), score);
+            }
+        }
+        if let Some(fact) = fact.downcast_ref::<T>() {
+            for (binding, value) in &self.value_bindings {
+                token.context.set_value(binding.clone(), value(fact));
+            }
+        }
+        Ok(())
+    }
+
     fn clone_box(&self) -> Box<dyn Pattern> {
         Box::new(ObjectPattern::<T> {
             alias: self.alias.clone(),
             constraints: self.constraints.iter().map(|c| c.clone_box()).collect(),
+            #[cfg(feature = "async-constraints")]
+            async_constraints: self.async_constraints.clone(),
+            join_keys: self.join_keys.clone(),
+            score_bindings: self.score_bindings.clone(),
+            value_bindings: self.value_bindings.clone(),
+            bound_constraints: self
+                .bound_constraints
+                .iter()
+                .map(|c| c.clone_box())
+                .collect(),
+            reads: self.reads.clone(),
             _phantom: PhantomData,
         })
     }
@@ -134,10 +665,27 @@ impl Pattern for NotPattern {
         Ok(!self.pattern.matches(fact, context)?)
     }
 
+    #[cfg(feature = "async-constraints")]
+    fn matches_async<'a>(
+        &'a self,
+        fact: &'a Arc<FactHandle>,
+        context: &'a ConstraintContext,
+    ) -> PatternFuture<'a> {
+        Box::pin(async move { Ok(!self.pattern.matches_async(fact, context).await?) })
+    }
+
     fn alias(&self) -> &str {
         self.pattern.alias()
     }
 
+    fn join_keys(&self) -> &[JoinKey] {
+        self.pattern.join_keys()
+    }
+
+    fn constraint_count(&self) -> usize {
+        self.pattern.constraint_count()
+    }
+
     fn clone_box(&self) -> Box<dyn Pattern> {
         Box::new(self.clone())
     }
@@ -168,15 +716,202 @@ impl Pattern for ExistsPattern {
         self.pattern.matches(fact, context)
     }
 
+    #[cfg(feature = "async-constraints")]
+    fn matches_async<'a>(
+        &'a self,
+        fact: &'a Arc<FactHandle>,
+        context: &'a ConstraintContext,
+    ) -> PatternFuture<'a> {
+        self.pattern.matches_async(fact, context)
+    }
+
     fn alias(&self) -> &str {
         self.pattern.alias()
     }
 
+    fn join_keys(&self) -> &[JoinKey] {
+        self.pattern.join_keys()
+    }
+
+    fn constraint_count(&self) -> usize {
+        self.pattern.constraint_count()
+    }
+
+    fn clone_box(&self) -> Box<dyn Pattern> {
+        Box::new(self.clone())
+    }
+}
+
+/// Predicate of a [`TestPattern`] over a partial match
+pub type TestFn = Arc<dyn Fn(&Match) -> bool + Send + Sync>;
+
+/// A condition over the facts and values bound by earlier patterns, matching
+/// no fact of its own (CLIPS `test`, Drools `eval`)
+///
+/// Partial matches continue past it only while the predicate holds, so it
+/// can compare values across patterns:
+///
+/// ```ignore
+/// flow.rule("over_limit")
+///     .when(Box::new(ObjectPattern::<Order>::new("o").bind("total", |o| o.total)))
+///     .when(Box::new(ObjectPattern::<Account>::new("a").bind("limit", |a| a.limit)))
+///     .when(Box::new(TestPattern::new("total > limit", |m| {
+///         let amount = |name| m.value(name).and_then(Value::as_f64);
+///         amount("total") > amount("limit")
+///     })))
+///     .then(|_, _| Ok(()))?;
+/// ```
+///
+/// A test cannot be a rule's first pattern.
+#[derive(Clone)]
+pub struct TestPattern {
+    description: String,
+    test: TestFn,
+}
+
+impl TestPattern {
+    /// Create a test from a description and a predicate
+    pub fn new<F>(description: impl Into<String>, test: F) -> Self
+    where
+        F: Fn(&Match) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            description: description.into(),
+            test: Arc::new(test),
+        }
+    }
+
+    /// Get the description, such as `total > limit`
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl Debug for TestPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TestPattern")
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+impl Pattern for TestPattern {
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<TestPattern>()
+    }
+
+    /// Tests match no fact
+    fn matches(&self, _fact: &FactHandle, _context: &ConstraintContext) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn alias(&self) -> &str {
+        ""
+    }
+
+    fn constraint_count(&self) -> usize {
+        1
+    }
+
+    fn node_factory(&self) -> Option<NodeFactory> {
+        let test = Arc::clone(&self.test);
+        Some(Arc::new(move |child, position| {
+            Box::new(TestNode {
+                test: Arc::clone(&test),
+                child,
+                child_is_join: matches!(position, NodePosition::Join { last: false, .. }),
+            })
+        }))
+    }
+
     fn clone_box(&self) -> Box<dyn Pattern> {
         Box::new(self.clone())
     }
 }
 
+/// Passes on the partial matches a [`TestPattern`] holds for
+struct TestNode {
+    test: TestFn,
+    child: Box<dyn Node>,
+    /// Whether the child is a join node that also receives facts
+    child_is_join: bool,
+}
+
+impl Node for TestNode {
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if self.child_is_join {
+            return self.child.assert_fact(fact, memory);
+        }
+        Ok(Vec::new())
+    }
+
+    fn left_activate(
+        &self,
+        token: Match,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if !(self.test)(&token) {
+            return Ok(Vec::new());
+        }
+        self.child.left_activate(token, memory)
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        if self.child_is_join {
+            return self.child.retract_fact(fact, memory);
+        }
+        Ok(Vec::new())
+    }
+
+    fn patterns<'a>(&'a self, patterns: &mut Vec<(NodeId, &'a dyn Pattern)>) {
+        if self.child_is_join {
+            self.child.patterns(patterns);
+        }
+    }
+
+    fn refresh_fact(&self, fact: &Arc<FactHandle>, memory: &mut NetworkMemory) {
+        if self.child_is_join {
+            self.child.refresh_fact(fact, memory);
+        }
+    }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        self.child.retarget(rule);
+    }
+
+    fn forget(&self, memory: &mut NetworkMemory) {
+        self.child.forget(memory);
+    }
+
+    fn evict_fact(
+        &self,
+        fact: &Arc<FactHandle>,
+        alias: &str,
+        memory: &mut NetworkMemory,
+    ) -> Result<()> {
+        if self.child_is_join {
+            return self.child.evict_fact(fact, alias, memory);
+        }
+        Ok(())
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        self.child.rule_name()
+    }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        self.child.expire_windows(memory)
+    }
+}
+
 // Implement Clone for Box<dyn Pattern>
 impl Clone for Box<dyn Pattern> {
     fn clone(&self) -> Self {
@@ -205,6 +940,95 @@ mod tests {
         assert!(pattern.matches(&handle, &context).unwrap());
     }
 
+    #[test]
+    fn test_bind_values() {
+        let pattern = ObjectPattern::<TestFact>::new("t")
+            .bind("doubled", |t| t.value * 2)
+            .bind("label", |t| format!("#{}", t.value));
+
+        let mut token = Match::new();
+        Pattern::bind(
+            &pattern,
+            &FactHandle::new(TestFact { value: 21 }, 0),
+            &mut token,
+        )
+        .unwrap();
+        assert_eq!(token.value("doubled"), Some(&Value::Int(42)));
+        assert_eq!(token.context.value("label"), Some(&Value::from("#21")));
+        assert_eq!(token.value("missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_later_pattern_reads_bound_value() {
+        use crate::flow::Flow;
+
+        #[derive(Debug, Clone)]
+        struct Order {
+            total: i64,
+        }
+
+        #[derive(Debug, Clone)]
+        struct Limit {
+            max: i64,
+        }
+
+        let mut flow = Flow::new("limits");
+        flow.rule("over_limit")
+            .when(Box::new(ObjectPattern::<Order>::new("o").bind("total", |o| o.total))
+                as Box<dyn Pattern>)
+            .when(Box::new(ObjectPattern::<Limit>::new("l").with_bound_filter(
+                |l, context| matches!(context.value("total"), Some(Value::Int(total)) if *total > l.max),
+                "total > max",
+            )) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(Order { total: 150 }).unwrap();
+        session.assert(Limit { max: 100 }).unwrap();
+        session.assert(Limit { max: 200 }).unwrap();
+        session.assert(Order { total: 300 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 3);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_constraint() {
+        #[derive(Debug, Clone)]
+        struct Customer {
+            email: String,
+        }
+
+        let pattern = ObjectPattern::<Customer>::new("c")
+            .with_regex(|c| &c.email, r"@example\.com$")
+            .unwrap();
+        assert_eq!(
+            pattern.constraints[0].description(),
+            r"matches /@example\.com$/"
+        );
+
+        let context = ConstraintContext::new();
+        let customer = |email: &str| {
+            FactHandle::new(
+                Customer {
+                    email: email.to_string(),
+                },
+                0,
+            )
+        };
+        assert!(pattern
+            .matches(&customer("ann@example.com"), &context)
+            .unwrap());
+        assert!(!pattern
+            .matches(&customer("ann@example.org"), &context)
+            .unwrap());
+
+        assert!(matches!(
+            ObjectPattern::<Customer>::new("c").with_regex(|c| &c.email, "("),
+            Err(Error::InvalidConstraint(_))
+        ));
+    }
+
     #[test]
     fn test_object_pattern_type_check() {
         let pattern = ObjectPattern::<TestFact>::new("test");
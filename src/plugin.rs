@@ -0,0 +1,348 @@
+//! Sandboxed rule actions supplied as WebAssembly modules
+//!
+//! A plugin module exports its `memory` and a `run() -> i32` entry point
+//! (zero for success) and may import these host functions from `nools`:
+//!
+//! - `match_len() -> i32`: length of the match JSON
+//! - `read_match(ptr: i32)`: copy the match JSON to `ptr`
+//! - `emit_decision(ptr: i32, len: i32)`: emit a JSON decision
+//! - `assert_fact(ptr: i32, len: i32)`: assert a JSON object as a fact
+//!
+//! The match JSON is the [`Projection`] configured for the action. Decisions
+//! and facts are applied to the session only after `run` succeeds, and every
+//! invocation runs in a fresh store bounded by [`SandboxConfig`].
+
+use crate::error::{Error, Result};
+use crate::fact::FactId;
+use crate::projection::Projection;
+use crate::rule::Match;
+use crate::session::Session;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use wasmtime::{
+    Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+/// Resource limits for a plugin invocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxConfig {
+    /// Fuel available to one invocation (roughly, WASM instructions)
+    pub fuel: u64,
+    /// Maximum linear memory in bytes
+    pub max_memory_bytes: usize,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            max_memory_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A decision emitted by a plugin action
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginDecision {
+    /// Name of the plugin that emitted the decision
+    pub plugin: String,
+    /// The decision payload
+    pub payload: serde_json::Value,
+    /// Facts of the match the plugin ran for
+    pub facts: Vec<FactId>,
+}
+
+/// Per-invocation state visible to host functions
+struct HostState {
+    input: Vec<u8>,
+    decisions: Vec<serde_json::Value>,
+    facts: Vec<HashMap<String, Value>>,
+    limits: StoreLimits,
+}
+
+/// A rule action implemented by a WASM module
+#[derive(Clone)]
+pub struct WasmAction {
+    name: String,
+    engine: Engine,
+    module: Module,
+    linker: Arc<Linker<HostState>>,
+    projection: Projection,
+    config: SandboxConfig,
+}
+
+impl WasmAction {
+    /// Compile a plugin from WASM binary or text
+    pub fn new(
+        name: impl Into<String>,
+        wasm: impl AsRef<[u8]>,
+        projection: Projection,
+        config: SandboxConfig,
+    ) -> Result<Self> {
+        let name = name.into();
+        let mut engine_config = Config::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config).map_err(|e| compile_error(&name, e))?;
+        let module = Module::new(&engine, wasm).map_err(|e| compile_error(&name, e))?;
+        let linker = host_linker(&engine).map_err(|e| compile_error(&name, e))?;
+
+        Ok(Self {
+            name,
+            engine,
+            module,
+            linker: Arc::new(linker),
+            projection,
+            config,
+        })
+    }
+
+    /// Get the plugin name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run the plugin for a match and apply its effects to the session
+    pub fn run(&self, session: &mut Session, match_data: &Match) -> Result<()> {
+        let input = serde_json::to_vec(&match_data.project(&self.projection)?)
+            .map_err(|e| self.execution_error(e))?;
+        let state = HostState {
+            input,
+            decisions: Vec::new(),
+            facts: Vec::new(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.config.max_memory_bytes)
+                .build(),
+        };
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(self.config.fuel)
+            .map_err(|e| self.execution_error(e))?;
+
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .map_err(|e| self.execution_error(e))?;
+        let run = instance
+            .get_typed_func::<(), i32>(&mut store, "run")
+            .map_err(|e| self.execution_error(e))?;
+        let status = run
+            .call(&mut store, ())
+            .map_err(|e| self.execution_error(e))?;
+        if status != 0 {
+            return Err(self.execution_error(format!("run returned {}", status)));
+        }
+
+        let state = store.into_data();
+        let facts = match_data.fact_ids();
+        for payload in state.decisions {
+            session.assert(PluginDecision {
+                plugin: self.name.clone(),
+                payload,
+                facts: facts.clone(),
+            })?;
+        }
+        for fact in state.facts {
+            session.assert(fact)?;
+        }
+        Ok(())
+    }
+
+    fn execution_error(&self, error: impl std::fmt::Display) -> Error {
+        Error::Execution(format!("WASM action '{}' failed: {}", self.name, error))
+    }
+}
+
+impl std::fmt::Debug for WasmAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmAction")
+            .field("name", &self.name)
+            .field("projection", &self.projection)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+fn compile_error(name: &str, error: impl std::fmt::Display) -> Error {
+    Error::Compilation(format!("WASM action '{}': {}", name, error))
+}
+
+fn host_linker(engine: &Engine) -> wasmtime::Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap("nools", "match_len", |caller: Caller<'_, HostState>| {
+        caller.data().input.len() as i32
+    })?;
+
+    linker.func_wrap(
+        "nools",
+        "read_match",
+        |mut caller: Caller<'_, HostState>, ptr: i32| -> wasmtime::Result<()> {
+            let memory = guest_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let start = ptr as u32 as usize;
+            data.get_mut(start..start + state.input.len())
+                .ok_or_else(|| wasmtime::Error::msg("read_match out of bounds"))?
+                .copy_from_slice(&state.input);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        "nools",
+        "emit_decision",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let payload = serde_json::from_slice(&guest_bytes(&mut caller, ptr, len)?)?;
+            caller.data_mut().decisions.push(payload);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        "nools",
+        "assert_fact",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let fact = serde_json::from_slice(&guest_bytes(&mut caller, ptr, len)?)?;
+            caller.data_mut().facts.push(fact);
+            Ok(())
+        },
+    )?;
+
+    Ok(linker)
+}
+
+fn guest_memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(wasmtime::Error::msg("plugin does not export its memory")),
+    }
+}
+
+fn guest_bytes(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let start = ptr as u32 as usize;
+    let end = start + len as u32 as usize;
+    memory
+        .data(&caller)
+        .get(start..end)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmtime::Error::msg("guest buffer out of bounds"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use crate::rule::Rule;
+
+    /// Emits a fixed decision and asserts a fixed fact
+    const APPROVE: &str = r#"
+        (module
+          (import "nools" "emit_decision" (func $emit (param i32 i32)))
+          (import "nools" "assert_fact" (func $assert (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"approved\":true}")
+          (data (i32.const 32) "{\"audited\":1}")
+          (func (export "run") (result i32)
+            (call $emit (i32.const 0) (i32.const 17))
+            (call $assert (i32.const 32) (i32.const 13))
+            (i32.const 0)))"#;
+
+    /// Copies the match into memory and emits it back as its decision
+    const ECHO: &str = r#"
+        (module
+          (import "nools" "match_len" (func $len (result i32)))
+          (import "nools" "read_match" (func $read (param i32)))
+          (import "nools" "emit_decision" (func $emit (param i32 i32)))
+          (memory (export "memory") 1)
+          (func (export "run") (result i32)
+            (call $read (i32.const 0))
+            (call $emit (i32.const 0) (call $len))
+            (i32.const 0)))"#;
+
+    const SPIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "run") (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0)))"#;
+
+    async fn fire(action: WasmAction) -> Result<Session> {
+        let mut flow = Flow::new("plugins");
+        let rule = Rule::new("plugin")
+            .when(Box::new(
+                ObjectPattern::<HashMap<String, Value>>::new("r")
+                    .with_filter(|r| r.contains_key("amount"), "has amount"),
+            ) as Box<dyn Pattern>)
+            .then_wasm(action)
+            .build()?;
+        flow.add_rule(rule)?;
+
+        let mut session = flow.session();
+        let mut request = HashMap::new();
+        request.insert("amount".to_string(), Value::from(250));
+        session.assert(request)?;
+        session.match_rules().await?;
+        Ok(session)
+    }
+
+    #[tokio::test]
+    async fn test_plugin_emits_decisions_and_facts() {
+        let action = WasmAction::new(
+            "approve",
+            APPROVE,
+            Projection::new(),
+            SandboxConfig::default(),
+        )
+        .unwrap();
+        let session = fire(action).await.unwrap();
+
+        let decisions = session.get_facts::<PluginDecision>();
+        assert_eq!(decisions.len(), 1);
+        let decision = decisions[0].downcast_ref::<PluginDecision>().unwrap();
+        assert_eq!(decision.payload, serde_json::json!({ "approved": true }));
+        assert_eq!(session.get_facts::<HashMap<String, Value>>().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_reads_projected_match() {
+        let projection = Projection::new().select("r", &["amount"]);
+        let action = WasmAction::new("echo", ECHO, projection, SandboxConfig::default()).unwrap();
+        let session = fire(action).await.unwrap();
+
+        let decisions = session.get_facts::<PluginDecision>();
+        let decision = decisions[0].downcast_ref::<PluginDecision>().unwrap();
+        assert_eq!(
+            decision.payload,
+            serde_json::json!({ "r": { "amount": 250 } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plugin_runs_out_of_fuel() {
+        let config = SandboxConfig {
+            fuel: 10_000,
+            ..SandboxConfig::default()
+        };
+        let action = WasmAction::new("spin", SPIN, Projection::new(), config).unwrap();
+        assert!(fire(action).await.is_err());
+    }
+
+    #[test]
+    fn test_invalid_module() {
+        let result = WasmAction::new(
+            "bad",
+            "(module",
+            Projection::new(),
+            SandboxConfig::default(),
+        );
+        assert!(matches!(result, Err(Error::Compilation(_))));
+    }
+}
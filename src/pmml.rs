@@ -0,0 +1,855 @@
+//! PMML model import
+//!
+//! Compiles a subset of PMML 4.x (`Scorecard` and `TreeModel`) into ordinary
+//! rules on a [`Flow`]. Input facts are read through [`Fields`]; compiled rules
+//! assert [`ScorecardPoints`] and [`TreePrediction`] facts as their results.
+
+use crate::error::{Error, Result};
+use crate::fact::{Fact, FactId};
+use crate::flow::Flow;
+use crate::pattern::{ObjectPattern, Pattern};
+use crate::rule::Rule;
+use crate::session::Session;
+use crate::value::{Fields, Value};
+use roxmltree::{Document, Node};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Alias under which compiled rules bind the input fact
+pub const INPUT_ALIAS: &str = "input";
+
+/// Operator of a `SimplePredicate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimpleOperator {
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    IsMissing,
+    IsNotMissing,
+}
+
+impl SimpleOperator {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "equal" => SimpleOperator::Equal,
+            "notEqual" => SimpleOperator::NotEqual,
+            "lessThan" => SimpleOperator::LessThan,
+            "lessOrEqual" => SimpleOperator::LessOrEqual,
+            "greaterThan" => SimpleOperator::GreaterThan,
+            "greaterOrEqual" => SimpleOperator::GreaterOrEqual,
+            "isMissing" => SimpleOperator::IsMissing,
+            "isNotMissing" => SimpleOperator::IsNotMissing,
+            other => return Err(unsupported(format!("operator '{}'", other))),
+        })
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            SimpleOperator::Equal => "==",
+            SimpleOperator::NotEqual => "!=",
+            SimpleOperator::LessThan => "<",
+            SimpleOperator::LessOrEqual => "<=",
+            SimpleOperator::GreaterThan => ">",
+            SimpleOperator::GreaterOrEqual => ">=",
+            SimpleOperator::IsMissing => "is missing",
+            SimpleOperator::IsNotMissing => "is not missing",
+        }
+    }
+}
+
+/// Operator of a `CompoundPredicate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BooleanOperator {
+    And,
+    Or,
+    Xor,
+}
+
+/// A PMML predicate over the fields of an input fact
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// `<True/>`
+    True,
+    /// `<False/>`
+    False,
+    /// `<SimplePredicate>`
+    Simple {
+        field: String,
+        operator: SimpleOperator,
+        value: Option<Value>,
+    },
+    /// `<SimpleSetPredicate>`
+    Set {
+        field: String,
+        is_in: bool,
+        values: Vec<Value>,
+    },
+    /// `<CompoundPredicate>`
+    Compound {
+        operator: BooleanOperator,
+        predicates: Vec<Predicate>,
+    },
+}
+
+impl Predicate {
+    /// Evaluate this predicate; comparisons against missing fields are false
+    pub fn evaluate(&self, fields: &dyn Fields) -> bool {
+        match self {
+            Predicate::True => true,
+            Predicate::False => false,
+            Predicate::Simple {
+                field,
+                operator,
+                value,
+            } => {
+                let actual = fields.field(field).filter(|v| !v.is_null());
+                let ordering = match (&actual, value) {
+                    (Some(actual), Some(expected)) => actual.compare(expected),
+                    _ => None,
+                };
+                match operator {
+                    SimpleOperator::IsMissing => actual.is_none(),
+                    SimpleOperator::IsNotMissing => actual.is_some(),
+                    SimpleOperator::Equal => ordering == Some(Ordering::Equal),
+                    SimpleOperator::NotEqual => ordering.is_some_and(|o| o != Ordering::Equal),
+                    SimpleOperator::LessThan => ordering == Some(Ordering::Less),
+                    SimpleOperator::LessOrEqual => ordering.is_some_and(|o| o != Ordering::Greater),
+                    SimpleOperator::GreaterThan => ordering == Some(Ordering::Greater),
+                    SimpleOperator::GreaterOrEqual => ordering.is_some_and(|o| o != Ordering::Less),
+                }
+            }
+            Predicate::Set {
+                field,
+                is_in,
+                values,
+            } => match fields.field(field).filter(|v| !v.is_null()) {
+                Some(actual) => {
+                    let found = values
+                        .iter()
+                        .any(|v| actual.compare(v) == Some(Ordering::Equal));
+                    found == *is_in
+                }
+                None => false,
+            },
+            Predicate::Compound {
+                operator,
+                predicates,
+            } => match operator {
+                BooleanOperator::And => predicates.iter().all(|p| p.evaluate(fields)),
+                BooleanOperator::Or => predicates.iter().any(|p| p.evaluate(fields)),
+                BooleanOperator::Xor => {
+                    predicates.iter().filter(|p| p.evaluate(fields)).count() % 2 == 1
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::True => write!(f, "true"),
+            Predicate::False => write!(f, "false"),
+            Predicate::Simple {
+                field,
+                operator,
+                value: Some(value),
+            } => write!(f, "{} {} {}", field, operator.symbol(), value),
+            Predicate::Simple {
+                field, operator, ..
+            } => write!(f, "{} {}", field, operator.symbol()),
+            Predicate::Set {
+                field,
+                is_in,
+                values,
+            } => {
+                let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+                let op = if *is_in { "in" } else { "not in" };
+                write!(f, "{} {} [{}]", field, op, values.join(", "))
+            }
+            Predicate::Compound {
+                operator,
+                predicates,
+            } => {
+                let op = match operator {
+                    BooleanOperator::And => " and ",
+                    BooleanOperator::Or => " or ",
+                    BooleanOperator::Xor => " xor ",
+                };
+                let parts: Vec<String> = predicates.iter().map(|p| format!("({})", p)).collect();
+                write!(f, "{}", parts.join(op))
+            }
+        }
+    }
+}
+
+/// A conjunction of required predicates and rejected (earlier sibling) predicates
+#[derive(Debug, Clone, Default)]
+struct Guard {
+    require: Vec<Predicate>,
+    reject: Vec<Predicate>,
+}
+
+impl Guard {
+    fn evaluate(&self, fields: &dyn Fields) -> bool {
+        self.require.iter().all(|p| p.evaluate(fields))
+            && !self.reject.iter().any(|p| p.evaluate(fields))
+    }
+
+    fn describe(&self) -> String {
+        let mut parts: Vec<String> = self.require.iter().map(ToString::to_string).collect();
+        parts.extend(self.reject.iter().map(|p| format!("not ({})", p)));
+        parts.join(" and ")
+    }
+
+    fn pattern<T: Fact + Fields>(self) -> Box<dyn Pattern> {
+        let description = self.describe();
+        Box::new(
+            ObjectPattern::<T>::new(INPUT_ALIAS)
+                .with_filter(move |fact: &T| self.evaluate(fact), description),
+        )
+    }
+}
+
+/// Points contributed by one scorecard characteristic for an input fact
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScorecardPoints {
+    /// Name of the scorecard model
+    pub model: String,
+    /// Name of the characteristic
+    pub characteristic: String,
+    /// Reason code of the matched attribute, if any
+    pub reason_code: Option<String>,
+    /// Partial score of the matched attribute
+    pub points: f64,
+    /// The scored input fact
+    pub source: FactId,
+}
+
+/// The prediction of a decision tree for an input fact
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreePrediction {
+    /// Name of the tree model
+    pub model: String,
+    /// Score of the selected node
+    pub score: Value,
+    /// Id of the selected node, if the PMML gave one
+    pub node_id: Option<String>,
+    /// The scored input fact
+    pub source: FactId,
+}
+
+/// An attribute of a scorecard characteristic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    /// Condition selecting this attribute
+    pub predicate: Predicate,
+    /// Points contributed when selected
+    pub partial_score: f64,
+    /// Reason code, overriding the characteristic's
+    pub reason_code: Option<String>,
+}
+
+/// A scorecard characteristic; the first matching attribute contributes its score
+#[derive(Debug, Clone, PartialEq)]
+pub struct Characteristic {
+    /// Name of the characteristic
+    pub name: String,
+    /// Default reason code for its attributes
+    pub reason_code: Option<String>,
+    /// Baseline score used to rank reason codes
+    pub baseline_score: Option<f64>,
+    /// Attributes in document order
+    pub attributes: Vec<Attribute>,
+}
+
+/// A PMML `Scorecard`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scorecard {
+    /// Name of the model
+    pub name: String,
+    /// Score every input starts from
+    pub initial_score: f64,
+    /// Characteristics of the scorecard
+    pub characteristics: Vec<Characteristic>,
+}
+
+impl Scorecard {
+    /// Add one rule per characteristic attribute to a flow
+    pub fn compile<T: Fact + Fields>(&self, flow: &mut Flow) -> Result<()> {
+        for characteristic in &self.characteristics {
+            let mut earlier = Vec::new();
+            for (index, attribute) in characteristic.attributes.iter().enumerate() {
+                let guard = Guard {
+                    require: vec![attribute.predicate.clone()],
+                    reject: earlier.clone(),
+                };
+                earlier.push(attribute.predicate.clone());
+
+                let model = self.name.clone();
+                let name = characteristic.name.clone();
+                let reason_code = attribute
+                    .reason_code
+                    .clone()
+                    .or_else(|| characteristic.reason_code.clone());
+                let points = attribute.partial_score;
+                let rule = Rule::new(format!("{}::{}::{}", self.name, characteristic.name, index))
+                    .when(guard.pattern::<T>())
+                    .then(move |session, match_data| {
+                        session.assert(ScorecardPoints {
+                            model: model.clone(),
+                            characteristic: name.clone(),
+                            reason_code: reason_code.clone(),
+                            points,
+                            source: source_id(match_data)?,
+                        })?;
+                        Ok(())
+                    })
+                    .build()?;
+                flow.add_rule(rule)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Total score of an input fact once the compiled rules have fired
+    pub fn total_score(&self, session: &Session, source: impl Into<FactId>) -> f64 {
+        self.points(session, source)
+            .iter()
+            .fold(self.initial_score, |total, points| total + points.points)
+    }
+
+    /// Points asserted for an input fact by this scorecard's rules
+    pub fn points(&self, session: &Session, source: impl Into<FactId>) -> Vec<ScorecardPoints> {
+        let source = source.into();
+        session
+            .get_facts::<ScorecardPoints>()
+            .iter()
+            .filter_map(|handle| handle.downcast_ref::<ScorecardPoints>())
+            .filter(|points| points.model == self.name && points.source == source)
+            .cloned()
+            .collect()
+    }
+}
+
+/// What a tree predicts when no child of a node matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoTrueChildStrategy {
+    /// No prediction is made
+    #[default]
+    ReturnNullPrediction,
+    /// The score of the last matching node is predicted
+    ReturnLastPrediction,
+}
+
+/// A node of a PMML `TreeModel`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeNode {
+    /// Node id, if the PMML gave one
+    pub id: Option<String>,
+    /// Condition for entering this node
+    pub predicate: Predicate,
+    /// Score predicted at this node
+    pub score: Option<Value>,
+    /// Child nodes in document order
+    pub children: Vec<TreeNode>,
+}
+
+/// A PMML `TreeModel`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeModel {
+    /// Name of the model
+    pub name: String,
+    /// Behaviour when no child of a node matches
+    pub no_true_child_strategy: NoTrueChildStrategy,
+    /// Root node of the tree
+    pub root: TreeNode,
+}
+
+impl TreeModel {
+    /// Add one rule per reachable prediction to a flow
+    pub fn compile<T: Fact + Fields>(&self, flow: &mut Flow) -> Result<()> {
+        let guard = Guard {
+            require: vec![self.root.predicate.clone()],
+            reject: Vec::new(),
+        };
+        let mut rules = Vec::new();
+        self.collect_rules::<T>(&self.root, guard, "0".to_string(), &mut rules)?;
+        for rule in rules {
+            flow.add_rule(rule)?;
+        }
+        Ok(())
+    }
+
+    fn collect_rules<T: Fact + Fields>(
+        &self,
+        node: &TreeNode,
+        guard: Guard,
+        path: String,
+        rules: &mut Vec<Rule>,
+    ) -> Result<()> {
+        let predicts_here = node.children.is_empty()
+            || self.no_true_child_strategy == NoTrueChildStrategy::ReturnLastPrediction;
+        if let (true, Some(score)) = (predicts_here, &node.score) {
+            let mut leaf = guard.clone();
+            leaf.reject
+                .extend(node.children.iter().map(|child| child.predicate.clone()));
+            let model = self.name.clone();
+            let score = score.clone();
+            let node_id = node.id.clone();
+            rules.push(
+                Rule::new(format!("{}::node{}", self.name, path))
+                    .when(leaf.pattern::<T>())
+                    .then(move |session, match_data| {
+                        session.assert(TreePrediction {
+                            model: model.clone(),
+                            score: score.clone(),
+                            node_id: node_id.clone(),
+                            source: source_id(match_data)?,
+                        })?;
+                        Ok(())
+                    })
+                    .build()?,
+            );
+        }
+
+        // Children are tried in document order, so each one excludes its earlier siblings
+        let mut earlier = Vec::new();
+        for (index, child) in node.children.iter().enumerate() {
+            let mut child_guard = guard.clone();
+            child_guard.require.push(child.predicate.clone());
+            child_guard.reject.extend(earlier.iter().cloned());
+            earlier.push(child.predicate.clone());
+            self.collect_rules::<T>(child, child_guard, format!("{}.{}", path, index), rules)?;
+        }
+        Ok(())
+    }
+
+    /// Prediction asserted for an input fact by this tree's rules
+    pub fn prediction(
+        &self,
+        session: &Session,
+        source: impl Into<FactId>,
+    ) -> Option<TreePrediction> {
+        let source = source.into();
+        session
+            .get_facts::<TreePrediction>()
+            .iter()
+            .filter_map(|handle| handle.downcast_ref::<TreePrediction>())
+            .find(|prediction| prediction.model == self.name && prediction.source == source)
+            .cloned()
+    }
+}
+
+/// A model read from a PMML document
+#[derive(Debug, Clone, PartialEq)]
+pub enum PmmlModel {
+    /// A `Scorecard` model
+    Scorecard(Scorecard),
+    /// A `TreeModel` model
+    Tree(TreeModel),
+}
+
+impl PmmlModel {
+    /// Get the model name
+    pub fn name(&self) -> &str {
+        match self {
+            PmmlModel::Scorecard(scorecard) => &scorecard.name,
+            PmmlModel::Tree(tree) => &tree.name,
+        }
+    }
+
+    /// Compile this model into rules matching facts of type `T`
+    pub fn compile<T: Fact + Fields>(&self, flow: &mut Flow) -> Result<()> {
+        match self {
+            PmmlModel::Scorecard(scorecard) => scorecard.compile::<T>(flow),
+            PmmlModel::Tree(tree) => tree.compile::<T>(flow),
+        }
+    }
+}
+
+/// Parse the supported models of a PMML document
+pub fn parse(xml: &str) -> Result<Vec<PmmlModel>> {
+    let document =
+        Document::parse(xml).map_err(|e| Error::Compilation(format!("Invalid PMML: {}", e)))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "PMML" {
+        return Err(Error::Compilation(
+            "Invalid PMML: root element is not <PMML>".to_string(),
+        ));
+    }
+
+    let mut models = Vec::new();
+    for (index, element) in root.children().filter(Node::is_element).enumerate() {
+        let name = element
+            .attribute("modelName")
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("model{}", index));
+        match element.tag_name().name() {
+            "Scorecard" => models.push(PmmlModel::Scorecard(parse_scorecard(element, name)?)),
+            "TreeModel" => models.push(PmmlModel::Tree(parse_tree(element, name)?)),
+            "Header" | "DataDictionary" | "TransformationDictionary" | "Extension" => {}
+            other => return Err(unsupported(format!("model element <{}>", other))),
+        }
+    }
+    Ok(models)
+}
+
+/// Parse a PMML document and compile all of its models into a flow
+pub fn import<T: Fact + Fields>(flow: &mut Flow, xml: &str) -> Result<Vec<PmmlModel>> {
+    let models = parse(xml)?;
+    for model in &models {
+        model.compile::<T>(flow)?;
+    }
+    Ok(models)
+}
+
+fn parse_scorecard(element: Node, name: String) -> Result<Scorecard> {
+    let initial_score = optional_number(element, "initialScore")?.unwrap_or(0.0);
+    let use_reason_codes = element.attribute("useReasonCodes") != Some("false");
+
+    let mut characteristics = Vec::new();
+    for node in children(element, "Characteristics").flat_map(|c| children(c, "Characteristic")) {
+        let mut attributes = Vec::new();
+        for attribute in children(node, "Attribute") {
+            let partial_score = optional_number(attribute, "partialScore")?
+                .ok_or_else(|| unsupported("attribute without partialScore".to_string()))?;
+            attributes.push(Attribute {
+                predicate: parse_predicate_of(attribute)?,
+                partial_score,
+                reason_code: attribute
+                    .attribute("reasonCode")
+                    .filter(|_| use_reason_codes)
+                    .map(str::to_string),
+            });
+        }
+        characteristics.push(Characteristic {
+            name: node
+                .attribute("name")
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("characteristic{}", characteristics.len())),
+            reason_code: node
+                .attribute("reasonCode")
+                .filter(|_| use_reason_codes)
+                .map(str::to_string),
+            baseline_score: optional_number(node, "baselineScore")?,
+            attributes,
+        });
+    }
+
+    Ok(Scorecard {
+        name,
+        initial_score,
+        characteristics,
+    })
+}
+
+fn parse_tree(element: Node, name: String) -> Result<TreeModel> {
+    let no_true_child_strategy = match element.attribute("noTrueChildStrategy") {
+        None | Some("returnNullPrediction") => NoTrueChildStrategy::ReturnNullPrediction,
+        Some("returnLastPrediction") => NoTrueChildStrategy::ReturnLastPrediction,
+        Some(other) => return Err(unsupported(format!("noTrueChildStrategy '{}'", other))),
+    };
+    let root = children(element, "Node")
+        .next()
+        .ok_or_else(|| Error::Compilation(format!("TreeModel '{}' has no root <Node>", name)))?;
+
+    Ok(TreeModel {
+        name,
+        no_true_child_strategy,
+        root: parse_tree_node(root)?,
+    })
+}
+
+fn parse_tree_node(element: Node) -> Result<TreeNode> {
+    Ok(TreeNode {
+        id: element.attribute("id").map(str::to_string),
+        predicate: parse_predicate_of(element)?,
+        score: element.attribute("score").map(Value::parse),
+        children: children(element, "Node")
+            .map(parse_tree_node)
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Parse the predicate element nested in an `Attribute` or `Node`
+fn parse_predicate_of(element: Node) -> Result<Predicate> {
+    element
+        .children()
+        .filter(Node::is_element)
+        .find(|child| is_predicate(child.tag_name().name()))
+        .map(parse_predicate)
+        .unwrap_or_else(|| {
+            Err(Error::Compilation(format!(
+                "<{}> has no predicate",
+                element.tag_name().name()
+            )))
+        })
+}
+
+fn is_predicate(tag: &str) -> bool {
+    matches!(
+        tag,
+        "True" | "False" | "SimplePredicate" | "SimpleSetPredicate" | "CompoundPredicate"
+    )
+}
+
+fn parse_predicate(element: Node) -> Result<Predicate> {
+    match element.tag_name().name() {
+        "True" => Ok(Predicate::True),
+        "False" => Ok(Predicate::False),
+        "SimplePredicate" => Ok(Predicate::Simple {
+            field: required(element, "field")?.to_string(),
+            operator: SimpleOperator::parse(required(element, "operator")?)?,
+            value: element.attribute("value").map(Value::parse),
+        }),
+        "SimpleSetPredicate" => {
+            let is_in = match required(element, "booleanOperator")? {
+                "isIn" => true,
+                "isNotIn" => false,
+                other => return Err(unsupported(format!("set operator '{}'", other))),
+            };
+            let array = children(element, "Array").next().ok_or_else(|| {
+                Error::Compilation("<SimpleSetPredicate> has no <Array>".to_string())
+            })?;
+            Ok(Predicate::Set {
+                field: required(element, "field")?.to_string(),
+                is_in,
+                values: parse_array(array.text().unwrap_or_default()),
+            })
+        }
+        "CompoundPredicate" => {
+            let operator = match required(element, "booleanOperator")? {
+                "and" => BooleanOperator::And,
+                "or" => BooleanOperator::Or,
+                "xor" => BooleanOperator::Xor,
+                other => return Err(unsupported(format!("compound operator '{}'", other))),
+            };
+            let predicates = element
+                .children()
+                .filter(|child| child.is_element() && is_predicate(child.tag_name().name()))
+                .map(parse_predicate)
+                .collect::<Result<_>>()?;
+            Ok(Predicate::Compound {
+                operator,
+                predicates,
+            })
+        }
+        other => Err(unsupported(format!("predicate <{}>", other))),
+    }
+}
+
+/// Split the contents of an `<Array>`: whitespace separated, optionally double quoted
+fn parse_array(text: &str) -> Vec<Value> {
+    let mut values = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut item = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => item.extend(chars.next()),
+                    '"' => break,
+                    c => item.push(c),
+                }
+            }
+            values.push(Value::String(item));
+        } else {
+            let mut item = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                item.push(c);
+                chars.next();
+            }
+            values.push(Value::parse(&item));
+        }
+    }
+    values
+}
+
+fn children<'a, 'input>(
+    element: Node<'a, 'input>,
+    tag: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    element
+        .children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == tag)
+}
+
+fn required<'a>(element: Node<'a, '_>, name: &str) -> Result<&'a str> {
+    element.attribute(name).ok_or_else(|| {
+        Error::Compilation(format!(
+            "<{}> is missing attribute '{}'",
+            element.tag_name().name(),
+            name
+        ))
+    })
+}
+
+fn optional_number(element: Node, name: &str) -> Result<Option<f64>> {
+    element
+        .attribute(name)
+        .map(|text| {
+            text.trim().parse::<f64>().map_err(|_| {
+                Error::Compilation(format!("Attribute '{}' is not a number: {}", name, text))
+            })
+        })
+        .transpose()
+}
+
+fn unsupported(what: String) -> Error {
+    Error::Compilation(format!("Unsupported PMML {}", what))
+}
+
+fn source_id(match_data: &crate::rule::Match) -> Result<FactId> {
+    match_data
+        .get(INPUT_ALIAS)
+        .map(|handle| handle.id)
+        .ok_or_else(|| Error::Execution(format!("Match has no '{}' fact", INPUT_ALIAS)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SCORECARD: &str = r#"
+        <PMML version="4.4" xmlns="http://www.dmg.org/PMML-4_4">
+          <Header/>
+          <Scorecard modelName="credit" functionName="regression" initialScore="100">
+            <Characteristics>
+              <Characteristic name="age" reasonCode="RC_AGE">
+                <Attribute partialScore="-5"><SimplePredicate field="age" operator="isMissing"/></Attribute>
+                <Attribute partialScore="10"><SimplePredicate field="age" operator="lessThan" value="30"/></Attribute>
+                <Attribute partialScore="20"><True/></Attribute>
+              </Characteristic>
+              <Characteristic name="status">
+                <Attribute partialScore="15" reasonCode="RC_STATUS">
+                  <SimpleSetPredicate field="status" booleanOperator="isIn">
+                    <Array n="2" type="string">"home owner" renter</Array>
+                  </SimpleSetPredicate>
+                </Attribute>
+              </Characteristic>
+            </Characteristics>
+          </Scorecard>
+        </PMML>"#;
+
+    const TREE: &str = r#"
+        <PMML version="4.4">
+          <TreeModel modelName="golf" functionName="classification">
+            <Node score="play"><True/>
+              <Node score="no play">
+                <CompoundPredicate booleanOperator="and">
+                  <SimplePredicate field="outlook" operator="equal" value="sunny"/>
+                  <SimplePredicate field="humidity" operator="greaterThan" value="70"/>
+                </CompoundPredicate>
+              </Node>
+              <Node score="play"><SimplePredicate field="outlook" operator="equal" value="sunny"/></Node>
+              <Node score="may play"><True/></Node>
+            </Node>
+          </TreeModel>
+        </PMML>"#;
+
+    fn record(fields: &[(&str, Value)]) -> HashMap<String, Value> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_scorecard() {
+        let models = parse(SCORECARD).unwrap();
+        let PmmlModel::Scorecard(scorecard) = &models[0] else {
+            panic!("expected scorecard");
+        };
+        assert_eq!(scorecard.name, "credit");
+        assert_eq!(scorecard.initial_score, 100.0);
+        assert_eq!(scorecard.characteristics.len(), 2);
+        assert_eq!(
+            scorecard.characteristics[1].attributes[0].predicate,
+            Predicate::Set {
+                field: "status".to_string(),
+                is_in: true,
+                values: vec![Value::from("home owner"), Value::from("renter")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_unsupported_model_error() {
+        let xml = r#"<PMML version="4.4"><NeuralNetwork modelName="nn"/></PMML>"#;
+        assert!(parse(xml).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scorecard_rules() {
+        let mut flow = Flow::new("pmml");
+        let models = import::<HashMap<String, Value>>(&mut flow, SCORECARD).unwrap();
+        let PmmlModel::Scorecard(scorecard) = &models[0] else {
+            panic!("expected scorecard");
+        };
+
+        let mut session = flow.session();
+        let young = session
+            .assert(record(&[
+                ("age", Value::from(25)),
+                ("status", Value::from("renter")),
+            ]))
+            .unwrap();
+        let unknown = session.assert(record(&[])).unwrap();
+        session.match_rules().await.unwrap();
+
+        // The first matching attribute of each characteristic wins
+        assert_eq!(scorecard.total_score(&session, young), 125.0);
+        assert_eq!(scorecard.total_score(&session, unknown), 95.0);
+
+        let mut codes: Vec<_> = scorecard
+            .points(&session, young)
+            .into_iter()
+            .filter_map(|points| points.reason_code)
+            .collect();
+        codes.sort();
+        assert_eq!(codes, vec!["RC_AGE", "RC_STATUS"]);
+    }
+
+    #[tokio::test]
+    async fn test_tree_rules() {
+        let mut flow = Flow::new("pmml");
+        let models = import::<HashMap<String, Value>>(&mut flow, TREE).unwrap();
+        let PmmlModel::Tree(tree) = &models[0] else {
+            panic!("expected tree");
+        };
+
+        let mut session = flow.session();
+        let humid = session
+            .assert(record(&[
+                ("outlook", Value::from("sunny")),
+                ("humidity", Value::from(80)),
+            ]))
+            .unwrap();
+        let dry = session
+            .assert(record(&[
+                ("outlook", Value::from("sunny")),
+                ("humidity", Value::from(40)),
+            ]))
+            .unwrap();
+        let rainy = session
+            .assert(record(&[("outlook", Value::from("rain"))]))
+            .unwrap();
+        session.match_rules().await.unwrap();
+
+        let score = |id| tree.prediction(&session, id).map(|p| p.score);
+        assert_eq!(score(humid), Some(Value::from("no play")));
+        assert_eq!(score(dry), Some(Value::from("play")));
+        assert_eq!(score(rainy), Some(Value::from("may play")));
+    }
+}
@@ -0,0 +1,264 @@
+//! Recycling of the allocations behind short-lived facts and activations
+//!
+//! Asserting, firing and retracting millions of facts allocates a
+//! [`FactHandle`] per fact and an [`Activation`] with the maps of its
+//! [`Match`] per rule firing. With the `pool` feature, a session keeps the
+//! activations it fired and the fact handles working memory no longer needs,
+//! once nothing else holds them, and builds new ones in their allocations.
+//!
+//! Pools are bounded: beyond [`POOL_CAPACITY`] entries, released allocations
+//! are freed as usual. [`Session::pool_stats`] reports how often allocations
+//! were reused.
+//!
+//! [`Session::pool_stats`]: crate::session::Session::pool_stats
+
+use crate::fact::{Fact, FactHandle};
+use crate::rule::{Activation, Match, Rule};
+use std::sync::Arc;
+
+/// Maximum number of allocations each pool keeps for reuse
+pub const POOL_CAPACITY: usize = 1024;
+
+/// Counts of allocations served by a pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Allocations built in a recycled allocation
+    pub reused: u64,
+    /// Allocations made because the pool was empty
+    pub allocated: u64,
+    /// Allocations waiting in the pool
+    pub pooled: usize,
+}
+
+impl PoolStats {
+    /// Sum the counts of two pools
+    pub fn merge(self, other: PoolStats) -> PoolStats {
+        PoolStats {
+            reused: self.reused + other.reused,
+            allocated: self.allocated + other.allocated,
+            pooled: self.pooled + other.pooled,
+        }
+    }
+}
+
+/// Pool of activations and matches, kept by a session's network memory
+#[derive(Debug, Default)]
+pub struct ActivationPool {
+    /// Fired activations no one else holds, with cleared matches
+    activations: Vec<Arc<Activation>>,
+    /// Cleared matches keeping their maps' capacity
+    matches: Vec<Match>,
+    reused: u64,
+    allocated: u64,
+}
+
+impl ActivationPool {
+    /// Create an empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take an empty match, reusing the maps of a recycled one
+    pub fn new_match(&mut self) -> Match {
+        self.matches.pop().unwrap_or_default()
+    }
+
+    /// Build an activation, reusing the allocation of a recycled one
+    pub fn activation(
+        &mut self,
+        rule: Arc<Rule>,
+        match_data: Match,
+        recency: u64,
+    ) -> Arc<Activation> {
+        match self.activations.pop() {
+            Some(mut activation) => {
+                self.reused += 1;
+                // Recycled activations are only pooled while unshared
+                let reused = Arc::get_mut(&mut activation).expect("pooled activation is unshared");
+                let previous = reused.reset(rule, match_data, recency);
+                self.keep_match(previous);
+                activation
+            }
+            None => {
+                self.allocated += 1;
+                Arc::new(Activation::new(rule, match_data, recency))
+            }
+        }
+    }
+
+    /// Return an activation to the pool, if nothing else holds it anymore
+    pub fn recycle(&mut self, mut activation: Arc<Activation>) {
+        if self.activations.len() >= POOL_CAPACITY {
+            return;
+        }
+        if let Some(unshared) = Arc::get_mut(&mut activation) {
+            // Release the facts now rather than when the allocation is reused
+            unshared.match_data.clear();
+            self.activations.push(activation);
+        }
+    }
+
+    /// Return a match no longer needed to the pool
+    fn keep_match(&mut self, mut match_data: Match) {
+        if self.matches.len() < POOL_CAPACITY {
+            match_data.clear();
+            self.matches.push(match_data);
+        }
+    }
+
+    /// Get the pool's counts
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            reused: self.reused,
+            allocated: self.allocated,
+            pooled: self.activations.len(),
+        }
+    }
+}
+
+/// Pool of fact handles, kept by working memory
+#[derive(Debug)]
+pub struct FactPool {
+    /// Forgotten handles no one else holds, pointing at `placeholder`
+    handles: Vec<Arc<FactHandle>>,
+    /// Fact pooled handles point at, so they don't keep user data alive
+    placeholder: Arc<dyn Fact>,
+    reused: u64,
+    allocated: u64,
+}
+
+impl Default for FactPool {
+    fn default() -> Self {
+        Self {
+            handles: Vec::new(),
+            placeholder: Arc::new(()),
+            reused: 0,
+            allocated: 0,
+        }
+    }
+}
+
+impl FactPool {
+    /// Create an empty pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Share a fact handle, reusing the allocation of a recycled one
+    pub fn handle(&mut self, handle: FactHandle) -> Arc<FactHandle> {
+        match self.handles.pop() {
+            Some(mut pooled) => {
+                self.reused += 1;
+                *Arc::get_mut(&mut pooled).expect("pooled fact handle is unshared") = handle;
+                pooled
+            }
+            None => {
+                self.allocated += 1;
+                Arc::new(handle)
+            }
+        }
+    }
+
+    /// Return a fact handle to the pool, if nothing else holds it anymore
+    pub fn recycle(&mut self, mut handle: Arc<FactHandle>) {
+        if self.handles.len() >= POOL_CAPACITY {
+            return;
+        }
+        if let Some(unshared) = Arc::get_mut(&mut handle) {
+            unshared.fact = Arc::clone(&self.placeholder);
+            self.handles.push(handle);
+        }
+    }
+
+    /// Get the pool's counts
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            reused: self.reused,
+            allocated: self.allocated,
+            pooled: self.handles.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::pattern::{ObjectPattern, Pattern};
+
+    fn rule() -> Arc<Rule> {
+        Arc::new(Rule::new("r").then(|_, _| Ok(())).build().unwrap())
+    }
+
+    #[test]
+    fn test_activation_pool_reuses_unshared_activations() {
+        let mut pool = ActivationPool::new();
+        let mut match_data = pool.new_match();
+        match_data.insert("n".to_string(), Arc::new(FactHandle::new(1u32, 0)));
+        let first = pool.activation(rule(), match_data, 0);
+        let address = Arc::as_ptr(&first);
+
+        let shared = Arc::clone(&first);
+        pool.recycle(first);
+        assert_eq!(pool.stats().pooled, 0);
+
+        pool.recycle(shared);
+        assert_eq!(pool.stats().pooled, 1);
+
+        let match_data = pool_match(&mut pool, 2);
+        let second = pool.activation(rule(), match_data, 7);
+        assert_eq!(Arc::as_ptr(&second), address);
+        assert_eq!(second.recency, 7);
+        assert_eq!(second.match_data.get_as::<u32>("n").unwrap(), &2);
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                reused: 1,
+                allocated: 1,
+                pooled: 0
+            }
+        );
+        // The first activation's match is kept for the next one
+        assert!(pool.new_match().facts.capacity() > 0);
+    }
+
+    fn pool_match(pool: &mut ActivationPool, n: u32) -> Match {
+        let mut match_data = pool.new_match();
+        match_data.insert("n".to_string(), Arc::new(FactHandle::new(n, 0)));
+        match_data
+    }
+
+    #[test]
+    fn test_fact_pool_releases_facts_of_pooled_handles() {
+        let mut pool = FactPool::new();
+        let fact = Arc::new(String::from("data"));
+        let handle = pool.handle(FactHandle::new(Arc::clone(&fact), 0));
+        assert_eq!(Arc::strong_count(&fact), 2);
+
+        pool.recycle(handle);
+        assert_eq!(Arc::strong_count(&fact), 1);
+
+        let reused = pool.handle(FactHandle::new(3u8, 1));
+        assert_eq!(reused.downcast_ref::<u8>(), Some(&3));
+        assert_eq!(pool.stats().reused, 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_reuses_allocations_of_fired_and_retracted_facts() {
+        let mut flow = Flow::new("pool");
+        flow.rule("count")
+            .when(Box::new(ObjectPattern::<u32>::new("n")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        let mut session = flow.session();
+
+        for n in 0..10u32 {
+            let handle = session.assert(n).unwrap();
+            session.match_rules().await.unwrap();
+            session.retract(handle).unwrap();
+        }
+
+        let stats = session.pool_stats();
+        assert!(stats.reused >= 18, "{:?}", stats);
+    }
+}
@@ -0,0 +1,257 @@
+//! Projections of matched facts into lean, serializable maps
+//!
+//! A projection selects fields of the facts bound in a [`Match`] with a
+//! GraphQL-like spec such as `c { id name } o { orderTotal: total }`. Fields
+//! are read through [`crate::value::register_fields`].
+
+use crate::error::{Error, Result};
+use crate::rule::Match;
+use crate::value::Value;
+use std::collections::BTreeMap;
+
+/// Projected fields by alias, then by output name
+pub type Projected = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// A field selected from a bound fact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSelection {
+    /// Name of the field on the fact
+    pub field: String,
+    /// Name of the field in the output
+    pub output: String,
+}
+
+/// The fields selected from one alias
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasSelection {
+    /// Alias of the bound fact
+    pub alias: String,
+    /// Selected fields
+    pub fields: Vec<FieldSelection>,
+}
+
+/// A projection spec
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Projection {
+    selections: Vec<AliasSelection>,
+}
+
+impl Projection {
+    /// Create an empty projection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select fields of an alias under their own names
+    pub fn select(mut self, alias: impl Into<String>, fields: &[&str]) -> Self {
+        self.selections.push(AliasSelection {
+            alias: alias.into(),
+            fields: fields
+                .iter()
+                .map(|field| FieldSelection {
+                    field: field.to_string(),
+                    output: field.to_string(),
+                })
+                .collect(),
+        });
+        self
+    }
+
+    /// Parse a spec like `c { id name } o { orderTotal: total }`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut tokens = tokenize(spec).into_iter().peekable();
+        let mut selections = Vec::new();
+
+        while let Some(alias) = tokens.next() {
+            if !is_name(alias) {
+                return Err(invalid(
+                    spec,
+                    format!("expected an alias, found '{}'", alias),
+                ));
+            }
+            if tokens.next() != Some("{") {
+                return Err(invalid(spec, format!("expected '{{' after '{}'", alias)));
+            }
+
+            let mut fields = Vec::new();
+            loop {
+                let name = match tokens.next() {
+                    Some("}") => break,
+                    Some(name) if is_name(name) => name,
+                    Some(other) => {
+                        return Err(invalid(spec, format!("unexpected '{}'", other)));
+                    }
+                    None => return Err(invalid(spec, format!("unclosed '{}' selection", alias))),
+                };
+                let selection = if tokens.peek() == Some(&":") {
+                    tokens.next();
+                    match tokens.next() {
+                        Some(field) if is_name(field) => FieldSelection {
+                            field: field.to_string(),
+                            output: name.to_string(),
+                        },
+                        _ => {
+                            return Err(invalid(
+                                spec,
+                                format!("expected a field after '{}:'", name),
+                            ))
+                        }
+                    }
+                } else {
+                    FieldSelection {
+                        field: name.to_string(),
+                        output: name.to_string(),
+                    }
+                };
+                fields.push(selection);
+            }
+
+            selections.push(AliasSelection {
+                alias: alias.to_string(),
+                fields,
+            });
+        }
+
+        Ok(Self { selections })
+    }
+
+    /// Get the alias selections
+    pub fn selections(&self) -> &[AliasSelection] {
+        &self.selections
+    }
+
+    /// Apply this projection to a match
+    pub fn apply(&self, match_data: &Match) -> Result<Projected> {
+        let mut projected = Projected::new();
+        for selection in &self.selections {
+            let fact = match_data.get(&selection.alias).ok_or_else(|| {
+                Error::PatternMatch(format!(
+                    "Projection alias '{}' is not bound",
+                    selection.alias
+                ))
+            })?;
+
+            let mut fields = BTreeMap::new();
+            for field in &selection.fields {
+                let value = fact.field(&field.field).ok_or_else(|| {
+                    Error::PatternMatch(format!(
+                        "Fact '{}' ({}) has no field '{}'",
+                        selection.alias,
+                        fact.type_name(),
+                        field.field
+                    ))
+                })?;
+                fields.insert(field.output.clone(), value);
+            }
+            projected.insert(selection.alias.clone(), fields);
+        }
+        Ok(projected)
+    }
+}
+
+fn tokenize(spec: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in spec.char_indices() {
+        let is_punct = matches!(c, '{' | '}' | ':');
+        if c.is_whitespace() || c == ',' || is_punct {
+            if let Some(begin) = start.take() {
+                tokens.push(&spec[begin..index]);
+            }
+            if is_punct {
+                tokens.push(&spec[index..index + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(index);
+        }
+    }
+    if let Some(begin) = start {
+        tokens.push(&spec[begin..]);
+    }
+    tokens
+}
+
+fn is_name(token: &str) -> bool {
+    token
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+fn invalid(spec: &str, reason: String) -> Error {
+    Error::Compilation(format!("Invalid projection '{}': {}", spec, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fact::FactHandle;
+    use crate::value::{register_fields, Fields};
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone)]
+    struct Customer {
+        id: i64,
+        name: String,
+    }
+
+    impl Fields for Customer {
+        fn field(&self, name: &str) -> Option<Value> {
+            match name {
+                "id" => Some(self.id.into()),
+                "name" => Some(self.name.clone().into()),
+                _ => None,
+            }
+        }
+    }
+
+    fn customer_match() -> Match {
+        register_fields::<Customer>();
+        let mut match_data = Match::new();
+        let customer = Customer {
+            id: 7,
+            name: "Ada".to_string(),
+        };
+        match_data.insert("c".to_string(), Arc::new(FactHandle::new(customer, 0)));
+        match_data
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let projection = Projection::parse("c { id, customerName: name }").unwrap();
+        assert_eq!(projection.selections().len(), 1);
+        assert_eq!(
+            projection.selections()[0].fields[1],
+            FieldSelection {
+                field: "name".to_string(),
+                output: "customerName".to_string(),
+            }
+        );
+        assert!(Projection::parse("c { id").is_err());
+        assert!(Projection::parse("c id").is_err());
+    }
+
+    #[test]
+    fn test_apply_projection() {
+        let projected = customer_match()
+            .project(&Projection::parse("c { id customerName: name }").unwrap())
+            .unwrap();
+
+        assert_eq!(projected["c"]["id"], Value::Int(7));
+        assert_eq!(projected["c"]["customerName"], Value::from("Ada"));
+        assert_eq!(
+            serde_json::to_string(&projected).unwrap(),
+            r#"{"c":{"customerName":"Ada","id":7}}"#
+        );
+    }
+
+    #[test]
+    fn test_unknown_field_or_alias() {
+        let match_data = customer_match();
+        assert!(match_data
+            .project(&Projection::new().select("c", &["email"]))
+            .is_err());
+        assert!(match_data
+            .project(&Projection::new().select("o", &["id"]))
+            .is_err());
+    }
+}
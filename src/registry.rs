@@ -0,0 +1,168 @@
+//! Flows registered by name
+//!
+//! A [`FlowRegistry`] is the counterpart of nools' `flow()`, `getFlow()`,
+//! `hasFlow()` and `deleteFlow()`: applications register compiled flows once
+//! and create sessions from them by name. [`FlowRegistry::global`] is shared
+//! by the whole process; separate registries can be created for isolation.
+//!
+//! ```ignore
+//! use nools::registry::FlowRegistry;
+//!
+//! FlowRegistry::global().flow("orders", |flow| flow.add_rule(rule))?;
+//!
+//! // Anywhere else
+//! let mut session = FlowRegistry::global().session("orders")?;
+//! ```
+
+use crate::error::{Error, Result};
+use crate::flow::Flow;
+use crate::session::Session;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Flows by name
+#[derive(Debug, Default)]
+pub struct FlowRegistry {
+    flows: RwLock<HashMap<String, Arc<Flow>>>,
+}
+
+impl FlowRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the registry shared by the whole process
+    pub fn global() -> &'static FlowRegistry {
+        static GLOBAL: OnceLock<FlowRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(FlowRegistry::new)
+    }
+
+    /// Define a flow and register it, like `nools.flow(name, definer)`
+    ///
+    /// Nothing is registered if `define` fails.
+    pub fn flow<F>(&self, name: impl Into<String>, define: F) -> Result<Arc<Flow>>
+    where
+        F: FnOnce(&mut Flow) -> Result<()>,
+    {
+        let mut flow = Flow::new(name);
+        define(&mut flow)?;
+        Ok(self.register(flow))
+    }
+
+    /// Register a flow under its name, replacing any flow of the same name
+    pub fn register(&self, flow: Flow) -> Arc<Flow> {
+        let flow = Arc::new(flow);
+        self.write()
+            .insert(flow.name().to_string(), Arc::clone(&flow));
+        flow
+    }
+
+    /// Get a flow by name
+    pub fn get_flow(&self, name: &str) -> Option<Arc<Flow>> {
+        self.read().get(name).cloned()
+    }
+
+    /// Check if a flow is registered
+    pub fn has_flow(&self, name: &str) -> bool {
+        self.read().contains_key(name)
+    }
+
+    /// Unregister a flow, returning it
+    ///
+    /// Sessions already created from the flow keep working.
+    pub fn delete_flow(&self, name: &str) -> Option<Arc<Flow>> {
+        self.write().remove(name)
+    }
+
+    /// Unregister every flow
+    pub fn delete_flows(&self) {
+        self.write().clear();
+    }
+
+    /// Get the names of the registered flows, sorted
+    pub fn flow_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.read().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Create a session of a registered flow
+    pub fn session(&self, name: &str) -> Result<Session> {
+        self.get_flow(name)
+            .ok_or_else(|| Error::FlowNotFound(name.to_string()))?
+            .try_session()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, Arc<Flow>>> {
+        self.flows.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, Arc<Flow>>> {
+        self.flows.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::ObjectPattern;
+    use crate::rule::Rule;
+
+    #[derive(Debug, Clone)]
+    struct Order {
+        total: u32,
+    }
+
+    fn define(flow: &mut Flow) -> Result<()> {
+        flow.add_rule(
+            Rule::new("large")
+                .when(Box::new(
+                    ObjectPattern::<Order>::new("o").with_filter(|o| o.total > 100, "total > 100"),
+                ))
+                .then(|_, _| Ok(()))
+                .build()?,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_flow_registry() {
+        let registry = FlowRegistry::new();
+        let flow = registry.flow("orders", define).unwrap();
+        assert!(registry.has_flow("orders"));
+        assert!(Arc::ptr_eq(&registry.get_flow("orders").unwrap(), &flow));
+        assert!(registry
+            .flow("broken", |_| Err(Error::custom("invalid")))
+            .is_err());
+        assert_eq!(registry.flow_names(), ["orders"]);
+
+        let mut session = registry.session("orders").unwrap();
+        session.assert(Order { total: 250 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        let replaced = registry.register(Flow::new("orders"));
+        assert!(Arc::ptr_eq(
+            &registry.get_flow("orders").unwrap(),
+            &replaced
+        ));
+        assert!(registry.delete_flow("orders").is_some());
+        assert!(registry.delete_flow("orders").is_none());
+        assert!(matches!(
+            registry.session("orders"),
+            Err(Error::FlowNotFound(_))
+        ));
+
+        registry.register(Flow::new("a"));
+        registry.delete_flows();
+        assert!(registry.flow_names().is_empty());
+    }
+
+    #[test]
+    fn test_global_registry() {
+        FlowRegistry::global().register(Flow::new("registry::global"));
+        assert!(FlowRegistry::global().has_flow("registry::global"));
+        assert!(FlowRegistry::global()
+            .delete_flow("registry::global")
+            .is_some());
+    }
+}
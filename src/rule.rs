@@ -1,20 +1,76 @@
 //! Rule definitions and execution
 
+use crate::clock::Timer;
 use crate::constraint::ConstraintContext;
-use crate::error::Result;
-use crate::fact::FactHandle;
+use crate::error::{Error, Result};
+use crate::explain::{Explanation, PatternExplanation};
+use crate::fact::{Fact, FactHandle, FactId};
+use crate::flow::FlowEnv;
 use crate::pattern::Pattern;
+use crate::projection::{Projected, Projection};
 use crate::session::Session;
+use crate::value::Value;
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 /// Priority type for rules
 pub type Priority = i32;
 
+/// Priority computed from the matched facts
+pub type SalienceFn = Arc<dyn Fn(&Match) -> Priority + Send + Sync>;
+
 /// Action to execute when a rule fires
 pub type RuleAction = Arc<dyn Fn(&mut Session, &Match) -> Result<()> + Send + Sync>;
 
+/// Action computing a rule's changes from its match alone
+pub type ParallelAction = Arc<dyn Fn(&Match, &mut Effects) -> Result<()> + Send + Sync>;
+
+/// Condition on the deployment environment for a rule to be part of a flow
+pub type EnabledIf = Arc<dyn Fn(&FlowEnv) -> bool + Send + Sync>;
+
+/// Key computed from the matched facts
+pub type ConcurrencyKeyFn = Arc<dyn Fn(&Match) -> String + Send + Sync>;
+
+/// Which activations of a rule may fire simultaneously with others
+#[derive(Clone, Default)]
+pub enum Concurrency {
+    /// Fires alone
+    #[default]
+    Exclusive,
+    /// May fire alongside any activation that is not exclusive
+    Independent,
+    /// May fire alongside activations whose key differs, across all rules
+    Keyed(ConcurrencyKeyFn),
+}
+
+impl Debug for Concurrency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Concurrency::Exclusive => f.write_str("Exclusive"),
+            Concurrency::Independent => f.write_str("Independent"),
+            Concurrency::Keyed(_) => f.write_str("Keyed"),
+        }
+    }
+}
+
+/// What a session does when a rule's action returns an error or panics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop matching and return the error
+    #[default]
+    Halt,
+    /// Drop the failed activation and keep firing the rest of the agenda
+    SkipRule,
+    /// Run the action again up to `attempts` more times, then halt
+    ///
+    /// Changes made by a failed attempt are not undone before the next one.
+    Retry {
+        /// Number of additional runs
+        attempts: usize,
+    },
+}
+
 /// A match of facts that satisfy a rule's patterns
 #[derive(Debug, Clone)]
 pub struct Match {
@@ -22,6 +78,8 @@ pub struct Match {
     pub facts: HashMap<String, Arc<FactHandle>>,
     /// Constraint context with bindings
     pub context: ConstraintContext,
+    /// Model scores bound by patterns, by binding name
+    pub scores: HashMap<String, f64>,
 }
 
 impl Match {
@@ -30,6 +88,7 @@ impl Match {
         Self {
             facts: HashMap::new(),
             context: ConstraintContext::new(),
+            scores: HashMap::new(),
         }
     }
 
@@ -38,11 +97,77 @@ impl Match {
         self.facts.get(alias)
     }
 
+    /// Get the fact bound to an alias as a concrete type
+    pub fn get_as<T: Fact>(&self, alias: &str) -> Result<&T> {
+        let fact = self
+            .get(alias)
+            .ok_or_else(|| Error::PatternMatch(format!("Alias '{}' is not bound", alias)))?;
+        fact.downcast_ref::<T>().ok_or_else(|| {
+            Error::PatternMatch(format!(
+                "Fact '{}' is a {}, not a {}",
+                alias,
+                fact.type_name(),
+                std::any::type_name::<T>()
+            ))
+        })
+    }
+
     /// Add a fact to this match
     pub fn insert(&mut self, alias: String, fact: Arc<FactHandle>) {
         self.context.set(alias.clone(), Arc::clone(&fact));
         self.facts.insert(alias, fact);
     }
+
+    /// Replace the handle of a bound fact with a newer version of the same fact
+    pub(crate) fn replace_fact(&mut self, fact: &Arc<FactHandle>) {
+        for bound in self
+            .facts
+            .values_mut()
+            .chain(self.context.bindings.values_mut())
+        {
+            if bound.id == fact.id {
+                *bound = Arc::clone(fact);
+            }
+        }
+    }
+
+    /// Get the ids of the matched facts, ordered by alias
+    pub fn fact_ids(&self) -> Vec<FactId> {
+        let mut facts: Vec<_> = self.facts.iter().collect();
+        facts.sort_by(|a, b| a.0.cmp(b.0));
+        facts.into_iter().map(|(_, fact)| fact.id).collect()
+    }
+
+    /// Extract the fields selected by a projection from the bound facts
+    pub fn project(&self, projection: &Projection) -> Result<Projected> {
+        projection.apply(self)
+    }
+
+    /// Get a bound model score by name
+    pub fn score(&self, name: &str) -> Option<f64> {
+        self.scores.get(name).copied()
+    }
+
+    /// Bind a model score to this match
+    pub fn set_score(&mut self, name: String, score: f64) {
+        self.scores.insert(name, score);
+    }
+
+    /// Get a value bound by a pattern, see [`ObjectPattern::bind`]
+    ///
+    /// [`ObjectPattern::bind`]: crate::pattern::ObjectPattern::bind
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.context.value(name)
+    }
+
+    /// Unbind everything, keeping the maps' capacity
+    #[cfg(feature = "pool")]
+    pub(crate) fn clear(&mut self) {
+        self.facts.clear();
+        self.context.bindings.clear();
+        self.context.values.clear();
+        self.scores.clear();
+    }
 }
 
 impl Default for Match {
@@ -60,6 +185,12 @@ pub struct Activation {
     pub match_data: Match,
     /// Recency for conflict resolution
     pub recency: u64,
+    /// Salience, computed on first use
+    salience: OnceLock<Priority>,
+    /// Working memory generation when the activation entered the agenda
+    generation: OnceLock<u64>,
+    /// Explanation recorded when the activation entered the agenda
+    explanation: OnceLock<Explanation>,
 }
 
 impl Activation {
@@ -69,12 +200,160 @@ impl Activation {
             rule,
             match_data,
             recency,
+            salience: OnceLock::new(),
+            generation: OnceLock::new(),
+            explanation: OnceLock::new(),
+        }
+    }
+
+    /// Create an activation whose salience is fixed instead of computed from its rule
+    pub fn with_salience(
+        rule: Arc<Rule>,
+        match_data: Match,
+        recency: u64,
+        salience: Priority,
+    ) -> Self {
+        Self {
+            rule,
+            match_data,
+            recency,
+            salience: OnceLock::from(salience),
+            generation: OnceLock::new(),
+            explanation: OnceLock::new(),
+        }
+    }
+
+    /// Turn this activation into a new one, returning the previous match
+    #[cfg(feature = "pool")]
+    pub(crate) fn reset(&mut self, rule: Arc<Rule>, match_data: Match, recency: u64) -> Match {
+        self.rule = rule;
+        self.recency = recency;
+        self.salience = OnceLock::new();
+        self.generation = OnceLock::new();
+        self.explanation = OnceLock::new();
+        std::mem::replace(&mut self.match_data, match_data)
+    }
+
+    /// Rebuild this activation around another match, recency and salience,
+    /// keeping its generation and recorded explanation
+    pub(crate) fn rebuild(&self, match_data: Match, recency: u64, salience: Priority) -> Self {
+        Self {
+            rule: Arc::clone(&self.rule),
+            match_data,
+            recency,
+            salience: OnceLock::from(salience),
+            generation: self.generation.clone(),
+            explanation: self.explanation.clone(),
         }
     }
 
     /// Calculate salience for this activation
     pub fn salience(&self) -> Priority {
-        self.rule.priority
+        *self.salience.get_or_init(|| match &self.rule.salience {
+            Some(salience) => salience(&self.match_data),
+            None => self.rule.priority,
+        })
+    }
+
+    /// Concurrency key of this activation
+    ///
+    /// `None` for exclusive rules, an empty key for independent ones.
+    pub fn concurrency_key(&self) -> Option<String> {
+        match &self.rule.concurrency {
+            Concurrency::Exclusive => None,
+            Concurrency::Independent => Some(String::new()),
+            Concurrency::Keyed(key) => Some(key(&self.match_data)),
+        }
+    }
+
+    /// Working memory generation the rule's action reads, once on the agenda
+    pub fn generation(&self) -> Option<u64> {
+        self.generation.get().copied()
+    }
+
+    /// Record the working memory generation, unless already recorded
+    pub(crate) fn stamp_generation(&self, generation: u64) {
+        let _ = self.generation.set(generation);
+    }
+
+    /// Explain which facts the rule's patterns matched
+    ///
+    /// Constraint results are included if they were recorded when the
+    /// activation entered the agenda, see [`crate::explain`].
+    pub fn explain(&self) -> Explanation {
+        match self.explanation.get() {
+            Some(explanation) => explanation.clone(),
+            None => self.evaluate_explanation(false),
+        }
+    }
+
+    /// Evaluate and keep the explanation, unless already recorded
+    pub(crate) fn record_explanation(&self) {
+        self.explanation
+            .get_or_init(|| self.evaluate_explanation(true));
+    }
+
+    fn evaluate_explanation(&self, recorded: bool) -> Explanation {
+        let patterns = self
+            .rule
+            .patterns
+            .iter()
+            .filter_map(|pattern| {
+                let fact = self.match_data.get(pattern.alias())?;
+                Some(PatternExplanation {
+                    alias: pattern.alias().to_string(),
+                    fact: fact.id,
+                    type_name: FactHandle::type_name(fact),
+                    constraints: if recorded {
+                        pattern.explain(fact, &self.match_data)
+                    } else {
+                        Vec::new()
+                    },
+                })
+            })
+            .collect();
+        Explanation {
+            rule: self.rule.name.clone(),
+            patterns,
+            recorded,
+        }
+    }
+}
+
+/// Changes recorded by a parallel action, applied when its activation fires
+#[derive(Debug, Default)]
+pub struct Effects {
+    changes: Vec<Effect>,
+}
+
+#[derive(Debug)]
+enum Effect {
+    Assert(Box<dyn Fact>),
+    Retract(FactId),
+}
+
+impl Effects {
+    /// Assert a fact
+    pub fn assert<T: Fact>(&mut self, fact: T) {
+        self.changes.push(Effect::Assert(Box::new(fact)));
+    }
+
+    /// Retract a fact
+    pub fn retract(&mut self, fact_id: impl Into<FactId>) {
+        self.changes.push(Effect::Retract(fact_id.into()));
+    }
+
+    /// Apply the changes to a session, in the order they were recorded
+    pub(crate) fn apply(self, session: &mut Session) -> Result<()> {
+        for change in self.changes {
+            match change {
+                Effect::Assert(fact) => {
+                    session.assert_all_boxed([fact])?;
+                }
+                Effect::Retract(fact_id) => session.retract(fact_id)?,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -87,12 +366,36 @@ pub struct Rule {
     pub patterns: Vec<Box<dyn Pattern>>,
     /// Action to execute
     pub action: RuleAction,
+    /// Action reading only the match, which may run off the session
+    pub parallel_action: Option<ParallelAction>,
     /// Priority/salience
     pub priority: Priority,
+    /// Priority computed from the match, overriding `priority`
+    pub salience: Option<SalienceFn>,
     /// Agenda group
     pub agenda_group: String,
     /// Auto-focus on activation
     pub auto_focus: bool,
+    /// Activation group whose rules are mutually exclusive
+    pub activation_group: Option<String>,
+    /// Fraction of matches that activate the rule, if sampled
+    pub sample_rate: Option<f64>,
+    /// Tags for selecting rules
+    pub tags: Vec<String>,
+    /// Arbitrary key-value metadata
+    pub metadata: HashMap<String, String>,
+    /// Position of the rule in its flow's declaration order
+    pub load_order: u64,
+    /// Whether the action reads working memory live instead of as of activation
+    pub live_reads: bool,
+    /// Which activations may fire simultaneously with this rule's
+    pub concurrency: Concurrency,
+    /// Condition on the flow's environment for the rule to be active
+    pub enabled_if: Option<EnabledIf>,
+    /// Schedule on which matches fire while they hold
+    pub timer: Option<Timer>,
+    /// What to do when the action fails, overriding the session's policy
+    pub error_policy: Option<ErrorPolicy>,
 }
 
 impl Debug for Rule {
@@ -100,26 +403,70 @@ impl Debug for Rule {
         f.debug_struct("Rule")
             .field("name", &self.name)
             .field("patterns", &self.patterns)
+            .field("parallel", &self.parallel_action.is_some())
             .field("priority", &self.priority)
+            .field("dynamic_salience", &self.salience.is_some())
             .field("agenda_group", &self.agenda_group)
             .field("auto_focus", &self.auto_focus)
+            .field("activation_group", &self.activation_group)
+            .field("sample_rate", &self.sample_rate)
+            .field("tags", &self.tags)
+            .field("metadata", &self.metadata)
+            .field("load_order", &self.load_order)
+            .field("live_reads", &self.live_reads)
+            .field("concurrency", &self.concurrency)
+            .field("conditional", &self.enabled_if.is_some())
+            .field("timer", &self.timer)
+            .field("error_policy", &self.error_policy)
             .finish()
     }
 }
 
 impl Rule {
     /// Create a new rule
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(name: impl Into<String>) -> RuleBuilder {
         RuleBuilder {
             name: name.into(),
             patterns: Vec::new(),
             action: None,
+            parallel_action: None,
             priority: 0,
+            salience: None,
             agenda_group: "main".to_string(),
             auto_focus: false,
+            activation_group: None,
+            sample_rate: None,
+            tags: Vec::new(),
+            metadata: HashMap::new(),
+            live_reads: false,
+            concurrency: Concurrency::Exclusive,
+            enabled_if: None,
+            timer: None,
+            error_policy: None,
         }
     }
 
+    /// Number of patterns plus the constraints and joins they test
+    pub fn specificity(&self) -> usize {
+        self.patterns.len()
+            + self
+                .patterns
+                .iter()
+                .map(|pattern| pattern.constraint_count())
+                .sum::<usize>()
+    }
+
+    /// Check if this rule has a tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Check if this rule is active in an environment
+    pub fn is_enabled(&self, env: &FlowEnv) -> bool {
+        self.enabled_if.as_ref().is_none_or(|enabled| enabled(env))
+    }
+
     /// Fire this rule with the given match
     pub fn fire(&self, session: &mut Session, match_data: &Match) -> Result<()> {
         (self.action)(session, match_data)
@@ -131,9 +478,20 @@ pub struct RuleBuilder {
     name: String,
     patterns: Vec<Box<dyn Pattern>>,
     action: Option<RuleAction>,
+    parallel_action: Option<ParallelAction>,
     priority: Priority,
+    salience: Option<SalienceFn>,
     agenda_group: String,
     auto_focus: bool,
+    activation_group: Option<String>,
+    sample_rate: Option<f64>,
+    tags: Vec<String>,
+    metadata: HashMap<String, String>,
+    live_reads: bool,
+    concurrency: Concurrency,
+    enabled_if: Option<EnabledIf>,
+    timer: Option<Timer>,
+    error_policy: Option<ErrorPolicy>,
 }
 
 impl RuleBuilder {
@@ -149,15 +507,57 @@ impl RuleBuilder {
         F: Fn(&mut Session, &Match) -> Result<()> + Send + Sync + 'static,
     {
         self.action = Some(Arc::new(action));
+        self.parallel_action = None;
+        self
+    }
+
+    /// Set an action that only reads its match and records its changes
+    ///
+    /// `Session::match_rules_parallel` runs such actions for a batch of
+    /// activations at once and applies their [`Effects`] in firing order;
+    /// other ways of firing run the action and apply its effects in turn.
+    pub fn then_parallel<F>(mut self, action: F) -> Self
+    where
+        F: Fn(&Match, &mut Effects) -> Result<()> + Send + Sync + 'static,
+    {
+        let action: ParallelAction = Arc::new(action);
+        let run = Arc::clone(&action);
+        self = self.then(move |session, match_data| {
+            let effects = match session.take_effects() {
+                Some(effects) => effects?,
+                None => {
+                    let mut effects = Effects::default();
+                    run(match_data, &mut effects)?;
+                    effects
+                }
+            };
+            effects.apply(session)
+        });
+        self.parallel_action = Some(action);
         self
     }
 
+    /// Set the action to a sandboxed WASM plugin
+    #[cfg(feature = "wasm-plugins")]
+    pub fn then_wasm(self, action: crate::plugin::WasmAction) -> Self {
+        self.then(move |session, match_data| action.run(session, match_data))
+    }
+
     /// Set the priority/salience
     pub fn priority(mut self, priority: Priority) -> Self {
         self.priority = priority;
         self
     }
 
+    /// Compute the priority/salience from the matched facts
+    pub fn priority_fn<F>(mut self, salience: F) -> Self
+    where
+        F: Fn(&Match) -> Priority + Send + Sync + 'static,
+    {
+        self.salience = Some(Arc::new(salience));
+        self
+    }
+
     /// Set the agenda group
     pub fn agenda_group(mut self, group: impl Into<String>) -> Self {
         self.agenda_group = group.into();
@@ -170,30 +570,189 @@ impl RuleBuilder {
         self
     }
 
+    /// Set the activation group
+    pub fn activation_group(mut self, group: impl Into<String>) -> Self {
+        self.activation_group = Some(group.into());
+        self
+    }
+
+    /// Fire for only a deterministic pseudo-random fraction of matches
+    ///
+    /// Whether a match is sampled depends only on the rule, the matched facts
+    /// and the session's sample seed.
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = Some(rate.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Add a tag
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Add a metadata entry
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Let the action read working memory as it is while the action runs
+    ///
+    /// By default, [`Session::get_fact`] and [`Session::get_facts`] show an
+    /// action working memory as of when its activation was created, plus
+    /// the action's own changes.
+    pub fn live_reads(mut self, live_reads: bool) -> Self {
+        self.live_reads = live_reads;
+        self
+    }
+
+    /// Mark the rule's activations as safe to fire alongside any other
+    /// non-exclusive activation
+    pub fn independent(mut self) -> Self {
+        self.concurrency = Concurrency::Independent;
+        self
+    }
+
+    /// Let activations fire simultaneously unless they compute the same key
+    ///
+    /// Keys are shared across rules: keying by a customer id serializes all
+    /// keyed activations touching the same customer.
+    pub fn concurrency_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Match) -> String + Send + Sync + 'static,
+    {
+        self.concurrency = Concurrency::Keyed(Arc::new(key));
+        self
+    }
+
+    /// Only make the rule part of flows whose environment passes a check
+    ///
+    /// The check runs when the rule is added to a flow, when the flow's
+    /// environment changes and when a session is built for another
+    /// environment, never while matching.
+    pub fn enabled_if<F>(mut self, enabled: F) -> Self
+    where
+        F: Fn(&FlowEnv) -> bool + Send + Sync + 'static,
+    {
+        self.enabled_if = Some(Arc::new(enabled));
+        self
+    }
+
+    /// Fire matches on a schedule for as long as they hold
+    ///
+    /// Matches wait for the timer instead of firing when matched, and fire
+    /// again each period until a retraction or modification loses them.
+    /// Timers advance with the session's clock, see [`crate::clock`].
+    pub fn timer(mut self, timer: Timer) -> Self {
+        self.timer = Some(timer);
+        self
+    }
+
+    /// Decide what happens when the action fails
+    ///
+    /// Overrides the policy set with [`Session::set_error_policy`].
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = Some(policy);
+        self
+    }
+
     /// Build the rule
     pub fn build(self) -> Result<Rule> {
         let action = self
             .action
-            .ok_or_else(|| crate::error::Error::Compilation("Rule action not defined".into()))?;
+            .ok_or_else(|| Error::Compilation("Rule action not defined".into()))?;
 
         Ok(Rule {
             name: self.name,
             patterns: self.patterns,
             action,
+            parallel_action: self.parallel_action,
             priority: self.priority,
+            salience: self.salience,
             agenda_group: self.agenda_group,
             auto_focus: self.auto_focus,
+            activation_group: self.activation_group,
+            sample_rate: self.sample_rate,
+            tags: self.tags,
+            metadata: self.metadata,
+            load_order: 0,
+            live_reads: self.live_reads,
+            concurrency: self.concurrency,
+            enabled_if: self.enabled_if,
+            timer: self.timer,
+            error_policy: self.error_policy,
         })
     }
 }
 
+/// Declare a rule without building its patterns by hand
+///
+/// Each `when alias: Type (condition)` adds an `ObjectPattern<Type>` whose
+/// condition is an expression over `alias: &Type`; the condition is optional.
+/// The action receives the session, and optionally the [`Match`], with every
+/// alias bound to its fact. Optional `priority`, `agenda_group` and
+/// `auto_focus` settings go before the patterns, in that order. Evaluates to
+/// `Result<Rule>`.
+///
+/// ```ignore
+/// let rule = rule!("Hello",
+///     priority: 5,
+///     when m: Message (m.text.contains("hello")),
+///     then |session| {
+///         println!("{}", m.text);
+///         Ok(())
+///     }
+/// )?;
+/// flow.add_rule(rule)?;
+/// ```
+#[macro_export]
+macro_rules! rule {
+    (@pattern $alias:ident [$ty:ty]) => {
+        $crate::pattern::ObjectPattern::<$ty>::new(stringify!($alias))
+    };
+    (@pattern $alias:ident [$ty:ty] ($condition:expr)) => {
+        $crate::rule!(@pattern $alias [$ty])
+            .with_filter(|$alias: &$ty| $condition, stringify!($condition))
+    };
+    (
+        $name:expr,
+        $(priority: $priority:expr,)?
+        $(agenda_group: $group:expr,)?
+        $(auto_focus: $auto_focus:expr,)?
+        $(when $alias:ident : $($ty:ident)::+ $(($condition:expr))?,)+
+        then |$session:pat_param $(, $match_data:pat_param)?| $body:expr $(,)?
+    ) => {
+        $crate::rule::Rule::new($name)
+            $(.priority($priority))?
+            $(.agenda_group($group))?
+            $(.auto_focus($auto_focus))?
+            $(.when(::std::boxed::Box::new(
+                $crate::rule!(@pattern $alias [$($ty)::+] $(($condition))?)
+            )))+
+            .then(
+                move |$session: &mut $crate::session::Session,
+                      match_data: &$crate::rule::Match|
+                      -> $crate::error::Result<()> {
+                    $(
+                        #[allow(unused_variables)]
+                        let $alias = match_data.get_as::<$($ty)::+>(stringify!($alias))?;
+                    )+
+                    $(let $match_data = match_data;)?
+                    $body
+                },
+            )
+            .build()
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::fact::Fact;
     use crate::pattern::ObjectPattern;
 
     #[derive(Debug, Clone)]
+    #[allow(dead_code)]
     struct TestFact {
         value: i32,
     }
@@ -216,6 +775,20 @@ mod tests {
         assert_eq!(rule.patterns.len(), 1);
     }
 
+    #[test]
+    fn test_tags_and_metadata() {
+        let rule = Rule::new("flag_transfer")
+            .then(|_session, _match| Ok(()))
+            .tag("fraud")
+            .metadata("owner", "risk-team")
+            .build()
+            .unwrap();
+
+        assert!(rule.has_tag("fraud"));
+        assert!(!rule.has_tag("billing"));
+        assert_eq!(rule.metadata["owner"], "risk-team");
+    }
+
     #[test]
     fn test_match_operations() {
         let mut match_data = Match::new();
@@ -227,4 +800,79 @@ mod tests {
         assert!(match_data.get("test").is_some());
         assert_eq!(match_data.get("test").unwrap().id, handle.id);
     }
+
+    #[test]
+    fn test_get_as() {
+        let mut match_data = Match::new();
+        let handle = Arc::new(crate::fact::FactHandle::new(TestFact { value: 7 }, 0));
+        match_data.insert("test".to_string(), handle);
+
+        assert_eq!(match_data.get_as::<TestFact>("test").unwrap().value, 7);
+        assert!(matches!(
+            match_data.get_as::<TestFact>("missing"),
+            Err(Error::PatternMatch(_))
+        ));
+        assert!(match_data.get_as::<String>("test").is_err());
+    }
+
+    #[test]
+    fn test_dynamic_salience_is_cached() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let rule = Rule::new("claims")
+            .when(Box::new(ObjectPattern::<TestFact>::new("claim")) as Box<dyn Pattern>)
+            .then(|_session, _match| Ok(()))
+            .priority_fn(move |m| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                m.get("claim")
+                    .and_then(|f| f.downcast_ref::<TestFact>())
+                    .map_or(0, |claim| claim.value)
+            })
+            .build()
+            .unwrap();
+
+        let mut match_data = Match::new();
+        let handle = Arc::new(crate::fact::FactHandle::new(TestFact { value: 500 }, 0));
+        match_data.insert("claim".to_string(), handle);
+        let activation = Activation::new(Arc::new(rule), match_data, 0);
+
+        assert_eq!(activation.salience(), 500);
+        assert_eq!(activation.salience(), 500);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rule_macro() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let rule = crate::rule!("large",
+            priority: 3,
+            agenda_group: "checks",
+            auto_focus: true,
+            when small: TestFact (small.value < 10),
+            when large: TestFact (large.value >= 10),
+            then |_session, match_data| {
+                log.lock().unwrap().push((small.value, large.value));
+                assert_eq!(match_data.facts.len(), 2);
+                Ok(())
+            }
+        )
+        .unwrap();
+        assert_eq!(rule.priority, 3);
+        assert_eq!(rule.agenda_group, "checks");
+        assert!(rule.auto_focus);
+        assert_eq!(rule.patterns.len(), 2);
+        assert_eq!(rule.patterns[1].alias(), "large");
+
+        let any = crate::rule!("any", when t: TestFact, then |_| Ok(())).unwrap();
+        assert_eq!(any.patterns[0].constraint_count(), 0);
+
+        let mut flow = crate::flow::Flow::new("macro");
+        flow.add_rule(rule).unwrap();
+        let mut session = flow.session();
+        session.assert(TestFact { value: 3 }).unwrap();
+        session.assert(TestFact { value: 30 }).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), [(3, 30)]);
+    }
 }
@@ -0,0 +1,140 @@
+//! Typed scratch state shared between rule firings
+//!
+//! Counters, caches and other working state that should not be modeled as
+//! facts belong in a session's [`Scratchpad`] rather than in `Arc<Mutex<_>>`
+//! globals captured by actions. Values are stored serialized, so they are
+//! saved and restored with the session's
+//! [`Checkpoint`](crate::checkpoint::Checkpoint).
+
+use crate::error::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Key-value state of a session, see [`Session::kv`](crate::session::Session::kv)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scratchpad {
+    values: BTreeMap<String, serde_json::Value>,
+}
+
+impl Scratchpad {
+    /// Create an empty scratchpad
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a value under a key, replacing any previous one
+    pub fn insert<T: Serialize>(&mut self, key: impl Into<String>, value: T) -> Result<()> {
+        let key = key.into();
+        let value = serde_json::to_value(value).map_err(|e| {
+            Error::Execution(format!("Failed to store scratchpad value '{}': {}", key, e))
+        })?;
+        self.values.insert(key, value);
+        Ok(())
+    }
+
+    /// Get the value under a key, or `None` if it is missing or not a `T`
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        T::deserialize(self.values.get(key)?).ok()
+    }
+
+    /// Update the value under a key, starting from `T::default()` if missing
+    ///
+    /// Returns the updated value.
+    pub fn update<T, F>(&mut self, key: &str, f: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default,
+        F: FnOnce(&mut T),
+    {
+        let mut value = match self.values.get(key) {
+            Some(value) => T::deserialize(value).map_err(|e| {
+                Error::Execution(format!(
+                    "Scratchpad value '{}' is not a {}: {}",
+                    key,
+                    std::any::type_name::<T>(),
+                    e
+                ))
+            })?,
+            None => T::default(),
+        };
+        f(&mut value);
+        self.insert(key, &value)?;
+        Ok(value)
+    }
+
+    /// Remove the value under a key, returning whether there was one
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.values.remove(key).is_some()
+    }
+
+    /// Check if a key has a value
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Get the keys, in order
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+
+    /// Get the number of values
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Check if the scratchpad is empty
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Remove all values
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    /// The serialized values, by key
+    pub(crate) fn values(&self) -> &BTreeMap<String, serde_json::Value> {
+        &self.values
+    }
+
+    /// Replace all values with serialized ones
+    pub(crate) fn set_values(&mut self, values: BTreeMap<String, serde_json::Value>) {
+        self.values = values;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_typed_values() {
+        let mut kv = Scratchpad::new();
+        kv.insert("threshold", 10u32).unwrap();
+        kv.insert("names", vec!["a", "b"]).unwrap();
+
+        assert_eq!(kv.get::<u32>("threshold"), Some(10));
+        assert_eq!(kv.get::<Vec<String>>("names").unwrap(), ["a", "b"]);
+        assert_eq!(kv.get::<String>("threshold"), None);
+        assert_eq!(kv.get::<u32>("missing"), None);
+
+        assert_eq!(kv.update::<u64, _>("count", |n| *n += 1).unwrap(), 1);
+        assert_eq!(kv.update::<u64, _>("count", |n| *n += 1).unwrap(), 2);
+        let cache = kv
+            .update::<HashMap<String, i64>, _>("cache", |cache| {
+                cache.insert("x".into(), 4);
+            })
+            .unwrap();
+        assert_eq!(cache["x"], 4);
+        assert!(kv.update::<u64, _>("names", |n| *n += 1).is_err());
+
+        assert_eq!(
+            kv.keys().collect::<Vec<_>>(),
+            ["cache", "count", "names", "threshold"]
+        );
+        assert!(kv.remove("names"));
+        assert!(!kv.contains_key("names"));
+        assert_eq!(kv.len(), 3);
+    }
+}
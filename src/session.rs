@@ -1,11 +1,32 @@
 //! Session for rule execution
 
-use crate::agenda::Agenda;
+use crate::agenda::{Agenda, PriorityInheritance, StarvationMonitor};
+use crate::audit::{AuditLog, AuditRecorder};
+use crate::checkpoint::Checkpoint;
+use crate::clock::{Instant, SessionClock};
+use crate::diff::{ChangeJournal, FactDiff, Marker};
 use crate::error::Result;
-use crate::fact::{Fact, FactHandle, FactId};
-use crate::node::{Node, RootNode};
-use crate::working_memory::WorkingMemory;
+use crate::event::{EventListener, EventListeners};
+use crate::explain::ExplanationRecorder;
+use crate::fact::{Fact, FactHandle, FactId, FactsOf, TypedFactHandle};
+use crate::fixture::{FixtureCapture, FixtureTypes};
+use crate::flow::NetworkChanges;
+use crate::memory_report::MemoryReport;
+use crate::node::{NetworkMemory, Node, RootNode};
+use crate::rule::{Activation, Effects, ErrorPolicy, Match, Rule};
+use crate::scratchpad::Scratchpad;
+use crate::stats::{Stats, StatsCollector};
+use crate::working_memory::{find_in, ReadView, WorkingMemory};
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// A service or value shared with rule actions by name
+pub type Global = Arc<dyn Any + Send + Sync>;
 
 /// Session represents an instance of a flow with working memory
 pub struct Session {
@@ -15,10 +36,192 @@ pub struct Session {
     working_memory: WorkingMemory,
     /// Agenda for managing activations
     agenda: Agenda,
-    /// Root node of the Rete network
+    /// Root node of the Rete network (shared with the flow and its other sessions)
     root: Arc<RwLock<RootNode>>,
+    /// This session's node memories
+    memory: NetworkMemory,
+    /// Rule networks the node memories and agenda reflect, by rule name
+    networks: HashMap<String, Arc<dyn Node>>,
+    /// Revision of the root node `networks` was last synced with
+    network_revision: Option<u64>,
     /// Whether execution has been halted
     halted: bool,
+    /// Limits of firing runs
+    fire_guard: FireGuard,
+    /// What to do when an action fails, unless its rule overrides it
+    error_policy: ErrorPolicy,
+    /// Capture of asserted facts into a fixture
+    capture: Option<FixtureCapture>,
+    /// Monitor for agenda groups waiting too long for focus
+    starvation_monitor: Option<StarvationMonitor>,
+    /// Collector of facts that produced no activation
+    unmatched: Option<UnmatchedCollector>,
+    /// Collector of per-rule statistics
+    stats: Option<Arc<StatsCollector>>,
+    /// Whether activations record their explanation
+    explanations: bool,
+    /// Recorder of the audit log
+    audit: Option<Arc<AuditRecorder>>,
+    /// Writer of fact changes to an attached store
+    store: Option<Arc<crate::store::StoreWriter>>,
+    /// Store the agenda is checkpointed to
+    session_store: Option<Arc<dyn crate::store::SessionStore>>,
+    /// Firings between checkpoints, 0 to checkpoint at the end of each run
+    checkpoint_interval: usize,
+    /// Firings since the last checkpoint
+    unsaved_firings: usize,
+    /// The first checkpoint that failed since the last flush
+    store_error: Option<crate::error::Error>,
+    /// Listeners notified of fact and rule events
+    listeners: EventListeners,
+    /// Journal of fact changes, started by the first marker
+    journal: Option<Arc<ChangeJournal>>,
+    /// View of working memory read by the firing rule's action
+    read_view: Option<ReadView>,
+    /// Effects computed ahead for the firing rule's parallel action
+    effects: Option<Result<Effects>>,
+    /// Transient state shared between rule firings
+    kv: Scratchpad,
+    /// Services and values available to rule actions by name
+    globals: HashMap<String, Global>,
+}
+
+/// Tracks facts asserted during a match cycle that produced no activation
+#[derive(Debug, Default)]
+struct UnmatchedCollector {
+    /// Facts asserted since the last cycle ended
+    asserted: Vec<FactId>,
+    /// Facts referenced by an activation since the last cycle ended
+    matched: HashSet<FactId>,
+    /// Unmatched facts of the last completed cycle
+    last_cycle: Vec<FactId>,
+}
+
+impl UnmatchedCollector {
+    fn matched(&mut self, activations: &[Arc<Activation>]) {
+        for activation in activations {
+            self.matched
+                .extend(activation.match_data.facts.values().map(|f| f.id));
+        }
+    }
+
+    fn end_cycle(&mut self, working_memory: &WorkingMemory) {
+        let matched = std::mem::take(&mut self.matched);
+        self.last_cycle = std::mem::take(&mut self.asserted)
+            .into_iter()
+            .filter(|id| !matched.contains(id) && working_memory.get(*id).is_some())
+            .collect();
+    }
+}
+
+/// Get the message of a caught panic
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "non-string panic payload".to_string()),
+    }
+}
+
+/// Aborts firing runs that look endless, see [`Session::set_fire_guard`]
+///
+/// A run is one call to a `match_rules` method. Instead of firing an
+/// activation that would exceed a limit, it fails with
+/// [`Error::FireLimitExceeded`]; that activation is taken off the agenda.
+///
+/// [`Error::FireLimitExceeded`]: crate::error::Error::FireLimitExceeded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FireGuard {
+    max_fires: Option<usize>,
+    max_repeats: Option<usize>,
+}
+
+impl FireGuard {
+    /// Create a guard without limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fire at most `n` activations per run
+    pub fn max_fires(mut self, n: usize) -> Self {
+        self.max_fires = Some(n);
+        self
+    }
+
+    /// Fire a rule at most `n` times per run on facts with the same ids and contents
+    ///
+    /// Rules that keep re-triggering each other while returning facts to an
+    /// earlier state repeat such firings; loops that make progress, such as
+    /// counting down, change the facts and are not caught. Contents are
+    /// compared through the facts' `Debug` output.
+    pub fn max_repeats(mut self, n: usize) -> Self {
+        self.max_repeats = Some(n);
+        self
+    }
+
+    fn start(self) -> GuardedRun {
+        GuardedRun {
+            guard: self,
+            fired: 0,
+            seen: HashMap::new(),
+        }
+    }
+}
+
+/// A firing run checked against a [`FireGuard`]
+struct GuardedRun {
+    guard: FireGuard,
+    fired: usize,
+    /// Firings per hash of rule name, fact ids and fact contents
+    seen: HashMap<u64, usize>,
+}
+
+impl GuardedRun {
+    /// Count a firing of `activation`, failing if it exceeds a limit
+    fn admit(&mut self, activation: &Activation) -> Result<()> {
+        if self.guard.max_fires.is_some_and(|max| self.fired >= max) {
+            return Err(self.exceeded(format!("more than {} firings in one run", self.fired)));
+        }
+        if let Some(max) = self.guard.max_repeats {
+            let mut facts: Vec<_> = activation.match_data.facts.iter().collect();
+            facts.sort_by(|a, b| a.0.cmp(b.0));
+            let mut hasher = DefaultHasher::new();
+            activation.rule.name.hash(&mut hasher);
+            for (alias, fact) in facts {
+                alias.hash(&mut hasher);
+                fact.id.hash(&mut hasher);
+                format!("{:?}", fact.fact).hash(&mut hasher);
+            }
+            let repeats = self.seen.entry(hasher.finish()).or_insert(0);
+            if *repeats >= max {
+                return Err(self.exceeded(format!(
+                    "rule '{}' fired more than {} times on the same facts",
+                    activation.rule.name, max
+                )));
+            }
+            *repeats += 1;
+        }
+        self.fired += 1;
+        Ok(())
+    }
+
+    fn exceeded(&self, reason: String) -> crate::error::Error {
+        crate::error::Error::FireLimitExceeded {
+            fired: self.fired,
+            reason,
+        }
+    }
+}
+
+/// Outcome of [`Session::match_rules_with_limit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitedFiring {
+    /// Number of rules fired
+    pub fired: usize,
+    /// Whether firing stopped at the limit with activations still pending
+    pub limit_reached: bool,
 }
 
 impl Session {
@@ -33,8 +236,43 @@ impl Session {
             working_memory: WorkingMemory::new(),
             agenda: Agenda::with_strategies(strategies),
             root,
+            memory: NetworkMemory::new(),
+            networks: HashMap::new(),
+            network_revision: None,
             halted: false,
+            fire_guard: FireGuard::default(),
+            error_policy: ErrorPolicy::default(),
+            capture: None,
+            starvation_monitor: None,
+            unmatched: None,
+            stats: None,
+            explanations: false,
+            audit: None,
+            store: None,
+            session_store: None,
+            checkpoint_interval: 0,
+            unsaved_firings: 0,
+            store_error: None,
+            listeners: EventListeners::default(),
+            journal: None,
+            read_view: None,
+            effects: None,
+            kv: Scratchpad::new(),
+            globals: HashMap::new(),
         }
+        .instrumented()
+    }
+
+    /// Report this session's events to the enabled observability features
+    #[allow(unused_mut)]
+    fn instrumented(mut self) -> Self {
+        #[cfg(feature = "metrics")]
+        self.add_event_listener(Arc::new(crate::event::MetricsListener {
+            flow: self.flow_name.clone(),
+        }));
+        #[cfg(feature = "tracing")]
+        self.add_event_listener(Arc::new(crate::event::TracingListener));
+        self
     }
 
     /// Get the flow name
@@ -42,67 +280,581 @@ impl Session {
         &self.flow_name
     }
 
+    /// Deduplicate facts of type `T` by value
+    ///
+    /// From now on, asserting a `T` equal to one already in working memory
+    /// returns the existing fact instead of inserting a duplicate, so the
+    /// same event can be ingested more than once.
+    pub fn deduplicate<T: Fact + Eq + Hash>(&mut self) {
+        self.working_memory.deduplicate::<T>();
+    }
+
     /// Assert a fact into working memory
-    pub fn assert<T: Fact>(&mut self, fact: T) -> Result<FactId> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "assert", level = "debug", skip_all, fields(flow = %self.flow_name))
+    )]
+    pub fn assert<T: Fact>(&mut self, fact: T) -> Result<TypedFactHandle<T>> {
+        self.sync_networks()?;
+        if let Some(fact_id) = self.working_memory.find_equal(&fact) {
+            return Ok(TypedFactHandle::new(fact_id));
+        }
+        self.check_storable(&fact)?;
         let handle = self.working_memory.assert(fact)?;
         let fact_id = handle.id;
+        if let Some(capture) = &mut self.capture {
+            capture.observe(&handle);
+        }
+        if let Some(collector) = &mut self.unmatched {
+            collector.asserted.push(fact_id);
+        }
+        self.listeners.notify(|l| l.on_fact_asserted(&handle));
+        self.advance_generation();
+
+        // Propagate through Rete network
+        let root = self.root.read().map_err(|e| {
+            crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
+        })?;
+
+        let activations = root.assert_fact(handle, &mut self.memory)?;
+
+        // Add activations to agenda
+        if let Some(collector) = &mut self.unmatched {
+            collector.matched(&activations);
+        }
+        for activation in activations {
+            self.agenda.insert(activation)?;
+        }
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.check_invariants("assert");
+        self.record_agenda_depth();
+
+        Ok(TypedFactHandle::new(fact_id))
+    }
+
+    /// Assert many facts, ordering the agenda once for the whole batch
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "assert_all", level = "debug", skip_all, fields(flow = %self.flow_name))
+    )]
+    pub fn assert_all<T: Fact>(
+        &mut self,
+        facts: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<FactId>> {
+        self.sync_networks()?;
+        let facts: Vec<T> = facts.into_iter().collect();
+        if let Some(fact) = facts.first() {
+            self.check_storable(fact)?;
+        }
+        let mut fact_ids = Vec::new();
+        let mut handles = Vec::new();
+        for fact in facts {
+            match self.working_memory.find_equal(&fact) {
+                Some(fact_id) => fact_ids.push(fact_id),
+                None => {
+                    let handle = self.working_memory.assert(fact)?;
+                    fact_ids.push(handle.id);
+                    handles.push(handle);
+                }
+            }
+        }
+        self.propagate_all(handles)?;
+        Ok(fact_ids)
+    }
+
+    /// Assert many facts of different types, ordering the agenda once for the whole batch
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "assert_all_boxed", level = "debug", skip_all, fields(flow = %self.flow_name))
+    )]
+    pub fn assert_all_boxed(
+        &mut self,
+        facts: impl IntoIterator<Item = Box<dyn Fact>>,
+    ) -> Result<Vec<FactId>> {
+        self.sync_networks()?;
+        let facts: Vec<_> = facts.into_iter().collect();
+        for fact in &facts {
+            self.check_storable(fact.as_ref())?;
+        }
+        let mut fact_ids = Vec::new();
+        let mut handles = Vec::new();
+        for fact in facts {
+            match self.working_memory.find_equal(fact.as_ref()) {
+                Some(fact_id) => fact_ids.push(fact_id),
+                None => {
+                    let handle = self.working_memory.assert_boxed(fact)?;
+                    fact_ids.push(handle.id);
+                    handles.push(handle);
+                }
+            }
+        }
+        self.propagate_all(handles)?;
+        Ok(fact_ids)
+    }
+
+    /// Fail if a store is attached whose registry can't serialize the fact
+    fn check_storable(&self, fact: &dyn Fact) -> Result<()> {
+        match &self.store {
+            Some(writer) => writer.check(fact),
+            None => Ok(()),
+        }
+    }
+
+    fn propagate_all(&mut self, handles: Vec<Arc<FactHandle>>) -> Result<()> {
+        let fact_ids: Vec<_> = handles.iter().map(|handle| handle.id).collect();
+        if let Some(capture) = &mut self.capture {
+            handles.iter().for_each(|handle| capture.observe(handle));
+        }
+        if let Some(collector) = &mut self.unmatched {
+            collector.asserted.extend(&fact_ids);
+        }
+        for handle in &handles {
+            self.listeners.notify(|l| l.on_fact_asserted(handle));
+        }
+        self.advance_generation();
 
         // Propagate through Rete network
-        let mut root = self.root.write().map_err(|e| {
+        let root = self.root.read().map_err(|e| {
             crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
         })?;
 
-        let activations = root.assert_fact(handle)?;
+        let activations = root.assert_facts(handles, &mut self.memory)?;
+
+        if let Some(collector) = &mut self.unmatched {
+            collector.matched(&activations);
+        }
+        self.agenda.insert_all(activations)?;
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.check_invariants("assert_all");
+        self.record_agenda_depth();
+
+        Ok(())
+    }
+
+    /// Assert a fact, awaiting any asynchronous constraints during propagation
+    ///
+    /// Note: the Rete network stays locked while constraints are awaited, so
+    /// the returned future is not `Send`.
+    #[cfg(feature = "async-constraints")]
+    #[allow(clippy::await_holding_lock)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "assert_async", level = "debug", skip_all, fields(flow = %self.flow_name))
+    )]
+    pub async fn assert_async<T: Fact>(&mut self, fact: T) -> Result<TypedFactHandle<T>> {
+        self.sync_networks()?;
+        if let Some(fact_id) = self.working_memory.find_equal(&fact) {
+            return Ok(TypedFactHandle::new(fact_id));
+        }
+        self.check_storable(&fact)?;
+        let handle = self.working_memory.assert(fact)?;
+        let fact_id = handle.id;
+        if let Some(capture) = &mut self.capture {
+            capture.observe(&handle);
+        }
+        if let Some(collector) = &mut self.unmatched {
+            collector.asserted.push(fact_id);
+        }
+        self.listeners.notify(|l| l.on_fact_asserted(&handle));
+        self.advance_generation();
+
+        // Propagate through Rete network
+        let activations = {
+            let root = self.root.read().map_err(|e| {
+                crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
+            })?;
+
+            root.assert_fact_async(handle, &mut self.memory).await?
+        };
 
         // Add activations to agenda
+        if let Some(collector) = &mut self.unmatched {
+            collector.matched(&activations);
+        }
         for activation in activations {
             self.agenda.insert(activation)?;
         }
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.check_invariants("assert_async");
+        self.record_agenda_depth();
 
-        Ok(fact_id)
+        Ok(TypedFactHandle::new(fact_id))
     }
 
     /// Retract a fact from working memory
-    pub fn retract(&mut self, fact_id: FactId) -> Result<()> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "retract", level = "debug", skip_all, fields(flow = %self.flow_name, fact = fact_id.into().as_u64()))
+    )]
+    pub fn retract(&mut self, fact_id: impl Into<FactId> + Copy) -> Result<()> {
+        let fact_id = fact_id.into();
+        self.sync_networks()?;
         let handle = self.working_memory.retract(fact_id)?;
+        self.listeners.notify(|l| l.on_fact_retracted(&handle));
+        self.advance_generation();
 
         // Propagate through Rete network
-        let mut root = self.root.write().map_err(|e| {
+        let root = self.root.read().map_err(|e| {
             crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
         })?;
 
-        root.retract_fact(handle)?;
+        #[cfg(feature = "pool")]
+        let released = Arc::clone(&handle);
+        root.retract_fact(handle, &mut self.memory)?;
+
+        // Pending activations must not fire against a fact that no longer exists
+        self.agenda.cancel_for_fact(fact_id);
+        self.agenda.forget_fired(fact_id);
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.check_invariants("retract");
+        self.record_agenda_depth();
+        #[cfg(feature = "pool")]
+        self.working_memory.recycle(released);
 
         Ok(())
     }
 
+    /// Retract the fact of a handle, failing with [`Error::StaleFact`] if the
+    /// fact was modified or retracted since the handle was taken
+    ///
+    /// [`Error::StaleFact`]: crate::error::Error::StaleFact
+    pub fn retract_handle(&mut self, handle: &FactHandle) -> Result<()> {
+        self.working_memory.check_current(handle)?;
+        self.retract(handle.id)
+    }
+
     /// Modify a fact in working memory
-    pub fn modify(&mut self, fact_id: FactId) -> Result<()> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "modify", level = "debug", skip_all, fields(flow = %self.flow_name, fact = fact_id.into().as_u64()))
+    )]
+    pub fn modify(&mut self, fact_id: impl Into<FactId> + Copy) -> Result<()> {
+        let fact_id = fact_id.into();
+        self.sync_networks()?;
+        let handle = self.working_memory.modify(fact_id)?;
+        self.propagate_modify(fact_id, handle)
+    }
+
+    /// Modify the fact of a handle, failing with [`Error::StaleFact`] if the
+    /// fact was modified or retracted since the handle was taken
+    ///
+    /// [`Error::StaleFact`]: crate::error::Error::StaleFact
+    pub fn modify_handle(&mut self, handle: &FactHandle) -> Result<()> {
+        self.working_memory.check_current(handle)?;
+        self.modify(handle.id)
+    }
+
+    /// Change a fact's data and re-run matching against the new version
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "modify_with", level = "debug", skip_all, fields(flow = %self.flow_name, fact = fact_id.into().as_u64()))
+    )]
+    pub fn modify_with<T, F>(&mut self, fact_id: impl Into<FactId> + Copy, f: F) -> Result<()>
+    where
+        T: Fact + Clone,
+        F: FnOnce(&mut T),
+    {
+        let fact_id = fact_id.into();
+        self.sync_networks()?;
+        let fact = self.updated_fact(fact_id, f)?;
+        let handle = self.working_memory.replace(fact_id, fact)?;
+        self.propagate_modify(fact_id, handle)
+    }
+
+    /// Modify a fact whose listed fields changed
+    ///
+    /// Only rules with a pattern on the fact's type that reads a changed
+    /// field (or does not declare what it reads) re-match the fact and may
+    /// fire again; other rules keep their matches and pending activations.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "modify_fields", level = "debug", skip_all, fields(flow = %self.flow_name, fact = fact_id.into().as_u64(), changed = ?changed))
+    )]
+    pub fn modify_fields(
+        &mut self,
+        fact_id: impl Into<FactId> + Copy,
+        changed: &[&str],
+    ) -> Result<()> {
+        let fact_id = fact_id.into();
+        self.sync_networks()?;
         let handle = self.working_memory.modify(fact_id)?;
+        self.propagate_modify_fields(handle, changed)
+    }
+
+    /// Change some fields of a fact's data, re-matching only rules that read them
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "modify_fields_with", level = "debug", skip_all, fields(flow = %self.flow_name, fact = fact_id.into().as_u64(), changed = ?changed))
+    )]
+    pub fn modify_fields_with<T, F>(
+        &mut self,
+        fact_id: impl Into<FactId> + Copy,
+        changed: &[&str],
+        f: F,
+    ) -> Result<()>
+    where
+        T: Fact + Clone,
+        F: FnOnce(&mut T),
+    {
+        let fact_id = fact_id.into();
+        self.sync_networks()?;
+        let fact = self.updated_fact(fact_id, f)?;
+        let handle = self.working_memory.replace(fact_id, fact)?;
+        self.propagate_modify_fields(handle, changed)
+    }
+
+    /// Clone a fact's data and apply an update to the copy
+    fn updated_fact<T, F>(&self, fact_id: FactId, f: F) -> Result<T>
+    where
+        T: Fact + Clone,
+        F: FnOnce(&mut T),
+    {
+        let handle = self
+            .working_memory
+            .get(fact_id)
+            .ok_or_else(|| crate::error::Error::FactNotFound(format!("{:?}", fact_id)))?;
+        let mut fact = handle.downcast_ref::<T>().cloned().ok_or_else(|| {
+            crate::error::Error::Execution(format!(
+                "Fact {:?} is a {}, not a {}",
+                fact_id,
+                handle.type_name(),
+                std::any::type_name::<T>()
+            ))
+        })?;
+        f(&mut fact);
+        Ok(fact)
+    }
+
+    fn propagate_modify_fields(&mut self, handle: Arc<FactHandle>, changed: &[&str]) -> Result<()> {
+        self.listeners.notify(|l| l.on_fact_modified(&handle));
+        self.advance_generation();
+        let root = self.root.read().map_err(|e| {
+            crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
+        })?;
+
+        let (activations, rematched) =
+            root.modify_fact_fields(Arc::clone(&handle), changed, &mut self.memory)?;
+        self.agenda.refresh_fact(&handle, &rematched)?;
 
+        if let Some(collector) = &mut self.unmatched {
+            collector.matched(&activations);
+        }
+        for activation in activations {
+            self.agenda.insert(activation)?;
+        }
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.check_invariants("modify_fields");
+        self.record_agenda_depth();
+
+        Ok(())
+    }
+
+    fn propagate_modify(&mut self, fact_id: FactId, handle: Arc<FactHandle>) -> Result<()> {
+        self.listeners.notify(|l| l.on_fact_modified(&handle));
+        self.advance_generation();
         // Propagate through Rete network
-        let mut root = self.root.write().map_err(|e| {
+        let root = self.root.read().map_err(|e| {
             crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
         })?;
 
-        let activations = root.modify_fact(handle)?;
+        let activations = root.modify_fact(handle, &mut self.memory)?;
+
+        // Replace activations created from the previous version of the fact
+        self.agenda.cancel_for_fact(fact_id);
+        self.agenda.forget_fired(fact_id);
 
         // Add activations to agenda
+        if let Some(collector) = &mut self.unmatched {
+            collector.matched(&activations);
+        }
         for activation in activations {
             self.agenda.insert(activation)?;
         }
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.check_invariants("modify");
+        self.record_agenda_depth();
+
+        Ok(())
+    }
+
+    /// Panic if the engine's internal state is inconsistent, see [`crate::invariants`]
+    #[cfg(feature = "debug-invariants")]
+    fn check_invariants(&self, operation: &str) {
+        crate::invariants::check(operation, &self.working_memory, &self.memory, &self.agenda);
+    }
+
+    #[cfg(not(feature = "debug-invariants"))]
+    fn check_invariants(&self, _operation: &str) {}
+
+    /// Publish the number of pending activations as a `metrics` gauge
+    #[cfg(feature = "metrics")]
+    fn record_agenda_depth(&self) {
+        metrics::gauge!("nools_agenda_depth", "flow" => self.flow_name.clone())
+            .set(self.agenda.activations().count() as f64);
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn record_agenda_depth(&self) {}
+
+    /// Run a query of the flow against working memory
+    ///
+    /// `args` is matched by the query's patterns alongside the facts in
+    /// working memory without being asserted, so later patterns can join on
+    /// it; pass `()` to a query without parameters. Matches are returned in
+    /// the order they were found. Inside a rule's action, the query sees the
+    /// same working memory as [`Session::get_facts`].
+    pub fn query<A: Fact>(&self, name: &str, args: A) -> Result<Vec<Match>> {
+        let network = self
+            .root
+            .read()
+            .map_err(|e| crate::error::Error::Execution(format!("Failed to acquire lock: {}", e)))?
+            .query_network(name)
+            .ok_or_else(|| crate::error::Error::QueryNotFound(name.to_string()))?;
+
+        let view = self
+            .read_view
+            .unwrap_or_else(|| ReadView::at(self.working_memory.generation()));
+        let facts = self.working_memory.get_all_at(view);
+        let mut memory = NetworkMemory::new();
+        memory.set_clock(Arc::clone(self.memory.clock()));
+        let mut matches = Vec::new();
+        for fact in std::iter::once(Arc::new(FactHandle::new(args, 0))).chain(facts) {
+            for activation in network.assert_fact(fact, &mut memory)? {
+                matches.push(activation.match_data.clone());
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Bring this session up to date with its flow after [`Flow::reload`]
+    ///
+    /// Node memories of kept rules are left alone. Those of removed and
+    /// rebuilt rules are dropped with their pending activations, then
+    /// rebuilt and added rules are primed with the facts in working memory.
+    /// Refraction still applies, so matches that already fired under a
+    /// rule's previous version do not fire again.
+    ///
+    /// Sessions do this on their own at their next assert, retract, modify
+    /// or match, so calling it is only needed to see the changes right away.
+    ///
+    /// [`Flow::reload`]: crate::flow::Flow::reload
+    pub fn reprime(&mut self, changes: &NetworkChanges) -> Result<()> {
+        for node in &changes.retired {
+            node.forget(&mut self.memory);
+        }
+        self.sync_networks()?;
+        self.check_invariants("reprime");
+        self.record_agenda_depth();
+        Ok(())
+    }
+
+    /// Catch up with rule networks added to, rebuilt in or removed from the
+    /// flow since this session last looked
+    ///
+    /// New networks are primed with the facts in working memory, so rules
+    /// added to a flow after its sessions were created match facts asserted
+    /// before.
+    fn sync_networks(&mut self) -> Result<()> {
+        let root = self.root.read().map_err(|e| {
+            crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
+        })?;
+        if self.network_revision == Some(root.revision()) {
+            return Ok(());
+        }
+        self.network_revision = Some(root.revision());
 
+        let mut stale = HashSet::new();
+        let mut primed = Vec::new();
+        let mut networks = HashMap::new();
+        for (name, network) in root.rule_networks() {
+            match self.networks.remove(name) {
+                Some(known) if Arc::ptr_eq(&known, network) => {}
+                Some(known) => {
+                    known.forget(&mut self.memory);
+                    stale.insert(name.to_string());
+                    primed.push(Arc::clone(network));
+                }
+                None => primed.push(Arc::clone(network)),
+            }
+            networks.insert(name.to_string(), Arc::clone(network));
+        }
+        for (name, removed) in std::mem::replace(&mut self.networks, networks) {
+            removed.forget(&mut self.memory);
+            stale.insert(name);
+        }
+        self.agenda.cancel_rules(&stale);
+        if primed.is_empty() {
+            return Ok(());
+        }
+
+        let mut facts = self.working_memory.get_all();
+        facts.sort_by_key(|fact| fact.recency);
+        let mut activations = Vec::new();
+        for network in primed {
+            for fact in &facts {
+                activations.extend(network.assert_fact(Arc::clone(fact), &mut self.memory)?);
+            }
+        }
+        self.agenda.insert_all(activations)?;
+        cancel_evicted(&mut self.memory, &mut self.agenda);
         Ok(())
     }
 
     /// Get a fact by ID
-    pub fn get_fact(&self, fact_id: FactId) -> Option<Arc<FactHandle>> {
-        self.working_memory.get(fact_id)
+    ///
+    /// Inside a rule's action this reads working memory as of when the
+    /// activation was created plus the action's own changes, unless the
+    /// rule was built with `live_reads`.
+    pub fn get_fact(&self, fact_id: impl Into<FactId>) -> Option<Arc<FactHandle>> {
+        let fact_id = fact_id.into();
+        match self.read_view {
+            Some(view) => self.working_memory.get_at(fact_id, view),
+            None => self.working_memory.get(fact_id),
+        }
+    }
+
+    /// Check whether a handle is the current version of its fact
+    ///
+    /// Always reads live working memory, even inside a rule's action.
+    pub fn is_current(&self, handle: &FactHandle) -> bool {
+        self.working_memory.is_current(handle)
     }
 
     /// Get all facts of a specific type
+    ///
+    /// Inside a rule's action this reads working memory as of when the
+    /// activation was created plus the action's own changes, unless the
+    /// rule was built with `live_reads`.
     pub fn get_facts<T: Fact>(&self) -> Vec<Arc<FactHandle>> {
-        self.working_memory.get_by_type::<T>()
+        match self.read_view {
+            Some(view) => self.working_memory.get_by_type_at::<T>(view),
+            None => self.working_memory.get_by_type::<T>(),
+        }
+    }
+
+    /// Get all facts of a specific type, typed
+    ///
+    /// Reads the same view of working memory as [`Session::get_facts`].
+    pub fn iter_facts<T: Fact>(&self) -> FactsOf<T> {
+        FactsOf::new(self.get_facts::<T>())
+    }
+
+    /// Find the facts of type `T` matching `predicate`
+    ///
+    /// Reads the same view of working memory as [`Session::get_facts`].
+    pub fn find<T: Fact>(&self, predicate: impl Fn(&T) -> bool) -> Vec<(FactId, Arc<FactHandle>)> {
+        find_in(self.get_facts::<T>(), predicate)
+    }
+
+    /// Stamp activations created from now on with the current working memory
+    /// generation, dropping history that no activation can read anymore
+    fn advance_generation(&mut self) {
+        let generation = self.working_memory.generation();
+        if !self.agenda.has_pending() {
+            self.working_memory
+                .forget_history(self.read_view.map_or(generation, |view| view.generation));
+        }
+        self.agenda.set_generation(generation);
     }
 
     /// Set focus to an agenda group
@@ -111,6 +863,87 @@ impl Session {
         Ok(self)
     }
 
+    /// Remove an agenda group from the focus stack, keeping its activations
+    pub fn deactivate_group(&mut self, group: &str) -> Result<&mut Self> {
+        self.agenda.deactivate_group(group)?;
+        Ok(self)
+    }
+
+    /// Cancel all pending activations of an agenda group and its children
+    ///
+    /// Returns how many activations were cancelled.
+    pub fn clear_group(&mut self, group: &str) -> Result<usize> {
+        Ok(self.agenda.clear_group(group)?.len())
+    }
+
+    /// Get the scratchpad for state shared between rule firings
+    pub fn kv(&mut self) -> &mut Scratchpad {
+        &mut self.kv
+    }
+
+    /// Make a service or value available to rule actions under a name
+    ///
+    /// Replaces any global of the same name, whatever its type.
+    pub fn set_global<T: Any + Send + Sync>(&mut self, name: impl Into<String>, value: T) {
+        self.globals.insert(name.into(), Arc::new(value));
+    }
+
+    /// Share an already reference counted global, see [`Session::set_global`]
+    pub fn set_global_arc(&mut self, name: impl Into<String>, value: Global) {
+        self.globals.insert(name.into(), value);
+    }
+
+    /// Get a global by name, or `None` if it is missing or not a `T`
+    pub fn global<T: Any + Send + Sync>(&self, name: &str) -> Option<Arc<T>> {
+        Arc::clone(self.globals.get(name)?).downcast().ok()
+    }
+
+    /// Remove a global, returning whether there was one
+    pub fn remove_global(&mut self, name: &str) -> bool {
+        self.globals.remove(name).is_some()
+    }
+
+    /// Set whether an agenda group leaves the focus stack once it runs empty
+    pub fn set_auto_deactivate(&mut self, group: &str, auto_deactivate: bool) {
+        self.agenda.set_auto_deactivate(group, auto_deactivate);
+    }
+
+    /// Get the clock time windows and timers read
+    pub fn clock(&self) -> Arc<dyn SessionClock> {
+        Arc::clone(self.memory.clock())
+    }
+
+    /// Set the clock time windows and timers read, e.g. a
+    /// [`PseudoClock`](crate::clock::PseudoClock) in tests
+    pub fn set_clock(&mut self, clock: Arc<dyn SessionClock>) {
+        self.agenda.set_clock(Arc::clone(&clock));
+        self.memory.set_clock(clock);
+    }
+
+    /// Move the session's clock forward, expiring time windows and putting
+    /// due timer matches on the agenda
+    ///
+    /// A real-time clock ignores the advance, but windows and timers are
+    /// still checked.
+    pub fn advance_time(&mut self, by: Duration) -> Result<()> {
+        self.memory.clock().advance(by);
+        self.catch_up()
+    }
+
+    /// Apply the time that passed to windows and timers
+    fn catch_up(&mut self) -> Result<()> {
+        self.sync_networks()?;
+        let root = self.root.read().map_err(|e| {
+            crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
+        })?;
+        root.expire_windows(&mut self.memory)?;
+        cancel_evicted(&mut self.memory, &mut self.agenda);
+        self.agenda.release_due()?;
+        self.check_invariants("advance_time");
+        self.record_agenda_depth();
+        Ok(())
+    }
+
     /// Halt execution
     pub fn halt(&mut self) {
         self.halted = true;
@@ -121,87 +954,718 @@ impl Session {
         self.halted
     }
 
-    /// Match and fire rules once
-    pub async fn match_rules(&mut self) -> Result<usize> {
-        let mut fired_count = 0;
+    /// Abort firing runs that exceed the guard's limits
+    pub fn set_fire_guard(&mut self, guard: FireGuard) {
+        self.fire_guard = guard;
+    }
 
-        while !self.agenda.is_empty() && !self.halted {
-            if let Some(activation) = self.agenda.pop() {
-                activation.rule.fire(self, &activation.match_data)?;
-                fired_count += 1;
-            }
-        }
+    /// Decide what happens when a rule's action returns an error or panics
+    ///
+    /// Defaults to [`ErrorPolicy::Halt`]; rules built with
+    /// [`RuleBuilder::on_error`](crate::rule::RuleBuilder::on_error) override it.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
 
-        Ok(fired_count)
+    /// Set the seed deciding which matches of sampled rules fire
+    pub fn set_sample_seed(&mut self, seed: u64) {
+        self.agenda.set_sample_seed(seed);
     }
 
-    /// Match and fire rules until halt is called
-    pub async fn match_until_halt(&mut self) -> Result<usize> {
-        let mut fired_count = 0;
+    /// Report agenda groups starved of focus after each firing run
+    pub fn monitor_starvation(&mut self, monitor: StarvationMonitor) {
+        self.starvation_monitor = Some(monitor);
+    }
 
-        while !self.halted {
-            if let Some(activation) = self.agenda.pop() {
-                activation.rule.fire(self, &activation.match_data)?;
-                fired_count += 1;
-            } else {
-                // No more activations, wait a bit or break
-                // In a real implementation, this might wait for new facts
-                break;
-            }
-        }
+    /// Collect facts asserted from now on that produce no activation
+    pub fn collect_unmatched(&mut self) {
+        self.unmatched
+            .get_or_insert_with(UnmatchedCollector::default);
+    }
 
-        Ok(fired_count)
+    /// Get the facts asserted during the last match cycle that produced no activation
+    ///
+    /// Empty unless [`Session::collect_unmatched`] was called.
+    pub fn unmatched_facts(&self) -> &[FactId] {
+        self.unmatched
+            .as_ref()
+            .map(|collector| collector.last_cycle.as_slice())
+            .unwrap_or(&[])
     }
 
-    /// Dispose of this session
-    pub fn dispose(&mut self) {
-        self.working_memory.dispose();
-        self.agenda.dispose();
+    /// Collect per-rule statistics from now on
+    pub fn collect_stats(&mut self) {
+        if self.stats.is_none() {
+            let collector = Arc::new(StatsCollector::default());
+            self.add_event_listener(collector.clone());
+            self.memory.measure_match_times();
+            self.stats = Some(collector);
+        }
     }
 
-    /// Get the number of facts in working memory
-    pub fn fact_count(&self) -> usize {
-        self.working_memory.len()
+    /// Get the activation counts, firing counts and timings of each rule
+    ///
+    /// Empty unless [`Session::collect_stats`] was called.
+    pub fn stats(&self) -> Stats {
+        self.stats
+            .as_ref()
+            .map(|collector| collector.stats(self.memory.match_times()))
+            .unwrap_or_default()
     }
 
-    /// Check if working memory is empty
-    pub fn is_empty(&self) -> bool {
-        self.working_memory.is_empty()
+    /// Get how many fact handles and activations were built in recycled
+    /// allocations, see [`crate::pool`]
+    #[cfg(feature = "pool")]
+    pub fn pool_stats(&self) -> crate::pool::PoolStats {
+        self.working_memory
+            .pool_stats()
+            .merge(self.memory.pool_stats())
     }
-}
 
-impl Drop for Session {
-    fn drop(&mut self) {
-        self.dispose();
+    /// Report the memory held by the network's nodes, working memory and the
+    /// agenda, see [`crate::memory_report`]
+    pub fn memory_report(&self) -> Result<MemoryReport> {
+        let root = self.root.read().map_err(|e| {
+            crate::error::Error::Execution(format!("Failed to acquire lock: {}", e))
+        })?;
+        Ok(MemoryReport::new(
+            &root,
+            &self.memory,
+            &self.working_memory.get_all(),
+            &self.agenda,
+        ))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::agenda::ConflictResolution;
+    /// Record which constraints held for every activation created from now on
+    ///
+    /// See [`Activation::explain`].
+    pub fn record_explanations(&mut self) {
+        if !self.explanations {
+            self.explanations = true;
+            self.add_event_listener(Arc::new(ExplanationRecorder));
+        }
+    }
 
-    #[derive(Debug, Clone)]
-    struct TestFact {
-        value: i32,
+    /// Log every fact change and rule firing from now on, see [`crate::audit`]
+    ///
+    /// Facts of the types registered in `types` are snapshotted as JSON.
+    /// Calling this again while recording has no effect.
+    pub fn record_audit_log(&mut self, types: FixtureTypes) {
+        if self.audit.is_none() {
+            let recorder = Arc::new(AuditRecorder::new(types));
+            self.add_event_listener(recorder.clone());
+            self.audit = Some(recorder);
+        }
     }
 
-    #[test]
-    fn test_session_creation() {
-        let root = Arc::new(RwLock::new(RootNode::new()));
-        let session = Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+    /// Get the audit log recorded so far
+    ///
+    /// Empty unless [`Session::record_audit_log`] was called.
+    pub fn audit_log(&self) -> AuditLog {
+        match &self.audit {
+            Some(recorder) => recorder.log(&self.flow_name),
+            None => AuditLog {
+                flow: self.flow_name.clone(),
+                entries: Vec::new(),
+            },
+        }
+    }
 
-        assert_eq!(session.flow_name(), "test");
-        assert!(session.is_empty());
+    /// Register a listener for fact, activation and rule events
+    pub fn add_event_listener(&mut self, listener: Arc<dyn EventListener>) {
+        self.agenda.add_listener(Arc::clone(&listener));
+        self.listeners.add(listener);
     }
 
-    #[test]
-    fn test_assert_and_retract() {
-        let root = Arc::new(RwLock::new(RootNode::new()));
-        let mut session = Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+    /// Mark the current state of working memory for [`Session::diff_since`]
+    ///
+    /// Fact changes are journaled from the first mark on.
+    pub fn mark(&mut self) -> Marker {
+        let listeners = &mut self.listeners;
+        self.journal
+            .get_or_insert_with(|| {
+                let journal = Arc::new(ChangeJournal::default());
+                listeners.add(journal.clone());
+                journal
+            })
+            .mark()
+    }
 
-        let fact = TestFact { value: 42 };
-        let id = session.assert(fact).unwrap();
+    /// Get the facts asserted, modified and retracted since a marker
+    pub fn diff_since(&self, marker: Marker) -> FactDiff {
+        self.journal
+            .as_ref()
+            .map(|journal| journal.diff_since(marker))
+            .unwrap_or_default()
+    }
+
+    /// Set how activations created by a firing rule's action are prioritized
+    pub fn set_priority_inheritance(&mut self, inheritance: PriorityInheritance) {
+        self.agenda.set_priority_inheritance(inheritance);
+    }
+
+    /// Fire an activation, applying its error policy
+    ///
+    /// Returns whether the action succeeded; `false` means the activation
+    /// was skipped after failing.
+    fn fire(&mut self, activation: &Activation) -> Result<bool> {
+        let policy = activation.rule.error_policy.unwrap_or(self.error_policy);
+        let mut retries = match policy {
+            ErrorPolicy::Retry { attempts } => attempts,
+            _ => 0,
+        };
+        loop {
+            match self.run_action(activation) {
+                Ok(()) => {
+                    self.listeners.notify(|l| l.on_rule_fired(activation));
+                    if self.session_store.is_some() {
+                        self.unsaved_firings += 1;
+                        if self.unsaved_firings == self.checkpoint_interval {
+                            self.checkpoint_store();
+                        }
+                    }
+                    return Ok(true);
+                }
+                Err(e) => {
+                    self.listeners
+                        .notify(|l| l.on_action_failed(activation, &e));
+                    if retries > 0 {
+                        retries -= 1;
+                    } else if policy == ErrorPolicy::SkipRule {
+                        return Ok(false);
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hand a fired activation back to the network memory's pool
+    fn recycle(&mut self, activation: Arc<Activation>) {
+        #[cfg(feature = "pool")]
+        self.memory.recycle(activation);
+        #[cfg(not(feature = "pool"))]
+        drop(activation);
+    }
+
+    /// Run an activation's action, catching panics
+    fn run_action(&mut self, activation: &Activation) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "fire",
+            flow = %self.flow_name,
+            rule = %activation.rule.name,
+            facts = ?activation.match_data.fact_ids()
+        )
+        .entered();
+        let previous = self.agenda.begin_firing(activation.salience());
+        let view = match activation.generation() {
+            Some(generation) if !activation.rule.live_reads => Some(ReadView {
+                generation,
+                live_after: self.working_memory.generation(),
+            }),
+            _ => None,
+        };
+        let previous_view = std::mem::replace(&mut self.read_view, view);
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            activation.rule.fire(self, &activation.match_data)
+        }));
+        let elapsed = start.elapsed();
+        if let Some(collector) = &self.stats {
+            collector.fired(&activation.rule.name, elapsed);
+        }
+        #[cfg(feature = "metrics")]
+        metrics::histogram!(
+            "nools_fire_duration_seconds",
+            "flow" => self.flow_name.clone(),
+            "rule" => activation.rule.name.clone()
+        )
+        .record(elapsed);
+        self.read_view = previous_view;
+        self.agenda.end_firing(previous);
+        self.advance_generation();
+        self.record_agenda_depth();
+        match result {
+            Ok(result) => result.map_err(|e| crate::error::Error::ActionFailed {
+                rule: activation.rule.name.clone(),
+                facts: activation.match_data.fact_ids(),
+                source: Box::new(e),
+            })?,
+            Err(payload) => {
+                return Err(crate::error::Error::ActionPanicked {
+                    rule: activation.rule.name.clone(),
+                    message: panic_message(payload.as_ref()),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Bookkeeping at the end of a firing run
+    fn end_cycle(&mut self) {
+        if let Some(monitor) = &mut self.starvation_monitor {
+            monitor.check(&self.agenda);
+        }
+        if let Some(collector) = &mut self.unmatched {
+            collector.end_cycle(&self.working_memory);
+        }
+        if self.unsaved_firings > 0 {
+            self.checkpoint_store();
+        }
+    }
+
+    /// Match and fire rules once
+    ///
+    /// Timer matches that are due by the session's clock fire too.
+    pub async fn match_rules(&mut self) -> Result<usize> {
+        self.sync_networks()?;
+        self.agenda.release_due()?;
+        let mut run = self.fire_guard.start();
+        let mut fired_count = 0;
+
+        while !self.agenda.is_empty() && !self.halted {
+            if let Some(activation) = self.agenda.pop() {
+                run.admit(&activation)?;
+                if self.fire(&activation)? {
+                    fired_count += 1;
+                }
+                self.recycle(activation);
+            }
+        }
+
+        self.end_cycle();
+        Ok(fired_count)
+    }
+
+    /// Match and fire rules, running the parallel actions of each batch at once
+    ///
+    /// Pops batches of up to `max_batch` activations that may fire
+    /// simultaneously, see [`Agenda::pop_batch`], and runs the actions set
+    /// with [`RuleBuilder::then_parallel`] of a batch on rayon's thread pool.
+    /// The batch then fires in firing order, each activation applying the
+    /// effects computed for it while other actions run as usual. Activations
+    /// of a batch are trusted not to interfere, as their concurrency keys
+    /// declare: only those whose facts an earlier one retracted are dropped.
+    /// When the fire guard trips, an action fails or the session halts, the
+    /// activations of the batch that did not fire go back on the agenda.
+    ///
+    /// [`RuleBuilder::then_parallel`]: crate::rule::RuleBuilder::then_parallel
+    #[cfg(feature = "parallel")]
+    pub async fn match_rules_parallel(&mut self, max_batch: usize) -> Result<usize> {
+        use rayon::prelude::*;
+
+        self.sync_networks()?;
+        self.agenda.release_due()?;
+        let mut run = self.fire_guard.start();
+        let mut fired_count = 0;
+
+        while !self.agenda.is_empty() && !self.halted {
+            let batch = self.agenda.pop_batch(max_batch);
+            if batch.is_empty() {
+                break;
+            }
+            let effects: Vec<_> = batch
+                .par_iter()
+                .map(|activation| {
+                    let action = activation.rule.parallel_action.as_ref()?;
+                    // A panicking action runs again when it fires, which reports the panic
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        let mut effects = Effects::default();
+                        action(&activation.match_data, &mut effects).map(|()| effects)
+                    }))
+                    .ok()
+                })
+                .collect();
+            let mut batch = batch.into_iter().zip(effects);
+            while let Some((activation, effects)) = batch.next() {
+                if !self.still_matches(&activation) {
+                    self.recycle(activation);
+                    continue;
+                }
+                if let Err(e) = run.admit(&activation) {
+                    let rest = batch.map(|(activation, _)| activation);
+                    self.requeue(std::iter::once(activation).chain(rest))?;
+                    return Err(e);
+                }
+                self.effects = effects;
+                let fired = self.fire(&activation);
+                self.effects = None;
+                self.recycle(activation);
+                match fired {
+                    Ok(true) => fired_count += 1,
+                    Ok(false) => {}
+                    Err(e) => {
+                        self.requeue(batch.map(|(activation, _)| activation))?;
+                        return Err(e);
+                    }
+                }
+                if self.halted {
+                    self.requeue(batch.map(|(activation, _)| activation))?;
+                    break;
+                }
+            }
+        }
+
+        self.end_cycle();
+        Ok(fired_count)
+    }
+
+    /// Check that every fact an activation matched is still in working memory
+    #[cfg(feature = "parallel")]
+    fn still_matches(&self, activation: &Activation) -> bool {
+        activation
+            .match_data
+            .facts
+            .values()
+            .all(|fact| self.working_memory.get(fact.id).is_some())
+    }
+
+    /// Put the unfired rest of a batch back on the agenda, dropping the
+    /// activations whose facts were retracted meanwhile
+    #[cfg(feature = "parallel")]
+    fn requeue(&mut self, activations: impl Iterator<Item = Arc<Activation>>) -> Result<()> {
+        let current: Vec<_> = activations
+            .filter(|activation| self.still_matches(activation))
+            .collect();
+        self.agenda.requeue(current)
+    }
+
+    /// Take the effects computed ahead for the firing rule's parallel action
+    pub(crate) fn take_effects(&mut self) -> Option<Result<Effects>> {
+        self.effects.take()
+    }
+
+    /// Match and fire rules, firing at most `max_fires` activations
+    ///
+    /// Guards against rules that keep activating each other: activations left
+    /// when the limit is reached stay on the agenda.
+    pub async fn match_rules_with_limit(&mut self, max_fires: usize) -> Result<LimitedFiring> {
+        self.sync_networks()?;
+        self.agenda.release_due()?;
+        let mut run = self.fire_guard.start();
+        let mut fired = 0;
+
+        while fired < max_fires && !self.agenda.is_empty() && !self.halted {
+            if let Some(activation) = self.agenda.pop() {
+                run.admit(&activation)?;
+                if self.fire(&activation)? {
+                    fired += 1;
+                }
+                self.recycle(activation);
+            }
+        }
+
+        self.end_cycle();
+        Ok(LimitedFiring {
+            fired,
+            limit_reached: fired == max_fires && !self.halted && !self.agenda.is_empty(),
+        })
+    }
+
+    /// Fire only the activations of rules matching the predicate
+    ///
+    /// Activations of other rules stay on the agenda.
+    pub async fn fire_where<F>(&mut self, f: F) -> Result<usize>
+    where
+        F: Fn(&Rule) -> bool,
+    {
+        self.match_rules_filtered(|activation| f(&activation.rule))
+            .await
+    }
+
+    /// Fire only the pending activations accepted by the filter
+    ///
+    /// Rejected activations stay on the agenda, like an agenda filter: use it
+    /// to fire only rules with a tag, or only activations touching a fact.
+    pub async fn match_rules_filtered<F>(&mut self, filter: F) -> Result<usize>
+    where
+        F: Fn(&Activation) -> bool,
+    {
+        self.sync_networks()?;
+        self.agenda.release_due()?;
+        let mut run = self.fire_guard.start();
+        let mut fired_count = 0;
+
+        while !self.halted {
+            match self.agenda.pop_filtered(&filter) {
+                Some(activation) => {
+                    run.admit(&activation)?;
+                    if self.fire(&activation)? {
+                        fired_count += 1;
+                    }
+                    self.recycle(activation);
+                }
+                None => break,
+            }
+        }
+
+        self.end_cycle();
+        Ok(fired_count)
+    }
+
+    /// Match and fire rules until halt is called
+    ///
+    /// While timer matches are pending, waits on the session's clock for the
+    /// next one instead of returning: a real-time clock blocks the thread,
+    /// a pseudo clock jumps ahead.
+    pub async fn match_until_halt(&mut self) -> Result<usize> {
+        self.sync_networks()?;
+        let mut run = self.fire_guard.start();
+        let mut fired_count = 0;
+
+        while !self.halted {
+            if let Some(activation) = self.agenda.pop() {
+                run.admit(&activation)?;
+                if self.fire(&activation)? {
+                    fired_count += 1;
+                }
+                self.recycle(activation);
+            } else if let Some(due) = self.agenda.next_timer() {
+                self.memory.clock().wait_until(due);
+                self.catch_up()?;
+            } else {
+                break;
+            }
+        }
+
+        self.end_cycle();
+        Ok(fired_count)
+    }
+
+    /// Start capturing asserted facts into a fixture
+    pub fn capture(&mut self, capture: FixtureCapture) {
+        self.capture = Some(capture);
+    }
+
+    /// Stop capturing and return the capture
+    pub fn take_capture(&mut self) -> Option<FixtureCapture> {
+        self.capture.take()
+    }
+
+    /// Capture the agenda and scratchpad in a serializable checkpoint
+    pub fn checkpoint(&self) -> Checkpoint {
+        let mut checkpoint = self.agenda.checkpoint(&self.flow_name);
+        checkpoint.scratchpad = self.kv.values().clone();
+        checkpoint
+    }
+
+    /// Restore a checkpoint taken from a session of the same flow
+    ///
+    /// Re-assert the facts first; `fact_ids` maps each checkpointed fact id to
+    /// the id of its re-asserted copy.
+    pub fn restore(
+        &mut self,
+        checkpoint: &Checkpoint,
+        fact_ids: &HashMap<FactId, FactId>,
+    ) -> Result<()> {
+        if checkpoint.flow != self.flow_name {
+            return Err(crate::error::Error::Execution(format!(
+                "Checkpoint of flow '{}' cannot be restored into flow '{}'",
+                checkpoint.flow, self.flow_name
+            )));
+        }
+        self.agenda.restore(checkpoint, fact_ids)?;
+        self.kv.set_values(checkpoint.scratchpad.clone());
+        Ok(())
+    }
+
+    /// Serialize the facts in working memory, oldest first
+    ///
+    /// Fails if a fact's type is not registered in `types`.
+    pub fn snapshot(&self, types: &FixtureTypes) -> Result<crate::snapshot::MemorySnapshot> {
+        crate::snapshot::MemorySnapshot::capture(&self.working_memory, types)
+    }
+
+    /// Keep the facts of this session in a store from now on, see [`crate::store`]
+    ///
+    /// Facts already in the store are asserted first and every fact of the
+    /// session is written to it, so facts of unregistered types fail the call.
+    /// Returns a map from each previously stored fact id to the id of its
+    /// asserted copy, ready to pass to [`Session::restore`].
+    pub fn attach_store(
+        &mut self,
+        store: Arc<dyn crate::store::WorkingMemoryStore>,
+        types: FixtureTypes,
+    ) -> Result<HashMap<FactId, FactId>> {
+        if self.store.is_some() {
+            return Err(crate::error::Error::Execution(
+                "A store is already attached to this session".to_string(),
+            ));
+        }
+        let mut fact_ids = HashMap::new();
+        let mut after = None;
+        loop {
+            let stored = crate::snapshot::MemorySnapshot {
+                facts: store.load_page(after, crate::store::PAGE_SIZE)?,
+            };
+            let Some(last) = stored.facts.last() else {
+                break;
+            };
+            after = Some(last.id);
+            fact_ids.extend(self.restore_facts(&stored, &types)?);
+        }
+
+        let writer = Arc::new(crate::store::StoreWriter::new(Arc::clone(&store), types));
+        let current = self.working_memory.get_all();
+        for fact in &current {
+            writer.put(fact)?;
+        }
+        let current: HashSet<_> = current.iter().map(|fact| fact.id).collect();
+        for stored in fact_ids.keys() {
+            if !current.contains(stored) {
+                store.remove(*stored)?;
+            }
+        }
+        store.flush()?;
+
+        self.add_event_listener(writer.clone());
+        self.store = Some(writer);
+        Ok(fact_ids)
+    }
+
+    /// Keep the facts and agenda of this session in a store from now on
+    ///
+    /// Attaches the store like [`Session::attach_store`], restores the
+    /// checkpoint it holds, and checkpoints the agenda to it at the end of each
+    /// run, or every [`Session::set_checkpoint_interval`] firings. Usually
+    /// called through [`Flow::restore_session`].
+    ///
+    /// [`Flow::restore_session`]: crate::flow::Flow::restore_session
+    pub fn attach_session_store(
+        &mut self,
+        store: Arc<dyn crate::store::SessionStore>,
+        types: FixtureTypes,
+    ) -> Result<()> {
+        let fact_ids = self.attach_store(store.clone(), types)?;
+        if let Some(checkpoint) = store.load_checkpoint()? {
+            self.restore(&checkpoint, &fact_ids)?;
+        }
+        store.save_checkpoint(&self.checkpoint())?;
+        self.session_store = Some(store);
+        Ok(())
+    }
+
+    /// Checkpoint the agenda every `firings` rule firings
+    ///
+    /// Sessions attached with [`Session::attach_session_store`] checkpoint at
+    /// the end of each run by default (an interval of 0). A shorter interval
+    /// refires fewer rules after a crash, at the cost of serializing the agenda
+    /// more often.
+    pub fn set_checkpoint_interval(&mut self, firings: usize) {
+        self.checkpoint_interval = firings;
+    }
+
+    /// Make the fact changes written to the attached store durable
+    ///
+    /// Fails with the first write or checkpoint that failed since the last
+    /// flush. Sessions attached with [`Session::attach_session_store`] also
+    /// checkpoint their agenda.
+    pub fn flush_store(&mut self) -> Result<()> {
+        if let Some(error) = self.store_error.take() {
+            return Err(error);
+        }
+        if let Some(writer) = &self.store {
+            writer.flush()?;
+        }
+        if let Some(store) = &self.session_store {
+            self.unsaved_firings = 0;
+            store.save_checkpoint(&self.checkpoint())?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoint the agenda after firings, keeping a failure for [`Session::flush_store`]
+    ///
+    /// The firings already took effect, so a store error doesn't stop the run.
+    fn checkpoint_store(&mut self) {
+        if self.store_error.is_some() {
+            return;
+        }
+        if let Err(error) = self.flush_store() {
+            self.store_error = Some(error);
+        }
+    }
+
+    /// Assert the facts of a snapshot
+    ///
+    /// Returns a map from each snapshotted fact id to the id of its asserted
+    /// copy, ready to pass to [`Session::restore`].
+    pub fn restore_facts(
+        &mut self,
+        snapshot: &crate::snapshot::MemorySnapshot,
+        types: &FixtureTypes,
+    ) -> Result<HashMap<FactId, FactId>> {
+        let fact_ids = self.assert_all_boxed(snapshot.facts(types)?)?;
+        Ok(snapshot
+            .facts
+            .iter()
+            .map(|fact| fact.id)
+            .zip(fact_ids)
+            .collect())
+    }
+
+    /// Dispose of this session
+    pub fn dispose(&mut self) {
+        self.working_memory.dispose();
+        self.memory.clear();
+        self.agenda.dispose();
+        self.kv.clear();
+        self.globals.clear();
+    }
+
+    /// Get the number of facts in working memory
+    pub fn fact_count(&self) -> usize {
+        self.working_memory.len()
+    }
+
+    /// Check if working memory is empty
+    pub fn is_empty(&self) -> bool {
+        self.working_memory.is_empty()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.dispose();
+    }
+}
+
+/// Cancel pending activations binding facts that windows dropped
+fn cancel_evicted(memory: &mut NetworkMemory, agenda: &mut Agenda) {
+    for eviction in memory.take_evictions() {
+        agenda.cancel_binding(&eviction.rule, &eviction.alias, eviction.fact);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agenda::ConflictResolution;
+
+    #[derive(Debug, Clone)]
+    #[allow(dead_code)]
+    struct TestFact {
+        value: i32,
+    }
+
+    #[test]
+    fn test_session_creation() {
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let session = Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+
+        assert_eq!(session.flow_name(), "test");
+        assert!(session.is_empty());
+    }
+
+    #[test]
+    fn test_assert_and_retract() {
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let mut session = Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+
+        let fact = TestFact { value: 42 };
+        let id = session.assert(fact).unwrap();
 
         assert_eq!(session.fact_count(), 1);
 
@@ -220,4 +1684,1725 @@ mod tests {
         let facts = session.get_facts::<TestFact>();
         assert_eq!(facts.len(), 2);
     }
+
+    #[test]
+    fn test_iter_facts() {
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let mut session = Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+
+        let first = session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 2 }).unwrap();
+        session.assert(3u32).unwrap();
+
+        let facts = session.iter_facts::<TestFact>();
+        assert_eq!(facts.len(), 2);
+        let values: Vec<_> = facts.iter().map(|(_, fact)| fact.value).collect();
+        assert_eq!(values, [1, 2]);
+        let (id, fact) = (&facts).into_iter().next().unwrap();
+        assert_eq!((id, fact.value), (first.id(), 1));
+    }
+
+    #[test]
+    fn test_find() {
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let mut session = Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 2 }).unwrap();
+
+        let found = session.find(|fact: &TestFact| fact.value == 2);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.downcast_ref::<TestFact>().unwrap().value, 2);
+        assert!(session.find(|fact: &TestFact| fact.value > 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retract_before_fire_cancels_activation() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fired);
+
+        let mut flow = Flow::new("test");
+        flow.rule("count")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        let id = session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 2 }).unwrap();
+        session.retract(id).unwrap();
+
+        let count = session.match_rules().await.unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_modify_allows_rule_to_fire_again() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("any")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        let id = session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        session.modify(id).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+    }
+
+    /// Flow whose independent rules retract odd numbers and double the rest
+    fn parallel_flow() -> crate::flow::Flow {
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = crate::flow::Flow::new("test");
+        flow.rule("drop_odd")
+            .priority(10)
+            .independent()
+            .when(Box::new(ObjectPattern::<u32>::new("n")) as Box<dyn Pattern>)
+            .then_parallel(|m, effects| {
+                if m.get_as::<u32>("n")? % 2 == 1 {
+                    effects.retract(m.get("n").unwrap().id);
+                }
+                Ok(())
+            })
+            .unwrap();
+        flow.rule("double")
+            .independent()
+            .when(Box::new(ObjectPattern::<u32>::new("n")) as Box<dyn Pattern>)
+            .then_parallel(|m, effects| {
+                effects.assert(format!("{}", m.get_as::<u32>("n")? * 2));
+                Ok(())
+            })
+            .unwrap();
+        flow
+    }
+
+    #[tokio::test]
+    async fn test_parallel_action_applies_effects_when_fired() {
+        let mut session = parallel_flow().session();
+        session.assert_all([1u32, 2, 3]).unwrap();
+
+        assert_eq!(session.match_rules().await.unwrap(), 4);
+        assert_eq!(session.get_facts::<String>().len(), 1);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[tokio::test]
+    async fn test_match_rules_parallel_fires_batches() {
+        let mut session = parallel_flow().session();
+        session.assert_all([1u32, 2, 3]).unwrap();
+
+        assert_eq!(session.match_rules_parallel(16).await.unwrap(), 4);
+        let doubled: Vec<_> = session
+            .iter_facts::<String>()
+            .iter()
+            .map(|(_, fact)| fact.to_string())
+            .collect();
+        assert_eq!(doubled, ["4"]);
+        assert_eq!(session.get_facts::<u32>().len(), 1);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[tokio::test]
+    async fn test_match_rules_parallel_requeues_unfired_batch() {
+        let mut session = parallel_flow().session();
+        session.assert_all([2u32, 4, 6]).unwrap();
+        session.set_fire_guard(FireGuard::new().max_fires(2));
+
+        let error = session.match_rules_parallel(16).await.unwrap_err();
+        assert!(matches!(
+            error,
+            crate::error::Error::FireLimitExceeded { fired: 2, .. }
+        ));
+
+        session.set_fire_guard(FireGuard::new());
+        assert_eq!(session.match_rules().await.unwrap(), 4);
+        assert_eq!(session.get_facts::<String>().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_restore_revalidates_facts() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&fired);
+
+        let mut flow = Flow::new("test");
+        flow.rule("record")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(move |session, m| {
+                let value = m
+                    .get("t")
+                    .unwrap()
+                    .downcast_ref::<TestFact>()
+                    .unwrap()
+                    .value;
+                log.lock().unwrap().push(value);
+                session.kv().update::<u32, _>("fired", |n| *n += 1)?;
+                if value == 3 {
+                    session.halt();
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        let ids: Vec<_> = (1..=3)
+            .map(|value| session.assert(TestFact { value }).unwrap())
+            .collect();
+        session.match_rules().await.unwrap();
+        let checkpoint = Checkpoint::from_json(&session.checkpoint().to_json().unwrap()).unwrap();
+        assert_eq!(checkpoint.pending.len(), 2);
+        assert_eq!(checkpoint.fired.len(), 1);
+
+        // Fact 2 did not survive the restart
+        let mut restored = flow.session();
+        let mut fact_ids = HashMap::new();
+        fact_ids.insert(
+            ids[0].id(),
+            restored.assert(TestFact { value: 1 }).unwrap().id(),
+        );
+        fact_ids.insert(
+            ids[2].id(),
+            restored.assert(TestFact { value: 3 }).unwrap().id(),
+        );
+        restored.restore(&checkpoint, &fact_ids).unwrap();
+
+        assert_eq!(restored.kv().get::<u32>("fired"), Some(1));
+        assert_eq!(restored.match_rules().await.unwrap(), 1);
+        assert_eq!(*fired.lock().unwrap(), vec![3, 1]);
+        assert_eq!(restored.kv().get::<u32>("fired"), Some(2));
+        assert!(Flow::new("other")
+            .session()
+            .restore(&checkpoint, &fact_ids)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fire_where_selects_tagged_rules() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        for (name, tag) in [
+            ("velocity", "fraud"),
+            ("geo", "fraud"),
+            ("invoice", "billing"),
+        ] {
+            flow.rule(name)
+                .tag(tag)
+                .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+                .then(|_, _| Ok(()))
+                .unwrap();
+        }
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+
+        let fired = session
+            .fire_where(|rule| rule.has_tag("fraud"))
+            .await
+            .unwrap();
+        assert_eq!(fired, 2);
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_match_rules_filtered_by_fact() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("any")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        let target = session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 2 }).unwrap();
+
+        let fired = session
+            .match_rules_filtered(|activation| {
+                activation.match_data.fact_ids().contains(&target.id())
+            })
+            .await
+            .unwrap();
+        assert_eq!(fired, 1);
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_starved_group_is_reported_once() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let mut flow = Flow::new("test");
+        flow.rule("escalate")
+            .agenda_group("review")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&reports);
+        let mut session = flow.session();
+        session.monitor_starvation(StarvationMonitor::new(Duration::ZERO, move |group| {
+            log.lock()
+                .unwrap()
+                .push((group.name.clone(), group.pending));
+        }));
+
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.match_rules().await.unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(*reports.lock().unwrap(), vec![("review".to_string(), 1)]);
+
+        session.focus("review").unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(reports.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_modify_with_rematches_new_data() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("large")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 10, "value > 10"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        let id = session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+
+        session
+            .modify_with(id, |t: &mut TestFact| t.value = 50)
+            .unwrap();
+        let handle = session.get_fact(id).unwrap();
+        assert_eq!(handle.downcast_ref::<TestFact>().unwrap().value, 50);
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        session
+            .modify_with(id, |t: &mut TestFact| t.value = 2)
+            .unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+        assert!(session.modify_with(id, |_: &mut String| {}).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_modify_fields_rematches_only_readers() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+
+        #[derive(Debug, Clone)]
+        struct Ticket {
+            priority: i32,
+            note: String,
+        }
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut flow = Flow::new("test");
+        flow.rule("urgent")
+            .when(Box::new(
+                ObjectPattern::<Ticket>::new("t")
+                    .reads(&["priority"])
+                    .with_filter(|t| t.priority > 5, "priority > 5"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        let log = Arc::clone(&seen);
+        flow.rule("annotate")
+            .agenda_group("notes")
+            .when(
+                Box::new(ObjectPattern::<Ticket>::new("t").reads(&["priority"]))
+                    as Box<dyn Pattern>,
+            )
+            .then(move |_, m| {
+                log.lock()
+                    .unwrap()
+                    .push(m.get_as::<Ticket>("t")?.note.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        let id = session
+            .assert(Ticket {
+                priority: 9,
+                note: "new".to_string(),
+            })
+            .unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        // Neither rule reads the note: "urgent" does not fire again and the
+        // pending "annotate" activation sees the new note
+        session
+            .modify_fields_with(id, &["note"], |t: &mut Ticket| {
+                t.note = "triaged".to_string()
+            })
+            .unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+        session.focus("notes").unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(*seen.lock().unwrap(), vec!["triaged".to_string()]);
+
+        session.modify_fields(id, &["priority"]).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_facts_per_cycle() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("positive")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 0, "value > 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: -1 }).unwrap();
+        session.collect_unmatched();
+
+        session.assert(TestFact { value: 1 }).unwrap();
+        let ignored = session.assert(TestFact { value: -2 }).unwrap();
+        let retracted = session.assert(TestFact { value: -3 }).unwrap();
+        session.assert("unknown".to_string()).unwrap();
+        session.retract(retracted).unwrap();
+        session.match_rules().await.unwrap();
+
+        assert_eq!(session.unmatched_facts().len(), 2);
+        assert_eq!(session.unmatched_facts()[0], ignored);
+
+        session.match_rules().await.unwrap();
+        assert!(session.unmatched_facts().is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_metrics() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use metrics::{
+            Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+            Unit,
+        };
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Samples(Mutex<Vec<f64>>);
+
+        impl HistogramFn for Samples {
+            fn record(&self, value: f64) {
+                self.0.lock().unwrap().push(value);
+            }
+        }
+
+        /// Keeps every metric, by name and labels
+        #[derive(Default)]
+        struct Registry {
+            values: Mutex<HashMap<String, Arc<AtomicU64>>>,
+            samples: Mutex<HashMap<String, Arc<Samples>>>,
+        }
+
+        fn describe(key: &Key) -> String {
+            let labels: Vec<_> = key
+                .labels()
+                .map(|l| format!("{}={}", l.key(), l.value()))
+                .collect();
+            format!("{}{{{}}}", key.name(), labels.join(","))
+        }
+
+        impl Registry {
+            fn value(&self, key: &Key) -> Arc<AtomicU64> {
+                let mut values = self.values.lock().unwrap();
+                Arc::clone(values.entry(describe(key)).or_default())
+            }
+
+            fn counter(&self, key: &str) -> u64 {
+                self.values.lock().unwrap()[key].load(Ordering::SeqCst)
+            }
+
+            fn gauge(&self, key: &str) -> f64 {
+                f64::from_bits(self.counter(key))
+            }
+        }
+
+        impl Recorder for Registry {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.value(key))
+            }
+
+            fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::from_arc(self.value(key))
+            }
+
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                let mut samples = self.samples.lock().unwrap();
+                Histogram::from_arc(Arc::clone(samples.entry(describe(key)).or_default()))
+            }
+        }
+
+        let mut flow = Flow::new("orders");
+        flow.rule("positive")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 0, "value > 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let registry = Registry::default();
+        let guard = metrics::set_default_local_recorder(&registry);
+        let mut session = flow.session();
+        session
+            .assert_all([
+                TestFact { value: 1 },
+                TestFact { value: 2 },
+                TestFact { value: -1 },
+            ])
+            .unwrap();
+        assert_eq!(registry.gauge("nools_agenda_depth{flow=orders}"), 2.0);
+        session.match_rules().await.unwrap();
+        drop(guard);
+
+        assert_eq!(
+            registry.counter("nools_facts_asserted_total{flow=orders}"),
+            3
+        );
+        assert_eq!(
+            registry.counter("nools_rules_fired_total{flow=orders,rule=positive}"),
+            2
+        );
+        assert_eq!(registry.gauge("nools_agenda_depth{flow=orders}"), 0.0);
+        let samples = registry.samples.lock().unwrap();
+        assert_eq!(
+            samples["nools_fire_duration_seconds{flow=orders,rule=positive}"]
+                .0
+                .lock()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_tracing_spans_and_events() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Records span names and event messages in order
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl Visit for &Recorder {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0.lock().unwrap().push(format!("{:?}", value));
+                }
+            }
+        }
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut log = self.0.lock().unwrap();
+                log.push(format!("span {}", span.metadata().name()));
+                Id::from_u64(log.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                event.record(&mut &*self);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let mut flow = Flow::new("test");
+        flow.rule("positive")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 0, "value > 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let guard = tracing::subscriber::set_default(Arc::clone(&recorder));
+        let mut session = flow.session();
+        let id = session.assert(TestFact { value: 1 }).unwrap();
+        session.match_rules().await.unwrap();
+        session.retract(id).unwrap();
+        drop(guard);
+
+        let log = recorder.0.lock().unwrap();
+        assert_eq!(
+            *log,
+            [
+                "span assert",
+                "fact asserted",
+                "activation created",
+                "span fire",
+                "rule fired",
+                "span retract",
+                "fact retracted",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explain_activations() {
+        use crate::explain::{ConstraintResult, Explanation};
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Explanations(Mutex<Vec<Explanation>>);
+
+        impl EventListener for Explanations {
+            fn on_rule_fired(&self, activation: &Activation) {
+                self.0.lock().unwrap().push(activation.explain());
+            }
+        }
+
+        let mut flow = Flow::new("test");
+        flow.rule("pair")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t")
+                    .with_filter(|t| t.value > 0, "value > 0")
+                    .with_filter(|t| t.value < 10, "value < 10"),
+            ) as Box<dyn Pattern>)
+            .when(Box::new(ObjectPattern::<String>::new("s")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let explanations = Arc::new(Explanations::default());
+        let mut session = flow.session();
+        session.add_event_listener(explanations.clone());
+        session.assert("unrecorded".to_string()).unwrap();
+        let fact = session.assert(TestFact { value: 5 }).unwrap();
+        session.match_rules().await.unwrap();
+
+        session.record_explanations();
+        let name = session.assert("recorded".to_string()).unwrap();
+        session.match_rules().await.unwrap();
+
+        let explanations = explanations.0.lock().unwrap();
+        let [unrecorded, recorded] = explanations.as_slice() else {
+            panic!("expected two firings, got {:?}", explanations);
+        };
+        assert!(!unrecorded.recorded);
+        assert!(unrecorded.patterns[0].constraints.is_empty());
+
+        assert!(recorded.recorded);
+        assert_eq!(recorded.rule, "pair");
+        let aliases: Vec<_> = recorded.patterns.iter().map(|p| p.alias.as_str()).collect();
+        assert_eq!(aliases, ["t", "s"]);
+        assert_eq!(recorded.patterns[0].fact, fact);
+        assert_eq!(recorded.patterns[1].fact, name);
+        assert_eq!(recorded.patterns[1].type_name, "alloc::string::String");
+        assert_eq!(
+            recorded.patterns[0].constraints,
+            [
+                ConstraintResult {
+                    description: "value > 0".to_string(),
+                    passed: true,
+                },
+                ConstraintResult {
+                    description: "value < 10".to_string(),
+                    passed: true,
+                },
+            ]
+        );
+        assert!(recorded.to_string().contains("value < 10: passed"));
+    }
+
+    #[tokio::test]
+    async fn test_rule_stats() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("positive")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 0, "value > 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| {
+                std::thread::sleep(Duration::from_millis(2));
+                Ok(())
+            })
+            .unwrap();
+        flow.rule("negative")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value < 0, "value < 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert!(session.stats().is_empty());
+
+        session.collect_stats();
+        session.assert(TestFact { value: 2 }).unwrap();
+        session.assert(TestFact { value: -1 }).unwrap();
+        let cancelled = session.assert(TestFact { value: -2 }).unwrap();
+        session.retract(cancelled).unwrap();
+        session.match_rules().await.unwrap();
+
+        let stats = session.stats();
+        let positive = stats.rule("positive").unwrap();
+        assert_eq!(positive.activations, 1);
+        assert_eq!(positive.fires, 2);
+        assert!(positive.average_action_time() >= Duration::from_millis(2));
+        let negative = stats.rule("negative").unwrap();
+        assert_eq!((negative.activations, negative.fires), (2, 1));
+        assert!(negative.match_time > Duration::ZERO);
+        assert_eq!(stats.slowest()[0].0, "positive");
+    }
+
+    #[tokio::test]
+    async fn test_assert_all() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("pair")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .when(Box::new(ObjectPattern::<String>::new("s")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        let ids = session
+            .assert_all((0..3).map(|value| TestFact { value }))
+            .unwrap();
+        assert_eq!(ids.len(), 3);
+
+        let boxed: Vec<Box<dyn Fact>> =
+            vec![Box::new("a".to_string()), Box::new(TestFact { value: 3 })];
+        session.assert_all_boxed(boxed).unwrap();
+
+        assert_eq!(session.fact_count(), 5);
+        assert_eq!(session.match_rules().await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_event_listener() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl EventListener for Recorder {
+            fn on_fact_asserted(&self, fact: &FactHandle) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("assert {}", fact.id.as_u64()));
+            }
+            fn on_fact_retracted(&self, fact: &FactHandle) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("retract {}", fact.id.as_u64()));
+            }
+            fn on_fact_modified(&self, fact: &FactHandle) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("modify {}", fact.id.as_u64()));
+            }
+            fn on_activation_created(&self, activation: &Activation) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("create {}", activation.rule.name));
+            }
+            fn on_activation_cancelled(&self, activation: &Activation) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("cancel {}", activation.rule.name));
+            }
+            fn on_rule_fired(&self, activation: &Activation) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("fire {}", activation.rule.name));
+            }
+        }
+
+        let mut flow = Flow::new("test");
+        flow.rule("positive")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value > 0, "value > 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let mut session = flow.session();
+        session.add_event_listener(recorder.clone());
+
+        let first = session.assert(TestFact { value: 1 }).unwrap();
+        let second = session.assert(TestFact { value: 2 }).unwrap();
+        session.retract(second).unwrap();
+        session.match_rules().await.unwrap();
+        session
+            .modify_with(first, |t: &mut TestFact| t.value = -1)
+            .unwrap();
+
+        let events = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            [
+                format!("assert {}", first.as_u64()),
+                "create positive".to_string(),
+                format!("assert {}", second.as_u64()),
+                "create positive".to_string(),
+                format!("retract {}", second.as_u64()),
+                "cancel positive".to_string(),
+                "fire positive".to_string(),
+                format!("modify {}", first.as_u64()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_since_marker() {
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let mut session =
+            Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+        let kept = session.assert(TestFact { value: 1 }).unwrap();
+        let dropped = session.assert(TestFact { value: 2 }).unwrap();
+        let marker = session.mark();
+        assert!(session.diff_since(marker).is_empty());
+
+        let added = session.assert(TestFact { value: 3 }).unwrap();
+        let transient = session.assert(TestFact { value: 4 }).unwrap();
+        session
+            .modify_with(added, |t: &mut TestFact| t.value = 30)
+            .unwrap();
+        session
+            .modify_with(kept, |t: &mut TestFact| t.value = 10)
+            .unwrap();
+        session.retract(transient).unwrap();
+        session.retract(dropped).unwrap();
+
+        let diff = session.diff_since(marker);
+        let ids = |facts: &[Arc<FactHandle>]| facts.iter().map(|f| f.id).collect::<Vec<_>>();
+        assert_eq!(ids(&diff.asserted), [added]);
+        assert_eq!(ids(&diff.modified), [kept]);
+        assert_eq!(ids(&diff.retracted), [dropped]);
+        assert_eq!(
+            diff.modified[0].downcast_ref::<TestFact>().unwrap().value,
+            10
+        );
+        assert_eq!(
+            diff.asserted[0].downcast_ref::<TestFact>().unwrap().value,
+            30
+        );
+
+        let later = session.mark();
+        assert!(session.diff_since(later).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_actions_read_working_memory_as_of_activation() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut flow = Flow::new("test");
+        flow.rule("replace_one")
+            .priority(10)
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value == 1, "value == 1"),
+            ) as Box<dyn Pattern>)
+            .then(|session, match_data| {
+                session.retract(match_data.get("t").unwrap().id)?;
+                session.assert(TestFact { value: 3 })?;
+                Ok(())
+            })
+            .unwrap();
+        for (name, live) in [("snapshot", false), ("live", true)] {
+            let seen = Arc::clone(&seen);
+            flow.rule(name)
+                .live_reads(live)
+                .when(Box::new(
+                    ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value == 2, "value == 2"),
+                ) as Box<dyn Pattern>)
+                .then(move |session, _| {
+                    let mut values: Vec<_> = session
+                        .get_facts::<TestFact>()
+                        .iter()
+                        .map(|f| f.downcast_ref::<TestFact>().unwrap().value)
+                        .collect();
+                    values.sort();
+                    seen.lock().unwrap().push((name, values));
+                    Ok(())
+                })
+                .unwrap();
+        }
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 2 }).unwrap();
+        session.match_rules().await.unwrap();
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(seen, [("live", vec![2, 3]), ("snapshot", vec![1, 2])]);
+        assert_eq!(session.get_facts::<TestFact>().len(), 2);
+    }
+
+    #[test]
+    fn test_stale_handles() {
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let mut session =
+            Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+        let id = session.assert(TestFact { value: 1 }).unwrap();
+        let old = session.get_fact(id).unwrap();
+        assert!(old.is_current(&session));
+
+        session
+            .modify_with(id, |t: &mut TestFact| t.value = 2)
+            .unwrap();
+        assert!(!old.is_current(&session));
+        assert!(matches!(
+            session.modify_handle(&old),
+            Err(crate::error::Error::StaleFact { fact, .. }) if fact == id
+        ));
+        assert!(session.retract_handle(&old).is_err());
+
+        let current = session.get_fact(id).unwrap();
+        assert!(current.generation > old.generation);
+        session.modify_handle(&current).unwrap();
+        let current = session.get_fact(id).unwrap();
+        session.retract_handle(&current).unwrap();
+        assert!(!current.is_current(&session));
+        assert_eq!(session.fact_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore_facts() {
+        use crate::snapshot::MemorySnapshot;
+
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        struct Order {
+            total: u32,
+        }
+
+        let types = FixtureTypes::new().register::<Order>("Order");
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let mut session = Session::new(
+            "test".to_string(),
+            Arc::clone(&root),
+            vec![ConflictResolution::Salience],
+        );
+        let first = session.assert(Order { total: 10 }).unwrap();
+        session.assert(Order { total: 20 }).unwrap();
+        let json = session.snapshot(&types).unwrap().to_json().unwrap();
+
+        let mut restored =
+            Session::new("test".to_string(), root, vec![ConflictResolution::Salience]);
+        let fact_ids = restored
+            .restore_facts(&MemorySnapshot::from_json(&json).unwrap(), &types)
+            .unwrap();
+        assert_eq!(restored.fact_count(), 2);
+        let copy = restored.get_fact(fact_ids[&first.id()]).unwrap();
+        assert_eq!(copy.downcast_ref::<Order>().unwrap().total, 10);
+
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert!(session.snapshot(&types).is_err());
+    }
+
+    #[test]
+    fn test_attach_store() {
+        use crate::store::{MemoryStore, WorkingMemoryStore};
+
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        struct Order {
+            total: u32,
+        }
+
+        let types = FixtureTypes::new().register::<Order>("Order");
+        let store = Arc::new(MemoryStore::new());
+        let root = Arc::new(RwLock::new(RootNode::new()));
+        let session = |root: &Arc<RwLock<RootNode>>| {
+            Session::new(
+                "test".to_string(),
+                Arc::clone(root),
+                vec![ConflictResolution::Salience],
+            )
+        };
+
+        let mut first = session(&root);
+        let kept = first.assert(Order { total: 10 }).unwrap();
+        assert!(first
+            .attach_store(store.clone(), types.clone())
+            .unwrap()
+            .is_empty());
+        assert!(first.attach_store(store.clone(), types.clone()).is_err());
+        assert_eq!(store.len(), 1);
+
+        let dropped = first.assert(Order { total: 20 }).unwrap();
+        first
+            .modify_with(kept, |order: &mut Order| order.total = 15)
+            .unwrap();
+        first.retract(dropped).unwrap();
+        assert!(first.assert(TestFact { value: 1 }).is_err());
+        assert!(first.assert_all([TestFact { value: 2 }]).is_err());
+        assert_eq!(first.fact_count(), 1);
+        first.flush_store().unwrap();
+        assert_eq!(store.load().unwrap()[0].fact["total"], 15);
+
+        // A restarted session reloads the stored facts
+        let mut restarted = session(&root);
+        let fact_ids = restarted.attach_store(store.clone(), types).unwrap();
+        let reloaded = restarted.get_fact(fact_ids[&kept.id()]).unwrap();
+        assert_eq!(reloaded.downcast_ref::<Order>().unwrap().total, 15);
+        assert_eq!(restarted.fact_count(), 1);
+        assert_eq!(store.load().unwrap()[0].id, reloaded.id);
+    }
+
+    #[test]
+    fn test_attach_store_loads_facts_in_pages() {
+        use crate::store::{MemoryStore, PAGE_SIZE};
+
+        let types = FixtureTypes::new().register::<u32>("u32");
+        let store = Arc::new(MemoryStore::new());
+        let count = PAGE_SIZE as u32 + 2;
+        let mut first = crate::flow::Flow::new("test").session();
+        first.attach_store(store.clone(), types.clone()).unwrap();
+        first.assert_all(0..count).unwrap();
+
+        let mut restarted = crate::flow::Flow::new("test").session();
+        let fact_ids = restarted.attach_store(store.clone(), types).unwrap();
+        assert_eq!(fact_ids.len(), count as usize);
+        assert_eq!(restarted.fact_count(), count as usize);
+        assert_eq!(store.len(), count as usize);
+    }
+
+    #[tokio::test]
+    async fn test_session_store_checkpoints_per_run() {
+        use crate::checkpoint::Checkpoint;
+        use crate::snapshot::SerializedFact;
+        use crate::store::{MemoryStore, SessionStore, WorkingMemoryStore};
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        #[derive(Debug, Default)]
+        struct CountingStore {
+            inner: MemoryStore,
+            saved: AtomicUsize,
+            failing: AtomicBool,
+        }
+
+        impl WorkingMemoryStore for CountingStore {
+            fn put(&self, fact: &SerializedFact) -> Result<()> {
+                self.inner.put(fact)
+            }
+
+            fn remove(&self, fact: FactId) -> Result<()> {
+                self.inner.remove(fact)
+            }
+
+            fn load_page(
+                &self,
+                after: Option<FactId>,
+                limit: usize,
+            ) -> Result<Vec<SerializedFact>> {
+                self.inner.load_page(after, limit)
+            }
+        }
+
+        impl SessionStore for CountingStore {
+            fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+                if self.failing.load(Ordering::SeqCst) {
+                    return Err(crate::error::Error::Execution("disk full".to_string()));
+                }
+                self.saved.fetch_add(1, Ordering::SeqCst);
+                self.inner.save_checkpoint(checkpoint)
+            }
+
+            fn load_checkpoint(&self) -> Result<Option<Checkpoint>> {
+                self.inner.load_checkpoint()
+            }
+        }
+
+        let mut flow = crate::flow::Flow::new("test");
+        flow.rule("count")
+            .when(Box::new(crate::pattern::ObjectPattern::<u32>::new("n"))
+                as Box<dyn crate::pattern::Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        let types = FixtureTypes::new().register::<u32>("u32");
+        let store = Arc::new(CountingStore::default());
+        let mut session = flow.session();
+        session.attach_session_store(store.clone(), types).unwrap();
+        assert_eq!(store.saved.load(Ordering::SeqCst), 1);
+
+        // One checkpoint at the end of the run, not one per firing
+        session.assert_all(0..5u32).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 5);
+        assert_eq!(store.saved.load(Ordering::SeqCst), 2);
+
+        session.set_checkpoint_interval(2);
+        session.assert_all(0..5u32).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(store.saved.load(Ordering::SeqCst), 2 + 3);
+
+        // A failed checkpoint doesn't undo the run, flushing reports it
+        store.failing.store(true, Ordering::SeqCst);
+        session.assert(7u32).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        store.failing.store(false, Ordering::SeqCst);
+        assert!(session.flush_store().is_err());
+        session.flush_store().unwrap();
+        assert_eq!(store.saved.load(Ordering::SeqCst), 2 + 3 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_deduplicate() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct Event {
+            id: u32,
+        }
+
+        let counter = Arc::new(AtomicUsize::new(0));
+        let fired = Arc::clone(&counter);
+        let mut flow = Flow::new("test");
+        flow.rule("ingest")
+            .when(Box::new(ObjectPattern::<Event>::new("e")) as Box<dyn Pattern>)
+            .then(move |_, _| {
+                fired.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        let first = session.assert(Event { id: 1 }).unwrap();
+        session.deduplicate::<Event>();
+        assert_eq!(session.assert(Event { id: 1 }).unwrap(), first);
+        let ids = session
+            .assert_all([Event { id: 2 }, Event { id: 1 }, Event { id: 2 }])
+            .unwrap();
+        assert_eq!(ids[1], first);
+        assert_eq!(ids[0], ids[2]);
+        assert_eq!(session.fact_count(), 2);
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+        // Equal facts are asserted again once the original is gone
+        session.retract(first).unwrap();
+        assert_ne!(session.assert(Event { id: 1 }).unwrap(), first);
+
+        // Other types keep their duplicates
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.get_facts::<TestFact>().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_typed_handle_updates_within_firing() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let large = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&large);
+        let mut flow = Flow::new("test");
+        flow.rule("derive")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value == 1, "value == 1"),
+            ) as Box<dyn Pattern>)
+            .then(|session, _| {
+                let derived = session.assert(TestFact { value: 10 })?;
+                derived.update(session, |t| t.value *= 10)?;
+                assert_eq!(derived.get(session).unwrap().value, 100);
+                let scratch = session.assert(TestFact { value: 500 })?;
+                scratch.retract(session)
+            })
+            .unwrap();
+        flow.rule("large")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value >= 100, "value >= 100"),
+            ) as Box<dyn Pattern>)
+            .then(move |_, _| {
+                seen.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        let handle = session.assert(TestFact { value: 1 }).unwrap();
+        session.match_rules().await.unwrap();
+        assert_eq!(large.load(Ordering::SeqCst), 1);
+        assert_eq!(session.fact_count(), 2);
+
+        assert_eq!(handle.read(&session, |t| t.value), Some(1));
+        let fact_id: FactId = handle.into();
+        assert_eq!(handle, fact_id);
+        assert!(session.get_fact(handle).is_some());
+        session.retract(handle).unwrap();
+        assert_eq!(handle.read(&session, |t| t.value), None);
+    }
+
+    #[tokio::test]
+    async fn test_timer_rule_fires_while_match_holds() {
+        use crate::clock::{PseudoClock, Timer};
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let reminders = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&reminders);
+        let mut flow = Flow::new("test");
+        flow.rule("remind")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .timer(Timer::every(Duration::from_secs(30)))
+            .then(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.set_clock(Arc::new(PseudoClock::new()));
+        let alert = session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+        session.advance_time(Duration::from_secs(29)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+        session.advance_time(Duration::from_secs(1)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        session.advance_time(Duration::from_secs(30)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+
+        session.retract(alert).unwrap();
+        session.advance_time(Duration::from_secs(60)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 0);
+        assert_eq!(reminders.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_restore_keeps_timers() {
+        use crate::clock::{PseudoClock, Timer};
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("remind")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .timer(Timer::every(Duration::from_secs(30)))
+            .then(|session, _| {
+                session.kv().update::<u32, _>("remind", |n| *n += 1)?;
+                Ok(())
+            })
+            .unwrap();
+        flow.rule("once")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .timer(Timer::after(Duration::from_secs(5)))
+            .then(|session, _| {
+                session.kv().update::<u32, _>("once", |n| *n += 1)?;
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.set_clock(Arc::new(PseudoClock::new()));
+        let alert = session.assert(TestFact { value: 1 }).unwrap();
+        session.advance_time(Duration::from_secs(20)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        let checkpoint = Checkpoint::from_json(&session.checkpoint().to_json().unwrap()).unwrap();
+        assert_eq!(checkpoint.scheduled.len(), 1);
+        assert_eq!(checkpoint.scheduled[0].due_in, Duration::from_secs(10));
+        assert_eq!(
+            checkpoint.scheduled[0].period,
+            Some(Duration::from_secs(30))
+        );
+
+        // The restored reminder is due in the 10s left, and "once" stays fired
+        let mut restored = flow.session();
+        restored.set_clock(Arc::new(PseudoClock::new()));
+        let mut fact_ids = HashMap::new();
+        fact_ids.insert(
+            alert.id(),
+            restored.assert(TestFact { value: 1 }).unwrap().id(),
+        );
+        restored.restore(&checkpoint, &fact_ids).unwrap();
+        restored.advance_time(Duration::from_secs(9)).unwrap();
+        assert_eq!(restored.match_rules().await.unwrap(), 0);
+        restored.advance_time(Duration::from_secs(1)).unwrap();
+        assert_eq!(restored.match_rules().await.unwrap(), 1);
+        restored.advance_time(Duration::from_secs(30)).unwrap();
+        assert_eq!(restored.match_rules().await.unwrap(), 1);
+        assert_eq!(restored.kv().get::<u32>("remind"), Some(2));
+        assert_eq!(restored.kv().get::<u32>("once"), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_limited_and_filtered_runs_release_due_timers() {
+        use crate::clock::{PseudoClock, Timer};
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("remind")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .timer(Timer::every(Duration::from_secs(30)))
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session.set_clock(Arc::new(PseudoClock::new()));
+        session.assert(TestFact { value: 1 }).unwrap();
+        session.advance_time(Duration::from_secs(30)).unwrap();
+        assert_eq!(session.match_rules_with_limit(10).await.unwrap().fired, 1);
+        session.advance_time(Duration::from_secs(30)).unwrap();
+        assert_eq!(session.match_rules_filtered(|_| true).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_match_until_halt_waits_for_timers() {
+        use crate::clock::PseudoClock;
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("tick")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .timer("int: 10s 30s".parse().unwrap())
+            .then(|session, _| {
+                let ticks = session
+                    .kv()
+                    .update("ticks", |ticks: &mut i32| *ticks += 1)?;
+                if ticks == 3 {
+                    session.halt();
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let clock = Arc::new(PseudoClock::new());
+        let mut session = flow.session();
+        session.set_clock(clock.clone());
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_until_halt().await.unwrap(), 3);
+        assert_eq!(clock.elapsed(), Duration::from_secs(70));
+    }
+
+    #[tokio::test]
+    async fn test_match_rules_with_limit() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        // Each firing asserts the next fact, so the rule would never stop
+        let mut flow = Flow::new("test");
+        flow.rule("runaway")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|session, match_data| {
+                let value = match_data.get_as::<TestFact>("t")?.value;
+                session.assert(TestFact { value: value + 1 })?;
+                Ok(())
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: 0 }).unwrap();
+        let outcome = session.match_rules_with_limit(5).await.unwrap();
+        assert_eq!(
+            outcome,
+            LimitedFiring {
+                fired: 5,
+                limit_reached: true
+            }
+        );
+        assert_eq!(session.fact_count(), 6);
+
+        session.halt();
+        let outcome = session.match_rules_with_limit(5).await.unwrap();
+        assert_eq!(outcome.fired, 0);
+        assert!(!outcome.limit_reached);
+    }
+
+    #[tokio::test]
+    async fn test_fire_guard() {
+        use crate::error::Error;
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        // "flip" and "flop" keep toggling the fact between two states
+        let mut flow = Flow::new("test");
+        flow.rule("flip")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value == 0, "value == 0"),
+            ) as Box<dyn Pattern>)
+            .then(|session, m| {
+                let id = m.get("t").unwrap().id;
+                session.modify_with(id, |t: &mut TestFact| t.value = 1)
+            })
+            .unwrap();
+        flow.rule("flop")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value == 1, "value == 1"),
+            ) as Box<dyn Pattern>)
+            .then(|session, m| {
+                let id = m.get("t").unwrap().id;
+                session.modify_with(id, |t: &mut TestFact| t.value = 0)
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.set_fire_guard(FireGuard::new().max_repeats(3));
+        session.assert(TestFact { value: 0 }).unwrap();
+        match session.match_rules().await {
+            Err(Error::FireLimitExceeded { fired, reason }) => {
+                assert_eq!(fired, 6);
+                assert!(reason.contains("'flip'"), "{}", reason);
+            }
+            other => panic!("expected the cycle to be caught, got {:?}", other),
+        }
+
+        // A loop making progress is only stopped by the total limit
+        let mut flow = Flow::new("test");
+        flow.rule("count")
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value < 100, "value < 100"),
+            ) as Box<dyn Pattern>)
+            .then(|session, m| {
+                let id = m.get("t").unwrap().id;
+                session.modify_with(id, |t: &mut TestFact| t.value += 1)
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.set_fire_guard(FireGuard::new().max_repeats(1).max_fires(150));
+        session.assert(TestFact { value: 0 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 100);
+
+        session.set_fire_guard(FireGuard::new().max_fires(10));
+        session.assert(TestFact { value: 50 }).unwrap();
+        assert!(matches!(
+            session.match_rules().await,
+            Err(Error::FireLimitExceeded { fired: 10, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_error_context() {
+        use crate::constraint::{Constraint, ConstraintContext};
+        use crate::error::Error;
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        #[derive(Debug, Clone)]
+        struct Lookup;
+
+        impl Constraint for Lookup {
+            fn evaluate(&self, _: &FactHandle, _: &ConstraintContext) -> Result<bool> {
+                Err(Error::custom("service unavailable"))
+            }
+
+            fn clone_box(&self) -> Box<dyn Constraint> {
+                Box::new(self.clone())
+            }
+
+            fn description(&self) -> String {
+                "lookup(t)".to_string()
+            }
+        }
+
+        let mut flow = Flow::new("test");
+        flow.rule("checked")
+            .when(
+                Box::new(ObjectPattern::<String>::new("s").with_constraint(Box::new(Lookup)))
+                    as Box<dyn Pattern>,
+            )
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("failing")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Err(Error::custom("no quota")))
+            .unwrap();
+
+        let mut session = flow.session();
+        let error = session.assert("x".to_string()).unwrap_err();
+        let Error::ConstraintFailed {
+            rule,
+            alias,
+            constraint,
+            source,
+            ..
+        } = &error
+        else {
+            panic!("expected a constraint failure, got {:?}", error);
+        };
+        assert_eq!(rule.as_deref(), Some("checked"));
+        assert_eq!((alias.as_str(), constraint.as_str()), ("s", "lookup(t)"));
+        assert!(matches!(**source, Error::Custom(_)));
+        assert!(error
+            .to_string()
+            .starts_with("Constraint 'lookup(t)' of pattern 's' of rule 'checked' failed on"));
+
+        let id = session.assert(TestFact { value: 1 }).unwrap();
+        match session.match_rules().await {
+            Err(Error::ActionFailed {
+                rule,
+                facts,
+                source,
+            }) => {
+                assert_eq!(rule, "failing");
+                assert_eq!(facts, [id]);
+                assert_eq!(source.to_string(), "no quota");
+            }
+            other => panic!("expected an action failure, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_action() {
+        use crate::error::Error;
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+
+        let mut flow = Flow::new("test");
+        flow.rule("buggy")
+            .priority(1)
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value < 0, "value < 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, m| {
+                let value = m.get_as::<TestFact>("t")?.value;
+                panic!("negative value {}", value)
+            })
+            .unwrap();
+        flow.rule("count")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: -1 }).unwrap();
+        match session.match_rules().await {
+            Err(Error::ActionPanicked { rule, message }) => {
+                assert_eq!(rule, "buggy");
+                assert_eq!(message, "negative value -1");
+            }
+            other => panic!("expected a caught panic, got {:?}", other),
+        }
+
+        // The session keeps working
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_test_pattern() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern, TestPattern};
+        use std::sync::Mutex;
+
+        #[derive(Debug, Clone)]
+        struct Limit(i32);
+
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut flow = Flow::new("test");
+        flow.rule("over_limit")
+            .when(
+                Box::new(ObjectPattern::<TestFact>::new("t").bind("value", |t| t.value))
+                    as Box<dyn Pattern>,
+            )
+            .when(Box::new(TestPattern::new("value > 10", |m| {
+                m.value("value").and_then(|v| v.as_f64()) > Some(10.0)
+            })) as Box<dyn Pattern>)
+            .when(
+                Box::new(ObjectPattern::<Limit>::new("l").bind("limit", |l| l.0))
+                    as Box<dyn Pattern>,
+            )
+            .when(Box::new(TestPattern::new("value > limit", |m| {
+                let value = m.value("value").and_then(|v| v.as_f64());
+                value > m.value("limit").and_then(|v| v.as_f64())
+            })) as Box<dyn Pattern>)
+            .then({
+                let fired = fired.clone();
+                move |_, m| {
+                    fired.lock().unwrap().push(m.get_as::<TestFact>("t")?.value);
+                    Ok(())
+                }
+            })
+            .unwrap();
+
+        let mut session = flow.session();
+        session.assert(TestFact { value: 5 }).unwrap();
+        session.assert(TestFact { value: 30 }).unwrap();
+        session.assert(TestFact { value: 50 }).unwrap();
+        session.assert(Limit(40)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(*fired.lock().unwrap(), [50]);
+
+        let mut flow = Flow::new("test");
+        let first = flow
+            .rule("first")
+            .when(Box::new(TestPattern::new("true", |_| true)) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()));
+        assert!(first.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_error_policy() {
+        use crate::error::Error;
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use crate::rule::ErrorPolicy;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut flow = Flow::new("test");
+        flow.rule("flaky")
+            .priority(2)
+            .on_error(ErrorPolicy::Retry { attempts: 2 })
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then({
+                let attempts = attempts.clone();
+                move |_, _| match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(Error::Execution("unavailable".into())),
+                    _ => Ok(()),
+                }
+            })
+            .unwrap();
+        flow.rule("broken")
+            .priority(1)
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Err(Error::Execution("broken".into())))
+            .unwrap();
+        flow.rule("count")
+            .when(Box::new(ObjectPattern::<TestFact>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("strict")
+            .on_error(ErrorPolicy::Halt)
+            .when(Box::new(
+                ObjectPattern::<TestFact>::new("t").with_filter(|t| t.value < 0, "value < 0"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Err(Error::Execution("negative".into())))
+            .unwrap();
+
+        // Halting is the default
+        let mut session = flow.session();
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert!(session.match_rules().await.is_err());
+
+        // Skipped activations do not count as fired
+        attempts.store(0, Ordering::SeqCst);
+        let mut session = flow.session();
+        session.set_error_policy(ErrorPolicy::SkipRule);
+        session.assert(TestFact { value: 1 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // A rule's own policy wins over the session's
+        session.assert(TestFact { value: -1 }).unwrap();
+        match session.match_rules().await {
+            Err(Error::ActionFailed { rule, .. }) => assert_eq!(rule, "strict"),
+            other => panic!("expected the strict rule to halt, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_priority_inheritance() {
+        use crate::flow::Flow;
+        use crate::pattern::{ObjectPattern, Pattern};
+        use std::sync::Mutex;
+
+        async fn order(inheritance: PriorityInheritance) -> Vec<String> {
+            let fired = Arc::new(Mutex::new(Vec::new()));
+            let mut flow = Flow::new("test");
+            for (name, priority, value) in [("start", 10, 1), ("noise", 5, 2), ("follow_up", 0, 3)]
+            {
+                let fired = Arc::clone(&fired);
+                flow.rule(name)
+                    .priority(priority)
+                    .when(Box::new(
+                        ObjectPattern::<TestFact>::new("t")
+                            .with_filter(move |t| t.value == value, "value"),
+                    ) as Box<dyn Pattern>)
+                    .then(move |session, _| {
+                        fired.lock().unwrap().push(name.to_string());
+                        if name == "start" {
+                            session.assert(TestFact { value: 3 })?;
+                        }
+                        Ok(())
+                    })
+                    .unwrap();
+            }
+
+            let mut session = flow.session();
+            session.set_priority_inheritance(inheritance);
+            session.assert(TestFact { value: 1 }).unwrap();
+            session.assert(TestFact { value: 2 }).unwrap();
+            session.match_rules().await.unwrap();
+            let order = fired.lock().unwrap().clone();
+            order
+        }
+
+        assert_eq!(
+            order(PriorityInheritance::None).await,
+            ["start", "noise", "follow_up"]
+        );
+        assert_eq!(
+            order(PriorityInheritance::Inherit).await,
+            ["start", "follow_up", "noise"]
+        );
+        assert_eq!(
+            order(PriorityInheritance::Boost).await,
+            ["start", "follow_up", "noise"]
+        );
+    }
 }
@@ -0,0 +1,120 @@
+//! Serializable snapshots of working memory
+//!
+//! Facts are type-erased, so each fact type that should survive
+//! serialization is registered under a stable name in a [`FixtureTypes`]
+//! registry, the one fixtures use. [`Session::snapshot`] then captures working memory as a
+//! [`MemorySnapshot`] that can be saved as JSON, and
+//! [`Session::restore_facts`] asserts its facts into another session:
+//!
+//! ```ignore
+//! let types = FixtureTypes::new().register::<Order>("Order");
+//! let json = session.snapshot(&types)?.to_json()?;
+//!
+//! let mut restored = flow.session();
+//! restored.restore_facts(&MemorySnapshot::from_json(&json)?, &types)?;
+//! ```
+//!
+//! [`Session::snapshot`]: crate::session::Session::snapshot
+//! [`Session::restore_facts`]: crate::session::Session::restore_facts
+//! [`FixtureTypes`]: crate::fixture::FixtureTypes
+
+use crate::error::{Error, Result};
+use crate::fact::{Fact, FactId};
+use crate::fixture::FixtureTypes;
+use crate::working_memory::WorkingMemory;
+use serde::{Deserialize, Serialize};
+
+/// A serialized fact
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedFact {
+    /// Id of the fact when it was serialized
+    pub id: FactId,
+    /// Registered name of the fact type
+    #[serde(rename = "type")]
+    pub type_name: String,
+    /// The serialized fact
+    pub fact: serde_json::Value,
+}
+
+/// The facts of a working memory, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemorySnapshot {
+    /// The serialized facts
+    pub facts: Vec<SerializedFact>,
+}
+
+impl MemorySnapshot {
+    /// Serialize every fact in working memory
+    ///
+    /// Fails if a fact's type is not registered.
+    pub fn capture(working_memory: &WorkingMemory, types: &FixtureTypes) -> Result<Self> {
+        let mut facts = working_memory.get_all();
+        facts.sort_by_key(|fact| fact.recency);
+        let facts = facts
+            .iter()
+            .map(|fact| types.serialize(fact))
+            .collect::<Result<_>>()?;
+        Ok(Self { facts })
+    }
+
+    /// Deserialize every fact, in snapshot order
+    pub fn facts(&self, types: &FixtureTypes) -> Result<Vec<Box<dyn Fact>>> {
+        self.facts
+            .iter()
+            .map(|fact| types.deserialize(fact))
+            .collect()
+    }
+
+    /// Serialize this snapshot to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| Error::Execution(format!("Failed to serialize snapshot: {}", e)))
+    }
+
+    /// Deserialize a snapshot from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| Error::Execution(format!("Failed to deserialize snapshot: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        total: f64,
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let types = FixtureTypes::new().register::<Order>("Order");
+        let wm = WorkingMemory::new();
+        let first = wm.assert(Order { total: 10.0 }).unwrap().id;
+        wm.assert(Order { total: 20.0 }).unwrap();
+
+        let snapshot = MemorySnapshot::capture(&wm, &types).unwrap();
+        assert_eq!(snapshot.facts[0].id, first);
+        assert_eq!(snapshot.facts[0].type_name, "Order");
+
+        let snapshot = MemorySnapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        let totals: Vec<_> = snapshot
+            .facts(&types)
+            .unwrap()
+            .iter()
+            .map(|fact| {
+                fact.as_ref()
+                    .as_any()
+                    .downcast_ref::<Order>()
+                    .unwrap()
+                    .total
+            })
+            .collect();
+        assert_eq!(totals, [10.0, 20.0]);
+
+        wm.assert(1u32).unwrap();
+        assert!(MemorySnapshot::capture(&wm, &types).is_err());
+        assert!(FixtureTypes::new().deserialize(&snapshot.facts[0]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Per-rule execution statistics
+//!
+//! After [`Session::collect_stats`], a session counts the activations and
+//! firings of every rule and measures the time spent in its action and in
+//! matching facts against its network. [`Session::stats`] reports them, so
+//! slow rules can be found in production.
+//!
+//! [`Session::collect_stats`]: crate::session::Session::collect_stats
+//! [`Session::stats`]: crate::session::Session::stats
+
+use crate::event::EventListener;
+use crate::rule::Activation;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Statistics of one rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuleStats {
+    /// Activations added to the agenda
+    pub activations: u64,
+    /// Times the action ran, including failed runs
+    pub fires: u64,
+    /// Total time spent in the action
+    pub action_time: Duration,
+    /// Total time spent matching facts against the rule's network
+    pub match_time: Duration,
+}
+
+impl RuleStats {
+    /// Get the average time spent in the action per firing
+    pub fn average_action_time(&self) -> Duration {
+        match u32::try_from(self.fires) {
+            Ok(0) => Duration::ZERO,
+            Ok(fires) => self.action_time / fires,
+            Err(_) => Duration::from_secs_f64(self.action_time.as_secs_f64() / self.fires as f64),
+        }
+    }
+}
+
+/// Statistics of a session's rules, by rule name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    rules: HashMap<String, RuleStats>,
+}
+
+impl Stats {
+    /// Get the statistics of a rule
+    pub fn rule(&self, name: &str) -> Option<&RuleStats> {
+        self.rules.get(name)
+    }
+
+    /// Iterate over the statistics of every rule
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RuleStats)> {
+        self.rules
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats))
+    }
+
+    /// Get the rules sorted by total action and match time, slowest first
+    pub fn slowest(&self) -> Vec<(&str, &RuleStats)> {
+        let mut rules: Vec<_> = self.iter().collect();
+        rules.sort_by(|(a_name, a), (b_name, b)| {
+            (b.action_time + b.match_time)
+                .cmp(&(a.action_time + a.match_time))
+                .then_with(|| a_name.cmp(b_name))
+        });
+        rules
+    }
+
+    /// Check whether no rule has statistics
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Collects the statistics of a session's rules
+#[derive(Debug, Default)]
+pub(crate) struct StatsCollector(Mutex<HashMap<String, RuleStats>>);
+
+impl StatsCollector {
+    fn rules(&self) -> MutexGuard<'_, HashMap<String, RuleStats>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, rule: &str, f: impl FnOnce(&mut RuleStats)) {
+        let mut rules = self.rules();
+        match rules.get_mut(rule) {
+            Some(stats) => f(stats),
+            None => f(rules.entry(rule.to_string()).or_default()),
+        }
+    }
+
+    /// Record a run of a rule's action
+    pub(crate) fn fired(&self, rule: &str, elapsed: Duration) {
+        self.update(rule, |stats| {
+            stats.fires += 1;
+            stats.action_time += elapsed;
+        });
+    }
+
+    /// Get the statistics, adding the match times measured by the network
+    pub(crate) fn stats(&self, match_times: Option<&HashMap<String, Duration>>) -> Stats {
+        let mut rules = self.rules().clone();
+        for (rule, time) in match_times.into_iter().flatten() {
+            rules.entry(rule.clone()).or_default().match_time = *time;
+        }
+        Stats { rules }
+    }
+}
+
+impl EventListener for StatsCollector {
+    fn on_activation_created(&self, activation: &Activation) {
+        self.update(&activation.rule.name, |stats| stats.activations += 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_average_action_time() {
+        let collector = StatsCollector::default();
+        collector.fired("slow", Duration::from_millis(30));
+        collector.fired("slow", Duration::from_millis(10));
+        collector.fired("fast", Duration::from_millis(1));
+        let match_times = HashMap::from([("fast".to_string(), Duration::from_millis(2))]);
+
+        let stats = collector.stats(Some(&match_times));
+        let slow = stats.rule("slow").unwrap();
+        assert_eq!(slow.fires, 2);
+        assert_eq!(slow.average_action_time(), Duration::from_millis(20));
+        assert_eq!(
+            stats.rule("fast").unwrap().match_time,
+            Duration::from_millis(2)
+        );
+        assert_eq!(RuleStats::default().average_action_time(), Duration::ZERO);
+
+        let order: Vec<_> = stats.slowest().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(order, ["slow", "fast"]);
+    }
+}
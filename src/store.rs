@@ -0,0 +1,346 @@
+//! Durable storage of working memory
+//!
+//! A [`WorkingMemoryStore`] attached to a session with
+//! [`Session::attach_store`] receives every fact the session asserts,
+//! modifies or retracts, serialized through a [`FixtureTypes`] registry. When a
+//! session is attached to a store that already holds facts, for example after
+//! a restart, the stored facts are asserted first, so the session picks up
+//! where the previous one stopped:
+//!
+//! ```ignore
+//! let types = FixtureTypes::new().register::<Order>("Order");
+//! let mut session = flow.session();
+//! session.attach_store(Arc::new(SledStore::open("orders.db")?), types)?;
+//! session.assert(Order { total: 10.0 })?;
+//! session.flush_store()?;
+//! ```
+//!
+//! Asserted facts still live in RAM, since the network matches against
+//! them; the store keeps them durable. Stored facts are read back a page of
+//! [`PAGE_SIZE`] facts at a time, so a session attached to a large store
+//! never holds their serialized copies all at once. Facts of types missing
+//! from the registry are rejected when they are asserted. [`MemoryStore`]
+//! keeps the facts in a map, and [`SledStore`] in an embedded sled database
+//! with the `sled` feature.
+//!
+//! A [`SessionStore`] also keeps a [`Checkpoint`] of the agenda, saved at the
+//! end of each firing run or every [`Session::set_checkpoint_interval`]
+//! firings. [`Flow::restore_session`] rehydrates a session from one, so a
+//! decision service that crashed resumes without refiring the rules it
+//! applied before its last checkpoint.
+//!
+//! [`Session::attach_store`]: crate::session::Session::attach_store
+//! [`Session::set_checkpoint_interval`]: crate::session::Session::set_checkpoint_interval
+//! [`Flow::restore_session`]: crate::flow::Flow::restore_session
+
+use crate::checkpoint::Checkpoint;
+use crate::error::{Error, Result};
+use crate::event::EventListener;
+use crate::fact::{Fact, FactHandle, FactId};
+use crate::fixture::FixtureTypes;
+use crate::snapshot::SerializedFact;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Number of stored facts read at a time when a session attaches to a store
+pub const PAGE_SIZE: usize = 1024;
+
+/// Durable storage for the facts of a working memory
+pub trait WorkingMemoryStore: Send + Sync {
+    /// Insert a fact, replacing the stored fact with the same id
+    fn put(&self, fact: &SerializedFact) -> Result<()>;
+
+    /// Remove a fact
+    fn remove(&self, fact: FactId) -> Result<()>;
+
+    /// Load up to `limit` stored facts with ids after `after`, ordered by id
+    fn load_page(&self, after: Option<FactId>, limit: usize) -> Result<Vec<SerializedFact>>;
+
+    /// Load every stored fact, ordered by id
+    fn load(&self) -> Result<Vec<SerializedFact>> {
+        let mut facts: Vec<SerializedFact> = Vec::new();
+        loop {
+            let page = self.load_page(facts.last().map(|fact| fact.id), PAGE_SIZE)?;
+            if page.is_empty() {
+                return Ok(facts);
+            }
+            facts.extend(page);
+        }
+    }
+
+    /// Make the changes written so far durable
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Durable storage for the facts and agenda of a session
+pub trait SessionStore: WorkingMemoryStore {
+    /// Replace the stored checkpoint, durably
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()>;
+
+    /// Load the stored checkpoint, if one was saved
+    fn load_checkpoint(&self) -> Result<Option<Checkpoint>>;
+}
+
+/// A store keeping facts in memory
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    facts: Mutex<BTreeMap<FactId, SerializedFact>>,
+    checkpoint: Mutex<Option<Checkpoint>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn facts(&self) -> MutexGuard<'_, BTreeMap<FactId, SerializedFact>> {
+        self.facts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Get the number of stored facts
+    pub fn len(&self) -> usize {
+        self.facts().len()
+    }
+
+    /// Check whether no fact is stored
+    pub fn is_empty(&self) -> bool {
+        self.facts().is_empty()
+    }
+}
+
+impl WorkingMemoryStore for MemoryStore {
+    fn put(&self, fact: &SerializedFact) -> Result<()> {
+        self.facts().insert(fact.id, fact.clone());
+        Ok(())
+    }
+
+    fn remove(&self, fact: FactId) -> Result<()> {
+        self.facts().remove(&fact);
+        Ok(())
+    }
+
+    fn load_page(&self, after: Option<FactId>, limit: usize) -> Result<Vec<SerializedFact>> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        Ok(self
+            .facts()
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(_, fact)| fact.clone())
+            .collect())
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        *self.checkpoint.lock().unwrap_or_else(|e| e.into_inner()) = Some(checkpoint.clone());
+        Ok(())
+    }
+
+    fn load_checkpoint(&self) -> Result<Option<Checkpoint>> {
+        Ok(self
+            .checkpoint
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone())
+    }
+}
+
+/// A store keeping facts in a sled tree, keyed by fact id
+///
+/// The checkpoint is kept in the same tree under [`SledStore::CHECKPOINT_KEY`].
+#[cfg(feature = "sled")]
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    tree: sled::Tree,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    /// Open or create a sled database at `path` and store facts in its default tree
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let db = sled::open(path).map_err(store_error)?;
+        Ok(Self::new((*db).clone()))
+    }
+
+    /// Store facts in a tree of an open database
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Key of the checkpoint; fact keys are 8-byte ids
+    pub const CHECKPOINT_KEY: &'static [u8] = b"checkpoint";
+}
+
+#[cfg(feature = "sled")]
+fn store_error(e: impl std::fmt::Display) -> Error {
+    Error::Execution(format!("Store error: {}", e))
+}
+
+#[cfg(feature = "sled")]
+impl WorkingMemoryStore for SledStore {
+    fn put(&self, fact: &SerializedFact) -> Result<()> {
+        let value = serde_json::to_vec(fact).map_err(store_error)?;
+        self.tree
+            .insert(fact.id.as_u64().to_be_bytes(), value)
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    fn remove(&self, fact: FactId) -> Result<()> {
+        self.tree
+            .remove(fact.as_u64().to_be_bytes())
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    fn load_page(&self, after: Option<FactId>, limit: usize) -> Result<Vec<SerializedFact>> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after.as_u64().to_be_bytes()),
+            None => Bound::Unbounded,
+        };
+        self.tree
+            .range((start, Bound::Unbounded))
+            .filter(|entry| !matches!(entry, Ok((key, _)) if key.len() != 8))
+            .take(limit)
+            .map(|entry| {
+                let (_, value) = entry.map_err(store_error)?;
+                serde_json::from_slice(&value).map_err(store_error)
+            })
+            .collect()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.tree.flush().map_err(store_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled")]
+impl SessionStore for SledStore {
+    fn save_checkpoint(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let value = serde_json::to_vec(checkpoint).map_err(store_error)?;
+        self.tree
+            .insert(Self::CHECKPOINT_KEY, value)
+            .map_err(store_error)?;
+        self.flush()
+    }
+
+    fn load_checkpoint(&self) -> Result<Option<Checkpoint>> {
+        match self.tree.get(Self::CHECKPOINT_KEY).map_err(store_error)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value).map_err(store_error)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Writes a session's fact changes to a store
+pub(crate) struct StoreWriter {
+    store: Arc<dyn WorkingMemoryStore>,
+    types: FixtureTypes,
+    /// The first write that failed since the last flush
+    error: Mutex<Option<Error>>,
+}
+
+impl StoreWriter {
+    pub(crate) fn new(store: Arc<dyn WorkingMemoryStore>, types: FixtureTypes) -> Self {
+        Self {
+            store,
+            types,
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Fail if a fact can't be written to the store
+    pub(crate) fn check(&self, fact: &dyn Fact) -> Result<()> {
+        self.types.check(fact)
+    }
+
+    /// Write a fact to the store
+    pub(crate) fn put(&self, fact: &FactHandle) -> Result<()> {
+        self.store.put(&self.types.serialize(fact)?)
+    }
+
+    /// Fail with the first write error since the last flush, otherwise flush the store
+    pub(crate) fn flush(&self) -> Result<()> {
+        let error = self.error.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match error {
+            Some(error) => Err(error),
+            None => self.store.flush(),
+        }
+    }
+
+    fn record(&self, result: Result<()>) {
+        if let Err(error) = result {
+            self.error
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get_or_insert(error);
+        }
+    }
+}
+
+impl std::fmt::Debug for StoreWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoreWriter")
+            .field("types", &self.types)
+            .finish()
+    }
+}
+
+impl EventListener for StoreWriter {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        self.record(self.put(fact));
+    }
+
+    fn on_fact_retracted(&self, fact: &FactHandle) {
+        self.record(self.store.remove(fact.id));
+    }
+
+    fn on_fact_modified(&self, fact: &FactHandle) {
+        self.record(self.put(fact));
+    }
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sled_store() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = SledStore::new(db.open_tree("facts").unwrap());
+        let fact = |id: u64, total: u32| SerializedFact {
+            id: serde_json::from_value(serde_json::json!(id)).unwrap(),
+            type_name: "Order".to_string(),
+            fact: serde_json::json!({ "total": total }),
+        };
+
+        store.put(&fact(2, 20)).unwrap();
+        store.put(&fact(1, 10)).unwrap();
+        store.put(&fact(2, 30)).unwrap();
+        store.flush().unwrap();
+        assert_eq!(store.load().unwrap(), [fact(1, 10), fact(2, 30)]);
+
+        assert_eq!(store.load_page(None, 1).unwrap(), [fact(1, 10)]);
+        assert_eq!(
+            store.load_page(Some(fact(1, 10).id), 5).unwrap(),
+            [fact(2, 30)]
+        );
+
+        store.remove(fact(1, 10).id).unwrap();
+        assert_eq!(store.load().unwrap(), [fact(2, 30)]);
+
+        assert_eq!(store.load_checkpoint().unwrap(), None);
+        let checkpoint = Checkpoint {
+            flow: "orders".to_string(),
+            ..Checkpoint::default()
+        };
+        store.save_checkpoint(&checkpoint).unwrap();
+        assert_eq!(store.load_checkpoint().unwrap(), Some(checkpoint));
+        assert_eq!(store.load().unwrap(), [fact(2, 30)]);
+    }
+}
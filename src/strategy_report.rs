@@ -0,0 +1,328 @@
+//! Compare conflict resolution strategies on a workload
+//!
+//! [`StrategyComparison`] runs the same workload in a fresh session of a flow
+//! under each strategy configuration and reports the order rules fired in,
+//! how long the run took and how much the agenda churned, so strategies can
+//! be chosen from measurements.
+
+use crate::agenda::ConflictResolution;
+use crate::clock::Instant;
+use crate::error::Result;
+use crate::event::EventListener;
+use crate::fact::{FactHandle, FactId};
+use crate::flow::Flow;
+use crate::rule::Activation;
+use crate::session::Session;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A rule firing, identified independently of the session it happened in
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Firing {
+    /// Name of the fired rule
+    pub rule: String,
+    /// Assertion order of the matched facts within the run, by alias
+    pub facts: Vec<usize>,
+}
+
+impl fmt::Display for Firing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}", self.rule, self.facts)
+    }
+}
+
+/// The outcome of running the workload under one configuration
+#[derive(Debug, Clone)]
+pub struct StrategyRun {
+    /// Label of the configuration
+    pub label: String,
+    /// Rules fired, in order
+    pub firings: Vec<Firing>,
+    /// Wall time of asserting the workload and firing
+    pub duration: Duration,
+    /// Activations added to the agenda
+    pub activations_created: usize,
+    /// Activations removed from the agenda without firing
+    pub activations_cancelled: usize,
+}
+
+impl StrategyRun {
+    /// Activations that entered or left the agenda without firing
+    pub fn churn(&self) -> usize {
+        self.activations_created.saturating_sub(self.firings.len()) + self.activations_cancelled
+    }
+}
+
+/// How a run's firing order differs from the baseline run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderingDifference {
+    /// Label of the compared configuration
+    pub label: String,
+    /// Index of the first firing that differs
+    pub position: usize,
+    /// The baseline's firing at that position
+    pub baseline: Option<Firing>,
+    /// This configuration's firing at that position
+    pub firing: Option<Firing>,
+    /// Whether both runs fired the same firings, only in another order
+    pub same_firings: bool,
+}
+
+/// Runs of one workload under several strategy configurations
+#[derive(Debug, Clone)]
+pub struct StrategyReport {
+    /// One run per configuration, the first being the baseline
+    pub runs: Vec<StrategyRun>,
+}
+
+impl StrategyReport {
+    /// Get the first difference in firing order of each run from the baseline
+    pub fn differences(&self) -> Vec<OrderingDifference> {
+        let Some((baseline, others)) = self.runs.split_first() else {
+            return Vec::new();
+        };
+
+        others
+            .iter()
+            .filter_map(|run| {
+                let len = baseline.firings.len().max(run.firings.len());
+                let position = (0..len).find(|&i| baseline.firings.get(i) != run.firings.get(i))?;
+                Some(OrderingDifference {
+                    label: run.label.clone(),
+                    position,
+                    baseline: baseline.firings.get(position).cloned(),
+                    firing: run.firings.get(position).cloned(),
+                    same_firings: same_multiset(&baseline.firings, &run.firings),
+                })
+            })
+            .collect()
+    }
+}
+
+fn same_multiset(a: &[Firing], b: &[Firing]) -> bool {
+    let mut counts: HashMap<&Firing, isize> = HashMap::new();
+    for firing in a {
+        *counts.entry(firing).or_default() += 1;
+    }
+    for firing in b {
+        *counts.entry(firing).or_default() -= 1;
+    }
+    counts.values().all(|count| *count == 0)
+}
+
+impl fmt::Display for StrategyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .runs
+            .iter()
+            .map(|run| run.label.len())
+            .max()
+            .unwrap_or(0)
+            .max("strategy".len());
+        writeln!(
+            f,
+            "{:<width$}  {:>6}  {:>6}  {:>12}",
+            "strategy", "fired", "churn", "time"
+        )?;
+        for run in &self.runs {
+            writeln!(
+                f,
+                "{:<width$}  {:>6}  {:>6}  {:>12}",
+                run.label,
+                run.firings.len(),
+                run.churn(),
+                format!("{:.2?}", run.duration)
+            )?;
+        }
+
+        let Some(baseline) = self.runs.first() else {
+            return Ok(());
+        };
+        for difference in self.differences() {
+            let show = |firing: &Option<Firing>| {
+                firing
+                    .as_ref()
+                    .map_or_else(|| "nothing".to_string(), Firing::to_string)
+            };
+            writeln!(
+                f,
+                "{} differs from {} at firing {}: {} instead of {}{}",
+                difference.label,
+                baseline.label,
+                difference.position,
+                show(&difference.firing),
+                show(&difference.baseline),
+                if difference.same_firings {
+                    " (same firings, reordered)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Records firings and agenda churn during a run
+#[derive(Default)]
+struct RunRecorder(Mutex<RunState>);
+
+#[derive(Default)]
+struct RunState {
+    asserted: HashMap<FactId, usize>,
+    firings: Vec<Firing>,
+    created: usize,
+    cancelled: usize,
+}
+
+impl RunRecorder {
+    fn state(&self) -> std::sync::MutexGuard<'_, RunState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl EventListener for RunRecorder {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        let mut state = self.state();
+        let next = state.asserted.len();
+        state.asserted.insert(fact.id, next);
+    }
+
+    fn on_activation_created(&self, _activation: &Activation) {
+        self.state().created += 1;
+    }
+
+    fn on_activation_cancelled(&self, _activation: &Activation) {
+        self.state().cancelled += 1;
+    }
+
+    fn on_rule_fired(&self, activation: &Activation) {
+        let mut state = self.state();
+        let mut aliases: Vec<_> = activation.match_data.facts.iter().collect();
+        aliases.sort_by(|a, b| a.0.cmp(b.0));
+        let facts = aliases
+            .into_iter()
+            .map(|(_, fact)| state.asserted.get(&fact.id).copied().unwrap_or(usize::MAX))
+            .collect();
+        state.firings.push(Firing {
+            rule: activation.rule.name.clone(),
+            facts,
+        });
+    }
+}
+
+/// Runs a workload under several strategy configurations
+pub struct StrategyComparison<'a> {
+    flow: &'a Flow,
+    configurations: Vec<(String, Vec<ConflictResolution>)>,
+}
+
+impl<'a> StrategyComparison<'a> {
+    /// Compare strategies on a flow, starting with its own as the baseline
+    pub fn new(flow: &'a Flow) -> Self {
+        Self {
+            flow,
+            configurations: vec![("flow".to_string(), flow.strategies().to_vec())],
+        }
+    }
+
+    /// Add a configuration to compare
+    pub fn strategies(
+        mut self,
+        label: impl Into<String>,
+        strategies: Vec<ConflictResolution>,
+    ) -> Self {
+        self.configurations.push((label.into(), strategies));
+        self
+    }
+
+    /// Run `workload` in a fresh session per configuration and fire all rules
+    ///
+    /// The workload must assert the same facts in the same order every time
+    /// for firings to be comparable across runs.
+    pub async fn run<F>(&self, workload: F) -> Result<StrategyReport>
+    where
+        F: Fn(&mut Session) -> Result<()>,
+    {
+        let mut runs = Vec::new();
+        for (label, strategies) in &self.configurations {
+            let recorder = Arc::new(RunRecorder::default());
+            let mut session = self.flow.try_session_with_strategies(strategies.clone())?;
+            session.add_event_listener(recorder.clone());
+
+            let start = Instant::now();
+            workload(&mut session)?;
+            session.match_rules().await?;
+            let duration = start.elapsed();
+
+            let mut state = recorder.state();
+            runs.push(StrategyRun {
+                label: label.clone(),
+                firings: std::mem::take(&mut state.firings),
+                duration,
+                activations_created: state.created,
+                activations_cancelled: state.cancelled,
+            });
+        }
+        Ok(StrategyReport { runs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{ObjectPattern, Pattern};
+
+    #[derive(Debug, Clone)]
+    struct Task {
+        priority: i32,
+    }
+
+    #[tokio::test]
+    async fn test_compare_strategies() {
+        let mut flow = Flow::new("tasks");
+        flow.rule("urgent")
+            .priority(10)
+            .when(Box::new(
+                ObjectPattern::<Task>::new("t").with_filter(|t| t.priority > 5, "urgent"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("any")
+            .when(Box::new(ObjectPattern::<Task>::new("t")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let report = StrategyComparison::new(&flow)
+            .strategies("recency", vec![ConflictResolution::ActivationRecency])
+            .strategies(
+                "fact recency",
+                vec![
+                    ConflictResolution::Salience,
+                    ConflictResolution::FactRecency,
+                ],
+            )
+            .run(|session| {
+                session.assert(Task { priority: 9 })?;
+                session.assert(Task { priority: 1 })?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(report.runs.len(), 3);
+        assert_eq!(report.runs[0].firings[0].rule, "urgent");
+        assert_eq!(report.runs[0].firings.len(), 3);
+        assert_eq!(report.runs[0].churn(), 0);
+
+        let differences = report.differences();
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].label, "recency");
+        assert!(differences[0].same_firings);
+        assert!(report
+            .to_string()
+            .contains("recency differs from flow at firing 0"));
+    }
+}
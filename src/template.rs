@@ -0,0 +1,472 @@
+//! Fact types defined at runtime
+//!
+//! A [`FactTemplate`] names a fact type and its typed slots without a Rust
+//! struct, so rulebases loaded from configuration can declare the facts
+//! they match. Templates are built in code, read from a JSON schema or
+//! taken from the `define` blocks of a rule file with
+//! [`dsl::parse_templates`](crate::dsl::parse_templates). Their facts are
+//! [`DefinedFact`]s, matched by a [`TemplatePattern`]:
+//!
+//! ```ignore
+//! let order = FactTemplate::new("Order")
+//!     .slot("total", SlotType::Number, 0)
+//!     .slot("status", SlotType::String, "open");
+//!
+//! flow.rule("big_order")
+//!     .when(Box::new(TemplatePattern::new("o", &order).with_expression("total > 100")?))
+//!     .then(|_, _| Ok(()))?;
+//!
+//! session.assert(order.create([("total", 150)])?)?;
+//! ```
+
+use crate::analysis::Comparison;
+use crate::constraint::{Constraint, ConstraintContext};
+use crate::dsl::DefinedFact;
+use crate::error::{Error, Result};
+use crate::explain::ConstraintResult;
+use crate::expr::Expression;
+use crate::fact::FactHandle;
+use crate::pattern::Pattern;
+use crate::rule::Match;
+use crate::value::{register_fields, Value};
+use serde::Deserialize;
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Type of the values a slot holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotType {
+    /// Any value
+    Any,
+    /// Booleans
+    Bool,
+    /// Integers
+    Integer,
+    /// Integers and floating point numbers
+    Number,
+    /// Strings
+    String,
+}
+
+impl SlotType {
+    /// Check if a slot of this type can hold `value`; every slot can be null
+    pub fn accepts(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (_, Value::Null)
+                | (SlotType::Any, _)
+                | (SlotType::Bool, Value::Bool(_))
+                | (SlotType::Integer, Value::Int(_))
+                | (SlotType::Number, Value::Int(_) | Value::Float(_))
+                | (SlotType::String, Value::String(_))
+        )
+    }
+
+    /// Infer the type of a slot from its default, as `define` blocks do
+    fn of(default: &Value) -> Self {
+        match default {
+            Value::Null => SlotType::Any,
+            Value::Bool(_) => SlotType::Bool,
+            Value::Int(_) | Value::Float(_) => SlotType::Number,
+            Value::String(_) => SlotType::String,
+        }
+    }
+
+    /// Read a JSON schema `type`, ignoring `"null"` in a list of types
+    fn from_schema(schema: &serde_json::Value) -> Option<Self> {
+        let name = match schema {
+            serde_json::Value::Array(types) => types
+                .iter()
+                .filter_map(|t| t.as_str())
+                .find(|t| *t != "null")?,
+            other => other.as_str()?,
+        };
+        match name {
+            "boolean" => Some(SlotType::Bool),
+            "integer" => Some(SlotType::Integer),
+            "number" => Some(SlotType::Number),
+            "string" => Some(SlotType::String),
+            _ => None,
+        }
+    }
+}
+
+/// A named, typed field of a [`FactTemplate`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    /// Name of the slot
+    pub name: String,
+    /// Type of the values it holds
+    pub slot_type: SlotType,
+    /// Value of the slot in new facts
+    pub default: Value,
+}
+
+/// A fact type with named, typed slots, defined at runtime
+#[derive(Debug, Clone, PartialEq)]
+pub struct FactTemplate {
+    name: String,
+    slots: Vec<Slot>,
+}
+
+impl FactTemplate {
+    /// Create a template with no slots
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            slots: Vec::new(),
+        }
+    }
+
+    /// Add a slot, replacing any slot of the same name
+    pub fn slot(
+        mut self,
+        name: impl Into<String>,
+        slot_type: SlotType,
+        default: impl Into<Value>,
+    ) -> Self {
+        let slot = Slot {
+            name: name.into(),
+            slot_type,
+            default: default.into(),
+        };
+        match self.slots.iter_mut().find(|s| s.name == slot.name) {
+            Some(existing) => *existing = slot,
+            None => self.slots.push(slot),
+        }
+        self
+    }
+
+    /// Create a template whose slot types are inferred from their defaults
+    ///
+    /// Numbers make number slots and nulls make slots of any type.
+    pub fn from_defaults(
+        name: impl Into<String>,
+        defaults: impl IntoIterator<Item = (String, Value)>,
+    ) -> Self {
+        defaults
+            .into_iter()
+            .fold(Self::new(name), |template, (slot, default)| {
+                let slot_type = SlotType::of(&default);
+                template.slot(slot, slot_type, default)
+            })
+    }
+
+    /// Read a template from a JSON schema of an object
+    ///
+    /// The template is named after the schema's `title`. Each of its
+    /// `properties` becomes a slot typed by the property's `type`
+    /// (`boolean`, `integer`, `number` or `string`, any value if absent)
+    /// and defaulting to its `default`.
+    pub fn from_json_schema(schema: &serde_json::Value) -> Result<Self> {
+        let invalid = |message: String| Error::Compilation(format!("Invalid schema: {}", message));
+        let name = schema
+            .get("title")
+            .and_then(|title| title.as_str())
+            .ok_or_else(|| invalid("missing 'title'".to_string()))?;
+        let mut template = Self::new(name);
+        let properties = match schema.get("properties") {
+            Some(serde_json::Value::Object(properties)) => properties,
+            Some(_) => return Err(invalid("'properties' is not an object".to_string())),
+            None => return Ok(template),
+        };
+        for (slot, property) in properties {
+            let slot_type = match property.get("type") {
+                Some(schema_type) => SlotType::from_schema(schema_type).ok_or_else(|| {
+                    invalid(format!("unsupported type {} of '{}'", schema_type, slot))
+                })?,
+                None => SlotType::Any,
+            };
+            let default = match property.get("default") {
+                Some(default) => Value::deserialize(default)
+                    .map_err(|_| invalid(format!("the default of '{}' is not a value", slot)))?,
+                None => Value::Null,
+            };
+            if !slot_type.accepts(&default) {
+                return Err(invalid(format!(
+                    "the default of '{}' is not of its type",
+                    slot
+                )));
+            }
+            template = template.slot(slot.clone(), slot_type, default);
+        }
+        Ok(template)
+    }
+
+    /// Get the name of the fact type
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the slots, in the order they were added
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    /// Get the default of every slot, by slot name
+    pub fn defaults(&self) -> BTreeMap<String, Value> {
+        self.slots
+            .iter()
+            .map(|slot| (slot.name.clone(), slot.default.clone()))
+            .collect()
+    }
+
+    /// Create a fact with every slot at its default
+    pub fn instance(&self) -> DefinedFact {
+        self.slots
+            .iter()
+            .fold(DefinedFact::new(self.name.clone()), |fact, slot| {
+                fact.with(slot.name.clone(), slot.default.clone())
+            })
+    }
+
+    /// Create a fact from the defaults and the given slot values
+    ///
+    /// Fails if a value names no slot or does not fit its slot's type.
+    pub fn create<K, V>(&self, values: impl IntoIterator<Item = (K, V)>) -> Result<DefinedFact>
+    where
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        let fact = values
+            .into_iter()
+            .fold(self.instance(), |fact, (slot, value)| {
+                fact.with(slot, value)
+            });
+        self.check(&fact)?;
+        Ok(fact)
+    }
+
+    /// Check that a fact is of this template's type and fits its slots
+    pub fn check(&self, fact: &DefinedFact) -> Result<()> {
+        if fact.type_name() != self.name {
+            return Err(Error::InvalidFact(format!(
+                "a {} is not a {}",
+                fact.type_name(),
+                self.name
+            )));
+        }
+        for (field, value) in fact.fields() {
+            match self.slots.iter().find(|slot| slot.name == *field) {
+                Some(slot) if slot.slot_type.accepts(value) => {}
+                Some(slot) => {
+                    return Err(Error::InvalidFact(format!(
+                        "slot '{}' of {} holds {:?}, not {:?}",
+                        field, self.name, slot.slot_type, value
+                    )))
+                }
+                None => {
+                    return Err(Error::InvalidFact(format!(
+                        "{} has no slot '{}'",
+                        self.name, field
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A pattern matching the facts of a [`FactTemplate`]
+///
+/// Facts of other templates, and facts whose slots do not fit the
+/// template's types, do not match.
+#[derive(Debug, Clone)]
+pub struct TemplatePattern {
+    alias: String,
+    template: Arc<FactTemplate>,
+    constraints: Vec<Expression>,
+}
+
+impl TemplatePattern {
+    /// Create a pattern matching every fact of a template
+    pub fn new(alias: impl Into<String>, template: &FactTemplate) -> Self {
+        register_fields::<DefinedFact>();
+        Self {
+            alias: alias.into(),
+            template: Arc::new(template.clone()),
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Add a constraint on the slots written as an [`Expression`], such as `"total > 100"`
+    ///
+    /// Fails if the expression does not parse or reads a field that is not a slot.
+    pub fn with_expression(mut self, source: &str) -> Result<Self> {
+        let expression = Expression::parse(source)?;
+        if let Some(field) = expression
+            .fields()
+            .into_iter()
+            .find(|field| self.template.slots.iter().all(|slot| slot.name != *field))
+        {
+            return Err(Error::InvalidConstraint(format!(
+                "'{}' reads '{}', which is not a slot of {}",
+                source, field, self.template.name
+            )));
+        }
+        self.constraints.push(expression);
+        Ok(self)
+    }
+
+    /// Get the template
+    pub fn template(&self) -> &FactTemplate {
+        &self.template
+    }
+
+    fn fits(&self, fact: &FactHandle) -> bool {
+        fact.downcast_ref::<DefinedFact>()
+            .is_some_and(|fact| self.template.check(fact).is_ok())
+    }
+}
+
+impl Pattern for TemplatePattern {
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<DefinedFact>()
+    }
+
+    fn matches(&self, fact: &FactHandle, context: &ConstraintContext) -> Result<bool> {
+        if !self.fits(fact) {
+            return Ok(false);
+        }
+        for constraint in &self.constraints {
+            if !constraint.evaluate(fact, context)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    fn constraint_count(&self) -> usize {
+        self.constraints.len()
+    }
+
+    fn comparisons(&self) -> Vec<Comparison> {
+        self.constraints
+            .iter()
+            .flat_map(|constraint| constraint.comparisons())
+            .collect()
+    }
+
+    fn explain(&self, fact: &FactHandle, token: &Match) -> Vec<ConstraintResult> {
+        self.constraints
+            .iter()
+            .map(|constraint| ConstraintResult {
+                description: constraint.description(),
+                passed: constraint.evaluate(fact, &token.context).unwrap_or(false),
+            })
+            .collect()
+    }
+
+    fn clone_box(&self) -> Box<dyn Pattern> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use serde_json::json;
+
+    fn order() -> FactTemplate {
+        FactTemplate::new("Order")
+            .slot("total", SlotType::Number, 0)
+            .slot("status", SlotType::String, "open")
+            .slot("lines", SlotType::Integer, 1)
+    }
+
+    #[test]
+    fn test_create_facts() {
+        let order = order();
+        let fact = order.create([("total", 150)]).unwrap();
+        assert_eq!(fact.get("total"), Some(&Value::Int(150)));
+        assert_eq!(fact.get("status"), Some(&Value::from("open")));
+
+        assert!(matches!(
+            order.create([("lines", 1.5)]),
+            Err(Error::InvalidFact(_))
+        ));
+        assert!(matches!(
+            order.create([("customer", "ann")]),
+            Err(Error::InvalidFact(_))
+        ));
+        assert!(order.check(&DefinedFact::new("Invoice")).is_err());
+        assert!(order
+            .check(&DefinedFact::new("Order").with("status", Value::Null))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = json!({
+            "title": "Order",
+            "type": "object",
+            "properties": {
+                "total": { "type": "number", "default": 0 },
+                "status": { "type": ["string", "null"], "default": "open" },
+                "lines": { "type": "integer", "default": 1 },
+                "note": {}
+            }
+        });
+        let template = FactTemplate::from_json_schema(&schema).unwrap();
+        assert_eq!(template.name(), "Order");
+        assert_eq!(
+            template.defaults(),
+            order().slot("note", SlotType::Any, Value::Null).defaults()
+        );
+        assert!(template.create([("lines", 2.5)]).is_err());
+
+        let invalid = [
+            json!({ "properties": {} }),
+            json!({ "title": "T", "properties": { "items": { "type": "array" } } }),
+            json!({ "title": "T", "properties": { "n": { "type": "integer", "default": "x" } } }),
+        ];
+        for schema in invalid {
+            assert!(
+                FactTemplate::from_json_schema(&schema).is_err(),
+                "{}",
+                schema
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_template_pattern() {
+        let order = order();
+        assert!(TemplatePattern::new("o", &order)
+            .with_expression("customer == 'ann'")
+            .is_err());
+
+        let mut flow = Flow::new("orders");
+        flow.rule("big_order")
+            .when(Box::new(
+                TemplatePattern::new("o", &order)
+                    .with_expression("total > 100")
+                    .unwrap(),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+
+        let mut session = flow.session();
+        session
+            .assert(order.create([("total", 150)]).unwrap())
+            .unwrap();
+        session
+            .assert(order.create([("total", 50)]).unwrap())
+            .unwrap();
+        // Same shape, other type
+        session
+            .assert(DefinedFact::new("Invoice").with("total", 500))
+            .unwrap();
+        // Slot of the wrong type
+        session
+            .assert(DefinedFact::new("Order").with("total", "lots"))
+            .unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,215 @@
+//! Per-tenant flows built on a shared rule library
+//!
+//! Each tenant gets its own [`Flow`] made of the base flow's rules plus the
+//! tenant's overlay rules. Base rules the tenant does not override reuse the
+//! base flow's network nodes, so the per-tenant cost is only the overlay.
+
+use crate::error::{Error, Result};
+use crate::flow::Flow;
+use crate::rule::Rule;
+use crate::session::Session;
+use std::collections::{HashMap, HashSet};
+
+/// A tenant's flow and the base rules it overrides
+#[derive(Debug)]
+struct Tenant {
+    flow: Flow,
+    overrides: HashSet<String>,
+}
+
+/// A base flow shared by many tenant flows
+#[derive(Debug)]
+pub struct TenantRegistry {
+    base: Flow,
+    tenants: HashMap<String, Tenant>,
+}
+
+impl TenantRegistry {
+    /// Create a registry around a base flow
+    pub fn new(base: Flow) -> Self {
+        Self {
+            base,
+            tenants: HashMap::new(),
+        }
+    }
+
+    /// Get the base flow
+    pub fn base(&self) -> &Flow {
+        &self.base
+    }
+
+    /// Add a rule to the base flow and every tenant that does not override it
+    pub fn add_base_rule(&mut self, rule: Rule) -> Result<()> {
+        let name = rule.name.clone();
+        self.base.add_rule(rule)?;
+        for tenant in self.tenants.values_mut() {
+            if !tenant.overrides.contains(&name) {
+                tenant.flow.share_rule(&self.base, &name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Register a tenant, starting from the base rules
+    pub fn add_tenant(&mut self, tenant: impl Into<String>) -> Result<()> {
+        let tenant = tenant.into();
+        if self.tenants.contains_key(&tenant) {
+            return Err(Error::Compilation(format!(
+                "Tenant '{}' already exists",
+                tenant
+            )));
+        }
+
+        let mut flow = self
+            .base
+            .empty_like(format!("{}::{}", self.base.name(), tenant));
+        for name in self.base.rule_names() {
+            flow.share_rule(&self.base, &name)?;
+        }
+        self.tenants.insert(
+            tenant,
+            Tenant {
+                flow,
+                overrides: HashSet::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Add an overlay rule for a tenant
+    ///
+    /// A rule named like a base rule replaces it for this tenant only.
+    pub fn add_tenant_rule(&mut self, tenant: &str, rule: Rule) -> Result<()> {
+        let overrides_base = self.base.has_rule(&rule.name);
+        let entry = self
+            .tenants
+            .get_mut(tenant)
+            .ok_or_else(|| Error::TenantNotFound(tenant.to_string()))?;
+
+        if overrides_base {
+            entry.overrides.insert(rule.name.clone());
+            entry.flow.replace_rule(rule)
+        } else {
+            entry.flow.add_rule(rule)
+        }
+    }
+
+    /// Remove a tenant
+    pub fn remove_tenant(&mut self, tenant: &str) -> Result<()> {
+        self.tenants
+            .remove(tenant)
+            .map(|_| ())
+            .ok_or_else(|| Error::TenantNotFound(tenant.to_string()))
+    }
+
+    /// Get a tenant's flow
+    pub fn tenant(&self, tenant: &str) -> Result<&Flow> {
+        self.tenants
+            .get(tenant)
+            .map(|entry| &entry.flow)
+            .ok_or_else(|| Error::TenantNotFound(tenant.to_string()))
+    }
+
+    /// Create a session for a tenant
+    pub fn session(&self, tenant: &str) -> Result<Session> {
+        self.tenant(tenant)?.try_session()
+    }
+
+    /// Get all tenant names
+    pub fn tenant_names(&self) -> Vec<String> {
+        self.tenants.keys().cloned().collect()
+    }
+
+    /// Count the rules of a tenant that reuse the base flow's network
+    pub fn shared_rule_count(&self, tenant: &str) -> Result<usize> {
+        let flow = self.tenant(tenant)?;
+        Ok(flow
+            .rule_names()
+            .iter()
+            .filter(|name| flow.shares_rule_network(&self.base, name))
+            .count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Debug, Clone)]
+    struct Order {
+        total: u32,
+    }
+
+    fn discount_rule(threshold: u32, fired: &Arc<AtomicUsize>) -> Rule {
+        let fired = Arc::clone(fired);
+        Rule::new("discount")
+            .when(Box::new(
+                ObjectPattern::<Order>::new("o")
+                    .with_filter(move |o| o.total > threshold, "total > threshold"),
+            ) as Box<dyn Pattern>)
+            .then(move |_, _| {
+                fired.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .build()
+            .unwrap()
+    }
+
+    fn simple_rule(name: &str) -> Rule {
+        Rule::new(name)
+            .when(Box::new(ObjectPattern::<Order>::new("o")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tenants_share_base_networks() {
+        let base_fired = Arc::new(AtomicUsize::new(0));
+        let mut registry = TenantRegistry::new(Flow::new("shop"));
+        registry
+            .add_base_rule(discount_rule(100, &base_fired))
+            .unwrap();
+        registry.add_tenant("acme").unwrap();
+        registry.add_tenant("globex").unwrap();
+        registry.add_base_rule(simple_rule("audit")).unwrap();
+
+        assert_eq!(registry.shared_rule_count("acme").unwrap(), 2);
+        assert_eq!(registry.shared_rule_count("globex").unwrap(), 2);
+        assert!(registry.session("initech").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_override_is_isolated() {
+        let base_fired = Arc::new(AtomicUsize::new(0));
+        let acme_fired = Arc::new(AtomicUsize::new(0));
+
+        let mut registry = TenantRegistry::new(Flow::new("shop"));
+        registry
+            .add_base_rule(discount_rule(100, &base_fired))
+            .unwrap();
+        registry.add_tenant("acme").unwrap();
+        registry.add_tenant("globex").unwrap();
+        registry
+            .add_tenant_rule("acme", discount_rule(10, &acme_fired))
+            .unwrap();
+        registry
+            .add_tenant_rule("acme", simple_rule("loyalty"))
+            .unwrap();
+
+        assert_eq!(registry.shared_rule_count("acme").unwrap(), 0);
+        assert!(!registry.tenant("globex").unwrap().has_rule("loyalty"));
+
+        for tenant in ["acme", "globex"] {
+            let mut session = registry.session(tenant).unwrap();
+            session.assert(Order { total: 50 }).unwrap();
+            session.match_rules().await.unwrap();
+        }
+
+        assert_eq!(acme_fired.load(Ordering::SeqCst), 1);
+        assert_eq!(base_fired.load(Ordering::SeqCst), 0);
+    }
+}
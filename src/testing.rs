@@ -0,0 +1,297 @@
+//! Given/When/Then tests for rulebases
+//!
+//! [`RuleTest`] asserts the given facts into a fresh session of a flow,
+//! fires its rules and returns a [`RuleTestRun`] whose assertions check
+//! which rules fired, in what order, and which facts resulted:
+//!
+//! ```ignore
+//! RuleTest::new(&flow)
+//!     .given(Order { total: 150, status: "open".into() })
+//!     .when_matched()
+//!     .await?
+//!     .fired("big_order")
+//!     .not_fired("small_order")
+//!     .fired_in_order(&["validate", "big_order"])
+//!     .has_fact(|o: &Order| o.status == "flagged");
+//! ```
+//!
+//! Assertions panic like `assert!`, listing the rules that fired, so they
+//! read naturally inside `#[test]` functions.
+
+use crate::error::Result;
+use crate::event::FiredRules;
+use crate::fact::Fact;
+use crate::flow::Flow;
+use crate::session::Session;
+use std::sync::Arc;
+
+/// The given facts and focused agenda groups of a rule test
+pub struct RuleTest<'a> {
+    flow: &'a Flow,
+    facts: Vec<Box<dyn Fact>>,
+    focus: Vec<String>,
+}
+
+impl<'a> RuleTest<'a> {
+    /// Start a test of a flow's rules
+    pub fn new(flow: &'a Flow) -> Self {
+        Self {
+            flow,
+            facts: Vec::new(),
+            focus: Vec::new(),
+        }
+    }
+
+    /// Given a fact in working memory
+    pub fn given<T: Fact>(mut self, fact: T) -> Self {
+        self.facts.push(Box::new(fact));
+        self
+    }
+
+    /// Given a type-erased fact in working memory
+    pub fn given_boxed(mut self, fact: Box<dyn Fact>) -> Self {
+        self.facts.push(fact);
+        self
+    }
+
+    /// Given several facts in working memory, asserted in order
+    pub fn given_all<T: Fact>(mut self, facts: impl IntoIterator<Item = T>) -> Self {
+        self.facts.extend(
+            facts
+                .into_iter()
+                .map(|fact| Box::new(fact) as Box<dyn Fact>),
+        );
+        self
+    }
+
+    /// Focus an agenda group before firing; the last one focused fires first
+    pub fn focus(mut self, group: impl Into<String>) -> Self {
+        self.focus.push(group.into());
+        self
+    }
+
+    /// When the rules fire, via [`Session::match_rules`]
+    pub async fn when_matched(self) -> Result<RuleTestRun> {
+        let fired_rules = Arc::new(FiredRules::default());
+        let mut session = self.flow.try_session()?;
+        session.add_event_listener(fired_rules.clone());
+        session.assert_all_boxed(self.facts)?;
+        for group in self.focus {
+            session.focus(group)?;
+        }
+        session.match_rules().await?;
+
+        Ok(RuleTestRun {
+            session,
+            fired: fired_rules.take(),
+        })
+    }
+}
+
+/// The outcome of a rule test, to assert on
+pub struct RuleTestRun {
+    session: Session,
+    fired: Vec<String>,
+}
+
+impl RuleTestRun {
+    /// Names of the rules fired, in firing order
+    pub fn fired_rules(&self) -> &[String] {
+        &self.fired
+    }
+
+    /// The session the rules fired in
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Facts of a type in working memory, oldest first
+    pub fn facts<T: Fact + Clone>(&self) -> Vec<T> {
+        let mut handles = self.session.get_facts::<T>();
+        handles.sort_by_key(|handle| handle.recency);
+        handles
+            .iter()
+            .filter_map(|handle| handle.downcast_ref::<T>().cloned())
+            .collect()
+    }
+
+    fn times_fired(&self, rule: &str) -> usize {
+        self.fired.iter().filter(|name| *name == rule).count()
+    }
+
+    /// Then a rule fired at least once
+    #[track_caller]
+    pub fn fired(&self, rule: &str) -> &Self {
+        assert!(
+            self.times_fired(rule) > 0,
+            "expected rule `{}` to fire; fired: {:?}",
+            rule,
+            self.fired
+        );
+        self
+    }
+
+    /// Then a rule fired exactly `times` times
+    #[track_caller]
+    pub fn fired_times(&self, rule: &str, times: usize) -> &Self {
+        assert_eq!(
+            self.times_fired(rule),
+            times,
+            "expected rule `{}` to fire {} times; fired: {:?}",
+            rule,
+            times,
+            self.fired
+        );
+        self
+    }
+
+    /// Then a rule never fired
+    #[track_caller]
+    pub fn not_fired(&self, rule: &str) -> &Self {
+        assert_eq!(
+            self.times_fired(rule),
+            0,
+            "expected rule `{}` not to fire; fired: {:?}",
+            rule,
+            self.fired
+        );
+        self
+    }
+
+    /// Then the rules fired in this order, possibly with others in between
+    #[track_caller]
+    pub fn fired_in_order(&self, rules: &[&str]) -> &Self {
+        let mut fired = self.fired.iter();
+        assert!(
+            rules.iter().all(|rule| fired.any(|name| name == rule)),
+            "expected rules {:?} to fire in order; fired: {:?}",
+            rules,
+            self.fired
+        );
+        self
+    }
+
+    /// Then exactly these rules fired, in this order
+    #[track_caller]
+    pub fn fired_exactly(&self, rules: &[&str]) -> &Self {
+        assert_eq!(
+            self.fired, rules,
+            "expected exactly rules {:?} to fire; fired: {:?}",
+            rules, self.fired
+        );
+        self
+    }
+
+    /// Then working memory holds a fact of a type matching `predicate`
+    #[track_caller]
+    pub fn has_fact<T: Fact + Clone>(&self, predicate: impl Fn(&T) -> bool) -> &Self {
+        let facts = self.facts::<T>();
+        assert!(
+            facts.iter().any(predicate),
+            "expected a matching {} fact; found: {:?}",
+            std::any::type_name::<T>(),
+            facts
+        );
+        self
+    }
+
+    /// Then working memory holds `count` facts of a type
+    #[track_caller]
+    pub fn fact_count<T: Fact + Clone>(&self, count: usize) -> &Self {
+        let facts = self.facts::<T>();
+        assert_eq!(
+            facts.len(),
+            count,
+            "expected {} {} facts; found: {:?}",
+            count,
+            std::any::type_name::<T>(),
+            facts
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{ObjectPattern, Pattern};
+
+    #[derive(Debug, Clone)]
+    struct Order {
+        total: i64,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Flag {
+        reason: String,
+    }
+
+    fn flow() -> Flow {
+        let mut flow = Flow::new("orders");
+        flow.rule("big_order")
+            .priority(10)
+            .when(
+                Box::new(ObjectPattern::<Order>::new("o").with_filter(|o| o.total > 100, "big"))
+                    as Box<dyn Pattern>,
+            )
+            .then(|session, _| {
+                session.assert(Flag {
+                    reason: "big".into(),
+                })?;
+                Ok(())
+            })
+            .unwrap();
+        flow.rule("any_order")
+            .when(Box::new(ObjectPattern::<Order>::new("o")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow.rule("flagged")
+            .when(Box::new(ObjectPattern::<Flag>::new("f")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))
+            .unwrap();
+        flow
+    }
+
+    #[tokio::test]
+    async fn test_given_when_then() {
+        let flow = flow();
+        RuleTest::new(&flow)
+            .given(Order { total: 150 })
+            .when_matched()
+            .await
+            .unwrap()
+            .fired("big_order")
+            .fired_times("any_order", 1)
+            .fired_in_order(&["big_order", "flagged"])
+            .has_fact(|f: &Flag| f.reason == "big")
+            .fact_count::<Order>(1);
+
+        let run = RuleTest::new(&flow)
+            .given_all([Order { total: 5 }, Order { total: 7 }])
+            .when_matched()
+            .await
+            .unwrap();
+        run.not_fired("big_order")
+            .fired_exactly(&["any_order", "any_order"])
+            .fact_count::<Flag>(0);
+        assert_eq!(
+            run.facts::<Order>()
+                .iter()
+                .map(|o| o.total)
+                .collect::<Vec<_>>(),
+            [5, 7]
+        );
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expected rules [\"flagged\", \"big_order\"] to fire in order")]
+    async fn test_fired_in_order_failure() {
+        let flow = flow();
+        RuleTest::new(&flow)
+            .given(Order { total: 150 })
+            .when_matched()
+            .await
+            .unwrap()
+            .fired_in_order(&["flagged", "big_order"]);
+    }
+}
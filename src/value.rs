@@ -0,0 +1,355 @@
+//! Dynamically typed field values and named field access for facts
+
+use crate::fact::{Fact, FactHandle};
+use serde::{Deserialize, Serialize};
+use std::any::{Any, TypeId};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+/// A dynamically typed field value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// Missing or null value
+    Null,
+    /// Boolean value
+    Bool(bool),
+    /// Integer value
+    Int(i64),
+    /// Floating point value
+    Float(f64),
+    /// String value
+    String(String),
+}
+
+impl Value {
+    /// Parse a textual literal, preferring booleans and numbers over strings
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if let Ok(b) = text.parse::<bool>() {
+            Value::Bool(b)
+        } else if let Ok(i) = text.parse::<i64>() {
+            Value::Int(i)
+        } else if let Ok(f) = text.parse::<f64>() {
+            Value::Float(f)
+        } else {
+            Value::String(text.to_string())
+        }
+    }
+
+    /// Check if this value is null
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Get this value as a float, if it is numeric
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Get this value as a string slice, if it is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get this value as a boolean, if it is one
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Compare two values, treating integers and floats as one numeric type
+    ///
+    /// Returns `None` for values of incomparable types.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<i8> for Value {
+    fn from(value: i8) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<i16> for Value {
+    fn from(value: i16) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<u8> for Value {
+    fn from(value: u8) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<u16> for Value {
+    fn from(value: u16) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+/// Values beyond `i64::MAX` become floats
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        i64::try_from(value).map_or(Value::Float(value as f64), Value::Int)
+    }
+}
+
+/// Values beyond `i64::MAX` become floats
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        i64::try_from(value).map_or(Value::Float(value as f64), Value::Int)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::Float(value.into())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// Named field access for facts
+///
+/// Implement this for fact types that should be usable from data-driven
+/// rules (imported models, decision tables) that refer to fields by name.
+pub trait Fields {
+    /// Get a field by name, or `None` if the fact has no such field
+    fn field(&self, name: &str) -> Option<Value>;
+}
+
+impl Fields for HashMap<String, Value> {
+    fn field(&self, name: &str) -> Option<Value> {
+        self.get(name).cloned()
+    }
+}
+
+type FieldAccessor = fn(&dyn Any, &str) -> Option<Value>;
+
+fn accessors() -> &'static RwLock<HashMap<TypeId, FieldAccessor>> {
+    static ACCESSORS: OnceLock<RwLock<HashMap<TypeId, FieldAccessor>>> = OnceLock::new();
+    ACCESSORS.get_or_init(|| {
+        let mut accessors: HashMap<TypeId, FieldAccessor> = HashMap::new();
+        accessors.insert(
+            TypeId::of::<HashMap<String, Value>>(),
+            access::<HashMap<String, Value>>,
+        );
+        RwLock::new(accessors)
+    })
+}
+
+fn access<T: Fields + 'static>(fact: &dyn Any, name: &str) -> Option<Value> {
+    fact.downcast_ref::<T>()?.field(name)
+}
+
+/// Register a fact type's [`Fields`] so its fields can be read from a type-erased [`FactHandle`]
+pub fn register_fields<T: Fact + Fields>() {
+    accessors()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(TypeId::of::<T>(), access::<T>);
+}
+
+fn access_serde<T: Serialize + 'static>(fact: &dyn Any, name: &str) -> Option<Value> {
+    let json = serde_json::to_value(fact.downcast_ref::<T>()?).ok()?;
+    let field = name.split('.').try_fold(&json, |json, key| json.get(key))?;
+    Value::deserialize(field).ok()
+}
+
+/// Register a fact type so its fields are read through its `Serialize` implementation
+///
+/// For types without a [`Fields`] implementation. Dotted names such as
+/// `address.city` read nested fields; arrays and objects are not values, so
+/// they read as `None`.
+pub fn register_serde_fields<T: Fact + Serialize>() {
+    accessors()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(TypeId::of::<T>(), access_serde::<T>);
+}
+
+/// Check if a fact type's fields have been registered
+pub fn has_fields(type_id: TypeId) -> bool {
+    accessors()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains_key(&type_id)
+}
+
+/// Read a field of a fact whose type has been registered with [`register_fields`]
+pub fn fact_field(fact: &FactHandle, name: &str) -> Option<Value> {
+    let accessor = *accessors()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&fact.type_id)?;
+    accessor(fact.fact.as_ref().as_any(), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_literals() {
+        assert_eq!(Value::parse("true"), Value::Bool(true));
+        assert_eq!(Value::parse("42"), Value::Int(42));
+        assert_eq!(Value::parse(" 2.5 "), Value::Float(2.5));
+        assert_eq!(Value::parse("sunny"), Value::String("sunny".to_string()));
+    }
+
+    #[test]
+    fn test_numeric_comparison_across_types() {
+        assert_eq!(
+            Value::Int(2).compare(&Value::Float(2.0)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Value::Float(1.5).compare(&Value::Int(2)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(Value::from("a").compare(&Value::Int(1)), None);
+    }
+
+    #[test]
+    fn test_registered_fact_fields() {
+        #[derive(Debug, Clone)]
+        struct Point {
+            x: i64,
+        }
+
+        impl Fields for Point {
+            fn field(&self, name: &str) -> Option<Value> {
+                match name {
+                    "x" => Some(self.x.into()),
+                    _ => None,
+                }
+            }
+        }
+
+        let handle = FactHandle::new(Point { x: 3 }, 0);
+        assert_eq!(fact_field(&handle, "x"), None);
+
+        register_fields::<Point>();
+        assert_eq!(fact_field(&handle, "x"), Some(Value::Int(3)));
+        assert_eq!(fact_field(&handle, "y"), None);
+    }
+
+    #[test]
+    fn test_serde_fact_fields() {
+        #[derive(Debug, Clone, Serialize)]
+        struct Address {
+            city: String,
+        }
+
+        #[derive(Debug, Clone, Serialize)]
+        struct Customer {
+            age: u32,
+            score: f64,
+            address: Address,
+        }
+
+        let customer = Customer {
+            age: 41,
+            score: 0.5,
+            address: Address {
+                city: "Lisbon".to_string(),
+            },
+        };
+        let handle = FactHandle::new(customer, 0);
+        register_serde_fields::<Customer>();
+        assert_eq!(fact_field(&handle, "age"), Some(Value::Int(41)));
+        assert_eq!(fact_field(&handle, "score"), Some(Value::Float(0.5)));
+        assert_eq!(
+            fact_field(&handle, "address.city"),
+            Some(Value::from("Lisbon"))
+        );
+        assert_eq!(fact_field(&handle, "address"), None);
+        assert_eq!(fact_field(&handle, "name"), None);
+    }
+
+    #[test]
+    fn test_map_fields() {
+        let mut record = HashMap::new();
+        record.insert("age".to_string(), Value::from(30));
+        assert_eq!(record.field("age"), Some(Value::Int(30)));
+        assert_eq!(record.field("income"), None);
+    }
+}
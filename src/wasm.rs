@@ -1,16 +1,37 @@
-use wasm_bindgen::prelude::*;
+//! JavaScript bindings over the Rete engine
+//!
+//! The classes exported here wrap [`crate::flow::Flow`] and
+//! [`crate::session::Session`], so rules built from JavaScript are compiled
+//! into the same network, agenda and working memory as native ones.
+//!
+//! Facts are plain JavaScript objects, converted with serde-wasm-bindgen and
+//! typed by their constructor's name. Rule conditions are [`Expression`]s
+//! over their fields, naming the fact either bare, as `count > 3`, or
+//! through an alias such as `m.count > 3 && m.text.includes('hello')`.
+
+use crate::dsl::{CompileOptions, DefinedFact};
+use crate::event::EventListener;
+use crate::expr::Expression;
+use crate::fact::{Fact, FactHandle};
+use crate::flow::Flow as EngineFlow;
+use crate::pattern::{ObjectPattern, Pattern};
+use crate::rule::{Activation, Match, Rule as EngineRule};
+use crate::session::Session as EngineSession;
+use crate::template::FactTemplate;
+use crate::value::{register_fields, Fields, Value};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use wasm_bindgen::prelude::*;
 
 // Set up panic hook for better error messages in WASM
 #[cfg(feature = "console_error_panic_hook")]
 pub use console_error_panic_hook::set_once as set_panic_hook;
 
-// Use wee_alloc as the global allocator (smaller binary size)
-#[cfg(feature = "wee_alloc")]
-#[global_allocator]
-static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
-
 /// Initialize the WASM module
 #[wasm_bindgen(start)]
 pub fn init() {
@@ -18,204 +39,510 @@ pub fn init() {
     console_error_panic_hook::set_once();
 }
 
-/// A fact that can be asserted into the working memory
-#[wasm_bindgen]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Fact {
-    id: u64,
-    data: String,
+fn js_error(error: crate::error::Error) -> JsValue {
+    JsValue::from_str(&error.to_string())
 }
 
-#[wasm_bindgen]
-impl Fact {
-    #[wasm_bindgen(constructor)]
-    pub fn new(data: String) -> Fact {
-        use std::sync::atomic::{AtomicU64, Ordering};
-        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
-        
-        Fact {
-            id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
-            data,
+/// Convert to plain JavaScript objects and arrays, rather than `Map`s
+fn to_js(value: &impl Serialize) -> Result<JsValue, JsValue> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+}
+
+/// Drive a future that completes without waiting, as matching without
+/// asynchronous constraints does
+fn run_now<F: Future>(future: F) -> Result<F::Output, JsValue> {
+    let mut future = std::pin::pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => Ok(output),
+        Poll::Pending => Err(JsValue::from_str("Matching did not complete synchronously")),
+    }
+}
+
+/// A JavaScript object asserted as a fact, with the name of its type
+#[derive(Debug, Clone)]
+pub(crate) struct DynamicFact {
+    type_name: String,
+    data: serde_json::Value,
+}
+
+impl DynamicFact {
+    fn type_name(&self) -> &str {
+        &self.type_name
+    }
+}
+
+/// Fields of the object; dotted names read nested fields
+impl Fields for DynamicFact {
+    fn field(&self, name: &str) -> Option<Value> {
+        let field = name
+            .split('.')
+            .try_fold(&self.data, |data, key| data.get(key))?;
+        Value::deserialize(field).ok()
+    }
+}
+
+/// The type name and data of a fact asserted from JavaScript or a rule file
+fn fact_data(fact: &FactHandle) -> Option<(&str, serde_json::Value)> {
+    let fact = fact.fact.as_ref().as_any();
+    if let Some(fact) = fact.downcast_ref::<DynamicFact>() {
+        return Some((&fact.type_name, fact.data.clone()));
+    }
+    let fact = fact.downcast_ref::<DefinedFact>()?;
+    let fields = fact
+        .fields()
+        .map(|(field, value)| (field.to_string(), serde_json::json!(value)))
+        .collect();
+    Some((fact.type_name(), serde_json::Value::Object(fields)))
+}
+
+/// The name of an object's constructor, such as `Order` for `new Order()`
+fn constructor_name(object: &JsValue) -> Option<String> {
+    let constructor = js_sys::Reflect::get(object, &"constructor".into()).ok()?;
+    let name = constructor.dyn_into::<js_sys::Function>().ok()?.name();
+    Some(String::from(name))
+}
+
+/// Events a session can call back on, as in nools.js
+const EVENTS: &[&str] = &["assert", "retract", "fire"];
+
+/// JavaScript callbacks for session events
+///
+/// Callbacks run synchronously, as the engine reports each event. The first
+/// error a callback throws is returned from the session call that raised it.
+#[derive(Default)]
+struct Callbacks {
+    handlers: RefCell<HashMap<String, Vec<js_sys::Function>>>,
+    error: RefCell<Option<JsValue>>,
+}
+
+// SAFETY: without the atomics target feature, wasm32 runs the bindings on a
+// single thread, so the JavaScript functions and values held here are never
+// touched from another one; the bounds only satisfy `EventListener`
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for Callbacks {}
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Sync for Callbacks {}
+
+#[cfg(target_feature = "atomics")]
+compile_error!("the JavaScript bindings hold JavaScript values and do not support wasm threads");
+
+impl Callbacks {
+    /// Call the callbacks of `event` with `args`, converted to JavaScript
+    fn emit(&self, event: &str, args: impl FnOnce() -> Result<Vec<JsValue>, JsValue>) {
+        let handlers = self.handlers.borrow().get(event).cloned();
+        let Some(handlers) = handlers else {
+            return;
+        };
+        let result = args().and_then(|args| {
+            let args: js_sys::Array = args.into_iter().collect();
+            handlers
+                .iter()
+                .try_for_each(|handler| handler.apply(&JsValue::NULL, &args).map(|_| ()))
+        });
+        if let Err(error) = result {
+            self.error.borrow_mut().get_or_insert(error);
         }
     }
 
-    #[wasm_bindgen(getter)]
-    pub fn id(&self) -> u64 {
-        self.id
+    /// Fail with the first error a callback threw since the last check
+    fn check(&self) -> Result<(), JsValue> {
+        match self.error.borrow_mut().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 
-    #[wasm_bindgen(getter)]
-    pub fn data(&self) -> String {
-        self.data.clone()
+    fn fact(fact: &FactHandle) -> Result<JsValue, JsValue> {
+        match fact_data(fact) {
+            Some((_, data)) => to_js(&data),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+}
+
+impl EventListener for Callbacks {
+    fn on_fact_asserted(&self, fact: &FactHandle) {
+        self.emit("assert", || Ok(vec![Self::fact(fact)?]));
     }
 
-    #[wasm_bindgen(setter)]
-    pub fn set_data(&mut self, data: String) {
-        self.data = data;
+    fn on_fact_retracted(&self, fact: &FactHandle) {
+        self.emit("retract", || Ok(vec![Self::fact(fact)?]));
+    }
+
+    fn on_rule_fired(&self, activation: &Activation) {
+        self.emit("fire", || {
+            let facts = activation
+                .rule
+                .patterns
+                .iter()
+                .filter_map(|pattern| activation.match_data.get(pattern.alias()))
+                .map(|fact| Self::fact(fact))
+                .collect::<Result<js_sys::Array, _>>()?;
+            Ok(vec![activation.rule.name.as_str().into(), facts.into()])
+        });
     }
 }
 
-/// A rule with a condition and action
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Rule {
-    name: String,
-    priority: i32,
-    condition: String,
-    action: String,
+/// A pattern matching facts of `fact_type`, or of any type, that satisfy a condition
+fn condition_pattern<T: Fact>(
+    alias: String,
+    fact_type: Option<String>,
+    type_name: fn(&T) -> &str,
+    condition: &str,
+) -> Result<Box<dyn Pattern>, JsValue> {
+    let mut pattern = ObjectPattern::<T>::new(alias);
+    if let Some(fact_type) = fact_type {
+        let description = format!("type == '{}'", fact_type);
+        pattern = pattern.with_filter(move |fact| type_name(fact) == fact_type, description);
+    }
+    let pattern = pattern.with_constraint(Box::new(parse_condition(condition)?));
+    Ok(Box::new(pattern))
+}
+
+/// Parse a condition, taking the alias it reads every field through, if any
+fn parse_condition(source: &str) -> Result<Expression, JsValue> {
+    let bare = Expression::parse(source).map_err(js_error)?;
+    let fields = bare.fields();
+    let alias = fields
+        .first()
+        .and_then(|field| field.split_once('.'))
+        .map(|(alias, _)| alias)
+        .filter(|alias| {
+            fields
+                .iter()
+                .all(|field| field.split_once('.').map(|(a, _)| a) == Some(*alias))
+        })
+        .unwrap_or_default();
+    Expression::parse_with_alias(source, alias).map_err(js_error)
 }
 
+/// Fact types declared by `define` blocks or JSON schemas, by name
+type Templates = Rc<RefCell<HashMap<String, FactTemplate>>>;
+
 /// The main Flow container for rules
 #[wasm_bindgen]
 pub struct Flow {
-    name: String,
-    rules: Vec<Rule>,
+    inner: Rc<RefCell<EngineFlow>>,
+    templates: Templates,
 }
 
 #[wasm_bindgen]
 impl Flow {
     #[wasm_bindgen(constructor)]
     pub fn new(name: String) -> Flow {
+        register_fields::<DynamicFact>();
         Flow {
-            name,
-            rules: Vec::new(),
+            inner: Rc::new(RefCell::new(EngineFlow::new(name))),
+            templates: Templates::default(),
         }
     }
 
-    /// Add a rule to the flow
-    pub fn add_rule(&mut self, name: String, priority: i32) -> RuleBuilder {
+    /// Declare a fact type with a JSON schema of an object, named by its `title`
+    ///
+    /// Facts asserted under the type are checked against the schema's
+    /// properties and start from their defaults; conditions restricted to the
+    /// type match them. Declare types before adding rules on them.
+    #[wasm_bindgen(js_name = defineType)]
+    pub fn define_type(&self, schema: JsValue) -> Result<(), JsValue> {
+        self.define_schema(&serde_wasm_bindgen::from_value(schema)?)
+    }
+
+    fn define_schema(&self, schema: &serde_json::Value) -> Result<(), JsValue> {
+        let template = FactTemplate::from_json_schema(schema).map_err(js_error)?;
+        register_fields::<DefinedFact>();
+        let mut templates = self.templates.borrow_mut();
+        if templates.contains_key(template.name()) {
+            let message = format!("Type '{}' is already defined", template.name());
+            return Err(JsValue::from_str(&message));
+        }
+        templates.insert(template.name().to_string(), template);
+        Ok(())
+    }
+
+    /// Add a rule to the flow; its conditions are added with `when`
+    #[wasm_bindgen(js_name = addRule)]
+    pub fn add_rule(&self, name: String, priority: i32) -> RuleBuilder {
         RuleBuilder {
+            flow: Rc::clone(&self.inner),
+            templates: Rc::clone(&self.templates),
             name,
             priority,
-            flow_name: self.name.clone(),
+            agenda_group: None,
+            auto_focus: false,
+            conditions: Vec::new(),
         }
     }
 
     /// Get the flow name
     #[wasm_bindgen(getter)]
     pub fn name(&self) -> String {
-        self.name.clone()
+        self.inner.borrow().name().to_string()
     }
 
     /// Create a new session from this flow
-    pub fn session(&self) -> Session {
-        Session {
-            flow_name: self.name.clone(),
-            rules: self.rules.clone(),
-            facts: Vec::new(),
-            halted: false,
-        }
+    pub fn session(&self) -> Result<Session, JsValue> {
+        let mut inner = self.inner.borrow().try_session().map_err(js_error)?;
+        let callbacks = Arc::new(Callbacks::default());
+        inner.add_event_listener(Arc::clone(&callbacks) as Arc<dyn EventListener>);
+        Ok(Session {
+            inner,
+            templates: Rc::clone(&self.templates),
+            callbacks,
+        })
     }
 
     /// Get number of rules
     #[wasm_bindgen(getter = ruleCount)]
     pub fn rule_count(&self) -> usize {
-        self.rules.len()
+        self.inner.borrow().rule_names().len()
     }
 }
 
 /// Builder for creating rules
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct RuleBuilder {
+    flow: Rc<RefCell<EngineFlow>>,
+    templates: Templates,
     name: String,
     priority: i32,
-    flow_name: String,
+    agenda_group: Option<String>,
+    auto_focus: bool,
+    /// Conditions with the type of fact each one matches, if restricted
+    conditions: Vec<(String, Option<String>)>,
 }
 
 #[wasm_bindgen]
 impl RuleBuilder {
-    pub fn when(&self, condition: String) -> RuleBuilder {
-        RuleBuilder {
-            name: self.name.clone(),
-            priority: self.priority,
-            flow_name: self.flow_name.clone(),
+    /// Add a condition on facts of `fact_type`, or of any type, registering
+    /// the rule with the conditions added so far
+    ///
+    /// Fails if the condition does not parse.
+    pub fn when(
+        &self,
+        condition: String,
+        fact_type: Option<String>,
+    ) -> Result<RuleBuilder, JsValue> {
+        self.update(|builder| builder.conditions.push((condition, fact_type)))
+    }
+
+    /// Put the rule in an agenda group, whose activations fire only while it has focus
+    #[wasm_bindgen(js_name = agendaGroup)]
+    pub fn agenda_group(&self, group: String) -> Result<RuleBuilder, JsValue> {
+        self.update(|builder| builder.agenda_group = Some(group))
+    }
+
+    /// Focus the rule's agenda group whenever the rule is activated
+    #[wasm_bindgen(js_name = autoFocus)]
+    pub fn auto_focus(&self, auto_focus: bool) -> Result<RuleBuilder, JsValue> {
+        self.update(|builder| builder.auto_focus = auto_focus)
+    }
+
+    /// Copy this builder with a change, registering the rule once it has conditions
+    fn update(&self, change: impl FnOnce(&mut RuleBuilder)) -> Result<RuleBuilder, JsValue> {
+        let mut builder = self.clone();
+        change(&mut builder);
+        if !builder.conditions.is_empty() {
+            builder.register()?;
+        }
+        Ok(builder)
+    }
+
+    /// Replace the flow's definition of this rule
+    fn register(&self) -> Result<(), JsValue> {
+        let name = self.name.clone();
+        let log = move |_: &mut EngineSession, match_data: &Match| {
+            for fact in match_data.facts.values() {
+                let message = format!("Rule '{}' fired for fact {}", name, fact.id.as_u64());
+                web_sys::console::log_1(&message.into());
+            }
+            Ok(())
+        };
+        let mut rule = EngineRule::new(&self.name)
+            .priority(self.priority)
+            .auto_focus(self.auto_focus)
+            .then(log);
+        if let Some(group) = &self.agenda_group {
+            rule = rule.agenda_group(group);
+        }
+        let templates = self.templates.borrow();
+        for (i, (condition, fact_type)) in self.conditions.iter().enumerate() {
+            let alias = format!("f{}", i);
+            let fact_type = fact_type.clone();
+            let pattern = match &fact_type {
+                Some(name) if templates.contains_key(name) => {
+                    condition_pattern(alias, fact_type, DefinedFact::type_name, condition)?
+                }
+                _ => condition_pattern(alias, fact_type, DynamicFact::type_name, condition)?,
+            };
+            rule = rule.when(pattern);
         }
+        let rule = rule.build().map_err(js_error)?;
+
+        let mut flow = self.flow.borrow_mut();
+        if flow.has_rule(&self.name) {
+            flow.remove_rule(&self.name).map_err(js_error)?;
+        }
+        flow.add_rule(rule).map_err(js_error)
     }
 }
 
 /// A session for asserting facts and firing rules
 #[wasm_bindgen]
 pub struct Session {
-    flow_name: String,
-    rules: Vec<Rule>,
-    facts: Vec<Fact>,
-    halted: bool,
+    inner: EngineSession,
+    templates: Templates,
+    callbacks: Arc<Callbacks>,
 }
 
 #[wasm_bindgen]
 impl Session {
-    /// Assert a fact into the working memory
-    pub fn assert(&mut self, fact: Fact) {
-        self.facts.push(fact);
+    /// Assert an object into the working memory, returning its fact ID
+    ///
+    /// The fact's type is `fact_type`, or else the name of the object's
+    /// constructor, such as `Order` for `new Order()` and `Object` for `{}`.
+    pub fn assert(&mut self, fact: JsValue, fact_type: Option<String>) -> Result<u64, JsValue> {
+        let type_name = fact_type
+            .or_else(|| constructor_name(&fact))
+            .unwrap_or_else(|| "Object".to_string());
+        let data = serde_wasm_bindgen::from_value(fact)?;
+        self.assert_data(type_name, data)
+    }
+
+    fn assert_data(&mut self, type_name: String, data: serde_json::Value) -> Result<u64, JsValue> {
+        let template = self.templates.borrow().get(&type_name).cloned();
+        let handle = match template {
+            Some(template) => {
+                let fields = match data {
+                    serde_json::Value::Object(fields) => fields,
+                    _ => {
+                        return Err(JsValue::from_str(&format!(
+                            "A {} must be an object",
+                            type_name
+                        )))
+                    }
+                };
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, value)| Ok((field, Value::deserialize(value)?)))
+                    .collect::<serde_json::Result<Vec<_>>>()
+                    .map_err(|e| JsValue::from_str(&format!("Invalid {}: {}", type_name, e)))?;
+                let fact = template.create(fields).map_err(js_error)?;
+                self.inner.assert(fact).map_err(js_error)?.id()
+            }
+            None => {
+                let fact = DynamicFact { type_name, data };
+                self.inner.assert(fact).map_err(js_error)?.id()
+            }
+        };
+        self.callbacks.check()?;
+        Ok(handle.as_u64())
     }
 
     /// Retract a fact from the working memory by ID
-    pub fn retract(&mut self, fact_id: u64) -> bool {
-        if let Some(pos) = self.facts.iter().position(|f| f.id == fact_id) {
-            self.facts.remove(pos);
-            true
-        } else {
-            false
+    ///
+    /// Returns `false` if no fact in working memory has the ID, as when it
+    /// was already retracted.
+    pub fn retract(&mut self, fact_id: u64) -> Result<bool, JsValue> {
+        let id = self
+            .handles()
+            .iter()
+            .map(|handle| handle.id)
+            .find(|id| id.as_u64() == fact_id);
+        let Some(id) = id else {
+            return Ok(false);
+        };
+        self.inner.retract(id).map_err(js_error)?;
+        self.callbacks.check()?;
+        Ok(true)
+    }
+
+    /// Call `callback` on every `'assert'` or `'retract'` of a fact, with the
+    /// fact, or every `'fire'` of a rule, with the rule name and matched facts
+    pub fn on(&mut self, event: String, callback: js_sys::Function) -> Result<(), JsValue> {
+        if !EVENTS.contains(&event.as_str()) {
+            return Err(JsValue::from_str(&format!("Unknown event '{}'", event)));
         }
+        self.callbacks
+            .handlers
+            .borrow_mut()
+            .entry(event)
+            .or_default()
+            .push(callback);
+        Ok(())
     }
 
-    /// Get all facts as JSON
+    /// Get all facts as objects, oldest first
+    #[wasm_bindgen(js_name = getFacts)]
     pub fn get_facts(&self) -> Result<JsValue, JsValue> {
-        serde_wasm_bindgen::to_value(&self.facts)
-            .map_err(|e| JsValue::from_str(&format!("Serialization error: {}", e)))
+        to_js(&self.facts(None))
+    }
+
+    /// Get the facts of a type as objects, oldest first
+    #[wasm_bindgen(js_name = getFactsOfType)]
+    pub fn get_facts_of_type(&self, fact_type: String) -> Result<JsValue, JsValue> {
+        to_js(&self.facts(Some(&fact_type)))
+    }
+
+    /// Facts asserted from JavaScript or by rule actions, oldest first
+    fn handles(&self) -> Vec<Arc<FactHandle>> {
+        let mut handles = self.inner.get_facts::<DynamicFact>();
+        handles.extend(self.inner.get_facts::<DefinedFact>());
+        handles.sort_by_key(|handle| handle.recency);
+        handles
+    }
+
+    fn facts(&self, fact_type: Option<&str>) -> Vec<serde_json::Value> {
+        self.handles()
+            .iter()
+            .filter_map(|handle| fact_data(handle))
+            .filter(|(type_name, _)| fact_type.is_none_or(|t| *type_name == t))
+            .map(|(_, data)| data)
+            .collect()
     }
 
     /// Get number of facts
     #[wasm_bindgen(getter = factCount)]
     pub fn fact_count(&self) -> usize {
-        self.facts.len()
+        self.inner.fact_count()
     }
 
     /// Match and fire all rules
+    #[wasm_bindgen(js_name = matchRules)]
     pub fn match_rules(&mut self) -> Result<u32, JsValue> {
-        if self.halted {
-            return Ok(0);
-        }
-
-        let mut fired = 0;
-
-        // Sort rules by priority (higher first)
-        let mut sorted_rules = self.rules.clone();
-        sorted_rules.sort_by(|a, b| b.priority.cmp(&a.priority));
-
-        // Simple pattern matching - just fire rules for each fact
-        for rule in &sorted_rules {
-            for fact in &self.facts {
-                // In a real implementation, this would evaluate the condition
-                // For now, just fire the rule
-                fired += 1;
-                
-                // Log to console
-                web_sys::console::log_1(&format!(
-                    "Rule '{}' fired for fact {} (priority: {})",
-                    rule.name, fact.id, rule.priority
-                ).into());
-            }
-        }
+        let fired = run_now(self.inner.match_rules())?.map_err(js_error)?;
+        self.callbacks.check()?;
+        Ok(fired as u32)
+    }
 
-        Ok(fired)
+    /// Give an agenda group focus, so its activations fire next
+    ///
+    /// Fails if no rule is in the group.
+    pub fn focus(&mut self, group: String) -> Result<(), JsValue> {
+        self.inner.focus(group).map_err(js_error)?;
+        Ok(())
     }
 
     /// Halt the session
     pub fn halt(&mut self) {
-        self.halted = true;
+        self.inner.halt();
     }
 
     /// Check if session is halted
     #[wasm_bindgen(getter)]
     pub fn halted(&self) -> bool {
-        self.halted
+        self.inner.is_halted()
     }
 
     /// Dispose the session
     pub fn dispose(&mut self) {
-        self.facts.clear();
-        self.rules.clear();
-        self.halted = true;
+        self.inner.dispose();
+        self.inner.halt();
     }
 }
 
@@ -225,6 +552,27 @@ pub fn flow(name: String) -> Flow {
     Flow::new(name)
 }
 
+/// Compile rules written in the nools DSL into a flow
+///
+/// Facts asserted under a type declared by a `define` block are checked
+/// against its fields and start from their defaults. Actions are limited to
+/// the statements [`crate::dsl`] runs itself.
+#[wasm_bindgen]
+pub fn compile(source: String, name: Option<String>) -> Result<Flow, JsValue> {
+    let name = name.unwrap_or_else(|| "flow".to_string());
+    let flow = crate::dsl::compile(name, &source, &CompileOptions::new()).map_err(js_error)?;
+    let templates = crate::dsl::parse_templates(&source)
+        .map_err(js_error)?
+        .into_iter()
+        .map(|template| (template.name().to_string(), template))
+        .collect();
+    register_fields::<DynamicFact>();
+    Ok(Flow {
+        inner: Rc::new(RefCell::new(flow)),
+        templates: Rc::new(RefCell::new(templates)),
+    })
+}
+
 /// Get the version of the library
 #[wasm_bindgen]
 pub fn version() -> String {
@@ -234,28 +582,256 @@ pub fn version() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+    use wasm_bindgen_test::wasm_bindgen_test;
 
-    #[test]
-    fn test_fact_creation() {
-        let fact = Fact::new("test".to_string());
-        assert_eq!(fact.data(), "test");
-    }
-
-    #[test]
+    #[wasm_bindgen_test]
     fn test_flow_creation() {
         let flow = Flow::new("test".to_string());
         assert_eq!(flow.name(), "test");
         assert_eq!(flow.rule_count(), 0);
     }
 
-    #[test]
+    #[wasm_bindgen_test]
     fn test_session() {
         let flow = Flow::new("test".to_string());
-        let mut session = flow.session();
-        
-        let fact = Fact::new("data".to_string());
-        session.assert(fact);
-        
+        flow.add_rule("any".to_string(), 0)
+            .when("data".to_string(), None)
+            .unwrap();
+        assert_eq!(flow.rule_count(), 1);
+        let mut session = flow.session().unwrap();
+
+        let id = session
+            .assert_data("Object".to_string(), json!({ "data": "data" }))
+            .unwrap();
         assert_eq!(session.fact_count(), 1);
+        assert_eq!(session.match_rules().unwrap(), 1);
+        assert!(session.retract(id).unwrap());
+        assert!(!session.retract(id).unwrap());
+        assert_eq!(session.fact_count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_conditions() {
+        let flow = Flow::new("messages".to_string());
+        flow.add_rule("hello".to_string(), 0)
+            .when("m.count > 3 && m.text.includes('hello')".to_string(), None)
+            .unwrap();
+        flow.add_rule("bare".to_string(), 0)
+            .when("count == 1".to_string(), None)
+            .unwrap();
+
+        let mut session = flow.session().unwrap();
+        for data in [
+            json!({ "count": 5, "text": "hello world" }),
+            json!({ "count": 2, "text": "hello" }),
+            json!({ "count": 1, "text": "bye" }),
+            json!("not an object"),
+        ] {
+            session.assert_data("Message".to_string(), data).unwrap();
+        }
+        assert_eq!(session.match_rules().unwrap(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fact_types() {
+        let flow = Flow::new("orders".to_string());
+        flow.add_rule("big_order".to_string(), 0)
+            .when(
+                "o.customer.country == 'PT'".to_string(),
+                Some("Order".to_string()),
+            )
+            .unwrap();
+
+        let mut session = flow.session().unwrap();
+        let order = json!({ "total": 150, "customer": { "country": "PT" } });
+        session
+            .assert_data("Order".to_string(), order.clone())
+            .unwrap();
+        session
+            .assert_data(
+                "Customer".to_string(),
+                json!({ "customer": { "country": "PT" } }),
+            )
+            .unwrap();
+        assert_eq!(session.match_rules().unwrap(), 1);
+        assert_eq!(session.facts(Some("Order")), [order]);
+        assert_eq!(session.facts(None).len(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_compile() {
+        let source = r#"
+            define Message {
+                text: '',
+                status: 'new'
+            }
+
+            define Reply {
+                text: ''
+            }
+
+            rule Hello {
+                when {
+                    m : Message m.text.includes('hello') && m.status == 'new';
+                }
+                then {
+                    modify(m, function () { this.status = 'done'; });
+                    assert(new Reply({text: m.text + '!'}));
+                }
+            }
+        "#;
+        let flow = compile(source.to_string(), Some("greetings".to_string())).unwrap();
+        assert_eq!(flow.name(), "greetings");
+        assert_eq!(flow.rule_count(), 1);
+
+        let mut session = flow.session().unwrap();
+        session
+            .assert_data("Message".to_string(), json!({ "text": "hello" }))
+            .unwrap();
+        session
+            .assert_data("Other".to_string(), json!({ "text": "hello" }))
+            .unwrap();
+        assert_eq!(session.match_rules().unwrap(), 1);
+        assert_eq!(
+            session.facts(Some("Message")),
+            [json!({ "text": "hello", "status": "done" })]
+        );
+        assert_eq!(session.facts(Some("Reply")), [json!({ "text": "hello!" })]);
+        assert_eq!(session.facts(None).len(), 3);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_agenda_groups() {
+        let flow = Flow::new("groups".to_string());
+        flow.add_rule("main".to_string(), 0)
+            .when("x > 0".to_string(), None)
+            .unwrap();
+        flow.add_rule("validate".to_string(), 0)
+            .agenda_group("validation".to_string())
+            .unwrap()
+            .when("x > 0".to_string(), None)
+            .unwrap();
+        flow.add_rule("audit".to_string(), 0)
+            .when("x > 0".to_string(), None)
+            .unwrap()
+            .agenda_group("audit".to_string())
+            .unwrap()
+            .auto_focus(true)
+            .unwrap();
+        assert_eq!(flow.rule_count(), 3);
+
+        let mut session = flow.session().unwrap();
+        session
+            .assert_data("Object".to_string(), json!({ "x": 1 }))
+            .unwrap();
+        assert_eq!(session.match_rules().unwrap(), 2);
+        session.focus("validation".to_string()).unwrap();
+        assert_eq!(session.match_rules().unwrap(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_define_type() {
+        let flow = Flow::new("orders".to_string());
+        let schema = json!({
+            "title": "Order",
+            "properties": {
+                "total": { "type": "number" },
+                "status": { "type": "string", "default": "open" }
+            }
+        });
+        flow.define_schema(&schema).unwrap();
+        flow.add_rule("big_order".to_string(), 0)
+            .when(
+                "o.total > 100 && o.status == 'open'".to_string(),
+                Some("Order".to_string()),
+            )
+            .unwrap();
+
+        let mut session = flow.session().unwrap();
+        session
+            .assert_data("Order".to_string(), json!({ "total": 150 }))
+            .unwrap();
+        session
+            .assert_data("Order".to_string(), json!({ "total": 50 }))
+            .unwrap();
+        assert_eq!(session.match_rules().unwrap(), 1);
+        assert_eq!(
+            session.facts(Some("Order"))[0],
+            json!({ "total": 150, "status": "open" })
+        );
+    }
+
+    /// A callback recording the first two arguments of each call
+    fn recorder() -> (js_sys::Function, Rc<RefCell<Vec<[JsValue; 2]>>>) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&calls);
+        let callback = Closure::<dyn FnMut(JsValue, JsValue)>::new(move |a, b| {
+            log.borrow_mut().push([a, b]);
+        });
+        (callback.into_js_value().unchecked_into(), calls)
+    }
+
+    fn json_of(value: &JsValue) -> serde_json::Value {
+        serde_wasm_bindgen::from_value(value.clone()).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_event_callbacks() {
+        let flow = Flow::new("events".to_string());
+        flow.add_rule("big".to_string(), 0)
+            .when("x > 1".to_string(), Some("Item".to_string()))
+            .unwrap();
+        let mut session = flow.session().unwrap();
+        let (on_assert, asserts) = recorder();
+        let (on_retract, retracts) = recorder();
+        let (on_fire, fires) = recorder();
+        session.on("assert".to_string(), on_assert).unwrap();
+        session.on("retract".to_string(), on_retract).unwrap();
+        session.on("fire".to_string(), on_fire).unwrap();
+
+        let id = session
+            .assert_data("Item".to_string(), json!({ "x": 2 }))
+            .unwrap();
+        assert_eq!(session.match_rules().unwrap(), 1);
+        assert!(session.retract(id).unwrap());
+
+        let asserts = asserts.borrow();
+        assert_eq!(asserts.len(), 1);
+        assert_eq!(json_of(&asserts[0][0]), json!({ "x": 2 }));
+        let fires = fires.borrow();
+        assert_eq!(fires.len(), 1);
+        assert_eq!(fires[0][0].as_string().as_deref(), Some("big"));
+        assert_eq!(json_of(&fires[0][1]), json!([{ "x": 2 }]));
+        let retracts = retracts.borrow();
+        assert_eq!(retracts.len(), 1);
+        assert_eq!(json_of(&retracts[0][0]), json!({ "x": 2 }));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_callback_errors() {
+        let flow = Flow::new("events".to_string());
+        let mut session = flow.session().unwrap();
+        let throw = js_sys::Function::new_no_args("throw new Error('rejected')");
+        session.on("assert".to_string(), throw).unwrap();
+
+        let error = session
+            .assert_data("Item".to_string(), json!({ "x": 1 }))
+            .unwrap_err();
+        let error: js_sys::Error = error.dyn_into().unwrap();
+        assert_eq!(String::from(error.message()), "rejected");
+        // The fact stays asserted and the error is reported once
+        assert_eq!(session.fact_count(), 1);
+        assert_eq!(session.match_rules().unwrap(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_unknown_event() {
+        let flow = Flow::new("events".to_string());
+        let mut session = flow.session().unwrap();
+        let (callback, _) = recorder();
+
+        let error = session.on("modify".to_string(), callback).unwrap_err();
+        assert_eq!(error.as_string().as_deref(), Some("Unknown event 'modify'"));
     }
 }
@@ -0,0 +1,399 @@
+//! Re-run a fixture whenever its rule or fact file changes
+//!
+//! [`Watch`] polls a rule file and a JSON-lines fact file. When either one
+//! changes, it recompiles the rules, replays the facts in a fresh session
+//! and reports the fired rules and decisions that differ from the previous
+//! run, for a tight edit-and-check loop while authoring rules.
+//!
+//! Each line of the fact file is a [`FixtureFact`], for example
+//! `{"type": "Order", "fact": {"total": 150}}`.
+//!
+//! [`watch_rule_file`] watches a rule file in the nools DSL, building facts
+//! of the types its `define` blocks declare; the `nools watch` command runs it:
+//!
+//! ```text
+//! nools watch rules.nools facts.jsonl
+//! ```
+
+use crate::dmn::Decision;
+use crate::dsl::{self, CompileOptions};
+use crate::error::{Error, Result};
+use crate::event::FiredRules;
+use crate::fixture::{FixtureFact, FixtureTypes};
+use crate::flow::Flow;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// What one run of the fixture produced
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchRun {
+    /// Rules fired, in firing order
+    pub fired: Vec<String>,
+    /// Decisions asserted by decision tables, as `table {output: value}`
+    pub decisions: Vec<String>,
+    /// Why the run failed, if it did
+    pub error: Option<String>,
+}
+
+/// Read facts from a JSON-lines file, skipping blank lines
+pub fn load_facts(path: impl AsRef<Path>) -> Result<Vec<FixtureFact>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| Error::Execution(format!("Failed to read facts {}: {}", path.display(), e)))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                Error::Execution(format!(
+                    "Invalid fact at {}:{}: {}",
+                    path.display(),
+                    number + 1,
+                    e
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Assert facts into a fresh session of a flow and fire all rules
+pub async fn run_facts(flow: &Flow, types: &FixtureTypes, facts: &[FixtureFact]) -> WatchRun {
+    let fired_rules = Arc::new(FiredRules::default());
+    let result = async {
+        let mut session = flow.try_session()?;
+        session.add_event_listener(fired_rules.clone());
+        for fact in facts {
+            types.assert(&mut session, &fact.type_name, fact.fact.clone())?;
+        }
+        session.match_rules().await?;
+        Ok::<_, Error>(session)
+    }
+    .await;
+
+    let fired = fired_rules.take();
+    match result {
+        Ok(session) => WatchRun {
+            fired,
+            decisions: session
+                .get_facts::<Decision>()
+                .iter()
+                .filter_map(|handle| handle.downcast_ref::<Decision>())
+                .map(describe_decision)
+                .collect(),
+            error: None,
+        },
+        Err(e) => WatchRun {
+            fired,
+            decisions: Vec::new(),
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+fn describe_decision(decision: &Decision) -> String {
+    let outputs: BTreeMap<_, _> = decision.outputs.iter().collect();
+    let outputs: Vec<_> = outputs
+        .into_iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect();
+    format!("{} {{{}}}", decision.table, outputs.join(", "))
+}
+
+/// A line of a sequence diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/// Diff two sequences by their longest common subsequence
+fn diff_lines<'a>(previous: &'a [String], current: &'a [String]) -> Vec<Line<'a>> {
+    let (n, m) = (previous.len(), current.len());
+    let mut common = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if previous[i] == current[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && previous[i] == current[j] {
+            lines.push(Line::Same(&previous[i]));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || common[i][j + 1] >= common[i + 1][j]) {
+            lines.push(Line::Added(&current[j]));
+            j += 1;
+        } else {
+            lines.push(Line::Removed(&previous[i]));
+            i += 1;
+        }
+    }
+    lines
+}
+
+/// Render what changed between two runs
+///
+/// Lines only in `current` are prefixed with `+`, lines only in `previous`
+/// with `-`, in green and red when `color` is set.
+pub fn render_diff(previous: &WatchRun, current: &WatchRun, color: bool) -> String {
+    let mut out = String::new();
+    for (title, before, after) in [
+        ("fired", &previous.fired, &current.fired),
+        ("decisions", &previous.decisions, &current.decisions),
+    ] {
+        let _ = writeln!(out, "{}:", title);
+        for line in diff_lines(before, after) {
+            let (prefix, text, paint) = match line {
+                Line::Same(text) => (' ', text, ""),
+                Line::Added(text) => ('+', text, GREEN),
+                Line::Removed(text) => ('-', text, RED),
+            };
+            if color && !paint.is_empty() {
+                let _ = writeln!(out, "{}{} {}{}", paint, prefix, text, RESET);
+            } else {
+                let _ = writeln!(out, "{} {}", prefix, text);
+            }
+        }
+    }
+    if let Some(error) = &current.error {
+        let _ = writeln!(out, "error: {}", error);
+    }
+    out
+}
+
+/// Re-runs facts against rules whenever either file changes
+pub struct Watch<C> {
+    rules: PathBuf,
+    facts: PathBuf,
+    types: FixtureTypes,
+    compile: C,
+    /// Whether the `define` blocks of the rule source are fact types
+    templates: bool,
+    color: bool,
+    modified: Option<(SystemTime, SystemTime)>,
+    previous: WatchRun,
+}
+
+/// Watch a rule file in the nools DSL, see [`crate::dsl`]
+///
+/// Each run compiles the rules with `options` and reads facts of the types
+/// declared by the file's `define` blocks, besides those in `types`.
+pub fn watch_rule_file(
+    rules: impl Into<PathBuf>,
+    facts: impl Into<PathBuf>,
+    types: FixtureTypes,
+    options: CompileOptions,
+) -> Watch<impl Fn(&str) -> Result<Flow>> {
+    let rules = rules.into();
+    let name = dsl::flow_name(&rules);
+    let mut watch = Watch::new(rules, facts, types, move |source: &str| {
+        dsl::compile(name.clone(), source, &options)
+    });
+    watch.templates = true;
+    watch
+}
+
+impl<C> Watch<C>
+where
+    C: Fn(&str) -> Result<Flow>,
+{
+    /// Watch a rule file and a fact file; `compile` builds a flow from the rule source
+    pub fn new(
+        rules: impl Into<PathBuf>,
+        facts: impl Into<PathBuf>,
+        types: FixtureTypes,
+        compile: C,
+    ) -> Self {
+        Self {
+            rules: rules.into(),
+            facts: facts.into(),
+            types,
+            compile,
+            templates: false,
+            color: false,
+            modified: None,
+            previous: WatchRun::default(),
+        }
+    }
+
+    /// Color added and removed lines with ANSI escapes
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Re-run if a file changed since the last poll, returning the report
+    ///
+    /// Unreadable files count as unchanged, so an editor replacing a file
+    /// is picked up once the new file is in place.
+    pub async fn poll(&mut self) -> Option<String> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let stamps = (modified(&self.rules)?, modified(&self.facts)?);
+        if self.modified == Some(stamps) {
+            return None;
+        }
+        self.modified = Some(stamps);
+        Some(self.run().await)
+    }
+
+    /// Recompile the rules, replay the facts and report the changes
+    pub async fn run(&mut self) -> String {
+        // Failed runs are reported against, but never replace, the last good run
+        let (flow, types, facts) = match self.load() {
+            Ok(loaded) => loaded,
+            Err(e) => return format!("error: {}\n", e),
+        };
+        let current = run_facts(&flow, &types, &facts).await;
+        let report = render_diff(&self.previous, &current, self.color);
+        if current.error.is_none() {
+            self.previous = current;
+        }
+        report
+    }
+
+    fn load(&self) -> Result<(Flow, FixtureTypes, Vec<FixtureFact>)> {
+        let source = std::fs::read_to_string(&self.rules).map_err(|e| {
+            Error::Execution(format!(
+                "Failed to read rules {}: {}",
+                self.rules.display(),
+                e
+            ))
+        })?;
+        let flow = (self.compile)(&source)?;
+        let mut types = self.types.clone();
+        if self.templates {
+            for template in dsl::parse_templates(&source)? {
+                types = types.register_template(&template);
+            }
+        }
+        Ok((flow, types, load_facts(&self.facts)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{ObjectPattern, Pattern};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Order {
+        total: i64,
+    }
+
+    /// Compiles "threshold N" into a flow flagging orders above N
+    fn compile(source: &str) -> Result<Flow> {
+        let threshold: i64 = source
+            .trim()
+            .strip_prefix("threshold ")
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| Error::Compilation(format!("bad rules: {}", source)))?;
+        let mut flow = Flow::new("orders");
+        flow.rule("big_order")
+            .when(Box::new(
+                ObjectPattern::<Order>::new("o").with_filter(move |o| o.total > threshold, "big"),
+            ) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))?;
+        flow.rule("any_order")
+            .when(Box::new(ObjectPattern::<Order>::new("o")) as Box<dyn Pattern>)
+            .then(|_, _| Ok(()))?;
+        Ok(flow)
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let before = ["a", "b", "c"].map(String::from);
+        let after = ["a", "c", "d"].map(String::from);
+        assert_eq!(
+            diff_lines(&before, &after),
+            [
+                Line::Same("a"),
+                Line::Removed("b"),
+                Line::Same("c"),
+                Line::Added("d")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_reports_changes() {
+        let dir = std::env::temp_dir().join(format!("nools-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("rules.nools");
+        let facts = dir.join("facts.jsonl");
+        std::fs::write(&rules, "threshold 100").unwrap();
+        std::fs::write(
+            &facts,
+            "{\"type\": \"Order\", \"fact\": {\"total\": 150}}\n\n",
+        )
+        .unwrap();
+
+        let types = FixtureTypes::new().register::<Order>("Order");
+        let mut watch = Watch::new(&rules, &facts, types, compile);
+        let first = watch.poll().await.unwrap();
+        assert!(first.contains("+ big_order"));
+        assert!(first.contains("+ any_order"));
+        assert!(watch.poll().await.is_none());
+
+        std::fs::write(&rules, "threshold 200").unwrap();
+        let report = watch.run().await;
+        assert!(report.contains("- big_order"));
+        assert!(report.contains("  any_order"));
+
+        std::fs::write(&rules, "oops").unwrap();
+        let report = watch.run().await;
+        assert!(report.contains("error: Compilation error: bad rules: oops"));
+        std::fs::write(&rules, "threshold 200").unwrap();
+        assert!(!watch.run().await.contains('+'));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watch_rule_file_reads_defined_facts() {
+        let dir = std::env::temp_dir().join(format!("nools-watch-dsl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rules = dir.join("orders.nools");
+        let facts = dir.join("facts.jsonl");
+        let source = |threshold: i64| {
+            format!(
+                "define Order {{ total: 0, status: 'open' }}
+                 rule big_order {{
+                     when {{ o: Order o.total > {} && o.status == 'open' }}
+                     then {{ modify(o, function () {{ this.status = 'flagged'; }}); }}
+                 }}",
+                threshold
+            )
+        };
+        std::fs::write(&rules, source(100)).unwrap();
+        std::fs::write(
+            &facts,
+            "{\"type\": \"Order\", \"fact\": {\"total\": 150}}\n",
+        )
+        .unwrap();
+
+        let mut watch = watch_rule_file(&rules, &facts, FixtureTypes::new(), CompileOptions::new());
+        assert!(watch.run().await.contains("+ big_order"));
+
+        std::fs::write(&rules, source(200)).unwrap();
+        assert!(watch.run().await.contains("- big_order"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,437 @@
+//! Sliding windows over the facts a pattern matches
+//!
+//! A windowed pattern only keeps the most recent facts it matched: the last
+//! `n` with [`Pattern::over_length`], or those that arrived within a time
+//! span with [`Pattern::over_window`]. Facts leaving the window stay in
+//! working memory but drop out of the rule's partial matches, and pending
+//! activations binding them are cancelled.
+//!
+//! ```ignore
+//! flow.rule("average temperature")
+//!     .when(Box::new(ObjectPattern::<Reading>::new("r").over_length(10)))
+//!     .then(|session, m| { ... })?;
+//! ```
+//!
+//! Time windows read the session's [clock](crate::clock). They are checked
+//! whenever the pattern's node is reached and when the session's time is
+//! advanced, so a fact expires at the first of these after its time is up.
+
+use crate::analysis::Comparison;
+use crate::clock::Instant;
+use crate::constraint::ConstraintContext;
+use crate::error::Result;
+use crate::explain::ConstraintResult;
+use crate::fact::FactHandle;
+#[cfg(feature = "async-constraints")]
+use crate::node::NodeFuture;
+use crate::node::{
+    AlphaNode, Eviction, JoinNode, NetworkMemory, Node, NodeFactory, NodeId, NodePosition,
+};
+use crate::pattern::{JoinKey, Pattern};
+use crate::rule::{Activation, Match, Rule};
+use std::any::TypeId;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Which facts a windowed pattern keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// Facts that arrived within the duration
+    Time(Duration),
+    /// The last `n` facts
+    Length(usize),
+}
+
+/// A pattern matching only the facts in its window
+pub struct WindowPattern {
+    inner: Box<dyn Pattern>,
+    window: Window,
+}
+
+impl WindowPattern {
+    /// Window the facts matched by a pattern
+    ///
+    /// A length window of 0 keeps the last fact, like a length of 1.
+    pub fn new(inner: Box<dyn Pattern>, window: Window) -> Self {
+        let window = match window {
+            Window::Length(n) => Window::Length(n.max(1)),
+            window => window,
+        };
+        Self { inner, window }
+    }
+
+    /// Get the window
+    pub fn window(&self) -> Window {
+        self.window
+    }
+}
+
+impl Clone for WindowPattern {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone_box(),
+            window: self.window,
+        }
+    }
+}
+
+impl std::fmt::Debug for WindowPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WindowPattern")
+            .field("pattern", &self.inner)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+impl Pattern for WindowPattern {
+    fn type_id(&self) -> TypeId {
+        self.inner.type_id()
+    }
+
+    fn matches(&self, fact: &FactHandle, context: &ConstraintContext) -> Result<bool> {
+        self.inner.matches(fact, context)
+    }
+
+    #[cfg(feature = "async-constraints")]
+    fn matches_async<'a>(
+        &'a self,
+        fact: &'a Arc<FactHandle>,
+        context: &'a ConstraintContext,
+    ) -> crate::pattern::PatternFuture<'a> {
+        self.inner.matches_async(fact, context)
+    }
+
+    fn alias(&self) -> &str {
+        self.inner.alias()
+    }
+
+    fn join_keys(&self) -> &[JoinKey] {
+        self.inner.join_keys()
+    }
+
+    fn reads(&self) -> Option<&[String]> {
+        self.inner.reads()
+    }
+
+    fn constraint_count(&self) -> usize {
+        self.inner.constraint_count()
+    }
+
+    fn comparisons(&self) -> Vec<Comparison> {
+        self.inner.comparisons()
+    }
+
+    fn explain(&self, fact: &FactHandle, token: &Match) -> Vec<ConstraintResult> {
+        self.inner.explain(fact, token)
+    }
+
+    fn matches_bound(&self, fact: &FactHandle, token: &Match) -> Result<bool> {
+        self.inner.matches_bound(fact, token)
+    }
+
+    fn bind(&self, fact: &FactHandle, token: &mut Match) -> Result<()> {
+        self.inner.bind(fact, token)
+    }
+
+    fn node_factory(&self) -> Option<NodeFactory> {
+        let pattern: Arc<dyn Pattern> = Arc::from(self.inner.clone_box());
+        let window = self.window;
+        Some(Arc::new(move |child, position| {
+            let inner: Box<dyn Node> = match (pattern.node_factory(), position) {
+                (Some(factory), _) => factory(child, position),
+                (None, NodePosition::Alone) => {
+                    let mut alpha = AlphaNode::new(pattern.clone_box());
+                    alpha.add_child(child);
+                    Box::new(alpha)
+                }
+                (None, position) => Box::new(JoinNode::link(pattern.clone_box(), child, position)),
+            };
+            Box::new(WindowNode {
+                id: NodeId::new(),
+                pattern: Arc::clone(&pattern),
+                window,
+                rule: inner.rule_name(),
+                inner,
+            })
+        }))
+    }
+
+    fn clone_box(&self) -> Box<dyn Pattern> {
+        Box::new(self.clone())
+    }
+}
+
+/// Facts in a window, oldest first, with their arrival time
+#[derive(Default)]
+struct WindowState {
+    entries: VecDeque<(Arc<FactHandle>, Instant)>,
+}
+
+/// Keeps the window of a pattern in front of the pattern's own node
+struct WindowNode {
+    id: NodeId,
+    pattern: Arc<dyn Pattern>,
+    window: Window,
+    rule: Option<String>,
+    inner: Box<dyn Node>,
+}
+
+impl WindowNode {
+    /// Drop a fact from the matches below this node
+    fn evict(&self, fact: Arc<FactHandle>, memory: &mut NetworkMemory) -> Result<()> {
+        let alias = self.pattern.alias();
+        self.inner.evict_fact(&fact, alias, memory)?;
+        if let Some(rule) = &self.rule {
+            memory.evict(Eviction {
+                rule: rule.clone(),
+                alias: alias.to_string(),
+                fact: fact.id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Evict the facts whose time is up from this window
+    fn expire(&self, memory: &mut NetworkMemory) -> Result<()> {
+        let Window::Time(duration) = self.window else {
+            return Ok(());
+        };
+        let now = memory.clock().now();
+        let state: &mut WindowState = memory.state(self.id);
+        let kept = state
+            .entries
+            .iter()
+            .position(|(_, arrived)| now.duration_since(*arrived) < duration)
+            .unwrap_or(state.entries.len());
+        let expired: Vec<_> = state.entries.drain(..kept).map(|(fact, _)| fact).collect();
+        for fact in expired {
+            self.evict(fact, memory)?;
+        }
+        Ok(())
+    }
+
+    /// Add a newly matched fact, evicting those it pushes out of a length window
+    fn admit(&self, fact: &Arc<FactHandle>, memory: &mut NetworkMemory) -> Result<()> {
+        let now = memory.clock().now();
+        let state: &mut WindowState = memory.state(self.id);
+        state.entries.push_back((Arc::clone(fact), now));
+        let overflow = match self.window {
+            Window::Length(n) => state.entries.len().saturating_sub(n),
+            Window::Time(_) => 0,
+        };
+        let evicted: Vec<_> = state
+            .entries
+            .drain(..overflow)
+            .map(|(fact, _)| fact)
+            .collect();
+        for fact in evicted {
+            self.evict(fact, memory)?;
+        }
+        Ok(())
+    }
+}
+
+impl Node for WindowNode {
+    fn assert_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        self.expire(memory)?;
+        if self.pattern.matches(&fact, &ConstraintContext::new())? {
+            self.admit(&fact, memory)?;
+        }
+        self.inner.assert_fact(fact, memory)
+    }
+
+    #[cfg(feature = "async-constraints")]
+    fn assert_fact_async<'a>(
+        &'a self,
+        fact: Arc<FactHandle>,
+        memory: &'a mut NetworkMemory,
+    ) -> NodeFuture<'a> {
+        Box::pin(async move {
+            self.expire(memory)?;
+            let context = ConstraintContext::new();
+            if self.pattern.matches_async(&fact, &context).await? {
+                self.admit(&fact, memory)?;
+            }
+            self.inner.assert_fact_async(fact, memory).await
+        })
+    }
+
+    fn retract_fact(
+        &self,
+        fact: Arc<FactHandle>,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        let state: &mut WindowState = memory.state(self.id);
+        state.entries.retain(|(entry, _)| entry.id != fact.id);
+        self.expire(memory)?;
+        self.inner.retract_fact(fact, memory)
+    }
+
+    fn left_activate(
+        &self,
+        token: Match,
+        memory: &mut NetworkMemory,
+    ) -> Result<Vec<Arc<Activation>>> {
+        self.expire(memory)?;
+        self.inner.left_activate(token, memory)
+    }
+
+    fn reacts_to(&self, fact: &FactHandle, changed: &[&str]) -> bool {
+        self.inner.reacts_to(fact, changed)
+    }
+
+    fn refresh_fact(&self, fact: &Arc<FactHandle>, memory: &mut NetworkMemory) {
+        let state: &mut WindowState = memory.state(self.id);
+        for (entry, _) in state.entries.iter_mut().filter(|(e, _)| e.id == fact.id) {
+            *entry = Arc::clone(fact);
+        }
+        self.inner.refresh_fact(fact, memory);
+    }
+
+    fn retarget(&self, rule: &Arc<Rule>) {
+        self.inner.retarget(rule);
+    }
+
+    fn forget(&self, memory: &mut NetworkMemory) {
+        memory.remove_state(self.id);
+        self.inner.forget(memory);
+    }
+
+    fn evict_fact(
+        &self,
+        fact: &Arc<FactHandle>,
+        alias: &str,
+        memory: &mut NetworkMemory,
+    ) -> Result<()> {
+        if self.pattern.alias() == alias {
+            let state: &mut WindowState = memory.state(self.id);
+            state.entries.retain(|(entry, _)| entry.id != fact.id);
+        }
+        self.inner.evict_fact(fact, alias, memory)
+    }
+
+    fn rule_name(&self) -> Option<String> {
+        self.rule.clone()
+    }
+
+    fn expire_windows(&self, memory: &mut NetworkMemory) -> Result<()> {
+        self.expire(memory)?;
+        self.inner.expire_windows(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::PseudoClock;
+    use crate::flow::Flow;
+    use crate::pattern::ObjectPattern;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Clone)]
+    struct Reading {
+        value: i32,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Report;
+
+    type Log = Arc<Mutex<Vec<i32>>>;
+
+    fn log_readings(flow: &mut Flow, patterns: Vec<Box<dyn Pattern>>, log: &Log) {
+        let log = Arc::clone(log);
+        let mut rule = flow.rule("log");
+        for pattern in patterns {
+            rule = rule.when(pattern);
+        }
+        rule.then(move |_, m| {
+            log.lock().unwrap().push(m.get_as::<Reading>("r")?.value);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_length_window() {
+        let log = Log::default();
+        let mut flow = Flow::new("test");
+        let readings = ObjectPattern::<Reading>::new("r").over_length(2);
+        log_readings(&mut flow, vec![Box::new(readings)], &log);
+
+        let mut session = flow.session();
+        for value in 1..=3 {
+            session.assert(Reading { value }).unwrap();
+        }
+        assert_eq!(session.match_rules().await.unwrap(), 2);
+        let mut fired = log.lock().unwrap().clone();
+        fired.sort();
+        assert_eq!(fired, [2, 3]);
+        assert_eq!(session.fact_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_length_window_in_join() {
+        let log = Log::default();
+        let mut flow = Flow::new("test");
+        let patterns: Vec<Box<dyn Pattern>> = vec![
+            Box::new(ObjectPattern::<Report>::new("report")),
+            Box::new(ObjectPattern::<Reading>::new("r").over_length(2)),
+        ];
+        log_readings(&mut flow, patterns, &log);
+
+        let mut session = flow.session();
+        let first = session.assert(Reading { value: 1 }).unwrap();
+        session.assert(Reading { value: 2 }).unwrap();
+        session.assert(Report).unwrap();
+        session.assert(Reading { value: 3 }).unwrap();
+        session.match_rules().await.unwrap();
+        let mut fired = log.lock().unwrap().clone();
+        fired.sort();
+        assert_eq!(fired, [2, 3]);
+
+        // An evicted fact can still be retracted
+        session.retract(first).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_time_window_follows_session_clock() {
+        let log = Log::default();
+        let mut flow = Flow::new("test");
+        let readings = ObjectPattern::<Reading>::new("r").over_window(Duration::from_secs(10));
+        log_readings(&mut flow, vec![Box::new(readings)], &log);
+
+        let mut session = flow.session();
+        session.set_clock(Arc::new(PseudoClock::new()));
+        session.assert(Reading { value: 1 }).unwrap();
+        session.advance_time(Duration::from_secs(5)).unwrap();
+        session.assert(Reading { value: 2 }).unwrap();
+        session.advance_time(Duration::from_secs(6)).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(*log.lock().unwrap(), [2]);
+
+        session.advance_time(Duration::from_secs(4)).unwrap();
+        session.assert(Reading { value: 3 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(*log.lock().unwrap(), [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_time_window_expires_facts() {
+        let log = Log::default();
+        let mut flow = Flow::new("test");
+        let readings = ObjectPattern::<Reading>::new("r").over_window(Duration::ZERO);
+        log_readings(&mut flow, vec![Box::new(readings)], &log);
+
+        let mut session = flow.session();
+        session.assert(Reading { value: 1 }).unwrap();
+        session.assert(Reading { value: 2 }).unwrap();
+        assert_eq!(session.match_rules().await.unwrap(), 1);
+        assert_eq!(*log.lock().unwrap(), [2]);
+    }
+}
@@ -3,11 +3,53 @@
 use crate::error::{Error, Result};
 use crate::fact::{Fact, FactHandle, FactId};
 use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::cell::RefCell;
 
+/// A past state of working memory to read facts at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadView {
+    /// Generation whose facts are read
+    pub generation: u64,
+    /// Facts changed after this generation are read live
+    pub live_after: u64,
+}
+
+impl ReadView {
+    /// Read facts as they were at a generation
+    pub fn at(generation: u64) -> Self {
+        Self {
+            generation,
+            live_after: u64::MAX,
+        }
+    }
+}
+
+/// A change's generation, the changed fact and its version before the change
+type Change = (u64, FactId, Option<Arc<FactHandle>>);
+
+/// Facts of a deduplicated type, by hash
+#[derive(Debug)]
+struct EqualityIndex {
+    hash: fn(&dyn Fact) -> u64,
+    eq: fn(&dyn Fact, &dyn Fact) -> bool,
+    facts: HashMap<u64, Vec<FactId>>,
+}
+
+fn hash_fact<T: Fact + Hash>(fact: &dyn Fact) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    fact.as_any().downcast_ref::<T>().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn eq_facts<T: Fact + Eq>(a: &dyn Fact, b: &dyn Fact) -> bool {
+    a.as_any().downcast_ref::<T>() == b.as_any().downcast_ref::<T>()
+}
+
 /// Working memory stores all facts currently in the system
 /// (WASM-compatible version using RefCell instead of DashMap)
 #[derive(Debug)]
@@ -18,6 +60,15 @@ pub struct WorkingMemory {
     facts_by_type: RefCell<HashMap<TypeId, Vec<Arc<FactHandle>>>>,
     /// Recency counter for conflict resolution
     recency: AtomicU64,
+    /// Number of changes made so far
+    generation: AtomicU64,
+    /// Changes by generation, with each fact's version before the change
+    history: RefCell<Vec<Change>>,
+    /// Deduplicated types, by type
+    equality: RefCell<HashMap<TypeId, EqualityIndex>>,
+    /// Allocations of forgotten fact handles, reused for new ones
+    #[cfg(feature = "pool")]
+    pool: RefCell<crate::pool::FactPool>,
 }
 
 impl WorkingMemory {
@@ -27,15 +78,87 @@ impl WorkingMemory {
             facts: RefCell::new(HashMap::new()),
             facts_by_type: RefCell::new(HashMap::new()),
             recency: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+            history: RefCell::new(Vec::new()),
+            equality: RefCell::new(HashMap::new()),
+            #[cfg(feature = "pool")]
+            pool: RefCell::new(crate::pool::FactPool::new()),
+        }
+    }
+
+    /// Deduplicate facts of type `T` by value
+    ///
+    /// Facts of `T` already in memory are indexed; [`WorkingMemory::find_equal`]
+    /// then finds the fact equal to a new one.
+    pub fn deduplicate<T: Fact + Eq + Hash>(&self) {
+        let mut index = EqualityIndex {
+            hash: hash_fact::<T>,
+            eq: eq_facts::<T>,
+            facts: HashMap::new(),
+        };
+        for handle in self.get_by_type::<T>() {
+            let hash = (index.hash)(handle.fact.as_ref());
+            index.facts.entry(hash).or_default().push(handle.id);
+        }
+        self.equality.borrow_mut().insert(TypeId::of::<T>(), index);
+    }
+
+    /// Find a fact equal to `fact`, if its type is deduplicated
+    pub fn find_equal(&self, fact: &dyn Fact) -> Option<FactId> {
+        let equality = self.equality.borrow();
+        let index = equality.get(&fact.as_any().type_id())?;
+        let facts = self.facts.borrow();
+        index
+            .facts
+            .get(&(index.hash)(fact))?
+            .iter()
+            .copied()
+            .find(|id| {
+                facts
+                    .get(id)
+                    .is_some_and(|handle| (index.eq)(handle.fact.as_ref(), fact))
+            })
+    }
+
+    /// Add a fact to the equality index of its type
+    fn index_equal(&self, handle: &FactHandle) {
+        if let Some(index) = self.equality.borrow_mut().get_mut(&handle.type_id) {
+            let hash = (index.hash)(handle.fact.as_ref());
+            index.facts.entry(hash).or_default().push(handle.id);
+        }
+    }
+
+    /// Remove a fact from the equality index of its type
+    fn unindex_equal(&self, handle: &FactHandle) {
+        if let Some(index) = self.equality.borrow_mut().get_mut(&handle.type_id) {
+            let hash = (index.hash)(handle.fact.as_ref());
+            if let Some(ids) = index.facts.get_mut(&hash) {
+                ids.retain(|id| *id != handle.id);
+                if ids.is_empty() {
+                    index.facts.remove(&hash);
+                }
+            }
         }
     }
 
     /// Assert a new fact into working memory
     pub fn assert<T: Fact>(&self, fact: T) -> Result<Arc<FactHandle>> {
         let recency = self.recency.fetch_add(1, Ordering::SeqCst);
-        let handle = Arc::new(FactHandle::new(fact, recency));
+        Ok(self.insert(FactHandle::new(fact, recency)))
+    }
+
+    /// Assert a type-erased fact into working memory
+    pub fn assert_boxed(&self, fact: Box<dyn Fact>) -> Result<Arc<FactHandle>> {
+        let recency = self.recency.fetch_add(1, Ordering::SeqCst);
+        Ok(self.insert(FactHandle::from_boxed(fact, recency)))
+    }
+
+    /// Index a new fact handle
+    fn insert(&self, mut handle: FactHandle) -> Arc<FactHandle> {
         let type_id = handle.type_id;
         let id = handle.id;
+        handle.generation = self.record(id, None);
+        let handle = self.share(handle);
 
         // Store in main index
         self.facts.borrow_mut().insert(id, Arc::clone(&handle));
@@ -44,10 +167,46 @@ impl WorkingMemory {
         self.facts_by_type
             .borrow_mut()
             .entry(type_id)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(Arc::clone(&handle));
+        self.index_equal(&handle);
 
-        Ok(handle)
+        handle
+    }
+
+    /// Share a new fact handle, in a recycled allocation when pooling
+    fn share(&self, handle: FactHandle) -> Arc<FactHandle> {
+        #[cfg(feature = "pool")]
+        return self.pool.borrow_mut().handle(handle);
+        #[cfg(not(feature = "pool"))]
+        Arc::new(handle)
+    }
+
+    /// Hand forgotten versions of facts back for their allocations to be reused
+    fn release(&self, changes: impl IntoIterator<Item = Change>) {
+        #[cfg(feature = "pool")]
+        {
+            let mut pool = self.pool.borrow_mut();
+            for (_, _, previous) in changes {
+                if let Some(handle) = previous {
+                    pool.recycle(handle);
+                }
+            }
+        }
+        #[cfg(not(feature = "pool"))]
+        drop(changes.into_iter());
+    }
+
+    /// Hand a retracted fact's handle back, if nothing holds it anymore
+    #[cfg(feature = "pool")]
+    pub fn recycle(&self, handle: Arc<FactHandle>) {
+        self.pool.borrow_mut().recycle(handle);
+    }
+
+    /// Get the counts of fact handles built in reused allocations
+    #[cfg(feature = "pool")]
+    pub fn pool_stats(&self) -> crate::pool::PoolStats {
+        self.pool.borrow().stats()
     }
 
     /// Retract a fact from working memory
@@ -57,25 +216,50 @@ impl WorkingMemory {
             .borrow_mut()
             .remove(&fact_id)
             .ok_or_else(|| Error::FactNotFound(format!("{:?}", fact_id)))?;
+        self.record(fact_id, Some(Arc::clone(&handle)));
 
         // Remove from type index
         if let Some(facts) = self.facts_by_type.borrow_mut().get_mut(&handle.type_id) {
             facts.retain(|f| f.id != fact_id);
         }
+        self.unindex_equal(&handle);
 
         Ok(handle)
     }
 
     /// Modify a fact (retract and re-assert with updated recency)
     pub fn modify(&self, fact_id: FactId) -> Result<Arc<FactHandle>> {
+        let fact = self
+            .get(fact_id)
+            .map(|handle| Arc::clone(&handle.fact))
+            .ok_or_else(|| Error::FactNotFound(format!("{:?}", fact_id)))?;
+        self.swap(fact_id, fact)
+    }
+
+    /// Replace a fact's data, keeping its ID and updating its recency
+    pub fn replace<T: Fact>(&self, fact_id: FactId, fact: T) -> Result<Arc<FactHandle>> {
+        match self.get(fact_id) {
+            Some(handle) if handle.type_id != TypeId::of::<T>() => Err(Error::Execution(format!(
+                "Fact {:?} is a {}, not a {}",
+                fact_id,
+                handle.type_name(),
+                std::any::type_name::<T>()
+            ))),
+            _ => self.swap(fact_id, Arc::new(fact)),
+        }
+    }
+
+    /// Swap in new data for a fact with a fresh recency
+    fn swap(&self, fact_id: FactId, fact: Arc<dyn Fact>) -> Result<Arc<FactHandle>> {
         let old_handle = self.retract(fact_id)?;
         let recency = self.recency.fetch_add(1, Ordering::SeqCst);
 
-        let new_handle = Arc::new(FactHandle {
+        let new_handle = self.share(FactHandle {
             id: old_handle.id,
-            fact: Arc::clone(&old_handle.fact),
+            fact,
             type_id: old_handle.type_id,
             recency,
+            generation: self.generation(),
         });
 
         // Re-insert with updated recency
@@ -84,15 +268,34 @@ impl WorkingMemory {
         self.facts_by_type
             .borrow_mut()
             .entry(new_handle.type_id)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(Arc::clone(&new_handle));
+        self.index_equal(&new_handle);
 
         Ok(new_handle)
     }
 
     /// Get a fact by ID
     pub fn get(&self, fact_id: FactId) -> Option<Arc<FactHandle>> {
-        self.facts.borrow().get(&fact_id).map(|h| Arc::clone(h))
+        self.facts.borrow().get(&fact_id).map(Arc::clone)
+    }
+
+    /// Check whether a handle is the current version of its fact
+    pub fn is_current(&self, handle: &FactHandle) -> bool {
+        self.get(handle.id)
+            .is_some_and(|current| current.generation == handle.generation)
+    }
+
+    /// Fail with [`Error::StaleFact`] unless a handle is the current version of its fact
+    pub fn check_current(&self, handle: &FactHandle) -> Result<()> {
+        if self.is_current(handle) {
+            Ok(())
+        } else {
+            Err(Error::StaleFact {
+                fact: handle.id,
+                generation: handle.generation,
+            })
+        }
     }
 
     /// Get all facts of a specific type
@@ -101,13 +304,143 @@ impl WorkingMemory {
         self.facts_by_type
             .borrow()
             .get(&type_id)
-            .map(|facts| facts.clone())
+            .cloned()
             .unwrap_or_default()
     }
 
+    /// Find the facts of type `T` matching `predicate`
+    pub fn find<T: Fact>(&self, predicate: impl Fn(&T) -> bool) -> Vec<(FactId, Arc<FactHandle>)> {
+        find_in(self.get_by_type::<T>(), predicate)
+    }
+
+    /// Describe every way the type index disagrees with the main index
+    #[cfg(feature = "debug-invariants")]
+    pub(crate) fn index_problems(&self) -> Vec<String> {
+        let facts = self.facts.borrow();
+        let mut problems = Vec::new();
+        let mut indexed = 0;
+        for (type_id, handles) in self.facts_by_type.borrow().iter() {
+            indexed += handles.len();
+            for handle in handles {
+                match facts.get(&handle.id) {
+                    None => problems.push(format!(
+                        "type index holds {:?} ({}), which is not in working memory",
+                        handle.id,
+                        handle.type_name()
+                    )),
+                    Some(current) if current.recency != handle.recency => problems.push(format!(
+                        "type index holds {:?} at recency {}, working memory at {}",
+                        handle.id, handle.recency, current.recency
+                    )),
+                    Some(current) if current.type_id != *type_id => problems.push(format!(
+                        "type index files {:?} ({}) under another type",
+                        handle.id,
+                        handle.type_name()
+                    )),
+                    Some(_) => {}
+                }
+            }
+        }
+        if indexed != facts.len() {
+            problems.push(format!(
+                "type index holds {} facts, working memory {}",
+                indexed,
+                facts.len()
+            ));
+        }
+        problems
+    }
+
+    /// Record a change to a fact as a new generation
+    fn record(&self, fact_id: FactId, previous: Option<Arc<FactHandle>>) -> u64 {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.history
+            .borrow_mut()
+            .push((generation, fact_id, previous));
+        generation
+    }
+
+    /// Get the number of changes made so far
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Drop the history needed to read generations before `generation`
+    pub fn forget_history(&self, generation: u64) {
+        let mut history = self.history.borrow_mut();
+        let kept = history.partition_point(|(changed, _, _)| *changed <= generation);
+        self.release(history.drain(..kept));
+    }
+
+    /// Versions at a view of the facts that changed since, `None` if absent
+    fn reverted(&self, view: ReadView) -> HashMap<FactId, Option<Arc<FactHandle>>> {
+        let history = self.history.borrow();
+        let mut previous = HashMap::new();
+        let mut live = HashSet::new();
+        for (changed, id, version) in history
+            .iter()
+            .filter(|(changed, _, _)| *changed > view.generation)
+        {
+            if *changed > view.live_after {
+                live.insert(*id);
+            } else {
+                previous.entry(*id).or_insert_with(|| version.clone());
+            }
+        }
+        previous.retain(|id, _| !live.contains(id));
+        previous
+    }
+
+    /// Get a fact as a view sees it
+    ///
+    /// Changes made before a generation passed to
+    /// [`WorkingMemory::forget_history`] can no longer be undone.
+    pub fn get_at(&self, fact_id: FactId, view: ReadView) -> Option<Arc<FactHandle>> {
+        match self.reverted(view).remove(&fact_id) {
+            Some(previous) => previous,
+            None => self.get(fact_id),
+        }
+    }
+
+    /// Get all facts of a specific type as a view sees them
+    pub fn get_by_type_at<T: Fact>(&self, view: ReadView) -> Vec<Arc<FactHandle>> {
+        let previous = self.reverted(view);
+        if previous.is_empty() {
+            return self.get_by_type::<T>();
+        }
+
+        let type_id = TypeId::of::<T>();
+        let mut facts: Vec<_> = self
+            .get_by_type::<T>()
+            .into_iter()
+            .filter(|handle| !previous.contains_key(&handle.id))
+            .collect();
+        facts.extend(
+            previous
+                .into_values()
+                .flatten()
+                .filter(|handle| handle.type_id == type_id),
+        );
+        facts.sort_by_key(|handle| handle.recency);
+        facts
+    }
+
     /// Get all facts
     pub fn get_all(&self) -> Vec<Arc<FactHandle>> {
-        self.facts.borrow().values().map(|h| Arc::clone(h)).collect()
+        self.facts.borrow().values().map(Arc::clone).collect()
+    }
+
+    /// Get all facts as a view sees them, in recency order
+    pub fn get_all_at(&self, view: ReadView) -> Vec<Arc<FactHandle>> {
+        let previous = self.reverted(view);
+        let mut facts: Vec<_> = self
+            .get_all()
+            .into_iter()
+            .filter(|handle| !previous.contains_key(&handle.id))
+            .collect();
+        facts.extend(previous.into_values().flatten());
+        facts.sort_by_key(|handle| handle.recency);
+        facts
     }
 
     /// Get the number of facts in memory
@@ -124,6 +457,11 @@ impl WorkingMemory {
     pub fn clear(&self) {
         self.facts.borrow_mut().clear();
         self.facts_by_type.borrow_mut().clear();
+        let forgotten = std::mem::take(&mut *self.history.borrow_mut());
+        self.release(forgotten);
+        for index in self.equality.borrow_mut().values_mut() {
+            index.facts.clear();
+        }
     }
 
     /// Dispose of working memory
@@ -132,6 +470,18 @@ impl WorkingMemory {
     }
 }
 
+/// Keep the handles whose `T` matches `predicate`
+pub(crate) fn find_in<T: Fact>(
+    handles: Vec<Arc<FactHandle>>,
+    predicate: impl Fn(&T) -> bool,
+) -> Vec<(FactId, Arc<FactHandle>)> {
+    handles
+        .into_iter()
+        .filter(|handle| handle.downcast_ref::<T>().is_some_and(&predicate))
+        .map(|handle| (handle.id, handle))
+        .collect()
+}
+
 impl Default for WorkingMemory {
     fn default() -> Self {
         Self::new()
@@ -143,11 +493,13 @@ mod tests {
     use super::*;
 
     #[derive(Debug, Clone)]
+    #[allow(dead_code)]
     struct TestFact {
         value: i32,
     }
 
     #[derive(Debug, Clone)]
+    #[allow(dead_code)]
     struct OtherFact {
         name: String,
     }
@@ -162,6 +514,23 @@ mod tests {
         assert_eq!(retrieved.id, handle.id);
     }
 
+    #[test]
+    fn test_find_equal() {
+        let wm = WorkingMemory::new();
+        let kept = wm.assert(1u32).unwrap().id;
+        wm.deduplicate::<u32>();
+        assert_eq!(wm.find_equal(&1u32), Some(kept));
+        assert_eq!(wm.find_equal(&2u32), None);
+
+        wm.replace(kept, 2u32).unwrap();
+        assert_eq!(wm.find_equal(&1u32), None);
+        assert_eq!(wm.find_equal(&2u32), Some(kept));
+
+        wm.retract(kept).unwrap();
+        assert_eq!(wm.find_equal(&2u32), None);
+        assert_eq!(wm.find_equal(&TestFact { value: 2 }), None);
+    }
+
     #[test]
     fn test_get_by_type() {
         let wm = WorkingMemory::new();
@@ -179,6 +548,54 @@ mod tests {
         assert_eq!(other_facts.len(), 1);
     }
 
+    #[test]
+    fn test_read_past_generation() {
+        let wm = WorkingMemory::new();
+        let kept = wm.assert(TestFact { value: 1 }).unwrap().id;
+        let retracted = wm.assert(TestFact { value: 2 }).unwrap().id;
+        let generation = wm.generation();
+
+        wm.replace(kept, TestFact { value: 10 }).unwrap();
+        wm.retract(retracted).unwrap();
+        wm.assert(TestFact { value: 3 }).unwrap();
+
+        let values = |facts: Vec<Arc<FactHandle>>| {
+            facts
+                .iter()
+                .map(|f| f.downcast_ref::<TestFact>().unwrap().value)
+                .collect::<Vec<_>>()
+        };
+        let view = ReadView::at(generation);
+        assert_eq!(values(wm.get_by_type_at::<TestFact>(view)), [1, 2]);
+        assert_eq!(values(wm.get_by_type::<TestFact>()), [10, 3]);
+        assert!(wm.get_at(retracted, view).is_some());
+        assert!(wm.get_by_type_at::<OtherFact>(view).is_empty());
+
+        // Changes after `live_after` show through the view
+        let own = ReadView {
+            generation,
+            live_after: wm.generation(),
+        };
+        wm.replace(kept, TestFact { value: 11 }).unwrap();
+        assert_eq!(values(wm.get_by_type_at::<TestFact>(own)), [2, 11]);
+
+        wm.forget_history(wm.generation());
+        assert_eq!(values(wm.get_by_type_at::<TestFact>(view)), [3, 11]);
+    }
+
+    #[test]
+    fn test_find() {
+        let wm = WorkingMemory::new();
+        wm.assert(TestFact { value: 1 }).unwrap();
+        let big = wm.assert(TestFact { value: 10 }).unwrap();
+        wm.assert("other".to_string()).unwrap();
+
+        let found = wm.find(|fact: &TestFact| fact.value > 5);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, big.id);
+        assert!(wm.find(|_: &String| false).is_empty());
+    }
+
     #[test]
     fn test_retract() {
         let wm = WorkingMemory::new();
@@ -204,6 +621,18 @@ mod tests {
         assert!(new_handle.recency > old_recency);
     }
 
+    #[test]
+    fn test_replace() {
+        let wm = WorkingMemory::new();
+        let id = wm.assert(TestFact { value: 1 }).unwrap().id;
+
+        let handle = wm.replace(id, TestFact { value: 2 }).unwrap();
+        assert_eq!(handle.id, id);
+        assert_eq!(handle.downcast_ref::<TestFact>().unwrap().value, 2);
+        assert_eq!(wm.get_by_type::<TestFact>().len(), 1);
+        assert!(wm.replace(id, OtherFact { name: "x".into() }).is_err());
+    }
+
     #[test]
     fn test_clear() {
         let wm = WorkingMemory::new();
@@ -0,0 +1,57 @@
+//! Rules generated by nools-buildgen, compiled against the engine
+
+use nools::rule::Match;
+use nools::{Flow, Result, Session};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static VIP_ORDERS: AtomicUsize = AtomicUsize::new(0);
+static OPEN_ORDERS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone)]
+struct Customer {
+    id: u32,
+    vip: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Order {
+    customer_id: u32,
+    total: f64,
+    status: String,
+}
+
+fn flag_vip_order(_: &mut Session, _: &Match) -> Result<()> {
+    VIP_ORDERS.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+fn count_open_order(_: &mut Session, _: &Match) -> Result<()> {
+    OPEN_ORDERS.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+include!("../buildgen/tests/fixtures/orders.rs");
+
+#[tokio::test]
+async fn test_generated_rules_fire() {
+    let mut flow = Flow::new("orders");
+    add_rules(&mut flow).unwrap();
+
+    let mut session = flow.session();
+    session.assert(Customer { id: 1, vip: true }).unwrap();
+    session.assert(Customer { id: 2, vip: false }).unwrap();
+    for (customer_id, total, status) in [(1, 250.0, "open"), (1, 50.0, "paid"), (2, 500.0, "open")]
+    {
+        session
+            .assert(Order {
+                customer_id,
+                total,
+                status: status.to_string(),
+            })
+            .unwrap();
+    }
+    session.match_rules().await.unwrap();
+
+    assert_eq!(VIP_ORDERS.load(Ordering::SeqCst), 1);
+    assert_eq!(OPEN_ORDERS.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,56 @@
+//! Fact types using the derive macros of nools-derive
+
+use nools::fact::Fact;
+use nools::value::{fact_field, Fields, Value};
+use nools::{Flow, Pattern};
+
+#[derive(Debug, Clone, Fact)]
+struct Message {
+    text: String,
+    count: usize,
+    priority: Option<i32>,
+    #[fact(skip)]
+    raw: Vec<u8>,
+}
+
+fn message(text: &str, count: usize) -> Message {
+    Message {
+        text: text.to_string(),
+        count,
+        priority: None,
+        raw: Vec::new(),
+    }
+}
+
+#[test]
+fn test_derived_fields() {
+    let m = message("hello", 3);
+    assert_eq!(m.field("text"), Some(Value::from("hello")));
+    assert_eq!(m.field("count"), Some(Value::Int(3)));
+    assert_eq!(m.field("priority"), Some(Value::Null));
+    assert_eq!(m.field("raw"), None);
+    assert!(Message::field_raw().get(&m).is_empty());
+    assert_eq!(Message::field_count().name(), "count");
+}
+
+#[tokio::test]
+async fn test_derived_constraints_and_expressions() {
+    let mut flow = Flow::new("messages");
+    let pattern = Message::pattern("m")
+        .with(Message::field_count().gt(5))
+        .with_expression("text.length < 10")
+        .unwrap();
+    flow.rule("busy")
+        .when(Box::new(pattern) as Box<dyn Pattern>)
+        .then(|_, _| Ok(()))
+        .unwrap();
+
+    let mut session = flow.session();
+    session.assert(message("hi", 9)).unwrap();
+    session.assert(message("hi", 2)).unwrap();
+    session.assert(message("far too long", 9)).unwrap();
+    assert_eq!(session.match_rules().await.unwrap(), 1);
+
+    let handle = session.get_facts::<Message>()[0].clone();
+    assert_eq!(fact_field(&handle, "text"), Some(Value::from("hi")));
+}
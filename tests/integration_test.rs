@@ -188,3 +188,203 @@ async fn test_pattern_filtering() {
     let fired = session.match_rules().await.unwrap();
     assert_eq!(fired, 1);
 }
+
+#[derive(Debug, Clone)]
+struct Customer {
+    id: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Order {
+    customer_id: u32,
+    total: u32,
+}
+
+#[tokio::test]
+async fn test_join_rule_fires_per_matching_pair() {
+    let mut flow = Flow::new("join_test");
+
+    let rule = Rule::new("large_order")
+        .when(Box::new(ObjectPattern::<Customer>::new("c")) as Box<dyn Pattern>)
+        .when(Box::new(
+            ObjectPattern::<Order>::new("o")
+                .with_filter(|o| o.total > 100, "total > 100")
+                .join_on("c", |c: &Customer| c.id, |o: &Order| o.customer_id),
+        ) as Box<dyn Pattern>)
+        .then(|_session, match_data| {
+            let customer = match_data
+                .get("c")
+                .unwrap()
+                .downcast_ref::<Customer>()
+                .unwrap();
+            let order = match_data
+                .get("o")
+                .unwrap()
+                .downcast_ref::<Order>()
+                .unwrap();
+            assert_eq!(customer.id, order.customer_id);
+            Ok(())
+        })
+        .build()
+        .unwrap();
+
+    flow.add_rule(rule).unwrap();
+
+    let mut session = flow.session();
+    for id in 0..100 {
+        session.assert(Customer { id }).unwrap();
+    }
+    for (customer_id, total) in [(7, 500), (7, 50), (42, 150), (1000, 150)] {
+        session.assert(Order { customer_id, total }).unwrap();
+    }
+
+    let fired = session.match_rules().await.unwrap();
+    assert_eq!(fired, 2);
+}
+
+#[tokio::test]
+async fn test_sessions_from_same_flow_are_isolated() {
+    let mut flow = Flow::new("isolation_test");
+
+    let rule = Rule::new("customer_order")
+        .when(Box::new(ObjectPattern::<Customer>::new("c")) as Box<dyn Pattern>)
+        .when(Box::new(ObjectPattern::<Order>::new("o").join_on(
+            "c",
+            |c: &Customer| c.id,
+            |o: &Order| o.customer_id,
+        )) as Box<dyn Pattern>)
+        .then(|_session, _| Ok(()))
+        .build()
+        .unwrap();
+
+    flow.add_rule(rule).unwrap();
+
+    let mut first = flow.session();
+    let mut second = flow.session();
+
+    first.assert(Customer { id: 1 }).unwrap();
+    second
+        .assert(Order {
+            customer_id: 1,
+            total: 10,
+        })
+        .unwrap();
+
+    assert_eq!(first.match_rules().await.unwrap(), 0);
+    assert_eq!(second.match_rules().await.unwrap(), 0);
+}
+
+#[derive(Debug, Clone)]
+struct Payment {
+    amount: f64,
+}
+
+#[tokio::test]
+async fn test_model_score_condition_and_binding() {
+    use std::sync::{Arc, Mutex};
+
+    let mut flow = Flow::new("model_test");
+    flow.register_model(
+        "fraud",
+        Arc::new(|features: &[f64]| Ok(features[0] / 10_000.0)),
+    );
+    let model = flow.model("fraud").unwrap();
+
+    let scores = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&scores);
+    let rule = Rule::new("flag_payment")
+        .when(Box::new(
+            ObjectPattern::<Payment>::new("p")
+                .with_filter(|p| p.amount > 1000.0, "amount > 1000")
+                .with_model_filter(
+                    Arc::clone(&model),
+                    |p: &Payment| vec![p.amount],
+                    |score| score > 0.8,
+                    "fraud score > 0.8",
+                )
+                .bind_model_score("fraud_score", model, |p: &Payment| vec![p.amount]),
+        ) as Box<dyn Pattern>)
+        .then(move |_session, match_data| {
+            seen.lock()
+                .unwrap()
+                .push(match_data.score("fraud_score").unwrap());
+            Ok(())
+        })
+        .build()
+        .unwrap();
+
+    flow.add_rule(rule).unwrap();
+
+    let mut session = flow.session();
+    for amount in [500.0, 5_000.0, 9_000.0] {
+        session.assert(Payment { amount }).unwrap();
+    }
+
+    let fired = session.match_rules().await.unwrap();
+    assert_eq!(fired, 1);
+    assert_eq!(*scores.lock().unwrap(), vec![0.9]);
+}
+
+#[cfg(feature = "async-constraints")]
+#[tokio::test]
+async fn test_async_constraint_evaluation() {
+    let mut flow = Flow::new("async_test");
+
+    let rule = Rule::new("async_rule")
+        .when(Box::new(
+            ObjectPattern::<Message>::new("m").with_async_filter(
+                |m| {
+                    let count = m.count;
+                    async move { count > 2 }
+                },
+                "count > 2 (async)",
+            ),
+        ) as Box<dyn Pattern>)
+        .then(|_session, _| Ok(()))
+        .build()
+        .unwrap();
+
+    flow.add_rule(rule).unwrap();
+
+    let mut session = flow.session();
+    session
+        .assert_async(Message {
+            text: "low".to_string(),
+            count: 1,
+        })
+        .await
+        .unwrap();
+    session
+        .assert_async(Message {
+            text: "high".to_string(),
+            count: 3,
+        })
+        .await
+        .unwrap();
+
+    let fired = session.match_rules().await.unwrap();
+    assert_eq!(fired, 1);
+}
+
+#[cfg(feature = "async-constraints")]
+#[tokio::test]
+async fn test_async_constraint_rejected_on_sync_assert() {
+    let mut flow = Flow::new("async_sync_test");
+
+    let rule = Rule::new("async_rule")
+        .when(Box::new(
+            ObjectPattern::<Message>::new("m").with_async_filter(|_| async { true }, "always"),
+        ) as Box<dyn Pattern>)
+        .then(|_session, _| Ok(()))
+        .build()
+        .unwrap();
+
+    flow.add_rule(rule).unwrap();
+
+    let mut session = flow.session();
+    let result = session.assert(Message {
+        text: "test".to_string(),
+        count: 0,
+    });
+    assert!(result.is_err());
+}